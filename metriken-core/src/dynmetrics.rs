@@ -9,6 +9,7 @@
 //! exception of [`DynPinnedMetric`] and [`DynBoxedMetric`].
 
 use std::borrow::Cow;
+use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, HashMap};
 use std::ops::Deref;
 use std::pin::Pin;
@@ -19,16 +20,29 @@ use parking_lot::{const_rwlock, RwLock, RwLockReadGuard};
 use crate::null::NullMetric;
 use crate::provide::ProviderMap;
 use crate::wrapper::FormattingFn;
-use crate::{Format, Metadata, Metric, MetricEntry};
+use crate::{Format, Metadata, Metric, MetricEntry, Unit, Value};
+
+/// The number of sweeps a metric may go without being updated before it is
+/// removed from the registry.
+struct Ttl(u32);
+
+/// The last value seen for a metric with a [`Ttl`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Observed {
+    Counter(u64),
+    Gauge(i64),
+}
 
 pub(crate) struct DynMetricsRegistry {
     metrics: BTreeMap<usize, MetricEntry>,
+    idle: BTreeMap<usize, (Observed, u32)>,
 }
 
 impl DynMetricsRegistry {
     const fn new() -> Self {
         Self {
             metrics: BTreeMap::new(),
+            idle: BTreeMap::new(),
         }
     }
 
@@ -43,6 +57,47 @@ impl DynMetricsRegistry {
     fn unregister(&mut self, metric: *const dyn Metric) {
         let key = metric as *const () as usize;
         self.metrics.remove(&key);
+        self.idle.remove(&key);
+    }
+
+    fn expire(&mut self, on_evict: &mut dyn FnMut(&MetricEntry)) {
+        let mut expired = Vec::new();
+
+        for (key, entry) in &self.metrics {
+            let Some(ttl) = entry.request_ref::<Ttl>() else {
+                continue;
+            };
+
+            let observed = match entry.value() {
+                Some(Value::Counter(value)) => Observed::Counter(value),
+                Some(Value::Gauge(value)) => Observed::Gauge(value),
+                _ => continue,
+            };
+
+            let sweeps = match self.idle.entry(*key) {
+                Entry::Vacant(idle) => idle.insert((observed, 0)).1,
+                Entry::Occupied(mut idle) => {
+                    let idle = idle.get_mut();
+                    if idle.0 == observed {
+                        idle.1 += 1;
+                    } else {
+                        *idle = (observed, 0);
+                    }
+                    idle.1
+                }
+            };
+
+            if sweeps >= ttl.0 {
+                expired.push(*key);
+            }
+        }
+
+        for key in expired {
+            self.idle.remove(&key);
+            if let Some(entry) = self.metrics.remove(&key) {
+                on_evict(&entry);
+            }
+        }
     }
 
     pub(crate) fn metrics(&self) -> &BTreeMap<usize, MetricEntry> {
@@ -90,10 +145,24 @@ impl MetricBuilder {
         self
     }
 
+    /// Set the unit of this metric. This is stored in the `unit` metadata key.
+    pub fn unit(self, unit: Unit<'_>) -> Self {
+        self.metadata("unit", unit.as_str())
+    }
+
     pub fn formatter(self, formatter: fn(&MetricEntry, Format) -> String) -> Self {
         self.provide(FormattingFn(formatter))
     }
 
+    /// Remove this metric from the registry once its value has not changed
+    /// for `intervals` consecutive calls to [`expire`].
+    ///
+    /// Only counters and gauges can expire. Other metric types ignore this
+    /// setting.
+    pub fn ttl(self, intervals: u32) -> Self {
+        self.provide(Ttl(intervals))
+    }
+
     /// Add provided type data to this metric.
     ///
     /// These can then be accessed via [`MetricEntry::request_ref`].
@@ -156,6 +225,20 @@ pub(crate) fn unregister(metric: *const dyn Metric) {
     REGISTRY.write().unregister(metric);
 }
 
+/// Sweep the registry for dynamic metrics which have outlived their
+/// time-to-live.
+///
+/// Each call counts as one interval for the purposes of [`MetricBuilder::ttl`].
+/// Metrics whose value has not changed for their time-to-live are removed from
+/// the registry and passed to `on_evict`. The metric handle itself remains
+/// usable but will no longer appear in [`metrics`](crate::metrics).
+///
+/// `on_evict` is called while the registry is locked, so it must not register
+/// or unregister dynamic metrics or call [`metrics`](crate::metrics).
+pub fn expire(mut on_evict: impl FnMut(&MetricEntry)) {
+    REGISTRY.write().expire(&mut on_evict);
+}
+
 /// A metric combined with a set of dynamic providers.
 struct ProviderMetric<M> {
     metric: M,
@@ -171,7 +254,7 @@ impl<M: Metric> Metric for ProviderMetric<M> {
         self.metric.as_any()
     }
 
-    fn value(&self) -> Option<crate::Value> {
+    fn value(&self) -> Option<crate::Value<'_>> {
         self.metric.value()
     }
 
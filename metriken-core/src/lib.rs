@@ -30,12 +30,14 @@ mod metadata;
 mod metrics;
 mod null;
 mod provide;
+mod unit;
 mod wrapper;
 
 pub use crate::formatter::{default_formatter, Format};
 pub use crate::metadata::{Metadata, MetadataIter};
 pub use crate::metrics::{metrics, DynMetricsIter, Metrics, MetricsIter};
 pub use crate::provide::{request_ref, request_value, Request};
+pub use crate::unit::Unit;
 
 /// Global interface to a metric.
 ///
@@ -61,7 +63,7 @@ pub trait Metric: Send + Sync + 'static {
     /// If your metric's value does not correspond to one of the variants of
     /// [`Value`] then return [`Value::Other`] and metric consumers can use
     /// [`as_any`](crate::Metric::as_any) to specifically handle your metric.
-    fn value(&self) -> Option<Value>;
+    fn value(&self) -> Option<Value<'_>>;
 
     /// Provides type based access to context.
     ///
@@ -128,6 +130,11 @@ impl MetricEntry {
     pub fn formatted(&self, format: Format) -> String {
         let formatter = self
             .request_value::<crate::wrapper::FormattingFn>()
+            .or_else(|| {
+                // dynamic metrics provide their formatter by reference
+                self.request_ref::<crate::wrapper::FormattingFn>()
+                    .map(|func| crate::wrapper::FormattingFn(func.0))
+            })
             .map(|func| func.0)
             .unwrap_or(crate::default_formatter);
 
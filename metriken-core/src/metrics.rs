@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use std::iter::FusedIterator;
+use std::sync::OnceLock;
 
 use parking_lot::RwLockReadGuard;
 
@@ -43,12 +44,47 @@ impl Metrics {
         &crate::export::METRICS
     }
 
+    /// All metrics that were registered via the `#[metric]` attribute macro,
+    /// sorted by name.
+    ///
+    /// The linker decides the order of [`static_metrics`] so it cannot be
+    /// sorted at compile time. Instead this index is built the first time it
+    /// is requested and reused for the lifetime of the program.
+    ///
+    /// [`static_metrics`]: Self::static_metrics
+    pub fn static_metrics_by_name(&self) -> &'static [&'static MetricEntry] {
+        static INDEX: OnceLock<Box<[&'static MetricEntry]>> = OnceLock::new();
+
+        INDEX.get_or_init(|| {
+            let mut index: Vec<_> = crate::export::METRICS.iter().collect();
+            index.sort_by(|a, b| a.name().cmp(b.name()));
+            index.into_boxed_slice()
+        })
+    }
+
+    /// Find a metric by name.
+    ///
+    /// Statically declared metrics are found using a binary search over
+    /// [`static_metrics_by_name`] and are preferred over dynamic metrics of
+    /// the same name. If multiple metrics share the name then which one is
+    /// returned is unspecified.
+    ///
+    /// [`static_metrics_by_name`]: Self::static_metrics_by_name
+    pub fn get(&self, name: &str) -> Option<&MetricEntry> {
+        let index = self.static_metrics_by_name();
+        if let Ok(idx) = index.binary_search_by(|entry| entry.name().cmp(name)) {
+            return Some(index[idx]);
+        }
+
+        self.dynamic_metrics().find(|entry| entry.name() == name)
+    }
+
     /// A list containing all metrics that were dynamically registered.
-    pub fn dynamic_metrics(&self) -> DynMetricsIter {
+    pub fn dynamic_metrics(&self) -> DynMetricsIter<'_> {
         DynMetricsIter(self.dyn_metrics.metrics().values())
     }
 
-    pub fn iter(&self) -> MetricsIter {
+    pub fn iter(&self) -> MetricsIter<'_> {
         self.into_iter()
     }
 }
@@ -88,7 +124,7 @@ impl<'a> Iterator for MetricsIter<'a> {
 
     fn size_hint(&self) -> (usize, Option<usize>) {
         let (slo, shi) = self.sm.size_hint();
-        let (dlo, dhi) = self.sm.size_hint();
+        let (dlo, dhi) = self.dm.size_hint();
 
         match (shi, dhi) {
             (Some(shi), Some(dhi)) => (slo.saturating_add(dlo), shi.checked_add(dhi)),
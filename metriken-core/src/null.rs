@@ -13,7 +13,7 @@ impl Metric for NullMetric {
         None
     }
 
-    fn value(&self) -> Option<crate::Value> {
+    fn value(&self) -> Option<crate::Value<'_>> {
         None
     }
 }
@@ -251,12 +251,15 @@ impl ProviderMap {
 
     pub fn provide<'a>(&'a self, request: &mut Request<'a>) {
         if let Some(element) = self.0.get(&request.0.tag_id()) {
-            element.provide(request);
+            // Deref explicitly since `Box<dyn Provide>` is itself `Provide`.
+            (**element).provide(request);
         }
     }
 
     fn typeid_for<T: Provide>() -> TypeId {
-        TypeId::of::<tags::Ref<T>>()
+        // Entries are provided by reference so this must match the tag used
+        // by `request_ref`.
+        TypeId::of::<tags::Ref<tags::MaybeSizedValue<T>>>()
     }
 }
 
@@ -0,0 +1,136 @@
+/// The unit of a metric.
+///
+/// Units are attached to metrics through the `unit` metadata key, either with
+/// the `unit` argument of the `#[metric]` attribute or with
+/// [`MetricBuilder::unit`]. Using this enum rather than free-form strings means
+/// that a typo in a unit name is a compile error.
+///
+/// Unit names which are not recognized by [`Unit::parse`] are kept as
+/// [`Unit::Custom`].
+///
+/// [`MetricBuilder::unit`]: crate::dynmetrics::MetricBuilder::unit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Unit<'a> {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+    Bits,
+    Bytes,
+    /// A fraction where `1.0` is the whole.
+    Ratio,
+    /// A fraction where `100` is the whole.
+    Percent,
+    /// A number of events or items.
+    Count,
+    /// Any other unit.
+    Custom(&'a str),
+}
+
+/// The quantity measured by a unit. Units can only be converted into other
+/// units of the same dimension.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Time,
+    Data,
+    Fraction,
+    Count,
+}
+
+impl<'a> Unit<'a> {
+    /// The name of this unit, as stored in the `unit` metadata key.
+    pub const fn as_str(&self) -> &'a str {
+        match self {
+            Self::Nanoseconds => "nanoseconds",
+            Self::Microseconds => "microseconds",
+            Self::Milliseconds => "milliseconds",
+            Self::Seconds => "seconds",
+            Self::Bits => "bits",
+            Self::Bytes => "bytes",
+            Self::Ratio => "ratio",
+            Self::Percent => "percent",
+            Self::Count => "count",
+            Self::Custom(name) => name,
+        }
+    }
+
+    /// Parse a unit from its name.
+    pub fn parse(name: &'a str) -> Self {
+        match name {
+            "nanoseconds" => Self::Nanoseconds,
+            "microseconds" => Self::Microseconds,
+            "milliseconds" => Self::Milliseconds,
+            "seconds" => Self::Seconds,
+            "bits" => Self::Bits,
+            "bytes" => Self::Bytes,
+            "ratio" => Self::Ratio,
+            "percent" => Self::Percent,
+            "count" => Self::Count,
+            _ => Self::Custom(name),
+        }
+    }
+
+    /// The dimension of this unit and its size relative to the base unit of
+    /// that dimension, as a fraction.
+    fn scale(&self) -> Option<(Dimension, u64, u64)> {
+        Some(match self {
+            Self::Nanoseconds => (Dimension::Time, 1, 1_000_000_000),
+            Self::Microseconds => (Dimension::Time, 1, 1_000_000),
+            Self::Milliseconds => (Dimension::Time, 1, 1_000),
+            Self::Seconds => (Dimension::Time, 1, 1),
+            Self::Bits => (Dimension::Data, 1, 8),
+            Self::Bytes => (Dimension::Data, 1, 1),
+            Self::Ratio => (Dimension::Fraction, 1, 1),
+            Self::Percent => (Dimension::Fraction, 1, 100),
+            Self::Count => (Dimension::Count, 1, 1),
+            Self::Custom(_) => return None,
+        })
+    }
+
+    /// The base unit for the dimension of this unit: seconds for time, bytes
+    /// for data, and a ratio for fractions. These match the conventions used
+    /// by Prometheus. Custom units are their own base.
+    pub fn base(&self) -> Self {
+        match self.scale() {
+            Some((Dimension::Time, ..)) => Self::Seconds,
+            Some((Dimension::Data, ..)) => Self::Bytes,
+            Some((Dimension::Fraction, ..)) => Self::Ratio,
+            Some((Dimension::Count, ..)) => Self::Count,
+            None => *self,
+        }
+    }
+
+    /// The factor which converts a value in this unit into a value in `to`.
+    /// Returns `None` if the units measure different quantities.
+    ///
+    /// ```
+    /// # use metriken_core::Unit;
+    /// assert_eq!(Unit::Milliseconds.factor(Unit::Microseconds), Some(1000.0));
+    /// assert_eq!(Unit::Seconds.factor(Unit::Bytes), None);
+    /// ```
+    pub fn factor(&self, to: Unit<'_>) -> Option<f64> {
+        if self.as_str() == to.as_str() {
+            return Some(1.0);
+        }
+
+        let (from, from_num, from_den) = self.scale()?;
+        let (to, to_num, to_den) = to.scale()?;
+        if from != to {
+            return None;
+        }
+
+        Some((from_num * to_den) as f64 / (from_den * to_num) as f64)
+    }
+
+    /// Convert a value in this unit into a value in `to`.
+    pub fn convert(&self, value: f64, to: Unit<'_>) -> Option<f64> {
+        self.factor(to).map(|factor| value * factor)
+    }
+}
+
+impl std::fmt::Display for Unit<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -14,6 +14,17 @@ mod metric;
 /// so it can be used much the same as a normal static.
 ///
 /// # Parameters
+/// - (optional) `name`: The name of the metric. Defaults to the name of the
+///   static.
+/// - (optional) `description`: A human-readable description of the metric.
+///   Exporters include this in every snapshot entry for the metric.
+/// - (optional) `unit`: The unit of the metric, either a `metriken::Unit` or
+///   the name of one as a string literal, such as `"nanoseconds"`. Unknown
+///   unit names are rejected at compile time. This is stored in the `unit`
+///   metadata key.
+/// - (optional) `metadata`: A set of key-value pairs, such as
+///   `metadata = { team = "storage" }`, which are attached to every snapshot
+///   entry for the metric.
 /// - (optional) `crate`: The path to the `metriken` crate. This allows the
 ///   `metric` macro to be used within other macros that get exported to
 ///   third-party crates which may not have added `metriken` to their
@@ -21,6 +32,16 @@ mod metric;
 /// - (optional) `formatter`: A function to be used to determine the output name
 ///   for this metric.
 ///
+/// ```ignore
+/// #[metric(
+///     name = "request/latency",
+///     description = "time taken to serve a request",
+///     unit = "nanoseconds",
+///     metadata = { endpoint = "get" }
+/// )]
+/// static LATENCY: AtomicHistogram = AtomicHistogram::new(7, 64);
+/// ```
+///
 /// [`Deref`]: std::ops::Deref
 /// [`DerefMut`]: std::ops::DerefMut
 #[proc_macro_attribute]
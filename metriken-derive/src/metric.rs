@@ -14,6 +14,20 @@ use syn::{parse_quote, Expr, Ident, ItemStatic, Path, Token};
 
 use crate::args::{ArgName, Metadata, MetadataEntry, SingleArg, SingleArgExt};
 
+/// The names of the units which may be given as a string literal. This must
+/// match the names used by `metriken::Unit`.
+const UNITS: &[&str] = &[
+    "nanoseconds",
+    "microseconds",
+    "milliseconds",
+    "seconds",
+    "bits",
+    "bytes",
+    "ratio",
+    "percent",
+    "count",
+];
+
 /// All arguments to the metric attribute macro
 ///
 /// ```text
@@ -31,6 +45,7 @@ struct MetricArgs {
     krate: Option<SingleArg<Path>>,
     name: Option<SingleArg<Expr>>,
     description: Option<SingleArg<Expr>>,
+    unit: Option<SingleArg<Expr>>,
 }
 
 impl Parse for MetricArgs {
@@ -53,6 +68,7 @@ impl Parse for MetricArgs {
                 "metadata" => args.metadata.insert_or_duplicate(input.parse()?)?,
                 "name" => args.name.insert_or_duplicate(input.parse()?)?,
                 "description" => args.description.insert_or_duplicate(input.parse()?)?,
+                "unit" => args.unit.insert_or_duplicate(input.parse()?)?,
                 "formatter" => args.formatter.insert_or_duplicate(input.parse()?)?,
                 "crate" => {
                     let krate = SingleArg {
@@ -134,6 +150,8 @@ pub(crate) fn metric(
         }
     }
 
+    let metadata_has_unit = metadata.0.contains_key("unit");
+
     let name: syn::Expr = args.name.map(|name| name.value).unwrap_or_else(|| {
         let name = syn::LitStr::new(&static_name.to_string(), static_name.span());
         parse_quote!(#name)
@@ -149,7 +167,7 @@ pub(crate) fn metric(
         .map(|fmt| fmt.value)
         .unwrap_or_else(|| parse_quote!(#krate::default_formatter));
 
-    let attrs: Vec<_> = metadata
+    let mut attrs: Vec<_> = metadata
         .0
         .into_values()
         .map(|entry| {
@@ -160,7 +178,37 @@ pub(crate) fn metric(
         })
         .collect();
 
-    item.expr = Box::new(parse_quote! {{
+    if let Some(unit) = args.unit {
+        if metadata_has_unit {
+            return Err(syn::Error::new_spanned(
+                &unit.ident,
+                "unit is specified both as an argument and as metadata",
+            ));
+        }
+
+        let unit = match unit.value {
+            Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(name),
+                ..
+            }) => {
+                if !UNITS.contains(&name.value().as_str()) {
+                    return Err(syn::Error::new_spanned(
+                        &name,
+                        format_args!(
+                            "unknown unit `{}`, use `Unit::Custom` for custom units",
+                            name.value()
+                        ),
+                    ));
+                }
+
+                quote!(#name)
+            }
+            unit => quote!( #krate::Unit::as_str(&#unit) ),
+        };
+        attrs.push(quote!( "unit" => #unit ));
+    }
+
+    *item.expr = parse_quote! {{
         #private::declare_metric_v1! {
             metric: #static_name,
             name: #name,
@@ -170,7 +218,7 @@ pub(crate) fn metric(
         };
 
         #static_expr
-    }});
+    }};
 
     Ok(quote! { #item })
 }
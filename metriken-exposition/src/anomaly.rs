@@ -0,0 +1,324 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::SystemTime;
+
+use crate::snapshot::{MetricType, Snapshot};
+use crate::temporality::{series_key, SeriesKey};
+use crate::text::round;
+
+/// The metadata key added to a counter or gauge which was flagged by an
+/// [`AnomalyDetector`], with the anomaly score as its value.
+pub const ANOMALY: &str = "anomaly";
+
+/// The snapshot metadata key holding the number of metrics flagged by an
+/// [`AnomalyDetector`]. It is only present if at least one was flagged.
+pub const ANOMALIES: &str = "anomalies";
+
+/// How an [`AnomalyDetector`] scores a value against the recent values of the
+/// same metric.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AnomalyMethod {
+    /// The number of standard deviations from the mean.
+    ZScore,
+    /// The modified z-score, which uses the median and the median absolute
+    /// deviation, so that earlier outliers don't hide later ones.
+    #[default]
+    Mad,
+}
+
+impl AnomalyMethod {
+    /// The default threshold for the score of this method.
+    fn threshold(self) -> f64 {
+        match self {
+            Self::ZScore => 3.0,
+            Self::Mad => 3.5,
+        }
+    }
+
+    /// Score a value against the window. A value which differs from a window
+    /// with no spread at all has an infinite score.
+    fn score(self, window: &VecDeque<f64>, value: f64) -> f64 {
+        let n = window.len() as f64;
+        let (center, spread) = match self {
+            Self::ZScore => {
+                let mean = window.iter().sum::<f64>() / n;
+                let variance = window.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+                (mean, variance.sqrt())
+            }
+            Self::Mad => {
+                let center = median(window.iter().copied().collect());
+                let deviations: Vec<f64> = window.iter().map(|v| (v - center).abs()).collect();
+                let mad = median(deviations.clone());
+                // 0.6745 is the 75th percentile of the standard normal
+                // distribution, which makes the score comparable to a
+                // z-score. If more than half of the window is the same
+                // value the mean absolute deviation is used instead.
+                let spread = if mad > 0.0 {
+                    mad / 0.6745
+                } else {
+                    deviations.iter().sum::<f64>() / n * 1.2533
+                };
+                (center, spread)
+            }
+        };
+
+        let deviation = (value - center).abs();
+        if deviation == 0.0 {
+            0.0
+        } else {
+            deviation / spread
+        }
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+#[derive(Clone, Default)]
+struct Series {
+    /// The value and time of the previous snapshot, for counters.
+    previous: Option<(u64, SystemTime)>,
+    window: VecDeque<f64>,
+}
+
+/// Flags counters and gauges whose values are unusual compared with their
+/// recent history, so that an agent can decide what to ship, or a consumer
+/// what to look at, without reprocessing the whole stream.
+///
+/// Counters are scored by their rate over the interval since the previous
+/// snapshot and gauges by their value, against a window of the values before
+/// it. A metric whose score is above the threshold has the [`ANOMALY`]
+/// metadata key set to its score, and the snapshot has the [`ANOMALIES`] key
+/// set to the number of metrics flagged. Metrics are not scored until their
+/// window holds a minimum number of values.
+///
+/// Like a [`TemporalityConverter`], each stream of snapshots needs its own
+/// detector, and snapshots must be passed to [`AnomalyDetector::detect`] in
+/// the order they were taken.
+///
+/// [`TemporalityConverter`]: crate::TemporalityConverter
+///
+/// ```
+/// # use metriken_exposition::{AnomalyDetector, AnomalyMethod, Snapshot, ANOMALIES, ANOMALY};
+/// # use std::time::{Duration, SystemTime};
+/// let mut detector = AnomalyDetector::new(AnomalyMethod::Mad).min_samples(5);
+///
+/// for (secs, depth) in [10, 12, 11, 9, 10, 11, 250].into_iter().enumerate() {
+///     let snapshot = Snapshot::builder()
+///         .systemtime(SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64))
+///         .gauge("queue_depth", depth, &[])
+///         .build()
+///         .unwrap();
+///     let snapshot = detector.detect(snapshot);
+///     if depth == 250 {
+///         assert_eq!(snapshot.get_metadata(ANOMALIES), Some("1"));
+///         assert!(snapshot.gauges[0].metadata.contains_key(ANOMALY));
+///     } else {
+///         assert_eq!(snapshot.get_metadata(ANOMALIES), None);
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct AnomalyDetector {
+    method: AnomalyMethod,
+    window: usize,
+    min_samples: usize,
+    threshold: f64,
+    series: HashMap<SeriesKey, Series>,
+}
+
+impl AnomalyDetector {
+    /// A detector using the provided method and its usual threshold, `3.0`
+    /// for [`AnomalyMethod::ZScore`] and `3.5` for [`AnomalyMethod::Mad`],
+    /// which scores values against the previous 60 once at least 10 have
+    /// been seen.
+    pub fn new(method: AnomalyMethod) -> Self {
+        Self {
+            method,
+            window: 60,
+            min_samples: 10,
+            threshold: method.threshold(),
+            series: HashMap::new(),
+        }
+    }
+
+    /// The number of previous values each value is scored against.
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// The number of previous values needed before a metric is scored.
+    pub fn min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples.max(1);
+        self
+    }
+
+    /// The score above which a value is flagged.
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Score the counters and gauges of a snapshot, flagging those which are
+    /// anomalous, and add their values to the history.
+    ///
+    /// The history of a metric which is missing from the snapshot is
+    /// dropped, and the history of a counter which went backwards starts
+    /// over.
+    pub fn detect(&mut self, mut snapshot: Snapshot) -> Snapshot {
+        let mut seen = HashSet::new();
+        let mut anomalies = 0;
+        let now = snapshot.systemtime;
+        let (method, window, min_samples, threshold) =
+            (self.method, self.window, self.min_samples, self.threshold);
+
+        // score the value against the window, then add it to the window
+        let observe = |series: &mut Series, value: f64| {
+            let score = (series.window.len() >= min_samples)
+                .then(|| method.score(&series.window, value))
+                .filter(|score| *score > threshold);
+            if series.window.len() == window {
+                series.window.pop_front();
+            }
+            series.window.push_back(value);
+            score
+        };
+
+        for counter in snapshot.counters.iter_mut() {
+            let key = series_key(&counter.name, &counter.metadata);
+            let series = self.series.entry(key.clone()).or_default();
+            seen.insert(key);
+
+            let previous = series.previous.replace((counter.value, now));
+            let Some((value, time)) = previous else {
+                continue;
+            };
+            let secs = now.duration_since(time).unwrap_or_default().as_secs_f64();
+            if secs <= 0.0 {
+                continue;
+            }
+            let rate = if counter.metric_type == MetricType::DeltaCounter {
+                counter.value as f64 / secs
+            } else if let Some(delta) = counter.value.checked_sub(value) {
+                delta as f64 / secs
+            } else {
+                series.window.clear();
+                continue;
+            };
+
+            if let Some(score) = observe(series, rate) {
+                counter.metadata.insert(ANOMALY.to_string(), round(score));
+                anomalies += 1;
+            }
+        }
+
+        for gauge in snapshot.gauges.iter_mut() {
+            let key = series_key(&gauge.name, &gauge.metadata);
+            let series = self.series.entry(key.clone()).or_default();
+            seen.insert(key);
+
+            if let Some(score) = observe(series, gauge.value as f64) {
+                gauge.metadata.insert(ANOMALY.to_string(), round(score));
+                anomalies += 1;
+            }
+        }
+
+        self.series.retain(|key, _| seen.contains(key));
+
+        if anomalies > 0 {
+            snapshot
+                .metadata
+                .insert(ANOMALIES.to_string(), anomalies.to_string());
+        }
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn snapshot(secs: u64, requests: u64, depth: i64) -> Snapshot {
+        Snapshot::builder()
+            .systemtime(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .counter("requests", requests, &[])
+            .gauge("depth", depth, &[])
+            .build()
+            .unwrap()
+    }
+
+    fn flagged(snapshot: &Snapshot) -> Vec<&str> {
+        snapshot
+            .counters
+            .iter()
+            .map(|c| (&c.name, &c.metadata))
+            .chain(snapshot.gauges.iter().map(|g| (&g.name, &g.metadata)))
+            .filter(|(_, metadata)| metadata.contains_key(ANOMALY))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn counter_rates() {
+        for method in [AnomalyMethod::ZScore, AnomalyMethod::Mad] {
+            let mut detector = AnomalyDetector::new(method).min_samples(5);
+            let mut total = 0;
+
+            for (secs, rate) in [100, 104, 98, 101, 97, 103, 100, 500, 99]
+                .into_iter()
+                .enumerate()
+            {
+                total += rate;
+                let snapshot = detector.detect(snapshot(secs as u64, total, 1));
+                if rate == 500 {
+                    assert_eq!(flagged(&snapshot), ["requests"], "{method:?}");
+                    assert_eq!(snapshot.get_metadata(ANOMALIES), Some("1"));
+                } else {
+                    assert!(flagged(&snapshot).is_empty(), "{method:?} at {secs}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn constant_window() {
+        let mut detector = AnomalyDetector::new(AnomalyMethod::Mad).min_samples(3);
+        for secs in 0..5 {
+            assert!(flagged(&detector.detect(snapshot(secs, 0, 7))).is_empty());
+        }
+
+        let snapshot = detector.detect(snapshot(5, 0, 8));
+        assert_eq!(flagged(&snapshot), ["depth"]);
+        assert_eq!(snapshot.gauges[0].metadata.get(ANOMALY).unwrap(), "inf");
+    }
+
+    #[test]
+    fn reset_and_missing() {
+        let mut detector = AnomalyDetector::new(AnomalyMethod::ZScore)
+            .min_samples(2)
+            .window(3);
+        for secs in 0..4 {
+            detector.detect(snapshot(secs, secs * 10, 0));
+        }
+        assert_eq!(detector.series.values().next().unwrap().window.len(), 3);
+
+        // a counter which goes backwards starts over without being flagged
+        let snapshot = detector.detect(snapshot(4, 0, 0));
+        assert!(flagged(&snapshot).is_empty());
+        let requests = &detector.series[&series_key("requests", &HashMap::new())];
+        assert!(requests.window.is_empty());
+
+        detector.detect(Snapshot::builder().build().unwrap());
+        assert!(detector.series.is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+/// Credentials sent by an exporter, or required by an exposition endpoint,
+/// in the HTTP `Authorization` header.
+///
+/// The `Debug` representation does not include the secret.
+#[derive(Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Auth {
+    /// A bearer token, sent as `Authorization: Bearer <token>`.
+    Bearer(String),
+    /// A username and password, sent as `Authorization: Basic <base64>`.
+    Basic { username: String, password: String },
+}
+
+impl Auth {
+    /// Bearer token credentials.
+    pub fn bearer(token: impl Into<String>) -> Self {
+        Self::Bearer(token.into())
+    }
+
+    /// Basic credentials.
+    pub fn basic(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self::Basic {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// The value of the `Authorization` header for these credentials.
+    pub fn header_value(&self) -> String {
+        match self {
+            Self::Bearer(token) => format!("Bearer {token}"),
+            Self::Basic { username, password } => {
+                format!(
+                    "Basic {}",
+                    base64(format!("{username}:{password}").as_bytes())
+                )
+            }
+        }
+    }
+
+    /// Check the value of the `Authorization` header of a request against
+    /// these credentials. Requests without the header are rejected.
+    ///
+    /// The comparison takes the same time regardless of how much of the
+    /// header matches, so it does not leak the credentials through timing.
+    pub fn verify(&self, header: Option<&str>) -> bool {
+        let Some(header) = header else {
+            return false;
+        };
+
+        let (scheme, credentials) = header.trim().split_once(' ').unwrap_or((header, ""));
+        let expected = self.header_value();
+        let (expected_scheme, expected_credentials) =
+            expected.split_once(' ').unwrap_or((&expected, ""));
+
+        scheme.eq_ignore_ascii_case(expected_scheme)
+            & constant_time_eq(
+                credentials.trim().as_bytes(),
+                expected_credentials.as_bytes(),
+            )
+    }
+}
+
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bearer(_) => f.debug_tuple("Bearer").field(&"<redacted>").finish(),
+            Self::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
+/// Compare two byte strings in time which depends only on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Standard base64 encoding with padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let word = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(word >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_value() {
+        assert_eq!(Auth::bearer("abc").header_value(), "Bearer abc");
+        assert_eq!(
+            Auth::basic("Aladdin", "open sesame").header_value(),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+
+    #[test]
+    fn verify() {
+        let auth = Auth::bearer("secret");
+        assert!(auth.verify(Some("Bearer secret")));
+        assert!(auth.verify(Some("bearer secret")));
+        assert!(!auth.verify(Some("Bearer secreT")));
+        assert!(!auth.verify(Some("Bearer")));
+        assert!(!auth.verify(Some("Basic secret")));
+        assert!(!auth.verify(None));
+
+        let auth = Auth::basic("user", "pass");
+        assert!(auth.verify(Some(&auth.header_value())));
+        assert!(!auth.verify(Some(&Auth::basic("user", "word").header_value())));
+    }
+
+    #[test]
+    fn debug_redacts() {
+        let debug = format!("{:?}", Auth::basic("user", "hunter2"));
+        assert!(debug.contains("user"));
+        assert!(!debug.contains("hunter2"));
+
+        let debug = format!("{:?}", Auth::bearer("hunter2"));
+        assert!(!debug.contains("hunter2"));
+    }
+}
@@ -0,0 +1,567 @@
+//! Avro encoding using the schema in `schema/snapshot.avsc`.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use apache_avro::types::Value;
+use apache_avro::{GenericSingleObjectReader, GenericSingleObjectWriter, Schema};
+
+use crate::snapshot::{self, MetricType, Snapshot};
+
+/// The Avro schema for snapshots, as JSON. This is the schema to register
+/// with a schema registry before producing messages with
+/// [`Snapshot::to_confluent_avro`].
+pub const AVRO_SCHEMA: &str = include_str!("../schema/snapshot.avsc");
+
+/// The first byte of a message in the Confluent wire format.
+const CONFLUENT_MAGIC: u8 = 0;
+
+/// The symbols of the `MetricType` enum, in schema order.
+const METRIC_TYPES: [(MetricType, &str); 6] = [
+    (MetricType::Counter, "COUNTER"),
+    (MetricType::DeltaCounter, "DELTA_COUNTER"),
+    (MetricType::Gauge, "GAUGE"),
+    (MetricType::Histogram, "HISTOGRAM"),
+    (MetricType::Summary, "SUMMARY"),
+    (MetricType::DeltaHistogram, "DELTA_HISTOGRAM"),
+];
+
+fn schema() -> &'static Schema {
+    static SCHEMA: OnceLock<Schema> = OnceLock::new();
+    SCHEMA.get_or_init(|| Schema::parse_str(AVRO_SCHEMA).expect("invalid snapshot schema"))
+}
+
+/// Errors that can occur while encoding or decoding Avro snapshots.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AvroError {
+    Avro(Box<apache_avro::Error>),
+    Histogram(histogram::Error),
+    /// A decoded record did not have the shape of the snapshot schema, or a
+    /// message did not have the expected header.
+    Malformed(&'static str),
+}
+
+impl std::fmt::Display for AvroError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Avro(e) => write!(f, "avro error: {e}"),
+            Self::Histogram(e) => write!(f, "invalid histogram: {e}"),
+            Self::Malformed(field) => write!(f, "malformed snapshot: {field}"),
+        }
+    }
+}
+
+impl std::error::Error for AvroError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Avro(e) => Some(e),
+            Self::Histogram(e) => Some(e),
+            Self::Malformed(_) => None,
+        }
+    }
+}
+
+impl From<apache_avro::Error> for AvroError {
+    fn from(e: apache_avro::Error) -> Self {
+        Self::Avro(Box::new(e))
+    }
+}
+
+impl From<histogram::Error> for AvroError {
+    fn from(e: histogram::Error) -> Self {
+        Self::Histogram(e)
+    }
+}
+
+fn metric_type(metric_type: MetricType) -> Value {
+    let index = METRIC_TYPES
+        .iter()
+        .position(|(t, _)| *t == metric_type)
+        .unwrap_or_default();
+    Value::Enum(index as u32, METRIC_TYPES[index].1.to_string())
+}
+
+fn metadata(metadata: &HashMap<String, String>) -> Value {
+    Value::Map(
+        metadata
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect(),
+    )
+}
+
+fn optional(value: Option<u64>) -> Value {
+    match value {
+        Some(v) => Value::Union(1, Box::new(Value::Long(v as i64))),
+        None => Value::Union(0, Box::new(Value::Null)),
+    }
+}
+
+fn record(fields: Vec<(&str, Value)>) -> Value {
+    Value::Record(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    )
+}
+
+fn to_value(snapshot: &Snapshot) -> Value {
+    let timestamp = snapshot
+        .systemtime
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+
+    let counters = snapshot
+        .counters
+        .iter()
+        .map(|c| {
+            record(vec![
+                ("name", Value::String(c.name.clone())),
+                ("metric_type", metric_type(c.metric_type)),
+                ("value", Value::Long(c.value as i64)),
+                ("metadata", metadata(&c.metadata)),
+            ])
+        })
+        .collect();
+
+    let gauges = snapshot
+        .gauges
+        .iter()
+        .map(|g| {
+            record(vec![
+                ("name", Value::String(g.name.clone())),
+                ("metric_type", metric_type(g.metric_type)),
+                ("value", Value::Long(g.value)),
+                ("metadata", metadata(&g.metadata)),
+            ])
+        })
+        .collect();
+
+    let histograms = snapshot
+        .histograms
+        .iter()
+        .map(|h| {
+            let sparse = histogram::SparseHistogram::from(&h.value);
+            record(vec![
+                ("name", Value::String(h.name.clone())),
+                ("metric_type", metric_type(h.metric_type)),
+                (
+                    "grouping_power",
+                    Value::Int(sparse.config.grouping_power() as i32),
+                ),
+                (
+                    "max_value_power",
+                    Value::Int(sparse.config.max_value_power() as i32),
+                ),
+                (
+                    "bucket_indices",
+                    Value::Array(
+                        sparse
+                            .index
+                            .iter()
+                            .map(|i| Value::Long(*i as i64))
+                            .collect(),
+                    ),
+                ),
+                (
+                    "bucket_counts",
+                    Value::Array(
+                        sparse
+                            .count
+                            .iter()
+                            .map(|c| Value::Long(*c as i64))
+                            .collect(),
+                    ),
+                ),
+                ("metadata", metadata(&h.metadata)),
+            ])
+        })
+        .collect();
+
+    let stats = snapshot
+        .stats
+        .iter()
+        .map(|s| {
+            record(vec![
+                ("name", Value::String(s.name.clone())),
+                ("metric_type", metric_type(s.metric_type)),
+                ("min", optional(s.min)),
+                ("max", optional(s.max)),
+                ("sum", Value::Long(s.sum as i64)),
+                ("count", Value::Long(s.count as i64)),
+                ("metadata", metadata(&s.metadata)),
+            ])
+        })
+        .collect();
+
+    record(vec![
+        ("timestamp", Value::Long(timestamp as i64)),
+        ("metadata", metadata(&snapshot.metadata)),
+        ("counters", Value::Array(counters)),
+        ("gauges", Value::Array(gauges)),
+        ("histograms", Value::Array(histograms)),
+        ("stats", Value::Array(stats)),
+    ])
+}
+
+/// The fields of a decoded record, which are taken out by name.
+struct Fields(Vec<(String, Value)>);
+
+impl Fields {
+    fn new(value: Value) -> Result<Self, AvroError> {
+        match value {
+            Value::Record(fields) => Ok(Self(fields)),
+            _ => Err(AvroError::Malformed("expected a record")),
+        }
+    }
+
+    fn take(&mut self, name: &'static str) -> Result<Value, AvroError> {
+        self.0
+            .iter_mut()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| std::mem::replace(value, Value::Null))
+            .ok_or(AvroError::Malformed(name))
+    }
+
+    fn string(&mut self, name: &'static str) -> Result<String, AvroError> {
+        match self.take(name)? {
+            Value::String(s) => Ok(s),
+            _ => Err(AvroError::Malformed(name)),
+        }
+    }
+
+    fn long(&mut self, name: &'static str) -> Result<i64, AvroError> {
+        match self.take(name)? {
+            Value::Long(v) => Ok(v),
+            _ => Err(AvroError::Malformed(name)),
+        }
+    }
+
+    fn int(&mut self, name: &'static str) -> Result<i32, AvroError> {
+        match self.take(name)? {
+            Value::Int(v) => Ok(v),
+            _ => Err(AvroError::Malformed(name)),
+        }
+    }
+
+    fn optional(&mut self, name: &'static str) -> Result<Option<u64>, AvroError> {
+        match self.take(name)? {
+            Value::Union(_, value) => match *value {
+                Value::Null => Ok(None),
+                Value::Long(v) => Ok(Some(v as u64)),
+                _ => Err(AvroError::Malformed(name)),
+            },
+            _ => Err(AvroError::Malformed(name)),
+        }
+    }
+
+    fn array(&mut self, name: &'static str) -> Result<Vec<Value>, AvroError> {
+        match self.take(name)? {
+            Value::Array(values) => Ok(values),
+            _ => Err(AvroError::Malformed(name)),
+        }
+    }
+
+    fn longs(&mut self, name: &'static str) -> Result<Vec<i64>, AvroError> {
+        self.array(name)?
+            .into_iter()
+            .map(|v| match v {
+                Value::Long(v) => Ok(v),
+                _ => Err(AvroError::Malformed(name)),
+            })
+            .collect()
+    }
+
+    fn metadata(&mut self) -> Result<HashMap<String, String>, AvroError> {
+        match self.take("metadata")? {
+            Value::Map(map) => map
+                .into_iter()
+                .map(|(k, v)| match v {
+                    Value::String(v) => Ok((k, v)),
+                    _ => Err(AvroError::Malformed("metadata")),
+                })
+                .collect(),
+            _ => Err(AvroError::Malformed("metadata")),
+        }
+    }
+
+    fn metric_type(&mut self) -> Result<MetricType, AvroError> {
+        match self.take("metric_type")? {
+            Value::Enum(_, symbol) => METRIC_TYPES
+                .iter()
+                .find(|(_, s)| *s == symbol)
+                .map(|(t, _)| *t)
+                .ok_or(AvroError::Malformed("metric_type")),
+            _ => Err(AvroError::Malformed("metric_type")),
+        }
+    }
+}
+
+fn from_value(value: Value) -> Result<Snapshot, AvroError> {
+    let mut fields = Fields::new(value)?;
+
+    let mut snapshot = Snapshot::new();
+    snapshot.systemtime =
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(fields.long("timestamp")? as u64);
+    snapshot.metadata = fields.metadata()?;
+
+    for value in fields.array("counters")? {
+        let mut c = Fields::new(value)?;
+        snapshot.counters.push(snapshot::Counter {
+            name: c.string("name")?,
+            metric_type: c.metric_type()?,
+            value: c.long("value")? as u64,
+            metadata: c.metadata()?,
+        });
+    }
+
+    for value in fields.array("gauges")? {
+        let mut g = Fields::new(value)?;
+        snapshot.gauges.push(snapshot::Gauge {
+            name: g.string("name")?,
+            metric_type: g.metric_type()?,
+            value: g.long("value")?,
+            metadata: g.metadata()?,
+        });
+    }
+
+    for value in fields.array("histograms")? {
+        let mut h = Fields::new(value)?;
+        let name = h.string("name")?;
+        let metric_type = h.metric_type()?;
+        let config = histogram::Config::new(
+            h.int("grouping_power")?.try_into().unwrap_or(u8::MAX),
+            h.int("max_value_power")?.try_into().unwrap_or(u8::MAX),
+        )?;
+
+        let index: Vec<usize> = h
+            .longs("bucket_indices")?
+            .into_iter()
+            .map(|i| i as usize)
+            .collect();
+        let count: Vec<u64> = h
+            .longs("bucket_counts")?
+            .into_iter()
+            .map(|c| c as u64)
+            .collect();
+
+        if index.len() != count.len() || index.iter().any(|i| *i >= config.total_buckets()) {
+            return Err(histogram::Error::OutOfRange.into());
+        }
+
+        let sparse = histogram::SparseHistogram {
+            config,
+            index,
+            count,
+        };
+
+        snapshot.histograms.push(snapshot::Histogram {
+            name,
+            metric_type,
+            value: histogram::Histogram::from(&sparse),
+            metadata: h.metadata()?,
+        });
+    }
+
+    for value in fields.array("stats")? {
+        let mut s = Fields::new(value)?;
+        snapshot.stats.push(snapshot::Stats {
+            name: s.string("name")?,
+            metric_type: s.metric_type()?,
+            min: s.optional("min")?,
+            max: s.optional("max")?,
+            sum: s.long("sum")? as u64,
+            count: s.long("count")? as u64,
+            metadata: s.metadata()?,
+        });
+    }
+
+    Ok(snapshot)
+}
+
+impl Snapshot {
+    /// Encode this snapshot as a single Avro object, which is prefixed with
+    /// the fingerprint of [`AVRO_SCHEMA`].
+    pub fn to_avro(&self) -> Result<Vec<u8>, AvroError> {
+        let mut writer = GenericSingleObjectWriter::new_with_capacity(schema(), 1024)?;
+        let mut buf = Vec::new();
+        writer.write_value(to_value(self), &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Decode a snapshot that was encoded with [`Self::to_avro`].
+    pub fn from_avro(bytes: &[u8]) -> Result<Self, AvroError> {
+        let reader = GenericSingleObjectReader::new(schema().clone())?;
+        let value = reader.read_value(&mut &bytes[..])?;
+        from_value(value)
+    }
+
+    /// Encode this snapshot in the Confluent wire format used by Kafka
+    /// producers and consumers with a schema registry. The schema id is the
+    /// id returned by the registry when [`AVRO_SCHEMA`] was registered.
+    pub fn to_confluent_avro(&self, schema_id: u32) -> Result<Vec<u8>, AvroError> {
+        let mut buf = vec![CONFLUENT_MAGIC];
+        buf.extend_from_slice(&schema_id.to_be_bytes());
+        buf.extend(apache_avro::to_avro_datum(schema(), to_value(self))?);
+        Ok(buf)
+    }
+
+    /// Decode a snapshot in the Confluent wire format, returning the schema
+    /// id from the message along with the snapshot. The message must have been
+    /// written with [`AVRO_SCHEMA`].
+    pub fn from_confluent_avro(bytes: &[u8]) -> Result<(u32, Self), AvroError> {
+        if bytes.len() < 5 || bytes[0] != CONFLUENT_MAGIC {
+            return Err(AvroError::Malformed("missing confluent header"));
+        }
+
+        let schema_id = u32::from_be_bytes(bytes[1..5].try_into().unwrap());
+        let value = apache_avro::from_avro_datum(schema(), &mut &bytes[5..], None)?;
+        Ok((schema_id, from_value(value)?))
+    }
+}
+
+/// Writes snapshots to an Avro object container file.
+pub struct AvroWriter<W: Write> {
+    inner: apache_avro::Writer<'static, W>,
+}
+
+impl<W: Write> AvroWriter<W> {
+    /// Create a writer which writes the container header and snapshots to the
+    /// provided writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            inner: apache_avro::Writer::new(schema(), writer),
+        }
+    }
+
+    /// Append a snapshot to the container.
+    pub fn push(&mut self, snapshot: &Snapshot) -> Result<(), AvroError> {
+        self.inner.append(to_value(snapshot))?;
+        Ok(())
+    }
+
+    /// Flush any buffered snapshots and return the inner writer.
+    pub fn finalize(self) -> Result<W, AvroError> {
+        Ok(self.inner.into_inner()?)
+    }
+}
+
+/// Reads snapshots from an Avro object container file.
+pub struct AvroReader<R: Read> {
+    inner: apache_avro::Reader<'static, R>,
+}
+
+impl<R: Read> AvroReader<R> {
+    /// Read the container header from the provided reader. The container may
+    /// have been written with any schema which resolves to [`AVRO_SCHEMA`].
+    pub fn new(reader: R) -> Result<Self, AvroError> {
+        Ok(Self {
+            inner: apache_avro::Reader::with_schema(schema(), reader)?,
+        })
+    }
+}
+
+impl<R: Read> Iterator for AvroReader<R> {
+    type Item = Result<Snapshot, AvroError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = self.inner.next()?;
+        Some(value.map_err(AvroError::from).and_then(from_value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut value = histogram::Histogram::new(4, 10).unwrap();
+        value.add(100, 3).unwrap();
+
+        let mut snapshot = Snapshot::new();
+        snapshot
+            .metadata
+            .insert("source".to_string(), "test".to_string());
+        snapshot.counters.push(snapshot::Counter {
+            name: "counter".to_string(),
+            metric_type: MetricType::DeltaCounter,
+            value: u64::MAX,
+            metadata: HashMap::new(),
+        });
+        snapshot.gauges.push(snapshot::Gauge {
+            name: "gauge".to_string(),
+            metric_type: MetricType::Gauge,
+            value: -1,
+            metadata: HashMap::new(),
+        });
+        snapshot.histograms.push(snapshot::Histogram {
+            name: "histogram".to_string(),
+            metric_type: MetricType::Histogram,
+            value,
+            metadata: HashMap::new(),
+        });
+        snapshot.stats.push(snapshot::Stats {
+            name: "stats".to_string(),
+            metric_type: MetricType::Summary,
+            min: None,
+            max: Some(7),
+            sum: 7,
+            count: 1,
+            metadata: HashMap::new(),
+        });
+        snapshot
+    }
+
+    fn check(decoded: &Snapshot, original: &Snapshot) {
+        assert_eq!(decoded.systemtime, original.systemtime);
+        assert_eq!(decoded.metadata, original.metadata);
+        assert_eq!(decoded.counters[0].value, u64::MAX);
+        assert_eq!(decoded.counters[0].metric_type, MetricType::DeltaCounter);
+        assert_eq!(decoded.gauges[0].value, -1);
+        assert_eq!(decoded.histograms[0].value, original.histograms[0].value);
+        assert_eq!(decoded.stats[0].min, None);
+        assert_eq!(decoded.stats[0].max, Some(7));
+    }
+
+    #[test]
+    fn single_object() {
+        let original = snapshot();
+        let encoded = original.to_avro().unwrap();
+        assert_eq!(&encoded[..2], &[0xC3, 0x01]);
+        check(&Snapshot::from_avro(&encoded).unwrap(), &original);
+    }
+
+    #[test]
+    fn confluent() {
+        let original = snapshot();
+        let encoded = original.to_confluent_avro(42).unwrap();
+        let (schema_id, decoded) = Snapshot::from_confluent_avro(&encoded).unwrap();
+        assert_eq!(schema_id, 42);
+        check(&decoded, &original);
+
+        let mut corrupt = encoded.clone();
+        corrupt[0] = 1;
+        assert!(Snapshot::from_confluent_avro(&corrupt).is_err());
+    }
+
+    #[test]
+    fn container() {
+        let original = snapshot();
+        let mut writer = AvroWriter::new(Vec::new());
+        writer.push(&original).unwrap();
+        writer.push(&original).unwrap();
+        let buf = writer.finalize().unwrap();
+
+        let decoded: Vec<Snapshot> = AvroReader::new(&buf[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(decoded.len(), 2);
+        check(&decoded[1], &original);
+    }
+}
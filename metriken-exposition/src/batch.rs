@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use histogram::SparseHistogram;
+
+#[cfg(all(feature = "serde", feature = "cbor"))]
+use ciborium::de::Error as DeserializeCborError;
+#[cfg(all(feature = "serde", feature = "postcard"))]
+use postcard::Error as PostcardError;
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+use rmp_serde::decode::Error as DeserializeMsgpackError;
+#[cfg(all(feature = "serde", feature = "json"))]
+use serde_json::Error as JsonError;
+
+use crate::hyperloglog::HyperLogLog;
+use crate::sketch::DDSketch;
+use crate::snapshot::{
+    Cardinality, Counter, Event, Gauge, HeavyHitter, Histogram, MetricType, Sketch, Snapshot,
+    Stats, TopK, SEQUENCE,
+};
+
+/// A container holding several snapshots.
+///
+/// Metric names and metadata rarely change from one snapshot to the next, so
+/// the batch stores each distinct name and metadata map once and has the
+/// individual snapshots refer to them. Histograms are stored in their sparse
+/// representation. Together these make a serialized batch much smaller than
+/// the same snapshots serialized independently.
+///
+/// Snapshots are reconstructed in the order they were pushed when iterating
+/// over the batch.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "BatchData"))]
+pub struct SnapshotBatch {
+    /// Interned metadata maps.
+    metadata: Vec<HashMap<String, String>>,
+    /// Interned metric names along with their type and the index of their
+    /// metadata.
+    metrics: Vec<(String, MetricType, usize)>,
+    snapshots: Vec<BatchedSnapshot>,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    interner: Interner,
+}
+
+/// The serialized form of a batch, whose indices are checked before it is
+/// turned into a [`SnapshotBatch`], so that a malformed batch is an error
+/// rather than a panic when its snapshots are reconstructed.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct BatchData {
+    metadata: Vec<HashMap<String, String>>,
+    metrics: Vec<(String, MetricType, usize)>,
+    snapshots: Vec<BatchedSnapshot>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<BatchData> for SnapshotBatch {
+    type Error = String;
+
+    fn try_from(data: BatchData) -> Result<Self, String> {
+        let metadata = |index: usize| index < data.metadata.len();
+        let metric = |index: usize| index < data.metrics.len();
+
+        let valid = data.metrics.iter().all(|(_, _, index)| metadata(*index))
+            && data.snapshots.iter().all(|s| {
+                metadata(s.metadata)
+                    && s.counters.iter().all(|(index, _)| metric(*index))
+                    && s.gauges.iter().all(|(index, _)| metric(*index))
+                    && s.histograms.iter().all(|(index, _)| metric(*index))
+                    && s.stats.iter().all(|stats| metric(stats.metric))
+                    && s.sketches.iter().all(|(index, _)| metric(*index))
+                    && s.cardinalities.iter().all(|(index, _, _)| metric(*index))
+                    && s.top_k.iter().all(|(index, _, _)| metric(*index))
+            });
+        if !valid {
+            return Err("snapshot batch refers to a metric or metadata it does not contain".into());
+        }
+
+        Ok(Self {
+            metadata: data.metadata,
+            metrics: data.metrics,
+            snapshots: data.snapshots,
+            interner: Interner::default(),
+        })
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct BatchedSnapshot {
+    systemtime: SystemTime,
+    metadata: usize,
+    counters: Vec<(usize, u64)>,
+    gauges: Vec<(usize, i64)>,
+    histograms: Vec<(usize, SparseHistogram)>,
+    stats: Vec<BatchedStats>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    sketches: Vec<(usize, DDSketch)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    cardinalities: Vec<(usize, HyperLogLog, u64)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    top_k: Vec<(usize, u64, Vec<HeavyHitter>)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    events: Vec<Event>,
+    /// The [`SEQUENCE`] number, which is kept out of the interned metadata
+    /// since it differs for every snapshot.
+    #[cfg_attr(feature = "serde", serde(default))]
+    sequence: Option<u64>,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct BatchedStats {
+    metric: usize,
+    min: Option<u64>,
+    max: Option<u64>,
+    sum: u64,
+    count: u64,
+}
+
+/// Lookup tables for the interned values. These are not serialized and are
+/// rebuilt on demand after a batch has been deserialized.
+#[derive(Clone, Default)]
+struct Interner {
+    metadata: HashMap<Vec<(String, String)>, usize>,
+    metrics: HashMap<(String, MetricType, usize), usize>,
+}
+
+impl SnapshotBatch {
+    /// Create a new, empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of snapshots in this batch.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Indicates whether this batch contains any snapshots.
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Add a snapshot to the end of this batch.
+    pub fn push(&mut self, snapshot: Snapshot) {
+        self.rebuild_interner();
+
+        let mut metadata = snapshot.metadata;
+        let sequence = metadata.get(SEQUENCE).and_then(|s| s.parse().ok());
+        if sequence.is_some() {
+            metadata.remove(SEQUENCE);
+        }
+        let metadata = self.intern_metadata(metadata);
+
+        let counters = snapshot
+            .counters
+            .into_iter()
+            .map(|c| {
+                (
+                    self.intern_metric(c.name, c.metric_type, c.metadata),
+                    c.value,
+                )
+            })
+            .collect();
+        let gauges = snapshot
+            .gauges
+            .into_iter()
+            .map(|g| {
+                (
+                    self.intern_metric(g.name, g.metric_type, g.metadata),
+                    g.value,
+                )
+            })
+            .collect();
+        let histograms = snapshot
+            .histograms
+            .into_iter()
+            .map(|h| {
+                let sparse = SparseHistogram::from(&h.value);
+                (
+                    self.intern_metric(h.name, h.metric_type, h.metadata),
+                    sparse,
+                )
+            })
+            .collect();
+        let stats = snapshot
+            .stats
+            .into_iter()
+            .map(|s| BatchedStats {
+                metric: self.intern_metric(s.name, s.metric_type, s.metadata),
+                min: s.min,
+                max: s.max,
+                sum: s.sum,
+                count: s.count,
+            })
+            .collect();
+        let sketches = snapshot
+            .sketches
+            .into_iter()
+            .map(|s| {
+                (
+                    self.intern_metric(s.name, s.metric_type, s.metadata),
+                    s.value,
+                )
+            })
+            .collect();
+        let cardinalities = snapshot
+            .cardinalities
+            .into_iter()
+            .map(|c| {
+                (
+                    self.intern_metric(c.name, c.metric_type, c.metadata),
+                    c.value,
+                    c.estimate,
+                )
+            })
+            .collect();
+        let top_k = snapshot
+            .top_k
+            .into_iter()
+            .map(|t| {
+                (
+                    self.intern_metric(t.name, t.metric_type, t.metadata),
+                    t.k,
+                    t.entries,
+                )
+            })
+            .collect();
+
+        self.snapshots.push(BatchedSnapshot {
+            systemtime: snapshot.systemtime,
+            metadata,
+            counters,
+            gauges,
+            histograms,
+            stats,
+            sketches,
+            cardinalities,
+            top_k,
+            events: snapshot.events,
+            sequence,
+        });
+    }
+
+    /// Iterate over the snapshots within this batch.
+    pub fn iter(&self) -> impl Iterator<Item = Snapshot> + '_ {
+        self.snapshots.iter().map(|s| self.reconstruct(s))
+    }
+
+    /// Deserialize a batch that was serialized with
+    /// [`Snapshot::to_msgpack`].
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self, DeserializeMsgpackError> {
+        rmp_serde::from_slice(bytes)
+    }
+
+    /// Deserialize a batch that was serialized with [`Snapshot::to_json`].
+    #[cfg(all(feature = "serde", feature = "json"))]
+    pub fn from_json(bytes: &[u8]) -> Result<Self, JsonError> {
+        serde_json::from_slice(bytes)
+    }
+
+    /// Deserialize a batch that was serialized with [`Snapshot::to_cbor`].
+    #[cfg(all(feature = "serde", feature = "cbor"))]
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, DeserializeCborError<std::io::Error>> {
+        ciborium::from_reader(bytes)
+    }
+
+    /// Deserialize a batch that was serialized with [`Snapshot::to_postcard`].
+    #[cfg(all(feature = "serde", feature = "postcard"))]
+    pub fn from_postcard(bytes: &[u8]) -> Result<Self, PostcardError> {
+        postcard::from_bytes(bytes)
+    }
+
+    fn reconstruct(&self, batched: &BatchedSnapshot) -> Snapshot {
+        let metric = |index: usize| {
+            let (name, metric_type, metadata) = &self.metrics[index];
+            (name.clone(), *metric_type, self.metadata[*metadata].clone())
+        };
+
+        let mut snapshot = Snapshot::new();
+        snapshot.systemtime = batched.systemtime;
+        snapshot.metadata = self.metadata[batched.metadata].clone();
+        if let Some(sequence) = batched.sequence {
+            snapshot
+                .metadata
+                .insert(SEQUENCE.to_string(), sequence.to_string());
+        }
+
+        for (index, value) in &batched.counters {
+            let (name, metric_type, metadata) = metric(*index);
+            snapshot.counters.push(Counter {
+                name,
+                metric_type,
+                value: *value,
+                metadata,
+            });
+        }
+
+        for (index, value) in &batched.gauges {
+            let (name, metric_type, metadata) = metric(*index);
+            snapshot.gauges.push(Gauge {
+                name,
+                metric_type,
+                value: *value,
+                metadata,
+            });
+        }
+
+        for (index, value) in &batched.histograms {
+            let (name, metric_type, metadata) = metric(*index);
+            snapshot.histograms.push(Histogram {
+                name,
+                metric_type,
+                value: histogram::Histogram::from(value),
+                metadata,
+            });
+        }
+
+        for stats in &batched.stats {
+            let (name, metric_type, metadata) = metric(stats.metric);
+            snapshot.stats.push(Stats {
+                name,
+                metric_type,
+                min: stats.min,
+                max: stats.max,
+                sum: stats.sum,
+                count: stats.count,
+                metadata,
+            });
+        }
+
+        for (index, value) in &batched.sketches {
+            let (name, metric_type, metadata) = metric(*index);
+            snapshot.sketches.push(Sketch {
+                name,
+                metric_type,
+                value: value.clone(),
+                metadata,
+            });
+        }
+
+        for (index, value, estimate) in &batched.cardinalities {
+            let (name, metric_type, metadata) = metric(*index);
+            snapshot.cardinalities.push(Cardinality {
+                name,
+                metric_type,
+                value: value.clone(),
+                estimate: *estimate,
+                metadata,
+            });
+        }
+
+        for (index, k, entries) in &batched.top_k {
+            let (name, metric_type, metadata) = metric(*index);
+            snapshot.top_k.push(TopK {
+                name,
+                metric_type,
+                k: *k,
+                entries: entries.clone(),
+                metadata,
+            });
+        }
+
+        snapshot.events = batched.events.clone();
+
+        snapshot
+    }
+
+    fn intern_metadata(&mut self, metadata: HashMap<String, String>) -> usize {
+        let mut key: Vec<(String, String)> = metadata.clone().into_iter().collect();
+        key.sort();
+
+        *self.interner.metadata.entry(key).or_insert_with(|| {
+            self.metadata.push(metadata);
+            self.metadata.len() - 1
+        })
+    }
+
+    fn intern_metric(
+        &mut self,
+        name: String,
+        metric_type: MetricType,
+        metadata: HashMap<String, String>,
+    ) -> usize {
+        let metadata = self.intern_metadata(metadata);
+
+        *self
+            .interner
+            .metrics
+            .entry((name.clone(), metric_type, metadata))
+            .or_insert_with(|| {
+                self.metrics.push((name, metric_type, metadata));
+                self.metrics.len() - 1
+            })
+    }
+
+    /// Rebuild the lookup tables if this batch was deserialized.
+    fn rebuild_interner(&mut self) {
+        if self.interner.metadata.len() == self.metadata.len() {
+            return;
+        }
+
+        for (index, metadata) in self.metadata.iter().enumerate() {
+            let mut key: Vec<(String, String)> = metadata.clone().into_iter().collect();
+            key.sort();
+            self.interner.metadata.entry(key).or_insert(index);
+        }
+
+        for (index, metric) in self.metrics.iter().enumerate() {
+            self.interner.metrics.entry(metric.clone()).or_insert(index);
+        }
+    }
+}
+
+impl Extend<Snapshot> for SnapshotBatch {
+    fn extend<T: IntoIterator<Item = Snapshot>>(&mut self, iter: T) {
+        for snapshot in iter {
+            self.push(snapshot);
+        }
+    }
+}
+
+impl FromIterator<Snapshot> for SnapshotBatch {
+    fn from_iter<T: IntoIterator<Item = Snapshot>>(iter: T) -> Self {
+        let mut batch = Self::new();
+        batch.extend(iter);
+        batch
+    }
+}
+
+impl From<Vec<Snapshot>> for SnapshotBatch {
+    fn from(snapshots: Vec<Snapshot>) -> Self {
+        Self::from_iter(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn build_snapshots() -> Vec<Snapshot> {
+        let metadata = HashMap::from([("unit".to_string(), "bytes".to_string())]);
+
+        (0..10)
+            .map(|i| {
+                let mut histogram = histogram::Histogram::new(4, 10).unwrap();
+                histogram.add(i, 3).unwrap();
+
+                let mut snapshot = Snapshot::new();
+                snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_secs(i);
+                snapshot
+                    .metadata
+                    .insert("source".to_string(), "test".to_string());
+                snapshot.counters.push(Counter {
+                    name: "counter".to_string(),
+                    metric_type: MetricType::Counter,
+                    value: i * 10,
+                    metadata: metadata.clone(),
+                });
+                snapshot.gauges.push(Gauge {
+                    name: "gauge".to_string(),
+                    metric_type: MetricType::Gauge,
+                    value: -(i as i64),
+                    metadata: HashMap::new(),
+                });
+                snapshot.histograms.push(Histogram {
+                    name: "histogram".to_string(),
+                    metric_type: MetricType::Histogram,
+                    value: histogram,
+                    metadata: metadata.clone(),
+                });
+                snapshot
+            })
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut snapshots = build_snapshots();
+        snapshots[1].events.push(Event::new("deploy"));
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        sketch.add(5.0);
+        snapshots[2].sketches.push(Sketch::new("sketch", sketch));
+        let batch = SnapshotBatch::from(snapshots.clone());
+
+        assert_eq!(batch.len(), snapshots.len());
+        assert_eq!(batch.metrics.len(), 4);
+        assert_eq!(batch.metadata.len(), 3);
+
+        for (original, rebuilt) in snapshots.iter().zip(batch.iter()) {
+            assert_eq!(original.systemtime, rebuilt.systemtime);
+            assert_eq!(original.metadata, rebuilt.metadata);
+            assert_eq!(original.counters[0].value, rebuilt.counters[0].value);
+            assert_eq!(original.counters[0].metadata, rebuilt.counters[0].metadata);
+            assert_eq!(original.gauges[0].value, rebuilt.gauges[0].value);
+            assert_eq!(original.histograms[0].value, rebuilt.histograms[0].value);
+            assert_eq!(original.events, rebuilt.events);
+            assert_eq!(original.sketches.len(), rebuilt.sketches.len());
+        }
+        let rebuilt = batch.iter().nth(2).unwrap();
+        assert_eq!(rebuilt.sketches[0].value, snapshots[2].sketches[0].value);
+    }
+
+    #[test]
+    fn sequence() {
+        let mut snapshots = build_snapshots();
+        for (i, snapshot) in snapshots.iter_mut().enumerate() {
+            snapshot
+                .metadata
+                .insert(SEQUENCE.to_string(), i.to_string());
+        }
+        let batch = SnapshotBatch::from(snapshots.clone());
+
+        // The snapshots share one metadata map despite their sequence numbers.
+        assert_eq!(batch.metadata.len(), 3);
+
+        for (original, rebuilt) in snapshots.iter().zip(batch.iter()) {
+            assert_eq!(original.metadata, rebuilt.metadata);
+            assert_eq!(original.sequence(), rebuilt.sequence());
+        }
+    }
+
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    #[test]
+    fn msgpack_is_compact() {
+        let snapshots = build_snapshots();
+        let batch = SnapshotBatch::from(snapshots.clone());
+
+        let individual: usize = snapshots
+            .iter()
+            .map(|s| Snapshot::to_msgpack(s).unwrap().len())
+            .sum();
+        let encoded = Snapshot::to_msgpack(&batch).unwrap();
+        assert!(encoded.len() < individual);
+
+        let mut decoded = SnapshotBatch::from_msgpack(&encoded).unwrap();
+        assert_eq!(decoded.len(), snapshots.len());
+
+        // Pushing after deserialization reuses the existing interned values.
+        decoded.push(snapshots[0].clone());
+        assert_eq!(decoded.metrics.len(), 3);
+        assert_eq!(decoded.len(), snapshots.len() + 1);
+    }
+
+    #[cfg(all(feature = "serde", feature = "json"))]
+    #[test]
+    fn out_of_range_index() {
+        let batch = SnapshotBatch::from(build_snapshots());
+        let mut value = serde_json::to_value(&batch).unwrap();
+        assert!(SnapshotBatch::from_json(value.to_string().as_bytes()).is_ok());
+
+        value["snapshots"][0]["counters"][0][0] = 99.into();
+        assert!(SnapshotBatch::from_json(value.to_string().as_bytes()).is_err());
+
+        let mut value = serde_json::to_value(&batch).unwrap();
+        value["metrics"][0][2] = 99.into();
+        assert!(SnapshotBatch::from_json(value.to_string().as_bytes()).is_err());
+    }
+
+    #[cfg(all(feature = "serde", feature = "cbor", feature = "postcard"))]
+    #[test]
+    fn cbor_and_postcard() {
+        let snapshots = build_snapshots();
+        let batch = SnapshotBatch::from(snapshots.clone());
+
+        let decoded = SnapshotBatch::from_cbor(&Snapshot::to_cbor(&batch).unwrap()).unwrap();
+        assert_eq!(decoded.len(), snapshots.len());
+
+        let decoded =
+            SnapshotBatch::from_postcard(&Snapshot::to_postcard(&batch).unwrap()).unwrap();
+        assert_eq!(decoded.len(), snapshots.len());
+
+        for (original, rebuilt) in snapshots.iter().zip(decoded.iter()) {
+            assert_eq!(original.systemtime, rebuilt.systemtime);
+            assert_eq!(original.histograms[0].value, rebuilt.histograms[0].value);
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
+
+#[cfg(feature = "msgpack")]
+use rmp_serde::decode::Error as DeserializeMsgpackError;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+#[cfg(feature = "json")]
+use serde_json::Error as JsonError;
+
+use crate::snapshot::{
+    Cardinality, Counter, Event, Gauge, HeavyHitter, Histogram, MetricType, Sketch, Snapshot,
+    SnapshotIndex, Stats, TopK,
+};
+
+/// Metadata whose keys and values borrow from the input where they can.
+pub type MetadataRef<'a> = HashMap<Cow<'a, str>, Cow<'a, str>>;
+
+/// A snapshot deserialized without copying its strings, which borrows the
+/// names and metadata of its metrics and events from the input.
+///
+/// This is for consumers which only read snapshots, such as an analyzer
+/// scanning a large recording, where allocating a `String` for every name
+/// and metadata entry dominates the time spent decoding. Strings which can't
+/// be borrowed, such as JSON strings with escapes, are copied. The values of
+/// histograms, sketches, and cardinalities are always copied.
+///
+/// Use [`SnapshotRef::into_owned`] to keep a snapshot beyond the life of the
+/// input.
+#[derive(Clone, Deserialize)]
+#[non_exhaustive]
+pub struct SnapshotRef<'a> {
+    #[serde(deserialize_with = "crate::timestamp::deserialize")]
+    pub systemtime: SystemTime,
+
+    #[serde(default, borrow, deserialize_with = "metadata")]
+    pub metadata: MetadataRef<'a>,
+
+    #[serde(borrow)]
+    pub counters: Vec<CounterRef<'a>>,
+    #[serde(borrow)]
+    pub gauges: Vec<GaugeRef<'a>>,
+    #[serde(borrow)]
+    pub histograms: Vec<HistogramRef<'a>>,
+
+    #[serde(default, borrow)]
+    pub stats: Vec<StatsRef<'a>>,
+
+    #[serde(default, borrow)]
+    pub sketches: Vec<SketchRef<'a>>,
+
+    #[serde(default, borrow)]
+    pub cardinalities: Vec<CardinalityRef<'a>>,
+
+    #[serde(default, borrow)]
+    pub top_k: Vec<TopKRef<'a>>,
+
+    #[serde(default, borrow)]
+    pub events: Vec<EventRef<'a>>,
+}
+
+/// A [`Counter`] which borrows its name and metadata.
+#[derive(Clone, Deserialize)]
+#[non_exhaustive]
+pub struct CounterRef<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(default = "MetricType::counter")]
+    pub metric_type: MetricType,
+    pub value: u64,
+    #[serde(borrow, deserialize_with = "metadata")]
+    pub metadata: MetadataRef<'a>,
+}
+
+/// A [`Gauge`] which borrows its name and metadata.
+#[derive(Clone, Deserialize)]
+#[non_exhaustive]
+pub struct GaugeRef<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(default = "MetricType::gauge")]
+    pub metric_type: MetricType,
+    pub value: i64,
+    #[serde(borrow, deserialize_with = "metadata")]
+    pub metadata: MetadataRef<'a>,
+}
+
+/// A [`Histogram`] which borrows its name and metadata.
+#[derive(Clone, Deserialize)]
+#[non_exhaustive]
+pub struct HistogramRef<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(default = "MetricType::histogram")]
+    pub metric_type: MetricType,
+    pub value: histogram::Histogram,
+    #[serde(borrow, deserialize_with = "metadata")]
+    pub metadata: MetadataRef<'a>,
+}
+
+/// A [`Stats`] which borrows its name and metadata.
+#[derive(Clone, Deserialize)]
+#[non_exhaustive]
+pub struct StatsRef<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(default = "MetricType::summary")]
+    pub metric_type: MetricType,
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+    pub sum: u64,
+    pub count: u64,
+    #[serde(borrow, deserialize_with = "metadata")]
+    pub metadata: MetadataRef<'a>,
+}
+
+/// A [`Sketch`] which borrows its name and metadata.
+#[derive(Clone, Deserialize)]
+#[non_exhaustive]
+pub struct SketchRef<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(default = "MetricType::sketch")]
+    pub metric_type: MetricType,
+    pub value: crate::DDSketch,
+    #[serde(borrow, deserialize_with = "metadata")]
+    pub metadata: MetadataRef<'a>,
+}
+
+/// A [`Cardinality`] which borrows its name and metadata.
+#[derive(Clone, Deserialize)]
+#[non_exhaustive]
+pub struct CardinalityRef<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(default = "MetricType::cardinality")]
+    pub metric_type: MetricType,
+    pub value: crate::HyperLogLog,
+    pub estimate: u64,
+    #[serde(borrow, deserialize_with = "metadata")]
+    pub metadata: MetadataRef<'a>,
+}
+
+/// A [`TopK`] which borrows its name, keys, and metadata.
+#[derive(Clone, Deserialize)]
+#[non_exhaustive]
+pub struct TopKRef<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(default = "MetricType::top_k")]
+    pub metric_type: MetricType,
+    pub k: u64,
+    #[serde(borrow)]
+    pub entries: Vec<HeavyHitterRef<'a>>,
+    #[serde(borrow, deserialize_with = "metadata")]
+    pub metadata: MetadataRef<'a>,
+}
+
+/// A [`HeavyHitter`] which borrows its key.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct HeavyHitterRef<'a> {
+    #[serde(borrow)]
+    pub key: Cow<'a, str>,
+    pub count: u64,
+    pub error: u64,
+}
+
+/// An [`Event`] which borrows its name and metadata.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct EventRef<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(deserialize_with = "crate::timestamp::deserialize")]
+    pub systemtime: SystemTime,
+    #[serde(borrow, deserialize_with = "metadata")]
+    pub metadata: MetadataRef<'a>,
+}
+
+impl<'a> SnapshotRef<'a> {
+    /// Deserialize a snapshot that was serialized with
+    /// [`Snapshot::to_msgpack`], borrowing its strings from `bytes`.
+    #[cfg(feature = "msgpack")]
+    pub fn from_msgpack(bytes: &'a [u8]) -> Result<Self, DeserializeMsgpackError> {
+        rmp_serde::from_slice(bytes)
+    }
+
+    /// Deserialize a snapshot that was serialized with [`Snapshot::to_json`],
+    /// borrowing its strings from `bytes` where they contain no escapes.
+    #[cfg(feature = "json")]
+    pub fn from_json(bytes: &'a [u8]) -> Result<Self, JsonError> {
+        serde_json::from_slice(bytes)
+    }
+
+    /// Copy the borrowed strings to produce a [`Snapshot`].
+    pub fn into_owned(self) -> Snapshot {
+        Snapshot {
+            systemtime: self.systemtime,
+            metadata: owned(self.metadata),
+            counters: self.counters.into_iter().map(|c| c.into_owned()).collect(),
+            gauges: self.gauges.into_iter().map(|g| g.into_owned()).collect(),
+            histograms: self
+                .histograms
+                .into_iter()
+                .map(|h| h.into_owned())
+                .collect(),
+            stats: self.stats.into_iter().map(|s| s.into_owned()).collect(),
+            sketches: self.sketches.into_iter().map(|s| s.into_owned()).collect(),
+            cardinalities: self
+                .cardinalities
+                .into_iter()
+                .map(|c| c.into_owned())
+                .collect(),
+            top_k: self.top_k.into_iter().map(|t| t.into_owned()).collect(),
+            events: self.events.into_iter().map(|e| e.into_owned()).collect(),
+            index: SnapshotIndex::default(),
+        }
+    }
+}
+
+impl CounterRef<'_> {
+    /// Copy the borrowed strings to produce a [`Counter`].
+    pub fn into_owned(self) -> Counter {
+        Counter {
+            name: self.name.into_owned(),
+            metric_type: self.metric_type,
+            value: self.value,
+            metadata: owned(self.metadata),
+        }
+    }
+}
+
+impl GaugeRef<'_> {
+    /// Copy the borrowed strings to produce a [`Gauge`].
+    pub fn into_owned(self) -> Gauge {
+        Gauge {
+            name: self.name.into_owned(),
+            metric_type: self.metric_type,
+            value: self.value,
+            metadata: owned(self.metadata),
+        }
+    }
+}
+
+impl HistogramRef<'_> {
+    /// Copy the borrowed strings to produce a [`Histogram`].
+    pub fn into_owned(self) -> Histogram {
+        Histogram {
+            name: self.name.into_owned(),
+            metric_type: self.metric_type,
+            value: self.value,
+            metadata: owned(self.metadata),
+        }
+    }
+}
+
+impl StatsRef<'_> {
+    /// Copy the borrowed strings to produce a [`Stats`].
+    pub fn into_owned(self) -> Stats {
+        Stats {
+            name: self.name.into_owned(),
+            metric_type: self.metric_type,
+            min: self.min,
+            max: self.max,
+            sum: self.sum,
+            count: self.count,
+            metadata: owned(self.metadata),
+        }
+    }
+}
+
+impl SketchRef<'_> {
+    /// Copy the borrowed strings to produce a [`Sketch`].
+    pub fn into_owned(self) -> Sketch {
+        Sketch {
+            name: self.name.into_owned(),
+            metric_type: self.metric_type,
+            value: self.value,
+            metadata: owned(self.metadata),
+        }
+    }
+}
+
+impl CardinalityRef<'_> {
+    /// Copy the borrowed strings to produce a [`Cardinality`].
+    pub fn into_owned(self) -> Cardinality {
+        Cardinality {
+            name: self.name.into_owned(),
+            metric_type: self.metric_type,
+            value: self.value,
+            estimate: self.estimate,
+            metadata: owned(self.metadata),
+        }
+    }
+}
+
+impl TopKRef<'_> {
+    /// Copy the borrowed strings to produce a [`TopK`].
+    pub fn into_owned(self) -> TopK {
+        TopK {
+            name: self.name.into_owned(),
+            metric_type: self.metric_type,
+            k: self.k,
+            entries: self.entries.into_iter().map(|e| e.into_owned()).collect(),
+            metadata: owned(self.metadata),
+        }
+    }
+}
+
+impl HeavyHitterRef<'_> {
+    /// Copy the borrowed key to produce a [`HeavyHitter`].
+    pub fn into_owned(self) -> HeavyHitter {
+        HeavyHitter {
+            key: self.key.into_owned(),
+            count: self.count,
+            error: self.error,
+        }
+    }
+}
+
+impl EventRef<'_> {
+    /// Copy the borrowed strings to produce an [`Event`].
+    pub fn into_owned(self) -> Event {
+        Event {
+            name: self.name.into_owned(),
+            systemtime: self.systemtime,
+            metadata: owned(self.metadata),
+        }
+    }
+}
+
+fn owned(metadata: MetadataRef<'_>) -> HashMap<String, String> {
+    metadata
+        .into_iter()
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect()
+}
+
+/// Deserialize metadata, borrowing each key and value when the input allows
+/// it. Serde always copies a `Cow` which is inside a collection, so the map
+/// is visited by hand.
+fn metadata<'de: 'a, 'a, D>(deserializer: D) -> Result<MetadataRef<'a>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct MetadataVisitor;
+
+    impl<'de> Visitor<'de> for MetadataVisitor {
+        type Value = MetadataRef<'de>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map of strings")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut metadata = HashMap::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some((key, value)) = map.next_entry::<Str, Str>()? {
+                metadata.insert(key.0, value.0);
+            }
+            Ok(metadata)
+        }
+    }
+
+    deserializer.deserialize_map(MetadataVisitor)
+}
+
+/// A string which borrows from the input when it can.
+struct Str<'a>(Cow<'a, str>);
+
+impl<'de> Deserialize<'de> for Str<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StrVisitor;
+
+        impl<'de> Visitor<'de> for StrVisitor {
+            type Value = Str<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E> {
+                Ok(Str(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(Str(Cow::Owned(v.to_string())))
+            }
+
+            fn visit_string<E>(self, v: String) -> Result<Self::Value, E> {
+                Ok(Str(Cow::Owned(v)))
+            }
+        }
+
+        deserializer.deserialize_str(StrVisitor)
+    }
+}
+
+#[cfg(all(test, any(feature = "msgpack", feature = "json")))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot::builder()
+            .systemtime(SystemTime::UNIX_EPOCH + Duration::from_secs(1))
+            .metadata("source", "test")
+            .counter("requests", 5, &[("op", "get")])
+            .gauge("connections", -2, &[])
+            .histogram("latency", histogram::Histogram::new(2, 8).unwrap(), &[])
+            .top_k("keys", 2, vec![HeavyHitter::new("a", 3, 0)], &[])
+            .event(Event::new("deploy").metadata("note", "line\nbreak"))
+            .build()
+            .unwrap()
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack() {
+        let snapshot = snapshot();
+        let bytes = Snapshot::to_msgpack(&snapshot).unwrap();
+        let borrowed = SnapshotRef::from_msgpack(&bytes).unwrap();
+
+        assert!(matches!(
+            borrowed.counters[0].name,
+            Cow::Borrowed("requests")
+        ));
+        assert!(matches!(
+            borrowed.counters[0].metadata.get("op"),
+            Some(Cow::Borrowed("get"))
+        ));
+        assert!(matches!(
+            borrowed.top_k[0].entries[0].key,
+            Cow::Borrowed("a")
+        ));
+        assert!(borrowed.into_owned() == snapshot);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn recording() {
+        use crate::{MsgpackIndex, MsgpackRefs, MsgpackWriter};
+
+        let first = snapshot();
+        let mut repeat = first.clone();
+        repeat.systemtime += Duration::from_secs(1);
+        repeat.events.clear();
+        let mut last = repeat.clone();
+        last.systemtime += Duration::from_secs(1);
+        last.counters[0].value += 1;
+
+        let mut writer = MsgpackWriter::with_index(Vec::new(), MsgpackIndex::Footer).dedup(true);
+        for snapshot in [&first, &repeat, &repeat, &last] {
+            writer.push(snapshot).unwrap();
+        }
+        let recording = writer.finalize().unwrap();
+
+        let snapshots: Vec<Snapshot> = MsgpackRefs::new(&recording)
+            .unwrap()
+            .map(|s| s.unwrap().into_owned())
+            .collect();
+        assert_eq!(snapshots.len(), 4);
+        assert!(snapshots[0] == first);
+        assert!(snapshots[1] == repeat && snapshots[2] == repeat);
+        assert!(snapshots[3] == last);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        let snapshot = snapshot();
+        let bytes = Snapshot::to_json(&snapshot).unwrap();
+        let borrowed = SnapshotRef::from_json(&bytes).unwrap();
+
+        assert!(matches!(
+            borrowed.gauges[0].name,
+            Cow::Borrowed("connections")
+        ));
+        // strings with escapes can't be borrowed from JSON
+        let event = &borrowed.events[0];
+        assert!(matches!(event.metadata.get("note"), Some(Cow::Owned(_))));
+        assert!(borrowed.into_owned() == snapshot);
+    }
+}
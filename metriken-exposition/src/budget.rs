@@ -0,0 +1,280 @@
+use std::fmt::Write;
+
+use crate::Snapshot;
+
+/// The snapshot metadata key listing the metrics dropped to keep a snapshot
+/// within its [`Budget`], such as `histograms=4,counters=120`.
+pub const BUDGET_DROPPED: &str = "budget_dropped";
+
+/// A step taken to reduce the size of a snapshot which is over its
+/// [`Budget`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Degradation {
+    /// Drop every histogram.
+    Histograms,
+    /// Drop every stats metric.
+    Stats,
+    /// Drop every sketch.
+    Sketches,
+    /// Drop every cardinality metric.
+    Cardinalities,
+    /// Drop every top-k metric.
+    TopK,
+    /// Keep only the counters with the largest values.
+    TopCounters(usize),
+    /// Keep only the gauges with the largest absolute values.
+    TopGauges(usize),
+}
+
+impl Degradation {
+    /// Apply the step, returning the name of the metrics dropped and how
+    /// many were.
+    fn apply(self, snapshot: &mut Snapshot) -> (&'static str, usize) {
+        match self {
+            Self::Histograms => ("histograms", snapshot.histograms.drain(..).count()),
+            Self::Stats => ("stats", snapshot.stats.drain(..).count()),
+            Self::Sketches => ("sketches", snapshot.sketches.drain(..).count()),
+            Self::Cardinalities => ("cardinalities", snapshot.cardinalities.drain(..).count()),
+            Self::TopK => ("top_k", snapshot.top_k.drain(..).count()),
+            Self::TopCounters(k) => {
+                let values: Vec<_> = snapshot.counters.iter().map(|c| c.value as i128).collect();
+                ("counters", retain_top(&mut snapshot.counters, &values, k))
+            }
+            Self::TopGauges(k) => {
+                let values: Vec<_> = snapshot
+                    .gauges
+                    .iter()
+                    .map(|g| (g.value as i128).abs())
+                    .collect();
+                ("gauges", retain_top(&mut snapshot.gauges, &values, k))
+            }
+        }
+    }
+}
+
+/// Keep the `k` entries with the largest values, in their original order,
+/// returning how many were dropped.
+fn retain_top<T>(entries: &mut Vec<T>, values: &[i128], k: usize) -> usize {
+    if entries.len() <= k {
+        return 0;
+    }
+
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by(|a, b| values[*b].cmp(&values[*a]));
+
+    let mut keep = vec![false; entries.len()];
+    for index in order.into_iter().take(k) {
+        keep[index] = true;
+    }
+
+    let before = entries.len();
+    let mut keep = keep.into_iter();
+    entries.retain(|_| keep.next().unwrap_or(false));
+    before - entries.len()
+}
+
+/// A limit on the serialized size of a snapshot, for deployments with a
+/// fixed uplink budget.
+///
+/// The size of a snapshot is measured with a function for the target format,
+/// such as the length of its msgpack encoding. When a snapshot is over the
+/// limit, the configured [`Degradation`] steps are applied in order until it
+/// fits, and the metrics dropped are listed in the [`BUDGET_DROPPED`]
+/// metadata key. If the snapshot is still over the limit after every step,
+/// it is left as it is.
+///
+/// ```
+/// # #[cfg(all(feature = "snapshotter", feature = "protobuf"))]
+/// # {
+/// # use metriken_exposition::{Budget, Degradation, SnapshotterBuilder};
+/// let snapshotter = SnapshotterBuilder::new()
+///     .budget(
+///         Budget::new(64 * 1024, |snapshot| snapshot.to_protobuf().len())
+///             .degrade(Degradation::Histograms)
+///             .degrade(Degradation::TopCounters(100)),
+///     )
+///     .build();
+/// # }
+/// ```
+pub struct Budget {
+    bytes: usize,
+    size: Box<dyn Fn(&Snapshot) -> usize + Send + Sync>,
+    steps: Vec<Degradation>,
+}
+
+impl Budget {
+    /// Limit snapshots to `bytes`, measured with the `size` function. No
+    /// degradation steps are configured.
+    pub fn new(bytes: usize, size: impl Fn(&Snapshot) -> usize + Send + Sync + 'static) -> Self {
+        Self {
+            bytes,
+            size: Box::new(size),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Add a step to take if the snapshot is still over the limit after the
+    /// steps added before it.
+    pub fn degrade(mut self, step: Degradation) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Reduce the size of the snapshot until it is within the limit or every
+    /// step has been taken. The size includes the [`BUDGET_DROPPED`]
+    /// metadata, which is updated after each step that drops metrics.
+    pub fn apply(&self, snapshot: &mut Snapshot) {
+        let mut dropped = String::new();
+        let mut steps = self.steps.iter();
+
+        while (self.size)(snapshot) > self.bytes {
+            let Some(step) = steps.next() else {
+                break;
+            };
+
+            let (metrics, count) = step.apply(snapshot);
+            if count > 0 {
+                if !dropped.is_empty() {
+                    dropped.push(',');
+                }
+                let _ = write!(dropped, "{metrics}={count}");
+                snapshot
+                    .metadata
+                    .insert(BUDGET_DROPPED.to_string(), dropped.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let histogram = histogram::Histogram::new(2, 8).unwrap();
+        Snapshot::builder()
+            .counter("a", 5, &[])
+            .counter("b", 50, &[])
+            .counter("c", 1, &[])
+            .counter("d", 20, &[])
+            .gauge("e", -7, &[])
+            .histogram("f", histogram.clone(), &[])
+            .histogram("g", histogram, &[])
+            .build()
+            .unwrap()
+    }
+
+    /// A size of 10 bytes for each counter and gauge, and 100 for each
+    /// histogram.
+    fn size(snapshot: &Snapshot) -> usize {
+        10 * (snapshot.counters.len() + snapshot.gauges.len()) + 100 * snapshot.histograms.len()
+    }
+
+    #[test]
+    fn degrades_in_order() {
+        let budget = Budget::new(100, size)
+            .degrade(Degradation::Histograms)
+            .degrade(Degradation::TopCounters(2))
+            .degrade(Degradation::TopGauges(0));
+
+        // dropping the histograms is enough
+        let mut within = snapshot();
+        budget.apply(&mut within);
+        assert!(within.histograms.is_empty());
+        assert_eq!(within.counters.len(), 4);
+        assert_eq!(within.get_metadata(BUDGET_DROPPED), Some("histograms=2"));
+
+        let budget = Budget::new(30, size)
+            .degrade(Degradation::Histograms)
+            .degrade(Degradation::TopCounters(2))
+            .degrade(Degradation::TopGauges(0));
+
+        let mut snapshot = snapshot();
+        budget.apply(&mut snapshot);
+        let names: Vec<_> = snapshot.counters.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["b", "d"]);
+        // the gauge was not dropped, as the snapshot fit without that step
+        assert_eq!(snapshot.gauges.len(), 1);
+        assert_eq!(
+            snapshot.get_metadata(BUDGET_DROPPED),
+            Some("histograms=2,counters=2")
+        );
+    }
+
+    #[test]
+    fn sketches() {
+        let mut snapshot = snapshot();
+        snapshot
+            .sketches
+            .push(crate::Sketch::new("h", crate::DDSketch::new(0.01).unwrap()));
+
+        Budget::new(0, |snapshot| snapshot.sketches.len())
+            .degrade(Degradation::Sketches)
+            .apply(&mut snapshot);
+        assert!(snapshot.sketches.is_empty());
+        assert_eq!(snapshot.get_metadata(BUDGET_DROPPED), Some("sketches=1"));
+    }
+
+    #[test]
+    fn cardinalities() {
+        let mut snapshot = snapshot();
+        snapshot.cardinalities.push(crate::Cardinality::new(
+            "clients",
+            crate::HyperLogLog::new(4).unwrap(),
+        ));
+
+        Budget::new(0, |snapshot| snapshot.cardinalities.len())
+            .degrade(Degradation::Cardinalities)
+            .apply(&mut snapshot);
+        assert!(snapshot.cardinalities.is_empty());
+        assert_eq!(
+            snapshot.get_metadata(BUDGET_DROPPED),
+            Some("cardinalities=1")
+        );
+    }
+
+    #[test]
+    fn top_k() {
+        let mut snapshot = snapshot();
+        let entries = vec![crate::HeavyHitter::new("/", 10, 0)];
+        snapshot.top_k.push(crate::TopK::new("paths", 1, entries));
+
+        Budget::new(0, |snapshot| snapshot.top_k.len())
+            .degrade(Degradation::TopK)
+            .apply(&mut snapshot);
+        assert!(snapshot.top_k.is_empty());
+        assert_eq!(snapshot.get_metadata(BUDGET_DROPPED), Some("top_k=1"));
+    }
+
+    #[test]
+    fn counts_dropped_metadata() {
+        let size = |snapshot: &Snapshot| {
+            size(snapshot) + snapshot.get_metadata(BUDGET_DROPPED).map_or(0, str::len)
+        };
+        let budget = Budget::new(55, size)
+            .degrade(Degradation::Histograms)
+            .degrade(Degradation::TopCounters(2));
+
+        // dropping the histograms leaves 50 bytes of metrics, but the
+        // metadata listing them takes the snapshot over the limit
+        let mut snapshot = snapshot();
+        budget.apply(&mut snapshot);
+        assert_eq!(snapshot.counters.len(), 2);
+        assert_eq!(
+            snapshot.get_metadata(BUDGET_DROPPED),
+            Some("histograms=2,counters=2")
+        );
+        assert!(size(&snapshot) <= 55);
+    }
+
+    #[test]
+    fn within_budget() {
+        let mut within = snapshot();
+        Budget::new(usize::MAX, size)
+            .degrade(Degradation::Histograms)
+            .apply(&mut within);
+        assert_eq!(size(&within), size(&snapshot()));
+        assert_eq!(within.get_metadata(BUDGET_DROPPED), None);
+    }
+}
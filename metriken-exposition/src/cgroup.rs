@@ -0,0 +1,366 @@
+use std::path::{Path, PathBuf};
+
+use metriken::{Counter, DynBoxedMetric, Gauge, MetricBuilder, Unit};
+
+use crate::host::Devices;
+use crate::snapshotter::Collector;
+
+/// The statistics read from each cgroup file, as the metric name, the key
+/// of the value within the file, and the unit of the value.
+const CPU_STAT: &[(&str, &str, Option<Unit<'static>>)] = &[
+    ("cgroup/cpu/usage", "usage_usec", Some(Unit::Microseconds)),
+    ("cgroup/cpu/user", "user_usec", Some(Unit::Microseconds)),
+    ("cgroup/cpu/system", "system_usec", Some(Unit::Microseconds)),
+    ("cgroup/cpu/periods", "nr_periods", None),
+    ("cgroup/cpu/throttled_periods", "nr_throttled", None),
+    (
+        "cgroup/cpu/throttled",
+        "throttled_usec",
+        Some(Unit::Microseconds),
+    ),
+];
+
+const MEMORY_EVENTS: &[(&str, &str, Option<Unit<'static>>)] = &[
+    ("cgroup/memory/events/high", "high", None),
+    ("cgroup/memory/events/max", "max", None),
+    ("cgroup/memory/events/oom", "oom", None),
+    ("cgroup/memory/events/oom_kill", "oom_kill", None),
+];
+
+const IO_STAT: &[(&str, &str, Option<Unit<'static>>)] = &[
+    ("cgroup/io/read/bytes", "rbytes", Some(Unit::Bytes)),
+    ("cgroup/io/write/bytes", "wbytes", Some(Unit::Bytes)),
+    ("cgroup/io/read/operations", "rios", None),
+    ("cgroup/io/write/operations", "wios", None),
+];
+
+/// A single value read from the cgroup.
+#[derive(Debug, PartialEq, Eq)]
+struct Reading {
+    name: &'static str,
+    device: Option<String>,
+    gauge: bool,
+    unit: Option<Unit<'static>>,
+    value: u64,
+}
+
+impl Reading {
+    fn counter(name: &'static str, unit: Option<Unit<'static>>, value: u64) -> Self {
+        Self {
+            name,
+            device: None,
+            gauge: false,
+            unit,
+            value,
+        }
+    }
+}
+
+/// Find the cgroup v2 directory of the current process from the contents
+/// of `/proc/self/cgroup`.
+fn cgroup_path(contents: &str) -> Option<&str> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| path.trim_end())
+}
+
+/// Read the values of a flat keyed file such as `cpu.stat`, with one
+/// `key value` pair per line.
+fn flat_keyed(
+    contents: &str,
+    keys: &[(&'static str, &str, Option<Unit<'static>>)],
+) -> Vec<Reading> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            let (name, _, unit) = keys.iter().find(|(_, k, _)| *k == key)?;
+            Some(Reading::counter(name, *unit, value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Read the total stall time from a pressure file, such as `cpu.pressure`,
+/// with a line such as `some avg10=0.00 avg60=0.00 avg300=0.00 total=1234`
+/// for the time some tasks were stalled and another for the time all of
+/// them were.
+fn pressure(contents: &str, resource: &str) -> Vec<Reading> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = match (resource, fields.next()?) {
+                ("cpu", "some") => "cgroup/cpu/pressure/some",
+                ("cpu", "full") => "cgroup/cpu/pressure/full",
+                ("memory", "some") => "cgroup/memory/pressure/some",
+                ("memory", "full") => "cgroup/memory/pressure/full",
+                ("io", "some") => "cgroup/io/pressure/some",
+                ("io", "full") => "cgroup/io/pressure/full",
+                _ => return None,
+            };
+            let total = fields.find_map(|field| field.strip_prefix("total="))?;
+            Some(Reading::counter(
+                name,
+                Some(Unit::Microseconds),
+                total.parse().ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// Read `io.stat`, which has a line such as `8:0 rbytes=1 wbytes=2 rios=3
+/// wios=4 dbytes=0 dios=0` for each device.
+fn io_stat(contents: &str) -> Vec<Reading> {
+    let mut readings = Vec::new();
+
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(device) = fields.next() else {
+            continue;
+        };
+
+        for field in fields {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            let Some((name, _, unit)) = IO_STAT.iter().find(|(_, k, _)| *k == key) else {
+                continue;
+            };
+            let Ok(value) = value.parse() else {
+                continue;
+            };
+
+            readings.push(Reading {
+                device: Some(device.to_string()),
+                ..Reading::counter(name, *unit, value)
+            });
+        }
+    }
+
+    readings
+}
+
+/// Read a file holding a single number of bytes, such as `memory.current`.
+/// Limits which are not set hold `max` and are left out.
+fn bytes(contents: &str, name: &'static str) -> Option<Reading> {
+    Some(Reading {
+        gauge: true,
+        ..Reading::counter(name, Some(Unit::Bytes), contents.trim().parse().ok()?)
+    })
+}
+
+enum CgroupMetric {
+    Counter(DynBoxedMetric<Counter>),
+    Gauge(DynBoxedMetric<Gauge>),
+}
+
+/// Collects the resource usage of the cgroup v2 the process belongs to, such
+/// as the container it runs in.
+///
+/// The following metrics are kept, when the controller for them is enabled:
+///
+/// - `cgroup/cpu/usage`, `cgroup/cpu/user`, and `cgroup/cpu/system`: the CPU
+///   time used, in microseconds
+/// - `cgroup/cpu/periods`, `cgroup/cpu/throttled_periods`, and
+///   `cgroup/cpu/throttled`: the enforcement periods of the CPU limit, how
+///   many of them were throttled, and for how long
+/// - `cgroup/memory/current` and `cgroup/memory/max`: the memory in use and
+///   its limit, in bytes, where the limit is left out if it is not set
+/// - `cgroup/memory/events/*`: counts of the `high`, `max`, `oom`, and
+///   `oom_kill` memory events
+/// - `cgroup/io/read/*` and `cgroup/io/write/*`: the bytes and operations
+///   of each device, with the `major:minor` numbers of the device in the
+///   `device` metadata key
+/// - `cgroup/{cpu,memory,io}/pressure/{some,full}`: the time, in
+///   microseconds, that some or all tasks were stalled on the resource
+///
+/// On systems without cgroup v2 no metrics are registered.
+///
+/// ```
+/// # use metriken_exposition::{CgroupCollector, SnapshotterBuilder};
+/// let snapshotter = SnapshotterBuilder::new()
+///     .collector(CgroupCollector::new())
+///     .build();
+/// ```
+pub struct CgroupCollector {
+    path: Option<PathBuf>,
+    metrics: Devices<CgroupMetric>,
+}
+
+impl Default for CgroupCollector {
+    fn default() -> Self {
+        let path = std::fs::read_to_string("/proc/self/cgroup")
+            .ok()
+            .and_then(|contents| {
+                let path = cgroup_path(&contents)?.trim_start_matches('/');
+                Some(Path::new("/sys/fs/cgroup").join(path))
+            });
+
+        Self {
+            path,
+            metrics: Devices::default(),
+        }
+    }
+}
+
+impl CgroupCollector {
+    /// Collect the metrics of the cgroup of the current process.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect the metrics of the cgroup in the provided directory, such as
+    /// `/sys/fs/cgroup/system.slice/example.service`.
+    pub fn with_path(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+            metrics: Devices::default(),
+        }
+    }
+
+    fn read(&self) -> Vec<Reading> {
+        let Some(path) = &self.path else {
+            return Vec::new();
+        };
+        let read = |file: &str| std::fs::read_to_string(path.join(file)).unwrap_or_default();
+
+        let mut readings = flat_keyed(&read("cpu.stat"), CPU_STAT);
+        readings.extend(bytes(&read("memory.current"), "cgroup/memory/current"));
+        readings.extend(bytes(&read("memory.max"), "cgroup/memory/max"));
+        readings.extend(flat_keyed(&read("memory.events"), MEMORY_EVENTS));
+        readings.extend(io_stat(&read("io.stat")));
+        for resource in ["cpu", "memory", "io"] {
+            readings.extend(pressure(&read(&format!("{resource}.pressure")), resource));
+        }
+        readings
+    }
+}
+
+impl Collector for CgroupCollector {
+    fn refresh(&self) {
+        let readings = self.read().into_iter().map(|reading| {
+            let key = match &reading.device {
+                Some(device) => format!("{}/{device}", reading.name),
+                None => reading.name.to_string(),
+            };
+            (key, reading)
+        });
+
+        self.metrics.update(
+            readings,
+            |_, reading| {
+                let mut builder = MetricBuilder::new(reading.name);
+                if let Some(device) = &reading.device {
+                    builder = builder.metadata("device", device);
+                }
+                if let Some(unit) = reading.unit {
+                    builder = builder.unit(unit);
+                }
+
+                match reading.gauge {
+                    true => CgroupMetric::Gauge(builder.build(Gauge::new())),
+                    false => CgroupMetric::Counter(builder.build(Counter::new())),
+                }
+            },
+            |metric, reading| match metric {
+                CgroupMetric::Counter(counter) => {
+                    counter.set(reading.value);
+                }
+                CgroupMetric::Gauge(gauge) => {
+                    gauge.set(reading.value as i64);
+                }
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path() {
+        assert_eq!(
+            cgroup_path("0::/system.slice/example.service\n"),
+            Some("/system.slice/example.service")
+        );
+        // cgroup v1 hierarchies are ignored
+        assert_eq!(cgroup_path("12:memory:/user.slice\n"), None);
+    }
+
+    #[test]
+    fn files() {
+        let cpu = flat_keyed(
+            "usage_usec 1000\nuser_usec 600\nsystem_usec 400\nnr_periods 10\nnr_throttled 2\nthrottled_usec 50\n",
+            CPU_STAT,
+        );
+        assert_eq!(cpu.len(), 6);
+        assert_eq!(
+            cpu[5],
+            Reading::counter("cgroup/cpu/throttled", Some(Unit::Microseconds), 50)
+        );
+
+        assert_eq!(bytes("max\n", "cgroup/memory/max"), None);
+        assert_eq!(
+            bytes("4096\n", "cgroup/memory/current").map(|r| (r.gauge, r.value)),
+            Some((true, 4096))
+        );
+
+        let io = io_stat("8:0 rbytes=1 wbytes=2 rios=3 wios=4 dbytes=0 dios=0\n");
+        assert_eq!(io.len(), 4);
+        assert_eq!(io[1].name, "cgroup/io/write/bytes");
+        assert_eq!(io[1].device.as_deref(), Some("8:0"));
+        assert_eq!(io[1].value, 2);
+
+        let pressure = pressure(
+            "some avg10=0.00 avg60=0.00 avg300=0.00 total=1234\nfull avg10=0.00 avg60=0.00 avg300=0.00 total=56\n",
+            "memory",
+        );
+        assert_eq!(
+            pressure,
+            [
+                Reading::counter(
+                    "cgroup/memory/pressure/some",
+                    Some(Unit::Microseconds),
+                    1234
+                ),
+                Reading::counter("cgroup/memory/pressure/full", Some(Unit::Microseconds), 56),
+            ]
+        );
+    }
+
+    #[test]
+    fn collect() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("memory.current"), "8192\n").unwrap();
+        std::fs::write(dir.path().join("memory.max"), "max\n").unwrap();
+        std::fs::write(dir.path().join("memory.events"), "oom_kill 3\n").unwrap();
+
+        let collector = CgroupCollector::with_path(dir.path());
+        collector.refresh();
+
+        let value = |name: &str| {
+            metriken::metrics()
+                .iter()
+                .find(|m| m.name() == name)
+                .and_then(|m| match m.value() {
+                    Some(metriken::Value::Counter(v)) => Some(format!("Counter({v})")),
+                    Some(metriken::Value::Gauge(v)) => Some(format!("Gauge({v})")),
+                    _ => None,
+                })
+        };
+        assert_eq!(
+            value("cgroup/memory/current").as_deref(),
+            Some("Gauge(8192)")
+        );
+        assert_eq!(
+            value("cgroup/memory/events/oom_kill").as_deref(),
+            Some("Counter(3)")
+        );
+        assert_eq!(value("cgroup/memory/max"), None);
+
+        drop(collector);
+        assert_eq!(value("cgroup/memory/current"), None);
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use arrow::datatypes::Schema;
+use parquet::arrow::arrow_to_parquet_schema;
+use parquet::errors::ParquetError;
+use parquet::file::metadata::RowGroupMetaDataPtr;
+use parquet::format::{FileMetaData, RowGroup};
+use parquet::schema::types::to_thrift;
+use parquet::thrift::TSerializable;
+use thrift::protocol::{TCompactInputProtocol, TCompactOutputProtocol};
+
+/// The start of a checkpoint file, which is followed by the length of the
+/// complete part of the output and the footer for it.
+const MAGIC: &[u8; 4] = b"MKC1";
+
+/// The magic at the start and end of a parquet file.
+const PARQUET_MAGIC: &[u8; 4] = b"PAR1";
+
+/// The row groups of a conversion which are complete in its output.
+///
+/// The checkpoint holds the parquet footer which the output would have if it
+/// ended after its last complete row group, so the footer of a resumed
+/// conversion can be rewritten to include those row groups.
+#[derive(Clone, Debug)]
+pub(crate) struct Checkpoint {
+    footer: FileMetaData,
+    /// The length of the output up to the end of the last complete row group
+    offset: u64,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint at the provided path, returning `None` if there is
+    /// no checkpoint.
+    pub(crate) fn read(path: &Path) -> Result<Option<Self>, ParquetError> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let invalid =
+            || ParquetError::General(format!("{} is not a conversion checkpoint", path.display()));
+        if bytes.len() < 12 || &bytes[..4] != MAGIC {
+            return Err(invalid());
+        }
+        let offset = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
+        let footer =
+            FileMetaData::read_from_in_protocol(&mut TCompactInputProtocol::new(&bytes[12..]))
+                .map_err(|_| invalid())?;
+
+        Ok(Some(Self { footer, offset }))
+    }
+
+    /// Replace the checkpoint at the provided path. The new checkpoint is
+    /// written next to it and renamed over it, so that an interruption leaves
+    /// the previous checkpoint intact.
+    fn write(&self, path: &Path) -> Result<(), ParquetError> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&self.offset.to_le_bytes());
+        self.footer
+            .write_to_out_protocol(&mut TCompactOutputProtocol::new(&mut bytes))?;
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// The number of rows in the complete row groups.
+    pub(crate) fn rows(&self) -> u64 {
+        self.footer.num_rows as u64
+    }
+
+    /// Open the output of the interrupted conversion, discarding everything
+    /// after the last complete row group.
+    pub(crate) fn open(&self, path: &Path) -> Result<File, ParquetError> {
+        let mut output = OpenOptions::new().read(true).write(true).open(path)?;
+        if output.metadata()?.len() < self.offset {
+            return Err(ParquetError::General(format!(
+                "{} is shorter than its checkpoint",
+                path.display()
+            )));
+        }
+        output.set_len(self.offset)?;
+        output.seek(SeekFrom::End(0))?;
+        Ok(output)
+    }
+
+    /// Rewrite the footer at the end of the output of the resumed conversion
+    /// to include the row groups written before it was interrupted, returning
+    /// the new footer.
+    ///
+    /// The row groups from before the interruption have no page index, as
+    /// the page index is only written along with the footer.
+    pub(crate) fn finish(
+        &self,
+        output: &mut File,
+        mut footer: FileMetaData,
+    ) -> Result<FileMetaData, ParquetError> {
+        // The footer is followed by its length and the magic.
+        let len = output.seek(SeekFrom::End(-8))?;
+        let mut tail = [0; 8];
+        output.read_exact(&mut tail)?;
+        let footer_len = u32::from_le_bytes(tail[..4].try_into().unwrap()) as u64;
+        output.set_len(len - footer_len)?;
+        output.seek(SeekFrom::End(0))?;
+
+        let resumed = std::mem::take(&mut footer.row_groups);
+        footer.row_groups = self.footer.row_groups.clone();
+        footer
+            .row_groups
+            .extend(resumed.into_iter().map(|r| shift(r, self.shift())));
+        footer.num_rows += self.footer.num_rows;
+        for (ordinal, row_group) in footer.row_groups.iter_mut().enumerate() {
+            if row_group.ordinal.is_some() {
+                row_group.ordinal = Some(ordinal as i16);
+            }
+        }
+
+        let mut bytes = Vec::new();
+        footer.write_to_out_protocol(&mut TCompactOutputProtocol::new(&mut bytes))?;
+        output.write_all(&bytes)?;
+        output.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        output.write_all(PARQUET_MAGIC)?;
+        Ok(footer)
+    }
+
+    /// The difference between a position in the output of the resumed
+    /// conversion and the same position as seen by its parquet writer, which
+    /// starts counting from zero and whose magic is dropped.
+    fn shift(&self) -> i64 {
+        self.offset as i64 - PARQUET_MAGIC.len() as i64
+    }
+}
+
+/// Move each offset in the row group by the provided number of bytes.
+fn shift(mut row_group: RowGroup, by: i64) -> RowGroup {
+    let by_some = |offset: Option<i64>| offset.map(|o| o + by);
+    row_group.file_offset = by_some(row_group.file_offset);
+    for column in &mut row_group.columns {
+        column.file_offset += by;
+        column.offset_index_offset = by_some(column.offset_index_offset);
+        column.column_index_offset = by_some(column.column_index_offset);
+        if let Some(metadata) = &mut column.meta_data {
+            metadata.data_page_offset += by;
+            metadata.index_page_offset = by_some(metadata.index_page_offset);
+            metadata.dictionary_page_offset = by_some(metadata.dictionary_page_offset);
+            metadata.bloom_filter_offset = by_some(metadata.bloom_filter_offset);
+        }
+    }
+    row_group
+}
+
+/// The output of a conversion which records checkpoints. It counts the bytes
+/// which have reached the output, as the parquet writer buffers its writes,
+/// and when resuming it drops the magic the parquet writer starts with.
+pub(crate) struct CheckpointWrite {
+    output: File,
+    skip: usize,
+    written: Arc<AtomicU64>,
+}
+
+impl CheckpointWrite {
+    pub(crate) fn new(output: File, resumed: bool) -> Self {
+        Self {
+            output,
+            skip: if resumed { PARQUET_MAGIC.len() } else { 0 },
+            written: Default::default(),
+        }
+    }
+}
+
+impl Write for CheckpointWrite {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.skip > 0 {
+            let skipped = self.skip.min(buf.len());
+            self.skip -= skipped;
+            return Ok(skipped);
+        }
+        let written = self.output.write(buf)?;
+        self.written.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Records a checkpoint each time a row group of a conversion is complete.
+pub(crate) struct Checkpointer {
+    path: PathBuf,
+    checkpoint: Checkpoint,
+    /// The position in the output where the parquet writer started
+    start: u64,
+    shift: i64,
+    written: Arc<AtomicU64>,
+    /// The number of row groups flushed by the parquet writer so far
+    flushed: usize,
+    /// Row groups which have been flushed by the parquet writer but may not
+    /// have reached the output, with the position of their end
+    pending: VecDeque<(RowGroup, u64)>,
+}
+
+impl Checkpointer {
+    /// Record checkpoints for a conversion which writes to `output` and
+    /// may be resuming from a checkpoint.
+    pub(crate) fn new(
+        path: PathBuf,
+        resumed: Option<Checkpoint>,
+        output: &CheckpointWrite,
+    ) -> Self {
+        let (checkpoint, shift) = match resumed {
+            Some(checkpoint) => {
+                let shift = checkpoint.shift();
+                (checkpoint, shift)
+            }
+            None => {
+                let footer = FileMetaData {
+                    version: 1,
+                    schema: Vec::new(),
+                    num_rows: 0,
+                    row_groups: Vec::new(),
+                    key_value_metadata: None,
+                    created_by: None,
+                    column_orders: None,
+                    encryption_algorithm: None,
+                    footer_signing_key_metadata: None,
+                };
+                (Checkpoint { footer, offset: 0 }, 0)
+            }
+        };
+
+        Self {
+            path,
+            start: checkpoint.offset,
+            checkpoint,
+            shift,
+            written: output.written.clone(),
+            flushed: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The number of rows which were already in the output.
+    pub(crate) fn rows(&self) -> u64 {
+        self.checkpoint.rows()
+    }
+
+    /// Set the schema of the output, which must match the schema of the
+    /// checkpoint being resumed from.
+    pub(crate) fn schema(&mut self, schema: &Schema) -> Result<(), ParquetError> {
+        let schema = to_thrift(arrow_to_parquet_schema(schema)?.root_schema())?;
+        if self.start > 0 && self.checkpoint.footer.schema != schema {
+            return Err(ParquetError::General(format!(
+                "the checkpoint at {} is for a different recording",
+                self.path.display()
+            )));
+        }
+        self.checkpoint.footer.schema = schema;
+        Ok(())
+    }
+
+    /// Record a checkpoint if more row groups have reached the output.
+    pub(crate) fn update(
+        &mut self,
+        row_groups: &[RowGroupMetaDataPtr],
+        bytes_written: usize,
+    ) -> Result<(), ParquetError> {
+        // The row groups are complete at the latest once everything the
+        // parquet writer has written so far has reached the output.
+        for row_group in &row_groups[self.flushed..] {
+            let end = (bytes_written as i64 + self.shift) as u64;
+            self.pending
+                .push_back((shift(row_group.to_thrift(), self.shift), end));
+        }
+        self.flushed = row_groups.len();
+
+        let output = self.start + self.written.load(Ordering::Relaxed);
+        let mut complete = false;
+        while self.pending.front().is_some_and(|(_, end)| *end <= output) {
+            let (row_group, end) = self.pending.pop_front().unwrap();
+            self.checkpoint.footer.num_rows += row_group.num_rows;
+            self.checkpoint.footer.row_groups.push(row_group);
+            self.checkpoint.offset = end;
+            complete = true;
+        }
+
+        if complete {
+            self.checkpoint.write(&self.path)?;
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,114 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read, Seek, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{Scope, ScopedJoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
 use parquet::errors::ParquetError;
+use parquet::format::FileMetaData;
 
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::checkpoint::{Checkpoint, CheckpointWrite, Checkpointer};
+use crate::msgpack::{MsgpackDecoder, MsgpackLayout};
+use crate::parquet::{EncodedRow, ParquetWriter};
 use crate::snapshot::Snapshot;
 use crate::{ParquetOptions, ParquetSchema};
 
+/// How `MsgpackToParquet` handles snapshots which are earlier than a snapshot
+/// that precedes them in the recording, as happens when recordings from
+/// several sources are merged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutOfOrder {
+    /// Write snapshots in the order they appear in the recording.
+    #[default]
+    Allow,
+    /// Sort snapshots within a window of the provided number of snapshots.
+    /// Conversion fails if a snapshot is out of order by more than the
+    /// window.
+    Sort(usize),
+    /// Skip snapshots which are earlier than the last snapshot written.
+    Drop,
+    /// Fail the conversion at the first out of order snapshot.
+    Error,
+}
+
+/// The progress of a conversion by a [`MsgpackToParquet`], which is passed
+/// to the callback set with [`MsgpackToParquet::progress`].
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct ConversionProgress {
+    /// The number of snapshots processed. The recording is read twice, so
+    /// each snapshot is counted twice by the end of the conversion.
+    pub snapshots: u64,
+    /// The number of bytes of the recording read so far, over both passes.
+    pub bytes_read: u64,
+    /// The number of bytes which will have been read when the conversion is
+    /// complete.
+    pub bytes_total: u64,
+    /// The number of bytes written to the parquet file so far. Rows are
+    /// buffered until their row group is complete, so this lags behind.
+    pub bytes_written: u64,
+    /// The time since the conversion started.
+    pub elapsed: Duration,
+}
+
+impl ConversionProgress {
+    /// The fraction of the conversion which is complete, from `0.0` to
+    /// `1.0`.
+    pub fn fraction(&self) -> f64 {
+        if self.bytes_total == 0 {
+            return 1.0;
+        }
+        self.bytes_read as f64 / self.bytes_total as f64
+    }
+
+    /// An estimate of the time until the conversion is complete, assuming
+    /// the rest of the recording is read at the same rate as so far.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.bytes_read == 0 {
+            return None;
+        }
+        let remaining = self.bytes_total.saturating_sub(self.bytes_read) as f64;
+        Some(self.elapsed.mul_f64(remaining / self.bytes_read as f64))
+    }
+}
+
+/// The callback which receives the progress of a conversion.
+#[derive(Clone)]
+struct ProgressCallback(Arc<dyn Fn(&ConversionProgress) + Send + Sync>);
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
 /// A struct for converting msgpack'd metriken snapshots into a parquet file.
+///
+/// The recording is read twice, once to build the schema and once to write
+/// the rows, and snapshots are decoded and written one at a time, so the
+/// memory used does not grow with the length of the recording. The largest
+/// use of memory is the row group being written, which can be bounded with
+/// [`ParquetOptions::memory_budget`].
+///
+/// By default everything happens on the calling thread. With
+/// [`MsgpackToParquet::threads`], the rows are encoded on a pool of threads
+/// and written on another while the recording is decoded on the calling
+/// thread.
 #[derive(Clone, Debug, Default)]
 pub struct MsgpackToParquet {
     parquet_options: ParquetOptions,
+    out_of_order: OutOfOrder,
+    threads: usize,
+    summary: Option<PathBuf>,
+    checkpoint: Option<PathBuf>,
+    progress: Option<ProgressCallback>,
+    cancel: Option<CancellationToken>,
 }
 
 impl MsgpackToParquet {
@@ -24,9 +122,70 @@ impl MsgpackToParquet {
     pub fn with_options(options: ParquetOptions) -> Self {
         Self {
             parquet_options: options,
+            ..Default::default()
         }
     }
 
+    /// Sets how snapshots which are not in time order are handled. The
+    /// default is to write them in the order they appear.
+    pub fn out_of_order(mut self, out_of_order: OutOfOrder) -> Self {
+        self.out_of_order = out_of_order;
+        self
+    }
+
+    /// Encode rows on the provided number of threads. With more than one
+    /// thread, the snapshots are decoded on the calling thread, encoded into
+    /// rows on a pool of `threads` threads, and written in their original
+    /// order on another thread, so that the conversion uses several cores.
+    /// The output is the same as with a single thread, which is the default.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Also write a summary table with the percentiles of each histogram
+    /// over each interval to a second parquet file at the provided path. See
+    /// [`crate::ParquetWriter::with_summary`].
+    pub fn summary_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.summary = Some(path.into());
+        self
+    }
+
+    /// Record the progress of the conversion in a checkpoint file at the
+    /// provided path each time a row group is complete, and resume from the
+    /// checkpoint if it exists, such as when a previous attempt at the same
+    /// conversion was interrupted. A resumed conversion keeps the complete
+    /// row groups of the output and only writes the rest, though the
+    /// recording is still read from the start. The checkpoint is removed
+    /// once the conversion succeeds.
+    ///
+    /// Checkpoints are only used by [`MsgpackToParquet::convert_file_path`],
+    /// and can not be combined with a summary file. The row groups written
+    /// before an interruption have no page index.
+    pub fn checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+
+    /// Call the provided function with the progress of the conversion after
+    /// each snapshot is processed. The function is called often, so it
+    /// should be cheap, such as updating a progress bar.
+    pub fn progress(
+        mut self,
+        callback: impl Fn(&ConversionProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(ProgressCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Stop the conversion when the provided token is cancelled. The
+    /// conversion then fails with a [`ParquetError::External`] holding a
+    /// [`Cancelled`] error, and the output is incomplete.
+    pub fn cancel_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
     /// Converts a file with metrics in msgpack format to a parquet file.
     /// Input and putput are file paths.
     /// If successful, returns the number of rows written out to the parquet
@@ -36,7 +195,32 @@ impl MsgpackToParquet {
         input: impl AsRef<Path>,
         output: impl AsRef<Path>,
     ) -> Result<i64, ParquetError> {
-        self.convert_file_handle(File::open(input)?, File::create(output)?)
+        let Some(path) = self.checkpoint.clone() else {
+            return self.convert_file_handle(File::open(input)?, File::create(output)?);
+        };
+        if self.summary.is_some() {
+            return Err(ParquetError::General(
+                "checkpoints can not be used with a summary file".to_string(),
+            ));
+        }
+
+        let input = File::open(input)?;
+        let resumed = Checkpoint::read(&path)?;
+        let mut output = match &resumed {
+            Some(checkpoint) => checkpoint.open(output.as_ref())?,
+            None => File::create(output)?,
+        };
+        let writer = CheckpointWrite::new(output.try_clone()?, resumed.is_some());
+
+        let checkpointer = Checkpointer::new(path.clone(), resumed.clone(), &writer);
+        let metadata = self.convert(input, writer, Some(checkpointer))?;
+        let metadata = match resumed {
+            Some(checkpoint) => checkpoint.finish(&mut output, metadata)?,
+            None => metadata,
+        };
+        std::fs::remove_file(path)?;
+
+        Ok(metadata.num_rows)
     }
 
     /// Converts a file with metrics in msgpack format to a parquet file.
@@ -48,26 +232,519 @@ impl MsgpackToParquet {
         reader: impl Read + Seek,
         writer: impl Write + Send,
     ) -> Result<i64, ParquetError> {
-        let mut reader = BufReader::new(reader);
+        if self.checkpoint.is_some() {
+            return Err(ParquetError::General(
+                "checkpoints are only supported when converting file paths".to_string(),
+            ));
+        }
+        Ok(self.convert(reader, writer, None)?.num_rows)
+    }
+
+    /// Convert the recording, skipping the rows which are already in the
+    /// output and recording checkpoints if there is a checkpointer.
+    fn convert(
+        self,
+        reader: impl Read + Seek,
+        writer: impl Write + Send,
+        checkpointer: Option<Checkpointer>,
+    ) -> Result<FileMetaData, ParquetError> {
+        let mut reader = reader;
+        let layout =
+            MsgpackLayout::read(&mut reader).map_err(|x| ParquetError::External(Box::new(x)))?;
+        reader.rewind()?;
+
+        let start = Instant::now();
+        let mut snapshots = 0;
+        // Report the progress after each snapshot, and stop if cancelled.
+        let mut update = |bytes_read: u64, bytes_written: usize| -> Result<(), ParquetError> {
+            snapshots += 1;
+            if self.cancel.as_ref().is_some_and(|t| t.is_cancelled()) {
+                return Err(ParquetError::External(Box::new(Cancelled)));
+            }
+            if let Some(progress) = &self.progress {
+                (progress.0)(&ConversionProgress {
+                    snapshots,
+                    bytes_read,
+                    bytes_total: 2 * layout.data_len,
+                    bytes_written: bytes_written as u64,
+                    elapsed: start.elapsed(),
+                });
+            }
+            Ok(())
+        };
+        // The bytes of the snapshots which have been decoded in a pass.
+        let position = |reader: &BufReader<std::io::Take<_>>| {
+            layout.data_len - reader.get_ref().limit() - reader.buffer().len() as u64
+        };
+
+        // Only read the snapshots, ignoring any trailing index.
+        let mut reader = BufReader::new(reader.take(layout.data_len));
         let mut schema = ParquetSchema::new();
 
         // First pass to build the schema
+        let mut decoder = MsgpackDecoder::default();
         while !reader.fill_buf().unwrap().is_empty() {
-            let s: Snapshot = rmp_serde::from_read(&mut reader)
+            let s = decoder
+                .decode(&mut reader)
                 .map_err(|x| ParquetError::External(Box::new(x)))?;
             schema.push(s);
+            update(position(&reader), 0)?;
         }
         let mut writer = schema.finalize(writer, self.parquet_options)?;
+        if let Some(path) = &self.summary {
+            writer = writer.with_summary(File::create(path)?)?;
+        }
+        // The number of rows which are already in the output.
+        let mut skip = 0;
+        if let Some(mut checkpointer) = checkpointer {
+            checkpointer.schema(writer.schema())?;
+            skip = checkpointer.rows();
+            writer = writer.on_write(Box::new(move |row_groups, bytes_written| {
+                checkpointer.update(row_groups, bytes_written)
+            }));
+        }
 
         // Rewind file pointer and second pass for the actual metrics
-        reader.rewind().unwrap();
-        while !reader.fill_buf().unwrap().is_empty() {
-            let s: Snapshot = rmp_serde::from_read(&mut reader)
-                .map_err(|x| ParquetError::External(Box::new(x)))?;
-            writer.push(s)?;
+        let mut reader = reader.into_inner().into_inner();
+        reader.rewind()?;
+        let mut reader = BufReader::new(reader.take(layout.data_len));
+        let mut sorter = Sorter::new(self.out_of_order);
+        let mut decoder = MsgpackDecoder::default();
+        std::thread::scope(|scope| {
+            let mut output = Output::new(scope, writer, self.threads);
+            while !reader.fill_buf().unwrap().is_empty() {
+                let s = decoder
+                    .decode(&mut reader)
+                    .map_err(|x| ParquetError::External(Box::new(x)))?;
+                if let Some(s) = sorter.push(s)? {
+                    match skip {
+                        0 => output.push(s)?,
+                        _ => skip -= 1,
+                    }
+                }
+                update(layout.data_len + position(&reader), output.bytes_written())?;
+            }
+            while let Some(s) = sorter.pop()? {
+                match skip {
+                    0 => output.push(s)?,
+                    _ => skip -= 1,
+                }
+            }
+            output.finish()?.finalize()
+        })
+    }
+}
+
+/// Where the second pass of a conversion sends the snapshots to be written.
+enum Output<'scope, W: Write + Send> {
+    Sequential(Box<ParquetWriter<W>>),
+    Parallel(Pipeline<'scope, W>),
+}
+
+impl<'scope, W: Write + Send + 'scope> Output<'scope, W> {
+    fn new(scope: &'scope Scope<'scope, '_>, writer: ParquetWriter<W>, threads: usize) -> Self {
+        if threads > 1 {
+            Self::Parallel(Pipeline::new(scope, writer, threads))
+        } else {
+            Self::Sequential(Box::new(writer))
         }
-        let metadata = writer.finalize()?;
+    }
 
-        Ok(metadata.num_rows)
+    fn push(&mut self, snapshot: Snapshot) -> Result<(), ParquetError> {
+        match self {
+            Self::Sequential(writer) => writer.push(snapshot),
+            Self::Parallel(pipeline) => pipeline.push(snapshot),
+        }
+    }
+
+    fn bytes_written(&self) -> usize {
+        match self {
+            Self::Sequential(writer) => writer.bytes_written(),
+            Self::Parallel(pipeline) => pipeline.bytes_written.load(Ordering::Relaxed) as usize,
+        }
+    }
+
+    /// Wait for every snapshot to be written, returning the writer so that
+    /// the file can be finalized.
+    fn finish(self) -> Result<ParquetWriter<W>, ParquetError> {
+        match self {
+            Self::Sequential(writer) => Ok(*writer),
+            Self::Parallel(mut pipeline) => pipeline.join(),
+        }
+    }
+}
+
+/// Encodes snapshots into rows on a pool of threads, and writes the rows in
+/// the order their snapshots were sent on another thread.
+struct Pipeline<'scope, W: Write + Send> {
+    /// The position of the next snapshot sent
+    sequence: u64,
+    snapshots: Option<SyncSender<(u64, Snapshot)>>,
+    writer: Option<ScopedJoinHandle<'scope, Result<ParquetWriter<W>, ParquetError>>>,
+    bytes_written: Arc<AtomicU64>,
+}
+
+impl<'scope, W: Write + Send + 'scope> Pipeline<'scope, W> {
+    fn new(scope: &'scope Scope<'scope, '_>, writer: ParquetWriter<W>, threads: usize) -> Self {
+        let encoder = writer.encoder();
+        let keep_histograms = writer.has_summary();
+
+        // Bound the number of snapshots and rows in flight, so that a slow
+        // writer does not let them accumulate.
+        let (snapshots, receiver) = sync_channel::<(u64, Snapshot)>(2 * threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (rows, encoded) = sync_channel::<(u64, Result<EncodedRow, ParquetError>)>(2 * threads);
+
+        for _ in 0..threads {
+            let (receiver, rows, encoder) = (receiver.clone(), rows.clone(), encoder.clone());
+            scope.spawn(move || loop {
+                // Stop once the snapshots are done or the writer has failed.
+                let Ok((sequence, snapshot)) = receiver.lock().unwrap().recv() else {
+                    return;
+                };
+                let row = encoder.encode(snapshot, keep_histograms);
+                if rows.send((sequence, row)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(rows);
+
+        let bytes_written = Arc::new(AtomicU64::new(0));
+        let written = bytes_written.clone();
+        let writer = scope.spawn(move || {
+            let mut writer = writer;
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            for (sequence, row) in encoded {
+                pending.insert(sequence, row?);
+                while let Some(row) = pending.remove(&next) {
+                    writer.write_row(row)?;
+                    next += 1;
+                }
+                written.store(writer.bytes_written() as u64, Ordering::Relaxed);
+            }
+            Ok(writer)
+        });
+
+        Self {
+            sequence: 0,
+            snapshots: Some(snapshots),
+            writer: Some(writer),
+            bytes_written,
+        }
+    }
+
+    fn push(&mut self, snapshot: Snapshot) -> Result<(), ParquetError> {
+        let sent = match &self.snapshots {
+            Some(snapshots) => snapshots.send((self.sequence, snapshot)).is_ok(),
+            None => false,
+        };
+        self.sequence += 1;
+        if sent {
+            return Ok(());
+        }
+
+        // The writer has stopped, which only happens when it fails.
+        match self.join() {
+            Ok(_) => Err(ParquetError::General(
+                "the writer stopped before the conversion was complete".to_string(),
+            )),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn join(&mut self) -> Result<ParquetWriter<W>, ParquetError> {
+        self.snapshots = None;
+        match self.writer.take() {
+            Some(writer) => writer
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e)),
+            None => Err(ParquetError::General(
+                "the writer has already stopped".to_string(),
+            )),
+        }
+    }
+}
+
+/// Applies the out of order policy to the stream of snapshots being written.
+struct Sorter {
+    policy: OutOfOrder,
+    /// Snapshots waiting to be written, ordered by time and then by position
+    /// in the recording.
+    buffer: BinaryHeap<Reverse<(SystemTime, usize, Pending)>>,
+    /// The time of the last snapshot that was written.
+    last: Option<SystemTime>,
+    /// The number of snapshots read so far.
+    read: usize,
+}
+
+/// Wrapper so that snapshots can be held in the heap without being compared.
+struct Pending(Snapshot);
+
+impl PartialEq for Pending {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl Sorter {
+    fn new(policy: OutOfOrder) -> Self {
+        Self {
+            policy,
+            buffer: BinaryHeap::new(),
+            last: None,
+            read: 0,
+        }
+    }
+
+    /// Accept the next snapshot from the recording, returning a snapshot
+    /// which is ready to be written, if any.
+    fn push(&mut self, snapshot: Snapshot) -> Result<Option<Snapshot>, ParquetError> {
+        let index = self.read;
+        self.read += 1;
+
+        match self.policy {
+            OutOfOrder::Allow => Ok(Some(snapshot)),
+            OutOfOrder::Drop => {
+                if self.last.is_some_and(|last| snapshot.systemtime < last) {
+                    return Ok(None);
+                }
+                self.last = Some(snapshot.systemtime);
+                Ok(Some(snapshot))
+            }
+            OutOfOrder::Error => {
+                self.check(index, snapshot.systemtime)?;
+                Ok(Some(snapshot))
+            }
+            OutOfOrder::Sort(window) => {
+                self.buffer
+                    .push(Reverse((snapshot.systemtime, index, Pending(snapshot))));
+                if self.buffer.len() > window {
+                    self.pop()
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Take the earliest buffered snapshot.
+    fn pop(&mut self) -> Result<Option<Snapshot>, ParquetError> {
+        match self.buffer.pop() {
+            Some(Reverse((time, index, Pending(snapshot)))) => {
+                self.check(index, time)?;
+                Ok(Some(snapshot))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Ensure the snapshot is not earlier than the last one written.
+    fn check(&mut self, index: usize, time: SystemTime) -> Result<(), ParquetError> {
+        if let Some(last) = self.last.filter(|last| time < *last) {
+            let behind = last.duration_since(time).unwrap_or_default();
+            return Err(ParquetError::General(format!(
+                "snapshot {index} in the recording is {behind:?} earlier than the \
+                 snapshot preceding it"
+            )));
+        }
+        self.last = Some(time);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn recording(seconds: &[u64]) -> File {
+        let mut file = tempfile::tempfile().unwrap();
+        for s in seconds {
+            let mut snapshot = Snapshot::new();
+            snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_secs(*s);
+            file.write_all(&Snapshot::to_msgpack(&snapshot).unwrap())
+                .unwrap();
+        }
+        file.rewind().unwrap();
+        file
+    }
+
+    fn convert(policy: OutOfOrder, seconds: &[u64]) -> Result<i64, ParquetError> {
+        MsgpackToParquet::new()
+            .out_of_order(policy)
+            .convert_file_handle(recording(seconds), tempfile::tempfile().unwrap())
+    }
+
+    #[test]
+    fn progress() {
+        let updates = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = updates.clone();
+        let rows = MsgpackToParquet::new()
+            .progress(move |p| recorded.lock().unwrap().push(*p))
+            .convert_file_handle(recording(&[1, 2, 3]), tempfile::tempfile().unwrap())
+            .unwrap();
+        assert_eq!(rows, 3);
+
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates.len(), 6);
+        assert!(updates
+            .windows(2)
+            .all(|w| w[0].bytes_read < w[1].bytes_read));
+        assert_eq!(updates[2].fraction(), 0.5);
+        let last = updates.last().unwrap();
+        assert_eq!(last.snapshots, 6);
+        assert_eq!(last.fraction(), 1.0);
+        assert_eq!(last.eta(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn cancel() {
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let error = MsgpackToParquet::new()
+            .progress(move |_| cancel.cancel())
+            .cancel_token(token)
+            .convert_file_handle(recording(&[1, 2, 3]), tempfile::tempfile().unwrap())
+            .unwrap_err();
+
+        let ParquetError::External(error) = error else {
+            panic!("unexpected error: {error}");
+        };
+        assert!(error.downcast_ref::<Cancelled>().is_some());
+    }
+
+    #[test]
+    fn threads() {
+        let mut file = tempfile::tempfile().unwrap();
+        for s in 0..100u64 {
+            let mut snapshot = Snapshot::new();
+            snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_secs(s);
+            // a counter which only appears in some snapshots
+            for name in ["always", "sometimes"]
+                .iter()
+                .take(1 + (s % 3 == 0) as usize)
+            {
+                snapshot.counters.push(crate::snapshot::Counter {
+                    name: name.to_string(),
+                    metric_type: crate::snapshot::MetricType::Counter,
+                    value: s,
+                    metadata: Default::default(),
+                });
+            }
+            file.write_all(&Snapshot::to_msgpack(&snapshot).unwrap())
+                .unwrap();
+        }
+
+        let convert = |threads: usize| {
+            let mut output = tempfile::tempfile().unwrap();
+            (&file).rewind().unwrap();
+            let rows = MsgpackToParquet::with_options(ParquetOptions::new().max_batch_size(16))
+                .threads(threads)
+                .convert_file_handle(&file, &output)
+                .unwrap();
+            assert_eq!(rows, 100);
+
+            let mut bytes = Vec::new();
+            output.rewind().unwrap();
+            output.read_to_end(&mut bytes).unwrap();
+            bytes
+        };
+
+        // the rows are written in the same order as without threads
+        assert_eq!(convert(4), convert(1));
+    }
+
+    #[test]
+    fn resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("recording.msgpack");
+        let mut file = File::create(&input).unwrap();
+        for s in 0..200u64 {
+            let mut snapshot = Snapshot::new();
+            snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_secs(s);
+            for c in 0..20 {
+                snapshot.counters.push(crate::snapshot::Counter {
+                    name: format!("counter/{c}"),
+                    metric_type: crate::snapshot::MetricType::Counter,
+                    value: s * c,
+                    metadata: Default::default(),
+                });
+            }
+            file.write_all(&Snapshot::to_msgpack(&snapshot).unwrap())
+                .unwrap();
+        }
+        drop(file);
+
+        let options = ParquetOptions::new().max_batch_size(10);
+        let checkpoint = dir.path().join("checkpoint");
+        let output = dir.path().join("output.parquet");
+
+        // interrupt the conversion partway through the second pass
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let error = MsgpackToParquet::with_options(options.clone())
+            .checkpoint(&checkpoint)
+            .cancel_token(token)
+            .progress(move |p| {
+                if p.snapshots == 350 {
+                    cancel.cancel();
+                }
+            })
+            .convert_file_path(&input, &output)
+            .unwrap_err();
+        assert!(matches!(error, ParquetError::External(_)));
+        let resumed = Checkpoint::read(&checkpoint).unwrap().unwrap();
+        assert!(resumed.rows() > 0 && resumed.rows() < 150);
+
+        let rows = MsgpackToParquet::with_options(options.clone())
+            .checkpoint(&checkpoint)
+            .convert_file_path(&input, &output)
+            .unwrap();
+        assert_eq!(rows, 200);
+        assert!(!checkpoint.exists());
+
+        let expected = dir.path().join("expected.parquet");
+        MsgpackToParquet::with_options(options)
+            .convert_file_path(&input, &expected)
+            .unwrap();
+
+        let batches = |path: &Path| {
+            parquet::arrow::arrow_reader::ParquetRecordBatchReader::try_new(
+                File::open(path).unwrap(),
+                1024,
+            )
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+        };
+        assert_eq!(batches(&output), batches(&expected));
+    }
+
+    #[test]
+    fn out_of_order() {
+        let seconds = [1, 3, 2, 4, 0];
+
+        assert_eq!(convert(OutOfOrder::Allow, &seconds).unwrap(), 5);
+        assert_eq!(convert(OutOfOrder::Drop, &seconds).unwrap(), 3);
+        assert!(convert(OutOfOrder::Error, &seconds).is_err());
+        assert!(convert(OutOfOrder::Sort(2), &seconds).is_err());
+        assert_eq!(convert(OutOfOrder::Sort(4), &seconds).unwrap(), 5);
     }
 }
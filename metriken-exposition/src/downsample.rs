@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::time::{Duration, SystemTime};
+
+use crate::snapshot::{
+    Cardinality, Counter, Event, Gauge, Histogram, MetricType, Sketch, Snapshot, Stats, TopK,
+};
+use crate::temporality::{series_key, SeriesKey};
+
+/// The metadata key holding the [`GaugeAggregation`] for a gauge.
+pub const AGGREGATION: &str = "aggregation";
+
+/// How the readings of a gauge are combined when several snapshots are
+/// reduced to one.
+///
+/// The aggregation is read from the [`AGGREGATION`] metadata key of each
+/// gauge, which can be set when the metric is declared:
+///
+/// ```
+/// # use metriken::*;
+/// #[metric(name = "queue_depth", metadata = { aggregation = "max" })]
+/// static QUEUE_DEPTH: Gauge = Gauge::new();
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GaugeAggregation {
+    /// Keep the most recent reading.
+    #[default]
+    Last,
+    /// Keep the highest reading, such as for a high-water mark.
+    Max,
+    /// Keep the lowest reading.
+    Min,
+    /// Average the readings, rounding towards zero.
+    Mean,
+    /// Add the readings together.
+    Sum,
+}
+
+impl GaugeAggregation {
+    /// The value used for this aggregation in the [`AGGREGATION`] metadata
+    /// key.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Last => "last",
+            Self::Max => "max",
+            Self::Min => "min",
+            Self::Mean => "mean",
+            Self::Sum => "sum",
+        }
+    }
+
+    /// Read the aggregation from the metadata of a gauge. Gauges without the
+    /// [`AGGREGATION`] key, or with an unrecognized value, use
+    /// [`GaugeAggregation::Last`].
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Self {
+        match metadata.get(AGGREGATION).map(|v| v.as_str()) {
+            Some("max") => Self::Max,
+            Some("min") => Self::Min,
+            Some("mean") => Self::Mean,
+            Some("sum") => Self::Sum,
+            _ => Self::Last,
+        }
+    }
+}
+
+/// A gauge along with the readings needed to compute its aggregate.
+struct GaugeState {
+    gauge: Gauge,
+    aggregation: GaugeAggregation,
+    sum: i128,
+    count: i128,
+}
+
+impl GaugeState {
+    fn new(gauge: Gauge) -> Self {
+        Self {
+            aggregation: GaugeAggregation::from_metadata(&gauge.metadata),
+            sum: gauge.value as i128,
+            count: 1,
+            gauge,
+        }
+    }
+
+    fn update(&mut self, gauge: Gauge) {
+        let value = match self.aggregation {
+            GaugeAggregation::Last | GaugeAggregation::Mean => gauge.value,
+            GaugeAggregation::Max => self.gauge.value.max(gauge.value),
+            GaugeAggregation::Min => self.gauge.value.min(gauge.value),
+            GaugeAggregation::Sum => self.gauge.value.wrapping_add(gauge.value),
+        };
+        self.sum += gauge.value as i128;
+        self.count += 1;
+        self.gauge = gauge;
+        self.gauge.value = value;
+    }
+
+    fn finish(mut self) -> Gauge {
+        if self.aggregation == GaugeAggregation::Mean {
+            self.gauge.value = (self.sum / self.count) as i64;
+        }
+        self.gauge
+    }
+}
+
+/// Metrics from each snapshot in a window, keyed by series and kept in the
+/// order they were first seen.
+#[derive(Default)]
+struct Window {
+    counters: Series<Counter>,
+    gauges: Series<GaugeState>,
+    histograms: Series<Histogram>,
+    stats: Series<Stats>,
+    sketches: Series<Sketch>,
+    cardinalities: Series<Cardinality>,
+    top_k: Series<TopK>,
+    events: Vec<Event>,
+}
+
+struct Series<T> {
+    index: HashMap<SeriesKey, usize>,
+    metrics: Vec<T>,
+}
+
+impl<T> Default for Series<T> {
+    fn default() -> Self {
+        Self {
+            index: HashMap::new(),
+            metrics: Vec::new(),
+        }
+    }
+}
+
+impl<T> Series<T> {
+    fn merge(&mut self, key: SeriesKey, metric: T, update: impl FnOnce(&mut T, T)) {
+        match self.index.get(&key) {
+            Some(idx) => update(&mut self.metrics[*idx], metric),
+            None => {
+                self.index.insert(key, self.metrics.len());
+                self.metrics.push(metric);
+            }
+        }
+    }
+}
+
+impl Window {
+    fn add(&mut self, snapshot: Snapshot) {
+        for counter in snapshot.counters {
+            self.counters.merge(
+                series_key(&counter.name, &counter.metadata),
+                counter,
+                |current, counter| {
+                    let value = match counter.metric_type {
+                        MetricType::DeltaCounter => current.value.wrapping_add(counter.value),
+                        _ => counter.value,
+                    };
+                    *current = counter;
+                    current.value = value;
+                },
+            );
+        }
+
+        for gauge in snapshot.gauges {
+            self.gauges.merge(
+                series_key(&gauge.name, &gauge.metadata),
+                GaugeState::new(gauge),
+                |current, state| current.update(state.gauge),
+            );
+        }
+
+        for histogram in snapshot.histograms {
+            self.histograms.merge(
+                series_key(&histogram.name, &histogram.metadata),
+                histogram,
+                |current, histogram| {
+                    let value = match histogram.metric_type {
+                        MetricType::DeltaHistogram => {
+                            current.value.wrapping_add(&histogram.value).ok()
+                        }
+                        _ => None,
+                    };
+                    *current = histogram;
+                    if let Some(value) = value {
+                        current.value = value;
+                    }
+                },
+            );
+        }
+
+        for stats in snapshot.stats {
+            self.stats.merge(
+                series_key(&stats.name, &stats.metadata),
+                stats,
+                |current, stats| {
+                    // each snapshot holds the stats of its own interval
+                    let min = current.min.into_iter().chain(stats.min).min();
+                    let max = current.max.into_iter().chain(stats.max).max();
+                    let sum = current.sum.wrapping_add(stats.sum);
+                    let count = current.count.wrapping_add(stats.count);
+                    *current = stats;
+                    current.min = min;
+                    current.max = max;
+                    current.sum = sum;
+                    current.count = count;
+                },
+            );
+        }
+
+        for sketch in snapshot.sketches {
+            self.sketches.merge(
+                series_key(&sketch.name, &sketch.metadata),
+                sketch,
+                |current, sketch| {
+                    let delta =
+                        sketch.metadata.get("temporality").map(|v| v.as_str()) == Some("delta");
+                    let mut value = current.value.clone();
+                    let merged = delta && value.merge(&sketch.value).is_ok();
+                    *current = sketch;
+                    if merged {
+                        current.value = value;
+                    }
+                },
+            );
+        }
+
+        // each snapshot holds the distinct values seen in its own interval,
+        // so the window holds the union of them
+        for cardinality in snapshot.cardinalities {
+            self.cardinalities.merge(
+                series_key(&cardinality.name, &cardinality.metadata),
+                cardinality,
+                |current, mut cardinality| {
+                    let _ = cardinality.merge(&current.value);
+                    *current = cardinality;
+                },
+            );
+        }
+
+        for top_k in snapshot.top_k {
+            self.top_k.merge(
+                series_key(&top_k.name, &top_k.metadata),
+                top_k,
+                |current, mut top_k| {
+                    top_k.merge(current);
+                    *current = top_k;
+                },
+            );
+        }
+
+        self.events.extend(snapshot.events);
+    }
+
+    fn finish(self, snapshot: &mut Snapshot) {
+        snapshot.counters = self.counters.metrics;
+        snapshot.gauges = self
+            .gauges
+            .metrics
+            .into_iter()
+            .map(GaugeState::finish)
+            .collect();
+        snapshot.histograms = self.histograms.metrics;
+        snapshot.stats = self.stats.metrics;
+        snapshot.sketches = self.sketches.metrics;
+        snapshot.cardinalities = self.cardinalities.metrics;
+        snapshot.top_k = self.top_k.metrics;
+        snapshot.events = self.events;
+    }
+}
+
+/// Reduces a stream of snapshots to at most one snapshot per interval.
+///
+/// Snapshots are grouped into windows of the provided interval, aligned to
+/// the unix epoch, and each window produces a single snapshot with the time
+/// and metadata of the last snapshot in it. Cumulative counters and
+/// histograms keep their last reading, delta counters and histograms are
+/// summed, and gauges are combined according to their [`GaugeAggregation`].
+/// Stats are combined into the min, max, sum, and count of the whole window,
+/// and the events of every snapshot in the window are kept.
+///
+/// The input is expected to be in time order.
+pub struct Downsample<I: Iterator<Item = Snapshot>> {
+    snapshots: Peekable<I>,
+    interval: Duration,
+}
+
+impl<I: Iterator<Item = Snapshot>> Downsample<I> {
+    /// Downsample the snapshots to the provided interval.
+    pub fn new(snapshots: impl IntoIterator<IntoIter = I>, interval: Duration) -> Self {
+        Self {
+            snapshots: snapshots.into_iter().peekable(),
+            interval,
+        }
+    }
+}
+
+/// The index of the window of the provided interval which contains the time.
+fn window(time: SystemTime, interval: Duration) -> u128 {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    since_epoch / interval.as_nanos().max(1)
+}
+
+impl<I: Iterator<Item = Snapshot>> Iterator for Downsample<I> {
+    type Item = Snapshot;
+
+    fn next(&mut self) -> Option<Snapshot> {
+        let first = self.snapshots.next()?;
+        let interval = self.interval;
+        let current = window(first.systemtime, interval);
+
+        let mut metrics = Window::default();
+        let mut last = Snapshot::new();
+        last.systemtime = first.systemtime;
+        last.metadata = first.metadata.clone();
+        metrics.add(first);
+
+        while let Some(snapshot) = self
+            .snapshots
+            .next_if(|s| window(s.systemtime, interval) == current)
+        {
+            last.systemtime = snapshot.systemtime;
+            last.metadata = snapshot.metadata.clone();
+            metrics.add(snapshot);
+        }
+
+        metrics.finish(&mut last);
+        Some(last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(seconds: u64, value: i64, aggregation: GaugeAggregation) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        snapshot.counters.push(Counter {
+            name: "requests".to_string(),
+            metric_type: MetricType::DeltaCounter,
+            value: 1,
+            metadata: HashMap::new(),
+        });
+        snapshot.gauges.push(Gauge {
+            name: "depth".to_string(),
+            metric_type: MetricType::Gauge,
+            value,
+            metadata: HashMap::from([(AGGREGATION.to_string(), aggregation.as_str().to_string())]),
+        });
+        snapshot
+    }
+
+    fn downsample(aggregation: GaugeAggregation) -> Vec<(u64, i64)> {
+        let snapshots = [(0, 3), (1, 9), (2, 4), (10, 1), (11, 2)]
+            .map(|(seconds, value)| snapshot(seconds, value, aggregation));
+
+        Downsample::new(snapshots, Duration::from_secs(10))
+            .map(|s| (s.counters[0].value, s.gauges[0].value))
+            .collect()
+    }
+
+    #[test]
+    fn aggregation() {
+        assert_eq!(downsample(GaugeAggregation::Last), [(3, 4), (2, 2)]);
+        assert_eq!(downsample(GaugeAggregation::Max), [(3, 9), (2, 2)]);
+        assert_eq!(downsample(GaugeAggregation::Min), [(3, 3), (2, 1)]);
+        assert_eq!(downsample(GaugeAggregation::Mean), [(3, 5), (2, 1)]);
+        assert_eq!(downsample(GaugeAggregation::Sum), [(3, 16), (2, 3)]);
+    }
+
+    #[test]
+    fn time_and_metadata() {
+        let mut snapshots = vec![
+            snapshot(1, 0, GaugeAggregation::Last),
+            snapshot(5, 0, GaugeAggregation::Last),
+        ];
+        snapshots[0].events.push(Event::new("deploy"));
+        snapshots[1].events.push(Event::new("gc"));
+
+        let downsampled: Vec<_> = Downsample::new(snapshots, Duration::from_secs(60)).collect();
+        assert_eq!(downsampled.len(), 1);
+        assert_eq!(
+            downsampled[0].systemtime,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(5)
+        );
+
+        let events: Vec<&str> = downsampled[0]
+            .events
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(events, ["deploy", "gc"]);
+    }
+
+    #[test]
+    fn sketches() {
+        let snapshots = (0..3).map(|seconds| {
+            let mut sketch = crate::DDSketch::new(0.01).unwrap();
+            sketch.add(seconds as f64);
+
+            let mut snapshot = snapshot(seconds, 0, GaugeAggregation::Last);
+            snapshot
+                .sketches
+                .push(Sketch::new("cumulative", sketch.clone()));
+            let mut delta = Sketch::new("delta", sketch);
+            delta
+                .metadata
+                .insert("temporality".to_string(), "delta".to_string());
+            snapshot.sketches.push(delta);
+            snapshot
+        });
+
+        let downsampled: Vec<_> = Downsample::new(snapshots, Duration::from_secs(60)).collect();
+        let counts: Vec<u64> = downsampled[0]
+            .sketches
+            .iter()
+            .map(|s| s.value.count())
+            .collect();
+        assert_eq!(counts, [1, 3]);
+    }
+
+    #[test]
+    fn stats() {
+        let snapshots =
+            [(0, None), (1, Some((2, 8))), (2, Some((5, 6)))].map(|(seconds, range)| {
+                let mut stats = Stats::new("size");
+                if let Some((min, max)) = range {
+                    stats.min = Some(min);
+                    stats.max = Some(max);
+                    stats.sum = min + max;
+                    stats.count = 2;
+                }
+
+                let mut snapshot = snapshot(seconds, 0, GaugeAggregation::Last);
+                snapshot.stats.push(stats);
+                snapshot
+            });
+
+        let downsampled: Vec<_> = Downsample::new(snapshots, Duration::from_secs(60)).collect();
+        let stats = &downsampled[0].stats[0];
+        assert_eq!((stats.min, stats.max), (Some(2), Some(8)));
+        assert_eq!((stats.sum, stats.count), (21, 4));
+    }
+
+    #[test]
+    fn cardinalities() {
+        let snapshots = (0..3).map(|seconds| {
+            let mut sketch = crate::HyperLogLog::new(8).unwrap();
+            // every interval sees the same value and one of its own
+            sketch.insert_hash(0);
+            sketch.insert_hash((seconds + 1) << 61);
+
+            let mut snapshot = snapshot(seconds, 0, GaugeAggregation::Last);
+            snapshot
+                .cardinalities
+                .push(Cardinality::new("clients", sketch));
+            snapshot
+        });
+
+        let downsampled: Vec<_> = Downsample::new(snapshots, Duration::from_secs(60)).collect();
+        assert_eq!(downsampled[0].cardinalities[0].estimate, 4);
+    }
+
+    #[test]
+    fn from_metadata() {
+        assert_eq!(
+            GaugeAggregation::from_metadata(&HashMap::new()),
+            GaugeAggregation::Last
+        );
+        assert_eq!(
+            GaugeAggregation::from_metadata(&HashMap::from([(
+                AGGREGATION.to_string(),
+                "bogus".to_string()
+            )])),
+            GaugeAggregation::Last
+        );
+    }
+}
@@ -0,0 +1,205 @@
+/// The largest scale supported by Prometheus native histograms and the
+/// OpenTelemetry exponential histogram data model in practice.
+pub const MAX_SCALE: i32 = 8;
+
+/// The smallest scale supported by Prometheus native histograms.
+pub const MIN_SCALE: i32 = -4;
+
+/// A histogram using the base-2 exponential bucketing shared by OTLP
+/// exponential histograms and Prometheus native histograms.
+///
+/// Bucket boundaries are powers of `base = 2^(2^-scale)`, and the bucket at
+/// index `i` covers the range `(base^i, base^(i+1)]`. Only positive buckets
+/// are represented since metriken histograms do not hold negative values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExponentialHistogram {
+    /// The resolution of the histogram. This is the Prometheus `schema`.
+    pub scale: i32,
+    /// The count of values which are exactly zero.
+    pub zero_count: u64,
+    /// The index of the first entry in `counts`.
+    pub offset: i32,
+    /// Contiguous bucket counts, starting at `offset`.
+    pub counts: Vec<u64>,
+}
+
+impl ExponentialHistogram {
+    /// Convert a metriken histogram, selecting the scale which best matches
+    /// its resolution.
+    ///
+    /// A histogram with grouping power `g` divides each power of two into
+    /// `2^g` buckets, as does an exponential histogram with scale `g`, so the
+    /// grouping power is used as the scale, clamped to at most 8.
+    pub fn from_histogram(histogram: &histogram::Histogram) -> Self {
+        let scale = (histogram.config().grouping_power() as i32).clamp(MIN_SCALE, MAX_SCALE);
+        Self::with_scale(histogram, scale)
+    }
+
+    /// Convert a metriken histogram using the provided scale.
+    ///
+    /// The source buckets are linear within each power of two, so their
+    /// boundaries do not line up exactly with the exponential buckets. Each
+    /// source bucket is attributed to the exponential bucket which contains
+    /// its upper bound, so that no value is placed in a bucket whose upper
+    /// boundary is less than it.
+    pub fn with_scale(histogram: &histogram::Histogram, scale: i32) -> Self {
+        let mut zero_count = 0;
+        let mut buckets: Vec<(i32, u64)> = Vec::new();
+
+        for bucket in histogram {
+            if bucket.count() == 0 {
+                continue;
+            }
+
+            if bucket.end() == 0 {
+                zero_count += bucket.count();
+                continue;
+            }
+
+            let index = index_for(bucket.end(), scale);
+            match buckets.last_mut() {
+                Some((last, count)) if *last == index => *count += bucket.count(),
+                _ => buckets.push((index, bucket.count())),
+            }
+        }
+
+        let offset = buckets.first().map(|(index, _)| *index).unwrap_or(0);
+        let mut counts = Vec::new();
+        for (index, count) in buckets {
+            let position = (index - offset) as usize;
+            if counts.len() <= position {
+                counts.resize(position + 1, 0);
+            }
+            counts[position] += count;
+        }
+
+        Self {
+            scale,
+            zero_count,
+            offset,
+            counts,
+        }
+    }
+
+    /// The total number of values in the histogram.
+    pub fn count(&self) -> u64 {
+        self.zero_count + self.counts.iter().sum::<u64>()
+    }
+
+    /// Convert back into a metriken histogram.
+    ///
+    /// The scale is used as the grouping power, so the resolution of the
+    /// result matches that of the source for scales from zero to 8. Metriken
+    /// histograms hold integers, so the count of each bucket is recorded at
+    /// the largest integer within the bucket. This is the inverse of
+    /// [`Self::with_scale`], which places each source bucket into the
+    /// exponential bucket containing its upper bound.
+    pub fn to_histogram(&self) -> Result<histogram::Histogram, histogram::Error> {
+        let grouping_power = self.scale.clamp(0, MAX_SCALE) as u8;
+        let mut histogram = histogram::Histogram::new(grouping_power, 64)?;
+
+        if self.zero_count > 0 {
+            histogram.add(0, self.zero_count)?;
+        }
+
+        for (position, count) in self.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+
+            let upper = self.upper_bound(self.offset + position as i32);
+            let value = if upper >= u64::MAX as f64 {
+                u64::MAX
+            } else {
+                upper.floor() as u64
+            };
+            histogram.add(value, *count)?;
+        }
+
+        Ok(histogram)
+    }
+
+    /// The upper boundary of the bucket at the provided index.
+    pub fn upper_bound(&self, index: i32) -> f64 {
+        2.0_f64.powf((index + 1) as f64 * 2.0_f64.powi(-self.scale))
+    }
+}
+
+/// Map a positive value to the index of the exponential bucket containing it.
+fn index_for(value: u64, scale: i32) -> i32 {
+    // Powers of two are exact boundaries and are computed without floating
+    // point to avoid rounding them into the next bucket.
+    if value.is_power_of_two() {
+        let exponent = value.trailing_zeros() as i32;
+        return if scale >= 0 {
+            (exponent << scale) - 1
+        } else {
+            ((exponent + (1 << -scale) - 1) >> -scale) - 1
+        };
+    }
+
+    let scaled = (value as f64).log2() * 2.0_f64.powi(scale);
+    scaled.ceil() as i32 - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_matches_grouping_power() {
+        let mut histogram = histogram::Histogram::new(3, 20).unwrap();
+        histogram.add(0, 2).unwrap();
+        histogram.add(1, 1).unwrap();
+        histogram.add(1000, 5).unwrap();
+
+        let exponential = ExponentialHistogram::from_histogram(&histogram);
+        assert_eq!(exponential.scale, 3);
+        assert_eq!(exponential.zero_count, 2);
+        assert_eq!(exponential.count(), 8);
+
+        // 1 lies on the boundary of bucket -1 which covers (2^-1/8, 1]
+        assert_eq!(exponential.offset, -1);
+        assert_eq!(exponential.counts[0], 1);
+
+        // the bucket holding 1000 must have an upper bound of at least 1000
+        let last = exponential.offset + exponential.counts.len() as i32 - 1;
+        assert_eq!(*exponential.counts.last().unwrap(), 5);
+        assert!(exponential.upper_bound(last) >= 1000.0);
+        assert!(exponential.upper_bound(last - 1) < 1000.0);
+    }
+
+    #[test]
+    fn index() {
+        assert_eq!(index_for(1, 0), -1);
+        assert_eq!(index_for(2, 0), 0);
+        assert_eq!(index_for(3, 0), 1);
+        assert_eq!(index_for(4, 0), 1);
+        assert_eq!(index_for(4, 1), 3);
+        assert_eq!(index_for(4, -1), 0);
+        assert_eq!(index_for(5, -1), 1);
+    }
+
+    #[test]
+    fn to_histogram() {
+        let mut histogram = histogram::Histogram::new(3, 20).unwrap();
+        histogram.add(0, 2).unwrap();
+        histogram.add(1000, 5).unwrap();
+
+        let exponential = ExponentialHistogram::from_histogram(&histogram);
+        let converted = exponential.to_histogram().unwrap();
+        assert_eq!(converted.config().grouping_power(), 3);
+
+        let buckets: Vec<(u64, u64)> = converted
+            .into_iter()
+            .filter(|b| b.count() > 0)
+            .map(|b| (b.start(), b.count()))
+            .collect();
+        assert_eq!(buckets[0], (0, 2));
+
+        // within the resolution of the source histogram
+        assert_eq!(buckets[1].1, 5);
+        assert!(buckets[1].0.abs_diff(1000) < 1000 / 8);
+        assert_eq!(ExponentialHistogram::from_histogram(&converted).count(), 7);
+    }
+}
@@ -0,0 +1,523 @@
+//! FlatBuffers tables matching `schema/snapshot.fbs`.
+//!
+//! The accessors are written by hand in the same shape as `flatc` output so
+//! that the build does not depend on the FlatBuffers compiler.
+
+use std::time::{Duration, SystemTime};
+
+use flatbuffers::{
+    FlatBufferBuilder, Follow, ForwardsUOffset, InvalidFlatbuffer, Table, VOffsetT, Vector,
+    Verifiable, Verifier, WIPOffset,
+};
+
+use crate::snapshot;
+
+/// Errors that can occur while reading a FlatBuffers snapshot.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum FlatbuffersError {
+    Invalid(InvalidFlatbuffer),
+    Histogram(histogram::Error),
+}
+
+impl std::fmt::Display for FlatbuffersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "invalid flatbuffer: {e}"),
+            Self::Histogram(e) => write!(f, "invalid histogram: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FlatbuffersError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Invalid(e) => Some(e),
+            Self::Histogram(e) => Some(e),
+        }
+    }
+}
+
+impl From<InvalidFlatbuffer> for FlatbuffersError {
+    fn from(e: InvalidFlatbuffer) -> Self {
+        Self::Invalid(e)
+    }
+}
+
+impl From<histogram::Error> for FlatbuffersError {
+    fn from(e: histogram::Error) -> Self {
+        Self::Histogram(e)
+    }
+}
+
+/// The vtable offset of the field with the provided id.
+const fn field(id: VOffsetT) -> VOffsetT {
+    4 + 2 * id
+}
+
+type Metrics<'a, T> = Vector<'a, ForwardsUOffset<T>>;
+
+macro_rules! table {
+    ($name:ident) => {
+        #[derive(Clone, Copy)]
+        struct $name<'a> {
+            table: Table<'a>,
+        }
+
+        impl<'a> Follow<'a> for $name<'a> {
+            type Inner = Self;
+
+            unsafe fn follow(buf: &'a [u8], loc: usize) -> Self {
+                Self {
+                    table: Table::new(buf, loc),
+                }
+            }
+        }
+    };
+}
+
+table!(SnapshotTable);
+table!(CounterTable);
+table!(GaugeTable);
+table!(HistogramTable);
+
+impl<'a> SnapshotTable<'a> {
+    const TIMESTAMP: VOffsetT = field(0);
+    const COUNTERS: VOffsetT = field(1);
+    const GAUGES: VOffsetT = field(2);
+    const HISTOGRAMS: VOffsetT = field(3);
+
+    fn timestamp(&self) -> u64 {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe { self.table.get::<u64>(Self::TIMESTAMP, Some(0)) }.unwrap_or(0)
+    }
+
+    fn counters(&self) -> Option<Metrics<'a, CounterTable<'a>>> {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe {
+            self.table
+                .get::<ForwardsUOffset<Metrics<CounterTable>>>(Self::COUNTERS, None)
+        }
+    }
+
+    fn gauges(&self) -> Option<Metrics<'a, GaugeTable<'a>>> {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe {
+            self.table
+                .get::<ForwardsUOffset<Metrics<GaugeTable>>>(Self::GAUGES, None)
+        }
+    }
+
+    fn histograms(&self) -> Option<Metrics<'a, HistogramTable<'a>>> {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe {
+            self.table
+                .get::<ForwardsUOffset<Metrics<HistogramTable>>>(Self::HISTOGRAMS, None)
+        }
+    }
+}
+
+impl Verifiable for SnapshotTable<'_> {
+    fn run_verifier(v: &mut Verifier, pos: usize) -> Result<(), InvalidFlatbuffer> {
+        v.visit_table(pos)?
+            .visit_field::<u64>("timestamp", Self::TIMESTAMP, false)?
+            .visit_field::<ForwardsUOffset<Metrics<CounterTable>>>(
+                "counters",
+                Self::COUNTERS,
+                false,
+            )?
+            .visit_field::<ForwardsUOffset<Metrics<GaugeTable>>>("gauges", Self::GAUGES, false)?
+            .visit_field::<ForwardsUOffset<Metrics<HistogramTable>>>(
+                "histograms",
+                Self::HISTOGRAMS,
+                false,
+            )?
+            .finish();
+        Ok(())
+    }
+}
+
+impl<'a> CounterTable<'a> {
+    const NAME: VOffsetT = field(0);
+    const VALUE: VOffsetT = field(1);
+
+    fn name(&self) -> &'a str {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe { self.table.get::<ForwardsUOffset<&str>>(Self::NAME, None) }.unwrap_or_default()
+    }
+
+    fn value(&self) -> u64 {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe { self.table.get::<u64>(Self::VALUE, Some(0)) }.unwrap_or(0)
+    }
+}
+
+impl Verifiable for CounterTable<'_> {
+    fn run_verifier(v: &mut Verifier, pos: usize) -> Result<(), InvalidFlatbuffer> {
+        v.visit_table(pos)?
+            .visit_field::<ForwardsUOffset<&str>>("name", Self::NAME, false)?
+            .visit_field::<u64>("value", Self::VALUE, false)?
+            .finish();
+        Ok(())
+    }
+}
+
+impl<'a> GaugeTable<'a> {
+    const NAME: VOffsetT = field(0);
+    const VALUE: VOffsetT = field(1);
+
+    fn name(&self) -> &'a str {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe { self.table.get::<ForwardsUOffset<&str>>(Self::NAME, None) }.unwrap_or_default()
+    }
+
+    fn value(&self) -> i64 {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe { self.table.get::<i64>(Self::VALUE, Some(0)) }.unwrap_or(0)
+    }
+}
+
+impl Verifiable for GaugeTable<'_> {
+    fn run_verifier(v: &mut Verifier, pos: usize) -> Result<(), InvalidFlatbuffer> {
+        v.visit_table(pos)?
+            .visit_field::<ForwardsUOffset<&str>>("name", Self::NAME, false)?
+            .visit_field::<i64>("value", Self::VALUE, false)?
+            .finish();
+        Ok(())
+    }
+}
+
+impl<'a> HistogramTable<'a> {
+    const NAME: VOffsetT = field(0);
+    const GROUPING_POWER: VOffsetT = field(1);
+    const MAX_VALUE_POWER: VOffsetT = field(2);
+    const BUCKET_INDICES: VOffsetT = field(3);
+    const BUCKET_COUNTS: VOffsetT = field(4);
+
+    fn name(&self) -> &'a str {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe { self.table.get::<ForwardsUOffset<&str>>(Self::NAME, None) }.unwrap_or_default()
+    }
+
+    fn grouping_power(&self) -> u8 {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe { self.table.get::<u8>(Self::GROUPING_POWER, Some(0)) }.unwrap_or(0)
+    }
+
+    fn max_value_power(&self) -> u8 {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe { self.table.get::<u8>(Self::MAX_VALUE_POWER, Some(0)) }.unwrap_or(0)
+    }
+
+    fn bucket_indices(&self) -> Option<Vector<'a, u64>> {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe {
+            self.table
+                .get::<ForwardsUOffset<Vector<u64>>>(Self::BUCKET_INDICES, None)
+        }
+    }
+
+    fn bucket_counts(&self) -> Option<Vector<'a, u64>> {
+        // SAFETY: the buffer was verified when the view was created
+        unsafe {
+            self.table
+                .get::<ForwardsUOffset<Vector<u64>>>(Self::BUCKET_COUNTS, None)
+        }
+    }
+
+    fn to_histogram(self) -> Result<histogram::Histogram, FlatbuffersError> {
+        let config = histogram::Config::new(self.grouping_power(), self.max_value_power())?;
+
+        let index: Vec<usize> = self
+            .bucket_indices()
+            .map(|v| v.iter().map(|i| i as usize).collect())
+            .unwrap_or_default();
+        let count: Vec<u64> = self
+            .bucket_counts()
+            .map(|v| v.iter().collect())
+            .unwrap_or_default();
+
+        if index.len() != count.len() || index.iter().any(|i| *i >= config.total_buckets()) {
+            return Err(histogram::Error::OutOfRange.into());
+        }
+
+        let sparse = histogram::SparseHistogram {
+            config,
+            index,
+            count,
+        };
+
+        Ok(histogram::Histogram::from(&sparse))
+    }
+}
+
+impl Verifiable for HistogramTable<'_> {
+    fn run_verifier(v: &mut Verifier, pos: usize) -> Result<(), InvalidFlatbuffer> {
+        v.visit_table(pos)?
+            .visit_field::<ForwardsUOffset<&str>>("name", Self::NAME, false)?
+            .visit_field::<u8>("grouping_power", Self::GROUPING_POWER, false)?
+            .visit_field::<u8>("max_value_power", Self::MAX_VALUE_POWER, false)?
+            .visit_field::<ForwardsUOffset<Vector<u64>>>(
+                "bucket_indices",
+                Self::BUCKET_INDICES,
+                false,
+            )?
+            .visit_field::<ForwardsUOffset<Vector<u64>>>(
+                "bucket_counts",
+                Self::BUCKET_COUNTS,
+                false,
+            )?
+            .finish();
+        Ok(())
+    }
+}
+
+/// Find the first entry with the provided name in a vector which is sorted by
+/// name.
+fn find<'a, T, F>(metrics: Option<Metrics<'a, T>>, name: &str, key: F) -> Option<T::Inner>
+where
+    T: Follow<'a> + 'a,
+    F: Fn(&T::Inner) -> &'a str,
+{
+    let metrics = metrics?;
+
+    let (mut low, mut high) = (0, metrics.len());
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if key(&metrics.get(mid)) < name {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    if low < metrics.len() {
+        let metric = metrics.get(low);
+        if key(&metric) == name {
+            return Some(metric);
+        }
+    }
+
+    None
+}
+
+/// A read-only view of a snapshot encoded with
+/// [`Snapshot::to_flatbuffers`](crate::Snapshot::to_flatbuffers).
+///
+/// Creating the view verifies the buffer but does not copy or decode it, so
+/// individual metrics can be looked up cheaply. Lookups by name use a binary
+/// search. If several metrics share a name, the lookups return the first of
+/// them and the iterators return all of them.
+#[derive(Clone, Copy)]
+pub struct FlatSnapshot<'a> {
+    root: SnapshotTable<'a>,
+}
+
+impl<'a> FlatSnapshot<'a> {
+    /// Verify the provided buffer and create a view over it.
+    pub fn new(buf: &'a [u8]) -> Result<Self, FlatbuffersError> {
+        let root = flatbuffers::root::<SnapshotTable>(buf)?;
+        Ok(Self { root })
+    }
+
+    /// The time at which the snapshot was taken.
+    pub fn systemtime(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(self.root.timestamp())
+    }
+
+    /// The value of the counter with the provided name.
+    pub fn counter(&self, name: &str) -> Option<u64> {
+        find(self.root.counters(), name, CounterTable::name).map(|c| c.value())
+    }
+
+    /// The value of the gauge with the provided name.
+    pub fn gauge(&self, name: &str) -> Option<i64> {
+        find(self.root.gauges(), name, GaugeTable::name).map(|g| g.value())
+    }
+
+    /// Decode the histogram with the provided name.
+    pub fn histogram(&self, name: &str) -> Result<Option<histogram::Histogram>, FlatbuffersError> {
+        find(self.root.histograms(), name, HistogramTable::name)
+            .map(HistogramTable::to_histogram)
+            .transpose()
+    }
+
+    /// The names and values of all counters, sorted by name.
+    pub fn counters(&self) -> impl Iterator<Item = (&'a str, u64)> {
+        self.root
+            .counters()
+            .into_iter()
+            .flat_map(|v| v.iter())
+            .map(|c| (c.name(), c.value()))
+    }
+
+    /// The names and values of all gauges, sorted by name.
+    pub fn gauges(&self) -> impl Iterator<Item = (&'a str, i64)> {
+        self.root
+            .gauges()
+            .into_iter()
+            .flat_map(|v| v.iter())
+            .map(|g| (g.name(), g.value()))
+    }
+
+    /// The names of all histograms, sorted by name.
+    pub fn histogram_names(&self) -> impl Iterator<Item = &'a str> {
+        self.root
+            .histograms()
+            .into_iter()
+            .flat_map(|v| v.iter())
+            .map(|h| h.name())
+    }
+}
+
+impl snapshot::Snapshot {
+    /// Encode this snapshot using the FlatBuffers schema in
+    /// `schema/snapshot.fbs`, which can be read without decoding it using
+    /// [`FlatSnapshot`].
+    ///
+    /// Only the timestamp, counters, gauges, and histograms are encoded.
+    /// Metadata and stats are omitted.
+    pub fn to_flatbuffers(&self) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let mut counters: Vec<&snapshot::Counter> = self.counters.iter().collect();
+        counters.sort_by(|a, b| a.name.cmp(&b.name));
+        let counters: Vec<WIPOffset<CounterTable>> = counters
+            .into_iter()
+            .map(|c| {
+                let name = fbb.create_string(&c.name);
+                let start = fbb.start_table();
+                fbb.push_slot::<u64>(CounterTable::VALUE, c.value, 0);
+                fbb.push_slot_always(CounterTable::NAME, name);
+                WIPOffset::new(fbb.end_table(start).value())
+            })
+            .collect();
+        let counters = fbb.create_vector(&counters);
+
+        let mut gauges: Vec<&snapshot::Gauge> = self.gauges.iter().collect();
+        gauges.sort_by(|a, b| a.name.cmp(&b.name));
+        let gauges: Vec<WIPOffset<GaugeTable>> = gauges
+            .into_iter()
+            .map(|g| {
+                let name = fbb.create_string(&g.name);
+                let start = fbb.start_table();
+                fbb.push_slot::<i64>(GaugeTable::VALUE, g.value, 0);
+                fbb.push_slot_always(GaugeTable::NAME, name);
+                WIPOffset::new(fbb.end_table(start).value())
+            })
+            .collect();
+        let gauges = fbb.create_vector(&gauges);
+
+        let mut histograms: Vec<&snapshot::Histogram> = self.histograms.iter().collect();
+        histograms.sort_by(|a, b| a.name.cmp(&b.name));
+        let histograms: Vec<WIPOffset<HistogramTable>> = histograms
+            .into_iter()
+            .map(|h| {
+                let sparse = histogram::SparseHistogram::from(&h.value);
+                let indices: Vec<u64> = sparse.index.iter().map(|i| *i as u64).collect();
+
+                let name = fbb.create_string(&h.name);
+                let indices = fbb.create_vector(&indices);
+                let counts = fbb.create_vector(&sparse.count);
+                let start = fbb.start_table();
+                fbb.push_slot_always(HistogramTable::BUCKET_COUNTS, counts);
+                fbb.push_slot_always(HistogramTable::BUCKET_INDICES, indices);
+                fbb.push_slot_always(HistogramTable::NAME, name);
+                fbb.push_slot::<u8>(
+                    HistogramTable::MAX_VALUE_POWER,
+                    sparse.config.max_value_power(),
+                    0,
+                );
+                fbb.push_slot::<u8>(
+                    HistogramTable::GROUPING_POWER,
+                    sparse.config.grouping_power(),
+                    0,
+                );
+                WIPOffset::new(fbb.end_table(start).value())
+            })
+            .collect();
+        let histograms = fbb.create_vector(&histograms);
+
+        let timestamp = self
+            .systemtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        let start = fbb.start_table();
+        fbb.push_slot::<u64>(SnapshotTable::TIMESTAMP, timestamp, 0);
+        fbb.push_slot_always(SnapshotTable::HISTOGRAMS, histograms);
+        fbb.push_slot_always(SnapshotTable::GAUGES, gauges);
+        fbb.push_slot_always(SnapshotTable::COUNTERS, counters);
+        let root: WIPOffset<SnapshotTable> = WIPOffset::new(fbb.end_table(start).value());
+
+        fbb.finish(root, None);
+        fbb.finished_data().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::MetricType;
+
+    #[test]
+    fn view() {
+        let mut value = histogram::Histogram::new(4, 10).unwrap();
+        value.add(100, 3).unwrap();
+
+        let mut snapshot = snapshot::Snapshot::new();
+        for (name, value) in [("b", 2), ("c", 3), ("a", 1)] {
+            snapshot.counters.push(snapshot::Counter {
+                name: name.to_string(),
+                metric_type: MetricType::Counter,
+                value,
+                metadata: HashMap::new(),
+            });
+        }
+        snapshot.gauges.push(snapshot::Gauge {
+            name: "gauge".to_string(),
+            metric_type: MetricType::Gauge,
+            value: -4,
+            metadata: HashMap::new(),
+        });
+        snapshot.histograms.push(snapshot::Histogram {
+            name: "histogram".to_string(),
+            metric_type: MetricType::Histogram,
+            value: value.clone(),
+            metadata: HashMap::new(),
+        });
+
+        let encoded = snapshot.to_flatbuffers();
+        let view = FlatSnapshot::new(&encoded).unwrap();
+
+        assert_eq!(
+            view.systemtime()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos(),
+            snapshot
+                .systemtime
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        );
+        assert_eq!(view.counter("a"), Some(1));
+        assert_eq!(view.counter("c"), Some(3));
+        assert_eq!(view.counter("d"), None);
+        assert_eq!(
+            view.counters().collect::<Vec<_>>(),
+            vec![("a", 1), ("b", 2), ("c", 3)]
+        );
+        assert_eq!(view.gauge("gauge"), Some(-4));
+        assert_eq!(view.histogram("histogram").unwrap(), Some(value));
+        assert_eq!(view.histogram("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(FlatSnapshot::new(&[1, 2, 3]).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+/// How floating point values which are not finite, NaN and ±infinity, are
+/// exported.
+///
+/// Not every format can hold these values: JSON has no representation for
+/// them at all, while Prometheus and parquet do. Exporters of floating point
+/// values apply a single policy with [`NonFinite::apply`] so that the same
+/// value is handled the same way in every format, rather than depending on
+/// the behavior of each serializer. Finite values are always exported as
+/// numbers. For values which are not finite:
+///
+/// | policy      | JSON, msgpack               | Prometheus            | parquet         |
+/// |-------------|-----------------------------|-----------------------|-----------------|
+/// | `Null`      | `null`                      | sample omitted        | null            |
+/// | `Drop`      | entry omitted               | sample omitted        | null            |
+/// | `Stringify` | `"NaN"`, `"+Inf"`, `"-Inf"` | `NaN`, `+Inf`, `-Inf` | the float value |
+///
+/// Prometheus has no null, so `Null` omits the sample just like `Drop`, and
+/// a parquet column has a cell for every row, so `Drop` leaves it null. The
+/// strings are the spellings used by Prometheus, so `Stringify` gives the
+/// native representation in the formats which have one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NonFinite {
+    /// Export the value as null, where the format allows it.
+    #[default]
+    Null,
+    /// Leave the value out.
+    Drop,
+    /// Export the value as `NaN`, `+Inf`, or `-Inf`.
+    Stringify,
+}
+
+impl NonFinite {
+    /// Apply the policy to a value, returning `None` if it is to be left out.
+    ///
+    /// ```
+    /// # use metriken_exposition::{FloatValue, NonFinite};
+    /// assert_eq!(NonFinite::Drop.apply(1.5), Some(FloatValue::Number(1.5)));
+    /// assert_eq!(NonFinite::Drop.apply(f64::NAN), None);
+    /// assert_eq!(NonFinite::Null.apply(f64::INFINITY), Some(FloatValue::Null));
+    /// assert_eq!(
+    ///     NonFinite::Stringify.apply(f64::NEG_INFINITY),
+    ///     Some(FloatValue::String("-Inf"))
+    /// );
+    /// ```
+    pub fn apply(self, value: f64) -> Option<FloatValue> {
+        if value.is_finite() {
+            return Some(FloatValue::Number(value));
+        }
+
+        match self {
+            Self::Null => Some(FloatValue::Null),
+            Self::Drop => None,
+            Self::Stringify => Some(FloatValue::String(if value.is_nan() {
+                "NaN"
+            } else if value > 0.0 {
+                "+Inf"
+            } else {
+                "-Inf"
+            })),
+        }
+    }
+}
+
+/// A floating point value after a [`NonFinite`] policy has been applied.
+///
+/// With the `serde` feature this serializes as a number, none, or a string,
+/// which is how it is written to JSON and msgpack.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FloatValue {
+    /// A finite value.
+    Number(f64),
+    /// A value which is not finite, exported as null.
+    Null,
+    /// A value which is not finite, exported as `NaN`, `+Inf`, or `-Inf`.
+    String(&'static str),
+}
+
+impl FloatValue {
+    /// The value of a Prometheus sample, or `None` if the sample should be
+    /// left out.
+    pub fn to_prometheus(&self) -> Option<String> {
+        match self {
+            Self::Number(value) => Some(value.to_string()),
+            Self::Null => None,
+            Self::String(value) => Some(value.to_string()),
+        }
+    }
+
+    /// The value of a parquet `Float64` cell, where `None` is null.
+    pub fn to_parquet(&self) -> Option<f64> {
+        match *self {
+            Self::Number(value) => Some(value),
+            Self::Null => None,
+            Self::String("NaN") => Some(f64::NAN),
+            Self::String("-Inf") => Some(f64::NEG_INFINITY),
+            Self::String(_) => Some(f64::INFINITY),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FloatValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Number(value) => serializer.serialize_f64(*value),
+            Self::Null => serializer.serialize_none(),
+            Self::String(value) => serializer.serialize_str(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [f64; 4] = [0.5, f64::NAN, f64::INFINITY, f64::NEG_INFINITY];
+
+    fn apply(policy: NonFinite) -> Vec<Option<FloatValue>> {
+        VALUES.iter().map(|value| policy.apply(*value)).collect()
+    }
+
+    #[test]
+    fn prometheus_and_parquet() {
+        let prometheus = |policy| -> Vec<_> {
+            apply(policy)
+                .into_iter()
+                .map(|v| v.and_then(|v| v.to_prometheus()))
+                .collect()
+        };
+        assert_eq!(
+            prometheus(NonFinite::Stringify),
+            [
+                Some("0.5".to_string()),
+                Some("NaN".to_string()),
+                Some("+Inf".to_string()),
+                Some("-Inf".to_string())
+            ]
+        );
+        assert_eq!(
+            prometheus(NonFinite::Null),
+            [Some("0.5".to_string()), None, None, None]
+        );
+
+        let parquet: Vec<_> = apply(NonFinite::Stringify)
+            .into_iter()
+            .map(|v| v.and_then(|v| v.to_parquet()))
+            .collect();
+        assert_eq!(parquet[0], Some(0.5));
+        assert!(parquet[1].unwrap().is_nan());
+        assert_eq!(parquet[2..], [Some(f64::INFINITY), Some(f64::NEG_INFINITY)]);
+        assert!(apply(NonFinite::Drop)[1..].iter().all(Option::is_none));
+    }
+
+    #[cfg(all(feature = "serde", feature = "json"))]
+    #[test]
+    fn json() {
+        let json = |policy| serde_json::to_string(&apply(policy)).unwrap();
+        assert_eq!(json(NonFinite::Null), "[0.5,null,null,null]");
+        assert_eq!(json(NonFinite::Stringify), r#"[0.5,"NaN","+Inf","-Inf"]"#);
+    }
+
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    #[test]
+    fn msgpack() {
+        let values: Vec<_> = apply(NonFinite::Stringify).into_iter().flatten().collect();
+        let encoded = rmp_serde::to_vec(&values).unwrap();
+
+        let decoded: (f64, String, String, String) = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(decoded.0, 0.5);
+        assert_eq!(
+            (decoded.1.as_str(), decoded.2.as_str(), decoded.3.as_str()),
+            ("NaN", "+Inf", "-Inf")
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+use crate::snapshot::{Gauge, MetricType, Snapshot, SEQUENCE};
+
+/// The name of the gauge added to snapshots inserted by [`FillPolicy::Marker`].
+pub const GAP_MARKER: &str = "gap";
+
+/// The factor of the expected interval beyond which the time between two
+/// snapshots is considered a gap.
+const DEFAULT_THRESHOLD: f64 = 1.5;
+
+/// A run of missing snapshots in a series.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gap {
+    /// The time of the last snapshot before the gap.
+    pub start: SystemTime,
+    /// The time of the first snapshot after the gap.
+    pub end: SystemTime,
+    /// The number of snapshots which are missing.
+    pub missing: usize,
+}
+
+/// Find the gaps in a time-ordered series of snapshots which were taken at the
+/// provided interval. Two consecutive snapshots are separated by a gap if they
+/// are more than 1.5 intervals apart.
+pub fn detect_gaps(snapshots: &[Snapshot], interval: Duration) -> Vec<Gap> {
+    snapshots
+        .windows(2)
+        .filter_map(|w| gap_between(&w[0], &w[1], interval, DEFAULT_THRESHOLD))
+        .collect()
+}
+
+fn gap_between(
+    previous: &Snapshot,
+    next: &Snapshot,
+    interval: Duration,
+    threshold: f64,
+) -> Option<Gap> {
+    let elapsed = next.systemtime.duration_since(previous.systemtime).ok()?;
+    if interval.is_zero() || elapsed.as_secs_f64() <= interval.as_secs_f64() * threshold {
+        return None;
+    }
+
+    let intervals = (elapsed.as_secs_f64() / interval.as_secs_f64()).round() as usize;
+
+    Some(Gap {
+        start: previous.systemtime,
+        end: next.systemtime,
+        missing: intervals.saturating_sub(1).max(1),
+    })
+}
+
+/// How a snapshot's sequence number relates to the snapshots seen before it,
+/// as reported by a [`SequenceTracker`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceStatus {
+    /// The snapshot is the first one seen, or directly follows the previous
+    /// one.
+    Next,
+    /// The provided number of snapshots are missing before this one.
+    Dropped(u64),
+    /// The snapshot was seen before, or arrived after a later snapshot.
+    Duplicate,
+    /// The sequence started over, such as when the process which takes the
+    /// snapshots restarted.
+    Restart,
+    /// The snapshot has no sequence number.
+    Unknown,
+}
+
+/// Checks the sequence numbers of a stream of snapshots, such as those
+/// received from another process, to find snapshots which were dropped or
+/// duplicated in transit.
+///
+/// Unlike [`detect_gaps`], this does not depend on the times of the
+/// snapshots, so it is not fooled by jitter in when they were taken.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceTracker {
+    last: Option<u64>,
+    dropped: u64,
+    duplicated: u64,
+}
+
+impl SequenceTracker {
+    /// Create a tracker which has not seen any snapshots.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check the next snapshot in the stream.
+    pub fn observe(&mut self, snapshot: &Snapshot) -> SequenceStatus {
+        let Some(sequence) = snapshot.sequence() else {
+            return SequenceStatus::Unknown;
+        };
+
+        let Some(last) = self.last else {
+            self.last = Some(sequence);
+            return SequenceStatus::Next;
+        };
+
+        if sequence <= last {
+            if sequence == 0 {
+                self.last = Some(0);
+                return SequenceStatus::Restart;
+            }
+            self.duplicated += 1;
+            return SequenceStatus::Duplicate;
+        }
+
+        self.last = Some(sequence);
+        match sequence - last - 1 {
+            0 => SequenceStatus::Next,
+            dropped => {
+                self.dropped += dropped;
+                SequenceStatus::Dropped(dropped)
+            }
+        }
+    }
+
+    /// The total number of snapshots found to be missing.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The total number of snapshots found to be duplicates.
+    pub fn duplicated(&self) -> u64 {
+        self.duplicated
+    }
+}
+
+/// How the missing snapshots within a gap are filled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FillPolicy {
+    /// Insert snapshots with no metrics, which appear as nulls in parquet.
+    #[default]
+    Null,
+    /// Insert snapshots with every metric from the preceding snapshot set to
+    /// zero.
+    Zero,
+    /// Insert copies of the preceding snapshot.
+    CarryForward,
+    /// Insert snapshots which contain only a [`GAP_MARKER`] gauge with a value
+    /// of one.
+    Marker,
+}
+
+/// An iterator adapter which fills the gaps in a time-ordered series of
+/// snapshots.
+///
+/// Inserted snapshots are spaced at the expected interval starting from the
+/// snapshot before the gap, and have their metadata copied from it apart from
+/// the [`SEQUENCE`] number, since they were never taken.
+pub struct GapFill<I: Iterator<Item = Snapshot>> {
+    inner: I,
+    interval: Duration,
+    threshold: f64,
+    policy: FillPolicy,
+    previous: Option<Snapshot>,
+    pending: VecDeque<Snapshot>,
+}
+
+impl<I: Iterator<Item = Snapshot>> GapFill<I> {
+    /// Fill the gaps in the provided snapshots, which are expected to be taken
+    /// at the provided interval.
+    pub fn new(snapshots: I, interval: Duration, policy: FillPolicy) -> Self {
+        Self {
+            inner: snapshots,
+            interval,
+            threshold: DEFAULT_THRESHOLD,
+            policy,
+            previous: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Sets the factor of the interval beyond which the time between two
+    /// snapshots is considered a gap. The default is 1.5.
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    fn fill(&self, previous: &Snapshot, systemtime: SystemTime) -> Snapshot {
+        let mut snapshot = match self.policy {
+            FillPolicy::CarryForward => previous.clone(),
+            FillPolicy::Zero => {
+                let mut snapshot = previous.clone();
+                snapshot.counters.iter_mut().for_each(|m| m.value = 0);
+                snapshot.gauges.iter_mut().for_each(|m| m.value = 0);
+                snapshot
+                    .histograms
+                    .iter_mut()
+                    .for_each(|m| m.value = histogram::Histogram::with_config(&m.value.config()));
+                snapshot.stats.iter_mut().for_each(|m| {
+                    m.min = None;
+                    m.max = None;
+                    m.sum = 0;
+                    m.count = 0;
+                });
+                snapshot.sketches.iter_mut().for_each(|m| m.value.clear());
+                snapshot.cardinalities.iter_mut().for_each(|m| {
+                    m.value.clear();
+                    m.estimate = 0;
+                });
+                snapshot.top_k.iter_mut().for_each(|m| m.entries.clear());
+                snapshot
+            }
+            FillPolicy::Null | FillPolicy::Marker => {
+                let mut snapshot = Snapshot::new();
+                snapshot.metadata = previous.metadata.clone();
+                snapshot
+            }
+        };
+
+        // events only happen once, so they are never carried into a gap
+        snapshot.events.clear();
+
+        if self.policy == FillPolicy::Marker {
+            snapshot.gauges.push(Gauge {
+                name: GAP_MARKER.to_string(),
+                metric_type: MetricType::Gauge,
+                value: 1,
+                metadata: Default::default(),
+            });
+        }
+
+        snapshot.metadata.remove(SEQUENCE);
+        snapshot.systemtime = systemtime;
+        snapshot
+    }
+}
+
+impl<I: Iterator<Item = Snapshot>> Iterator for GapFill<I> {
+    type Item = Snapshot;
+
+    fn next(&mut self) -> Option<Snapshot> {
+        if let Some(snapshot) = self.pending.pop_front() {
+            return Some(snapshot);
+        }
+
+        let next = self.inner.next()?;
+
+        if let Some(previous) = self.previous.take() {
+            if let Some(gap) = gap_between(&previous, &next, self.interval, self.threshold) {
+                for i in 1..=gap.missing {
+                    let systemtime = previous.systemtime + self.interval * i as u32;
+                    let filled = self.fill(&previous, systemtime);
+                    self.pending.push_back(filled);
+                }
+            }
+        }
+
+        self.previous = Some(next.clone());
+        self.pending.push_back(next);
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::Counter;
+
+    fn series(seconds: &[u64]) -> Vec<Snapshot> {
+        seconds
+            .iter()
+            .map(|s| {
+                let mut snapshot = Snapshot::new();
+                snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_secs(*s);
+                snapshot.counters.push(Counter {
+                    name: "counter".to_string(),
+                    metric_type: MetricType::Counter,
+                    value: *s,
+                    metadata: HashMap::new(),
+                });
+                snapshot
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detect() {
+        let gaps = detect_gaps(&series(&[0, 1, 2, 5, 6, 8]), Duration::from_secs(1));
+        assert_eq!(gaps.len(), 2);
+        assert_eq!(gaps[0].missing, 2);
+        assert_eq!(gaps[1].missing, 1);
+    }
+
+    #[test]
+    fn fill() {
+        let interval = Duration::from_secs(1);
+
+        let filled: Vec<Snapshot> =
+            GapFill::new(series(&[0, 3]).into_iter(), interval, FillPolicy::Null).collect();
+        assert_eq!(filled.len(), 4);
+        assert!(filled[1].counters.is_empty());
+        assert_eq!(
+            filled[2].systemtime,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(2)
+        );
+
+        let filled: Vec<Snapshot> =
+            GapFill::new(series(&[0, 3]).into_iter(), interval, FillPolicy::Zero).collect();
+        assert_eq!(filled[1].counters[0].value, 0);
+
+        let mut snapshots = series(&[1, 3]);
+        snapshots[0].events.push(crate::Event::new("deploy"));
+        let filled: Vec<Snapshot> =
+            GapFill::new(snapshots.into_iter(), interval, FillPolicy::CarryForward).collect();
+        assert_eq!(filled.len(), 3);
+        assert_eq!(filled[1].counters[0].value, 1);
+        assert!(filled[1].events.is_empty());
+
+        let filled: Vec<Snapshot> =
+            GapFill::new(series(&[0, 2]).into_iter(), interval, FillPolicy::Marker).collect();
+        assert_eq!(filled[1].gauges[0].name, GAP_MARKER);
+        assert!(filled[0].gauges.is_empty());
+    }
+
+    #[test]
+    fn sequence() {
+        let snapshots: Vec<Snapshot> = [Some(4), Some(5), Some(8), Some(8), Some(6), None, Some(0)]
+            .into_iter()
+            .map(|sequence| {
+                let mut snapshot = Snapshot::new();
+                if let Some(sequence) = sequence {
+                    snapshot
+                        .metadata
+                        .insert(SEQUENCE.to_string(), sequence.to_string());
+                }
+                snapshot
+            })
+            .collect();
+
+        let mut tracker = SequenceTracker::new();
+        let status: Vec<SequenceStatus> = snapshots.iter().map(|s| tracker.observe(s)).collect();
+        assert_eq!(
+            status,
+            [
+                SequenceStatus::Next,
+                SequenceStatus::Next,
+                SequenceStatus::Dropped(2),
+                SequenceStatus::Duplicate,
+                SequenceStatus::Duplicate,
+                SequenceStatus::Unknown,
+                SequenceStatus::Restart,
+            ]
+        );
+        assert_eq!(tracker.dropped(), 2);
+        assert_eq!(tracker.duplicated(), 2);
+
+        // filled snapshots do not repeat the sequence number
+        let filled: Vec<Snapshot> = GapFill::new(
+            [snapshots[0].clone(), {
+                let mut next = snapshots[2].clone();
+                next.systemtime = snapshots[0].systemtime + Duration::from_secs(2);
+                next
+            }]
+            .into_iter(),
+            Duration::from_secs(1),
+            FillPolicy::CarryForward,
+        )
+        .collect();
+        assert_eq!(filled.len(), 3);
+        assert_eq!(filled[1].sequence(), None);
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::batch::SnapshotBatch;
+use crate::snapshot::Snapshot;
+
+/// An in-memory ring buffer of the most recent snapshots, so that the last
+/// few minutes can be dumped at full resolution when an incident happens,
+/// even if the normal export path only ships aggregates.
+///
+/// The history is bounded by a number of snapshots and, optionally, by how
+/// far the oldest snapshot may be behind the newest one. The oldest
+/// snapshots are dropped first. The history can be shared between the thread
+/// taking snapshots and, for example, an HTTP handler which dumps it.
+///
+/// ```
+/// # use metriken_exposition::{Snapshot, SnapshotHistory};
+/// # use std::time::Duration;
+/// let history = SnapshotHistory::new(300).retention(Duration::from_secs(300));
+///
+/// history.push(Snapshot::builder().counter("requests", 1, &[]).build().unwrap());
+/// history.push(Snapshot::builder().counter("requests", 2, &[]).build().unwrap());
+///
+/// let values: Vec<u64> = history
+///     .snapshots()
+///     .iter()
+///     .map(|s| s.counters()[0].value)
+///     .collect();
+/// assert_eq!(values, [1, 2]);
+/// ```
+pub struct SnapshotHistory {
+    capacity: usize,
+    retention: Option<Duration>,
+    snapshots: Mutex<VecDeque<Snapshot>>,
+}
+
+impl SnapshotHistory {
+    /// Keep at most `capacity` snapshots.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            retention: None,
+            snapshots: Mutex::new(VecDeque::with_capacity(capacity.min(1024))),
+        }
+    }
+
+    /// Also drop snapshots taken more than `retention` before the newest
+    /// snapshot.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Snapshot>> {
+        self.snapshots.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add a snapshot, dropping the oldest snapshots which no longer fit.
+    pub fn push(&self, snapshot: Snapshot) {
+        let mut snapshots = self.lock();
+        if self.capacity == 0 {
+            return;
+        }
+
+        let newest = snapshot.systemtime;
+        if snapshots.len() == self.capacity {
+            snapshots.pop_front();
+        }
+        snapshots.push_back(snapshot);
+
+        if let Some(oldest) = self.retention.and_then(|r| newest.checked_sub(r)) {
+            while snapshots.front().is_some_and(|s| s.systemtime < oldest) {
+                snapshots.pop_front();
+            }
+        }
+    }
+
+    /// The number of snapshots held.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Whether no snapshots are held.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Drop every snapshot.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// A copy of every snapshot held, from oldest to newest.
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// A copy of the snapshots taken at or after `start`, from oldest to
+    /// newest.
+    pub fn since(&self, start: SystemTime) -> Vec<Snapshot> {
+        self.lock()
+            .iter()
+            .filter(|s| s.systemtime >= start)
+            .cloned()
+            .collect()
+    }
+
+    /// Every snapshot held, as a compact [`SnapshotBatch`].
+    pub fn to_batch(&self) -> SnapshotBatch {
+        let mut batch = SnapshotBatch::new();
+        for snapshot in self.snapshots() {
+            batch.push(snapshot);
+        }
+        batch
+    }
+
+    /// Write every snapshot held as a msgpack recording, which can be read
+    /// with a [`RecordingReader`]. The writer is returned once the recording
+    /// is complete.
+    ///
+    /// [`RecordingReader`]: crate::RecordingReader
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    pub fn write_msgpack<W: std::io::Write>(&self, writer: W) -> Result<W, crate::RecordingError> {
+        write_msgpack(&self.snapshots(), writer)
+    }
+
+    /// Write the history to a msgpack recording in `dir` when the process
+    /// panics, so that the metrics leading up to a crash can be examined
+    /// after it. The recording is named `metrics-<pid>-<unix seconds>.msgpack`
+    /// and is written before any panic hook which was already installed
+    /// runs, which includes the default hook that prints the panic message.
+    ///
+    /// This replaces the panic hook of the process, so it should be called
+    /// once, after any other hooks are installed. If the history is in use
+    /// by the panicking thread, nothing is written.
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    pub fn flush_on_panic(self: &std::sync::Arc<Self>, dir: impl Into<std::path::PathBuf>) {
+        let history = std::sync::Arc::downgrade(self);
+        let dir = dir.into();
+        let previous = std::panic::take_hook();
+
+        std::panic::set_hook(Box::new(move |info| {
+            if let Some(history) = history.upgrade() {
+                let _ = history.flush(&dir);
+            }
+            previous(info);
+        }));
+    }
+
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    fn flush(&self, dir: &std::path::Path) -> Result<(), crate::RecordingError> {
+        // the panicking thread may hold the lock, so waiting for it could
+        // deadlock
+        let snapshots: Vec<Snapshot> = match self.snapshots.try_lock() {
+            Ok(snapshots) => snapshots.iter().cloned().collect(),
+            Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner().iter().cloned().collect(),
+            Err(std::sync::TryLockError::WouldBlock) => return Ok(()),
+        };
+
+        let secs = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = dir.join(format!("metrics-{}-{secs}.msgpack", std::process::id()));
+
+        std::fs::create_dir_all(dir)?;
+        let file = write_msgpack(&snapshots, std::fs::File::create(&path)?)?;
+        file.sync_all()?;
+
+        #[cfg(feature = "log")]
+        log::error!(
+            "wrote {} snapshots to {} on panic",
+            snapshots.len(),
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+fn write_msgpack<W: std::io::Write>(
+    snapshots: &[Snapshot],
+    writer: W,
+) -> Result<W, crate::RecordingError> {
+    let mut writer = crate::MsgpackWriter::new(writer);
+    for snapshot in snapshots {
+        writer.push(snapshot)?;
+    }
+    writer.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(secs: u64) -> Snapshot {
+        Snapshot::builder()
+            .systemtime(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .counter("requests", secs, &[])
+            .build()
+            .unwrap()
+    }
+
+    fn values(snapshots: &[Snapshot]) -> Vec<u64> {
+        snapshots.iter().map(|s| s.counters()[0].value).collect()
+    }
+
+    #[test]
+    fn capacity() {
+        let history = SnapshotHistory::new(3);
+        for secs in 0..5 {
+            history.push(snapshot(secs));
+        }
+        assert_eq!(values(&history.snapshots()), [2, 3, 4]);
+        assert_eq!(
+            values(&history.since(SystemTime::UNIX_EPOCH + Duration::from_secs(3))),
+            [3, 4]
+        );
+        assert_eq!(history.to_batch().len(), 3);
+
+        history.clear();
+        assert!(history.is_empty());
+
+        let empty = SnapshotHistory::new(0);
+        empty.push(snapshot(0));
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn retention() {
+        let history = SnapshotHistory::new(100).retention(Duration::from_secs(10));
+        for secs in [0, 5, 10, 15, 20] {
+            history.push(snapshot(secs));
+        }
+        assert_eq!(values(&history.snapshots()), [10, 15, 20]);
+    }
+
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    #[test]
+    fn msgpack() {
+        let history = SnapshotHistory::new(10);
+        for secs in 1..4 {
+            history.push(snapshot(secs));
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.msgpack");
+        history
+            .write_msgpack(std::fs::File::create(&path).unwrap())
+            .unwrap();
+
+        let recorded = crate::RecordingReader::open(&path)
+            .unwrap()
+            .range(SystemTime::UNIX_EPOCH, SystemTime::now())
+            .unwrap();
+        assert_eq!(values(&recorded), [1, 2, 3]);
+    }
+}
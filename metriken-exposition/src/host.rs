@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use metriken::{Counter, DynBoxedMetric, Gauge, MetricBuilder, Unit};
+
+use crate::snapshotter::Collector;
+
+/// The metrics kept by a collector for each device, keyed by the name of the
+/// device.
+pub(crate) struct Devices<M> {
+    devices: Mutex<HashMap<String, M>>,
+}
+
+impl<M> Default for Devices<M> {
+    fn default() -> Self {
+        Self {
+            devices: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<M> Devices<M> {
+    /// Replace the set of devices, updating the metrics of each device with
+    /// `update`. Metrics are registered with `register` for new devices and
+    /// dropped, which unregisters them, for devices which are not present.
+    pub(crate) fn update<T>(
+        &self,
+        readings: impl IntoIterator<Item = (String, T)>,
+        register: impl Fn(&str, &T) -> M,
+        update: impl Fn(&M, &T),
+    ) {
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let mut current = HashMap::with_capacity(devices.len());
+
+        for (name, reading) in readings {
+            let metrics = match devices.remove(&name) {
+                Some(metrics) => metrics,
+                None => register(&name, &reading),
+            };
+            update(&metrics, &reading);
+            current.insert(name, metrics);
+        }
+
+        *devices = current;
+    }
+}
+
+/// The statistics of a network interface from `/proc/net/dev`.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct InterfaceStats {
+    receive_bytes: u64,
+    receive_packets: u64,
+    receive_errors: u64,
+    receive_drops: u64,
+    transmit_bytes: u64,
+    transmit_packets: u64,
+    transmit_errors: u64,
+    transmit_drops: u64,
+}
+
+/// Parse the contents of `/proc/net/dev`.
+fn parse_net_dev(contents: &str) -> Vec<(String, InterfaceStats)> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (name, values) = line.split_once(':')?;
+            let values: Vec<u64> = values
+                .split_whitespace()
+                .map(|v| v.parse().ok())
+                .collect::<Option<_>>()?;
+            if values.len() < 12 {
+                return None;
+            }
+
+            Some((
+                name.trim().to_string(),
+                InterfaceStats {
+                    receive_bytes: values[0],
+                    receive_packets: values[1],
+                    receive_errors: values[2],
+                    receive_drops: values[3],
+                    transmit_bytes: values[8],
+                    transmit_packets: values[9],
+                    transmit_errors: values[10],
+                    transmit_drops: values[11],
+                },
+            ))
+        })
+        .collect()
+}
+
+struct InterfaceMetrics {
+    receive_bytes: DynBoxedMetric<Counter>,
+    receive_packets: DynBoxedMetric<Counter>,
+    receive_errors: DynBoxedMetric<Counter>,
+    receive_drops: DynBoxedMetric<Counter>,
+    transmit_bytes: DynBoxedMetric<Counter>,
+    transmit_packets: DynBoxedMetric<Counter>,
+    transmit_errors: DynBoxedMetric<Counter>,
+    transmit_drops: DynBoxedMetric<Counter>,
+}
+
+/// Collects the traffic of each network interface of the host.
+///
+/// Each interface has the following counters, with the name of the interface
+/// in the `interface` metadata key:
+///
+/// - `network/receive/bytes` and `network/transmit/bytes`
+/// - `network/receive/packets` and `network/transmit/packets`
+/// - `network/receive/errors` and `network/transmit/errors`
+/// - `network/receive/drops` and `network/transmit/drops`
+///
+/// The readings come from `/proc/net/dev`, so only Linux is supported. On
+/// other systems no metrics are registered.
+///
+/// ```
+/// # use metriken_exposition::{NetworkCollector, SnapshotterBuilder};
+/// let snapshotter = SnapshotterBuilder::new()
+///     .collector(NetworkCollector::new())
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct NetworkCollector {
+    interfaces: Devices<InterfaceMetrics>,
+}
+
+impl NetworkCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Collector for NetworkCollector {
+    fn refresh(&self) {
+        let contents = std::fs::read_to_string("/proc/net/dev").unwrap_or_default();
+
+        self.interfaces.update(
+            parse_net_dev(&contents),
+            |name, _| {
+                let counter = |metric: &'static str, unit: Option<Unit<'_>>| {
+                    let mut builder = MetricBuilder::new(metric).metadata("interface", name);
+                    if let Some(unit) = unit {
+                        builder = builder.unit(unit);
+                    }
+                    builder.build(Counter::new())
+                };
+
+                InterfaceMetrics {
+                    receive_bytes: counter("network/receive/bytes", Some(Unit::Bytes)),
+                    receive_packets: counter("network/receive/packets", None),
+                    receive_errors: counter("network/receive/errors", None),
+                    receive_drops: counter("network/receive/drops", None),
+                    transmit_bytes: counter("network/transmit/bytes", Some(Unit::Bytes)),
+                    transmit_packets: counter("network/transmit/packets", None),
+                    transmit_errors: counter("network/transmit/errors", None),
+                    transmit_drops: counter("network/transmit/drops", None),
+                }
+            },
+            |metrics, stats| {
+                metrics.receive_bytes.set(stats.receive_bytes);
+                metrics.receive_packets.set(stats.receive_packets);
+                metrics.receive_errors.set(stats.receive_errors);
+                metrics.receive_drops.set(stats.receive_drops);
+                metrics.transmit_bytes.set(stats.transmit_bytes);
+                metrics.transmit_packets.set(stats.transmit_packets);
+                metrics.transmit_errors.set(stats.transmit_errors);
+                metrics.transmit_drops.set(stats.transmit_drops);
+            },
+        );
+    }
+}
+
+/// A mounted filesystem from `/proc/self/mounts`.
+#[derive(Debug, PartialEq, Eq)]
+struct Mount {
+    device: String,
+    mountpoint: String,
+    fstype: String,
+}
+
+/// Parse the contents of `/proc/self/mounts`, keeping only the filesystems
+/// which are backed by a device, and only the first mount of each mount
+/// point.
+fn parse_mounts(contents: &str) -> Vec<Mount> {
+    let mut mounts: Vec<Mount> = Vec::new();
+
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(device), Some(mountpoint), Some(fstype)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+
+        if !device.starts_with('/') {
+            continue;
+        }
+
+        let mountpoint = unescape(mountpoint);
+        if mounts.iter().any(|m| m.mountpoint == mountpoint) {
+            continue;
+        }
+
+        mounts.push(Mount {
+            device: unescape(device),
+            mountpoint,
+            fstype: fstype.to_string(),
+        });
+    }
+
+    mounts
+}
+
+/// Decode the octal escapes, such as `\040` for a space, used for special
+/// characters in `/proc/self/mounts`.
+fn unescape(field: &str) -> String {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..3)
+            .filter(|_| byte == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+
+        match escaped {
+            Some(escaped) => {
+                bytes.push(escaped);
+                rest = &tail[3..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// The usage of a filesystem, in bytes.
+#[derive(Debug, Default)]
+struct Usage {
+    size: u64,
+    used: u64,
+    available: u64,
+}
+
+#[cfg(unix)]
+fn usage(mountpoint: &str) -> Option<Usage> {
+    let path = std::ffi::CString::new(mountpoint).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    // SAFETY: the path is a valid C string and stat is a valid statvfs
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    #[allow(clippy::useless_conversion)]
+    let (blocks, free, available, fragment) = (
+        u64::from(stat.f_blocks),
+        u64::from(stat.f_bfree),
+        u64::from(stat.f_bavail),
+        u64::from(stat.f_frsize),
+    );
+
+    Some(Usage {
+        size: blocks * fragment,
+        used: blocks.saturating_sub(free) * fragment,
+        available: available * fragment,
+    })
+}
+
+#[cfg(not(unix))]
+fn usage(_mountpoint: &str) -> Option<Usage> {
+    None
+}
+
+struct FilesystemMetrics {
+    size: DynBoxedMetric<Gauge>,
+    used: DynBoxedMetric<Gauge>,
+    available: DynBoxedMetric<Gauge>,
+}
+
+/// Collects the disk usage of each mounted filesystem of the host.
+///
+/// Each filesystem has the `filesystem/size`, `filesystem/used`, and
+/// `filesystem/available` gauges, in bytes, with its `mountpoint`, `device`,
+/// and `fstype` in their metadata. Only filesystems which are backed by a
+/// device are included, which leaves out virtual filesystems such as `proc`
+/// and `tmpfs`.
+///
+/// The mounts are read from `/proc/self/mounts`, so only Linux is supported.
+/// On other systems no metrics are registered.
+#[derive(Default)]
+pub struct FilesystemCollector {
+    filesystems: Devices<FilesystemMetrics>,
+}
+
+impl FilesystemCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Collector for FilesystemCollector {
+    fn refresh(&self) {
+        let contents = std::fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+
+        let readings = parse_mounts(&contents).into_iter().filter_map(|mount| {
+            let usage = usage(&mount.mountpoint)?;
+            Some((mount.mountpoint.clone(), (mount, usage)))
+        });
+
+        self.filesystems.update(
+            readings,
+            |_, (mount, _)| {
+                let gauge = |metric: &'static str| {
+                    MetricBuilder::new(metric)
+                        .metadata("mountpoint", &mount.mountpoint)
+                        .metadata("device", &mount.device)
+                        .metadata("fstype", &mount.fstype)
+                        .unit(Unit::Bytes)
+                        .build(Gauge::new())
+                };
+
+                FilesystemMetrics {
+                    size: gauge("filesystem/size"),
+                    used: gauge("filesystem/used"),
+                    available: gauge("filesystem/available"),
+                }
+            },
+            |metrics, (_, usage)| {
+                metrics.size.set(usage.size as i64);
+                metrics.used.set(usage.used as i64);
+                metrics.available.set(usage.available as i64);
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn net_dev() {
+        let contents = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:  123456     789    0    0    0     0          0         0   123456     789    0    0    0     0       0          0
+  eth0: 9876543   54321    1    2    0     0          0        10  1234567   12345    3    4    0     0       0          0
+";
+
+        let interfaces = parse_net_dev(contents);
+        assert_eq!(interfaces.len(), 2);
+        assert_eq!(interfaces[0].0, "lo");
+        assert_eq!(
+            interfaces[1],
+            (
+                "eth0".to_string(),
+                InterfaceStats {
+                    receive_bytes: 9876543,
+                    receive_packets: 54321,
+                    receive_errors: 1,
+                    receive_drops: 2,
+                    transmit_bytes: 1234567,
+                    transmit_packets: 12345,
+                    transmit_errors: 3,
+                    transmit_drops: 4,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn mounts() {
+        let contents = "\
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+tmpfs /run tmpfs rw,nosuid,nodev 0 0
+/dev/sdb1 /mnt/my\\040disk xfs rw 0 0
+/dev/sdc1 / ext4 rw 0 0
+";
+
+        assert_eq!(
+            parse_mounts(contents),
+            [
+                Mount {
+                    device: "/dev/nvme0n1p2".to_string(),
+                    mountpoint: "/".to_string(),
+                    fstype: "ext4".to_string(),
+                },
+                Mount {
+                    device: "/dev/sdb1".to_string(),
+                    mountpoint: "/mnt/my disk".to_string(),
+                    fstype: "xfs".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn loopback() {
+        let collector = NetworkCollector::new();
+        collector.refresh();
+
+        let registered = |interface: Option<&str>| {
+            metriken::metrics().iter().any(|m| {
+                m.name() == "network/receive/bytes"
+                    && interface.is_none_or(|i| m.metadata().get("interface") == Some(i))
+            })
+        };
+        if std::path::Path::new("/proc/net/dev").exists() {
+            assert!(registered(Some("lo")));
+        }
+
+        // the metrics are unregistered along with the collector
+        drop(collector);
+        assert!(!registered(None));
+    }
+}
@@ -0,0 +1,230 @@
+/// The smallest supported precision of a [`HyperLogLog`].
+const MIN_PRECISION: u8 = 4;
+
+/// The largest supported precision of a [`HyperLogLog`].
+const MAX_PRECISION: u8 = 18;
+
+/// An error working with a [`HyperLogLog`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HyperLogLogError {
+    /// The precision is not between 4 and 18.
+    InvalidPrecision(u8),
+    /// The number of registers is not a power of two with a supported
+    /// precision.
+    InvalidRegisters(usize),
+    /// The sketches being merged have different precisions.
+    PrecisionMismatch,
+}
+
+impl std::fmt::Display for HyperLogLogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidPrecision(precision) => write!(
+                f,
+                "precision must be between {MIN_PRECISION} and {MAX_PRECISION}, not {precision}"
+            ),
+            Self::InvalidRegisters(len) => {
+                write!(f, "{len} registers is not a supported number of registers")
+            }
+            Self::PrecisionMismatch => {
+                write!(f, "sketches with different precisions can't be merged")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HyperLogLogError {}
+
+/// A HyperLogLog sketch, which estimates the number of distinct values it
+/// has seen, as reported by a `metriken::Cardinality`.
+///
+/// Each value sets the register selected by the top `precision` bits of its
+/// hash to the largest position of the first set bit in the rest of the hash
+/// seen so far. Sketches with the same precision can be merged by taking the
+/// maximum of each register, which gives exactly the sketch of every value
+/// seen by either, so the distinct values across several processes or
+/// several intervals can be counted without double counting.
+///
+/// ```
+/// # use metriken::Cardinality;
+/// # use metriken_exposition::HyperLogLog;
+/// // two processes, which each saw 1000 clients, 500 of them the same
+/// let (a, b) = (Cardinality::new(), Cardinality::new());
+/// for client in 0..1000 {
+///     a.insert(&client);
+///     b.insert(&(client + 500));
+/// }
+///
+/// let mut clients = HyperLogLog::from_registers(a.drain()).unwrap();
+/// clients
+///     .merge(&HyperLogLog::from_registers(b.drain()).unwrap())
+///     .unwrap();
+/// assert!(clients.estimate().abs_diff(1500) < 75);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// An empty sketch with `2^precision` registers. The standard error of
+    /// the estimate is about `1.04 / sqrt(2^precision)`, so `12` gives
+    /// estimates within about 1.6%.
+    pub fn new(precision: u8) -> Result<Self, HyperLogLogError> {
+        if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+            return Err(HyperLogLogError::InvalidPrecision(precision));
+        }
+        Ok(Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        })
+    }
+
+    /// A sketch holding the provided registers, such as those read from a
+    /// `metriken::Cardinality`. The precision follows from the number of
+    /// registers, which must be a power of two.
+    pub fn from_registers(registers: Vec<u8>) -> Result<Self, HyperLogLogError> {
+        let len = registers.len();
+        let precision = len.trailing_zeros() as u8;
+        if !len.is_power_of_two() || !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+            return Err(HyperLogLogError::InvalidRegisters(len));
+        }
+        Ok(Self {
+            precision,
+            registers,
+        })
+    }
+
+    /// The number of bits of each hash which select a register.
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    /// The registers of the sketch.
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Returns `true` if no values have been seen.
+    pub fn is_empty(&self) -> bool {
+        self.registers.iter().all(|r| *r == 0)
+    }
+
+    /// Record a value by its 64-bit hash, which should be uniformly
+    /// distributed.
+    pub fn insert_hash(&mut self, hash: u64) {
+        let index = (hash >> (64 - self.precision)) as usize;
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        let register = &mut self.registers[index];
+        *register = (*register).max(rank);
+    }
+
+    /// Estimate the number of distinct values seen, using linear counting
+    /// while most registers are empty.
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|r| 2.0_f64.powi(-(*r as i32)))
+            .sum();
+        let raw = alpha * m * m / sum;
+
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+
+    /// Add the values seen by another sketch to this one.
+    pub fn merge(&mut self, other: &Self) -> Result<(), HyperLogLogError> {
+        if self.precision != other.precision {
+            return Err(HyperLogLogError::PrecisionMismatch);
+        }
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+        Ok(())
+    }
+
+    /// Forget every value seen, keeping the precision.
+    pub fn clear(&mut self) {
+        self.registers.iter_mut().for_each(|r| *r = 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(value: u64) -> u64 {
+        // the finalizer from splitmix64
+        let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    #[test]
+    fn estimate() {
+        for n in [0, 1, 10, 1000, 100_000] {
+            let mut sketch = HyperLogLog::new(12).unwrap();
+            (0..n).for_each(|v| sketch.insert_hash(hash(v)));
+            let estimate = sketch.estimate() as f64;
+            assert!(
+                (estimate - n as f64).abs() <= n as f64 * 0.05,
+                "{n}: {estimate}"
+            );
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = HyperLogLog::new(10).unwrap();
+        let mut b = HyperLogLog::new(10).unwrap();
+        (0..5000).for_each(|v| a.insert_hash(hash(v)));
+        (2500..7500).for_each(|v| b.insert_hash(hash(v)));
+
+        a.merge(&b).unwrap();
+        assert!(a.estimate().abs_diff(7500) < 7500 / 10, "{}", a.estimate());
+
+        let c = HyperLogLog::new(11).unwrap();
+        assert_eq!(a.merge(&c), Err(HyperLogLogError::PrecisionMismatch));
+
+        a.clear();
+        assert!(a.is_empty());
+        assert_eq!(a.estimate(), 0);
+    }
+
+    #[test]
+    fn registers() {
+        assert_eq!(
+            HyperLogLog::new(3),
+            Err(HyperLogLogError::InvalidPrecision(3))
+        );
+        assert_eq!(
+            HyperLogLog::from_registers(vec![0; 100]),
+            Err(HyperLogLogError::InvalidRegisters(100))
+        );
+        assert_eq!(
+            HyperLogLog::from_registers(vec![0; 4096])
+                .unwrap()
+                .precision(),
+            12
+        );
+    }
+}
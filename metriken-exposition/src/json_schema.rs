@@ -0,0 +1,320 @@
+use std::sync::OnceLock;
+
+use serde_json::{Map, Number, Value};
+
+use crate::Snapshot;
+
+/// The JSON Schema for snapshots serialized with [`Snapshot::to_json`] or
+/// [`Snapshot::to_json_with`].
+pub const JSON_SCHEMA: &str = include_str!("../schema/snapshot.schema.json");
+
+fn schema() -> &'static Value {
+    static SCHEMA: OnceLock<Value> = OnceLock::new();
+    SCHEMA.get_or_init(|| serde_json::from_str(JSON_SCHEMA).expect("invalid snapshot schema"))
+}
+
+/// Errors that can occur while validating a JSON snapshot.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum JsonValidationError {
+    /// The payload is not valid JSON.
+    Json(serde_json::Error),
+    /// The payload does not match [`JSON_SCHEMA`]. The path is a JSON pointer
+    /// to the value which failed validation.
+    Schema { path: String, reason: String },
+}
+
+impl std::fmt::Display for JsonValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "invalid json: {e}"),
+            Self::Schema { path, reason } if path.is_empty() => write!(f, "{reason}"),
+            Self::Schema { path, reason } => write!(f, "{path}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for JsonValidationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Json(e) => Some(e),
+            Self::Schema { .. } => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for JsonValidationError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl Snapshot {
+    /// Check that a payload is a snapshot in the JSON form described by
+    /// [`JSON_SCHEMA`], without deserializing it.
+    ///
+    /// This allows consumers and producers in other languages to check their
+    /// payloads against the same contract as this crate.
+    ///
+    /// ```
+    /// # use metriken_exposition::Snapshot;
+    /// let json = r#"{
+    ///     "systemtime": "2024-05-01T12:30:00Z",
+    ///     "counters": [{"name": "requests", "value": 3, "metadata": {}}],
+    ///     "gauges": [],
+    ///     "histograms": []
+    /// }"#;
+    /// assert!(Snapshot::validate_json(json.as_bytes()).is_ok());
+    /// assert!(Snapshot::validate_json(br#"{"counters": []}"#).is_err());
+    /// ```
+    pub fn validate_json(bytes: &[u8]) -> Result<(), JsonValidationError> {
+        let value: Value = serde_json::from_slice(bytes)?;
+        validate(schema(), &value, &mut String::new())
+    }
+}
+
+/// Validate a value against a schema, supporting the subset of JSON Schema
+/// used by [`JSON_SCHEMA`]. `path` is the JSON pointer to the value.
+fn validate(schema: &Value, value: &Value, path: &mut String) -> Result<(), JsonValidationError> {
+    let fail = |path: &str, reason: String| {
+        Err(JsonValidationError::Schema {
+            path: path.to_string(),
+            reason,
+        })
+    };
+
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let Some(target) = reference
+            .strip_prefix('#')
+            .and_then(|pointer| self::schema().pointer(pointer))
+        else {
+            return fail(path, format!("unresolved schema reference `{reference}`"));
+        };
+        return validate(target, value, path);
+    }
+
+    if let Some(any_of) = schema.get("anyOf").and_then(Value::as_array) {
+        if !any_of.iter().any(|s| validate(s, value, path).is_ok()) {
+            return fail(path, "does not match any of the allowed forms".to_string());
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            return fail(
+                path,
+                format!("{value} is not one of {}", Value::from(allowed.clone())),
+            );
+        }
+    }
+
+    if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+        if !has_type(value, expected) {
+            return fail(path, format!("expected {expected}"));
+        }
+    }
+
+    if let Value::Number(number) = value {
+        if let Some(minimum) = schema.get("minimum").and_then(Value::as_number) {
+            if compare(number, minimum).is_lt() {
+                return fail(path, format!("{number} is less than {minimum}"));
+            }
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(Value::as_number) {
+            if compare(number, maximum).is_gt() {
+                return fail(path, format!("{number} is greater than {maximum}"));
+            }
+        }
+    }
+
+    if let Value::Array(items) = value {
+        if let Some(item_schema) = schema.get("items") {
+            for (idx, item) in items.iter().enumerate() {
+                within(path, &idx.to_string(), |path| {
+                    validate(item_schema, item, path)
+                })?;
+            }
+        }
+    }
+
+    if let Value::Object(object) = value {
+        validate_object(schema, object, path)?;
+    }
+
+    Ok(())
+}
+
+fn validate_object(
+    schema: &Value,
+    object: &Map<String, Value>,
+    path: &mut String,
+) -> Result<(), JsonValidationError> {
+    let empty = Map::new();
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+
+    for required in schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+    {
+        if !object.contains_key(required) {
+            return Err(JsonValidationError::Schema {
+                path: path.clone(),
+                reason: format!("missing required property `{required}`"),
+            });
+        }
+    }
+
+    for (key, value) in object {
+        let property_schema = match (properties.get(key), schema.get("additionalProperties")) {
+            (Some(property_schema), _) => property_schema,
+            (None, Some(Value::Bool(false))) => {
+                return Err(JsonValidationError::Schema {
+                    path: path.clone(),
+                    reason: format!("unexpected property `{key}`"),
+                })
+            }
+            (None, Some(additional)) if additional.is_object() => additional,
+            (None, _) => continue,
+        };
+
+        within(path, key, |path| validate(property_schema, value, path))?;
+    }
+
+    Ok(())
+}
+
+/// Run a validation with the path extended by one JSON pointer segment.
+fn within<T>(path: &mut String, segment: &str, f: impl FnOnce(&mut String) -> T) -> T {
+    let len = path.len();
+    path.push('/');
+    path.push_str(&segment.replace('~', "~0").replace('/', "~1"));
+    let result = f(path);
+    path.truncate(len);
+    result
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        _ => false,
+    }
+}
+
+/// Compare two numbers exactly when both are integers.
+fn compare(a: &Number, b: &Number) -> std::cmp::Ordering {
+    let integer = |n: &Number| {
+        n.as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from))
+    };
+
+    match (integer(a), integer(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => {
+            let (a, b) = (
+                a.as_f64().unwrap_or(f64::NAN),
+                b.as_f64().unwrap_or(f64::NAN),
+            );
+            a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::{JsonOptions, Rfc3339};
+
+    fn sketch() -> crate::DDSketch {
+        let mut sketch = crate::DDSketch::new(0.01).unwrap();
+        sketch.add(-3.0);
+        sketch.add(0.0);
+        sketch.add(250.0);
+        sketch
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot::builder()
+            .systemtime(SystemTime::UNIX_EPOCH + Duration::from_secs(1))
+            .metadata("source", "test")
+            .counter("requests", u64::MAX, &[("method", "GET")])
+            .delta_counter("errors", 1, &[])
+            .gauge("temperature", i64::MIN, &[])
+            .histogram("latency", histogram::Histogram::new(2, 8).unwrap(), &[])
+            .stats("sizes", Some(1), Some(10), 11, 2, &[])
+            .stats("idle", None, None, 0, 0, &[])
+            .sketch("size", sketch(), &[])
+            .cardinality("clients", crate::HyperLogLog::new(4).unwrap(), &[])
+            .top_k("keys", 2, vec![crate::HeavyHitter::new("a", 3, 1)], &[])
+            .event(crate::Event::new("deploy").metadata("version", "1.2.3"))
+            .build()
+            .unwrap()
+    }
+
+    fn reason(json: &str) -> String {
+        match Snapshot::validate_json(json.as_bytes()) {
+            Err(JsonValidationError::Schema { path, reason }) => format!("{path}: {reason}"),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn serialized_snapshots_are_valid() {
+        let snapshot = snapshot();
+        Snapshot::validate_json(&Snapshot::to_json(&snapshot).unwrap()).unwrap();
+
+        let options = JsonOptions::new().rfc3339(Rfc3339::new());
+        Snapshot::validate_json(&snapshot.to_json_with(&options).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn invalid() {
+        let time = r#""systemtime": {"secs_since_epoch": 1, "nanos_since_epoch": 0}"#;
+
+        assert_eq!(
+            reason(&format!(r#"{{{time}, "counters": [], "gauges": []}}"#)),
+            ": missing required property `histograms`"
+        );
+        assert_eq!(
+            reason(&format!(
+                r#"{{{time}, "counters": [{{"name": "a", "value": -1, "metadata": {{}}}}], "gauges": [], "histograms": []}}"#
+            )),
+            "/counters/0/value: -1 is less than 0"
+        );
+        assert_eq!(
+            reason(&format!(
+                r#"{{{time}, "metadata": {{"a/b": 1}}, "counters": [], "gauges": [], "histograms": []}}"#
+            )),
+            "/metadata/a~1b: expected string"
+        );
+        assert_eq!(
+            reason(&format!(
+                r#"{{{time}, "counters": [{{"name": "a", "metric_type": "meter", "value": 1, "metadata": {{}}}}], "gauges": [], "histograms": []}}"#
+            )),
+            r#"/counters/0/metric_type: "meter" is not one of ["counter","delta_counter","gauge","histogram","delta_histogram","summary","sketch","cardinality","top_k"]"#
+        );
+        assert_eq!(
+            reason(r#"{"systemtime": 1, "counters": [], "gauges": [], "histograms": []}"#),
+            "/systemtime: does not match any of the allowed forms"
+        );
+
+        assert!(matches!(
+            Snapshot::validate_json(b"{"),
+            Err(JsonValidationError::Json(_))
+        ));
+    }
+}
@@ -2,19 +2,183 @@
 //!
 //! Provides a standardized struct for a snapshot of the metric readings as well
 //! as a way of producing the snapshots.
+//!
+//! # Features
+//!
+//! Only the snapshot types and the conversions which need no additional
+//! dependencies are always available. Everything else is opt-in, so that
+//! programs which only consume snapshots do not need the metrics registry or
+//! the heavier serialization formats.
+//!
+//! * `snapshotter` (default) - `Snapshotter` for taking snapshots of the
+//!   metriken registry, and `PushLimiter`. Without it, `metriken` is not a
+//!   dependency.
+//! * `serde`, `json`, `msgpack`, `cbor`, `postcard` - serde based formats.
+//!   Deserializing snapshots requires `serde` along with the format.
+//! * `protobuf`, `flatbuffers`, `avro` - schema based formats.
+//! * `otlp` - conversion from OTLP metrics.
+//! * `parquet` - writing and reading parquet files, which depends on `arrow`
+//!   and `parquet`. `parquet-conversion` adds msgpack to parquet conversion.
+//! * `shmem` - exposition through shared memory.
+//! * `scrape` - collecting metrics from HTTP targets.
+//! * `config` - pipelines built from a TOML configuration.
+//! * `log` - log snapshot and export events.
+//! * `contention` - contention counters for metrics.
+//! * `host` - collectors for the network interfaces and filesystems of the
+//!   host, and for the resource usage and threads of the process.
+//! * `nvml` - a collector for NVIDIA GPUs, which loads NVML at runtime.
 
+mod anomaly;
+mod auth;
+#[cfg(feature = "avro")]
+mod avro;
+mod batch;
+#[cfg(feature = "serde")]
+mod borrowed;
+mod budget;
+mod cancel;
+#[cfg(feature = "host")]
+mod cgroup;
+#[cfg(all(feature = "serde", feature = "msgpack", feature = "parquet"))]
+mod checkpoint;
 #[cfg(all(feature = "serde", feature = "msgpack", feature = "parquet"))]
 mod convert;
+mod downsample;
+mod exponential;
+#[cfg(feature = "flatbuffers")]
+mod flatbuffers;
+mod float;
+mod gaps;
+mod history;
+#[cfg(feature = "host")]
+mod host;
+mod hyperloglog;
+#[cfg(feature = "json")]
+mod json_schema;
+mod merge;
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+mod msgpack;
+#[cfg(feature = "nvml")]
+mod nvml;
+#[cfg(feature = "otlp")]
+mod otlp;
 #[cfg(feature = "parquet")]
 mod parquet;
+#[cfg(all(feature = "serde", feature = "msgpack", feature = "parquet"))]
+mod parquet_reader;
+#[cfg(feature = "config")]
+mod pipeline;
+mod prometheus;
+mod prometheus_reader;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod push;
+mod rebucket;
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+mod recording;
+mod regression;
+mod report;
+#[cfg(feature = "scrape")]
+mod scrape;
+#[cfg(feature = "shmem")]
+mod shared;
+#[cfg(feature = "shmem")]
+mod shmem;
+mod sketch;
 mod snapshot;
+#[cfg(feature = "snapshotter")]
 mod snapshotter;
+mod stream;
+mod temporality;
+mod text;
+#[cfg(feature = "host")]
+mod threads;
+mod timestamp;
 
+pub use anomaly::{AnomalyDetector, AnomalyMethod, ANOMALIES, ANOMALY};
+pub use auth::Auth;
+#[cfg(feature = "avro")]
+pub use avro::{AvroError, AvroReader, AvroWriter, AVRO_SCHEMA};
+pub use batch::SnapshotBatch;
+#[cfg(feature = "serde")]
+pub use borrowed::{
+    CardinalityRef, CounterRef, EventRef, GaugeRef, HeavyHitterRef, HistogramRef, MetadataRef,
+    SketchRef, SnapshotRef, StatsRef, TopKRef,
+};
+pub use budget::{Budget, Degradation, BUDGET_DROPPED};
+pub use cancel::{CancellationToken, Cancelled};
+#[cfg(feature = "host")]
+pub use cgroup::CgroupCollector;
 #[cfg(all(feature = "serde", feature = "msgpack", feature = "parquet"))]
-pub use convert::MsgpackToParquet;
+pub use convert::{ConversionProgress, MsgpackToParquet, OutOfOrder};
+pub use downsample::{Downsample, GaugeAggregation, AGGREGATION};
+pub use exponential::ExponentialHistogram;
+#[cfg(feature = "flatbuffers")]
+pub use flatbuffers::{FlatSnapshot, FlatbuffersError};
+pub use float::{FloatValue, NonFinite};
+pub use gaps::{
+    detect_gaps, FillPolicy, Gap, GapFill, SequenceStatus, SequenceTracker, GAP_MARKER,
+};
+pub use history::SnapshotHistory;
+#[cfg(feature = "host")]
+pub use host::{FilesystemCollector, NetworkCollector};
+pub use hyperloglog::{HyperLogLog, HyperLogLogError};
+#[cfg(feature = "json")]
+pub use json_schema::{JsonValidationError, JSON_SCHEMA};
+pub use merge::{Merge, CLOCK_CORRECTION};
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+pub use msgpack::{MsgpackIndex, MsgpackRefs, MsgpackWriter};
+#[cfg(feature = "nvml")]
+pub use nvml::GpuCollector;
+#[cfg(feature = "otlp")]
+pub use otlp::OtlpError;
 #[cfg(feature = "parquet")]
 pub use parquet::{
-    ParquetCompression, ParquetHistogramType, ParquetOptions, ParquetSchema, ParquetWriter,
+    ParquetColumnNaming, ParquetCompression, ParquetHistogramType, ParquetOptions, ParquetSchema,
+    ParquetWriter,
+};
+#[cfg(feature = "config")]
+pub use pipeline::{
+    Diagnostic, ExporterConfig, FilterConfig, Pipeline, PipelineConfig, PipelineError,
+    PipelineHandle, Severity, TransformConfig,
+};
+pub use prometheus::{cumulative_buckets, PrometheusOptions};
+pub use prometheus_reader::PrometheusError;
+#[cfg(feature = "protobuf")]
+pub use protobuf::ProtobufError;
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+pub use push::DeadLetterFile;
+#[cfg(feature = "snapshotter")]
+pub use push::PushLimiter;
+pub use push::{PushLimits, RetryPolicy};
+pub use rebucket::{common_config, rebucket};
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+pub use recording::{RecordingError, RecordingFormat, RecordingReader};
+pub use regression::{MetricComparison, RegressionDetector, StatisticalTest, Verdict};
+pub use report::{DiffEntry, DiffKind, DiffReport};
+#[cfg(feature = "scrape")]
+pub use scrape::{ScrapeError, ScrapeFormat, ScrapeTarget, Scraper, UP};
+#[cfg(feature = "shmem")]
+pub use shared::{
+    SharedCounter, SharedGauge, SharedHistogram, SharedRegistry, SharedRegistryBuilder,
+};
+#[cfg(feature = "shmem")]
+pub use shmem::{ShmemError, ShmemReader, ShmemWriter};
+pub use sketch::{DDSketch, SketchError};
+#[cfg(all(feature = "json", feature = "serde"))]
+pub use snapshot::JsonOptions;
+pub use snapshot::{
+    Cardinality, Counter, Event, Gauge, HeavyHitter, Histogram, MetricType, Sketch, Snapshot,
+    SnapshotBuilder, SnapshotError, Stats, TopK, DURATION, RESOURCE, SEQUENCE,
+};
+#[cfg(feature = "snapshotter")]
+pub use snapshotter::{
+    Collector, MetricMut, Registry, SnapshotSource, Snapshotter, SnapshotterBuilder,
+    UninitializedPolicy, CLOCK_REGRESSION,
 };
-pub use snapshot::{Counter, Gauge, Histogram, Snapshot};
-pub use snapshotter::{Snapshotter, SnapshotterBuilder};
+pub use stream::{ConvertTemporality, FilterMetrics, GroupBy, SnapshotIterExt};
+pub use temporality::{Temporality, TemporalityConverter};
+pub use text::{format_human, TextOptions};
+#[cfg(feature = "host")]
+pub use threads::ThreadCollector;
+pub use timestamp::Rfc3339;
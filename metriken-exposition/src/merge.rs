@@ -0,0 +1,274 @@
+use std::iter::Peekable;
+use std::time::{Duration, SystemTime};
+
+use crate::snapshot::Snapshot;
+
+/// The default metadata key used to identify the source of each metric.
+const DEFAULT_LABEL: &str = "source";
+
+/// The snapshot metadata key recording the correction applied by alignment,
+/// in signed nanoseconds.
+pub const CLOCK_CORRECTION: &str = "clock_correction_ns";
+
+struct Source {
+    name: String,
+    snapshots: Peekable<Box<dyn Iterator<Item = Snapshot>>>,
+}
+
+/// Merges the snapshots from several sources, such as the recordings from
+/// multiple hosts, into a single time-ordered stream.
+///
+/// Each source is expected to be in time order. Every metric in a merged
+/// snapshot is labeled with the name of its source, and the name can also be
+/// prefixed onto the metric names for formats such as parquet where each
+/// metric name is a column.
+///
+/// ```
+/// # use metriken_exposition::{Merge, Snapshotter};
+/// let snapshotter = Snapshotter::default();
+/// let a = vec![snapshotter.snapshot()];
+/// let b = vec![snapshotter.snapshot()];
+///
+/// let merged: Vec<_> = Merge::new().source("a", a).source("b", b).collect();
+/// assert_eq!(merged.len(), 2);
+/// ```
+pub struct Merge {
+    label: String,
+    prefix_names: bool,
+    alignment: Option<Alignment>,
+    sources: Vec<Source>,
+}
+
+/// Parameters for snapping snapshot timestamps onto a common grid.
+#[derive(Clone, Copy)]
+struct Alignment {
+    interval: Duration,
+    max_skew: Duration,
+}
+
+impl Alignment {
+    /// Returns the aligned time and the signed correction in nanoseconds, or
+    /// `None` if the correction would exceed the maximum skew.
+    fn align(&self, time: SystemTime) -> Option<(SystemTime, i128)> {
+        let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_nanos();
+        let interval = self.interval.as_nanos();
+        if interval == 0 {
+            return None;
+        }
+
+        let remainder = since_epoch % interval;
+        let aligned = if remainder * 2 >= interval {
+            since_epoch - remainder + interval
+        } else {
+            since_epoch - remainder
+        };
+
+        let correction = aligned as i128 - since_epoch as i128;
+        if correction.unsigned_abs() > self.max_skew.as_nanos() {
+            return None;
+        }
+
+        let aligned = SystemTime::UNIX_EPOCH
+            + Duration::new(
+                (aligned / 1_000_000_000) as u64,
+                (aligned % 1_000_000_000) as u32,
+            );
+        Some((aligned, correction))
+    }
+}
+
+impl Default for Merge {
+    fn default() -> Self {
+        Self {
+            label: DEFAULT_LABEL.to_string(),
+            prefix_names: false,
+            alignment: None,
+            sources: Vec::new(),
+        }
+    }
+}
+
+impl Merge {
+    /// Create a new merge with no sources.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the metadata key used to label metrics with their source. The
+    /// default is `source`.
+    pub fn label(mut self, key: impl Into<String>) -> Self {
+        self.label = key.into();
+        self
+    }
+
+    /// When enabled, metric names are prefixed with the source name and a `/`
+    /// so that the same metric from different sources has a distinct name.
+    pub fn prefix_names(mut self, enabled: bool) -> Self {
+        self.prefix_names = enabled;
+        self
+    }
+
+    /// Snap the timestamp of each snapshot to the nearest multiple of the
+    /// interval since the unix epoch, correcting for clock skew between the
+    /// sources. Snapshots which would need to be moved by more than
+    /// `max_skew` keep their original timestamp. The applied correction is
+    /// recorded in the snapshot metadata under [`CLOCK_CORRECTION`].
+    pub fn align(mut self, interval: Duration, max_skew: Duration) -> Self {
+        self.alignment = Some(Alignment { interval, max_skew });
+        self
+    }
+
+    /// Add a source of snapshots with the provided name.
+    pub fn source<I>(mut self, name: impl Into<String>, snapshots: I) -> Self
+    where
+        I: IntoIterator<Item = Snapshot>,
+        I::IntoIter: 'static,
+    {
+        let snapshots: Box<dyn Iterator<Item = Snapshot>> = Box::new(snapshots.into_iter());
+        self.sources.push(Source {
+            name: name.into(),
+            snapshots: snapshots.peekable(),
+        });
+        self
+    }
+
+    fn label_snapshot(&self, source: &str, mut snapshot: Snapshot) -> Snapshot {
+        snapshot
+            .metadata
+            .insert(self.label.clone(), source.to_string());
+
+        if let Some((time, correction)) = self.alignment.and_then(|a| a.align(snapshot.systemtime))
+        {
+            snapshot.systemtime = time;
+            snapshot
+                .metadata
+                .insert(CLOCK_CORRECTION.to_string(), correction.to_string());
+        }
+
+        macro_rules! label {
+            ($metrics:expr) => {
+                for metric in $metrics.iter_mut() {
+                    metric
+                        .metadata
+                        .insert(self.label.clone(), source.to_string());
+                    if self.prefix_names {
+                        metric.name = format!("{source}/{}", metric.name);
+                    }
+                }
+            };
+        }
+
+        label!(snapshot.counters);
+        label!(snapshot.gauges);
+        label!(snapshot.histograms);
+        label!(snapshot.stats);
+        label!(snapshot.sketches);
+        label!(snapshot.cardinalities);
+        label!(snapshot.top_k);
+
+        snapshot
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Snapshot;
+
+    fn next(&mut self) -> Option<Snapshot> {
+        // Pick the source with the earliest next snapshot. Ties go to the
+        // source which was added first.
+        let alignment = self.alignment;
+        let mut earliest: Option<(usize, SystemTime)> = None;
+        for (idx, source) in self.sources.iter_mut().enumerate() {
+            let Some(snapshot) = source.snapshots.peek() else {
+                continue;
+            };
+            let time = alignment
+                .and_then(|a| a.align(snapshot.systemtime))
+                .map(|(time, _)| time)
+                .unwrap_or(snapshot.systemtime);
+            match earliest {
+                Some((_, earliest)) if earliest <= time => {}
+                _ => earliest = Some((idx, time)),
+            }
+        }
+
+        let (idx, _) = earliest?;
+        let snapshot = self.sources[idx].snapshots.next()?;
+        let name = self.sources[idx].name.clone();
+        Some(self.label_snapshot(&name, snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::{Counter, MetricType};
+
+    fn source(seconds: &[u64]) -> Vec<Snapshot> {
+        seconds
+            .iter()
+            .map(|s| {
+                let mut snapshot = Snapshot::new();
+                snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_secs(*s);
+                snapshot.counters.push(Counter {
+                    name: "requests".to_string(),
+                    metric_type: MetricType::Counter,
+                    value: *s,
+                    metadata: HashMap::new(),
+                });
+                snapshot
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge() {
+        let merged: Vec<Snapshot> = Merge::new()
+            .prefix_names(true)
+            .source("a", source(&[0, 2, 4]))
+            .source("b", source(&[1, 2, 3]))
+            .collect();
+
+        let order: Vec<(u64, &str)> = merged
+            .iter()
+            .map(|s| (s.counters[0].value, s.get_metadata("source").unwrap()))
+            .collect();
+        assert_eq!(
+            order,
+            vec![(0, "a"), (1, "b"), (2, "a"), (2, "b"), (3, "b"), (4, "a")]
+        );
+
+        assert_eq!(merged[1].counters[0].name, "b/requests");
+        assert_eq!(merged[1].counters[0].metadata.get("source").unwrap(), "b");
+    }
+
+    #[test]
+    fn align() {
+        let mut a = source(&[10]);
+        a[0].systemtime += Duration::from_millis(200);
+        let mut b = source(&[10]);
+        b[0].systemtime -= Duration::from_millis(100);
+        let mut c = source(&[10]);
+        c[0].systemtime += Duration::from_millis(400);
+
+        let merged: Vec<Snapshot> = Merge::new()
+            .align(Duration::from_secs(1), Duration::from_millis(250))
+            .source("a", a)
+            .source("b", b)
+            .source("c", c)
+            .collect();
+
+        let ten = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+        assert_eq!(merged[0].systemtime, ten);
+        assert_eq!(merged[0].get_metadata(CLOCK_CORRECTION), Some("-200000000"));
+        assert_eq!(merged[1].systemtime, ten);
+        assert_eq!(merged[1].get_metadata(CLOCK_CORRECTION), Some("100000000"));
+
+        // beyond the maximum skew
+        assert_eq!(merged[2].systemtime, ten + Duration::from_millis(400));
+        assert_eq!(merged[2].get_metadata(CLOCK_CORRECTION), None);
+    }
+}
@@ -0,0 +1,468 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufRead, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+use crate::borrowed::SnapshotRef;
+use crate::recording::RecordingError;
+use crate::snapshot::{Snapshot, SEQUENCE};
+
+/// The magic bytes which end every msgpack recording index.
+const INDEX_MAGIC: &[u8; 8] = b"MKNIDX01";
+
+/// The length of the trailer which follows the encoded index: the length of
+/// the encoded index as a little-endian `u64` followed by the magic bytes.
+const TRAILER_LEN: u64 = 16;
+
+/// The first byte of a repeat marker, which is the msgpack marker for a
+/// `uint 64`. Encoded snapshots always begin with an array or map marker, so
+/// the two can be told apart from the first byte.
+const REPEAT_MARKER: u8 = 0xcf;
+
+/// The length of a repeat marker: the marker byte followed by the timestamp
+/// of the repeated snapshot as a big-endian `u64` of nanoseconds since the
+/// unix epoch.
+const REPEAT_LEN: usize = 9;
+
+/// The first byte of a repeat marker for a snapshot with a sequence number,
+/// which is the msgpack marker for an `int 64`.
+const SEQUENCED_REPEAT_MARKER: u8 = 0xd3;
+
+/// The length of a sequenced repeat marker: a repeat marker followed by the
+/// sequence number of the repeated snapshot as a big-endian `u64`.
+const SEQUENCED_REPEAT_LEN: usize = 17;
+
+/// Pairs of timestamp (nanoseconds since the unix epoch) and byte offset for
+/// each snapshot in a recording.
+type Index = Vec<(u64, u64)>;
+
+/// Where, if anywhere, a `MsgpackWriter` writes its index.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum MsgpackIndex {
+    /// No index is written.
+    #[default]
+    None,
+    /// The index is appended to the recording after the last snapshot.
+    Footer,
+    /// The index is written to a separate file at the provided path.
+    Sidecar(PathBuf),
+}
+
+impl MsgpackIndex {
+    /// The conventional sidecar path for a recording, which is the recording
+    /// path with `.idx` appended.
+    pub fn sidecar_for(recording: impl AsRef<Path>) -> Self {
+        let mut path = recording.as_ref().as_os_str().to_owned();
+        path.push(".idx");
+        Self::Sidecar(path.into())
+    }
+}
+
+/// Writes snapshots to a msgpack recording, optionally recording an index of
+/// timestamp to byte offset which allows readers to seek directly to a point
+/// in the recording.
+///
+/// With [`MsgpackWriter::dedup`] enabled, a snapshot which is identical to the
+/// previous one apart from its time and sequence number is written as a
+/// compact repeat marker, which a [`crate::RecordingReader`] turns back into a
+/// full snapshot.
+pub struct MsgpackWriter<W: Write> {
+    writer: W,
+    index_mode: MsgpackIndex,
+    offset: u64,
+    index: Index,
+    dedup: bool,
+    /// The last snapshot written in full and its offset, if deduplicating.
+    previous: Option<(Snapshot, u64)>,
+}
+
+impl<W: Write> MsgpackWriter<W> {
+    /// Create a new writer which does not write an index.
+    pub fn new(writer: W) -> Self {
+        Self::with_index(writer, MsgpackIndex::None)
+    }
+
+    /// Create a new writer which writes an index as specified.
+    pub fn with_index(writer: W, index: MsgpackIndex) -> Self {
+        Self {
+            writer,
+            index_mode: index,
+            offset: 0,
+            index: Vec::new(),
+            dedup: false,
+            previous: None,
+        }
+    }
+
+    /// Write snapshots which are identical to the previous snapshot, other
+    /// than their time and sequence number, as repeat markers. Idle services
+    /// produce long runs of identical snapshots, which then take a few bytes
+    /// each.
+    ///
+    /// Recordings written with this enabled must be read with a
+    /// [`crate::RecordingReader`] or converted with `MsgpackToParquet`, which
+    /// understand the markers.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Serialize a snapshot and append it to the recording.
+    pub fn push(&mut self, snapshot: &Snapshot) -> Result<(), RecordingError> {
+        if self.dedup {
+            if let Some((previous, offset)) = &self.previous {
+                if same_values(previous, snapshot) {
+                    let mut marker = [REPEAT_MARKER; SEQUENCED_REPEAT_LEN];
+                    marker[1..REPEAT_LEN]
+                        .copy_from_slice(&unix_nanos(snapshot.systemtime).to_be_bytes());
+
+                    let len = match snapshot.sequence() {
+                        Some(sequence) => {
+                            marker[0] = SEQUENCED_REPEAT_MARKER;
+                            marker[REPEAT_LEN..].copy_from_slice(&sequence.to_be_bytes());
+                            SEQUENCED_REPEAT_LEN
+                        }
+                        None => REPEAT_LEN,
+                    };
+                    self.writer.write_all(&marker[..len])?;
+
+                    // Index the full snapshot, since reading must begin there
+                    // to reconstruct this one.
+                    self.index.push((unix_nanos(snapshot.systemtime), *offset));
+                    self.offset += len as u64;
+
+                    return Ok(());
+                }
+            }
+
+            self.previous = Some((snapshot.clone(), self.offset));
+        }
+
+        let bytes = Snapshot::to_msgpack(snapshot)?;
+        self.writer.write_all(&bytes)?;
+
+        self.index
+            .push((unix_nanos(snapshot.systemtime), self.offset));
+        self.offset += bytes.len() as u64;
+
+        Ok(())
+    }
+
+    /// The number of bytes of snapshots written so far.
+    #[cfg(feature = "config")]
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Write the index, if enabled, flush the recording, and return the
+    /// underlying writer.
+    pub fn finalize(mut self) -> Result<W, RecordingError> {
+        match &self.index_mode {
+            MsgpackIndex::None => {}
+            MsgpackIndex::Footer => write_index(&mut self.writer, &self.index)?,
+            MsgpackIndex::Sidecar(path) => {
+                let mut file = File::create(path)?;
+                write_index(&mut file, &self.index)?;
+                file.flush()?;
+            }
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Decodes the snapshots of a msgpack recording in order, expanding any repeat
+/// markers written by a deduplicating [`MsgpackWriter`].
+#[derive(Default)]
+pub(crate) struct MsgpackDecoder {
+    previous: Option<Snapshot>,
+}
+
+impl MsgpackDecoder {
+    /// Decode the next snapshot from the reader.
+    pub(crate) fn decode<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Snapshot, RecordingError> {
+        let len = match reader.fill_buf()?.first() {
+            Some(&REPEAT_MARKER) => REPEAT_LEN,
+            Some(&SEQUENCED_REPEAT_MARKER) => SEQUENCED_REPEAT_LEN,
+            _ => {
+                let snapshot: Snapshot = rmp_serde::from_read(reader)?;
+                self.previous = Some(snapshot.clone());
+                return Ok(snapshot);
+            }
+        };
+
+        let mut marker = [0; SEQUENCED_REPEAT_LEN];
+        reader.read_exact(&mut marker[..len])?;
+
+        let Some(previous) = &self.previous else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "repeat marker without a preceding snapshot",
+            )
+            .into());
+        };
+
+        let nanos = u64::from_be_bytes(marker[1..REPEAT_LEN].try_into().unwrap());
+        let mut snapshot = previous.clone();
+        snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos);
+
+        if len == SEQUENCED_REPEAT_LEN {
+            let sequence = u64::from_be_bytes(marker[REPEAT_LEN..].try_into().unwrap());
+            snapshot
+                .metadata
+                .insert(SEQUENCE.to_string(), sequence.to_string());
+        }
+
+        Ok(snapshot)
+    }
+}
+
+/// Iterates over the snapshots of a msgpack recording which is held in memory,
+/// such as a memory mapped file, borrowing the strings of each snapshot from
+/// it. See [`SnapshotRef`].
+///
+/// Repeat markers written by a deduplicating [`MsgpackWriter`] are expanded,
+/// and an index footer is skipped.
+pub struct MsgpackRefs<'a> {
+    data: &'a [u8],
+    previous: Option<SnapshotRef<'a>>,
+}
+
+impl<'a> MsgpackRefs<'a> {
+    /// Iterate over the snapshots in the recording.
+    pub fn new(recording: &'a [u8]) -> Result<Self, RecordingError> {
+        let layout = MsgpackLayout::read(&mut Cursor::new(recording))?;
+        Ok(Self {
+            data: &recording[..layout.data_len as usize],
+            previous: None,
+        })
+    }
+
+    fn decode(&mut self) -> Result<SnapshotRef<'a>, RecordingError> {
+        let len = match self.data[0] {
+            REPEAT_MARKER => REPEAT_LEN,
+            SEQUENCED_REPEAT_MARKER => SEQUENCED_REPEAT_LEN,
+            _ => {
+                // The borrowing deserializer can't say how much it read, so
+                // the length of the snapshot is found by skipping over it.
+                let mut skip = rmp_serde::Deserializer::new(Cursor::new(self.data));
+                IgnoredAny::deserialize(&mut skip)?;
+                let (encoded, rest) = self.data.split_at(skip.position() as usize);
+                self.data = rest;
+
+                let snapshot = SnapshotRef::from_msgpack(encoded)?;
+                self.previous = Some(snapshot.clone());
+                return Ok(snapshot);
+            }
+        };
+
+        let Some(marker) = self.data.get(..len) else {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        };
+        self.data = &self.data[len..];
+
+        let Some(previous) = &self.previous else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "repeat marker without a preceding snapshot",
+            )
+            .into());
+        };
+
+        let nanos = u64::from_be_bytes(marker[1..REPEAT_LEN].try_into().unwrap());
+        let mut snapshot = previous.clone();
+        snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos);
+
+        if len == SEQUENCED_REPEAT_LEN {
+            let sequence = u64::from_be_bytes(marker[REPEAT_LEN..].try_into().unwrap());
+            snapshot
+                .metadata
+                .insert(Cow::Borrowed(SEQUENCE), Cow::Owned(sequence.to_string()));
+        }
+
+        Ok(snapshot)
+    }
+}
+
+impl<'a> Iterator for MsgpackRefs<'a> {
+    type Item = Result<SnapshotRef<'a>, RecordingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let result = self.decode();
+        if result.is_err() {
+            // the rest of the recording can't be found after a bad snapshot
+            self.data = &[];
+        }
+        Some(result)
+    }
+}
+
+/// Whether two snapshots are identical apart from their time and sequence
+/// number.
+fn same_values(a: &Snapshot, b: &Snapshot) -> bool {
+    let metadata_len = |s: &Snapshot| s.metadata.len() - s.metadata.contains_key(SEQUENCE) as usize;
+
+    // snapshots with events are never repeats, so the events are not lost
+    a.events.is_empty()
+        && b.events.is_empty()
+        && metadata_len(a) == metadata_len(b)
+        && a.metadata
+            .iter()
+            .filter(|(k, _)| *k != SEQUENCE)
+            .all(|(k, v)| b.metadata.get(k) == Some(v))
+        && a.counters.len() == b.counters.len()
+        && a.gauges.len() == b.gauges.len()
+        && a.histograms.len() == b.histograms.len()
+        && a.stats.len() == b.stats.len()
+        && a.sketches.len() == b.sketches.len()
+        && a.cardinalities.len() == b.cardinalities.len()
+        && a.top_k.len() == b.top_k.len()
+        && a.counters.iter().zip(&b.counters).all(|(a, b)| {
+            a.name == b.name
+                && a.metric_type == b.metric_type
+                && a.value == b.value
+                && a.metadata == b.metadata
+        })
+        && a.gauges.iter().zip(&b.gauges).all(|(a, b)| {
+            a.name == b.name
+                && a.metric_type == b.metric_type
+                && a.value == b.value
+                && a.metadata == b.metadata
+        })
+        && a.histograms.iter().zip(&b.histograms).all(|(a, b)| {
+            a.name == b.name
+                && a.metric_type == b.metric_type
+                && a.value == b.value
+                && a.metadata == b.metadata
+        })
+        && a.stats.iter().zip(&b.stats).all(|(a, b)| {
+            a.name == b.name
+                && a.metric_type == b.metric_type
+                && (a.min, a.max, a.sum, a.count) == (b.min, b.max, b.sum, b.count)
+                && a.metadata == b.metadata
+        })
+        && a.sketches.iter().zip(&b.sketches).all(|(a, b)| {
+            a.name == b.name
+                && a.metric_type == b.metric_type
+                && a.value == b.value
+                && a.metadata == b.metadata
+        })
+        && a.cardinalities.iter().zip(&b.cardinalities).all(|(a, b)| {
+            a.name == b.name
+                && a.metric_type == b.metric_type
+                && a.value == b.value
+                && a.metadata == b.metadata
+        })
+        && a.top_k.iter().zip(&b.top_k).all(|(a, b)| {
+            a.name == b.name
+                && a.metric_type == b.metric_type
+                && a.k == b.k
+                && a.entries == b.entries
+                && a.metadata == b.metadata
+        })
+}
+
+/// The layout of a msgpack recording.
+pub(crate) struct MsgpackLayout {
+    /// The number of bytes at the start of the recording which contain
+    /// snapshots. This excludes the index footer, if any.
+    pub(crate) data_len: u64,
+    /// The index for the recording, if one was found.
+    pub(crate) index: Option<Index>,
+}
+
+impl MsgpackLayout {
+    /// Determine the layout of a recording, reading the index from its footer
+    /// if one is present. The position of the reader is left unspecified.
+    pub(crate) fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, RecordingError> {
+        let len = reader.seek(SeekFrom::End(0))?;
+
+        match read_index(reader, len)? {
+            Some((index, index_len)) => Ok(Self {
+                data_len: len - index_len,
+                index: Some(index),
+            }),
+            None => Ok(Self {
+                data_len: len,
+                index: None,
+            }),
+        }
+    }
+
+    /// Load the index from a sidecar file, replacing any footer index.
+    pub(crate) fn load_sidecar(&mut self, path: impl AsRef<Path>) -> Result<(), RecordingError> {
+        let mut file = File::open(path)?;
+        let len = file.seek(SeekFrom::End(0))?;
+
+        match read_index(&mut file, len)? {
+            Some((index, _)) => {
+                self.index = Some(index);
+                Ok(())
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "sidecar file does not contain a msgpack recording index",
+            )
+            .into()),
+        }
+    }
+}
+
+/// Write the encoded index followed by the trailer.
+fn write_index<W: Write>(writer: &mut W, index: &[(u64, u64)]) -> Result<(), RecordingError> {
+    let encoded = rmp_serde::encode::to_vec(index)?;
+    writer.write_all(&encoded)?;
+    writer.write_all(&(encoded.len() as u64).to_le_bytes())?;
+    writer.write_all(INDEX_MAGIC)?;
+    Ok(())
+}
+
+/// Read an index which ends at `end`. Returns the decoded index and the total
+/// number of bytes it occupies, or `None` if there is no index.
+fn read_index<R: Read + Seek>(
+    reader: &mut R,
+    end: u64,
+) -> Result<Option<(Index, u64)>, RecordingError> {
+    if end < TRAILER_LEN {
+        return Ok(None);
+    }
+
+    let mut trailer = [0; TRAILER_LEN as usize];
+    reader.seek(SeekFrom::Start(end - TRAILER_LEN))?;
+    reader.read_exact(&mut trailer)?;
+
+    if &trailer[8..] != INDEX_MAGIC {
+        return Ok(None);
+    }
+
+    let encoded_len = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    let Some(start) = end.checked_sub(TRAILER_LEN + encoded_len) else {
+        return Ok(None);
+    };
+
+    let mut encoded = vec![0; encoded_len as usize];
+    reader.seek(SeekFrom::Start(start))?;
+    reader.read_exact(&mut encoded)?;
+
+    let index = rmp_serde::from_slice(&encoded)?;
+    Ok(Some((index, TRAILER_LEN + encoded_len)))
+}
+
+/// Convert a `SystemTime` to nanoseconds since the unix epoch.
+pub(crate) fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
@@ -0,0 +1,290 @@
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr};
+
+use metriken::{DynBoxedMetric, Gauge, MetricBuilder, Unit};
+
+use crate::host::Devices;
+use crate::snapshotter::Collector;
+
+type Device = *mut c_void;
+type Return = c_int;
+
+const SUCCESS: Return = 0;
+const TEMPERATURE_GPU: c_int = 0;
+
+#[repr(C)]
+#[derive(Default)]
+struct Utilization {
+    gpu: c_uint,
+    memory: c_uint,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Memory {
+    total: u64,
+    free: u64,
+    used: u64,
+}
+
+/// The functions of the NVML library, loaded when the collector is created
+/// so that metriken does not link against it.
+struct Library {
+    handle: *mut c_void,
+    shutdown: unsafe extern "C" fn() -> Return,
+    device_count: unsafe extern "C" fn(*mut c_uint) -> Return,
+    device_handle: unsafe extern "C" fn(c_uint, *mut Device) -> Return,
+    device_name: unsafe extern "C" fn(Device, *mut c_char, c_uint) -> Return,
+    utilization: unsafe extern "C" fn(Device, *mut Utilization) -> Return,
+    memory: unsafe extern "C" fn(Device, *mut Memory) -> Return,
+    temperature: unsafe extern "C" fn(Device, c_int, *mut c_uint) -> Return,
+    power: unsafe extern "C" fn(Device, *mut c_uint) -> Return,
+}
+
+// NVML is thread-safe, and the handle is only closed when the library is
+// dropped.
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+impl Library {
+    fn load() -> Option<Self> {
+        unsafe {
+            let handle = ["libnvidia-ml.so.1", "libnvidia-ml.so"]
+                .into_iter()
+                .map(|name| {
+                    let name = std::ffi::CString::new(name).unwrap();
+                    libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL)
+                })
+                .find(|handle| !handle.is_null())?;
+
+            match Self::init(handle) {
+                Some(library) => Some(library),
+                None => {
+                    libc::dlclose(handle);
+                    None
+                }
+            }
+        }
+    }
+
+    unsafe fn init(handle: *mut c_void) -> Option<Self> {
+        unsafe fn symbol<T: Copy>(handle: *mut c_void, name: &CStr) -> Option<T> {
+            let symbol = libc::dlsym(handle, name.as_ptr());
+            if symbol.is_null() {
+                return None;
+            }
+            Some(std::mem::transmute_copy(&symbol))
+        }
+
+        let init: unsafe extern "C" fn() -> Return = symbol(handle, c"nvmlInit_v2")?;
+        let library = Self {
+            handle,
+            shutdown: symbol(handle, c"nvmlShutdown")?,
+            device_count: symbol(handle, c"nvmlDeviceGetCount_v2")?,
+            device_handle: symbol(handle, c"nvmlDeviceGetHandleByIndex_v2")?,
+            device_name: symbol(handle, c"nvmlDeviceGetName")?,
+            utilization: symbol(handle, c"nvmlDeviceGetUtilizationRates")?,
+            memory: symbol(handle, c"nvmlDeviceGetMemoryInfo")?,
+            temperature: symbol(handle, c"nvmlDeviceGetTemperature")?,
+            power: symbol(handle, c"nvmlDeviceGetPowerUsage")?,
+        };
+
+        if init() != SUCCESS {
+            return None;
+        }
+        Some(library)
+    }
+
+    /// Read the statistics of every device. Statistics which a device does
+    /// not support are left out.
+    fn read(&self) -> Vec<(String, Reading)> {
+        let mut count = 0;
+        if unsafe { (self.device_count)(&mut count) } != SUCCESS {
+            return Vec::new();
+        }
+
+        (0..count)
+            .filter_map(|index| unsafe {
+                let mut device = std::ptr::null_mut();
+                if (self.device_handle)(index, &mut device) != SUCCESS {
+                    return None;
+                }
+
+                let mut name = [0 as c_char; 96];
+                let name = match (self.device_name)(device, name.as_mut_ptr(), name.len() as _) {
+                    SUCCESS => CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned(),
+                    _ => String::new(),
+                };
+
+                let mut utilization = Utilization::default();
+                let utilization = ((self.utilization)(device, &mut utilization) == SUCCESS)
+                    .then_some(utilization);
+                let mut memory = Memory::default();
+                let memory = ((self.memory)(device, &mut memory) == SUCCESS).then_some(memory);
+                let mut temperature = 0;
+                let temperature = ((self.temperature)(device, TEMPERATURE_GPU, &mut temperature)
+                    == SUCCESS)
+                    .then_some(temperature);
+                let mut power = 0;
+                let power = ((self.power)(device, &mut power) == SUCCESS).then_some(power);
+
+                let reading = Reading {
+                    name,
+                    utilization: utilization.as_ref().map(|u| u.gpu),
+                    memory_utilization: utilization.as_ref().map(|u| u.memory),
+                    memory_used: memory.as_ref().map(|m| m.used),
+                    memory_total: memory.as_ref().map(|m| m.total),
+                    temperature,
+                    power,
+                };
+                Some((index.to_string(), reading))
+            })
+            .collect()
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            (self.shutdown)();
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+/// The statistics of a single GPU.
+struct Reading {
+    name: String,
+    utilization: Option<u32>,
+    memory_utilization: Option<u32>,
+    memory_used: Option<u64>,
+    memory_total: Option<u64>,
+    temperature: Option<u32>,
+    power: Option<u32>,
+}
+
+struct GpuMetrics {
+    utilization: DynBoxedMetric<Gauge>,
+    memory_utilization: DynBoxedMetric<Gauge>,
+    memory_used: DynBoxedMetric<Gauge>,
+    memory_total: DynBoxedMetric<Gauge>,
+    temperature: DynBoxedMetric<Gauge>,
+    power: DynBoxedMetric<Gauge>,
+}
+
+impl GpuMetrics {
+    fn new(device: &str, reading: &Reading) -> Self {
+        let gauge = |name: &'static str, unit: Unit<'static>| {
+            MetricBuilder::new(name)
+                .metadata("device", device)
+                .metadata("model", reading.name.as_str())
+                .unit(unit)
+                .build(Gauge::new())
+        };
+
+        Self {
+            utilization: gauge("gpu/utilization", Unit::Percent),
+            memory_utilization: gauge("gpu/memory/utilization", Unit::Percent),
+            memory_used: gauge("gpu/memory/used", Unit::Bytes),
+            memory_total: gauge("gpu/memory/total", Unit::Bytes),
+            temperature: gauge("gpu/temperature", Unit::Custom("celsius")),
+            power: gauge("gpu/power", Unit::Custom("milliwatts")),
+        }
+    }
+
+    fn update(&self, reading: &Reading) {
+        let set = |gauge: &Gauge, value: Option<u64>| {
+            if let Some(value) = value {
+                gauge.set(value as i64);
+            }
+        };
+
+        set(&self.utilization, reading.utilization.map(u64::from));
+        set(
+            &self.memory_utilization,
+            reading.memory_utilization.map(u64::from),
+        );
+        set(&self.memory_used, reading.memory_used);
+        set(&self.memory_total, reading.memory_total);
+        set(&self.temperature, reading.temperature.map(u64::from));
+        set(&self.power, reading.power.map(u64::from));
+    }
+}
+
+/// Collects the utilization, memory, temperature, and power draw of each
+/// NVIDIA GPU through NVML.
+///
+/// NVML is loaded from `libnvidia-ml.so` when the collector is created, which
+/// is installed along with the NVIDIA driver. If it cannot be loaded, the
+/// collector registers no metrics. The following gauges are kept for each
+/// GPU, with the index of the GPU in the `device` metadata key and its model
+/// in the `model` key:
+///
+/// - `gpu/utilization`: the percent of time a kernel was running
+/// - `gpu/memory/utilization`: the percent of time memory was being read or
+///   written
+/// - `gpu/memory/used` and `gpu/memory/total`: the memory in use and
+///   installed, in bytes
+/// - `gpu/temperature`: the temperature of the GPU, in degrees celsius
+/// - `gpu/power`: the power draw of the GPU, in milliwatts
+///
+/// ```
+/// # use metriken_exposition::{GpuCollector, SnapshotterBuilder};
+/// let snapshotter = SnapshotterBuilder::new()
+///     .collector(GpuCollector::new())
+///     .build();
+/// ```
+pub struct GpuCollector {
+    library: Option<Library>,
+    gpus: Devices<GpuMetrics>,
+}
+
+impl Default for GpuCollector {
+    fn default() -> Self {
+        Self {
+            library: Library::load(),
+            gpus: Devices::default(),
+        }
+    }
+}
+
+impl GpuCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether NVML was loaded. Without it no metrics are collected.
+    pub fn is_available(&self) -> bool {
+        self.library.is_some()
+    }
+}
+
+impl Collector for GpuCollector {
+    fn refresh(&self) {
+        let Some(library) = &self.library else {
+            return;
+        };
+
+        self.gpus
+            .update(library.read(), GpuMetrics::new, GpuMetrics::update);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unavailable() {
+        let collector = GpuCollector::new();
+        collector.refresh();
+
+        let registered = metriken::metrics()
+            .iter()
+            .filter(|m| m.name() == "gpu/utilization")
+            .count();
+
+        if !collector.is_available() {
+            assert_eq!(registered, 0);
+        }
+    }
+}
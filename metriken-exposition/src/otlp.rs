@@ -0,0 +1,854 @@
+//! Decoding of OTLP metrics, following the `ExportMetricsServiceRequest`
+//! message of the OpenTelemetry protocol. Only the fields which are mapped
+//! onto snapshots are declared, and all others are skipped when decoding.
+
+use std::time::{Duration, SystemTime};
+
+use prost::Message;
+
+use crate::exponential::ExponentialHistogram as Exponential;
+use crate::snapshot::{Snapshot, SnapshotBuilder, SnapshotError};
+
+/// Errors that can occur while converting OTLP metrics into a snapshot.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum OtlpError {
+    Decode(prost::DecodeError),
+    Histogram(histogram::Error),
+    Snapshot(SnapshotError),
+}
+
+impl std::fmt::Display for OtlpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Decode(e) => write!(f, "protobuf decode error: {e}"),
+            Self::Histogram(e) => write!(f, "invalid histogram: {e}"),
+            Self::Snapshot(e) => write!(f, "invalid snapshot: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for OtlpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode(e) => Some(e),
+            Self::Histogram(e) => Some(e),
+            Self::Snapshot(e) => Some(e),
+        }
+    }
+}
+
+impl From<prost::DecodeError> for OtlpError {
+    fn from(e: prost::DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+impl From<histogram::Error> for OtlpError {
+    fn from(e: histogram::Error) -> Self {
+        Self::Histogram(e)
+    }
+}
+
+impl From<SnapshotError> for OtlpError {
+    fn from(e: SnapshotError) -> Self {
+        Self::Snapshot(e)
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ExportMetricsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ResourceMetrics {
+    #[prost(message, optional, tag = "1")]
+    resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ScopeMetrics {
+    #[prost(message, repeated, tag = "2")]
+    metrics: Vec<Metric>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Metric {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    description: String,
+    #[prost(string, tag = "3")]
+    unit: String,
+    #[prost(oneof = "Data", tags = "5, 7, 9, 10, 11")]
+    data: Option<Data>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum Data {
+    #[prost(message, tag = "5")]
+    Gauge(Gauge),
+    #[prost(message, tag = "7")]
+    Sum(Sum),
+    #[prost(message, tag = "9")]
+    Histogram(Histogram),
+    #[prost(message, tag = "10")]
+    ExponentialHistogram(ExponentialHistogram),
+    #[prost(message, tag = "11")]
+    Summary(Summary),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum AggregationTemporality {
+    Unspecified = 0,
+    Delta = 1,
+    Cumulative = 2,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Gauge {
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<NumberDataPoint>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Sum {
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<NumberDataPoint>,
+    #[prost(enumeration = "AggregationTemporality", tag = "2")]
+    aggregation_temporality: i32,
+    #[prost(bool, tag = "3")]
+    is_monotonic: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Histogram {
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<HistogramDataPoint>,
+    #[prost(enumeration = "AggregationTemporality", tag = "2")]
+    aggregation_temporality: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ExponentialHistogram {
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<ExponentialHistogramDataPoint>,
+    #[prost(enumeration = "AggregationTemporality", tag = "2")]
+    aggregation_temporality: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Summary {
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<SummaryDataPoint>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct NumberDataPoint {
+    #[prost(message, repeated, tag = "7")]
+    attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "3")]
+    time_unix_nano: u64,
+    #[prost(oneof = "NumberValue", tags = "4, 6")]
+    value: Option<NumberValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum NumberValue {
+    #[prost(double, tag = "4")]
+    AsDouble(f64),
+    #[prost(sfixed64, tag = "6")]
+    AsInt(i64),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct HistogramDataPoint {
+    #[prost(message, repeated, tag = "9")]
+    attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "3")]
+    time_unix_nano: u64,
+    #[prost(fixed64, tag = "4")]
+    count: u64,
+    #[prost(double, optional, tag = "5")]
+    sum: Option<f64>,
+    #[prost(fixed64, repeated, tag = "6")]
+    bucket_counts: Vec<u64>,
+    #[prost(double, repeated, tag = "7")]
+    explicit_bounds: Vec<f64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ExponentialHistogramDataPoint {
+    #[prost(message, repeated, tag = "1")]
+    attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "3")]
+    time_unix_nano: u64,
+    #[prost(sint32, tag = "6")]
+    scale: i32,
+    #[prost(fixed64, tag = "7")]
+    zero_count: u64,
+    #[prost(message, optional, tag = "8")]
+    positive: Option<Buckets>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Buckets {
+    #[prost(sint32, tag = "1")]
+    offset: i32,
+    #[prost(uint64, repeated, tag = "2")]
+    bucket_counts: Vec<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct SummaryDataPoint {
+    #[prost(message, repeated, tag = "7")]
+    attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "3")]
+    time_unix_nano: u64,
+    #[prost(fixed64, tag = "4")]
+    count: u64,
+    #[prost(double, tag = "5")]
+    sum: f64,
+    #[prost(message, repeated, tag = "6")]
+    quantile_values: Vec<ValueAtQuantile>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ValueAtQuantile {
+    #[prost(double, tag = "1")]
+    quantile: f64,
+    #[prost(double, tag = "2")]
+    value: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct KeyValue {
+    #[prost(string, tag = "1")]
+    key: String,
+    #[prost(message, optional, tag = "2")]
+    value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct AnyValue {
+    #[prost(oneof = "Value", tags = "1, 2, 3, 4")]
+    value: Option<Value>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum Value {
+    #[prost(string, tag = "1")]
+    String(String),
+    #[prost(bool, tag = "2")]
+    Bool(bool),
+    #[prost(int64, tag = "3")]
+    Int(i64),
+    #[prost(double, tag = "4")]
+    Double(f64),
+}
+
+/// Attributes as metadata. Array, map, and bytes values are not supported and
+/// are skipped.
+fn attributes(attributes: &[KeyValue]) -> impl Iterator<Item = (String, String)> + '_ {
+    attributes.iter().filter_map(|kv| {
+        let value = match kv.value.as_ref()?.value.as_ref()? {
+            Value::String(s) => s.clone(),
+            Value::Bool(b) => b.to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::Double(d) => d.to_string(),
+        };
+        Some((kv.key.clone(), value))
+    })
+}
+
+/// The metriken unit for a UCUM unit, as used by OpenTelemetry. Dimensionless
+/// units and annotations such as `{request}` have no equivalent.
+fn unit(unit: &str) -> Option<&str> {
+    match unit {
+        "" | "1" => None,
+        "ns" => Some("nanoseconds"),
+        "us" => Some("microseconds"),
+        "ms" => Some("milliseconds"),
+        "s" => Some("seconds"),
+        "bit" => Some("bits"),
+        "By" => Some("bytes"),
+        "%" => Some("percent"),
+        unit if unit.starts_with('{') => None,
+        unit => Some(unit),
+    }
+}
+
+/// Accumulates the metrics of a request into a snapshot.
+struct Converter {
+    builder: SnapshotBuilder,
+    latest: u64,
+}
+
+/// How a number is represented in the snapshot.
+#[derive(Clone, Copy)]
+enum Kind {
+    Counter,
+    DeltaCounter,
+    Gauge,
+}
+
+impl Kind {
+    /// The kind for a monotonic value with the provided temporality.
+    fn counter(temporality: i32) -> Self {
+        if temporality == AggregationTemporality::Delta as i32 {
+            Self::DeltaCounter
+        } else {
+            Self::Counter
+        }
+    }
+}
+
+impl Converter {
+    /// Add a metric to the snapshot. `metadata` holds the common metadata
+    /// for the metric and the data point attributes are added to it.
+    fn push(
+        mut self,
+        kind: Kind,
+        name: String,
+        metadata: &[(String, String)],
+        point: &[KeyValue],
+        extra: &[(&str, &str)],
+        value: f64,
+    ) -> Self {
+        let metadata: Vec<(String, String)> =
+            metadata.iter().cloned().chain(attributes(point)).collect();
+        let mut metadata: Vec<(&str, &str)> = metadata
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        metadata.extend_from_slice(extra);
+
+        let value = value.round();
+        if !value.is_finite() {
+            return self;
+        }
+
+        self.builder = match kind {
+            Kind::Counter if value >= 0.0 => self.builder.counter(name, value as u64, &metadata),
+            Kind::DeltaCounter if value >= 0.0 => {
+                metadata.push(("temporality", "delta"));
+                self.builder.delta_counter(name, value as u64, &metadata)
+            }
+            Kind::Gauge => self.builder.gauge(name, value as i64, &metadata),
+            _ => self.builder,
+        };
+        self
+    }
+
+    /// Record the time of a data point, so the snapshot takes the time of
+    /// the most recent one.
+    fn time(&mut self, time_unix_nano: u64) {
+        self.latest = self.latest.max(time_unix_nano);
+    }
+
+    /// Add the data points of a metric to the snapshot.
+    fn metric(mut self, metric: &Metric, resource: &[(String, String)]) -> Result<Self, OtlpError> {
+        let mut metadata = resource.to_vec();
+        if !metric.description.is_empty() {
+            metadata.push(("description".to_string(), metric.description.clone()));
+        }
+        if let Some(unit) = unit(&metric.unit) {
+            metadata.push(("unit".to_string(), unit.to_string()));
+        }
+
+        let name = &metric.name;
+        match &metric.data {
+            Some(Data::Gauge(gauge)) => {
+                for point in &gauge.data_points {
+                    self.time(point.time_unix_nano);
+                    self = self.push(
+                        Kind::Gauge,
+                        name.clone(),
+                        &metadata,
+                        &point.attributes,
+                        &[],
+                        number(point),
+                    );
+                }
+            }
+            Some(Data::Sum(sum)) => {
+                let kind = match sum.is_monotonic {
+                    true => Kind::counter(sum.aggregation_temporality),
+                    false => Kind::Gauge,
+                };
+                for point in &sum.data_points {
+                    self.time(point.time_unix_nano);
+                    self = self.push(
+                        kind,
+                        name.clone(),
+                        &metadata,
+                        &point.attributes,
+                        &[],
+                        number(point),
+                    );
+                }
+            }
+            Some(Data::Histogram(histogram)) => {
+                let kind = Kind::counter(histogram.aggregation_temporality);
+                for point in &histogram.data_points {
+                    self.time(point.time_unix_nano);
+
+                    let mut total = 0;
+                    for (idx, count) in point.bucket_counts.iter().enumerate() {
+                        total += count;
+                        let le = point
+                            .explicit_bounds
+                            .get(idx)
+                            .map(|le| le.to_string())
+                            .unwrap_or_else(|| "+Inf".to_string());
+                        self = self.push(
+                            kind,
+                            format!("{name}_bucket"),
+                            &metadata,
+                            &point.attributes,
+                            &[("le", &le)],
+                            total as f64,
+                        );
+                    }
+                    if let Some(sum) = point.sum {
+                        self = self.push(
+                            kind,
+                            format!("{name}_sum"),
+                            &metadata,
+                            &point.attributes,
+                            &[],
+                            sum,
+                        );
+                    }
+                    self = self.push(
+                        kind,
+                        format!("{name}_count"),
+                        &metadata,
+                        &point.attributes,
+                        &[],
+                        point.count as f64,
+                    );
+                }
+            }
+            Some(Data::ExponentialHistogram(histogram)) => {
+                let delta =
+                    histogram.aggregation_temporality == AggregationTemporality::Delta as i32;
+                for point in &histogram.data_points {
+                    self.time(point.time_unix_nano);
+
+                    let positive = point.positive.clone().unwrap_or_default();
+                    let value = Exponential {
+                        scale: point.scale,
+                        zero_count: point.zero_count,
+                        offset: positive.offset,
+                        counts: positive.bucket_counts,
+                    }
+                    .to_histogram()?;
+
+                    let mut metadata: Vec<(String, String)> = metadata
+                        .iter()
+                        .cloned()
+                        .chain(attributes(&point.attributes))
+                        .collect();
+                    if delta {
+                        metadata.push(("temporality".to_string(), "delta".to_string()));
+                    }
+                    let metadata: Vec<(&str, &str)> = metadata
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.as_str()))
+                        .collect();
+                    self.builder = match delta {
+                        true => self.builder.delta_histogram(name.clone(), value, &metadata),
+                        false => self.builder.histogram(name.clone(), value, &metadata),
+                    };
+                }
+            }
+            Some(Data::Summary(summary)) => {
+                for point in &summary.data_points {
+                    self.time(point.time_unix_nano);
+
+                    for quantile in &point.quantile_values {
+                        let q = quantile.quantile.to_string();
+                        self = self.push(
+                            Kind::Gauge,
+                            name.clone(),
+                            &metadata,
+                            &point.attributes,
+                            &[("quantile", &q)],
+                            quantile.value,
+                        );
+                    }
+                    self = self.push(
+                        Kind::Counter,
+                        format!("{name}_sum"),
+                        &metadata,
+                        &point.attributes,
+                        &[],
+                        point.sum,
+                    );
+                    self = self.push(
+                        Kind::Counter,
+                        format!("{name}_count"),
+                        &metadata,
+                        &point.attributes,
+                        &[],
+                        point.count as f64,
+                    );
+                }
+            }
+            None => {}
+        }
+
+        Ok(self)
+    }
+}
+
+impl Snapshot {
+    /// Convert an OTLP `ExportMetricsServiceRequest`, the body of an
+    /// OTLP/HTTP protobuf export, into a snapshot.
+    ///
+    /// When every metric comes from the same resource, its attributes become
+    /// the resource attributes of the snapshot, stored under the
+    /// [`crate::RESOURCE`] metadata prefix. Otherwise resource attributes
+    /// become metric metadata so the metrics of each resource stay distinct.
+    /// Data point attributes become metric metadata, and the metric
+    /// description and unit are stored in the `description` and `unit`
+    /// metadata keys. The snapshot has the time of the most recent
+    /// data point.
+    ///
+    /// - Monotonic sums become counters, or delta counters for delta
+    ///   temporality. Other sums and gauges become gauges.
+    /// - Exponential histograms become histograms using
+    ///   [`crate::ExponentialHistogram::to_histogram`]. Negative buckets are
+    ///   dropped since metriken histograms only hold non-negative values.
+    /// - Explicit bucket histograms and summaries cannot be represented as
+    ///   histograms. They become `_bucket` (with an `le` label), `_sum`, and
+    ///   `_count` counters and `quantile` gauges, as in the Prometheus format.
+    ///
+    /// Snapshot values are integers, so values are rounded to the nearest
+    /// integer and values which cannot be represented are dropped. Delta
+    /// metrics are marked with the `temporality` metadata key, and a
+    /// [`crate::TemporalityConverter`] can sum them into running totals.
+    pub fn from_otlp(bytes: &[u8]) -> Result<Snapshot, OtlpError> {
+        let request = ExportMetricsServiceRequest::decode(bytes)?;
+
+        let mut converter = Converter {
+            builder: Snapshot::builder(),
+            latest: 0,
+        };
+
+        let resources: Vec<Vec<(String, String)>> = request
+            .resource_metrics
+            .iter()
+            .map(|resource| {
+                resource
+                    .resource
+                    .as_ref()
+                    .map(|r| attributes(&r.attributes).collect())
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let shared = resources.windows(2).all(|w| w[0] == w[1]);
+        if shared {
+            for (key, value) in resources.first().into_iter().flatten() {
+                converter.builder = converter.builder.resource(key, value);
+            }
+        }
+
+        for (resource, resource_attributes) in request.resource_metrics.iter().zip(&resources) {
+            let resource_attributes = if shared {
+                &[]
+            } else {
+                &resource_attributes[..]
+            };
+            for metric in resource.scope_metrics.iter().flat_map(|s| &s.metrics) {
+                converter = converter.metric(metric, resource_attributes)?;
+            }
+        }
+
+        let mut snapshot = converter.builder.build()?;
+        if converter.latest > 0 {
+            snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_nanos(converter.latest);
+        }
+        Ok(snapshot)
+    }
+}
+
+/// The value of a number data point.
+fn number(point: &NumberDataPoint) -> f64 {
+    match point.value {
+        Some(NumberValue::AsDouble(value)) => value,
+        Some(NumberValue::AsInt(value)) => value as f64,
+        None => f64::NAN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Temporality, TemporalityConverter};
+
+    fn attribute(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(Value::String(value.to_string())),
+            }),
+        }
+    }
+
+    fn metric(name: &str, unit: &str, data: Data) -> Metric {
+        Metric {
+            name: name.to_string(),
+            description: String::new(),
+            unit: unit.to_string(),
+            data: Some(data),
+        }
+    }
+
+    fn number_point(value: NumberValue, time_unix_nano: u64) -> NumberDataPoint {
+        NumberDataPoint {
+            attributes: vec![attribute("host", "a")],
+            time_unix_nano,
+            value: Some(value),
+        }
+    }
+
+    fn request(metrics: Vec<Metric>) -> Vec<u8> {
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Resource {
+                    attributes: vec![attribute("service.name", "api")],
+                }),
+                scope_metrics: vec![ScopeMetrics { metrics }],
+            }],
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn numbers() {
+        let bytes = request(vec![
+            metric(
+                "requests",
+                "{request}",
+                Data::Sum(Sum {
+                    data_points: vec![number_point(NumberValue::AsInt(10), 2_000)],
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                    is_monotonic: true,
+                }),
+            ),
+            metric(
+                "errors",
+                "",
+                Data::Sum(Sum {
+                    data_points: vec![number_point(NumberValue::AsInt(2), 1_000)],
+                    aggregation_temporality: AggregationTemporality::Delta as i32,
+                    is_monotonic: true,
+                }),
+            ),
+            metric(
+                "memory",
+                "By",
+                Data::Gauge(Gauge {
+                    data_points: vec![number_point(NumberValue::AsDouble(1.6), 1_000)],
+                }),
+            ),
+        ]);
+
+        let snapshot = Snapshot::from_otlp(&bytes).unwrap();
+        assert_eq!(
+            snapshot.systemtime(),
+            SystemTime::UNIX_EPOCH + Duration::from_nanos(2_000)
+        );
+
+        let errors = &snapshot.counters()[0];
+        assert_eq!(errors.name, "errors");
+        assert_eq!(errors.metric_type, crate::MetricType::DeltaCounter);
+        assert_eq!(errors.metadata["temporality"], "delta");
+
+        let requests = &snapshot.counters()[1];
+        assert_eq!(requests.value, 10);
+        assert!(!requests.metadata.contains_key("service.name"));
+        assert_eq!(snapshot.resource(), [("service.name", "api")]);
+        assert_eq!(requests.metadata["host"], "a");
+        assert!(requests.unit().is_none());
+
+        let memory = &snapshot.gauges()[0];
+        assert_eq!(memory.value, 2);
+        assert_eq!(memory.unit(), Some(metriken_core::Unit::Bytes));
+    }
+
+    #[test]
+    fn resources() {
+        let resource = |name: &str| ResourceMetrics {
+            resource: Some(Resource {
+                attributes: vec![attribute("service.name", name)],
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                metrics: vec![metric(
+                    "memory",
+                    "",
+                    Data::Gauge(Gauge {
+                        data_points: vec![number_point(NumberValue::AsInt(1), 1_000)],
+                    }),
+                )],
+            }],
+        };
+
+        // metrics from different resources keep the resource in their metadata
+        let bytes = ExportMetricsServiceRequest {
+            resource_metrics: vec![resource("api"), resource("worker")],
+        }
+        .encode_to_vec();
+
+        let snapshot = Snapshot::from_otlp(&bytes).unwrap();
+        assert!(snapshot.resource().is_empty());
+        let services: Vec<&str> = snapshot
+            .gauges()
+            .iter()
+            .map(|g| g.metadata["service.name"].as_str())
+            .collect();
+        assert_eq!(services, ["api", "worker"]);
+    }
+
+    #[test]
+    fn delta_histograms() {
+        let mut source = histogram::Histogram::new(3, 20).unwrap();
+        source.add(1000, 5).unwrap();
+        let exponential = crate::ExponentialHistogram::from_histogram(&source);
+
+        let bytes = request(vec![metric(
+            "latency",
+            "ns",
+            Data::ExponentialHistogram(ExponentialHistogram {
+                data_points: vec![ExponentialHistogramDataPoint {
+                    attributes: vec![],
+                    time_unix_nano: 1,
+                    scale: exponential.scale,
+                    zero_count: exponential.zero_count,
+                    positive: Some(Buckets {
+                        offset: exponential.offset,
+                        bucket_counts: exponential.counts.clone(),
+                    }),
+                }],
+                aggregation_temporality: AggregationTemporality::Delta as i32,
+            }),
+        )]);
+
+        let count =
+            |snapshot: &Snapshot| -> u64 { snapshot.histograms()[0].value.as_slice().iter().sum() };
+
+        let mut cumulative = TemporalityConverter::new(Temporality::Cumulative);
+        let mut delta = TemporalityConverter::new(Temporality::Delta);
+        for total in [5, 10, 15] {
+            let snapshot = Snapshot::from_otlp(&bytes).unwrap();
+            let histogram = &snapshot.histograms()[0];
+            assert_eq!(histogram.metric_type, crate::MetricType::DeltaHistogram);
+            assert_eq!(histogram.metadata["temporality"], "delta");
+
+            // deltas are passed through when converting to deltas
+            let converted = delta.convert(snapshot.clone());
+            assert_eq!(count(&converted), 5);
+
+            // and summed when converting to running totals
+            let converted = cumulative.convert(snapshot);
+            assert_eq!(count(&converted), total);
+            assert!(!converted.histograms()[0]
+                .metadata
+                .contains_key("temporality"));
+        }
+    }
+
+    #[test]
+    fn histograms() {
+        let mut source = histogram::Histogram::new(3, 20).unwrap();
+        source.add(0, 1).unwrap();
+        source.add(1000, 5).unwrap();
+        let exponential = crate::ExponentialHistogram::from_histogram(&source);
+
+        let bytes = request(vec![
+            metric(
+                "latency",
+                "ns",
+                Data::ExponentialHistogram(ExponentialHistogram {
+                    data_points: vec![ExponentialHistogramDataPoint {
+                        attributes: vec![],
+                        time_unix_nano: 1,
+                        scale: exponential.scale,
+                        zero_count: exponential.zero_count,
+                        positive: Some(Buckets {
+                            offset: exponential.offset,
+                            bucket_counts: exponential.counts.clone(),
+                        }),
+                    }],
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                }),
+            ),
+            metric(
+                "size",
+                "",
+                Data::Histogram(Histogram {
+                    data_points: vec![HistogramDataPoint {
+                        attributes: vec![],
+                        time_unix_nano: 1,
+                        count: 3,
+                        sum: Some(12.0),
+                        bucket_counts: vec![1, 2],
+                        explicit_bounds: vec![2.5],
+                    }],
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                }),
+            ),
+        ]);
+
+        let snapshot = Snapshot::from_otlp(&bytes).unwrap();
+
+        let latency = &snapshot.histograms()[0];
+        assert_eq!(latency.unit(), Some(metriken_core::Unit::Nanoseconds));
+        assert_eq!(latency.value.config().grouping_power(), 3);
+        let counts: Vec<(u64, u64)> = latency
+            .value
+            .into_iter()
+            .filter(|b| b.count() > 0)
+            .map(|b| (b.start(), b.count()))
+            .collect();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[0], (0, 1));
+        assert_eq!(counts[1].1, 5);
+        assert!(counts[1].0.abs_diff(1000) < 1000 / 8);
+
+        let counters: Vec<(&str, Option<&str>, u64)> = snapshot
+            .counters()
+            .iter()
+            .map(|c| {
+                (
+                    c.name.as_str(),
+                    c.metadata.get("le").map(|s| s.as_str()),
+                    c.value,
+                )
+            })
+            .collect();
+        assert_eq!(
+            counters,
+            [
+                ("size_bucket", Some("+Inf"), 3),
+                ("size_bucket", Some("2.5"), 1),
+                ("size_count", None, 3),
+                ("size_sum", None, 12),
+            ]
+        );
+    }
+}
@@ -1,16 +1,19 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::Write;
 use std::sync::Arc;
 
 use arrow::array::*;
+use arrow::buffer::OffsetBuffer;
 use arrow::datatypes::*;
 use parquet::arrow::ArrowWriter;
 use parquet::basic::{Compression, ZstdLevel};
 use parquet::errors::ParquetError;
+use parquet::file::metadata::RowGroupMetaDataPtr;
 use parquet::file::properties::WriterProperties;
 use parquet::format::{FileMetaData, KeyValue};
 
-use crate::snapshot::{HashedSnapshot, Snapshot};
+use crate::rebucket::{common_config, rebucket};
+use crate::snapshot::{Event, HashedSnapshot, Histogram, MetricType, Snapshot};
 
 /// The batch size (or maximum row group size) is the number of rows that
 /// the `ArrowWriter` caches in memory before attempting to write them to
@@ -27,6 +30,26 @@ use crate::snapshot::{HashedSnapshot, Snapshot};
 /// too large for histograms, so pick a more conservative default.
 const DEFAULT_MAX_BATCH_SIZE: usize = 50_000;
 
+/// The column suffixes, in schema order, used for each stats metric.
+pub(crate) const STATS_FIELDS: [&str; 4] = ["min", "max", "sum", "count"];
+
+/// The column metadata key holding the name of the metric stored in a column
+/// which was renamed by a [`ParquetColumnNaming`].
+pub(crate) const METRIC_NAME: &str = "metric_name";
+
+/// The file metadata key holding the mapping from column names to metric
+/// names, with one `column\tmetric` line for each renamed metric.
+pub(crate) const COLUMN_NAMES: &str = "metriken:column_names";
+
+/// The file metadata key holding the metadata of the metric in each column,
+/// as a JSON object keyed by column name. This lets queries recover the
+/// labels and units of metrics without the arrow schema.
+pub(crate) const COLUMN_METADATA: &str = "metriken:column_metadata";
+
+/// The percentiles written to the summary table for each histogram, with
+/// the names of their columns.
+const SUMMARY_PERCENTILES: [(f64, &str); 3] = [(50.0, "p50"), (90.0, "p90"), (99.0, "p99")];
+
 #[derive(Clone, Debug)]
 pub struct ParquetCompression {
     inner: Compression,
@@ -64,6 +87,93 @@ pub enum ParquetHistogramType {
     Sparse,
 }
 
+/// How metric names are turned into parquet column names, for query engines
+/// which do not accept the names of metrics as they are. By default names
+/// are used without changes.
+///
+/// Metrics whose names become the same are given distinct columns by adding
+/// `_2`, `_3`, and so on to the names, in the order of the original names, so
+/// the same set of metrics always gets the same columns. Renamed columns
+/// record the name of their metric in their `metric_name` metadata, and the
+/// file metadata holds a manifest of every renamed column.
+#[derive(Clone, Debug, Default)]
+pub struct ParquetColumnNaming {
+    replace_slashes: bool,
+    lowercase: bool,
+    max_length: Option<usize>,
+}
+
+impl ParquetColumnNaming {
+    /// Create a new naming policy which uses names without changes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace each `/` in names with `_`.
+    pub fn replace_slashes(mut self, enabled: bool) -> Self {
+        self.replace_slashes = enabled;
+        self
+    }
+
+    /// Convert names to lowercase.
+    pub fn lowercase(mut self, enabled: bool) -> Self {
+        self.lowercase = enabled;
+        self
+    }
+
+    /// Truncate names to at most this many characters. This applies to the
+    /// part of the column name from the metric name, and not to suffixes
+    /// such as `:buckets`.
+    pub fn max_length(mut self, length: usize) -> Self {
+        self.max_length = Some(length);
+        self
+    }
+
+    fn apply(&self, name: &str) -> String {
+        let mut name = name.to_string();
+        if self.replace_slashes {
+            name = name.replace('/', "_");
+        }
+        if self.lowercase {
+            name = name.to_lowercase();
+        }
+        self.truncate(name, 0)
+    }
+
+    /// Truncate a name so that there is room for a suffix of `reserve`
+    /// characters.
+    fn truncate(&self, name: String, reserve: usize) -> String {
+        match self.max_length {
+            Some(max) if name.chars().count() + reserve > max => {
+                name.chars().take(max.saturating_sub(reserve)).collect()
+            }
+            _ => name,
+        }
+    }
+
+    /// Assign a distinct column name to each of the metric names.
+    fn columns<'a>(&self, names: impl IntoIterator<Item = &'a String>) -> HashMap<String, String> {
+        let names: BTreeSet<&String> = names.into_iter().collect();
+        let mut used = HashSet::new();
+        let mut columns = HashMap::with_capacity(names.len());
+
+        for name in names {
+            let base = self.apply(name);
+            let mut column = base.clone();
+            let mut n = 1;
+            while used.contains(&column) {
+                n += 1;
+                let suffix = format!("_{n}");
+                column = self.truncate(base.clone(), suffix.len()) + &suffix;
+            }
+            used.insert(column.clone());
+            columns.insert(name.clone(), column);
+        }
+
+        columns
+    }
+}
+
 /// Options for `ParquetWriter` controlling the output parquet file.
 #[derive(Clone, Debug)]
 pub struct ParquetOptions {
@@ -73,6 +183,11 @@ pub struct ParquetOptions {
     max_batch_size: usize,
     /// Type of representation used to store histograms
     histogram_type: ParquetHistogramType,
+    /// How metric names are turned into column names
+    column_naming: ParquetColumnNaming,
+    /// The memory used by a row group being written, in bytes, after which
+    /// it is flushed
+    memory_budget: Option<usize>,
 }
 
 impl ParquetOptions {
@@ -103,6 +218,25 @@ impl ParquetOptions {
         self.histogram_type = histogram;
         self
     }
+
+    /// Sets the amount of memory, in bytes, that the row group being written
+    /// may use before it is written to the file, even if it has fewer rows
+    /// than the maximum batch size. Snapshots are written one at a time, so
+    /// this bounds the memory used to convert a recording of any length.
+    /// Rows with large histograms can use a lot of memory, so setting this
+    /// is recommended when converting in a memory-constrained environment.
+    /// Smaller row groups compress less well. There is no budget by default.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Sets how metric names are turned into column names. The default is
+    /// to use the names without changes.
+    pub fn column_naming(mut self, naming: ParquetColumnNaming) -> Self {
+        self.column_naming = naming;
+        self
+    }
 }
 
 impl Default for ParquetOptions {
@@ -111,6 +245,8 @@ impl Default for ParquetOptions {
             compression: Default::default(),
             max_batch_size: DEFAULT_MAX_BATCH_SIZE,
             histogram_type: ParquetHistogramType::Standard,
+            column_naming: ParquetColumnNaming::default(),
+            memory_budget: None,
         }
     }
 }
@@ -144,6 +280,9 @@ pub struct ParquetSchema {
     counters: BTreeMap<String, HashMap<String, String>>,
     gauges: BTreeMap<String, HashMap<String, String>>,
     histograms: BTreeMap<String, HashMap<String, String>>,
+    histogram_configs: HashMap<String, histogram::Config>,
+    stats: BTreeMap<String, HashMap<String, String>>,
+    events: bool,
     metadata: HashMap<String, String>,
     rows: usize,
 }
@@ -154,6 +293,9 @@ impl ParquetSchema {
             counters: BTreeMap::new(),
             gauges: BTreeMap::new(),
             histograms: BTreeMap::new(),
+            histogram_configs: HashMap::new(),
+            stats: BTreeMap::new(),
+            events: false,
             metadata: HashMap::new(),
             rows: 0,
         }
@@ -175,11 +317,30 @@ impl ParquetSchema {
         }
 
         for histogram in histograms {
-            self.histograms
-                .entry(histogram.name)
-                .or_insert(histogram.metadata);
+            // The configuration of a histogram may change between snapshots,
+            // such as across a deploy. The column uses a configuration which
+            // can hold every version and histograms are rebucketed into it.
+            let config = histogram.value.config();
+            self.histogram_configs
+                .entry(histogram.name.clone())
+                .and_modify(|c| *c = common_config(*c, config))
+                .or_insert(config);
+
+            // the column metadata records that the histogram holds deltas,
+            // so that it is read back as a delta histogram
+            let mut metadata = histogram.metadata;
+            if histogram.metric_type == MetricType::DeltaHistogram {
+                metadata.insert("temporality".to_string(), "delta".to_string());
+            }
+            self.histograms.entry(histogram.name).or_insert(metadata);
         }
 
+        for stats in snapshot.stats {
+            self.stats.entry(stats.name).or_insert(stats.metadata);
+        }
+
+        self.events |= !snapshot.events.is_empty();
+
         if self.metadata.is_empty() && !snapshot.metadata.is_empty() {
             self.metadata = snapshot.metadata;
         }
@@ -194,7 +355,10 @@ impl ParquetSchema {
         options: ParquetOptions,
     ) -> Result<ParquetWriter<impl Write + Send>, ParquetError> {
         let mut fields: Vec<Field> = Vec::with_capacity(
-            1 + self.counters.len() + self.gauges.len() + (self.histograms.len() * 3),
+            1 + self.counters.len()
+                + self.gauges.len()
+                + (self.histograms.len() * 3)
+                + (self.stats.len() * 4),
         );
 
         // Create one column for the timestamp
@@ -205,16 +369,45 @@ impl ParquetSchema {
             )])),
         );
 
+        let columns = options.column_naming.columns(
+            self.counters
+                .keys()
+                .chain(self.gauges.keys())
+                .chain(self.histograms.keys())
+                .chain(self.stats.keys()),
+        );
+        let mut renamed: Vec<(&String, &String)> = columns
+            .iter()
+            .filter(|(name, column)| name != column)
+            .collect();
+        renamed.sort_by_key(|(_, column)| *column);
+
+        // the metadata of the metric in each column, for the file metadata
+        let mut described: BTreeMap<String, HashMap<String, String>> = BTreeMap::new();
+
+        // the column for a metric, recording the original name of the metric
+        // in the metadata if it was renamed
+        let mut column = |name: &String, metadata: &mut HashMap<String, String>| -> String {
+            let column = columns[name].clone();
+            if column != *name {
+                metadata.insert(METRIC_NAME.to_string(), name.clone());
+            }
+            let mut description = metadata.clone();
+            description.insert(METRIC_NAME.to_string(), name.clone());
+            described.insert(column.clone(), description);
+            column
+        };
+
         let mut counters = Vec::with_capacity(self.counters.len());
 
         // Create one column field per-counter
         for (counter, mut metadata) in self.counters.into_iter() {
             // merge metric annotations into the metric metadata
             metadata.insert("metric_type".to_string(), "counter".to_string());
+            let name = column(&counter, &mut metadata);
 
             // add column to schema
-            fields
-                .push(Field::new(counter.clone(), DataType::UInt64, true).with_metadata(metadata));
+            fields.push(Field::new(name, DataType::UInt64, true).with_metadata(metadata));
 
             // initialize storage for the counter values
             counters.push(counter);
@@ -226,15 +419,17 @@ impl ParquetSchema {
         for (gauge, mut metadata) in self.gauges.into_iter() {
             // merge metric annotations into the metric metadata
             metadata.insert("metric_type".to_string(), "gauge".to_string());
+            let name = column(&gauge, &mut metadata);
 
             // add column to schema
-            fields.push(Field::new(gauge.clone(), DataType::Int64, true).with_metadata(metadata));
+            fields.push(Field::new(name, DataType::Int64, true).with_metadata(metadata));
 
             // initialize storage for the gauge values
             gauges.push(gauge);
         }
 
         let mut histograms = Vec::with_capacity(self.histograms.len());
+        let mut histogram_configs = Vec::with_capacity(self.histograms.len());
 
         // Create columns for the snapshot: the buckets are stored as a
         // nested list type where each list element is an array of `u64`s.
@@ -244,14 +439,28 @@ impl ParquetSchema {
         // representation, the non-zero bucket indices and counts are stored
         // in separate columns.
         for (histogram, mut metadata) in self.histograms.into_iter() {
+            let config = self.histogram_configs[&histogram];
+            metadata.insert(
+                "grouping_power".to_string(),
+                config.grouping_power().to_string(),
+            );
+            metadata.insert(
+                "max_value_power".to_string(),
+                config.max_value_power().to_string(),
+            );
+            // merge metric annotations into the metric metadata
+            let metric_type = match options.histogram_type {
+                ParquetHistogramType::Standard => "histogram",
+                ParquetHistogramType::Sparse => "sparse_histogram",
+            };
+            metadata.insert("metric_type".to_string(), metric_type.to_string());
+            let name = column(&histogram, &mut metadata);
+
             match options.histogram_type {
                 ParquetHistogramType::Standard => {
-                    // merge metric annotations into the metric metadata
-                    metadata.insert("metric_type".to_string(), "histogram".to_string());
-
                     fields.push(
                         Field::new(
-                            format!("{histogram}:buckets"),
+                            format!("{name}:buckets"),
                             DataType::new_list(DataType::UInt64, true),
                             true,
                         )
@@ -259,12 +468,9 @@ impl ParquetSchema {
                     );
                 }
                 ParquetHistogramType::Sparse => {
-                    // merge metric annotations into the metric metadata
-                    metadata.insert("metric_type".to_string(), "sparse_histogram".to_string());
-
                     fields.push(
                         Field::new(
-                            format!("{histogram}:bucket_indices"),
+                            format!("{name}:bucket_indices"),
                             DataType::new_list(DataType::UInt64, true),
                             true,
                         )
@@ -272,7 +478,7 @@ impl ParquetSchema {
                     );
                     fields.push(
                         Field::new(
-                            format!("{histogram}:bucket_counts"),
+                            format!("{name}:bucket_counts"),
                             DataType::new_list(DataType::UInt64, true),
                             true,
                         )
@@ -283,21 +489,66 @@ impl ParquetSchema {
 
             // initialize storage for the histogram values
             histograms.push(histogram);
+            histogram_configs.push(config);
         }
 
-        let metadata: Option<Vec<KeyValue>> = if self.metadata.is_empty() {
-            None
-        } else {
-            Some(
-                self.metadata
-                    .into_iter()
-                    .map(|(key, value)| KeyValue {
-                        key,
-                        value: Some(value),
-                    })
-                    .collect(),
-            )
-        };
+        let mut stats = Vec::with_capacity(self.stats.len());
+
+        // Create one column per-field for each stats metric
+        for (name, mut metadata) in self.stats.into_iter() {
+            // merge metric annotations into the metric metadata
+            metadata.insert("metric_type".to_string(), "stats".to_string());
+            let column = column(&name, &mut metadata);
+
+            for field in STATS_FIELDS {
+                fields.push(
+                    Field::new(format!("{column}:{field}"), DataType::UInt64, true)
+                        .with_metadata(metadata.clone()),
+                );
+            }
+
+            // initialize storage for the stats values
+            stats.push(name);
+        }
+
+        // Create a single column holding the list of events for each
+        // snapshot, only if any snapshot has events
+        if self.events {
+            fields.push(
+                Field::new("events", DataType::List(event_item()), false).with_metadata(
+                    HashMap::from([("metric_type".to_owned(), "events".to_owned())]),
+                ),
+            );
+        }
+
+        let mut metadata: Vec<KeyValue> = self
+            .metadata
+            .into_iter()
+            .map(|(key, value)| KeyValue {
+                key,
+                value: Some(value),
+            })
+            .collect();
+
+        if !described.is_empty() {
+            metadata.push(KeyValue {
+                key: COLUMN_METADATA.to_string(),
+                value: Some(json_object(&described)),
+            });
+        }
+
+        if !renamed.is_empty() {
+            let manifest: Vec<String> = renamed
+                .into_iter()
+                .map(|(name, column)| format!("{column}\t{name}"))
+                .collect();
+            metadata.push(KeyValue {
+                key: COLUMN_NAMES.to_string(),
+                value: Some(manifest.join("\n")),
+            });
+        }
+
+        let metadata = (!metadata.is_empty()).then_some(metadata);
 
         let schema = Arc::new(Schema::new(fields));
         let props = WriterProperties::builder()
@@ -309,27 +560,118 @@ impl ParquetSchema {
 
         Ok(ParquetWriter {
             writer: arrow_writer,
+            encoder: Arc::new(RowEncoder {
+                schema,
+                histogram_type: options.histogram_type,
+                counters,
+                gauges,
+                histograms,
+                histogram_configs,
+                stats,
+                events: self.events,
+            }),
             options,
-            schema,
-            counters,
-            gauges,
-            histograms,
+            summary: None,
+            on_write: None,
         })
     }
 }
 
+/// Encode the metadata of each column as a JSON object, with the keys of each
+/// column sorted so that the output is deterministic.
+fn json_object(columns: &BTreeMap<String, HashMap<String, String>>) -> String {
+    let mut out = String::from("{");
+    for (i, (column, metadata)) in columns.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        json_string(&mut out, column);
+        out.push_str(":{");
+
+        let metadata: BTreeMap<&String, &String> = metadata.iter().collect();
+        for (j, (key, value)) in metadata.into_iter().enumerate() {
+            if j > 0 {
+                out.push(',');
+            }
+            json_string(&mut out, key);
+            out.push(':');
+            json_string(&mut out, value);
+        }
+        out.push('}');
+    }
+    out.push('}');
+    out
+}
+
+/// Append a string to the output as a quoted and escaped JSON string.
+fn json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// The key and value of each entry in the metadata of an event.
+fn event_metadata_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("keys", DataType::Utf8, false),
+        Field::new("values", DataType::Utf8, false),
+    ])
+}
+
+/// The entries of the metadata map of each event.
+fn event_metadata_entries() -> FieldRef {
+    Arc::new(Field::new(
+        "entries",
+        DataType::Struct(event_metadata_fields()),
+        false,
+    ))
+}
+
+/// The fields of each event: its time, name, and metadata.
+fn event_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new(
+            "metadata",
+            DataType::Map(event_metadata_entries(), false),
+            false,
+        ),
+    ])
+}
+
+/// The list item of the `events` column.
+fn event_item() -> FieldRef {
+    Arc::new(Field::new("item", DataType::Struct(event_fields()), false))
+}
+
 pub struct ParquetWriter<W: Write + Send> {
-    /// Writer, options, and schema of the parquet file
+    /// Writer and options of the parquet file
     writer: ArrowWriter<W>,
     options: ParquetOptions,
-    schema: Arc<Schema>,
-
-    /// Schema-ordered list of counters, gauges, and histograms
-    counters: Vec<String>,
-    gauges: Vec<String>,
-    histograms: Vec<String>,
+    /// Converts snapshots into rows of the file
+    encoder: Arc<RowEncoder>,
+    /// The writer of the summary table, if enabled
+    summary: Option<SummaryWriter>,
+    /// Called after each row is written, such as to record checkpoints
+    on_write: Option<WriteHook>,
 }
 
+/// A function which is passed the row groups flushed so far and the bytes
+/// written by a `ParquetWriter`.
+pub(crate) type WriteHook =
+    Box<dyn FnMut(&[RowGroupMetaDataPtr], usize) -> Result<(), ParquetError> + Send>;
+
 impl<W: Write + Send> ParquetWriter<W> {
     /// Process individual snapshots of metrics and store them in a columnar
     /// representation. Fill in the gaps for missing data, i.e., missing or
@@ -337,34 +679,164 @@ impl<W: Write + Send> ParquetWriter<W> {
     /// Writes them to the ArrowWriter, which internally buffers batches until
     /// the maximum row group size is reached.
     pub fn push(&mut self, snapshot: Snapshot) -> Result<(), ParquetError> {
+        let row = self.encoder.encode(snapshot, self.summary.is_some())?;
+        self.write_row(row)
+    }
+
+    /// The encoder for the rows of this file, which can be shared with other
+    /// threads to convert snapshots in parallel.
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    pub(crate) fn encoder(&self) -> Arc<RowEncoder> {
+        self.encoder.clone()
+    }
+
+    /// The arrow schema of the file.
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    pub(crate) fn schema(&self) -> &Schema {
+        &self.encoder.schema
+    }
+
+    /// Returns `true` if a summary table is being written, so rows must
+    /// keep their histograms.
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    pub(crate) fn has_summary(&self) -> bool {
+        self.summary.is_some()
+    }
+
+    /// Write a row produced by the encoder of this file. Rows must be written
+    /// in order.
+    pub(crate) fn write_row(&mut self, row: EncodedRow) -> Result<(), ParquetError> {
+        if let Some(summary) = &mut self.summary {
+            summary.push(row.ts, &row.histograms)?;
+        }
+
+        self.writer.write(&row.batch)?;
+
+        if self
+            .options
+            .memory_budget
+            .is_some_and(|budget| self.writer.in_progress_size() >= budget)
+        {
+            self.writer.flush()?;
+        }
+
+        if let Some(on_write) = &mut self.on_write {
+            on_write(
+                self.writer.flushed_row_groups(),
+                self.writer.bytes_written(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Call the provided function after each row is written.
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    pub(crate) fn on_write(mut self, hook: WriteHook) -> Self {
+        self.on_write = Some(hook);
+        self
+    }
+
+    /// Also write a summary table to a second parquet file, with a row for
+    /// each histogram in each snapshot holding the `p50`, `p90`, `p99`, and
+    /// `max` of the values recorded over the interval since the previous
+    /// snapshot, along with their `count` and `rate` per second. This must
+    /// be called before any snapshots are pushed.
+    ///
+    /// The summary is much cheaper to query than the full buckets in the
+    /// main file, such as for dashboards, at the cost of only holding a few
+    /// fixed percentiles. Percentiles and the maximum are the upper bounds of
+    /// the buckets they fall in, and are null for intervals with no values.
+    /// Histograms which are cumulative have no row for the first snapshot
+    /// they appear in, since the interval is unknown, while delta histograms
+    /// are summarized as they are.
+    pub fn with_summary(
+        mut self,
+        writer: impl Write + Send + 'static,
+    ) -> Result<Self, ParquetError> {
+        self.summary = Some(SummaryWriter::new(Box::new(writer), &self.options)?);
+        Ok(self)
+    }
+
+    /// The number of bytes written to the parquet file so far, which does
+    /// not include the row group being buffered.
+    pub fn bytes_written(&self) -> usize {
+        self.writer.bytes_written()
+    }
+
+    /// Finish writing any buffered metrics and the parquet footer.
+    pub fn finalize(self) -> Result<FileMetaData, ParquetError> {
+        if let Some(summary) = self.summary {
+            summary.writer.close()?;
+        }
+        self.writer.close()
+    }
+}
+
+/// A snapshot converted into a row of a parquet file.
+pub(crate) struct EncodedRow {
+    ts: u64,
+    /// The histograms of the snapshot, if they are needed for the summary
+    histograms: HashMap<String, Histogram>,
+    batch: RecordBatch,
+}
+
+/// Converts snapshots into rows with the schema of a parquet file.
+pub(crate) struct RowEncoder {
+    schema: Arc<Schema>,
+    histogram_type: ParquetHistogramType,
+
+    /// Schema-ordered list of counters, gauges, histograms, and stats
+    counters: Vec<String>,
+    gauges: Vec<String>,
+    histograms: Vec<String>,
+    /// The configuration of each histogram column
+    histogram_configs: Vec<histogram::Config>,
+    stats: Vec<String>,
+    /// Whether the file has an events column
+    events: bool,
+}
+
+impl RowEncoder {
+    /// Convert a snapshot into a row, keeping its histograms if requested.
+    pub(crate) fn encode(
+        &self,
+        snapshot: Snapshot,
+        keep_histograms: bool,
+    ) -> Result<EncodedRow, ParquetError> {
         let mut columns: Vec<Arc<dyn Array>> = Vec::with_capacity(self.schema.fields().len());
 
         let mut hs: HashedSnapshot = HashedSnapshot::from(snapshot);
-
+        let histograms = match keep_histograms {
+            true => hs.histograms.clone(),
+            false => HashMap::new(),
+        };
         // Create a single element column for the timestamp
         columns.push(Arc::new(UInt64Array::from(vec![hs.ts])));
 
         // Create single element columns for metrics. Since `remove` returns
         // `None` if a metric in the schema does not exist in the snapshot gaps
         // are automatically filled without additional handling.
-        for counter in self.counters.iter_mut() {
+        for counter in self.counters.iter() {
             columns.push(Arc::new(UInt64Array::from(vec![hs
                 .counters
                 .remove(counter)
                 .map(|v| v.value)])));
         }
 
-        for gauge in self.gauges.iter_mut() {
+        for gauge in self.gauges.iter() {
             columns.push(Arc::new(Int64Array::from(vec![hs
                 .gauges
                 .remove(gauge)
                 .map(|v| v.value)])));
         }
 
-        for h in self.histograms.iter_mut() {
+        for (h, config) in self.histograms.iter().zip(&self.histogram_configs) {
             let histogram = hs.histograms.remove(h).map(|v| v.value);
             if let Some(hist) = histogram {
-                match self.options.histogram_type {
+                let hist = rebucket(&hist, config).map_err(|e| {
+                    ParquetError::General(format!("cannot convert histogram `{h}`: {e}"))
+                })?;
+                match self.histogram_type {
                     ParquetHistogramType::Standard => {
                         columns.push(Self::listu64_entry_from_slice(hist.as_slice()))
                     }
@@ -375,7 +847,7 @@ impl<W: Write + Send> ParquetWriter<W> {
                     }
                 };
             } else {
-                match self.options.histogram_type {
+                match self.histogram_type {
                     ParquetHistogramType::Standard => columns.push(Self::listu64_entry_null()),
                     ParquetHistogramType::Sparse => columns.append(&mut vec![
                         Self::listu64_entry_null(),
@@ -385,13 +857,32 @@ impl<W: Write + Send> ParquetWriter<W> {
             }
         }
 
-        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
-        self.writer.write(&batch)
-    }
+        for name in self.stats.iter() {
+            let stats = hs.stats.remove(name);
+            columns.push(Arc::new(UInt64Array::from(vec![stats
+                .as_ref()
+                .and_then(|v| v.min)])));
+            columns.push(Arc::new(UInt64Array::from(vec![stats
+                .as_ref()
+                .and_then(|v| v.max)])));
+            columns.push(Arc::new(UInt64Array::from(vec![stats
+                .as_ref()
+                .map(|v| v.sum)])));
+            columns.push(Arc::new(UInt64Array::from(vec![stats
+                .as_ref()
+                .map(|v| v.count)])));
+        }
 
-    /// Finish writing any buffered metrics and the parquet footer.
-    pub fn finalize(self) -> Result<FileMetaData, ParquetError> {
-        self.writer.close()
+        if self.events {
+            columns.push(Self::events_entry(&hs.events)?);
+        }
+
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        Ok(EncodedRow {
+            ts: hs.ts,
+            histograms,
+            batch,
+        })
     }
 
     /// Create a list entry for an arrow lists of u64s from a slice.
@@ -410,6 +901,55 @@ impl<W: Write + Send> ParquetWriter<W> {
         )]))
     }
 
+    /// Create a list entry holding the events of a snapshot.
+    fn events_entry(events: &[Event]) -> Result<Arc<ListArray>, ParquetError> {
+        let mut keys = StringBuilder::new();
+        let mut values = StringBuilder::new();
+        let mut offsets = vec![0];
+
+        for event in events {
+            let mut metadata: Vec<_> = event.metadata.iter().collect();
+            metadata.sort();
+            for (key, value) in metadata {
+                keys.append_value(key);
+                values.append_value(value);
+            }
+            offsets.push(keys.len() as i32);
+        }
+
+        let timestamps = UInt64Array::from_iter_values(events.iter().map(|e| {
+            e.systemtime
+                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64
+        }));
+        let names = StringArray::from_iter_values(events.iter().map(|e| e.name.as_str()));
+        let entries = StructArray::try_new(
+            event_metadata_fields(),
+            vec![Arc::new(keys.finish()), Arc::new(values.finish())],
+            None,
+        )?;
+        let metadata = MapArray::try_new(
+            event_metadata_entries(),
+            OffsetBuffer::new(offsets.into()),
+            entries,
+            None,
+            false,
+        )?;
+        let events = StructArray::try_new(
+            event_fields(),
+            vec![Arc::new(timestamps), Arc::new(names), Arc::new(metadata)],
+            None,
+        )?;
+
+        Ok(Arc::new(ListArray::try_new(
+            event_item(),
+            OffsetBuffer::from_lengths([events.len()]),
+            Arc::new(events),
+            None,
+        )?))
+    }
+
     /// Create a null list entry for an arrow lists of u64s.
     fn listu64_entry_null() -> Arc<ListArray> {
         Arc::new(ListArray::from_iter_primitive::<
@@ -420,6 +960,131 @@ impl<W: Write + Send> ParquetWriter<W> {
     }
 }
 
+/// Writes the per-interval summary of each histogram to a parquet file.
+struct SummaryWriter {
+    writer: ArrowWriter<Box<dyn Write + Send>>,
+    schema: Arc<Schema>,
+    /// The time and value of each cumulative histogram the last time it was
+    /// seen.
+    previous: HashMap<String, (u64, histogram::Histogram)>,
+    /// The time of the previous snapshot.
+    last: Option<u64>,
+}
+
+impl SummaryWriter {
+    fn new(writer: Box<dyn Write + Send>, options: &ParquetOptions) -> Result<Self, ParquetError> {
+        let mut fields = vec![
+            Field::new("timestamp", DataType::UInt64, false),
+            Field::new("metric", DataType::Utf8, false),
+        ];
+        for (_, name) in SUMMARY_PERCENTILES {
+            fields.push(Field::new(name, DataType::UInt64, true));
+        }
+        fields.push(Field::new("max", DataType::UInt64, true));
+        fields.push(Field::new("count", DataType::UInt64, false));
+        fields.push(Field::new("rate", DataType::Float64, true));
+
+        let schema = Arc::new(Schema::new(fields));
+        let props = WriterProperties::builder()
+            .set_compression(options.compression.inner)
+            .set_max_row_group_size(options.max_batch_size)
+            .build();
+
+        Ok(Self {
+            writer: ArrowWriter::try_new(writer, schema.clone(), Some(props))?,
+            schema,
+            previous: HashMap::new(),
+            last: None,
+        })
+    }
+
+    /// Write a row for each histogram in a snapshot taken at `ts`.
+    fn push(
+        &mut self,
+        ts: u64,
+        histograms: &HashMap<String, Histogram>,
+    ) -> Result<(), ParquetError> {
+        let mut names: Vec<&String> = histograms.keys().collect();
+        names.sort();
+
+        let mut metrics = StringBuilder::new();
+        let mut percentiles: Vec<UInt64Builder> = SUMMARY_PERCENTILES
+            .iter()
+            .map(|_| UInt64Builder::new())
+            .collect();
+        let mut maxes = UInt64Builder::new();
+        let mut counts = UInt64Builder::new();
+        let mut rates = Float64Builder::new();
+
+        for name in names {
+            let histogram = &histograms[name];
+
+            // the values recorded over the interval, and when it started
+            let (interval, start) = if histogram.metric_type == MetricType::DeltaHistogram {
+                (histogram.value.clone(), self.last)
+            } else {
+                let previous = self
+                    .previous
+                    .insert(name.clone(), (ts, histogram.value.clone()));
+                let Some((start, previous)) = previous else {
+                    continue;
+                };
+                // a histogram which went backwards was reset, so everything
+                // it holds was recorded during the interval
+                let interval = histogram
+                    .value
+                    .checked_sub(&previous)
+                    .unwrap_or_else(|_| histogram.value.clone());
+                (interval, Some(start))
+            };
+
+            let count: u64 = interval.as_slice().iter().sum();
+            let values = interval
+                .percentiles(&SUMMARY_PERCENTILES.map(|(p, _)| p))
+                .ok()
+                .flatten();
+            for (i, builder) in percentiles.iter_mut().enumerate() {
+                builder.append_option(values.as_ref().map(|v| v[i].1.end()));
+            }
+            maxes.append_option(
+                interval
+                    .into_iter()
+                    .filter(|b| b.count() > 0)
+                    .last()
+                    .map(|b| b.end()),
+            );
+            counts.append_value(count);
+            rates.append_option(
+                start
+                    .filter(|start| *start < ts)
+                    .map(|start| count as f64 * 1e9 / (ts - start) as f64),
+            );
+            metrics.append_value(name);
+        }
+
+        self.last = Some(ts);
+
+        if metrics.is_empty() {
+            return Ok(());
+        }
+
+        let rows = metrics.len();
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(vec![ts; rows])),
+            Arc::new(metrics.finish()),
+        ];
+        for mut builder in percentiles {
+            columns.push(Arc::new(builder.finish()));
+        }
+        columns.push(Arc::new(maxes.finish()));
+        columns.push(Arc::new(counts.finish()));
+        columns.push(Arc::new(rates.finish()));
+
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.write(&batch)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -429,7 +1094,7 @@ mod tests {
 
     use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
     use arrow::array::*;
-    use metriken::histogram::Histogram as H2Histogram;
+    use histogram::Histogram as H2Histogram;
 
     use crate::*;
 
@@ -440,19 +1105,36 @@ mod tests {
             metadata: HashMap::new(),
             counters: vec![Counter {
                 name: "counter".to_string(),
+                metric_type: MetricType::Counter,
                 value: 100,
                 metadata: HashMap::new(),
             }],
             gauges: vec![Gauge {
                 name: "gauge".to_string(),
+                metric_type: MetricType::Gauge,
                 value: 16,
                 metadata: HashMap::new(),
             }],
             histograms: vec![Histogram {
                 name: "histogram".to_string(),
+                metric_type: MetricType::Histogram,
                 value: h1,
                 metadata: HashMap::new(),
             }],
+            stats: vec![Stats {
+                name: "stats".to_string(),
+                metric_type: MetricType::Summary,
+                min: Some(3),
+                max: Some(9),
+                sum: 20,
+                count: 4,
+                metadata: HashMap::new(),
+            }],
+            sketches: Vec::new(),
+            cardinalities: Vec::new(),
+            top_k: Vec::new(),
+            events: Vec::new(),
+            ..Snapshot::new()
         };
 
         let h2 = H2Histogram::from_buckets(1, 3, vec![0, 1, 1, 0, 1, 0]).unwrap();
@@ -463,19 +1145,36 @@ mod tests {
             metadata: HashMap::new(),
             counters: vec![Counter {
                 name: "counter".to_string(),
+                metric_type: MetricType::Counter,
                 value: 121,
                 metadata: HashMap::new(),
             }],
             gauges: vec![Gauge {
                 name: "gauge".to_string(),
+                metric_type: MetricType::Gauge,
                 value: 6,
                 metadata: HashMap::new(),
             }],
             histograms: vec![Histogram {
                 name: "histogram".to_string(),
+                metric_type: MetricType::Histogram,
                 value: h2,
                 metadata: HashMap::new(),
             }],
+            stats: vec![Stats {
+                name: "stats".to_string(),
+                metric_type: MetricType::Summary,
+                min: None,
+                max: None,
+                sum: 0,
+                count: 0,
+                metadata: HashMap::new(),
+            }],
+            sketches: Vec::new(),
+            cardinalities: Vec::new(),
+            top_k: Vec::new(),
+            events: Vec::new(),
+            ..Snapshot::new()
         };
 
         vec![s1, s2]
@@ -522,6 +1221,24 @@ mod tests {
         assert_eq!(builder.metadata().row_group(1).num_rows(), 1);
     }
 
+    #[test]
+    fn test_memory_budget() {
+        let snapshots = build_snapshots();
+        let tmpfile = write_parquet(snapshots, ParquetOptions::new().memory_budget(1));
+        let builder = ParquetRecordBatchReaderBuilder::try_new(tmpfile).unwrap();
+
+        // every row is over the budget, so each is its own row group
+        assert_eq!(builder.metadata().row_groups().len(), 2);
+        assert_eq!(builder.metadata().row_group(0).num_rows(), 1);
+
+        let tmpfile = write_parquet(
+            build_snapshots(),
+            ParquetOptions::new().memory_budget(1 << 20),
+        );
+        let builder = ParquetRecordBatchReaderBuilder::try_new(tmpfile).unwrap();
+        assert_eq!(builder.metadata().row_groups().len(), 1);
+    }
+
     #[test]
     fn test_default() {
         let snapshots = build_snapshots();
@@ -534,13 +1251,22 @@ mod tests {
 
         // Check schema
         let fields: Vec<&String> = builder.schema().fields().iter().map(|x| x.name()).collect();
-        let expected = vec!["timestamp", "counter", "gauge", "histogram:buckets"];
+        let expected = vec![
+            "timestamp",
+            "counter",
+            "gauge",
+            "histogram:buckets",
+            "stats:min",
+            "stats:max",
+            "stats:sum",
+            "stats:count",
+        ];
         assert_eq!(fields.len(), expected.len());
         assert_eq!(fields, expected);
 
         // Check data
         let batch = builder.build().unwrap().next().unwrap().unwrap();
-        assert_eq!(batch.num_columns(), 4);
+        assert_eq!(batch.num_columns(), 8);
         assert_eq!(batch.num_rows(), 2);
 
         validate_u64_array(batch.column(1).clone(), &[100, 121]);
@@ -576,13 +1302,17 @@ mod tests {
             "gauge",
             "histogram:bucket_indices",
             "histogram:bucket_counts",
+            "stats:min",
+            "stats:max",
+            "stats:sum",
+            "stats:count",
         ];
         assert_eq!(fields.len(), expected.len());
         assert_eq!(fields, expected);
 
         // Check data
         let batch = builder.build().unwrap().next().unwrap().unwrap();
-        assert_eq!(batch.num_columns(), 5);
+        assert_eq!(batch.num_columns(), 9);
         assert_eq!(batch.num_rows(), 2);
 
         validate_u64_array(batch.column(1).clone(), &[100, 121]);
@@ -604,4 +1334,248 @@ mod tests {
         validate_u64_array(counts.value(0), &[1, 1]);
         validate_u64_array(counts.value(1), &[1, 1, 1]);
     }
+
+    #[test]
+    fn test_stats() {
+        let snapshots = build_snapshots();
+        let tmpfile = write_parquet(snapshots, ParquetOptions::new());
+        let builder = ParquetRecordBatchReaderBuilder::try_new(tmpfile).unwrap();
+        let batch = builder.build().unwrap().next().unwrap().unwrap();
+
+        let min = batch
+            .column(4)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(min.value(0), 3);
+        assert!(min.is_null(1));
+
+        let max = batch
+            .column(5)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(max.value(0), 9);
+        assert!(max.is_null(1));
+
+        validate_u64_array(batch.column(6).clone(), &[20, 0]);
+        validate_u64_array(batch.column(7).clone(), &[4, 0]);
+    }
+
+    #[test]
+    fn test_events() {
+        // the column is only added when there are events
+        let tmpfile = write_parquet(build_snapshots(), ParquetOptions::new());
+        let builder = ParquetRecordBatchReaderBuilder::try_new(tmpfile).unwrap();
+        assert!(builder.schema().field_with_name("events").is_err());
+
+        let mut snapshots = build_snapshots();
+        snapshots[1]
+            .events
+            .push(Event::new("deploy").metadata("version", "1.2.3"));
+        let tmpfile = write_parquet(snapshots, ParquetOptions::new());
+        let builder = ParquetRecordBatchReaderBuilder::try_new(tmpfile).unwrap();
+
+        let field = builder.schema().field_with_name("events").unwrap();
+        assert_eq!(field.metadata()["metric_type"], "events");
+
+        let batch = builder.build().unwrap().next().unwrap().unwrap();
+        let events = batch
+            .column_by_name("events")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<ListArray>()
+            .unwrap();
+        assert_eq!(events.value_length(0), 0);
+        assert_eq!(events.value_length(1), 1);
+    }
+
+    #[test]
+    fn test_column_naming() {
+        let mut snapshots = build_snapshots();
+        for (snapshot, value) in snapshots.iter_mut().zip([1, 2]) {
+            for name in ["Cache/Hits", "cache/hits", "cache_hits_total"] {
+                snapshot.counters.push(Counter {
+                    name: name.to_string(),
+                    metric_type: MetricType::Counter,
+                    value,
+                    metadata: HashMap::new(),
+                });
+            }
+            snapshot.histograms[0].name = "request/latency".to_string();
+        }
+
+        let naming = ParquetColumnNaming::new()
+            .replace_slashes(true)
+            .lowercase(true)
+            .max_length(10);
+        let tmpfile = write_parquet(snapshots, ParquetOptions::new().column_naming(naming));
+        let builder = ParquetRecordBatchReaderBuilder::try_new(tmpfile).unwrap();
+
+        let fields: Vec<&String> = builder.schema().fields().iter().map(|x| x.name()).collect();
+        assert_eq!(
+            fields,
+            [
+                "timestamp",
+                "cache_hits",
+                "cache_hi_2",
+                "cache_hi_3",
+                "counter",
+                "gauge",
+                "request_la:buckets",
+                "stats:min",
+                "stats:max",
+                "stats:sum",
+                "stats:count",
+            ]
+        );
+
+        let field = builder.schema().field_with_name("cache_hi_2").unwrap();
+        assert_eq!(field.metadata()["metric_name"], "cache/hits");
+        let field = builder.schema().field_with_name("counter").unwrap();
+        assert!(!field.metadata().contains_key("metric_name"));
+
+        let manifest = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .find(|kv| kv.key == "metriken:column_names")
+            .and_then(|kv| kv.value.clone())
+            .unwrap();
+        assert_eq!(
+            manifest,
+            "cache_hi_2\tcache/hits\n\
+             cache_hi_3\tcache_hits_total\n\
+             cache_hits\tCache/Hits\n\
+             request_la\trequest/latency"
+        );
+    }
+
+    #[test]
+    fn test_column_metadata() {
+        let mut snapshots = build_snapshots();
+        for snapshot in snapshots.iter_mut() {
+            snapshot.counters[0].metadata = HashMap::from([
+                ("unit".to_string(), "bytes".to_string()),
+                ("op".to_string(), "read \"fast\"".to_string()),
+            ]);
+        }
+
+        let tmpfile = write_parquet(snapshots, ParquetOptions::new());
+        let builder = ParquetRecordBatchReaderBuilder::try_new(tmpfile).unwrap();
+        let described = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .find(|kv| kv.key == "metriken:column_metadata")
+            .and_then(|kv| kv.value.clone())
+            .unwrap();
+
+        assert_eq!(
+            described,
+            r#"{"counter":{"metric_name":"counter","metric_type":"counter","op":"read \"fast\"","unit":"bytes"},"#
+                .to_owned()
+                + r#""gauge":{"metric_name":"gauge","metric_type":"gauge"},"#
+                + r#""histogram":{"grouping_power":"1","max_value_power":"3","metric_name":"histogram","metric_type":"histogram"},"#
+                + r#""stats":{"metric_name":"stats","metric_type":"stats"}}"#
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let snapshots = build_snapshots();
+        let mut schema = ParquetSchema::new();
+        for s in &snapshots {
+            schema.push(s.clone());
+        }
+
+        let mut summary = tempfile::tempfile().unwrap();
+        let mut writer = schema
+            .finalize(tempfile::tempfile().unwrap(), ParquetOptions::new())
+            .unwrap()
+            .with_summary(summary.try_clone().unwrap())
+            .unwrap();
+        for s in snapshots {
+            writer.push(s).unwrap();
+        }
+        writer.finalize().unwrap();
+        summary.rewind().unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(summary).unwrap();
+        let fields: Vec<&String> = builder.schema().fields().iter().map(|x| x.name()).collect();
+        assert_eq!(
+            fields,
+            [
+                "timestamp",
+                "metric",
+                "p50",
+                "p90",
+                "p99",
+                "max",
+                "count",
+                "rate"
+            ]
+        );
+
+        // the histogram is cumulative, so there is only a row for the
+        // interval between the two snapshots, which holds a single value in
+        // the bucket 4..=5
+        let batch = builder.build().unwrap().next().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 1);
+        for column in ["p50", "p90", "p99", "max"] {
+            validate_u64_array(batch.column_by_name(column).unwrap().clone(), &[5]);
+        }
+        validate_u64_array(batch.column_by_name("count").unwrap().clone(), &[1]);
+        let rate = batch
+            .column_by_name("rate")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap()
+            .value(0);
+        assert!((rate - 1.0 / 600.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_histogram_config_change() {
+        let mut snapshots = build_snapshots();
+        // the histogram is redeployed with a finer and wider configuration
+        snapshots[1].histograms[0].value =
+            H2Histogram::from_buckets(2, 4, vec![0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+
+        let tmpfile = write_parquet(snapshots, ParquetOptions::new());
+        let builder = ParquetRecordBatchReaderBuilder::try_new(tmpfile).unwrap();
+
+        let field = builder
+            .schema()
+            .field_with_name("histogram:buckets")
+            .unwrap();
+        assert_eq!(field.metadata()["grouping_power"], "1");
+        assert_eq!(field.metadata()["max_value_power"], "4");
+
+        let batch = builder.build().unwrap().next().unwrap().unwrap();
+        let buckets = batch
+            .column_by_name("histogram:buckets")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<ListArray>()
+            .unwrap();
+        let rows: Vec<Vec<u64>> = (0..2)
+            .map(|row| {
+                buckets
+                    .value(row)
+                    .as_any()
+                    .downcast_ref::<UInt64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(rows[0], vec![0, 1, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(rows[1], vec![0, 0, 0, 0, 2, 0, 0, 0]);
+    }
 }
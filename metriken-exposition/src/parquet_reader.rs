@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use arrow::array::*;
+
+use crate::parquet::{METRIC_NAME, STATS_FIELDS};
+use crate::snapshot::{Counter, Event, Gauge, Histogram, MetricType, Snapshot, Stats};
+
+/// Reconstruct the snapshots held in a `RecordBatch` that was read back from a
+/// parquet file written by a `ParquetWriter`. Metrics which are null in a row
+/// are omitted from the corresponding snapshot. Histograms can only be
+/// reconstructed if their configuration was recorded in the column metadata,
+/// as the `Snapshotter` does.
+pub(crate) fn snapshots_from_batch(
+    batch: &RecordBatch,
+    metadata: &HashMap<String, String>,
+) -> Vec<Snapshot> {
+    let schema = batch.schema();
+
+    let mut snapshots: Vec<Snapshot> = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<UInt64Array>()
+        .map(|ts| ts.values().to_vec())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|ts| {
+            let mut snapshot = Snapshot::new();
+            snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_nanos(ts);
+            snapshot.metadata = metadata.clone();
+            snapshot
+        })
+        .collect();
+
+    let mut idx = 1;
+    while idx < schema.fields().len() {
+        let field = schema.field(idx);
+        let mut metadata = field.metadata().clone();
+        let metric_type = metadata.remove("metric_type").unwrap_or_default();
+
+        // columns which were renamed record the name of their metric
+        let original = metadata.remove(METRIC_NAME);
+        let name = |suffix: &str| {
+            original
+                .clone()
+                .unwrap_or_else(|| field.name().trim_end_matches(suffix).to_string())
+        };
+
+        match metric_type.as_str() {
+            "counter" => {
+                let name = name("");
+                let metric_type = match metadata.get("temporality").map(|v| v.as_str()) {
+                    Some("delta") => MetricType::DeltaCounter,
+                    _ => MetricType::Counter,
+                };
+
+                if let Some(col) = batch.column(idx).as_any().downcast_ref::<UInt64Array>() {
+                    for (row, snapshot) in snapshots.iter_mut().enumerate() {
+                        if col.is_valid(row) {
+                            snapshot.counters.push(Counter {
+                                name: name.clone(),
+                                metric_type,
+                                value: col.value(row),
+                                metadata: metadata.clone(),
+                            });
+                        }
+                    }
+                }
+                idx += 1;
+            }
+            "gauge" => {
+                let name = name("");
+                if let Some(col) = batch.column(idx).as_any().downcast_ref::<Int64Array>() {
+                    for (row, snapshot) in snapshots.iter_mut().enumerate() {
+                        if col.is_valid(row) {
+                            snapshot.gauges.push(Gauge {
+                                name: name.clone(),
+                                metric_type: MetricType::Gauge,
+                                value: col.value(row),
+                                metadata: metadata.clone(),
+                            });
+                        }
+                    }
+                }
+                idx += 1;
+            }
+            "histogram" => {
+                let name = name(":buckets");
+                let config = histogram_config(&metadata);
+                let buckets = batch.column(idx).as_any().downcast_ref::<ListArray>();
+
+                if let (Some(config), Some(buckets)) = (config, buckets) {
+                    for (row, snapshot) in snapshots.iter_mut().enumerate() {
+                        let Some(buckets) = list_u64_entry(buckets, row) else {
+                            continue;
+                        };
+                        if let Ok(value) = histogram::Histogram::from_buckets(
+                            config.grouping_power(),
+                            config.max_value_power(),
+                            buckets,
+                        ) {
+                            snapshot.histograms.push(Histogram {
+                                name: name.clone(),
+                                metric_type: histogram_type(&metadata),
+                                value,
+                                metadata: metadata.clone(),
+                            });
+                        }
+                    }
+                }
+                idx += 1;
+            }
+            "sparse_histogram" => {
+                let name = name(":bucket_indices");
+                let config = histogram_config(&metadata);
+                let indices = batch.column(idx).as_any().downcast_ref::<ListArray>();
+                let counts = batch.column(idx + 1).as_any().downcast_ref::<ListArray>();
+
+                if let (Some(config), Some(indices), Some(counts)) = (config, indices, counts) {
+                    for (row, snapshot) in snapshots.iter_mut().enumerate() {
+                        let (Some(index), Some(count)) =
+                            (list_u64_entry(indices, row), list_u64_entry(counts, row))
+                        else {
+                            continue;
+                        };
+                        let sparse = histogram::SparseHistogram {
+                            config,
+                            index: index.into_iter().map(|i| i as usize).collect(),
+                            count,
+                        };
+                        snapshot.histograms.push(Histogram {
+                            name: name.clone(),
+                            metric_type: histogram_type(&metadata),
+                            value: histogram::Histogram::from(&sparse),
+                            metadata: metadata.clone(),
+                        });
+                    }
+                }
+                idx += 2;
+            }
+            "stats" => {
+                let name = name(":min");
+                let col = |offset: usize| {
+                    batch
+                        .column(idx + offset)
+                        .as_any()
+                        .downcast_ref::<UInt64Array>()
+                };
+
+                if let (Some(min), Some(max), Some(sum), Some(count)) =
+                    (col(0), col(1), col(2), col(3))
+                {
+                    let get = |col: &UInt64Array, row| col.is_valid(row).then(|| col.value(row));
+
+                    for (row, snapshot) in snapshots.iter_mut().enumerate() {
+                        let (Some(sum), Some(count)) = (get(sum, row), get(count, row)) else {
+                            continue;
+                        };
+                        snapshot.stats.push(Stats {
+                            name: name.clone(),
+                            metric_type: MetricType::Summary,
+                            min: get(min, row),
+                            max: get(max, row),
+                            sum,
+                            count,
+                            metadata: metadata.clone(),
+                        });
+                    }
+                }
+                idx += STATS_FIELDS.len();
+            }
+            "events" => {
+                if let Some(col) = batch.column(idx).as_any().downcast_ref::<ListArray>() {
+                    for (row, snapshot) in snapshots.iter_mut().enumerate() {
+                        if col.is_valid(row) {
+                            snapshot.events = events(&col.value(row));
+                        }
+                    }
+                }
+                idx += 1;
+            }
+            _ => idx += 1,
+        }
+    }
+
+    snapshots
+}
+
+/// Read the events of a single entry of the `events` column.
+fn events(entry: &ArrayRef) -> Vec<Event> {
+    let Some(entry) = entry.as_any().downcast_ref::<StructArray>() else {
+        return Vec::new();
+    };
+
+    let timestamps = entry.column(0).as_any().downcast_ref::<UInt64Array>();
+    let names = entry.column(1).as_any().downcast_ref::<StringArray>();
+    let metadata = entry.column(2).as_any().downcast_ref::<MapArray>();
+    let (Some(timestamps), Some(names), Some(metadata)) = (timestamps, names, metadata) else {
+        return Vec::new();
+    };
+
+    (0..entry.len())
+        .map(|idx| {
+            let entries = metadata.value(idx);
+            let keys = entries.column(0).as_any().downcast_ref::<StringArray>();
+            let values = entries.column(1).as_any().downcast_ref::<StringArray>();
+            let metadata = match (keys, values) {
+                (Some(keys), Some(values)) => keys
+                    .iter()
+                    .zip(values.iter())
+                    .filter_map(|(k, v)| Some((k?.to_string(), v?.to_string())))
+                    .collect(),
+                _ => HashMap::new(),
+            };
+
+            Event {
+                name: names.value(idx).to_string(),
+                systemtime: SystemTime::UNIX_EPOCH + Duration::from_nanos(timestamps.value(idx)),
+                metadata,
+            }
+        })
+        .collect()
+}
+
+/// Recover the type of a histogram from the `temporality` column metadata.
+fn histogram_type(metadata: &HashMap<String, String>) -> MetricType {
+    match metadata.get("temporality").map(|v| v.as_str()) {
+        Some("delta") => MetricType::DeltaHistogram,
+        _ => MetricType::Histogram,
+    }
+}
+
+/// Recover the histogram configuration from the column metadata.
+fn histogram_config(metadata: &HashMap<String, String>) -> Option<histogram::Config> {
+    let grouping_power = metadata.get("grouping_power")?.parse().ok()?;
+    let max_value_power = metadata.get("max_value_power")?.parse().ok()?;
+    histogram::Config::new(grouping_power, max_value_power).ok()
+}
+
+/// Read a single entry of an arrow list of u64s, returning `None` if the
+/// entry is null.
+fn list_u64_entry(list: &ListArray, row: usize) -> Option<Vec<u64>> {
+    if list.is_null(row) {
+        return None;
+    }
+
+    list.value(row)
+        .as_any()
+        .downcast_ref::<UInt64Array>()
+        .map(|v| v.values().to_vec())
+}
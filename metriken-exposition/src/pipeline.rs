@@ -0,0 +1,968 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+
+use metriken::{AtomicHistogram, RwLockHistogram, SampledHistogram, ThreadLocalHistogram};
+
+use crate::msgpack::MsgpackWriter;
+use crate::prometheus::PrometheusOptions;
+use crate::recording::RecordingError;
+#[cfg(feature = "shmem")]
+use crate::shmem::{ShmemError, ShmemWriter};
+use crate::snapshot::Snapshot;
+use crate::snapshotter::{Snapshotter, SnapshotterBuilder};
+
+/// Errors that can occur while loading a pipeline configuration or exporting
+/// snapshots through a pipeline.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PipelineError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Recording(RecordingError),
+    #[cfg(feature = "shmem")]
+    Shmem(ShmemError),
+    /// The interval could not be parsed.
+    Interval(String),
+}
+
+impl std::fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Toml(e) => write!(f, "invalid config: {e}"),
+            Self::Recording(e) => write!(f, "recording error: {e}"),
+            #[cfg(feature = "shmem")]
+            Self::Shmem(e) => write!(f, "shmem error: {e}"),
+            Self::Interval(interval) => write!(f, "invalid interval: `{interval}`"),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Toml(e) => Some(e),
+            Self::Recording(e) => Some(e),
+            #[cfg(feature = "shmem")]
+            Self::Shmem(e) => Some(e),
+            Self::Interval(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PipelineError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for PipelineError {
+    fn from(e: toml::de::Error) -> Self {
+        Self::Toml(e)
+    }
+}
+
+impl From<RecordingError> for PipelineError {
+    fn from(e: RecordingError) -> Self {
+        Self::Recording(e)
+    }
+}
+
+#[cfg(feature = "shmem")]
+impl From<ShmemError> for PipelineError {
+    fn from(e: ShmemError) -> Self {
+        Self::Shmem(e)
+    }
+}
+
+/// The declarative configuration for a [`Pipeline`].
+///
+/// A configuration is usually loaded from a TOML file:
+///
+/// ```toml
+/// interval = "10s"
+/// changed_only = false
+///
+/// [metadata]
+/// source = "cache-server"
+///
+/// [filter]
+/// include = ["cache/"]
+/// exclude = ["cache/debug/"]
+///
+/// [[transform]]
+/// prefix = "cache/"
+/// metadata = { team = "storage" }
+/// remove_metadata = ["internal"]
+///
+/// [[exporter]]
+/// type = "prometheus"
+/// path = "/var/lib/node_exporter/cache.prom"
+/// buckets = [1000, 10000, 100000]
+///
+/// [[exporter]]
+/// type = "msgpack"
+/// path = "/var/log/cache/metrics.msgpack"
+/// interval = "1m"
+/// ```
+///
+/// The configuration implements `serde::Deserialize` so it can also be
+/// embedded in a larger application config in any format supported by serde.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// How often snapshots are exported, as a number followed by one of the
+    /// units `ms`, `s`, `m`, or `h`. Defaults to `1s`. Exporters can set
+    /// their own interval.
+    #[serde(default)]
+    pub interval: Option<String>,
+    /// Metadata added to every snapshot.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Only export metrics which changed since the previous snapshot.
+    #[serde(default)]
+    pub changed_only: bool,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    #[serde(default, rename = "transform")]
+    pub transforms: Vec<TransformConfig>,
+    #[serde(default, rename = "exporter")]
+    pub exporters: Vec<ExporterConfig>,
+}
+
+impl PipelineConfig {
+    /// Parse a configuration from a TOML document.
+    pub fn from_toml(config: &str) -> Result<Self, PipelineError> {
+        Ok(toml::from_str(config)?)
+    }
+
+    /// Read and parse the TOML configuration file at the provided path.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PipelineError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Selects which metrics are exported by name prefix.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Only metrics whose name starts with one of these prefixes are
+    /// exported. All metrics are included if this is empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Metrics whose name starts with one of these prefixes are not
+    /// exported, even if they are included.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl FilterConfig {
+    fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| name.starts_with(p.as_str())))
+            && !self.exclude.iter().any(|p| name.starts_with(p.as_str()))
+    }
+}
+
+/// Changes the metadata of the metrics whose name starts with a prefix.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformConfig {
+    pub prefix: String,
+    /// Metadata added to each matching metric, replacing any existing value.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Metadata keys removed from each matching metric.
+    #[serde(default)]
+    pub remove_metadata: Vec<String>,
+}
+
+impl TransformConfig {
+    fn apply(&self, name: &str, metadata: &mut HashMap<String, String>) {
+        if !name.starts_with(self.prefix.as_str()) {
+            return;
+        }
+
+        for key in &self.remove_metadata {
+            metadata.remove(key);
+        }
+        metadata.extend(self.metadata.clone());
+    }
+}
+
+/// A destination for the snapshots produced by a pipeline.
+///
+/// Each exporter can set its own `interval`, in the same format as the
+/// interval of the pipeline, which is used if it is not set. A single
+/// snapshot is taken whenever any exporter is due and sent to every exporter
+/// which is, so the cost of collection is paid once however many exporters
+/// there are.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+#[non_exhaustive]
+pub enum ExporterConfig {
+    /// Writes the latest snapshot in the Prometheus text format to a file,
+    /// such as for the node exporter textfile collector. The file is
+    /// replaced atomically.
+    Prometheus {
+        path: PathBuf,
+        /// The `le` boundaries used for histograms.
+        #[serde(default)]
+        buckets: Vec<u64>,
+        #[serde(default)]
+        normalize_units: bool,
+        #[serde(default)]
+        interval: Option<String>,
+    },
+    /// Appends every snapshot to a msgpack recording.
+    Msgpack {
+        path: PathBuf,
+        #[serde(default)]
+        interval: Option<String>,
+    },
+    /// Writes the latest snapshot into a shared memory region.
+    #[cfg(feature = "shmem")]
+    Shmem {
+        path: PathBuf,
+        capacity: usize,
+        #[serde(default)]
+        interval: Option<String>,
+    },
+}
+
+impl ExporterConfig {
+    /// The interval of the exporter, if it has its own.
+    fn interval(&self) -> Option<&str> {
+        match self {
+            Self::Prometheus { interval, .. } | Self::Msgpack { interval, .. } => {
+                interval.as_deref()
+            }
+            #[cfg(feature = "shmem")]
+            Self::Shmem { interval, .. } => interval.as_deref(),
+        }
+    }
+}
+
+/// An exporter and the interval it exports on, if it differs from the
+/// interval of the pipeline.
+struct Scheduled {
+    exporter: Exporter,
+    interval: Option<Duration>,
+}
+
+impl Scheduled {
+    fn new(config: &ExporterConfig) -> Result<Self, PipelineError> {
+        Ok(Self {
+            exporter: Exporter::new(config)?,
+            interval: config.interval().map(parse_interval).transpose()?,
+        })
+    }
+}
+
+enum Exporter {
+    Prometheus {
+        path: PathBuf,
+        options: PrometheusOptions,
+    },
+    Msgpack(Box<MsgpackWriter<File>>),
+    #[cfg(feature = "shmem")]
+    Shmem(ShmemWriter),
+}
+
+impl Exporter {
+    fn new(config: &ExporterConfig) -> Result<Self, PipelineError> {
+        Ok(match config {
+            ExporterConfig::Prometheus {
+                path,
+                buckets,
+                normalize_units,
+                ..
+            } => {
+                let mut options = PrometheusOptions::new().normalize_units(*normalize_units);
+                if !buckets.is_empty() {
+                    options = options.default_buckets(buckets.clone());
+                }
+                Self::Prometheus {
+                    path: path.clone(),
+                    options,
+                }
+            }
+            ExporterConfig::Msgpack { path, .. } => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Self::Msgpack(Box::new(MsgpackWriter::new(file)))
+            }
+            #[cfg(feature = "shmem")]
+            ExporterConfig::Shmem { path, capacity, .. } => {
+                Self::Shmem(ShmemWriter::create(path, *capacity)?)
+            }
+        })
+    }
+
+    /// The kind of exporter, as used in its configuration.
+    #[cfg(feature = "log")]
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Prometheus { .. } => "prometheus",
+            Self::Msgpack(_) => "msgpack",
+            #[cfg(feature = "shmem")]
+            Self::Shmem(_) => "shmem",
+        }
+    }
+
+    /// Send a snapshot to this exporter, returning the number of bytes
+    /// written.
+    fn export(&mut self, snapshot: &Snapshot) -> Result<usize, PipelineError> {
+        match self {
+            Self::Prometheus { path, options } => {
+                let mut tmp = path.clone().into_os_string();
+                tmp.push(".tmp");
+
+                let text = snapshot.to_prometheus(options);
+                let mut file = File::create(&tmp)?;
+                file.write_all(text.as_bytes())?;
+                file.sync_all()?;
+                std::fs::rename(&tmp, path)?;
+                Ok(text.len())
+            }
+            Self::Msgpack(writer) => {
+                let offset = writer.offset();
+                writer.push(snapshot)?;
+                Ok((writer.offset() - offset) as usize)
+            }
+            #[cfg(feature = "shmem")]
+            Self::Shmem(writer) => {
+                let bytes = Snapshot::to_msgpack(snapshot).map_err(ShmemError::from)?;
+                writer.write(&bytes)?;
+                Ok(bytes.len())
+            }
+        }
+    }
+}
+
+/// How serious a problem found by [`Pipeline::validate`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The pipeline would still run, but probably not as intended.
+    Warning,
+    /// The pipeline would fail to start or to export.
+    Error,
+}
+
+/// A problem with a [`PipelineConfig`] found by [`Pipeline::validate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The part of the configuration the problem is in, such as `interval`
+    /// or `exporter[1]`.
+    pub location: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}: {}: {}", self.location, self.message)
+    }
+}
+
+/// Check that the file at `path` could be written without creating or
+/// modifying it.
+fn check_writable(path: &Path) -> Option<String> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => {
+            return Some(format!("`{}` is a directory", path.display()))
+        }
+        Ok(metadata) if metadata.permissions().readonly() => {
+            return Some(format!("`{}` is read-only", path.display()))
+        }
+        Ok(_) => return None,
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Some(format!("cannot access `{}`: {e}", path.display()))
+        }
+        Err(_) => {}
+    }
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match std::fs::metadata(parent) {
+        Ok(metadata) if !metadata.is_dir() => {
+            Some(format!("`{}` is not a directory", parent.display()))
+        }
+        Ok(metadata) if metadata.permissions().readonly() => {
+            Some(format!("directory `{}` is read-only", parent.display()))
+        }
+        Ok(_) => None,
+        Err(e) => Some(format!("cannot access `{}`: {e}", parent.display())),
+    }
+}
+
+/// Takes snapshots on an interval and sends them to a set of exporters, as
+/// described by a [`PipelineConfig`].
+///
+/// This allows operators to change where metrics are exported without
+/// recompiling the application. A running pipeline can be reconfigured
+/// through a [`PipelineHandle`].
+pub struct Pipeline {
+    snapshotter: Snapshotter,
+    interval: Duration,
+    filter: FilterConfig,
+    transforms: Vec<TransformConfig>,
+    exporters: Vec<Scheduled>,
+    shared: Arc<Shared>,
+}
+
+/// Changes requested through a [`PipelineHandle`] which have not yet been
+/// applied to the pipeline.
+#[derive(Default)]
+struct Update {
+    reload: Option<Box<Pipeline>>,
+    interval: Option<Duration>,
+    filter: Option<FilterConfig>,
+    /// The number of exports requested with [`PipelineHandle::trigger`].
+    requested: u64,
+    /// The number of requested exports which the pipeline has started.
+    started: u64,
+}
+
+impl Update {
+    fn is_pending(&self) -> bool {
+        self.reload.is_some()
+            || self.interval.is_some()
+            || self.filter.is_some()
+            || self.requested > self.started
+    }
+}
+
+/// The exports requested with [`PipelineHandle::trigger`] which have
+/// finished.
+#[derive(Default)]
+struct Triggered {
+    completed: u64,
+    snapshot: Option<Snapshot>,
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    update: Mutex<Update>,
+    changed: Condvar,
+    latest: Mutex<Option<Snapshot>>,
+    triggered: Mutex<Triggered>,
+    exported: Condvar,
+}
+
+impl Pipeline {
+    /// Build a pipeline from its configuration, opening each of the
+    /// exporters.
+    pub fn from_config(config: &PipelineConfig) -> Result<Self, PipelineError> {
+        let interval = match &config.interval {
+            Some(interval) => parse_interval(interval)?,
+            None => Duration::from_secs(1),
+        };
+
+        let mut builder = SnapshotterBuilder::new().changed_only(config.changed_only);
+        for (key, value) in &config.metadata {
+            builder = builder.metadata(key.clone(), value.clone());
+        }
+
+        let exporters = config
+            .exporters
+            .iter()
+            .map(Scheduled::new)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            snapshotter: builder.build(),
+            interval,
+            filter: config.filter.clone(),
+            transforms: config.transforms.clone(),
+            exporters,
+            shared: Default::default(),
+        })
+    }
+
+    /// Check a configuration without opening any exporters or taking any
+    /// snapshots, returning every problem found. A configuration with no
+    /// diagnostics of [`Severity::Error`] can be used to build a pipeline.
+    ///
+    /// Besides parsing the interval, this checks that each exporter can write
+    /// to its destination, and that every filter and transform prefix matches
+    /// at least one of the currently registered metrics. Metrics which are
+    /// registered later, such as dynamic metrics, are not seen, so those are
+    /// only reported as warnings.
+    pub fn validate(config: &PipelineConfig) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        if let Some(interval) = &config.interval {
+            if let Err(e) = parse_interval(interval) {
+                diagnostics.push(Diagnostic::new(Severity::Error, "interval", e.to_string()));
+            }
+        }
+
+        let names: Vec<String> = metriken::metrics()
+            .iter()
+            .map(|metric| metric.name().to_string())
+            .collect();
+        let matches_any = |prefix: &str| names.iter().any(|name| name.starts_with(prefix));
+        let has_histograms = metriken::metrics().iter().any(|metric| {
+            config.filter.matches(metric.name())
+                && metric.as_any().is_some_and(|any| {
+                    any.is::<AtomicHistogram>()
+                        || any.is::<RwLockHistogram>()
+                        || any.is::<ThreadLocalHistogram>()
+                        || any.is::<SampledHistogram>()
+                })
+        });
+
+        for (idx, prefix) in config.filter.include.iter().enumerate() {
+            if !matches_any(prefix) {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    format!("filter.include[{idx}]"),
+                    format!("`{prefix}` does not match any metric"),
+                ));
+            }
+        }
+        for (idx, prefix) in config.filter.exclude.iter().enumerate() {
+            if !matches_any(prefix) {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    format!("filter.exclude[{idx}]"),
+                    format!("`{prefix}` does not match any metric"),
+                ));
+            }
+        }
+        if !names.iter().any(|name| config.filter.matches(name)) {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                "filter",
+                "no metrics are selected for export",
+            ));
+        }
+        for (idx, transform) in config.transforms.iter().enumerate() {
+            if !matches_any(&transform.prefix) {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    format!("transform[{idx}]"),
+                    format!("`{}` does not match any metric", transform.prefix),
+                ));
+            }
+        }
+
+        if config.exporters.is_empty() {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                "exporter",
+                "no exporters are configured",
+            ));
+        }
+        for (idx, exporter) in config.exporters.iter().enumerate() {
+            let location = format!("exporter[{idx}]");
+            if let Some(interval) = exporter.interval() {
+                if let Err(e) = parse_interval(interval) {
+                    diagnostics.push(Diagnostic::new(
+                        Severity::Error,
+                        format!("{location}.interval"),
+                        e.to_string(),
+                    ));
+                }
+            }
+            let path = match exporter {
+                ExporterConfig::Prometheus { path, buckets, .. } => {
+                    if buckets.is_empty() && has_histograms {
+                        diagnostics.push(Diagnostic::new(
+                            Severity::Warning,
+                            location.clone(),
+                            "histograms are not exported without `buckets`",
+                        ));
+                    }
+                    path
+                }
+                ExporterConfig::Msgpack { path, .. } => path,
+                #[cfg(feature = "shmem")]
+                ExporterConfig::Shmem { path, capacity, .. } => {
+                    if *capacity == 0 {
+                        diagnostics.push(Diagnostic::new(
+                            Severity::Error,
+                            location.clone(),
+                            "`capacity` must be greater than zero",
+                        ));
+                    }
+                    path
+                }
+            };
+            if let Some(message) = check_writable(path) {
+                diagnostics.push(Diagnostic::new(Severity::Error, location, message));
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Returns a handle which can reconfigure this pipeline while it is
+    /// running.
+    pub fn handle(&self) -> PipelineHandle {
+        PipelineHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// The interval between snapshots, used by every exporter which does not
+    /// have its own.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The latest snapshot exported by the pipeline, after its filter and
+    /// transforms were applied. This can be served on demand, such as to a
+    /// Prometheus scrape, without taking another snapshot.
+    pub fn latest(&self) -> Option<Snapshot> {
+        self.shared.latest.lock().unwrap().clone()
+    }
+
+    /// Apply any changes requested through a handle.
+    fn apply_updates(&mut self) {
+        let update = {
+            let mut update = self.shared.update.lock().unwrap();
+            Update {
+                reload: update.reload.take(),
+                interval: update.interval.take(),
+                filter: update.filter.take(),
+                ..Default::default()
+            }
+        };
+
+        if let Some(reload) = update.reload {
+            #[cfg(feature = "log")]
+            log::info!("pipeline configuration reloaded");
+            let reload = *reload;
+            self.snapshotter = reload.snapshotter;
+            self.interval = reload.interval;
+            self.filter = reload.filter;
+            self.transforms = reload.transforms;
+            self.exporters = reload.exporters;
+        }
+        if let Some(interval) = update.interval {
+            self.interval = interval;
+        }
+        if let Some(filter) = update.filter {
+            self.filter = filter;
+        }
+    }
+
+    /// Take a snapshot, apply the filter and transforms, and send it to every
+    /// exporter, whatever its interval. Every exporter is tried even if an
+    /// earlier one fails, and the first error is returned.
+    pub fn export(&mut self) -> Result<Snapshot, PipelineError> {
+        self.export_due(|_| true)
+    }
+
+    /// Take a snapshot and send it to the exporters for whose interval `due`
+    /// returns true.
+    fn export_due(&mut self, due: impl Fn(Duration) -> bool) -> Result<Snapshot, PipelineError> {
+        self.apply_updates();
+
+        let mut snapshot = self.snapshotter.snapshot();
+
+        macro_rules! process {
+            ($field:ident) => {
+                snapshot.$field.retain(|m| self.filter.matches(&m.name));
+                for metric in snapshot.$field.iter_mut() {
+                    for transform in &self.transforms {
+                        transform.apply(&metric.name, &mut metric.metadata);
+                    }
+                }
+            };
+        }
+
+        process!(counters);
+        process!(gauges);
+        process!(histograms);
+        process!(stats);
+        process!(sketches);
+        process!(cardinalities);
+        process!(top_k);
+
+        let mut result = Ok(());
+        #[cfg_attr(not(feature = "log"), allow(unused_variables))]
+        for (idx, scheduled) in self.exporters.iter_mut().enumerate() {
+            if !due(scheduled.interval.unwrap_or(self.interval)) {
+                continue;
+            }
+
+            let exporter = &mut scheduled.exporter;
+            match exporter.export(&snapshot) {
+                Ok(bytes) => {
+                    #[cfg(feature = "log")]
+                    log::debug!("exporter[{idx}] ({}) wrote {bytes} bytes", exporter.kind());
+                }
+                Err(e) => {
+                    #[cfg(feature = "log")]
+                    log::warn!("exporter[{idx}] ({}) failed: {e}", exporter.kind());
+                    result = result.and(Err(e));
+                }
+            }
+        }
+
+        *self.shared.latest.lock().unwrap() = Some(snapshot.clone());
+
+        result.map(|_| snapshot)
+    }
+
+    /// Export a snapshot at the start of every interval, aligned to the unix
+    /// epoch. A snapshot is taken at the start of each interval of the
+    /// pipeline and of each exporter, and sent to the exporters whose
+    /// interval starts then. Changes made through a [`PipelineHandle`] take
+    /// effect immediately, without waiting for the current interval to end.
+    /// This only returns if an export fails.
+    ///
+    /// When only changed metrics are exported, an exporter with a longer
+    /// interval misses the changes in the snapshots it was not sent.
+    pub fn run(&mut self) -> Result<(), PipelineError> {
+        let result = self.run_until_error();
+
+        // wake any callers of `trigger` which are waiting for an export
+        self.shared.triggered.lock().unwrap().stopped = true;
+        self.shared.exported.notify_all();
+
+        result
+    }
+
+    fn run_until_error(&mut self) -> Result<(), PipelineError> {
+        loop {
+            self.apply_updates();
+
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let intervals = std::iter::once(self.interval)
+                .chain(self.exporters.iter().filter_map(|e| e.interval));
+            let wake = next_wake(now, intervals);
+            let wait = Duration::from_nanos((wake - now) as u64);
+
+            let shared = self.shared.clone();
+            let update = shared.update.lock().unwrap();
+            let (mut update, result) = shared
+                .changed
+                .wait_timeout_while(update, wait, |update| !update.is_pending())
+                .unwrap();
+
+            // every trigger requested so far is served by a single export
+            let trigger = (update.requested > update.started).then(|| {
+                update.started = update.requested;
+                update.started
+            });
+            drop(update);
+
+            if let Some(trigger) = trigger {
+                let result = self.export();
+
+                let mut triggered = shared.triggered.lock().unwrap();
+                triggered.completed = trigger;
+                triggered.snapshot = result.as_ref().ok().cloned();
+                drop(triggered);
+                shared.exported.notify_all();
+
+                result?;
+            } else if result.timed_out() {
+                self.export_due(|interval| wake % interval.as_nanos().max(1) == 0)?;
+            }
+        }
+    }
+}
+
+/// Reconfigures a [`Pipeline`] from another thread, such as from an admin
+/// endpoint or a `SIGHUP` handler which reloads the configuration file.
+///
+/// Changes are applied before the next snapshot is taken. The metrics
+/// registry is not affected.
+#[derive(Clone)]
+pub struct PipelineHandle {
+    shared: Arc<Shared>,
+}
+
+impl PipelineHandle {
+    fn update(&self, f: impl FnOnce(&mut Update)) {
+        f(&mut self.shared.update.lock().unwrap());
+        self.shared.changed.notify_all();
+    }
+
+    /// Change the interval between snapshots, for example to temporarily
+    /// take high frequency snapshots while debugging.
+    pub fn set_interval(&self, interval: Duration) {
+        self.update(|update| update.interval = Some(interval));
+    }
+
+    /// Replace the filter which selects the exported metrics.
+    pub fn set_filter(&self, filter: FilterConfig) {
+        self.update(|update| update.filter = Some(filter));
+    }
+
+    /// Replace the whole configuration of the pipeline, including its
+    /// exporters. The exporters are opened before this returns, so an
+    /// invalid configuration is reported here and leaves the pipeline
+    /// unchanged.
+    ///
+    /// This replaces any interval or filter set through this handle which
+    /// has not yet been applied.
+    pub fn reload(&self, config: &PipelineConfig) -> Result<(), PipelineError> {
+        let pipeline = Pipeline::from_config(config)?;
+        self.update(|update| {
+            update.reload = Some(Box::new(pipeline));
+            update.interval = None;
+            update.filter = None;
+        });
+        Ok(())
+    }
+
+    /// Make the running pipeline export a snapshot to every exporter right
+    /// away, outside of its schedule, and return it once it has been sent.
+    /// This is useful for debugging endpoints which need the metrics as they
+    /// are now.
+    ///
+    /// Triggers which arrive before the export starts share it, so many
+    /// concurrent callers cause a single export. The snapshot returned is
+    /// always taken after this was called. This waits for [`Pipeline::run`]
+    /// to take the snapshot and returns `None` if the export fails or the
+    /// pipeline stops.
+    pub fn trigger(&self) -> Option<Snapshot> {
+        let mut target = 0;
+        self.update(|update| {
+            update.requested += 1;
+            target = update.requested;
+        });
+
+        let triggered = self.shared.triggered.lock().unwrap();
+        let triggered = self
+            .shared
+            .exported
+            .wait_while(triggered, |t| t.completed < target && !t.stopped)
+            .unwrap();
+
+        match triggered.completed >= target {
+            true => triggered.snapshot.clone(),
+            false => None,
+        }
+    }
+
+    /// The latest snapshot exported by the pipeline. See
+    /// [`Pipeline::latest`].
+    pub fn latest(&self) -> Option<Snapshot> {
+        self.shared.latest.lock().unwrap().clone()
+    }
+}
+
+/// The time, in nanoseconds since the unix epoch, at which the next of the
+/// intervals starts after `now`. Intervals are aligned to the epoch.
+fn next_wake(now: u128, intervals: impl Iterator<Item = Duration>) -> u128 {
+    intervals
+        .map(|interval| {
+            let interval = interval.as_nanos().max(1);
+            now - now % interval + interval
+        })
+        .min()
+        .unwrap_or(now)
+}
+
+/// Parse an interval such as `500ms`, `10s`, `5m`, or `1h`.
+fn parse_interval(interval: &str) -> Result<Duration, PipelineError> {
+    let invalid = || PipelineError::Interval(interval.to_string());
+
+    let trimmed = interval.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (value, unit) = trimmed.split_at(split);
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+
+    let duration = match unit.trim() {
+        "ms" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 3600),
+        _ => return Err(invalid()),
+    };
+
+    if duration.is_zero() {
+        return Err(invalid());
+    }
+
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval() {
+        assert_eq!(parse_interval("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_interval("10s").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_interval("5 m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_interval("1h").unwrap(), Duration::from_secs(3600));
+
+        for invalid in ["", "10", "s", "0s", "1d", "-1s"] {
+            assert!(matches!(
+                parse_interval(invalid),
+                Err(PipelineError::Interval(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn wake() {
+        let secs = |s| Duration::from_secs(s);
+        let intervals = || [secs(1), secs(10)].into_iter();
+
+        assert_eq!(next_wake(500_000_000, intervals()), 1_000_000_000);
+        assert_eq!(next_wake(9_500_000_000, intervals()), 10_000_000_000);
+        // exactly at the start of an interval waits for the next one
+        assert_eq!(next_wake(10_000_000_000, intervals()), 11_000_000_000);
+        assert_eq!(next_wake(3, std::iter::empty()), 3);
+    }
+
+    #[test]
+    fn filter() {
+        let filter = FilterConfig {
+            include: vec!["a/".to_string(), "b/".to_string()],
+            exclude: vec!["a/debug".to_string()],
+        };
+        assert!(filter.matches("a/requests"));
+        assert!(filter.matches("b/requests"));
+        assert!(!filter.matches("a/debug/requests"));
+        assert!(!filter.matches("c/requests"));
+        assert!(FilterConfig::default().matches("anything"));
+    }
+
+    #[test]
+    fn unknown_fields() {
+        assert!(matches!(
+            PipelineConfig::from_toml("intreval = \"1s\""),
+            Err(PipelineError::Toml(_))
+        ));
+        assert!(matches!(
+            PipelineConfig::from_toml("[[exporter]]\ntype = \"carrier_pigeon\""),
+            Err(PipelineError::Toml(_))
+        ));
+    }
+}
@@ -0,0 +1,676 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use metriken_core::Unit;
+
+use crate::snapshot::{MetricType, Snapshot};
+use crate::text::format_human;
+
+/// Metadata keys which are used internally and are not exported as labels.
+const RESERVED_METADATA: [&str; 8] = [
+    "aggregation",
+    "description",
+    "grouping_power",
+    "max_value_power",
+    "sampling_rate",
+    "temporality",
+    "uninitialized",
+    "unit",
+];
+
+/// Options controlling how a snapshot is rendered in the Prometheus text
+/// exposition format.
+///
+/// Metriken histograms have far more buckets than is practical to export as
+/// classic Prometheus histograms, so histograms are converted to a set of
+/// user-specified `le` boundaries. Histograms without any configured
+/// boundaries are not exported. Prometheus histograms are cumulative, so
+/// delta histograms are not exported either, unless they are first summed
+/// into running totals with a [`TemporalityConverter`].
+///
+/// Metriken histograms do not record the sum of their values, so the `_sum`
+/// of each histogram is an estimate which takes every value to be at the
+/// midpoint of its bucket. Its relative error is bounded by the precision of
+/// the histogram.
+///
+/// Series which share an exported name are written together under a single
+/// `HELP` and `TYPE`. A metric whose exported name, or the name of one of its
+/// samples, is already used by a metric of a different type is left out, such
+/// as a counter named `latency_count` alongside a histogram named `latency`.
+///
+/// [`TemporalityConverter`]: crate::TemporalityConverter
+#[derive(Clone, Debug, Default)]
+pub struct PrometheusOptions {
+    default_buckets: Option<Vec<u64>>,
+    buckets: HashMap<String, Vec<u64>>,
+    normalize_units: bool,
+    human_values: bool,
+}
+
+impl PrometheusOptions {
+    /// Create a new set of options with no histogram boundaries configured.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the `le` boundaries used for histograms which do not have their
+    /// own boundaries configured.
+    pub fn default_buckets(mut self, boundaries: Vec<u64>) -> Self {
+        self.default_buckets = Some(normalize(boundaries));
+        self
+    }
+
+    /// Sets the `le` boundaries used for the histogram with the provided name.
+    pub fn buckets(mut self, name: impl Into<String>, boundaries: Vec<u64>) -> Self {
+        self.buckets.insert(name.into(), normalize(boundaries));
+        self
+    }
+
+    /// When enabled, metrics with a `unit` are converted into the base unit
+    /// for their dimension and the unit is appended to the name, following
+    /// the Prometheus naming conventions. For example, a histogram in
+    /// nanoseconds named `latency` is exported as `latency_seconds` with its
+    /// `le` boundaries in seconds.
+    pub fn normalize_units(mut self, enabled: bool) -> Self {
+        self.normalize_units = enabled;
+        self
+    }
+
+    /// When enabled, each counter and gauge sample with a `unit` is followed
+    /// by a comment with its value formatted for people, such as
+    /// `# memory_bytes 1.18 MiB`, which makes reading the output by hand
+    /// easier. Prometheus ignores these comments. This is off by default so
+    /// the output stays compact.
+    pub fn human_values(mut self, enabled: bool) -> Self {
+        self.human_values = enabled;
+        self
+    }
+
+    /// Write a comment with the human readable value of a sample, if enabled
+    /// and the metric has a unit.
+    fn write_human(&self, out: &mut String, sample: &str, value: f64, unit: Option<Unit<'_>>) {
+        match unit {
+            Some(unit) if self.human_values && unit != Unit::Count => {
+                let _ = writeln!(out, "# {sample} {}", format_human(value, unit));
+            }
+            _ => {}
+        }
+    }
+
+    /// The sanitized name and the conversion factor for a metric, converting
+    /// it into its base unit if normalization is enabled.
+    fn name_and_factor(&self, name: &str, unit: Option<Unit<'_>>) -> (String, Option<f64>) {
+        let mut name = sanitize(name);
+
+        let Some(unit) = unit.filter(|_| self.normalize_units) else {
+            return (name, None);
+        };
+
+        let base = unit.base();
+        if matches!(base, Unit::Count | Unit::Custom(_)) {
+            return (name, None);
+        }
+
+        let suffix = format!("_{base}");
+        if !name.ends_with(&suffix) {
+            name.push_str(&suffix);
+        }
+
+        (name, unit.factor(base).filter(|factor| *factor != 1.0))
+    }
+
+    fn boundaries(&self, name: &str) -> Option<&[u64]> {
+        self.buckets
+            .get(name)
+            .or(self.default_buckets.as_ref())
+            .map(|b| b.as_slice())
+    }
+}
+
+/// Sort and deduplicate a set of boundaries.
+fn normalize(mut boundaries: Vec<u64>) -> Vec<u64> {
+    boundaries.sort_unstable();
+    boundaries.dedup();
+    boundaries
+}
+
+/// Converts a histogram into cumulative counts for each of the provided `le`
+/// boundaries, which must be sorted. A final entry for `+Inf` is not included
+/// as it is equal to the total count.
+///
+/// A source bucket is only counted towards a boundary if every value it
+/// covers is less than or equal to that boundary. Buckets which straddle a
+/// boundary are attributed to the next boundary up, so the cumulative count
+/// for a boundary never includes values greater than it.
+pub fn cumulative_buckets(histogram: &histogram::Histogram, boundaries: &[u64]) -> Vec<u64> {
+    let mut counts = vec![0; boundaries.len()];
+
+    for bucket in histogram {
+        if bucket.count() == 0 {
+            continue;
+        }
+
+        let idx = boundaries.partition_point(|le| *le < bucket.end());
+        if let Some(count) = counts.get_mut(idx) {
+            *count += bucket.count();
+        }
+    }
+
+    let mut total = 0;
+    for count in counts.iter_mut() {
+        total += *count;
+        *count = total;
+    }
+
+    counts
+}
+
+impl Snapshot {
+    /// Render this snapshot in the Prometheus text exposition format.
+    pub fn to_prometheus(&self, options: &PrometheusOptions) -> String {
+        let help = self.help(options);
+        let mut families = Families::default();
+
+        // resource attributes are exposed as the labels of a single
+        // `target_info` series, as in OpenMetrics, rather than on every series
+        let resource = self.resource();
+        if !resource.is_empty() {
+            let labels: HashMap<String, String> = resource
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            if let Some(out) = families.family("target_info", "gauge", Some("Target metadata")) {
+                let _ = writeln!(out, "target_info{} 1", format_labels(&labels, None));
+            }
+        }
+
+        for counter in &self.counters {
+            let (name, factor) = options.name_and_factor(&counter.name, counter.unit());
+            let kind = type_name(counter.metric_type);
+            let Some(out) = families.family(&name, kind, help.get(name.as_str()).copied()) else {
+                continue;
+            };
+            let labels = format_labels(&counter.metadata, None);
+            let value = format_value(counter.value.into(), factor);
+            let _ = writeln!(out, "{name}{labels} {value}");
+            options.write_human(
+                out,
+                &format!("{name}{labels}"),
+                counter.value as f64,
+                counter.unit(),
+            );
+        }
+
+        for gauge in &self.gauges {
+            let (name, factor) = options.name_and_factor(&gauge.name, gauge.unit());
+            let kind = type_name(gauge.metric_type);
+            let Some(out) = families.family(&name, kind, help.get(name.as_str()).copied()) else {
+                continue;
+            };
+            let labels = format_labels(&gauge.metadata, None);
+            let value = format_value(gauge.value.into(), factor);
+            let _ = writeln!(out, "{name}{labels} {value}");
+            options.write_human(
+                out,
+                &format!("{name}{labels}"),
+                gauge.value as f64,
+                gauge.unit(),
+            );
+        }
+
+        for histogram in &self.histograms {
+            let Some(boundaries) = options.boundaries(&histogram.name) else {
+                continue;
+            };
+            if histogram.metric_type == MetricType::DeltaHistogram {
+                continue;
+            }
+
+            let (name, factor) = options.name_and_factor(&histogram.name, histogram.unit());
+            let kind = type_name(histogram.metric_type);
+            let Some(out) = families.family(&name, kind, help.get(name.as_str()).copied()) else {
+                continue;
+            };
+
+            let counts = cumulative_buckets(&histogram.value, boundaries);
+            for (le, count) in boundaries.iter().zip(counts) {
+                let le = format_value((*le).into(), factor);
+                let labels = format_labels(&histogram.metadata, Some(&le));
+                let _ = writeln!(out, "{name}_bucket{labels} {count}");
+            }
+
+            let total: u64 = histogram.value.as_slice().iter().sum();
+            let labels = format_labels(&histogram.metadata, Some("+Inf"));
+            let _ = writeln!(out, "{name}_bucket{labels} {total}");
+            let labels = format_labels(&histogram.metadata, None);
+            let sum = format_value(estimated_sum(&histogram.value), factor);
+            let _ = writeln!(out, "{name}_sum{labels} {sum}");
+            let _ = writeln!(out, "{name}_count{labels} {total}");
+        }
+
+        for stats in &self.stats {
+            let (name, factor) = options.name_and_factor(&stats.name, stats.unit());
+            let description = help.get(name.as_str()).copied();
+            let labels = format_labels(&stats.metadata, None);
+
+            for (suffix, value, factor) in [
+                ("min", stats.min, factor),
+                ("max", stats.max, factor),
+                ("sum", Some(stats.sum), factor),
+                ("count", Some(stats.count), None),
+            ] {
+                if let Some(value) = value {
+                    let name = format!("{name}_{suffix}");
+                    let Some(out) = families.family(&name, "gauge", description) else {
+                        continue;
+                    };
+                    let value = format_value(value.into(), factor);
+                    let _ = writeln!(out, "{name}{labels} {value}");
+                }
+            }
+        }
+
+        families.finish()
+    }
+
+    /// The description for each exported metric name. Series which share a
+    /// name are rendered under a single `HELP` line, so the first description
+    /// found for any of them is used.
+    fn help(&self, options: &PrometheusOptions) -> HashMap<String, &str> {
+        let mut help = HashMap::new();
+
+        macro_rules! describe {
+            ($metrics:expr) => {
+                for metric in $metrics.iter() {
+                    if let Some(description) = metric.description() {
+                        let (name, _) = options.name_and_factor(&metric.name, metric.unit());
+                        help.entry(name).or_insert(description);
+                    }
+                }
+            };
+        }
+
+        describe!(self.counters);
+        describe!(self.gauges);
+        describe!(self.histograms);
+        describe!(self.stats);
+
+        help
+    }
+}
+
+/// The Prometheus type for a metric type. Prometheus has no notion of delta
+/// counters, so these are exposed as gauges.
+fn type_name(metric_type: MetricType) -> &'static str {
+    match metric_type {
+        MetricType::Counter => "counter",
+        MetricType::Histogram => "histogram",
+        MetricType::Summary => "summary",
+        _ => "gauge",
+    }
+}
+
+/// The metric families of an exposition. The format requires every series
+/// of a family to follow its `HELP` and `TYPE` lines, so the samples of each
+/// family are collected before they are written.
+#[derive(Default)]
+struct Families {
+    families: Vec<Family>,
+    index: HashMap<String, usize>,
+    /// The names of the samples written by each family, such as the
+    /// `_bucket`, `_sum`, and `_count` samples of a histogram.
+    samples: HashSet<String>,
+}
+
+struct Family {
+    name: String,
+    kind: &'static str,
+    description: Option<String>,
+    lines: String,
+}
+
+impl Families {
+    /// The lines of the family with the name and type, which is added if it
+    /// is new. Returns `None` if the family can't be written because another
+    /// family has the same name but a different type, or writes samples with
+    /// the same names, such as a counter named `latency_count` alongside a
+    /// histogram named `latency`. The family which is seen first is kept.
+    fn family(
+        &mut self,
+        name: &str,
+        kind: &'static str,
+        description: Option<&str>,
+    ) -> Option<&mut String> {
+        if let Some(index) = self.index.get(name) {
+            let family = &mut self.families[*index];
+            return (family.kind == kind).then_some(&mut family.lines);
+        }
+
+        let samples = sample_names(name, kind);
+        if samples.iter().any(|sample| self.samples.contains(sample)) {
+            return None;
+        }
+        self.samples.extend(samples);
+
+        self.index.insert(name.to_string(), self.families.len());
+        self.families.push(Family {
+            name: name.to_string(),
+            kind,
+            description: description.map(|d| d.to_string()),
+            lines: String::new(),
+        });
+        self.families.last_mut().map(|family| &mut family.lines)
+    }
+
+    /// Write each family with its `HELP` and `TYPE` lines.
+    fn finish(self) -> String {
+        let mut out = String::new();
+        for family in self.families {
+            let name = family.name;
+            if let Some(description) = family.description {
+                let description = description.replace('\\', "\\\\").replace('\n', "\\n");
+                let _ = writeln!(out, "# HELP {name} {description}");
+            }
+            let _ = writeln!(out, "# TYPE {name} {}", family.kind);
+            out.push_str(&family.lines);
+        }
+        out
+    }
+}
+
+/// The names of the samples written for a family.
+fn sample_names(name: &str, kind: &str) -> Vec<String> {
+    match kind {
+        "histogram" => ["_bucket", "_sum", "_count"]
+            .iter()
+            .map(|suffix| format!("{name}{suffix}"))
+            .collect(),
+        _ => vec![name.to_string()],
+    }
+}
+
+/// Format the metric metadata as a label set, with an optional `le` label.
+fn format_labels(metadata: &HashMap<String, String>, le: Option<&str>) -> String {
+    let mut labels: Vec<(String, String)> = metadata
+        .iter()
+        .filter(|(k, _)| !RESERVED_METADATA.contains(&k.as_str()))
+        .map(|(k, v)| (sanitize(k), escape(v)))
+        .collect();
+    labels.sort();
+
+    if let Some(le) = le {
+        labels.push(("le".to_string(), le.to_string()));
+    }
+
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<String> = labels
+        .into_iter()
+        .map(|(k, v)| format!("{k}=\"{v}\""))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Replace any characters which are not valid in a Prometheus metric or label
+/// name with underscores.
+fn sanitize(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | ':' => c,
+            _ => '_',
+        })
+        .collect();
+
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+
+    sanitized
+}
+
+/// Estimate the sum of the values in a histogram, taking each value to be at
+/// the midpoint of its bucket.
+fn estimated_sum(histogram: &histogram::Histogram) -> i128 {
+    let sum: u128 = histogram
+        .into_iter()
+        .filter(|bucket| bucket.count() > 0)
+        .map(|bucket| {
+            let midpoint = bucket.start() + (bucket.end() - bucket.start()) / 2;
+            midpoint as u128 * bucket.count() as u128
+        })
+        .sum();
+    sum.min(i128::MAX as u128) as i128
+}
+
+/// Format a value, scaling it by the conversion factor if there is one.
+fn format_value(value: i128, factor: Option<f64>) -> String {
+    match factor {
+        Some(factor) => (value as f64 * factor).to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Histogram;
+
+    #[test]
+    fn cumulative() {
+        let mut histogram = histogram::Histogram::new(2, 10).unwrap();
+        for value in [1, 2, 3, 10, 11, 100, 500] {
+            histogram.increment(value).unwrap();
+        }
+
+        // 10 and 11 share the bucket 10..=11, which straddles the boundary at
+        // 10 and so must only be counted towards the boundary at 20.
+        assert_eq!(
+            cumulative_buckets(&histogram, &[2, 10, 20, 1000]),
+            vec![2, 3, 5, 7]
+        );
+    }
+
+    #[test]
+    fn render() {
+        let mut value = histogram::Histogram::new(2, 10).unwrap();
+        value.add(5, 3).unwrap();
+
+        let mut snapshot = Snapshot::new();
+        snapshot.histograms.push(Histogram {
+            name: "latency/ns".to_string(),
+            metric_type: MetricType::Histogram,
+            value,
+            metadata: HashMap::from([("description".to_string(), "latency".to_string())]),
+        });
+
+        let options = PrometheusOptions::new().buckets("latency/ns", vec![10, 1]);
+        let expected = "# HELP latency_ns latency\n\
+                        # TYPE latency_ns histogram\n\
+                        latency_ns_bucket{le=\"1\"} 0\n\
+                        latency_ns_bucket{le=\"10\"} 3\n\
+                        latency_ns_bucket{le=\"+Inf\"} 3\n\
+                        latency_ns_sum 15\n\
+                        latency_ns_count 3\n";
+        assert_eq!(snapshot.to_prometheus(&options), expected);
+
+        // histograms without boundaries are skipped
+        assert_eq!(snapshot.to_prometheus(&PrometheusOptions::new()), "");
+
+        // as are delta histograms, until they are converted to running totals
+        snapshot.histograms[0].metric_type = MetricType::DeltaHistogram;
+        assert_eq!(snapshot.to_prometheus(&options), "");
+        let mut converter = crate::TemporalityConverter::new(crate::Temporality::Cumulative);
+        let snapshot = converter.convert(snapshot);
+        assert_eq!(snapshot.to_prometheus(&options), expected);
+    }
+
+    #[test]
+    fn families() {
+        let mut value = histogram::Histogram::new(2, 10).unwrap();
+        value.add(5, 3).unwrap();
+
+        let mut snapshot = Snapshot::builder()
+            .counter("requests", 1, &[("op", "get")])
+            .counter("errors", 2, &[])
+            // collides with the samples of the histogram
+            .counter("latency_count", 4, &[])
+            .histogram("latency", value, &[])
+            .stats("size", Some(1), Some(1), 1, 1, &[])
+            .build()
+            .unwrap();
+        // a series which is not next to the others of its family
+        let mut put = crate::Counter::new("requests", 3);
+        put.metadata.insert("op".to_string(), "put".to_string());
+        snapshot.counters.push(put);
+        // collides with the name of the counter, which the builder rejects
+        snapshot.gauges.push(crate::Gauge::new("errors", 5));
+
+        let options = PrometheusOptions::new().default_buckets(vec![10]);
+        let expected = "# TYPE errors counter\n\
+                        errors 2\n\
+                        # TYPE latency_count counter\n\
+                        latency_count 4\n\
+                        # TYPE requests counter\n\
+                        requests{op=\"get\"} 1\n\
+                        requests{op=\"put\"} 3\n\
+                        # TYPE size_min gauge\n\
+                        size_min 1\n\
+                        # TYPE size_max gauge\n\
+                        size_max 1\n\
+                        # TYPE size_sum gauge\n\
+                        size_sum 1\n\
+                        # TYPE size_count gauge\n\
+                        size_count 1\n";
+        assert_eq!(snapshot.to_prometheus(&options), expected);
+    }
+
+    #[test]
+    fn help_from_any_series() {
+        let mut snapshot = Snapshot::new();
+        for (host, description) in [("a", None), ("b", Some("open connections"))] {
+            let mut metadata = HashMap::from([("host".to_string(), host.to_string())]);
+            if let Some(description) = description {
+                metadata.insert("description".to_string(), description.to_string());
+            }
+
+            snapshot.gauges.push(crate::Gauge {
+                name: "connections".to_string(),
+                metric_type: MetricType::Gauge,
+                value: 1,
+                metadata,
+            });
+        }
+
+        let expected = "# HELP connections open connections\n\
+                        # TYPE connections gauge\n\
+                        connections{host=\"a\"} 1\n\
+                        connections{host=\"b\"} 1\n";
+        assert_eq!(snapshot.to_prometheus(&PrometheusOptions::new()), expected);
+    }
+
+    #[test]
+    fn target_info() {
+        let snapshot = Snapshot::builder()
+            .resource("service.name", "api")
+            .resource("service.version", "1.2.3")
+            .metadata("source", "test")
+            .counter("requests", 3, &[])
+            .build()
+            .unwrap();
+
+        let expected = "# HELP target_info Target metadata\n\
+                        # TYPE target_info gauge\n\
+                        target_info{service_name=\"api\",service_version=\"1.2.3\"} 1\n\
+                        # TYPE requests counter\n\
+                        requests 3\n";
+        assert_eq!(snapshot.to_prometheus(&PrometheusOptions::new()), expected);
+    }
+
+    #[test]
+    fn human_values() {
+        let snapshot = Snapshot::builder()
+            .gauge("memory", 1_234_567, &[("unit", "bytes"), ("host", "a")])
+            .counter("requests", 3, &[("unit", "count")])
+            .build()
+            .unwrap();
+
+        let expected = "# TYPE requests counter\n\
+                        requests 3\n\
+                        # TYPE memory gauge\n\
+                        memory{host=\"a\"} 1234567\n\
+                        # memory{host=\"a\"} 1.18 MiB\n";
+        let options = PrometheusOptions::new().human_values(true);
+        assert_eq!(snapshot.to_prometheus(&options), expected);
+
+        // the comments are ignored when parsing
+        let parsed = Snapshot::from_prometheus(&snapshot.to_prometheus(&options)).unwrap();
+        assert_eq!(parsed.gauges()[0].value, 1_234_567);
+    }
+
+    #[test]
+    fn normalize_units() {
+        let mut value = histogram::Histogram::new(2, 32).unwrap();
+        value.add(500_000, 3).unwrap();
+
+        let unit = |unit: &str| HashMap::from([("unit".to_string(), unit.to_string())]);
+
+        let mut snapshot = Snapshot::new();
+        snapshot.gauges.push(crate::Gauge {
+            name: "memory".to_string(),
+            metric_type: MetricType::Gauge,
+            value: 1024,
+            metadata: unit("bytes"),
+        });
+        snapshot.gauges.push(crate::Gauge {
+            name: "utilization".to_string(),
+            metric_type: MetricType::Gauge,
+            value: 50,
+            metadata: unit("percent"),
+        });
+        snapshot.counters.push(crate::Counter {
+            name: "widgets".to_string(),
+            metric_type: MetricType::Counter,
+            value: 7,
+            metadata: unit("widgets"),
+        });
+        snapshot.histograms.push(Histogram {
+            name: "latency".to_string(),
+            metric_type: MetricType::Histogram,
+            value,
+            metadata: unit("nanoseconds"),
+        });
+
+        let options = PrometheusOptions::new()
+            .buckets("latency", vec![1_000_000])
+            .normalize_units(true);
+        let expected = "# TYPE widgets counter\n\
+                        widgets 7\n\
+                        # TYPE memory_bytes gauge\n\
+                        memory_bytes 1024\n\
+                        # TYPE utilization_ratio gauge\n\
+                        utilization_ratio 0.5\n\
+                        # TYPE latency_seconds histogram\n\
+                        latency_seconds_bucket{le=\"0.001\"} 3\n\
+                        latency_seconds_bucket{le=\"+Inf\"} 3\n\
+                        latency_seconds_sum 0.001474557\n\
+                        latency_seconds_count 3\n";
+        assert_eq!(snapshot.to_prometheus(&options), expected);
+
+        // units are not exported as labels when normalization is disabled
+        let options = PrometheusOptions::new().buckets("latency", vec![1_000_000]);
+        assert!(snapshot
+            .to_prometheus(&options)
+            .contains("latency_bucket{le=\"1000000\"} 3\n"));
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use crate::snapshot::{Snapshot, SnapshotError};
+
+/// Errors that can occur while parsing the Prometheus text exposition format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PrometheusError {
+    /// A line could not be parsed. Lines are numbered from one.
+    Syntax { line: usize, reason: &'static str },
+    /// The parsed metrics do not form a valid snapshot, such as when the same
+    /// series appears twice.
+    Snapshot(SnapshotError),
+}
+
+impl std::fmt::Display for PrometheusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Syntax { line, reason } => write!(f, "line {line}: {reason}"),
+            Self::Snapshot(e) => write!(f, "invalid snapshot: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for PrometheusError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Syntax { .. } => None,
+            Self::Snapshot(e) => Some(e),
+        }
+    }
+}
+
+impl From<SnapshotError> for PrometheusError {
+    fn from(e: SnapshotError) -> Self {
+        Self::Snapshot(e)
+    }
+}
+
+/// The `TYPE`, `HELP`, and `UNIT` lines for a metric family.
+#[derive(Default)]
+struct Family {
+    kind: Option<String>,
+    help: Option<String>,
+    unit: Option<String>,
+}
+
+/// How a sample is represented in the snapshot.
+enum Kind {
+    Counter,
+    Gauge,
+    Skip,
+}
+
+/// The family of a sample and the kind of metric it becomes.
+fn classify<'a>(families: &'a HashMap<String, Family>, name: &str) -> (Option<&'a Family>, Kind) {
+    if let Some(family) = families.get(name) {
+        let kind = match family.kind.as_deref() {
+            Some("counter") => Kind::Counter,
+            _ => Kind::Gauge,
+        };
+        return (Some(family), kind);
+    }
+
+    for suffix in ["_total", "_bucket", "_count", "_sum", "_created"] {
+        let Some(family) = name.strip_suffix(suffix).and_then(|f| families.get(f)) else {
+            continue;
+        };
+
+        let kind = match (family.kind.as_deref(), suffix) {
+            (_, "_created") => Kind::Skip,
+            (Some("counter"), "_total") => Kind::Counter,
+            (Some("histogram" | "summary"), "_bucket" | "_count" | "_sum") => Kind::Counter,
+            (Some("gaugehistogram"), _) => Kind::Gauge,
+            _ => continue,
+        };
+        return (Some(family), kind);
+    }
+
+    (None, Kind::Gauge)
+}
+
+impl Snapshot {
+    /// Parse metrics in the Prometheus or OpenMetrics text exposition format,
+    /// such as those scraped from another process, into a snapshot.
+    ///
+    /// Labels become metric metadata, and `HELP` and `UNIT` lines are stored
+    /// in the `description` and `unit` metadata keys. Counters become
+    /// counters and every other sample becomes a gauge, with two exceptions:
+    /// the `_bucket`, `_sum`, and `_count` samples of histograms and
+    /// summaries are counters, keeping their suffix and `le` label, and
+    /// `_created` samples are dropped. Metric names are kept as they are.
+    ///
+    /// Snapshot values are integers, so values are rounded to the nearest
+    /// integer. Samples which cannot be represented, such as `NaN` or
+    /// negative counters, are dropped. Sample timestamps are ignored and the
+    /// snapshot has the current time.
+    ///
+    /// ```
+    /// # use metriken_exposition::Snapshot;
+    /// let text = "# TYPE requests counter\nrequests{method=\"GET\"} 42\n";
+    /// let snapshot = Snapshot::from_prometheus(text).unwrap();
+    /// assert_eq!(snapshot.counters()[0].value, 42);
+    /// assert_eq!(snapshot.counters()[0].metadata["method"], "GET");
+    /// ```
+    pub fn from_prometheus(text: &str) -> Result<Snapshot, PrometheusError> {
+        let mut families: HashMap<String, Family> = HashMap::new();
+        let mut samples = Vec::new();
+
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let syntax = |reason| PrometheusError::Syntax {
+                line: idx + 1,
+                reason,
+            };
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(comment) = line.strip_prefix('#') {
+                let mut parts = comment.trim_start().splitn(3, ' ');
+                let keyword = parts.next().unwrap_or_default();
+                if keyword == "EOF" {
+                    break;
+                }
+                if !matches!(keyword, "TYPE" | "HELP" | "UNIT") {
+                    continue;
+                }
+
+                let name = parts.next().ok_or_else(|| syntax("missing metric name"))?;
+                let value = parts.next().unwrap_or_default().trim();
+                let family = families.entry(name.to_string()).or_default();
+                match keyword {
+                    "TYPE" => family.kind = Some(value.to_ascii_lowercase()),
+                    "HELP" => family.help = Some(unescape(value, false)),
+                    _ => family.unit = Some(value.to_string()).filter(|u| !u.is_empty()),
+                }
+                continue;
+            }
+
+            samples.push((idx + 1, parse_sample(line).map_err(syntax)?));
+        }
+
+        let mut builder = Snapshot::builder();
+        for (line, (name, labels, value)) in samples {
+            let (family, kind) = classify(&families, &name);
+
+            let mut metadata: Vec<(&str, &str)> = labels
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            if let Some(help) = family.and_then(|f| f.help.as_deref()) {
+                metadata.push(("description", help));
+            }
+            if let Some(unit) = family.and_then(|f| f.unit.as_deref()) {
+                metadata.push(("unit", unit));
+            }
+
+            let value: f64 = match value.as_str() {
+                "+Inf" | "Inf" => f64::INFINITY,
+                "-Inf" => f64::NEG_INFINITY,
+                value => value.parse().map_err(|_| PrometheusError::Syntax {
+                    line,
+                    reason: "invalid sample value",
+                })?,
+            };
+            if !value.is_finite() {
+                continue;
+            }
+            let value = value.round();
+
+            builder = match kind {
+                Kind::Counter if value >= 0.0 && value <= u64::MAX as f64 => {
+                    builder.counter(name, value as u64, &metadata)
+                }
+                Kind::Gauge if value >= i64::MIN as f64 && value <= i64::MAX as f64 => {
+                    builder.gauge(name, value as i64, &metadata)
+                }
+                _ => builder,
+            };
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// A sample name, its labels, and its unparsed value.
+type Sample = (String, Vec<(String, String)>, String);
+
+/// Parse a sample line: a metric name, optional labels, a value, and an
+/// optional timestamp and exemplar which are ignored.
+fn parse_sample(line: &str) -> Result<Sample, &'static str> {
+    let end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or("missing sample value")?;
+    let (name, mut rest) = line.split_at(end);
+    if name.is_empty() {
+        return Err("missing metric name");
+    }
+
+    let mut labels = Vec::new();
+    if let Some(mut remaining) = rest.strip_prefix('{') {
+        loop {
+            remaining = remaining.trim_start_matches([' ', ',']);
+            if let Some(after) = remaining.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+
+            let (label, after) = remaining.split_once('=').ok_or("invalid label")?;
+            let after = after
+                .trim_start()
+                .strip_prefix('"')
+                .ok_or("invalid label")?;
+
+            // find the closing quote, skipping escaped characters
+            let mut escaped = false;
+            let close = after
+                .char_indices()
+                .find(|(_, c)| {
+                    let close = *c == '"' && !escaped;
+                    escaped = *c == '\\' && !escaped;
+                    close
+                })
+                .map(|(idx, _)| idx)
+                .ok_or("unterminated label value")?;
+
+            labels.push((label.trim().to_string(), unescape(&after[..close], true)));
+            remaining = &after[close + 1..];
+        }
+    }
+
+    let value = rest
+        .split_whitespace()
+        .next()
+        .ok_or("missing sample value")?;
+
+    Ok((name.to_string(), labels, value.to_string()))
+}
+
+/// Undo the escaping of `HELP` text and label values.
+fn unescape(value: &str, quotes: bool) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('\\') => unescaped.push('\\'),
+            Some('"') if quotes => unescaped.push('"'),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PrometheusOptions;
+
+    #[test]
+    fn parse() {
+        let text = r#"
+# HELP http_requests_total Requests handled.
+# TYPE http_requests_total counter
+http_requests_total{method="GET",path="/a \"b\""} 1027 1395066363000
+http_requests_total{method="POST"} 3
+# TYPE temperature gauge
+# UNIT temperature celsius
+temperature -3.6
+# TYPE latency histogram
+latency_bucket{le="0.1"} 2
+latency_bucket{le="+Inf"} 5
+latency_sum 1.5
+latency_count 5
+latency_created 1395066363
+untyped_thing NaN
+# EOF
+ignored 1
+"#;
+        let snapshot = Snapshot::from_prometheus(text).unwrap();
+
+        let counters: Vec<(&str, u64)> = snapshot
+            .counters()
+            .iter()
+            .map(|c| (c.name.as_str(), c.value))
+            .collect();
+        assert_eq!(
+            counters,
+            [
+                ("http_requests_total", 1027),
+                ("http_requests_total", 3),
+                ("latency_bucket", 5),
+                ("latency_bucket", 2),
+                ("latency_count", 5),
+                ("latency_sum", 2),
+            ]
+        );
+
+        let get = &snapshot.counters()[0];
+        assert_eq!(get.metadata["path"], "/a \"b\"");
+        assert_eq!(get.description(), Some("Requests handled."));
+        assert_eq!(snapshot.counters()[2].metadata["le"], "+Inf");
+
+        assert_eq!(snapshot.gauges().len(), 1);
+        assert_eq!(snapshot.gauges()[0].value, -4);
+        assert_eq!(snapshot.gauges()[0].metadata["unit"], "celsius");
+    }
+
+    #[test]
+    fn openmetrics_counter() {
+        let text = "# TYPE requests counter\nrequests_total 7\nrequests_created 1.0\n# EOF\n";
+        let snapshot = Snapshot::from_prometheus(text).unwrap();
+        assert_eq!(snapshot.counters().len(), 1);
+        assert_eq!(snapshot.counters()[0].name, "requests_total");
+        assert!(snapshot.gauges().is_empty());
+    }
+
+    #[test]
+    fn round_trip() {
+        let exported = Snapshot::builder()
+            .counter("requests", 10, &[("host", "a")])
+            .gauge("connections", -2, &[])
+            .build()
+            .unwrap()
+            .to_prometheus(&PrometheusOptions::new());
+
+        let snapshot = Snapshot::from_prometheus(&exported).unwrap();
+        assert_eq!(snapshot.counters()[0].value, 10);
+        assert_eq!(snapshot.counters()[0].metadata["host"], "a");
+        assert_eq!(snapshot.gauges()[0].value, -2);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Snapshot::from_prometheus("ok 1\nbad{le=\"1} 2\n").err(),
+            Some(PrometheusError::Syntax {
+                line: 2,
+                reason: "unterminated label value"
+            })
+        );
+        assert_eq!(
+            Snapshot::from_prometheus("a one").err(),
+            Some(PrometheusError::Syntax {
+                line: 1,
+                reason: "invalid sample value"
+            })
+        );
+        assert!(matches!(
+            Snapshot::from_prometheus("a 1\na 2\n"),
+            Err(PrometheusError::Snapshot(SnapshotError::Duplicate(_)))
+        ));
+    }
+}
@@ -0,0 +1,358 @@
+//! Protobuf messages matching `proto/snapshot.proto`.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use prost::Message;
+
+use crate::snapshot;
+
+/// Errors that can occur while decoding a protobuf snapshot.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ProtobufError {
+    Decode(prost::DecodeError),
+    Histogram(histogram::Error),
+}
+
+impl std::fmt::Display for ProtobufError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Decode(e) => write!(f, "protobuf decode error: {e}"),
+            Self::Histogram(e) => write!(f, "invalid histogram: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtobufError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode(e) => Some(e),
+            Self::Histogram(e) => Some(e),
+        }
+    }
+}
+
+impl From<prost::DecodeError> for ProtobufError {
+    fn from(e: prost::DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+impl From<histogram::Error> for ProtobufError {
+    fn from(e: histogram::Error) -> Self {
+        Self::Histogram(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum MetricType {
+    Unspecified = 0,
+    Counter = 1,
+    DeltaCounter = 2,
+    Gauge = 3,
+    Histogram = 4,
+    Summary = 5,
+    Sketch = 6,
+    Cardinality = 7,
+    TopK = 8,
+    DeltaHistogram = 9,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Snapshot {
+    #[prost(uint64, tag = "1")]
+    timestamp: u64,
+    #[prost(map = "string, string", tag = "2")]
+    metadata: HashMap<String, String>,
+    #[prost(message, repeated, tag = "3")]
+    counters: Vec<Counter>,
+    #[prost(message, repeated, tag = "4")]
+    gauges: Vec<Gauge>,
+    #[prost(message, repeated, tag = "5")]
+    histograms: Vec<Histogram>,
+    #[prost(message, repeated, tag = "6")]
+    stats: Vec<Stats>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Counter {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(enumeration = "MetricType", tag = "2")]
+    metric_type: i32,
+    #[prost(uint64, tag = "3")]
+    value: u64,
+    #[prost(map = "string, string", tag = "4")]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Gauge {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(enumeration = "MetricType", tag = "2")]
+    metric_type: i32,
+    #[prost(int64, tag = "3")]
+    value: i64,
+    #[prost(map = "string, string", tag = "4")]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Histogram {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(enumeration = "MetricType", tag = "2")]
+    metric_type: i32,
+    #[prost(uint32, tag = "3")]
+    grouping_power: u32,
+    #[prost(uint32, tag = "4")]
+    max_value_power: u32,
+    #[prost(uint64, repeated, tag = "5")]
+    bucket_indices: Vec<u64>,
+    #[prost(uint64, repeated, tag = "6")]
+    bucket_counts: Vec<u64>,
+    #[prost(map = "string, string", tag = "7")]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Stats {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(enumeration = "MetricType", tag = "2")]
+    metric_type: i32,
+    #[prost(uint64, optional, tag = "3")]
+    min: Option<u64>,
+    #[prost(uint64, optional, tag = "4")]
+    max: Option<u64>,
+    #[prost(uint64, tag = "5")]
+    sum: u64,
+    #[prost(uint64, tag = "6")]
+    count: u64,
+    #[prost(map = "string, string", tag = "7")]
+    metadata: HashMap<String, String>,
+}
+
+impl From<snapshot::MetricType> for MetricType {
+    fn from(metric_type: snapshot::MetricType) -> Self {
+        match metric_type {
+            snapshot::MetricType::Counter => Self::Counter,
+            snapshot::MetricType::DeltaCounter => Self::DeltaCounter,
+            snapshot::MetricType::Gauge => Self::Gauge,
+            snapshot::MetricType::Histogram => Self::Histogram,
+            snapshot::MetricType::DeltaHistogram => Self::DeltaHistogram,
+            snapshot::MetricType::Summary => Self::Summary,
+            snapshot::MetricType::Sketch => Self::Sketch,
+            snapshot::MetricType::Cardinality => Self::Cardinality,
+            snapshot::MetricType::TopK => Self::TopK,
+        }
+    }
+}
+
+/// Decode a metric type, falling back to the type implied by the section of
+/// the snapshot if it is unspecified or unknown.
+fn metric_type(value: i32, default: snapshot::MetricType) -> snapshot::MetricType {
+    match MetricType::try_from(value) {
+        Ok(MetricType::Counter) => snapshot::MetricType::Counter,
+        Ok(MetricType::DeltaCounter) => snapshot::MetricType::DeltaCounter,
+        Ok(MetricType::Gauge) => snapshot::MetricType::Gauge,
+        Ok(MetricType::Histogram) => snapshot::MetricType::Histogram,
+        Ok(MetricType::DeltaHistogram) => snapshot::MetricType::DeltaHistogram,
+        Ok(MetricType::Summary) => snapshot::MetricType::Summary,
+        Ok(MetricType::Sketch) => snapshot::MetricType::Sketch,
+        Ok(MetricType::Cardinality) => snapshot::MetricType::Cardinality,
+        Ok(MetricType::TopK) => snapshot::MetricType::TopK,
+        _ => default,
+    }
+}
+
+impl snapshot::Snapshot {
+    /// Encode this snapshot using the protobuf schema in
+    /// `proto/snapshot.proto`.
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        let message = Snapshot {
+            timestamp: self
+                .systemtime
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            metadata: self.metadata.clone(),
+            counters: self
+                .counters
+                .iter()
+                .map(|c| Counter {
+                    name: c.name.clone(),
+                    metric_type: MetricType::from(c.metric_type) as i32,
+                    value: c.value,
+                    metadata: c.metadata.clone(),
+                })
+                .collect(),
+            gauges: self
+                .gauges
+                .iter()
+                .map(|g| Gauge {
+                    name: g.name.clone(),
+                    metric_type: MetricType::from(g.metric_type) as i32,
+                    value: g.value,
+                    metadata: g.metadata.clone(),
+                })
+                .collect(),
+            histograms: self
+                .histograms
+                .iter()
+                .map(|h| {
+                    let sparse = histogram::SparseHistogram::from(&h.value);
+                    Histogram {
+                        name: h.name.clone(),
+                        metric_type: MetricType::from(h.metric_type) as i32,
+                        grouping_power: sparse.config.grouping_power() as u32,
+                        max_value_power: sparse.config.max_value_power() as u32,
+                        bucket_indices: sparse.index.iter().map(|i| *i as u64).collect(),
+                        bucket_counts: sparse.count,
+                        metadata: h.metadata.clone(),
+                    }
+                })
+                .collect(),
+            stats: self
+                .stats
+                .iter()
+                .map(|s| Stats {
+                    name: s.name.clone(),
+                    metric_type: MetricType::from(s.metric_type) as i32,
+                    min: s.min,
+                    max: s.max,
+                    sum: s.sum,
+                    count: s.count,
+                    metadata: s.metadata.clone(),
+                })
+                .collect(),
+        };
+
+        message.encode_to_vec()
+    }
+
+    /// Decode a snapshot that was encoded with [`Self::to_protobuf`].
+    pub fn from_protobuf(bytes: &[u8]) -> Result<Self, ProtobufError> {
+        let message = Snapshot::decode(bytes)?;
+
+        let mut snapshot = Self::new();
+        snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_nanos(message.timestamp);
+        snapshot.metadata = message.metadata;
+
+        for c in message.counters {
+            snapshot.counters.push(snapshot::Counter {
+                name: c.name,
+                metric_type: metric_type(c.metric_type, snapshot::MetricType::Counter),
+                value: c.value,
+                metadata: c.metadata,
+            });
+        }
+
+        for g in message.gauges {
+            snapshot.gauges.push(snapshot::Gauge {
+                name: g.name,
+                metric_type: metric_type(g.metric_type, snapshot::MetricType::Gauge),
+                value: g.value,
+                metadata: g.metadata,
+            });
+        }
+
+        for h in message.histograms {
+            let config = histogram::Config::new(
+                h.grouping_power.try_into().unwrap_or(u8::MAX),
+                h.max_value_power.try_into().unwrap_or(u8::MAX),
+            )?;
+
+            if h.bucket_indices.len() != h.bucket_counts.len()
+                || h.bucket_indices
+                    .iter()
+                    .any(|i| *i as usize >= config.total_buckets())
+            {
+                return Err(histogram::Error::OutOfRange.into());
+            }
+
+            let sparse = histogram::SparseHistogram {
+                config,
+                index: h.bucket_indices.into_iter().map(|i| i as usize).collect(),
+                count: h.bucket_counts,
+            };
+
+            snapshot.histograms.push(snapshot::Histogram {
+                name: h.name,
+                metric_type: metric_type(h.metric_type, snapshot::MetricType::Histogram),
+                value: histogram::Histogram::from(&sparse),
+                metadata: h.metadata,
+            });
+        }
+
+        for s in message.stats {
+            snapshot.stats.push(snapshot::Stats {
+                name: s.name,
+                metric_type: metric_type(s.metric_type, snapshot::MetricType::Summary),
+                min: s.min,
+                max: s.max,
+                sum: s.sum,
+                count: s.count,
+                metadata: s.metadata,
+            });
+        }
+
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut value = histogram::Histogram::new(4, 10).unwrap();
+        value.add(100, 3).unwrap();
+
+        let mut original = snapshot::Snapshot::new();
+        original
+            .metadata
+            .insert("source".to_string(), "test".to_string());
+        original.counters.push(snapshot::Counter {
+            name: "counter".to_string(),
+            metric_type: snapshot::MetricType::DeltaCounter,
+            value: 5,
+            metadata: HashMap::new(),
+        });
+        original.histograms.push(snapshot::Histogram {
+            name: "histogram".to_string(),
+            metric_type: snapshot::MetricType::DeltaHistogram,
+            value,
+            metadata: HashMap::new(),
+        });
+        original.stats.push(snapshot::Stats {
+            name: "stats".to_string(),
+            metric_type: snapshot::MetricType::Summary,
+            min: None,
+            max: Some(7),
+            sum: 7,
+            count: 1,
+            metadata: HashMap::new(),
+        });
+
+        let decoded = snapshot::Snapshot::from_protobuf(&original.to_protobuf()).unwrap();
+        assert_eq!(decoded.systemtime, original.systemtime);
+        assert_eq!(decoded.metadata, original.metadata);
+        assert_eq!(decoded.counters[0].value, 5);
+        assert_eq!(
+            decoded.counters[0].metric_type,
+            snapshot::MetricType::DeltaCounter
+        );
+        assert_eq!(decoded.histograms[0].value, original.histograms[0].value);
+        assert_eq!(
+            decoded.histograms[0].metric_type,
+            snapshot::MetricType::DeltaHistogram
+        );
+        assert_eq!(decoded.stats[0].min, None);
+        assert_eq!(decoded.stats[0].max, Some(7));
+    }
+}
@@ -0,0 +1,567 @@
+use std::collections::hash_map::RandomState;
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+use std::fs::{File, OpenOptions};
+use std::hash::BuildHasher;
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+use std::io::Write;
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+use std::path::Path;
+use std::time::Duration;
+#[cfg(feature = "snapshotter")]
+use std::time::Instant;
+
+#[cfg(feature = "snapshotter")]
+use metriken::{Counter, DynBoxedMetric, MetricBuilder};
+
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+use crate::recording::RecordingError;
+#[cfg(any(feature = "snapshotter", all(feature = "serde", feature = "msgpack")))]
+use crate::snapshot::Snapshot;
+#[cfg(feature = "snapshotter")]
+use crate::snapshot::{Event, Gauge, Histogram, Stats};
+
+/// Limits on the requests made by an exporter which pushes snapshots to a
+/// remote endpoint.
+///
+/// By default there are no limits.
+#[derive(Clone, Debug, Default)]
+pub struct PushLimits {
+    max_payload_bytes: Option<usize>,
+    max_metrics: Option<usize>,
+    min_interval: Option<Duration>,
+}
+
+impl PushLimits {
+    /// Create a new set of limits with nothing limited.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The largest encoded payload which can be sent in one request.
+    /// Snapshots which encode to more than this are split across several
+    /// requests.
+    pub fn max_payload_bytes(mut self, bytes: usize) -> Self {
+        self.max_payload_bytes = Some(bytes);
+        self
+    }
+
+    /// The largest number of metrics which can be sent in one request.
+    pub fn max_metrics(mut self, metrics: usize) -> Self {
+        self.max_metrics = Some(metrics.max(1));
+        self
+    }
+
+    /// The shortest time allowed between the start of one send and the next.
+    pub fn min_interval(mut self, interval: Duration) -> Self {
+        self.min_interval = Some(interval);
+        self
+    }
+}
+
+/// How an exporter retries a send which failed.
+///
+/// The delay before each retry grows exponentially from the initial backoff
+/// up to the maximum backoff. Jitter reduces each delay by a random fraction
+/// of up to the configured amount so that many exporters recovering from the
+/// same outage do not retry in lockstep.
+///
+/// By default a send is attempted up to 5 times, starting with a 100ms
+/// backoff which doubles after each attempt up to 30s, with 20% jitter.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Create a new policy with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The total number of attempts, including the first. A value of 1
+    /// disables retries.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// The delay before the first retry.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// The longest delay between two attempts.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// The factor by which the delay grows after each retry.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// The largest fraction by which each delay is randomly reduced, between
+    /// 0 and 1.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The delay before the next attempt after `attempt` attempts have
+    /// failed. Returns `None` once all attempts have been used.
+    pub fn backoff(&self, attempt: u32) -> Option<Duration> {
+        if attempt == 0 || attempt >= self.max_attempts {
+            return None;
+        }
+
+        let exponent = (attempt - 1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+
+        // A random value in [0, 1). This doesn't need to be high quality, it
+        // only needs to differ between exporters.
+        let random = (RandomState::new().hash_one(attempt) >> 11) as f64 / (1u64 << 53) as f64;
+
+        Some(Duration::from_secs_f64(
+            backoff * (1.0 - self.jitter * random),
+        ))
+    }
+
+    /// Call `send` until it succeeds or all attempts have been used, sleeping
+    /// between attempts. `send` is passed the number of the attempt, starting
+    /// at 1. Returns the error from the final attempt if none succeeded.
+    pub fn retry<T, E>(&self, mut send: impl FnMut(u32) -> Result<T, E>) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match send(attempt) {
+                Ok(value) => return Ok(value),
+                Err(e) => match self.backoff(attempt) {
+                    Some(delay) => {
+                        #[cfg(feature = "log")]
+                        log::debug!("attempt {attempt} failed, retrying in {delay:?}");
+                        std::thread::sleep(delay)
+                    }
+                    None => {
+                        #[cfg(feature = "log")]
+                        log::warn!("giving up after {attempt} attempts");
+                        return Err(e);
+                    }
+                },
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// Persists snapshots which could not be sent so that they are not lost.
+///
+/// Snapshots are appended to the file as a msgpack recording, which can be
+/// replayed with a [`RecordingReader`] once the destination is reachable
+/// again. Each snapshot is flushed as it is written.
+///
+/// [`RecordingReader`]: crate::RecordingReader
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+pub struct DeadLetterFile {
+    file: File,
+    written: u64,
+}
+
+#[cfg(all(feature = "serde", feature = "msgpack"))]
+impl DeadLetterFile {
+    /// Open the file at the provided path for appending, creating it if it
+    /// does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file, written: 0 })
+    }
+
+    /// Append a snapshot to the file.
+    pub fn push(&mut self, snapshot: &Snapshot) -> Result<(), RecordingError> {
+        let bytes = Snapshot::to_msgpack(snapshot)?;
+        self.file.write_all(&bytes)?;
+        self.file.flush()?;
+        self.written += 1;
+
+        #[cfg(feature = "log")]
+        log::warn!(
+            "snapshot written to the dead letter file ({} bytes)",
+            bytes.len()
+        );
+
+        Ok(())
+    }
+
+    /// The number of snapshots written since the file was opened.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+#[cfg(feature = "snapshotter")]
+/// A single metric or event from a snapshot.
+#[derive(Clone, Copy)]
+enum Item<'a> {
+    Counter(&'a crate::snapshot::Counter),
+    Gauge(&'a Gauge),
+    Histogram(&'a Histogram),
+    Stats(&'a Stats),
+    Sketch(&'a crate::snapshot::Sketch),
+    Cardinality(&'a crate::snapshot::Cardinality),
+    TopK(&'a crate::snapshot::TopK),
+    Event(&'a Event),
+}
+
+#[cfg(feature = "snapshotter")]
+/// Applies [`PushLimits`] to the snapshots sent by a push exporter.
+///
+/// The limiter is independent of the transport. An exporter calls
+/// [`try_acquire`] before each send and [`batch`] to split a snapshot into
+/// payloads that fit within the limits.
+///
+/// The limiter registers three counters so that overflow is visible in the
+/// snapshots it is pushing:
+///
+/// * `<name>/push/deferred` - sends skipped because of the minimum interval
+/// * `<name>/push/split` - extra payloads created by splitting snapshots
+/// * `<name>/push/dropped` - metrics dropped because they alone exceed the
+///   maximum payload size
+///
+/// [`try_acquire`]: PushLimiter::try_acquire
+/// [`batch`]: PushLimiter::batch
+pub struct PushLimiter {
+    limits: PushLimits,
+    last_send: Option<Instant>,
+    deferred: DynBoxedMetric<Counter>,
+    split: DynBoxedMetric<Counter>,
+    dropped: DynBoxedMetric<Counter>,
+}
+
+#[cfg(feature = "snapshotter")]
+impl PushLimiter {
+    /// Create a limiter for the exporter with the provided name. The name is
+    /// used as the prefix for the overflow metrics.
+    pub fn new(name: &str, limits: PushLimits) -> Self {
+        let counter = |suffix: &str, description: &'static str| {
+            MetricBuilder::new(format!("{name}/push/{suffix}"))
+                .description(description)
+                .build(Counter::new())
+        };
+
+        Self {
+            limits,
+            last_send: None,
+            deferred: counter(
+                "deferred",
+                "sends skipped because of the minimum send interval",
+            ),
+            split: counter("split", "extra payloads created by splitting snapshots"),
+            dropped: counter(
+                "dropped",
+                "metrics dropped for exceeding the maximum payload size",
+            ),
+        }
+    }
+
+    /// The limits applied by this limiter.
+    pub fn limits(&self) -> &PushLimits {
+        &self.limits
+    }
+
+    /// Returns true if a send may start now, and records it as the time of
+    /// the last send. Returns false if the minimum interval has not elapsed
+    /// since the last send, in which case the exporter should skip this send.
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        if let (Some(last), Some(interval)) = (self.last_send, self.limits.min_interval) {
+            if now.saturating_duration_since(last) < interval {
+                #[cfg(feature = "log")]
+                log::debug!("send deferred by the minimum interval of {interval:?}");
+                self.deferred.increment();
+                return false;
+            }
+        }
+
+        self.last_send = Some(now);
+        true
+    }
+
+    /// Split a snapshot into encoded payloads which are within the limits.
+    ///
+    /// Each payload is the encoding of a snapshot with the time and metadata
+    /// of the original and a subset of its metrics and events. Each event is
+    /// sent once and counts as a metric towards the limits. Metrics are first
+    /// split into groups of at most the maximum number of metrics, and any
+    /// group which encodes to more than the maximum payload size is halved
+    /// until it fits. A metric which is too large to be sent on its own is
+    /// dropped.
+    pub fn batch<E>(
+        &self,
+        snapshot: &Snapshot,
+        mut encode: impl FnMut(&Snapshot) -> Result<Vec<u8>, E>,
+    ) -> Result<Vec<Vec<u8>>, E> {
+        let items: Vec<Item> = snapshot
+            .counters
+            .iter()
+            .map(Item::Counter)
+            .chain(snapshot.gauges.iter().map(Item::Gauge))
+            .chain(snapshot.histograms.iter().map(Item::Histogram))
+            .chain(snapshot.stats.iter().map(Item::Stats))
+            .chain(snapshot.sketches.iter().map(Item::Sketch))
+            .chain(snapshot.cardinalities.iter().map(Item::Cardinality))
+            .chain(snapshot.top_k.iter().map(Item::TopK))
+            .chain(snapshot.events.iter().map(Item::Event))
+            .collect();
+
+        let mut payloads = Vec::new();
+
+        if items.is_empty() {
+            payloads.push(encode(&subset(snapshot, &[]))?);
+            return Ok(payloads);
+        }
+
+        let chunk = self.limits.max_metrics.unwrap_or(items.len());
+        for items in items.chunks(chunk) {
+            self.encode_within_limit(snapshot, items, &mut encode, &mut payloads)?;
+        }
+
+        if payloads.len() > 1 {
+            self.split.add(payloads.len() as u64 - 1);
+        }
+
+        Ok(payloads)
+    }
+
+    fn encode_within_limit<E>(
+        &self,
+        snapshot: &Snapshot,
+        items: &[Item],
+        encode: &mut impl FnMut(&Snapshot) -> Result<Vec<u8>, E>,
+        payloads: &mut Vec<Vec<u8>>,
+    ) -> Result<(), E> {
+        let payload = encode(&subset(snapshot, items))?;
+
+        match self.limits.max_payload_bytes {
+            Some(max) if payload.len() > max => {
+                if items.len() == 1 {
+                    #[cfg(feature = "log")]
+                    log::warn!(
+                        "metric dropped: {} bytes exceeds the maximum payload of {max} bytes",
+                        payload.len()
+                    );
+                    self.dropped.increment();
+                    return Ok(());
+                }
+
+                let (left, right) = items.split_at(items.len() / 2);
+                self.encode_within_limit(snapshot, left, encode, payloads)?;
+                self.encode_within_limit(snapshot, right, encode, payloads)
+            }
+            _ => {
+                payloads.push(payload);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "snapshotter")]
+/// Build a snapshot with the time and metadata of the original and only the
+/// provided metrics and events.
+fn subset(snapshot: &Snapshot, items: &[Item]) -> Snapshot {
+    let mut subset = Snapshot::new();
+    subset.systemtime = snapshot.systemtime;
+    subset.metadata = snapshot.metadata.clone();
+
+    for item in items {
+        match item {
+            Item::Counter(m) => subset.counters.push((*m).clone()),
+            Item::Gauge(m) => subset.gauges.push((*m).clone()),
+            Item::Histogram(m) => subset.histograms.push((*m).clone()),
+            Item::Stats(m) => subset.stats.push((*m).clone()),
+            Item::Sketch(m) => subset.sketches.push((*m).clone()),
+            Item::Cardinality(m) => subset.cardinalities.push((*m).clone()),
+            Item::TopK(m) => subset.top_k.push((*m).clone()),
+            Item::Event(e) => subset.events.push((*e).clone()),
+        }
+    }
+
+    subset
+}
+
+#[cfg(all(test, feature = "snapshotter"))]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::snapshot::{Counter, MetricType};
+
+    fn build_snapshot(count: usize) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        for i in 0..count {
+            snapshot.counters.push(Counter {
+                name: format!("counter_{i}"),
+                metric_type: MetricType::Counter,
+                value: i as u64,
+                metadata: HashMap::new(),
+            });
+        }
+        snapshot
+    }
+
+    /// Encodes each metric as its name on its own line.
+    fn encode(snapshot: &Snapshot) -> Result<Vec<u8>, ()> {
+        Ok(snapshot
+            .counters
+            .iter()
+            .map(|c| format!("{}\n", c.name))
+            .collect::<String>()
+            .into_bytes())
+    }
+
+    #[test]
+    fn max_metrics() {
+        let limiter = PushLimiter::new("test_max_metrics", PushLimits::new().max_metrics(4));
+
+        let payloads = limiter.batch(&build_snapshot(10), encode).unwrap();
+        let lines: Vec<usize> = payloads
+            .iter()
+            .map(|p| p.iter().filter(|b| **b == b'\n').count())
+            .collect();
+        assert_eq!(lines, [4, 4, 2]);
+        assert_eq!(limiter.split.value(), 2);
+    }
+
+    #[test]
+    fn max_payload_bytes() {
+        let limiter = PushLimiter::new(
+            "test_max_payload_bytes",
+            PushLimits::new().max_payload_bytes(24),
+        );
+
+        // each counter encodes to 10 bytes, so at most two fit in a payload
+        let payloads = limiter.batch(&build_snapshot(5), encode).unwrap();
+        assert!(payloads.iter().all(|p| p.len() <= 24));
+        assert_eq!(payloads.iter().map(|p| p.len()).sum::<usize>(), 50);
+        assert_eq!(limiter.dropped.value(), 0);
+
+        // a metric which can never fit is dropped
+        let limiter = PushLimiter::new("test_dropped", PushLimits::new().max_payload_bytes(5));
+        let payloads = limiter.batch(&build_snapshot(3), encode).unwrap();
+        assert!(payloads.is_empty());
+        assert_eq!(limiter.dropped.value(), 3);
+    }
+
+    #[test]
+    fn min_interval() {
+        let mut limiter = PushLimiter::new(
+            "test_min_interval",
+            PushLimits::new().min_interval(Duration::from_secs(10)),
+        );
+
+        let start = Instant::now();
+        assert!(limiter.try_acquire_at(start));
+        assert!(!limiter.try_acquire_at(start + Duration::from_secs(5)));
+        assert!(limiter.try_acquire_at(start + Duration::from_secs(10)));
+        assert_eq!(limiter.deferred.value(), 1);
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::new()
+            .max_attempts(4)
+            .initial_backoff(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(3))
+            .jitter(0.0);
+
+        assert_eq!(policy.backoff(0), None);
+        assert_eq!(policy.backoff(1), Some(Duration::from_secs(1)));
+        assert_eq!(policy.backoff(2), Some(Duration::from_secs(2)));
+        assert_eq!(policy.backoff(3), Some(Duration::from_secs(3)));
+        assert_eq!(policy.backoff(4), None);
+
+        let policy = policy.jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.backoff(2).unwrap();
+            assert!(delay > Duration::from_secs(1) && delay <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn retry() {
+        let policy = RetryPolicy::new()
+            .max_attempts(3)
+            .initial_backoff(Duration::from_millis(1));
+
+        let mut attempts = Vec::new();
+        let result: Result<(), u32> = policy.retry(|attempt| {
+            attempts.push(attempt);
+            Err(attempt)
+        });
+        assert_eq!(result, Err(3));
+        assert_eq!(attempts, [1, 2, 3]);
+
+        let result: Result<u32, ()> =
+            policy.retry(|attempt| if attempt == 2 { Ok(attempt) } else { Err(()) });
+        assert_eq!(result, Ok(2));
+    }
+
+    #[cfg(all(feature = "serde", feature = "msgpack"))]
+    #[test]
+    fn dead_letter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead-letter");
+
+        let snapshots = [build_snapshot(1), build_snapshot(2)];
+        let mut file = DeadLetterFile::open(&path).unwrap();
+        file.push(&snapshots[0]).unwrap();
+        drop(file);
+
+        // reopening appends to the existing file
+        let mut file = DeadLetterFile::open(&path).unwrap();
+        file.push(&snapshots[1]).unwrap();
+        assert_eq!(file.written(), 1);
+
+        let replayed = crate::RecordingReader::open(&path)
+            .unwrap()
+            .range(
+                std::time::SystemTime::UNIX_EPOCH,
+                std::time::SystemTime::now() + Duration::from_secs(60),
+            )
+            .unwrap();
+        assert_eq!(replayed.len(), 2);
+        assert_eq!(replayed[1].counters.len(), 2);
+    }
+
+    #[test]
+    fn unlimited() {
+        let mut limiter = PushLimiter::new("test_unlimited", PushLimits::new());
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+
+        let payloads = limiter.batch(&build_snapshot(100), encode).unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(limiter.split.value(), 0);
+    }
+}
@@ -0,0 +1,99 @@
+use histogram::{Config, Error, Histogram};
+
+/// The smallest histogram configuration which can hold the values of
+/// histograms with either of the provided configurations.
+///
+/// Histograms with either configuration can be converted to the common one
+/// with [`rebucket`] without losing any counts, though the histograms with
+/// the finer grouping power lose some resolution.
+pub fn common_config(a: Config, b: Config) -> Config {
+    if a == b {
+        return a;
+    }
+
+    let grouping_power = a.grouping_power().min(b.grouping_power());
+    let max_value_power = a.max_value_power().max(b.max_value_power());
+
+    // the grouping power of either config is less than its max value power,
+    // so this combination is always valid
+    Config::new(grouping_power, max_value_power).unwrap_or(a)
+}
+
+/// Convert a histogram to a different configuration, such as when the
+/// configuration of a metric changed between snapshots.
+///
+/// The count of each bucket is moved to the bucket of the new configuration
+/// which contains its lower bound. Converting to a configuration with a lower
+/// or equal grouping power and a higher or equal max value power is exact in
+/// the sense that every value is counted in a bucket which covers it.
+///
+/// Returns [`Error::OutOfRange`] if the histogram has counts for values which
+/// are larger than the new configuration can hold.
+pub fn rebucket(histogram: &Histogram, config: &Config) -> Result<Histogram, Error> {
+    if histogram.config() == *config {
+        return Ok(histogram.clone());
+    }
+
+    let mut rebucketed = Histogram::with_config(config);
+    for bucket in histogram {
+        if bucket.count() == 0 {
+            continue;
+        }
+
+        let value = bucket.start();
+        if config.max_value_power() < 64 && value >= 1 << config.max_value_power() {
+            return Err(Error::OutOfRange);
+        }
+
+        rebucketed.add(value, bucket.count())?;
+    }
+
+    Ok(rebucketed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(grouping_power: u8, max_value_power: u8, values: &[u64]) -> Histogram {
+        let mut histogram = Histogram::new(grouping_power, max_value_power).unwrap();
+        for value in values {
+            histogram.increment(*value).unwrap();
+        }
+        histogram
+    }
+
+    #[test]
+    fn common() {
+        let a = Config::new(7, 32).unwrap();
+        let b = Config::new(4, 64).unwrap();
+        let common = common_config(a, b);
+        assert_eq!(common.grouping_power(), 4);
+        assert_eq!(common.max_value_power(), 64);
+        assert_eq!(common_config(a, a), a);
+    }
+
+    #[test]
+    fn coarser() {
+        let values = [0, 1, 17, 1000, 1 << 20];
+        let fine = histogram(7, 32, &values);
+        let config = common_config(fine.config(), Config::new(3, 40).unwrap());
+
+        let rebucketed = rebucket(&fine, &config).unwrap();
+        assert_eq!(rebucketed.config(), config);
+        assert_eq!(rebucketed, histogram(3, 40, &values));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let wide = histogram(2, 40, &[1 << 35]);
+        assert_eq!(
+            rebucket(&wide, &Config::new(2, 32).unwrap()),
+            Err(Error::OutOfRange)
+        );
+
+        // empty buckets above the new range are fine
+        let wide = histogram(2, 40, &[100]);
+        assert!(rebucket(&wide, &Config::new(2, 32).unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,559 @@
+#[cfg(feature = "parquet")]
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::SystemTime;
+
+use rmp_serde::decode::Error as DeserializeMsgpackError;
+use rmp_serde::encode::Error as SerializeMsgpackError;
+
+#[cfg(feature = "parquet")]
+use parquet::errors::ParquetError;
+
+use crate::msgpack::{unix_nanos, MsgpackDecoder, MsgpackIndex, MsgpackLayout};
+use crate::snapshot::Snapshot;
+
+/// The magic bytes at the start of every parquet file.
+#[cfg(feature = "parquet")]
+const PARQUET_MAGIC: &[u8; 4] = b"PAR1";
+
+/// Errors that can occur while reading a recording.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RecordingError {
+    Io(std::io::Error),
+    Msgpack(DeserializeMsgpackError),
+    MsgpackEncode(SerializeMsgpackError),
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetError),
+}
+
+impl std::fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Msgpack(e) => write!(f, "msgpack decode error: {e}"),
+            Self::MsgpackEncode(e) => write!(f, "msgpack encode error: {e}"),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(e) => write!(f, "parquet error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Msgpack(e) => Some(e),
+            Self::MsgpackEncode(e) => Some(e),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for RecordingError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<DeserializeMsgpackError> for RecordingError {
+    fn from(e: DeserializeMsgpackError) -> Self {
+        Self::Msgpack(e)
+    }
+}
+
+impl From<SerializeMsgpackError> for RecordingError {
+    fn from(e: SerializeMsgpackError) -> Self {
+        Self::MsgpackEncode(e)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<ParquetError> for RecordingError {
+    fn from(e: ParquetError) -> Self {
+        Self::Parquet(e)
+    }
+}
+
+/// The on-disk format of a recording.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingFormat {
+    /// Snapshots serialized with [`Snapshot::to_msgpack`] and written one
+    /// after the other.
+    Msgpack,
+    /// A parquet file written by a [`crate::ParquetWriter`].
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+/// Reads snapshots back out of a recording.
+///
+/// Recordings are assumed to be written in time order, which allows reads of
+/// a time window to stop as soon as the end of the window has been passed.
+/// For parquet recordings, row groups which fall entirely outside of the
+/// window are skipped without being decoded. For msgpack recordings written
+/// with an index (see [`crate::MsgpackWriter`]), reads seek directly to the
+/// first snapshot in the window.
+pub struct RecordingReader {
+    file: File,
+    format: RecordingFormat,
+    layout: MsgpackLayout,
+}
+
+impl RecordingReader {
+    /// Open the recording at the provided path. The format of the recording
+    /// is detected from its contents. For msgpack recordings without an
+    /// index footer, an index sidecar at the conventional path is used if it
+    /// exists.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        let mut reader = Self::from_file(File::open(path.as_ref())?)?;
+
+        if reader.format == RecordingFormat::Msgpack && reader.layout.index.is_none() {
+            if let MsgpackIndex::Sidecar(sidecar) = MsgpackIndex::sidecar_for(path) {
+                if sidecar.exists() {
+                    reader.layout.load_sidecar(sidecar)?;
+                }
+            }
+        }
+
+        Ok(reader)
+    }
+
+    /// Use the index in the provided sidecar file for a msgpack recording.
+    pub fn with_sidecar_index(mut self, path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        self.layout.load_sidecar(path)?;
+        Ok(self)
+    }
+
+    /// Create a reader for an already opened recording.
+    pub fn from_file(mut file: File) -> Result<Self, RecordingError> {
+        file.rewind()?;
+
+        let mut magic = [0; 4];
+        let format = match file.read_exact(&mut magic) {
+            #[cfg(feature = "parquet")]
+            Ok(()) if &magic == PARQUET_MAGIC => RecordingFormat::Parquet,
+            Ok(()) => RecordingFormat::Msgpack,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => RecordingFormat::Msgpack,
+            Err(e) => return Err(e.into()),
+        };
+        let layout = match format {
+            RecordingFormat::Msgpack => MsgpackLayout::read(&mut file)?,
+            #[cfg(feature = "parquet")]
+            RecordingFormat::Parquet => MsgpackLayout {
+                data_len: 0,
+                index: None,
+            },
+        };
+        file.rewind()?;
+
+        Ok(Self {
+            file,
+            format,
+            layout,
+        })
+    }
+
+    /// The format of the underlying recording.
+    pub fn format(&self) -> RecordingFormat {
+        self.format
+    }
+
+    /// Returns all of the snapshots taken at or after `start` and strictly
+    /// before `end`, in the order they appear in the recording.
+    pub fn range(
+        &mut self,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<Vec<Snapshot>, RecordingError> {
+        match self.format {
+            RecordingFormat::Msgpack => self.msgpack_range(start, end),
+            #[cfg(feature = "parquet")]
+            RecordingFormat::Parquet => self.parquet_range(start, end),
+        }
+    }
+
+    /// Returns the last snapshot in the recording. This only needs to decode a
+    /// single snapshot for indexed msgpack recordings.
+    pub fn last(&mut self) -> Result<Option<Snapshot>, RecordingError> {
+        match self.format {
+            RecordingFormat::Msgpack => {
+                if let Some(&(_, offset)) = self.layout.index.as_ref().and_then(|i| i.last()) {
+                    return Ok(self.msgpack_read_from(offset, |_| false)?.pop());
+                }
+                Ok(self.msgpack_read_from(0, |_| false)?.pop())
+            }
+            #[cfg(feature = "parquet")]
+            RecordingFormat::Parquet => {
+                let row_groups = parquet::file::reader::FileReader::metadata(
+                    &parquet::file::serialized_reader::SerializedFileReader::new(
+                        self.file.try_clone()?,
+                    )?,
+                )
+                .num_row_groups();
+                Ok(self.parquet_read(|idx, _| idx + 1 == row_groups)?.pop())
+            }
+        }
+    }
+
+    fn msgpack_range(
+        &mut self,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<Vec<Snapshot>, RecordingError> {
+        // With an index, begin reading at the first snapshot in the window.
+        let offset = match &self.layout.index {
+            Some(index) => {
+                let start = unix_nanos(start);
+                let idx = index.partition_point(|(ts, _)| *ts < start);
+                match index.get(idx) {
+                    Some((_, offset)) => *offset,
+                    None => return Ok(Vec::new()),
+                }
+            }
+            None => 0,
+        };
+
+        let mut snapshots = self.msgpack_read_from(offset, |s| s.systemtime >= end)?;
+        snapshots.retain(|s| s.systemtime >= start);
+        Ok(snapshots)
+    }
+
+    /// Read snapshots from the provided offset until the end of the data or
+    /// until `stop` returns true for a snapshot.
+    fn msgpack_read_from(
+        &mut self,
+        offset: u64,
+        stop: impl Fn(&Snapshot) -> bool,
+    ) -> Result<Vec<Snapshot>, RecordingError> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut reader =
+            BufReader::new((&self.file).take(self.layout.data_len.saturating_sub(offset)));
+        let mut decoder = MsgpackDecoder::default();
+        let mut snapshots = Vec::new();
+
+        while !reader.fill_buf()?.is_empty() {
+            let snapshot = decoder.decode(&mut reader)?;
+
+            if stop(&snapshot) {
+                break;
+            }
+
+            snapshots.push(snapshot);
+        }
+
+        Ok(snapshots)
+    }
+
+    #[cfg(feature = "parquet")]
+    fn parquet_range(
+        &mut self,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<Vec<Snapshot>, RecordingError> {
+        use parquet::file::statistics::Statistics;
+
+        let (start, end) = (unix_nanos(start), unix_nanos(end));
+
+        // Use the statistics for the timestamp column to select only the row
+        // groups which may overlap the requested window.
+        let mut snapshots = self.parquet_read(|_, rg| match rg.column(0).statistics() {
+            Some(Statistics::Int64(s)) if s.has_min_max_set() => {
+                (*s.max() as u64) >= start && (*s.min() as u64) < end
+            }
+            _ => true,
+        })?;
+
+        snapshots.retain(|s| {
+            let ts = unix_nanos(s.systemtime);
+            ts >= start && ts < end
+        });
+
+        Ok(snapshots)
+    }
+
+    /// Read all of the snapshots within the row groups for which `select`
+    /// returns true.
+    #[cfg(feature = "parquet")]
+    fn parquet_read(
+        &mut self,
+        select: impl Fn(usize, &parquet::file::metadata::RowGroupMetaData) -> bool,
+    ) -> Result<Vec<Snapshot>, RecordingError> {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(self.file.try_clone()?)?;
+
+        // Snapshot metadata is stored once, in the file metadata.
+        let metadata: HashMap<String, String> = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .map(|kv| {
+                kv.iter()
+                    .filter(|kv| {
+                        kv.key != crate::parquet::COLUMN_NAMES
+                            && kv.key != crate::parquet::COLUMN_METADATA
+                    })
+                    .filter_map(|kv| Some((kv.key.clone(), kv.value.clone()?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let row_groups: Vec<usize> = builder
+            .metadata()
+            .row_groups()
+            .iter()
+            .enumerate()
+            .filter(|(idx, rg)| select(*idx, rg))
+            .map(|(idx, _)| idx)
+            .collect();
+
+        let reader = builder.with_row_groups(row_groups).build()?;
+        let mut snapshots = Vec::new();
+
+        for batch in reader {
+            let batch = batch.map_err(ParquetError::from)?;
+            snapshots.extend(crate::parquet_reader::snapshots_from_batch(
+                &batch, &metadata,
+            ));
+        }
+
+        Ok(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{Counter, Event, MetricType, MsgpackWriter};
+
+    fn build_snapshots() -> Vec<Snapshot> {
+        (0..10)
+            .map(|i| {
+                let mut snapshot = Snapshot::new();
+                snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_secs(i);
+                snapshot.counters.push(Counter {
+                    name: "counter".to_string(),
+                    metric_type: MetricType::Counter,
+                    value: i,
+                    metadata: HashMap::new(),
+                });
+                snapshot
+            })
+            .collect()
+    }
+
+    #[test]
+    fn msgpack_range() {
+        let mut file = tempfile::tempfile().unwrap();
+        for snapshot in build_snapshots() {
+            file.write_all(&Snapshot::to_msgpack(&snapshot).unwrap())
+                .unwrap();
+        }
+
+        let mut reader = RecordingReader::from_file(file).unwrap();
+        assert_eq!(reader.format(), RecordingFormat::Msgpack);
+
+        let snapshots = reader
+            .range(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(3),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(6),
+            )
+            .unwrap();
+        let values: Vec<u64> = snapshots.iter().map(|s| s.counters[0].value).collect();
+        assert_eq!(values, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn msgpack_indexed() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for mode in [MsgpackIndex::Footer, MsgpackIndex::None] {
+            let path = dir.path().join(format!("{mode:?}.msgpack"));
+            let sidecar = MsgpackIndex::sidecar_for(&path);
+            let mode = match mode {
+                MsgpackIndex::None => sidecar,
+                mode => mode,
+            };
+
+            let mut writer = MsgpackWriter::with_index(File::create(&path).unwrap(), mode);
+            for snapshot in build_snapshots() {
+                writer.push(&snapshot).unwrap();
+            }
+            writer.finalize().unwrap();
+
+            let mut reader = RecordingReader::open(&path).unwrap();
+            assert!(reader.layout.index.is_some());
+
+            let snapshots = reader
+                .range(
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(3),
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(6),
+                )
+                .unwrap();
+            let values: Vec<u64> = snapshots.iter().map(|s| s.counters[0].value).collect();
+            assert_eq!(values, vec![3, 4, 5]);
+
+            let last = reader.last().unwrap().unwrap();
+            assert_eq!(last.counters[0].value, 9);
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_range() {
+        use crate::{ParquetOptions, ParquetSchema};
+
+        let mut snapshots = build_snapshots();
+        snapshots[4].events.push(
+            Event::new("deploy")
+                .systemtime(SystemTime::UNIX_EPOCH + Duration::from_millis(3_500))
+                .metadata("version", "1.2.3"),
+        );
+        let mut schema = ParquetSchema::new();
+        for snapshot in &snapshots {
+            schema.push(snapshot.clone());
+        }
+
+        let file = tempfile::tempfile().unwrap();
+        let mut writer = schema
+            .finalize(
+                file.try_clone().unwrap(),
+                ParquetOptions::new().max_batch_size(2),
+            )
+            .unwrap();
+        for snapshot in snapshots {
+            writer.push(snapshot).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut reader = RecordingReader::from_file(file).unwrap();
+        assert_eq!(reader.format(), RecordingFormat::Parquet);
+
+        let snapshots = reader
+            .range(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(3),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(6),
+            )
+            .unwrap();
+        let values: Vec<u64> = snapshots.iter().map(|s| s.counters[0].value).collect();
+        assert_eq!(values, vec![3, 4, 5]);
+
+        assert!(snapshots[0].events.is_empty());
+        assert_eq!(snapshots[1].events.len(), 1);
+        let event = &snapshots[1].events[0];
+        assert_eq!(event.name, "deploy");
+        assert_eq!(
+            event.systemtime,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(3_500)
+        );
+        assert_eq!(event.metadata["version"], "1.2.3");
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_renamed_columns() {
+        use crate::{ParquetColumnNaming, ParquetOptions, ParquetSchema};
+
+        let mut snapshots = build_snapshots();
+        for snapshot in snapshots.iter_mut() {
+            snapshot.counters[0].name = "Request/Count".to_string();
+        }
+        let mut schema = ParquetSchema::new();
+        for snapshot in &snapshots {
+            schema.push(snapshot.clone());
+        }
+
+        let file = tempfile::tempfile().unwrap();
+        let naming = ParquetColumnNaming::new()
+            .replace_slashes(true)
+            .lowercase(true);
+        let mut writer = schema
+            .finalize(
+                file.try_clone().unwrap(),
+                ParquetOptions::new().column_naming(naming),
+            )
+            .unwrap();
+        for snapshot in snapshots {
+            writer.push(snapshot).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        // the metrics have their original names
+        let mut reader = RecordingReader::from_file(file).unwrap();
+        let last = reader.last().unwrap().unwrap();
+        assert_eq!(last.counters[0].name, "Request/Count");
+        assert!(last.counters[0].metadata.is_empty());
+        assert!(last.get_metadata(crate::parquet::COLUMN_NAMES).is_none());
+        assert!(last.get_metadata(crate::parquet::COLUMN_METADATA).is_none());
+    }
+
+    #[test]
+    fn msgpack_dedup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dedup.msgpack");
+
+        // each value is repeated for at least three snapshots
+        let mut snapshots: Vec<Snapshot> = build_snapshots()
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut s)| {
+                s.counters[0].value = (s.counters[0].value / 3).min(2);
+                s.metadata
+                    .insert(crate::SEQUENCE.to_string(), i.to_string());
+                s
+            })
+            .collect();
+        // a snapshot with an event is written in full even if its values
+        // are repeated
+        snapshots[4].events.push(Event::new("gc"));
+
+        let mut writer =
+            MsgpackWriter::with_index(File::create(&path).unwrap(), MsgpackIndex::Footer)
+                .dedup(true);
+        for snapshot in &snapshots {
+            writer.push(snapshot).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut plain = Vec::new();
+        for snapshot in &snapshots {
+            plain.extend(Snapshot::to_msgpack(snapshot).unwrap());
+        }
+        assert!(std::fs::metadata(&path).unwrap().len() < plain.len() as u64);
+
+        let mut reader = RecordingReader::open(&path).unwrap();
+        let read = reader
+            .range(SystemTime::UNIX_EPOCH, SystemTime::now())
+            .unwrap();
+        assert_eq!(read.len(), snapshots.len());
+        for (read, written) in read.iter().zip(&snapshots) {
+            assert_eq!(read.systemtime, written.systemtime);
+            assert_eq!(read.sequence(), written.sequence());
+            assert_eq!(read.counters[0].value, written.counters[0].value);
+            assert_eq!(read.events, written.events);
+        }
+
+        // reads which begin at a repeated snapshot use the full snapshot
+        // before it
+        let window = reader
+            .range(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(4),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(6),
+            )
+            .unwrap();
+        let values: Vec<u64> = window.iter().map(|s| s.counters[0].value).collect();
+        assert_eq!(values, vec![1, 1]);
+
+        let last = reader.last().unwrap().unwrap();
+        assert_eq!(last.systemtime, snapshots[9].systemtime);
+        assert_eq!(last.counters[0].value, 2);
+    }
+}
@@ -0,0 +1,524 @@
+use std::collections::HashMap;
+
+use crate::rebucket::{common_config, rebucket};
+use crate::snapshot::{MetricType, Snapshot};
+use crate::temporality::{series_key, SeriesKey};
+use crate::text::text_name;
+
+/// The statistical test used to compare a metric between two recordings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StatisticalTest {
+    /// The Mann-Whitney U test, comparing the per-interval rates of a counter
+    /// or the values of a gauge.
+    MannWhitney,
+    /// The two-sample Kolmogorov-Smirnov test, comparing the distributions of
+    /// the values recorded by a histogram.
+    KolmogorovSmirnov,
+}
+
+/// The outcome of comparing a metric between a baseline and a candidate
+/// recording.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Verdict {
+    /// No significant difference was found.
+    Unchanged,
+    /// The metric is significantly higher in the candidate.
+    Increased,
+    /// The metric is significantly lower in the candidate.
+    Decreased,
+    /// There were too few samples to test the metric.
+    Inconclusive,
+}
+
+/// The comparison of a single metric between two recordings.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct MetricComparison {
+    /// The name of the metric along with its metadata, as in
+    /// [`Snapshot::to_text`].
+    pub name: String,
+    pub test: StatisticalTest,
+    /// The test statistic: `U` of the candidate for Mann-Whitney, or `D` for
+    /// Kolmogorov-Smirnov.
+    pub statistic: f64,
+    /// The probability of a difference at least this large if the metric was
+    /// unchanged.
+    pub p_value: f64,
+    pub verdict: Verdict,
+}
+
+/// Detects metrics which changed significantly between a baseline and a
+/// candidate recording, such as in a CI performance gate.
+///
+/// Counters are compared by their rate over each interval between
+/// consecutive snapshots, and gauges by their values, with the Mann-Whitney U
+/// test. Histograms are compared by the values recorded over each recording
+/// with the Kolmogorov-Smirnov test. Only metrics present in both recordings
+/// are compared, and a metric is reported as changed when the p-value of its
+/// test is below the significance level.
+///
+/// Histograms usually hold many values, which makes the Kolmogorov-Smirnov
+/// test sensitive to very small shifts in their distribution.
+///
+/// ```
+/// # use metriken_exposition::{RegressionDetector, Snapshot, Verdict};
+/// # use std::time::{Duration, SystemTime};
+/// fn recording(rates: &[u64]) -> Vec<Snapshot> {
+///     let mut total = 0;
+///     let mut snapshots = Vec::new();
+///     for (secs, rate) in std::iter::once(&0).chain(rates).enumerate() {
+///         total += rate;
+///         snapshots.push(
+///             Snapshot::builder()
+///                 .systemtime(SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64))
+///                 .counter("requests", total, &[])
+///                 .build()
+///                 .unwrap(),
+///         );
+///     }
+///     snapshots
+/// }
+///
+/// let baseline = recording(&[100, 102, 98, 101, 99, 100, 103, 97]);
+/// let candidate = recording(&[80, 82, 79, 81, 78, 80, 83, 77]);
+///
+/// let results = RegressionDetector::new().compare(&baseline, &candidate);
+/// assert_eq!(results[0].name, "requests");
+/// assert_eq!(results[0].verdict, Verdict::Decreased);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RegressionDetector {
+    alpha: f64,
+    min_samples: usize,
+}
+
+impl Default for RegressionDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegressionDetector {
+    /// A detector with a significance level of `0.05` which requires at least
+    /// five samples of each metric in each recording.
+    pub fn new() -> Self {
+        Self {
+            alpha: 0.05,
+            min_samples: 5,
+        }
+    }
+
+    /// The significance level, below which a p-value is reported as a
+    /// change.
+    pub fn alpha(mut self, alpha: f64) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// The number of samples, intervals or recorded values, each recording
+    /// must have for a metric to be tested. Metrics with fewer are
+    /// [`Verdict::Inconclusive`].
+    pub fn min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples;
+        self
+    }
+
+    /// Compare every metric present in both recordings, sorted by name.
+    pub fn compare(&self, baseline: &[Snapshot], candidate: &[Snapshot]) -> Vec<MetricComparison> {
+        let mut results = Vec::new();
+
+        let (baseline_samples, candidate_samples) = (samples(baseline), samples(candidate));
+        for (key, before) in &baseline_samples {
+            let Some(after) = candidate_samples.get(key) else {
+                continue;
+            };
+            let (statistic, p_value) = if before.len().min(after.len()) < self.min_samples {
+                (f64::NAN, f64::NAN)
+            } else {
+                mann_whitney(before, after)
+            };
+            // U is half the product of the sample sizes if the metric was
+            // unchanged
+            let increased = statistic > (before.len() * after.len()) as f64 / 2.0;
+            results.push(self.result(
+                key,
+                StatisticalTest::MannWhitney,
+                statistic,
+                p_value,
+                increased,
+            ));
+        }
+
+        let (baseline, candidate) = (distributions(baseline), distributions(candidate));
+        for (key, before) in &baseline {
+            let Some(after) = candidate.get(key) else {
+                continue;
+            };
+            let (statistic, p_value, shift) = match kolmogorov_smirnov(before, after) {
+                Some((n, d, p_value, shift)) if n >= self.min_samples as u64 => (d, p_value, shift),
+                _ => (f64::NAN, f64::NAN, 0.0),
+            };
+            results.push(self.result(
+                key,
+                StatisticalTest::KolmogorovSmirnov,
+                statistic,
+                p_value,
+                shift > 0.0,
+            ));
+        }
+
+        results.sort_by(|a, b| a.name.cmp(&b.name));
+        results
+    }
+
+    /// The result of a test, where a significant difference is an increase
+    /// if `increased` is set.
+    fn result(
+        &self,
+        key: &SeriesKey,
+        test: StatisticalTest,
+        statistic: f64,
+        p_value: f64,
+        increased: bool,
+    ) -> MetricComparison {
+        let verdict = if p_value.is_nan() {
+            Verdict::Inconclusive
+        } else if p_value >= self.alpha {
+            Verdict::Unchanged
+        } else if increased {
+            Verdict::Increased
+        } else {
+            Verdict::Decreased
+        };
+
+        let metadata = key.1.iter().cloned().collect();
+        MetricComparison {
+            name: text_name(&key.0, &metadata),
+            test,
+            statistic,
+            p_value,
+            verdict,
+        }
+    }
+}
+
+/// The per-interval rates of each counter and the values of each gauge.
+fn samples(snapshots: &[Snapshot]) -> HashMap<SeriesKey, Vec<f64>> {
+    let mut samples: HashMap<SeriesKey, Vec<f64>> = HashMap::new();
+
+    for pair in snapshots.windows(2) {
+        let secs = pair[1]
+            .systemtime
+            .duration_since(pair[0].systemtime)
+            .unwrap_or_default()
+            .as_secs_f64();
+        if secs <= 0.0 {
+            continue;
+        }
+        let previous: HashMap<_, _> = pair[0]
+            .counters
+            .iter()
+            .map(|c| (series_key(&c.name, &c.metadata), c.value))
+            .collect();
+        for counter in &pair[1].counters {
+            let key = series_key(&counter.name, &counter.metadata);
+            // a counter which went backwards was reset, so the interval is
+            // left out
+            if let Some(delta) = previous
+                .get(&key)
+                .and_then(|previous| counter.value.checked_sub(*previous))
+            {
+                samples.entry(key).or_default().push(delta as f64 / secs);
+            }
+        }
+    }
+
+    for gauge in snapshots.iter().flat_map(|s| &s.gauges) {
+        samples
+            .entry(series_key(&gauge.name, &gauge.metadata))
+            .or_default()
+            .push(gauge.value as f64);
+    }
+
+    samples
+}
+
+/// The values recorded by each histogram between the first and the last
+/// snapshot, or in the only snapshot. Delta histograms hold the values of a
+/// single interval, so those after the first snapshot are summed.
+fn distributions(snapshots: &[Snapshot]) -> HashMap<SeriesKey, histogram::Histogram> {
+    let (Some(first), Some(last)) = (snapshots.first(), snapshots.last()) else {
+        return HashMap::new();
+    };
+    let start: HashMap<_, _> = first
+        .histograms
+        .iter()
+        .map(|h| (series_key(&h.name, &h.metadata), &h.value))
+        .collect();
+
+    let mut distributions: HashMap<_, _> = last
+        .histograms
+        .iter()
+        .filter(|histogram| histogram.metric_type != MetricType::DeltaHistogram)
+        .map(|histogram| {
+            let key = series_key(&histogram.name, &histogram.metadata);
+            let value = match start.get(&key) {
+                Some(start) if snapshots.len() > 1 => histogram
+                    .value
+                    .checked_sub(start)
+                    .unwrap_or_else(|_| histogram.value.clone()),
+                _ => histogram.value.clone(),
+            };
+            (key, value)
+        })
+        .collect();
+
+    let intervals = if snapshots.len() > 1 {
+        &snapshots[1..]
+    } else {
+        snapshots
+    };
+    for histogram in intervals.iter().flat_map(|s| &s.histograms) {
+        if histogram.metric_type != MetricType::DeltaHistogram {
+            continue;
+        }
+        let key = series_key(&histogram.name, &histogram.metadata);
+        let total = match distributions.get(&key) {
+            // a histogram with a new configuration starts over
+            Some(total) => total
+                .wrapping_add(&histogram.value)
+                .unwrap_or_else(|_| histogram.value.clone()),
+            None => histogram.value.clone(),
+        };
+        distributions.insert(key, total);
+    }
+
+    distributions
+}
+
+/// The Mann-Whitney U statistic of the candidate and its two-sided p-value,
+/// using the normal approximation with a correction for ties.
+fn mann_whitney(baseline: &[f64], candidate: &[f64]) -> (f64, f64) {
+    let (n1, n2) = (baseline.len() as f64, candidate.len() as f64);
+    let n = n1 + n2;
+
+    let mut values: Vec<(f64, bool)> = baseline
+        .iter()
+        .map(|v| (*v, false))
+        .chain(candidate.iter().map(|v| (*v, true)))
+        .collect();
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // sum the ranks of the candidate, giving tied values their mean rank
+    let mut rank_sum = 0.0;
+    let mut ties = 0.0;
+    let mut i = 0;
+    while i < values.len() {
+        let mut j = i;
+        while j < values.len() && values[j].0 == values[i].0 {
+            j += 1;
+        }
+        let rank = (i + j + 1) as f64 / 2.0;
+        rank_sum += rank * values[i..j].iter().filter(|(_, c)| *c).count() as f64;
+        let t = (j - i) as f64;
+        ties += t * t * t - t;
+        i = j;
+    }
+
+    let u = rank_sum - n2 * (n2 + 1.0) / 2.0;
+    let mean = n1 * n2 / 2.0;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        // every value is the same
+        return (u, 1.0);
+    }
+
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    (u, erfc(z / std::f64::consts::SQRT_2).min(1.0))
+}
+
+/// The number of values in the smaller histogram, the Kolmogorov-Smirnov
+/// statistic, its p-value, and the direction of the largest difference,
+/// which is positive when the candidate holds larger values. Returns `None`
+/// if the histograms can't be compared.
+fn kolmogorov_smirnov(
+    baseline: &histogram::Histogram,
+    candidate: &histogram::Histogram,
+) -> Option<(u64, f64, f64, f64)> {
+    let config = common_config(baseline.config(), candidate.config());
+    let baseline = rebucket(baseline, &config).ok()?;
+    let candidate = rebucket(candidate, &config).ok()?;
+
+    let n1: u64 = baseline.as_slice().iter().sum();
+    let n2: u64 = candidate.as_slice().iter().sum();
+    if n1 == 0 || n2 == 0 {
+        return Some((0, f64::NAN, f64::NAN, 0.0));
+    }
+
+    let (mut c1, mut c2) = (0, 0);
+    let (mut d, mut shift) = (0.0, 0.0);
+    for (a, b) in baseline.as_slice().iter().zip(candidate.as_slice()) {
+        c1 += a;
+        c2 += b;
+        let difference = c1 as f64 / n1 as f64 - c2 as f64 / n2 as f64;
+        if difference.abs() > d {
+            d = difference.abs();
+            // a lower cumulative fraction means the candidate's values are
+            // larger
+            shift = difference;
+        }
+    }
+
+    let ne = (n1 as f64 * n2 as f64) / (n1 + n2) as f64;
+    let lambda = (ne.sqrt() + 0.12 + 0.11 / ne.sqrt()) * d;
+    Some((n1.min(n2), d, kolmogorov(lambda), shift))
+}
+
+/// The complement of the Kolmogorov distribution, `Q(λ)`.
+fn kolmogorov(lambda: f64) -> f64 {
+    if lambda < 0.2 {
+        return 1.0;
+    }
+    let mut sum = 0.0;
+    for j in 1..=100 {
+        let j = j as f64;
+        let term = (-2.0 * j * j * lambda * lambda).exp();
+        sum += if j as u64 % 2 == 1 { term } else { -term };
+        if term < 1e-12 {
+            break;
+        }
+    }
+    (2.0 * sum).clamp(0.0, 1.0)
+}
+
+/// The complementary error function, accurate to about `1.2e-7`.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.26551223
+            + t * (1.00002368
+                + t * (0.37409196
+                    + t * (0.09678418
+                        + t * (-0.18628806
+                            + t * (0.27886807
+                                + t * (-1.13520398
+                                    + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+            .exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn recording(rates: &[u64], latency: u64, memory: i64) -> Vec<Snapshot> {
+        let mut histogram = histogram::Histogram::new(4, 32).unwrap();
+        let mut total = 0;
+
+        std::iter::once(&0)
+            .chain(rates)
+            .enumerate()
+            .map(|(secs, rate)| {
+                total += rate;
+                for offset in 0..*rate {
+                    histogram.increment(latency + offset * 1000).unwrap();
+                }
+                Snapshot::builder()
+                    .systemtime(SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64))
+                    .counter("requests", total, &[])
+                    .gauge("memory", memory + secs as i64 % 3, &[])
+                    .histogram("latency", histogram.clone(), &[])
+                    .build()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn verdicts(results: &[MetricComparison]) -> Vec<(&str, StatisticalTest, Verdict)> {
+        results
+            .iter()
+            .map(|r| (r.name.as_str(), r.test, r.verdict))
+            .collect()
+    }
+
+    #[test]
+    fn detects_changes() {
+        let rates = [100, 102, 98, 101, 99, 100, 103, 97];
+        let baseline = recording(&rates, 100_000, 1000);
+        let candidate = recording(&rates.map(|r| r + 20), 400_000, 1000);
+
+        let results = RegressionDetector::new().compare(&baseline, &candidate);
+        assert_eq!(
+            verdicts(&results),
+            [
+                (
+                    "latency",
+                    StatisticalTest::KolmogorovSmirnov,
+                    Verdict::Increased
+                ),
+                ("memory", StatisticalTest::MannWhitney, Verdict::Unchanged),
+                ("requests", StatisticalTest::MannWhitney, Verdict::Increased),
+            ]
+        );
+        assert!(results[0].p_value < 1e-6);
+        assert_eq!(results[1].p_value, 1.0);
+
+        // the same recording is unchanged
+        let results = RegressionDetector::new().compare(&baseline, &baseline);
+        assert!(results.iter().all(|r| r.verdict == Verdict::Unchanged));
+
+        let results = RegressionDetector::new().compare(&candidate, &baseline);
+        assert_eq!(results[0].verdict, Verdict::Decreased);
+        assert_eq!(results[2].verdict, Verdict::Decreased);
+    }
+
+    #[test]
+    fn delta_histograms() {
+        let cumulative = recording(&[100, 102, 98, 101], 1000, 0);
+        let mut converter = crate::TemporalityConverter::new(crate::Temporality::Delta);
+        let delta: Vec<Snapshot> = cumulative
+            .iter()
+            .map(|s| converter.convert(s.clone()))
+            .collect();
+        assert_eq!(
+            delta[1].histograms[0].metric_type,
+            MetricType::DeltaHistogram
+        );
+
+        assert_eq!(distributions(&delta), distributions(&cumulative));
+        assert_eq!(distributions(&delta[..1]), distributions(&cumulative[..1]));
+    }
+
+    #[test]
+    fn too_few_samples() {
+        let baseline = recording(&[1, 2], 1000, 0);
+        let candidate = recording(&[3, 4], 1000, 0);
+        let results = RegressionDetector::new().compare(&baseline, &candidate);
+        assert!(results
+            .iter()
+            .all(|r| r.verdict == Verdict::Inconclusive && r.p_value.is_nan()));
+
+        let results = RegressionDetector::new()
+            .min_samples(2)
+            .compare(&baseline, &candidate);
+        assert!(results.iter().all(|r| r.verdict != Verdict::Inconclusive));
+    }
+
+    #[test]
+    fn mann_whitney_p_value() {
+        // scipy.stats.mannwhitneyu([1, 2, 3, 4, 5], [6, 7, 8, 9, 10],
+        // method="asymptotic") gives U=25 and p=0.0122
+        let (u, p) = mann_whitney(&[1.0, 2.0, 3.0, 4.0, 5.0], &[6.0, 7.0, 8.0, 9.0, 10.0]);
+        assert_eq!(u, 25.0);
+        assert!((p - 0.0122).abs() < 1e-4, "{p}");
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use metriken_core::Unit;
+
+use crate::snapshot::Snapshot;
+use crate::temporality::{series_key, SeriesKey};
+use crate::text::{format_human, round, text_name, PERCENTILES};
+
+/// What is compared by a [`DiffEntry`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum DiffKind {
+    /// The rate of a counter, per second, or its value if there was only a
+    /// single snapshot.
+    Counter,
+    /// The mean value of a gauge.
+    Gauge,
+    /// A percentile of a histogram, such as `99.0`.
+    Percentile(f64),
+}
+
+/// The change of a single metric between the baseline and the candidate.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct DiffEntry {
+    /// The name of the metric along with its metadata, as in
+    /// [`Snapshot::to_text`].
+    pub name: String,
+    pub kind: DiffKind,
+    pub baseline: f64,
+    pub candidate: f64,
+    unit: Option<String>,
+}
+
+impl DiffEntry {
+    /// The change relative to the baseline, where `1.0` is double the
+    /// baseline. A change from zero is infinite.
+    pub fn change(&self) -> f64 {
+        if self.baseline == 0.0 {
+            f64::INFINITY.copysign(self.candidate)
+        } else {
+            (self.candidate - self.baseline) / self.baseline.abs()
+        }
+    }
+
+    fn kind(&self) -> String {
+        match self.kind {
+            DiffKind::Counter => "rate".to_string(),
+            DiffKind::Gauge => "mean".to_string(),
+            DiffKind::Percentile(percentile) => format!("p{percentile}"),
+        }
+    }
+
+    fn format(&self, value: f64) -> String {
+        let value = match self.unit.as_deref() {
+            Some(unit) => format_human(value, Unit::parse(unit)),
+            None => round(value),
+        };
+        match self.kind {
+            DiffKind::Counter => format!("{value}/s"),
+            _ => value,
+        }
+    }
+
+    fn format_change(&self) -> String {
+        match self.change() {
+            change if change.is_infinite() => "new".to_string(),
+            change if change >= 0.0 => format!("+{}%", round(change * 100.0)),
+            change => format!("{}%", round(change * 100.0)),
+        }
+    }
+}
+
+/// The summary of the metrics of a run, either a single snapshot or a
+/// recording.
+#[derive(Default)]
+struct Summary {
+    counters: HashMap<SeriesKey, (f64, Option<String>)>,
+    gauges: HashMap<SeriesKey, (f64, Option<String>)>,
+    histograms: HashMap<SeriesKey, (histogram::Histogram, Option<String>)>,
+}
+
+impl Summary {
+    /// Summarize the snapshots of a run. With more than one snapshot the
+    /// counters are reported as their rate and the histograms as the values
+    /// recorded between the first and the last snapshot.
+    fn new(snapshots: &[Snapshot]) -> Self {
+        let mut summary = Self::default();
+        let (Some(first), Some(last)) = (snapshots.first(), snapshots.last()) else {
+            return summary;
+        };
+        let secs = last
+            .systemtime
+            .duration_since(first.systemtime)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let unit = |metadata: &HashMap<String, String>| metadata.get("unit").cloned();
+
+        let start: HashMap<_, _> = first
+            .counters
+            .iter()
+            .map(|c| (series_key(&c.name, &c.metadata), c.value))
+            .collect();
+        for counter in &last.counters {
+            let key = series_key(&counter.name, &counter.metadata);
+            let value = match start.get(&key) {
+                Some(start) if secs > 0.0 => counter.value.wrapping_sub(*start) as f64 / secs,
+                _ => counter.value as f64,
+            };
+            summary
+                .counters
+                .insert(key, (value, unit(&counter.metadata)));
+        }
+
+        let mut gauges: HashMap<SeriesKey, (f64, usize, Option<String>)> = HashMap::new();
+        for gauge in snapshots.iter().flat_map(|s| &s.gauges) {
+            let entry = gauges
+                .entry(series_key(&gauge.name, &gauge.metadata))
+                .or_insert((0.0, 0, unit(&gauge.metadata)));
+            entry.0 += gauge.value as f64;
+            entry.1 += 1;
+        }
+        summary.gauges = gauges
+            .into_iter()
+            .map(|(key, (sum, count, unit))| (key, (sum / count as f64, unit)))
+            .collect();
+
+        let start: HashMap<_, _> = first
+            .histograms
+            .iter()
+            .map(|h| (series_key(&h.name, &h.metadata), &h.value))
+            .collect();
+        for histogram in &last.histograms {
+            let key = series_key(&histogram.name, &histogram.metadata);
+            let value = match start.get(&key) {
+                Some(start) if snapshots.len() > 1 => histogram
+                    .value
+                    .wrapping_sub(start)
+                    .unwrap_or_else(|_| histogram.value.clone()),
+                _ => histogram.value.clone(),
+            };
+            summary
+                .histograms
+                .insert(key, (value, unit(&histogram.metadata)));
+        }
+
+        summary
+    }
+}
+
+/// A comparison of the metrics of two runs, such as the two sides of an A/B
+/// performance test, with the biggest changes first.
+///
+/// Each side is either a single snapshot or a recording. Counters are
+/// compared by their rate over a recording, or by their value for a single
+/// snapshot, gauges by their mean, and histograms by a few percentiles of the
+/// values recorded over the run. Only metrics present on both sides which
+/// changed are included, sorted by the size of their relative change.
+///
+/// ```
+/// # use metriken_exposition::{DiffReport, Snapshot};
+/// let baseline = Snapshot::builder().counter("errors", 10, &[]).build().unwrap();
+/// let candidate = Snapshot::builder().counter("errors", 15, &[]).build().unwrap();
+///
+/// let report = DiffReport::new(&[baseline], &[candidate]);
+/// assert_eq!(report.entries[0].change(), 0.5);
+/// assert_eq!(report.to_text(), "errors rate: 10/s -> 15/s (+50%)\n");
+/// ```
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct DiffReport {
+    pub entries: Vec<DiffEntry>,
+}
+
+impl DiffReport {
+    /// Compare the candidate run against the baseline.
+    pub fn new(baseline: &[Snapshot], candidate: &[Snapshot]) -> Self {
+        let (baseline, candidate) = (Summary::new(baseline), Summary::new(candidate));
+        let mut entries = Vec::new();
+
+        let mut push = |key: &SeriesKey, kind, values: (f64, f64), unit: &Option<String>| {
+            if values.0 == values.1 {
+                return;
+            }
+            let metadata = key.1.iter().cloned().collect();
+            entries.push(DiffEntry {
+                name: text_name(&key.0, &metadata),
+                kind,
+                baseline: values.0,
+                candidate: values.1,
+                unit: unit.clone(),
+            });
+        };
+
+        for (key, (value, unit)) in &baseline.counters {
+            if let Some((candidate, _)) = candidate.counters.get(key) {
+                push(key, DiffKind::Counter, (*value, *candidate), unit);
+            }
+        }
+
+        for (key, (value, unit)) in &baseline.gauges {
+            if let Some((candidate, _)) = candidate.gauges.get(key) {
+                push(key, DiffKind::Gauge, (*value, *candidate), unit);
+            }
+        }
+
+        for (key, (histogram, unit)) in &baseline.histograms {
+            let Some((other, _)) = candidate.histograms.get(key) else {
+                continue;
+            };
+            let (Ok(Some(before)), Ok(Some(after))) = (
+                histogram.percentiles(&PERCENTILES),
+                other.percentiles(&PERCENTILES),
+            ) else {
+                continue;
+            };
+            for ((percentile, before), (_, after)) in before.into_iter().zip(after) {
+                let values = (before.end() as f64, after.end() as f64);
+                push(key, DiffKind::Percentile(percentile), values, unit);
+            }
+        }
+
+        entries.sort_by(|a, b| {
+            b.change()
+                .abs()
+                .total_cmp(&a.change().abs())
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        Self { entries }
+    }
+
+    /// Render the report as text, with one change per line.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for entry in &self.entries {
+            let _ = writeln!(
+                out,
+                "{} {}: {} -> {} ({})",
+                entry.name,
+                entry.kind(),
+                entry.format(entry.baseline),
+                entry.format(entry.candidate),
+                entry.format_change(),
+            );
+        }
+        out
+    }
+
+    /// Render the report as a markdown table.
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::from(
+            "| metric | statistic | baseline | candidate | change |\n\
+             |---|---|---|---|---|\n",
+        );
+        for entry in &self.entries {
+            let _ = writeln!(
+                out,
+                "| `{}` | {} | {} | {} | {} |",
+                entry.name,
+                entry.kind(),
+                entry.format(entry.baseline),
+                entry.format(entry.candidate),
+                entry.format_change(),
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn run(requests: [u64; 2], latency: u64, memory: i64) -> Vec<Snapshot> {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let mut histogram = histogram::Histogram::new(4, 32).unwrap();
+
+        [0, 10]
+            .into_iter()
+            .zip(requests)
+            .map(|(secs, requests)| {
+                if secs > 0 {
+                    histogram.add(latency, 100).unwrap();
+                }
+                Snapshot::builder()
+                    .systemtime(start + Duration::from_secs(secs))
+                    .counter("requests", requests, &[("method", "GET")])
+                    .counter("errors", 3, &[])
+                    .gauge("memory", memory, &[("unit", "bytes")])
+                    .histogram("latency", histogram.clone(), &[("unit", "nanoseconds")])
+                    .build()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compare() {
+        let baseline = run([0, 1000], 1_000_000, 1024);
+        let candidate = run([0, 1500], 2_000_000, 1024);
+        let report = DiffReport::new(&baseline, &candidate);
+
+        let changes: Vec<_> = report
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.kind, e.change()))
+            .collect();
+        // the latency doubled, but is compared by the end of its bucket so
+        // the change is not exact
+        assert_eq!(changes.len(), 5);
+        assert!(changes[..4]
+            .iter()
+            .all(|(name, kind, change)| *name == "latency"
+                && matches!(kind, DiffKind::Percentile(_))
+                && (0.95..1.05).contains(change)));
+        assert_eq!(changes[4], ("requests{method=GET}", DiffKind::Counter, 0.5));
+
+        let text = report.to_text();
+        assert!(text.ends_with("requests{method=GET} rate: 100/s -> 150/s (+50%)\n"));
+        assert!(text.starts_with("latency p50: 1.02 ms -> 2.03 ms"));
+
+        let markdown = report.to_markdown();
+        assert!(markdown.contains("| `requests{method=GET}` | rate | 100/s | 150/s | +50% |"));
+    }
+
+    #[test]
+    fn new_metric() {
+        let baseline = Snapshot::builder()
+            .counter("errors", 0, &[])
+            .build()
+            .unwrap();
+        let candidate = Snapshot::builder()
+            .counter("errors", 4, &[])
+            .build()
+            .unwrap();
+        let report = DiffReport::new(&[baseline], &[candidate]);
+        assert_eq!(report.to_text(), "errors rate: 0/s -> 4/s (new)\n");
+    }
+}
@@ -0,0 +1,479 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::auth::Auth;
+use crate::prometheus_reader::PrometheusError;
+use crate::snapshot::{Gauge, MetricType, Snapshot};
+
+/// The default metadata key used to identify the target of each metric.
+const DEFAULT_LABEL: &str = "source";
+
+/// The name of the gauge which reports whether each target was scraped.
+pub const UP: &str = "up";
+
+/// Errors that can occur while scraping a target.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ScrapeError {
+    /// The target URL is not a valid `http://` URL.
+    Url(String),
+    Io(std::io::Error),
+    /// The target responded with a status other than `200 OK`, or with a
+    /// response which could not be parsed.
+    Http(String),
+    Prometheus(PrometheusError),
+    #[cfg(all(feature = "serde", feature = "json"))]
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Url(url) => write!(f, "invalid target url: {url}"),
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Http(e) => write!(f, "http error: {e}"),
+            Self::Prometheus(e) => write!(f, "prometheus parse error: {e}"),
+            #[cfg(all(feature = "serde", feature = "json"))]
+            Self::Json(e) => write!(f, "json decode error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ScrapeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Url(_) | Self::Http(_) => None,
+            Self::Io(e) => Some(e),
+            Self::Prometheus(e) => Some(e),
+            #[cfg(all(feature = "serde", feature = "json"))]
+            Self::Json(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ScrapeError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<PrometheusError> for ScrapeError {
+    fn from(e: PrometheusError) -> Self {
+        Self::Prometheus(e)
+    }
+}
+
+#[cfg(all(feature = "serde", feature = "json"))]
+impl From<serde_json::Error> for ScrapeError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// The exposition format served by a target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ScrapeFormat {
+    /// The Prometheus or OpenMetrics text format.
+    Prometheus,
+    /// A snapshot serialized with [`Snapshot::to_json`].
+    #[cfg(all(feature = "serde", feature = "json"))]
+    Json,
+}
+
+/// A target to scrape.
+#[derive(Clone, Debug)]
+pub struct ScrapeTarget {
+    name: String,
+    url: String,
+    format: ScrapeFormat,
+    auth: Option<Auth>,
+}
+
+impl ScrapeTarget {
+    /// A target serving the provided format at an `http://` URL. The name is
+    /// used to label the metrics scraped from the target.
+    pub fn new(name: impl Into<String>, url: impl Into<String>, format: ScrapeFormat) -> Self {
+        Self {
+            name: name.into(),
+            url: url.into(),
+            format,
+            auth: None,
+        }
+    }
+
+    /// Send credentials with each request to the target.
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// The name of the target.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Fetch and parse the exposition output of the target.
+    pub fn scrape(&self, timeout: Duration) -> Result<Snapshot, ScrapeError> {
+        let body = get(&self.url, self.auth.as_ref(), timeout)?;
+
+        match self.format {
+            ScrapeFormat::Prometheus => {
+                let text = String::from_utf8_lossy(&body);
+                Ok(Snapshot::from_prometheus(&text)?)
+            }
+            #[cfg(all(feature = "serde", feature = "json"))]
+            ScrapeFormat::Json => Ok(serde_json::from_slice(&body)?),
+        }
+    }
+}
+
+/// Periodically scrapes a set of targets and combines their metrics into a
+/// single snapshot, making it possible to record metrics from processes
+/// which only expose them over HTTP.
+///
+/// Every metric is labeled with the name of its target, and an [`UP`] gauge
+/// for each target reports whether it was scraped successfully. Targets are
+/// scraped concurrently. Only plain `http://` targets are supported.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use metriken_exposition::{ScrapeFormat, ScrapeTarget, Scraper};
+/// let scraper = Scraper::new(Duration::from_secs(10))
+///     .target(ScrapeTarget::new("node", "http://localhost:9100/metrics", ScrapeFormat::Prometheus));
+///
+/// scraper.run(|snapshot| {
+///     // write the snapshot to a recording
+///     Ok::<(), std::io::Error>(())
+/// })
+/// .unwrap();
+/// ```
+pub struct Scraper {
+    interval: Duration,
+    timeout: Duration,
+    label: String,
+    targets: Vec<ScrapeTarget>,
+}
+
+impl Scraper {
+    /// Create a scraper with no targets which scrapes at the provided
+    /// interval. Each request times out after the interval.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            timeout: interval,
+            label: DEFAULT_LABEL.to_string(),
+            targets: Vec::new(),
+        }
+    }
+
+    /// Add a target.
+    pub fn target(mut self, target: ScrapeTarget) -> Self {
+        self.targets.push(target);
+        self
+    }
+
+    /// Sets the time allowed for each request. Targets which do not respond
+    /// in time are reported as down.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the metadata key used to label metrics with their target. The
+    /// default is `source`.
+    pub fn label(mut self, key: impl Into<String>) -> Self {
+        self.label = key.into();
+        self
+    }
+
+    /// Scrape every target once and combine the results. The snapshot has
+    /// the time the scrape started.
+    pub fn scrape(&self) -> Snapshot {
+        let mut combined = Snapshot::new();
+
+        let results: Vec<Result<Snapshot, ScrapeError>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .targets
+                .iter()
+                .map(|target| scope.spawn(|| target.scrape(self.timeout)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        for (target, result) in self.targets.iter().zip(results) {
+            let up = match result {
+                Ok(snapshot) => {
+                    self.combine(&mut combined, &target.name, snapshot);
+                    1
+                }
+                Err(_e) => {
+                    #[cfg(feature = "log")]
+                    log::warn!("failed to scrape {}: {_e}", target.name);
+                    0
+                }
+            };
+
+            combined.gauges.push(Gauge {
+                name: UP.to_string(),
+                metric_type: MetricType::Gauge,
+                value: up,
+                metadata: [(self.label.clone(), target.name.clone())].into(),
+            });
+        }
+
+        combined
+    }
+
+    /// Add the metrics of a target to the combined snapshot.
+    fn combine(&self, combined: &mut Snapshot, name: &str, snapshot: Snapshot) {
+        macro_rules! combine {
+            ($field:ident) => {
+                for mut metric in snapshot.$field {
+                    metric.metadata.insert(self.label.clone(), name.to_string());
+                    combined.$field.push(metric);
+                }
+            };
+        }
+
+        combine!(counters);
+        combine!(gauges);
+        combine!(histograms);
+        combine!(stats);
+        combine!(sketches);
+        combine!(cardinalities);
+        combine!(top_k);
+    }
+
+    /// Scrape the targets at the start of every interval, aligned to the
+    /// unix epoch, and pass each combined snapshot to `handle`. This only
+    /// returns if `handle` returns an error.
+    pub fn run<E>(&self, mut handle: impl FnMut(Snapshot) -> Result<(), E>) -> Result<(), E> {
+        let interval = self.interval.as_nanos().max(1);
+
+        loop {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            std::thread::sleep(Duration::from_nanos((interval - now % interval) as u64));
+
+            handle(self.scrape())?;
+        }
+    }
+}
+
+/// The host, port, and path of an `http://` URL.
+fn parse_url(url: &str) -> Result<(&str, u16, &str), ScrapeError> {
+    let invalid = || ScrapeError::Url(url.to_string());
+
+    let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, port.parse().map_err(|_| invalid())?),
+        _ => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok((host, port, path))
+}
+
+/// Perform an HTTP/1.1 `GET` request and return the response body.
+fn get(url: &str, auth: Option<&Auth>, timeout: Duration) -> Result<Vec<u8>, ScrapeError> {
+    let (host, port, path) = parse_url(url)?;
+    let deadline = Instant::now() + timeout;
+
+    let addr = (host.trim_start_matches('[').trim_end_matches(']'), port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| ScrapeError::Url(url.to_string()))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+
+    let mut request =
+        format!("GET {path} HTTP/1.1\r\nHost: {host}\r\nAccept: */*\r\nConnection: close\r\n");
+    if let Some(auth) = auth {
+        request.push_str(&format!("Authorization: {}\r\n", auth.header_value()));
+    }
+    request.push_str("\r\n");
+
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = Vec::new();
+    let mut buf = [0; 16384];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into());
+        }
+        stream.set_read_timeout(Some(remaining))?;
+
+        match stream.read(&mut buf)? {
+            0 => break,
+            n => response.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    parse_response(&response)
+}
+
+/// Extract the body of an HTTP response, checking the status and decoding
+/// chunked transfer encoding.
+fn parse_response(response: &[u8]) -> Result<Vec<u8>, ScrapeError> {
+    let malformed = || ScrapeError::Http("malformed response".to_string());
+
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let head = std::str::from_utf8(&response[..end]).map_err(|_| malformed())?;
+    let body = &response[end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines.next().ok_or_else(malformed)?;
+    let code = status.split_whitespace().nth(1).ok_or_else(malformed)?;
+    if code != "200" {
+        return Err(ScrapeError::Http(format!("unexpected status: {status}")));
+    }
+
+    let mut chunked = false;
+    let mut length = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("content-length") {
+            length = Some(value.parse::<usize>().map_err(|_| malformed())?);
+        }
+    }
+
+    if !chunked {
+        return match length {
+            Some(length) => body.get(..length).map(|b| b.to_vec()).ok_or_else(malformed),
+            None => Ok(body.to_vec()),
+        };
+    }
+
+    let mut decoded = Vec::new();
+    let mut rest = body;
+    loop {
+        let line_end = rest
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(malformed)?;
+        let size = std::str::from_utf8(&rest[..line_end]).map_err(|_| malformed())?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| malformed())?;
+
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+
+        decoded.extend_from_slice(rest.get(..size).ok_or_else(malformed)?);
+        rest = rest.get(size + 2..).ok_or_else(malformed)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// Serve a single canned response and return the URL to request it from
+    /// along with a handle which returns the request that was received.
+    fn serve(response: String) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        (url, handle)
+    }
+
+    #[test]
+    fn url() {
+        assert_eq!(
+            parse_url("http://localhost:9100/metrics").unwrap(),
+            ("localhost", 9100, "/metrics")
+        );
+        assert_eq!(
+            parse_url("http://example.com").unwrap(),
+            ("example.com", 80, "/")
+        );
+        assert!(parse_url("https://example.com/").is_err());
+        assert!(parse_url("http://:80/").is_err());
+    }
+
+    #[test]
+    fn chunked() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                         4\r\nup 1\r\n1;ext=1\r\n\n\r\n0\r\n\r\n";
+        assert_eq!(parse_response(response).unwrap(), b"up 1\n");
+
+        let response = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+        assert!(matches!(
+            parse_response(response),
+            Err(ScrapeError::Http(_))
+        ));
+    }
+
+    #[test]
+    fn scrape() {
+        let body = "# TYPE requests counter\nrequests 42\n";
+        let (url, server) = serve(format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ));
+
+        let scraper = Scraper::new(Duration::from_secs(5))
+            .target(
+                ScrapeTarget::new("app", url, ScrapeFormat::Prometheus).auth(Auth::bearer("token")),
+            )
+            .target(ScrapeTarget::new(
+                "missing",
+                "http://",
+                ScrapeFormat::Prometheus,
+            ));
+        let snapshot = scraper.scrape();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /metrics HTTP/1.1\r\n"));
+        assert!(request.contains("Authorization: Bearer token\r\n"));
+
+        assert_eq!(snapshot.counters().len(), 1);
+        assert_eq!(snapshot.counters()[0].value, 42);
+        assert_eq!(snapshot.counters()[0].metadata["source"], "app");
+
+        let up: Vec<(&str, i64)> = snapshot
+            .gauges()
+            .iter()
+            .map(|g| (g.metadata["source"].as_str(), g.value))
+            .collect();
+        assert_eq!(up, [("app", 1), ("missing", 0)]);
+    }
+}
@@ -0,0 +1,535 @@
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use memmap2::MmapMut;
+
+use crate::shmem::ShmemError;
+use crate::snapshot::{Counter, Gauge, Histogram, MetricType, Snapshot};
+
+/// The magic bytes at the start of every shared registry.
+const MAGIC: u64 = u64::from_le_bytes(*b"MKNSHR01");
+
+/// The version of the registry layout.
+const VERSION: u64 = 1;
+
+/// The size of the header in bytes. The header holds, in order, the magic,
+/// the layout version, the length of the directory, and the offset of the
+/// metric storage, each as a native-endian `u64`. The directory follows the
+/// header and is a msgpack encoded list of metric entries.
+const HEADER_LEN: usize = 32;
+
+/// Each metric starts on its own cache line so that processes updating
+/// different metrics do not contend with each other.
+const CACHE_LINE: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram {
+        grouping_power: u8,
+        max_value_power: u8,
+    },
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    name: String,
+    kind: Kind,
+    /// The byte offset of the metric. This is relative to the start of the
+    /// metric storage in the file and absolute once the file is mapped.
+    offset: usize,
+}
+
+impl Kind {
+    fn len(&self) -> Result<usize, ShmemError> {
+        match self {
+            Self::Counter | Self::Gauge => Ok(8),
+            Self::Histogram {
+                grouping_power,
+                max_value_power,
+            } => {
+                let config = histogram::Config::new(*grouping_power, *max_value_power)?;
+                Ok(config.total_buckets() * 8)
+            }
+        }
+    }
+}
+
+/// Builds the layout of a [`SharedRegistry`].
+#[derive(Default)]
+pub struct SharedRegistryBuilder {
+    metrics: Vec<(String, Kind)>,
+}
+
+impl SharedRegistryBuilder {
+    /// Add a counter with the provided name.
+    pub fn counter(mut self, name: impl Into<String>) -> Self {
+        self.metrics.push((name.into(), Kind::Counter));
+        self
+    }
+
+    /// Add a gauge with the provided name.
+    pub fn gauge(mut self, name: impl Into<String>) -> Self {
+        self.metrics.push((name.into(), Kind::Gauge));
+        self
+    }
+
+    /// Add a histogram with the provided name and configuration.
+    pub fn histogram(
+        mut self,
+        name: impl Into<String>,
+        grouping_power: u8,
+        max_value_power: u8,
+    ) -> Self {
+        self.metrics.push((
+            name.into(),
+            Kind::Histogram {
+                grouping_power,
+                max_value_power,
+            },
+        ));
+        self
+    }
+
+    /// Create or truncate the file at the provided path and lay out the
+    /// registry within it. All metrics start at zero.
+    pub fn create(self, path: impl AsRef<Path>) -> Result<SharedRegistry, ShmemError> {
+        let mut entries = Vec::with_capacity(self.metrics.len());
+        let mut offset = 0;
+        for (name, kind) in self.metrics {
+            let len = kind.len()?;
+            entries.push(Entry { name, kind, offset });
+            offset += len.next_multiple_of(CACHE_LINE);
+        }
+        let data_len = offset;
+
+        let directory = rmp_serde::to_vec(&entries)?;
+        let data_offset = (HEADER_LEN + directory.len()).next_multiple_of(CACHE_LINE);
+        for entry in entries.iter_mut() {
+            entry.offset += data_offset;
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len((data_offset + data_len) as u64)?;
+
+        // SAFETY: the file was just created and sized by us. Other processes
+        // only ever modify it through atomic operations.
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        mmap[HEADER_LEN..HEADER_LEN + directory.len()].copy_from_slice(&directory);
+        mmap[8..16].copy_from_slice(&VERSION.to_ne_bytes());
+        mmap[16..24].copy_from_slice(&(directory.len() as u64).to_ne_bytes());
+        mmap[24..32].copy_from_slice(&(data_offset as u64).to_ne_bytes());
+        mmap[0..8].copy_from_slice(&MAGIC.to_ne_bytes());
+        mmap.flush()?;
+
+        Ok(SharedRegistry {
+            mmap: Arc::new(mmap),
+            entries,
+        })
+    }
+}
+
+/// A set of metrics stored in a memory-mapped file, which can be updated by
+/// several processes and read as one.
+///
+/// This is intended for pre-fork servers. The parent creates the registry
+/// before forking so that every worker inherits the mapping, or workers may
+/// [`open`](Self::open) it by path. Every process updates the same atomic
+/// values, so a single [`snapshot`](Self::snapshot) taken by any process
+/// reports the totals across all of them.
+///
+/// Counters and histograms are summed across processes. Gauges hold a single
+/// shared value, so they are best updated with [`SharedGauge::add`] and
+/// [`SharedGauge::sub`] rather than set.
+///
+/// ```no_run
+/// # use metriken_exposition::SharedRegistry;
+/// let registry = SharedRegistry::builder()
+///     .counter("requests")
+///     .histogram("latency", 7, 64)
+///     .create("/dev/shm/server-metrics")
+///     .unwrap();
+///
+/// // in each worker
+/// let requests = registry.counter("requests").unwrap();
+/// requests.increment();
+///
+/// // in the parent
+/// let snapshot = registry.snapshot();
+/// ```
+#[derive(Clone)]
+pub struct SharedRegistry {
+    mmap: Arc<MmapMut>,
+    entries: Vec<Entry>,
+}
+
+impl SharedRegistry {
+    /// Start building the layout of a new registry.
+    pub fn builder() -> SharedRegistryBuilder {
+        SharedRegistryBuilder::default()
+    }
+
+    /// Map a registry which was created by another process.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ShmemError> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        // SAFETY: the registry is only modified through atomic operations
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        let header = |idx: usize| -> Option<u64> {
+            let bytes = mmap.get(idx * 8..idx * 8 + 8)?;
+            Some(u64::from_ne_bytes(bytes.try_into().ok()?))
+        };
+
+        if header(0) != Some(MAGIC) || header(1) != Some(VERSION) {
+            return Err(ShmemError::InvalidRegion);
+        }
+
+        let directory_len = header(2).ok_or(ShmemError::InvalidRegion)? as usize;
+        let directory = mmap
+            .get(HEADER_LEN..HEADER_LEN.saturating_add(directory_len))
+            .ok_or(ShmemError::InvalidRegion)?;
+        let mut entries: Vec<Entry> = rmp_serde::from_slice(directory)?;
+
+        let data_offset = header(3).ok_or(ShmemError::InvalidRegion)? as usize;
+        if !data_offset.is_multiple_of(CACHE_LINE) || data_offset < HEADER_LEN + directory_len {
+            return Err(ShmemError::InvalidRegion);
+        }
+
+        for entry in entries.iter_mut() {
+            entry.offset = entry.offset.saturating_add(data_offset);
+            if !entry.offset.is_multiple_of(CACHE_LINE)
+                || entry.offset.saturating_add(entry.kind.len()?) > mmap.len()
+            {
+                return Err(ShmemError::InvalidRegion);
+            }
+        }
+
+        Ok(Self {
+            mmap: Arc::new(mmap),
+            entries,
+        })
+    }
+
+    fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// Get a handle to the counter with the provided name.
+    pub fn counter(&self, name: &str) -> Option<SharedCounter> {
+        let entry = self.entry(name).filter(|e| e.kind == Kind::Counter)?;
+        Some(SharedCounter {
+            mmap: self.mmap.clone(),
+            offset: entry.offset,
+        })
+    }
+
+    /// Get a handle to the gauge with the provided name.
+    pub fn gauge(&self, name: &str) -> Option<SharedGauge> {
+        let entry = self.entry(name).filter(|e| e.kind == Kind::Gauge)?;
+        Some(SharedGauge {
+            mmap: self.mmap.clone(),
+            offset: entry.offset,
+        })
+    }
+
+    /// Get a handle to the histogram with the provided name.
+    pub fn histogram(&self, name: &str) -> Option<SharedHistogram> {
+        let entry = self.entry(name)?;
+        let Kind::Histogram {
+            grouping_power,
+            max_value_power,
+        } = entry.kind
+        else {
+            return None;
+        };
+
+        Some(SharedHistogram {
+            mmap: self.mmap.clone(),
+            offset: entry.offset,
+            config: histogram::Config::new(grouping_power, max_value_power).ok()?,
+        })
+    }
+
+    /// Read the current value of every metric in the registry.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        snapshot.systemtime = SystemTime::now();
+        self.read(&mut snapshot);
+        snapshot
+    }
+
+    /// Add the current value of every metric to the snapshot.
+    fn read(&self, snapshot: &mut Snapshot) {
+        for entry in &self.entries {
+            match entry.kind {
+                Kind::Counter => snapshot.counters.push(Counter {
+                    name: entry.name.clone(),
+                    metric_type: MetricType::Counter,
+                    value: self.counter(&entry.name).map(|c| c.value()).unwrap_or(0),
+                    metadata: Default::default(),
+                }),
+                Kind::Gauge => snapshot.gauges.push(Gauge {
+                    name: entry.name.clone(),
+                    metric_type: MetricType::Gauge,
+                    value: self.gauge(&entry.name).map(|g| g.value()).unwrap_or(0),
+                    metadata: Default::default(),
+                }),
+                Kind::Histogram { .. } => {
+                    if let Some(value) = self.histogram(&entry.name).map(|h| h.load()) {
+                        snapshot.histograms.push(Histogram {
+                            name: entry.name.clone(),
+                            metric_type: MetricType::Histogram,
+                            value,
+                            metadata: Default::default(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// One process can take snapshots of the registry with a
+/// [`Snapshotter`](crate::Snapshotter) built by
+/// [`SnapshotterBuilder::with_source`](crate::SnapshotterBuilder::with_source),
+/// which adds sequence numbers, the clock guard, and any budget or history
+/// configured for it.
+#[cfg(feature = "snapshotter")]
+impl crate::SnapshotSource for SharedRegistry {
+    fn collect(&self, snapshot: &mut Snapshot) {
+        self.read(snapshot);
+    }
+}
+
+/// Get the atomic word at the provided byte offset within the mapping.
+///
+/// SAFETY: the offset must be word aligned and within the mapping, which is
+/// checked when the registry is created or opened.
+unsafe fn word<T>(mmap: &MmapMut, offset: usize) -> &T {
+    &*(mmap.as_ptr().add(offset) as *const T)
+}
+
+/// A counter stored in a [`SharedRegistry`].
+#[derive(Clone)]
+pub struct SharedCounter {
+    mmap: Arc<MmapMut>,
+    offset: usize,
+}
+
+impl SharedCounter {
+    fn atomic(&self) -> &AtomicU64 {
+        // SAFETY: the offset was validated against the mapping
+        unsafe { word(&self.mmap, self.offset) }
+    }
+
+    /// Increment the counter by one.
+    pub fn increment(&self) -> u64 {
+        self.add(1)
+    }
+
+    /// Add to the counter, returning the previous value.
+    pub fn add(&self, value: u64) -> u64 {
+        self.atomic().fetch_add(value, Ordering::Relaxed)
+    }
+
+    /// The current value of the counter across all processes.
+    pub fn value(&self) -> u64 {
+        self.atomic().load(Ordering::Relaxed)
+    }
+}
+
+/// A gauge stored in a [`SharedRegistry`].
+#[derive(Clone)]
+pub struct SharedGauge {
+    mmap: Arc<MmapMut>,
+    offset: usize,
+}
+
+impl SharedGauge {
+    fn atomic(&self) -> &AtomicI64 {
+        // SAFETY: the offset was validated against the mapping
+        unsafe { word(&self.mmap, self.offset) }
+    }
+
+    /// Add to the gauge, returning the previous value.
+    pub fn add(&self, value: i64) -> i64 {
+        self.atomic().fetch_add(value, Ordering::Relaxed)
+    }
+
+    /// Subtract from the gauge, returning the previous value.
+    pub fn sub(&self, value: i64) -> i64 {
+        self.atomic().fetch_sub(value, Ordering::Relaxed)
+    }
+
+    /// Set the gauge, returning the previous value. This overwrites the value
+    /// set by any other process.
+    pub fn set(&self, value: i64) -> i64 {
+        self.atomic().swap(value, Ordering::Relaxed)
+    }
+
+    /// The current value of the gauge.
+    pub fn value(&self) -> i64 {
+        self.atomic().load(Ordering::Relaxed)
+    }
+}
+
+/// A histogram stored in a [`SharedRegistry`].
+#[derive(Clone)]
+pub struct SharedHistogram {
+    mmap: Arc<MmapMut>,
+    offset: usize,
+    config: histogram::Config,
+}
+
+impl SharedHistogram {
+    fn bucket(&self, index: usize) -> &AtomicU64 {
+        // SAFETY: the offset and the length of the buckets were validated
+        // against the mapping, and the index is less than the bucket count
+        unsafe { word(&self.mmap, self.offset + index * 8) }
+    }
+
+    /// Record a single value.
+    pub fn increment(&self, value: u64) -> Result<(), histogram::Error> {
+        self.add(value, 1)
+    }
+
+    /// Record multiple instances of a value.
+    pub fn add(&self, value: u64, count: u64) -> Result<(), histogram::Error> {
+        let index = bucket_index(&self.config, value)?;
+        self.bucket(index).fetch_add(count, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Read the histogram across all processes.
+    pub fn load(&self) -> histogram::Histogram {
+        let buckets: Vec<u64> = (0..self.config.total_buckets())
+            .map(|i| self.bucket(i).load(Ordering::Relaxed))
+            .collect();
+
+        histogram::Histogram::from_buckets(
+            self.config.grouping_power(),
+            self.config.max_value_power(),
+            buckets,
+        )
+        .expect("bucket count matches config")
+    }
+}
+
+/// The index of the bucket for a value, matching the bucketing used by the
+/// `histogram` crate.
+fn bucket_index(config: &histogram::Config, value: u64) -> Result<usize, histogram::Error> {
+    let grouping_power = config.grouping_power() as u32;
+    let cutoff_power = grouping_power + 1;
+    let cutoff_value = 1_u64 << cutoff_power;
+
+    if value < cutoff_value {
+        return Ok(value as usize);
+    }
+
+    if config.max_value_power() < 64 && value >= 1 << config.max_value_power() {
+        return Err(histogram::Error::OutOfRange);
+    }
+
+    let power = 63 - value.leading_zeros();
+    let log_bin = (power - cutoff_power) as u64;
+    let offset = (value - (1 << power)) >> (power - grouping_power);
+
+    Ok((cutoff_value + (log_bin << grouping_power) + offset) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucketing() {
+        let config = histogram::Config::new(3, 20).unwrap();
+        for value in [0, 1, 15, 16, 17, 100, 1000, (1 << 20) - 1] {
+            let mut expected = histogram::Histogram::with_config(&config);
+            expected.increment(value).unwrap();
+            let index = expected.as_slice().iter().position(|c| *c == 1).unwrap();
+            assert_eq!(bucket_index(&config, value).unwrap(), index);
+        }
+        assert!(bucket_index(&config, 1 << 20).is_err());
+    }
+
+    #[test]
+    fn shared() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry");
+
+        let registry = SharedRegistry::builder()
+            .counter("requests")
+            .gauge("connections")
+            .histogram("latency", 4, 20)
+            .create(&path)
+            .unwrap();
+
+        // a second mapping of the same file, as another process would have
+        let other = SharedRegistry::open(&path).unwrap();
+
+        registry.counter("requests").unwrap().add(2);
+        other.counter("requests").unwrap().increment();
+        registry.gauge("connections").unwrap().add(5);
+        other.gauge("connections").unwrap().sub(2);
+        registry
+            .histogram("latency")
+            .unwrap()
+            .increment(100)
+            .unwrap();
+        other.histogram("latency").unwrap().add(100, 2).unwrap();
+
+        assert!(registry.counter("connections").is_none());
+        assert!(registry.counter("missing").is_none());
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.counters[0].value, 3);
+        assert_eq!(snapshot.gauges[0].value, 3);
+        let mut expected = histogram::Histogram::new(4, 20).unwrap();
+        expected.add(100, 3).unwrap();
+        assert_eq!(snapshot.histograms[0].value, expected);
+    }
+
+    #[cfg(feature = "snapshotter")]
+    #[test]
+    fn snapshotter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry");
+
+        let registry = SharedRegistry::builder()
+            .counter("requests")
+            .gauge("connections")
+            .create(&path)
+            .unwrap();
+        let worker = SharedRegistry::open(&path).unwrap();
+
+        let snapshotter = crate::SnapshotterBuilder::with_source(registry)
+            .metadata("source".to_string(), "shared".to_string())
+            .build();
+
+        worker.counter("requests").unwrap().add(4);
+        worker.gauge("connections").unwrap().add(2);
+
+        let first = snapshotter.snapshot();
+        assert_eq!(first.counter("requests"), Some(4));
+        assert_eq!(first.gauge("connections"), Some(2));
+        assert_eq!(first.get_metadata("source"), Some("shared"));
+
+        worker.counter("requests").unwrap().increment();
+        let second = snapshotter.snapshot();
+        assert_eq!(second.counter("requests"), Some(5));
+        assert!(first.sequence().is_some());
+        assert_eq!(second.sequence(), first.sequence().map(|s| s + 1));
+    }
+}
@@ -0,0 +1,345 @@
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+use memmap2::{Mmap, MmapMut};
+use rmp_serde::decode::Error as DeserializeMsgpackError;
+use rmp_serde::encode::Error as SerializeMsgpackError;
+
+use crate::snapshot::Snapshot;
+
+/// The magic bytes at the start of every region.
+const MAGIC: u64 = u64::from_le_bytes(*b"MKNSHM01");
+
+/// The version of the region layout.
+const VERSION: u64 = 1;
+
+/// The size of the header in bytes. The header holds, in order, the magic,
+/// the layout version, the capacity of the payload, the sequence number, and
+/// the length of the current payload, each as a native-endian `u64`.
+const HEADER_LEN: usize = 40;
+
+const MAGIC_WORD: usize = 0;
+const VERSION_WORD: usize = 1;
+const CAPACITY_WORD: usize = 2;
+const SEQUENCE_WORD: usize = 3;
+const LENGTH_WORD: usize = 4;
+
+/// The number of times a reader retries while the writer is updating the
+/// region before giving up.
+const MAX_RETRIES: usize = 1024;
+
+/// Errors that can occur while writing or reading a shared memory region.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ShmemError {
+    Io(std::io::Error),
+    Msgpack(DeserializeMsgpackError),
+    MsgpackEncode(SerializeMsgpackError),
+    Histogram(histogram::Error),
+    /// The file is not a region, or was written with an unsupported layout.
+    InvalidRegion,
+    /// The payload is larger than the capacity of the region.
+    TooLarge {
+        len: usize,
+        capacity: usize,
+    },
+    /// The region was being updated on every attempt to read it. This can
+    /// happen if the writer exited partway through an update.
+    Busy,
+}
+
+impl std::fmt::Display for ShmemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Msgpack(e) => write!(f, "msgpack decode error: {e}"),
+            Self::MsgpackEncode(e) => write!(f, "msgpack encode error: {e}"),
+            Self::Histogram(e) => write!(f, "invalid histogram: {e}"),
+            Self::InvalidRegion => write!(f, "not a snapshot region"),
+            Self::TooLarge { len, capacity } => {
+                write!(f, "payload of {len} bytes exceeds capacity of {capacity}")
+            }
+            Self::Busy => write!(f, "region is being updated"),
+        }
+    }
+}
+
+impl std::error::Error for ShmemError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Msgpack(e) => Some(e),
+            Self::MsgpackEncode(e) => Some(e),
+            Self::Histogram(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ShmemError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<DeserializeMsgpackError> for ShmemError {
+    fn from(e: DeserializeMsgpackError) -> Self {
+        Self::Msgpack(e)
+    }
+}
+
+impl From<SerializeMsgpackError> for ShmemError {
+    fn from(e: SerializeMsgpackError) -> Self {
+        Self::MsgpackEncode(e)
+    }
+}
+
+impl From<histogram::Error> for ShmemError {
+    fn from(e: histogram::Error) -> Self {
+        Self::Histogram(e)
+    }
+}
+
+/// View a mapped region as a slice of atomic words.
+///
+/// Both the header and the payload are accessed through atomics since they
+/// are shared with other processes which may be writing or reading
+/// concurrently.
+fn words(region: &[u8]) -> &[AtomicU64] {
+    debug_assert_eq!(
+        region.as_ptr() as usize % std::mem::align_of::<AtomicU64>(),
+        0
+    );
+    // SAFETY: mappings are page aligned, the length is rounded down to whole
+    // words, and `AtomicU64` has the same layout as `u64`
+    unsafe {
+        std::slice::from_raw_parts(
+            region.as_ptr() as *const AtomicU64,
+            region.len() / std::mem::size_of::<u64>(),
+        )
+    }
+}
+
+/// Writes the latest snapshot into a memory-mapped file so that other
+/// processes can read it without any involvement from the writer.
+///
+/// The region is protected by a sequence lock. The writer never blocks and
+/// makes no syscalls when updating the region. Readers copy the payload out
+/// and retry if the sequence number shows that it was modified while they were
+/// copying it.
+///
+/// Placing the file on a memory-backed filesystem such as `/dev/shm` avoids
+/// writeback to disk.
+pub struct ShmemWriter {
+    mmap: MmapMut,
+    capacity: usize,
+}
+
+impl ShmemWriter {
+    /// Create or truncate the file at the provided path and map a region
+    /// which can hold payloads of up to `capacity` bytes.
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> Result<Self, ShmemError> {
+        let capacity = capacity.next_multiple_of(std::mem::size_of::<u64>());
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len((HEADER_LEN + capacity) as u64)?;
+
+        // SAFETY: the file was just created and sized by us. Other processes
+        // only ever access it through the atomic operations used here.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        let header = words(&mmap);
+        header[VERSION_WORD].store(VERSION, Ordering::Relaxed);
+        header[CAPACITY_WORD].store(capacity as u64, Ordering::Relaxed);
+        header[SEQUENCE_WORD].store(0, Ordering::Relaxed);
+        header[LENGTH_WORD].store(0, Ordering::Relaxed);
+        // The magic is written last so that readers never see a partially
+        // initialized header.
+        header[MAGIC_WORD].store(MAGIC, Ordering::Release);
+
+        Ok(Self { mmap, capacity })
+    }
+
+    /// The maximum payload size in bytes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of times the region has been written to.
+    pub fn generation(&self) -> u64 {
+        words(&self.mmap)[SEQUENCE_WORD].load(Ordering::Relaxed) / 2
+    }
+
+    /// Replace the contents of the region with the provided payload.
+    pub fn write(&mut self, payload: &[u8]) -> Result<(), ShmemError> {
+        if payload.len() > self.capacity {
+            return Err(ShmemError::TooLarge {
+                len: payload.len(),
+                capacity: self.capacity,
+            });
+        }
+
+        let words = words(&self.mmap);
+        let sequence = &words[SEQUENCE_WORD];
+
+        // an odd sequence number marks the region as being updated
+        let start = sequence.load(Ordering::Relaxed);
+        sequence.store(start.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        words[LENGTH_WORD].store(payload.len() as u64, Ordering::Relaxed);
+        for (word, chunk) in words[HEADER_LEN / 8..]
+            .iter()
+            .zip(payload.chunks(std::mem::size_of::<u64>()))
+        {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            word.store(u64::from_ne_bytes(bytes), Ordering::Relaxed);
+        }
+
+        sequence.store(start.wrapping_add(2), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Serialize a snapshot as msgpack and write it to the region.
+    pub fn write_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), ShmemError> {
+        let bytes = Snapshot::to_msgpack(snapshot)?;
+        self.write(&bytes)
+    }
+}
+
+/// Reads the latest snapshot from a region written by a [`ShmemWriter`].
+pub struct ShmemReader {
+    mmap: Mmap,
+}
+
+impl ShmemReader {
+    /// Map the region in the file at the provided path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ShmemError> {
+        let file = File::open(path)?;
+
+        // SAFETY: the region is only modified through atomic operations and
+        // every read of it goes through atomic operations as well
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_LEN {
+            return Err(ShmemError::InvalidRegion);
+        }
+
+        let header = words(&mmap);
+        if header[MAGIC_WORD].load(Ordering::Acquire) != MAGIC
+            || header[VERSION_WORD].load(Ordering::Relaxed) != VERSION
+            || header[CAPACITY_WORD].load(Ordering::Relaxed) as usize > mmap.len() - HEADER_LEN
+        {
+            return Err(ShmemError::InvalidRegion);
+        }
+
+        Ok(Self { mmap })
+    }
+
+    /// The number of times the region has been written to. This can be
+    /// polled cheaply to detect when a new snapshot is available.
+    pub fn generation(&self) -> u64 {
+        words(&self.mmap)[SEQUENCE_WORD].load(Ordering::Acquire) / 2
+    }
+
+    /// Copy the current payload into the provided buffer, returning the
+    /// generation it was written in. The buffer is empty if the region has
+    /// not been written to yet.
+    pub fn read_into(&self, buf: &mut Vec<u8>) -> Result<u64, ShmemError> {
+        let words = words(&self.mmap);
+        let sequence = &words[SEQUENCE_WORD];
+        let capacity = words[CAPACITY_WORD].load(Ordering::Relaxed) as usize;
+
+        for _ in 0..MAX_RETRIES {
+            let start = sequence.load(Ordering::Acquire);
+            if start % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            let len = (words[LENGTH_WORD].load(Ordering::Relaxed) as usize).min(capacity);
+
+            buf.clear();
+            for word in &words[HEADER_LEN / 8..HEADER_LEN / 8 + len.div_ceil(8)] {
+                buf.extend_from_slice(&word.load(Ordering::Relaxed).to_ne_bytes());
+            }
+            buf.truncate(len);
+
+            fence(Ordering::Acquire);
+            if sequence.load(Ordering::Relaxed) == start {
+                return Ok(start / 2);
+            }
+        }
+
+        Err(ShmemError::Busy)
+    }
+
+    /// Copy out the current payload.
+    pub fn read(&self) -> Result<Vec<u8>, ShmemError> {
+        let mut buf = Vec::new();
+        self.read_into(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read and deserialize the current snapshot. Returns `None` if no
+    /// snapshot has been written yet.
+    pub fn snapshot(&self) -> Result<Option<Snapshot>, ShmemError> {
+        let mut buf = Vec::new();
+        if self.read_into(&mut buf)? == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(rmp_serde::from_slice(&buf)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics");
+
+        let mut writer = ShmemWriter::create(&path, 1 << 16).unwrap();
+        let reader = ShmemReader::open(&path).unwrap();
+        assert_eq!(reader.generation(), 0);
+        assert!(reader.snapshot().unwrap().is_none());
+
+        writer.write(b"hello").unwrap();
+        assert_eq!(reader.read().unwrap(), b"hello");
+        assert_eq!(reader.generation(), 1);
+
+        let snapshot = Snapshot::builder().build().unwrap();
+        writer.write_snapshot(&snapshot).unwrap();
+        assert_eq!(reader.generation(), 2);
+        assert_eq!(
+            reader.snapshot().unwrap().unwrap().systemtime,
+            snapshot.systemtime
+        );
+
+        assert!(matches!(
+            writer.write(&vec![0; 1 << 17]),
+            Err(ShmemError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), [0; 64]).unwrap();
+        assert!(matches!(
+            ShmemReader::open(file.path()),
+            Err(ShmemError::InvalidRegion)
+        ));
+    }
+}
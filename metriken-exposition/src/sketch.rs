@@ -0,0 +1,440 @@
+use std::collections::BTreeMap;
+
+use crate::exponential::{ExponentialHistogram, MAX_SCALE, MIN_SCALE};
+
+/// An error working with a [`DDSketch`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum SketchError {
+    /// The relative accuracy is not between zero and one.
+    InvalidAccuracy(f64),
+    /// The sketches being merged have different relative accuracies.
+    AccuracyMismatch,
+}
+
+impl std::fmt::Display for SketchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidAccuracy(accuracy) => write!(
+                f,
+                "relative accuracy must be between zero and one, not {accuracy}"
+            ),
+            Self::AccuracyMismatch => {
+                write!(
+                    f,
+                    "sketches with different relative accuracies can't be merged"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SketchError {}
+
+/// A DDSketch, a distribution which answers quantile queries with a bounded
+/// relative error, for exchanging distributions with systems which speak
+/// sketches natively, such as Datadog and OTLP exponential histograms.
+///
+/// Each value is placed in the bin with index `ceil(log_γ(|v|))`, where
+/// `γ = (1 + α) / (1 - α)` for a relative accuracy `α`, so any quantile is
+/// estimated to within `α` of its true value. Sketches with the same relative
+/// accuracy can be merged without any loss of accuracy. Negative values are
+/// held in a separate set of bins, and zero is counted on its own.
+///
+/// ```
+/// # use metriken_exposition::DDSketch;
+/// let mut sketch = DDSketch::new(0.01).unwrap();
+/// for value in 1..=1000 {
+///     sketch.add(value as f64);
+/// }
+///
+/// let p99 = sketch.quantile(0.99).unwrap();
+/// assert!((p99 - 990.0).abs() <= 990.0 * 0.01);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DDSketch {
+    relative_accuracy: f64,
+    gamma: f64,
+    zero_count: u64,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+}
+
+impl DDSketch {
+    /// An empty sketch with the provided relative accuracy, such as `0.01`
+    /// for quantiles within 1% of their true value.
+    pub fn new(relative_accuracy: f64) -> Result<Self, SketchError> {
+        if !(relative_accuracy > 0.0 && relative_accuracy < 1.0) {
+            return Err(SketchError::InvalidAccuracy(relative_accuracy));
+        }
+
+        Ok(Self {
+            relative_accuracy,
+            gamma: (1.0 + relative_accuracy) / (1.0 - relative_accuracy),
+            zero_count: 0,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+        })
+    }
+
+    /// An empty sketch whose bins are the buckets of an exponential histogram
+    /// with the provided scale, so that it converts to and from
+    /// [`ExponentialHistogram`] exactly.
+    pub fn with_scale(scale: i32) -> Self {
+        let gamma = 2.0_f64.powf(2.0_f64.powi(-scale));
+        Self {
+            relative_accuracy: (gamma - 1.0) / (gamma + 1.0),
+            gamma,
+            zero_count: 0,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+        }
+    }
+
+    /// The relative accuracy of the quantiles estimated by the sketch.
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+
+    fn index(&self, value: f64) -> i32 {
+        (value.ln() / self.gamma.ln()).ceil() as i32
+    }
+
+    /// The value reported for the bin at the provided index, which is
+    /// within the relative accuracy of every value in the bin.
+    fn value(&self, index: i32) -> f64 {
+        2.0 * self.gamma.powi(index) / (self.gamma + 1.0)
+    }
+
+    /// Add a value to the sketch. NaN is ignored.
+    pub fn add(&mut self, value: f64) {
+        self.add_n(value, 1);
+    }
+
+    /// Add a value to the sketch `count` times. NaN is ignored.
+    pub fn add_n(&mut self, value: f64, count: u64) {
+        if value.is_nan() || count == 0 {
+            return;
+        }
+
+        // values too small to be told apart from zero are counted as zero
+        if value.abs() < f64::MIN_POSITIVE {
+            self.zero_count += count;
+            return;
+        }
+
+        let index = self.index(value.abs());
+        let bins = if value > 0.0 {
+            &mut self.positive
+        } else {
+            &mut self.negative
+        };
+        *bins.entry(index).or_default() += count;
+    }
+
+    /// Remove every value, keeping the relative accuracy.
+    pub fn clear(&mut self) {
+        self.zero_count = 0;
+        self.positive.clear();
+        self.negative.clear();
+    }
+
+    /// The number of values in the sketch.
+    pub fn count(&self) -> u64 {
+        self.zero_count + self.positive.values().sum::<u64>() + self.negative.values().sum::<u64>()
+    }
+
+    /// Whether the sketch holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// The number of values which are zero.
+    pub fn zero_count(&self) -> u64 {
+        self.zero_count
+    }
+
+    /// The non-empty bins of positive values, as pairs of index and count,
+    /// in increasing order of index.
+    pub fn positive_bins(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        self.positive.iter().map(|(index, count)| (*index, *count))
+    }
+
+    /// The non-empty bins of negative values, by the index of their absolute
+    /// value, in increasing order of index.
+    pub fn negative_bins(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        self.negative.iter().map(|(index, count)| (*index, *count))
+    }
+
+    /// Estimate the value at the provided quantile, from `0.0` to `1.0`.
+    /// Returns `None` if the sketch is empty or the quantile is out of
+    /// range.
+    pub fn quantile(&self, quantile: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 || !(0.0..=1.0).contains(&quantile) {
+            return None;
+        }
+
+        let rank = (quantile * (count - 1) as f64).floor() as u64;
+        let mut seen = 0;
+
+        // negative values, from the most negative
+        for (index, n) in self.negative.iter().rev() {
+            seen += n;
+            if seen > rank {
+                return Some(-self.value(*index));
+            }
+        }
+
+        seen += self.zero_count;
+        if seen > rank {
+            return Some(0.0);
+        }
+
+        for (index, n) in &self.positive {
+            seen += n;
+            if seen > rank {
+                return Some(self.value(*index));
+            }
+        }
+
+        None
+    }
+
+    /// Add the values of another sketch with the same relative accuracy.
+    pub fn merge(&mut self, other: &DDSketch) -> Result<(), SketchError> {
+        if self.gamma != other.gamma {
+            return Err(SketchError::AccuracyMismatch);
+        }
+
+        self.zero_count += other.zero_count;
+        for (index, count) in &other.positive {
+            *self.positive.entry(*index).or_default() += count;
+        }
+        for (index, count) in &other.negative {
+            *self.negative.entry(*index).or_default() += count;
+        }
+        Ok(())
+    }
+
+    /// Convert a metriken histogram.
+    ///
+    /// A histogram only knows which bucket each value was in, so each bucket
+    /// is counted at its upper bound, as for [`ExponentialHistogram`]. The
+    /// quantiles of the sketch are within its relative accuracy of those of
+    /// the histogram, not of the original values.
+    pub fn from_histogram(
+        histogram: &histogram::Histogram,
+        relative_accuracy: f64,
+    ) -> Result<Self, SketchError> {
+        let mut sketch = Self::new(relative_accuracy)?;
+        for bucket in histogram {
+            sketch.add_n(bucket.end() as f64, bucket.count());
+        }
+        Ok(sketch)
+    }
+
+    /// Convert into a metriken histogram with the provided configuration.
+    ///
+    /// Each bin is recorded at its estimated value, rounded to the nearest
+    /// integer and clamped to the range of the histogram. Negative values
+    /// are recorded as zero.
+    pub fn to_histogram(
+        &self,
+        grouping_power: u8,
+        max_value_power: u8,
+    ) -> Result<histogram::Histogram, histogram::Error> {
+        let mut histogram = histogram::Histogram::new(grouping_power, max_value_power)?;
+        let max = if max_value_power >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << max_value_power) - 1
+        };
+
+        let negative = self.negative.values().sum::<u64>();
+        if self.zero_count + negative > 0 {
+            histogram.add(0, self.zero_count + negative)?;
+        }
+        for (index, count) in &self.positive {
+            let value = self.value(*index).round();
+            let value = if value >= max as f64 {
+                max
+            } else {
+                value as u64
+            };
+            histogram.add(value, *count)?;
+        }
+
+        Ok(histogram)
+    }
+
+    /// Convert an exponential histogram, without any loss of accuracy, into
+    /// a sketch created with [`DDSketch::with_scale`].
+    pub fn from_exponential(exponential: &ExponentialHistogram) -> Self {
+        let mut sketch = Self::with_scale(exponential.scale);
+        sketch.zero_count = exponential.zero_count;
+
+        // the exponential bucket at index `i` covers `(base^i, base^(i+1)]`,
+        // which is the bin at index `i + 1`
+        for (position, count) in exponential.counts.iter().enumerate() {
+            if *count > 0 {
+                sketch
+                    .positive
+                    .insert(exponential.offset + position as i32 + 1, *count);
+            }
+        }
+        sketch
+    }
+
+    /// Convert into an exponential histogram, without any loss of accuracy.
+    ///
+    /// Returns `None` if the bins of the sketch are not the buckets of an
+    /// exponential histogram with a scale from -4 to 8, as for sketches
+    /// created with [`DDSketch::with_scale`], or if it holds negative values.
+    pub fn to_exponential(&self) -> Option<ExponentialHistogram> {
+        if !self.negative.is_empty() {
+            return None;
+        }
+
+        let scale = -self.gamma.log2().log2();
+        if (scale - scale.round()).abs() > 1e-9 {
+            return None;
+        }
+        let scale = scale.round() as i32;
+        if !(MIN_SCALE..=MAX_SCALE).contains(&scale) {
+            return None;
+        }
+
+        let offset = self.positive.keys().next().map(|i| i - 1).unwrap_or(0);
+        let mut counts = Vec::new();
+        for (index, count) in &self.positive {
+            let position = (index - 1 - offset) as usize;
+            counts.resize(position, 0);
+            counts.push(*count);
+        }
+
+        Some(ExponentialHistogram {
+            scale,
+            zero_count: self.zero_count,
+            offset,
+            counts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_accuracy() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        for value in 1..=10_000 {
+            sketch.add(value as f64);
+        }
+        sketch.add_n(0.0, 10);
+        assert_eq!(sketch.count(), 10_010);
+
+        for quantile in [0.01, 0.5, 0.9, 0.99, 0.999, 1.0] {
+            let estimate = sketch.quantile(quantile).unwrap();
+            let rank = (quantile * 10_009.0).floor();
+            let actual = (rank - 9.0).max(0.0);
+            assert!(
+                (estimate - actual).abs() <= actual * 0.02,
+                "{quantile}: {estimate} vs {actual}"
+            );
+        }
+        assert_eq!(sketch.quantile(0.0), Some(0.0));
+        assert_eq!(sketch.quantile(1.5), None);
+        assert_eq!(DDSketch::new(0.02).unwrap().quantile(0.5), None);
+    }
+
+    #[test]
+    fn negative_values() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        for value in [-100.0, -10.0, 0.0, 10.0, 100.0] {
+            sketch.add(value);
+        }
+        sketch.add(f64::NAN);
+        assert_eq!(sketch.count(), 5);
+
+        let quantiles: Vec<f64> = [0.0, 0.25, 0.5, 0.75, 1.0]
+            .iter()
+            .map(|q| sketch.quantile(*q).unwrap().round())
+            .collect();
+        assert_eq!(quantiles, [-100.0, -10.0, 0.0, 10.0, 100.0]);
+    }
+
+    #[test]
+    fn merge() {
+        let (mut a, mut b) = (DDSketch::new(0.01).unwrap(), DDSketch::new(0.01).unwrap());
+        let mut both = DDSketch::new(0.01).unwrap();
+        for value in 1..=100 {
+            a.add(value as f64);
+            b.add(value as f64 * 10.0);
+            both.add(value as f64);
+            both.add(value as f64 * 10.0);
+        }
+
+        a.merge(&b).unwrap();
+        assert_eq!(a, both);
+        assert_eq!(
+            a.merge(&DDSketch::new(0.05).unwrap()),
+            Err(SketchError::AccuracyMismatch)
+        );
+        assert_eq!(
+            DDSketch::new(1.0).map(|_| ()),
+            Err(SketchError::InvalidAccuracy(1.0))
+        );
+    }
+
+    #[test]
+    fn exponential() {
+        let mut histogram = histogram::Histogram::new(3, 20).unwrap();
+        histogram.add(0, 2).unwrap();
+        histogram.add(1, 1).unwrap();
+        histogram.add(1000, 5).unwrap();
+
+        let exponential = ExponentialHistogram::from_histogram(&histogram);
+        let sketch = DDSketch::from_exponential(&exponential);
+        assert_eq!(sketch.count(), 8);
+        assert_eq!(sketch.to_exponential(), Some(exponential));
+
+        // the estimate of 1000 is within the accuracy of scale 3
+        let p100 = sketch.quantile(1.0).unwrap();
+        assert!((p100 - 1000.0).abs() <= 1000.0 * sketch.relative_accuracy());
+
+        // an arbitrary accuracy has no matching scale
+        assert_eq!(DDSketch::new(0.01).unwrap().to_exponential(), None);
+    }
+
+    #[test]
+    fn histogram() {
+        let mut histogram = histogram::Histogram::new(7, 32).unwrap();
+        histogram.add(0, 3).unwrap();
+        histogram.add(1_000_000, 100).unwrap();
+
+        let sketch = DDSketch::from_histogram(&histogram, 0.01).unwrap();
+        assert_eq!(sketch.count(), 103);
+        assert_eq!(sketch.zero_count(), 3);
+
+        let converted = sketch.to_histogram(7, 32).unwrap();
+        let p99 = converted.percentile(99.0).unwrap().unwrap();
+        let original = histogram.percentile(99.0).unwrap().unwrap();
+        assert!((p99.end() as f64 - original.end() as f64).abs() <= original.end() as f64 * 0.02);
+    }
+
+    #[cfg(all(feature = "serde", feature = "json"))]
+    #[test]
+    fn serde() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        sketch.add(5.0);
+        sketch.add(-5.0);
+
+        let json = serde_json::to_string(&sketch).unwrap();
+        let decoded: DDSketch = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.quantile(1.0), sketch.quantile(1.0));
+        assert_eq!(decoded.count(), 2);
+    }
+}
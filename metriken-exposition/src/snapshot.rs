@@ -1,46 +1,500 @@
-use std::collections::HashMap;
-use std::time::SystemTime;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 
+use metriken_core::Unit;
+
+#[cfg(feature = "cbor")]
+use ciborium::ser::Error as SerializeCborError;
+#[cfg(feature = "postcard")]
+use postcard::Error as PostcardError;
 #[cfg(feature = "msgpack")]
 use rmp_serde::encode::Error as SerializeMsgpackError;
 #[cfg(feature = "json")]
 use serde_json::Error as JsonError;
 
+/// The snapshot metadata key which holds the length of the interval covered
+/// by a snapshot, in nanoseconds, when it is known.
+pub const DURATION: &str = "duration_ns";
+
+/// The snapshot metadata key which holds the sequence number assigned by the
+/// `Snapshotter` which took the snapshot. Numbers start at zero and increase
+/// by one for each snapshot.
+pub const SEQUENCE: &str = "sequence";
+
+/// The prefix of the snapshot metadata keys which hold resource attributes,
+/// such as `resource.service.name`. These describe the process or host that
+/// produced every metric in the snapshot, and exporters render them in the
+/// way that is idiomatic for their format rather than as labels on each
+/// metric.
+pub const RESOURCE: &str = "resource.";
+
 // TODO(bmartin): derive Debug for Snapshot once the histogram snapshot has its
 // own debug impl.
 
-#[derive(Clone)]
+/// The type of a metric within a snapshot.
+///
+/// This is authoritative for exporters, which should not rely on the section
+/// of the snapshot that the metric appears in to determine its type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum MetricType {
+    /// A monotonically increasing counter holding a cumulative total.
+    Counter,
+    /// A counter holding the change since the previous snapshot. The value
+    /// is not monotonic across snapshots.
+    DeltaCounter,
+    /// A value which may go up or down.
+    Gauge,
+    /// A distribution of values holding a cumulative total.
+    Histogram,
+    /// A distribution of the values recorded since the previous snapshot.
+    /// The counts are not monotonic across snapshots.
+    DeltaHistogram,
+    /// Summary statistics for the values recorded over the snapshot interval.
+    Summary,
+    /// A sketch of a distribution of values, such as a [`DDSketch`].
+    ///
+    /// [`DDSketch`]: crate::DDSketch
+    Sketch,
+    /// An estimate of the number of distinct values seen over the snapshot
+    /// interval, held as a [`HyperLogLog`].
+    ///
+    /// [`HyperLogLog`]: crate::HyperLogLog
+    Cardinality,
+    /// The keys recorded most often over the snapshot interval, along with
+    /// their counts.
+    TopK,
+}
+
+impl MetricType {
+    /// Indicates whether the value of the metric can only increase from one
+    /// snapshot to the next.
+    pub fn is_monotonic(&self) -> bool {
+        matches!(self, Self::Counter | Self::Histogram)
+    }
+
+    // Defaults for snapshots serialized before the metric type was recorded.
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn counter() -> Self {
+        Self::Counter
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn gauge() -> Self {
+        Self::Gauge
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn histogram() -> Self {
+        Self::Histogram
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn summary() -> Self {
+        Self::Summary
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn sketch() -> Self {
+        Self::Sketch
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn cardinality() -> Self {
+        Self::Cardinality
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn top_k() -> Self {
+        Self::TopK
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub struct Counter {
     pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "MetricType::counter"))]
+    pub metric_type: MetricType,
     pub value: u64,
     pub metadata: HashMap<String, String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub struct Gauge {
     pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "MetricType::gauge"))]
+    pub metric_type: MetricType,
     pub value: i64,
     pub metadata: HashMap<String, String>,
 }
 
-#[derive(Clone)]
+/// A distribution of values.
+///
+/// Histograms are equal when they have the same configuration and the same
+/// count in every bucket, so the same values recorded into histograms with
+/// different configurations are not equal. Use [`rebucket`] to compare them.
+///
+/// [`rebucket`]: crate::rebucket
+#[derive(Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub struct Histogram {
     pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "MetricType::histogram"))]
+    pub metric_type: MetricType,
     pub value: histogram::Histogram,
     pub metadata: HashMap<String, String>,
 }
 
+/// The statistics recorded by a [`metriken::Stats`] metric over the snapshot
+/// interval.
+///
+/// `min` and `max` are `None` if no values were recorded during the interval.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Stats {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "MetricType::summary"))]
+    pub metric_type: MetricType,
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+    pub sum: u64,
+    pub count: u64,
+    pub metadata: HashMap<String, String>,
+}
+
+/// A distribution held as a [`DDSketch`], such as one received from a system
+/// which speaks sketches natively.
+///
+/// As for histograms, a sketch holds every value recorded since it was
+/// created, unless its `temporality` metadata key is `delta`, in which case
+/// it holds only the values recorded since the previous snapshot.
+///
+/// [`DDSketch`]: crate::DDSketch
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Sketch {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "MetricType::sketch"))]
+    pub metric_type: MetricType,
+    pub value: crate::DDSketch,
+    pub metadata: HashMap<String, String>,
+}
+
+/// The distinct values seen over the snapshot interval, such as the unique
+/// clients, held as a [`HyperLogLog`] along with its estimate.
+///
+/// The sketches of several processes, or of several intervals, can be merged
+/// to count the distinct values across all of them.
+///
+/// [`HyperLogLog`]: crate::HyperLogLog
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Cardinality {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "MetricType::cardinality"))]
+    pub metric_type: MetricType,
+    pub value: crate::HyperLogLog,
+    /// The estimate of the number of distinct values in `value`.
+    pub estimate: u64,
+    pub metadata: HashMap<String, String>,
+}
+
+/// The keys recorded most often over the snapshot interval, such as the
+/// hottest keys of a cache, as tracked by the SpaceSaving algorithm.
+///
+/// Up to `k` keys are tracked, with the highest count first. The true count
+/// of each key is between its `count - error` and its `count`.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct TopK {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "MetricType::top_k"))]
+    pub metric_type: MetricType,
+    /// The maximum number of keys tracked.
+    pub k: u64,
+    pub entries: Vec<HeavyHitter>,
+    pub metadata: HashMap<String, String>,
+}
+
+/// A key of a [`TopK`] along with its count.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct HeavyHitter {
+    pub key: String,
+    /// The count of the key, which may be an overestimate by up to `error`.
+    pub count: u64,
+    pub error: u64,
+}
+
+macro_rules! impl_metadata_accessors {
+    ($($ty:ty),*) => {
+        $(
+            impl $ty {
+                /// The human-readable description of this metric, from the
+                /// `description` metadata key.
+                pub fn description(&self) -> Option<&str> {
+                    self.metadata.get("description").map(|v| v.as_str())
+                }
+
+                /// The unit of this metric, from the `unit` metadata key.
+                pub fn unit(&self) -> Option<Unit<'_>> {
+                    self.metadata.get("unit").map(|unit| Unit::parse(unit))
+                }
+
+                /// The name of this metric along with its metadata, such as
+                /// `requests{op=get}`, which tells apart metrics which share
+                /// a name. The metadata keys which only describe the metric,
+                /// such as `description` and `unit`, are left out, as in the
+                /// text format.
+                pub fn canonical_name(&self) -> String {
+                    crate::text::text_name(&self.name, &self.metadata)
+                }
+            }
+        )*
+    };
+}
+
+impl_metadata_accessors!(Counter, Gauge, Histogram, Stats, Sketch, Cardinality, TopK);
+
+impl Counter {
+    /// A counter holding a cumulative total, with no metadata.
+    pub fn new(name: impl Into<String>, value: u64) -> Self {
+        Self {
+            name: name.into(),
+            metric_type: MetricType::Counter,
+            value,
+            metadata: HashMap::new(),
+        }
+    }
+}
+
+impl Gauge {
+    /// A gauge with no metadata.
+    pub fn new(name: impl Into<String>, value: i64) -> Self {
+        Self {
+            name: name.into(),
+            metric_type: MetricType::Gauge,
+            value,
+            metadata: HashMap::new(),
+        }
+    }
+}
+
+impl Histogram {
+    /// A histogram whose metadata holds only its `grouping_power` and
+    /// `max_value_power`.
+    pub fn new(name: impl Into<String>, value: histogram::Histogram) -> Self {
+        let config = value.config();
+        let metadata = HashMap::from([
+            (
+                "grouping_power".to_string(),
+                config.grouping_power().to_string(),
+            ),
+            (
+                "max_value_power".to_string(),
+                config.max_value_power().to_string(),
+            ),
+        ]);
+
+        Self {
+            name: name.into(),
+            metric_type: MetricType::Histogram,
+            value,
+            metadata,
+        }
+    }
+}
+
+impl Stats {
+    /// Statistics for an interval in which no values were recorded, with no
+    /// metadata.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            metric_type: MetricType::Summary,
+            min: None,
+            max: None,
+            sum: 0,
+            count: 0,
+            metadata: HashMap::new(),
+        }
+    }
+}
+
+impl Sketch {
+    /// A sketch with no metadata.
+    pub fn new(name: impl Into<String>, value: crate::DDSketch) -> Self {
+        Self {
+            name: name.into(),
+            metric_type: MetricType::Sketch,
+            value,
+            metadata: HashMap::new(),
+        }
+    }
+}
+
+impl Cardinality {
+    /// A cardinality with no metadata, and the estimate of the sketch.
+    pub fn new(name: impl Into<String>, value: crate::HyperLogLog) -> Self {
+        Self {
+            name: name.into(),
+            metric_type: MetricType::Cardinality,
+            estimate: value.estimate(),
+            value,
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Add the values seen by another sketch, updating the estimate.
+    pub fn merge(&mut self, other: &crate::HyperLogLog) -> Result<(), crate::HyperLogLogError> {
+        self.value.merge(other)?;
+        self.estimate = self.value.estimate();
+        Ok(())
+    }
+}
+
+impl TopK {
+    /// Heavy hitters with no metadata, which are sorted with the highest
+    /// count first.
+    pub fn new(name: impl Into<String>, k: u64, mut entries: Vec<HeavyHitter>) -> Self {
+        sort_heavy_hitters(&mut entries);
+        Self {
+            name: name.into(),
+            metric_type: MetricType::TopK,
+            k,
+            entries,
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Add the keys recorded by another `TopK`, such as one from another
+    /// process or another interval, keeping the larger `k`.
+    ///
+    /// A key which is missing from a full `TopK` may have been recorded up
+    /// to as many times as its lowest count, so that is added to both the
+    /// count and the error of the keys which only the other tracks.
+    pub fn merge(&mut self, other: &TopK) {
+        let floor = |top: &TopK| match top.entries.len() as u64 >= top.k {
+            true => top.entries.iter().map(|e| e.count).min().unwrap_or(0),
+            false => 0,
+        };
+        let (floor, other_floor) = (floor(self), floor(other));
+
+        let mut keys: HashMap<&str, (u64, u64)> = self
+            .entries
+            .iter()
+            .map(|e| (e.key.as_str(), (e.count, e.error)))
+            .collect();
+        let mut entries: Vec<HeavyHitter> = Vec::new();
+        for entry in &other.entries {
+            let (count, error) = keys.remove(entry.key.as_str()).unwrap_or((floor, floor));
+            entries.push(HeavyHitter::new(
+                entry.key.clone(),
+                count.saturating_add(entry.count),
+                error.saturating_add(entry.error),
+            ));
+        }
+        for (key, (count, error)) in keys {
+            entries.push(HeavyHitter::new(
+                key,
+                count.saturating_add(other_floor),
+                error.saturating_add(other_floor),
+            ));
+        }
+
+        self.k = self.k.max(other.k);
+        sort_heavy_hitters(&mut entries);
+        entries.truncate(self.k as usize);
+        self.entries = entries;
+    }
+}
+
+impl HeavyHitter {
+    /// A key whose true count is between `count - error` and `count`.
+    pub fn new(key: impl Into<String>, count: u64, error: u64) -> Self {
+        Self {
+            key: key.into(),
+            count,
+            error,
+        }
+    }
+}
+
+/// Sort heavy hitters by count, with the highest first, and then by key.
+fn sort_heavy_hitters(entries: &mut [HeavyHitter]) {
+    entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+}
+
+/// A point-in-time annotation on the snapshot stream, such as a deploy, a
+/// garbage collection, or a failover, which can be correlated with changes in
+/// the metrics around it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Event {
+    pub name: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::timestamp::deserialize")
+    )]
+    pub systemtime: SystemTime,
+    pub metadata: HashMap<String, String>,
+}
+
+impl Event {
+    /// An event which happened now, with no metadata.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            systemtime: SystemTime::now(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Set the time the event happened.
+    pub fn systemtime(mut self, systemtime: SystemTime) -> Self {
+        self.systemtime = systemtime;
+        self
+    }
+
+    /// Add a metadata key to the event, such as the version being deployed.
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
 /// Contains a snapshot of metric readings.
+///
+/// Snapshots have a single serialized format rather than one per version, so
+/// deserializing never has to guess which version a payload is. Sections
+/// which were added after the first release, such as `stats` and `events`,
+/// are empty when they are missing, so snapshots written by older versions
+/// can still be read.
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone)]
 #[non_exhaustive]
 pub struct Snapshot {
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::timestamp::deserialize")
+    )]
     pub systemtime: SystemTime,
 
     #[cfg_attr(feature = "serde", serde(default))]
@@ -49,6 +503,75 @@ pub struct Snapshot {
     pub counters: Vec<Counter>,
     pub gauges: Vec<Gauge>,
     pub histograms: Vec<Histogram>,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stats: Vec<Stats>,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sketches: Vec<Sketch>,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cardinalities: Vec<Cardinality>,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub top_k: Vec<TopK>,
+
+    /// Events which happened since the previous snapshot.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub events: Vec<Event>,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) index: SnapshotIndex,
+}
+
+/// The position of each counter, gauge, and histogram of a snapshot by its
+/// canonical name, which is built by the first lookup.
+#[derive(Clone, Default)]
+pub(crate) struct SnapshotIndex(OnceLock<Positions>);
+
+#[derive(Clone)]
+struct Positions {
+    counters: Section,
+    gauges: Section,
+    histograms: Section,
+}
+
+/// The positions of the metrics in one section of a snapshot, along with the
+/// length of the section when they were indexed.
+#[derive(Clone)]
+struct Section {
+    positions: HashMap<String, usize>,
+    len: usize,
+}
+
+impl Section {
+    fn new(names: impl ExactSizeIterator<Item = String>) -> Self {
+        let len = names.len();
+        let mut positions = HashMap::with_capacity(len);
+        for (position, name) in names.enumerate() {
+            positions.entry(name).or_insert(position);
+        }
+        Self { positions, len }
+    }
+
+    /// Find the metric with the canonical name. The metrics of a snapshot
+    /// can be modified after they were indexed, so a name which is not
+    /// indexed, a result which does not match, or a section which has changed
+    /// length falls back to a scan.
+    fn find<'a, T>(
+        &self,
+        metrics: &'a [T],
+        name: &str,
+        canonical: impl Fn(&T) -> String,
+    ) -> Option<&'a T> {
+        if metrics.len() == self.len {
+            let indexed = self.positions.get(name).map(|p| &metrics[*p]);
+            if let Some(metric) = indexed.filter(|m| canonical(m) == name) {
+                return Some(metric);
+            }
+        }
+        metrics.iter().find(|m| canonical(m) == name)
+    }
 }
 
 #[cfg(feature = "parquet")]
@@ -57,8 +580,200 @@ pub(crate) struct HashedSnapshot {
     pub(crate) counters: HashMap<String, Counter>,
     pub(crate) gauges: HashMap<String, Gauge>,
     pub(crate) histograms: HashMap<String, Histogram>,
+    pub(crate) stats: HashMap<String, Stats>,
+    pub(crate) events: Vec<Event>,
+}
+
+/// Snapshots are equal when they have the same time, metadata, metrics, and
+/// events, regardless of the order of the metrics and events within each
+/// section.
+impl PartialEq for Snapshot {
+    fn eq(&self, other: &Self) -> bool {
+        self.systemtime == other.systemtime
+            && self.metadata == other.metadata
+            && same_entries(&self.counters, &other.counters)
+            && same_entries(&self.gauges, &other.gauges)
+            && same_entries(&self.histograms, &other.histograms)
+            && same_entries(&self.stats, &other.stats)
+            && same_entries(&self.sketches, &other.sketches)
+            && same_entries(&self.cardinalities, &other.cardinalities)
+            && same_entries(&self.top_k, &other.top_k)
+            && same_entries(&self.events, &other.events)
+    }
+}
+
+/// Returns `true` if the sections hold the same entries, in any order.
+fn same_entries<T: PartialEq + ContentHash>(a: &[T], b: &[T]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    if a == b {
+        return true;
+    }
+
+    // equal entries have the same content hash, so each entry only needs to
+    // be compared with the entries of the other section in its bucket
+    let mut buckets: HashMap<u64, Vec<&T>> = HashMap::with_capacity(b.len());
+    for entry in b {
+        buckets.entry(entry_hash(entry)).or_default().push(entry);
+    }
+    a.iter().all(|x| {
+        let Some(bucket) = buckets.get_mut(&entry_hash(x)) else {
+            return false;
+        };
+        match bucket.iter().position(|y| *y == x) {
+            Some(i) => {
+                bucket.swap_remove(i);
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Hashes the content of a snapshot or one of its entries, so that values
+/// which are equal have the same hash.
+trait ContentHash {
+    fn hash_content(&self, state: &mut DefaultHasher);
+}
+
+fn hash_metadata(metadata: &HashMap<String, String>, state: &mut DefaultHasher) {
+    let mut entries: Vec<(&String, &String)> = metadata.iter().collect();
+    entries.sort_unstable();
+    entries.hash(state);
+}
+
+/// Hash the entries of a section so that their order does not matter.
+fn hash_section<T: ContentHash>(entries: &[T], state: &mut DefaultHasher) {
+    let sum = entries
+        .iter()
+        .fold(0u64, |sum, entry| sum.wrapping_add(entry_hash(entry)));
+    (entries.len(), sum).hash(state);
+}
+
+fn entry_hash<T: ContentHash>(entry: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    entry.hash_content(&mut hasher);
+    hasher.finish()
+}
+
+fn hash_histogram(histogram: &histogram::Histogram, state: &mut DefaultHasher) {
+    let config = histogram.config();
+    (config.grouping_power(), config.max_value_power()).hash(state);
+    histogram.as_slice().hash(state);
+}
+
+impl ContentHash for Counter {
+    fn hash_content(&self, state: &mut DefaultHasher) {
+        (&self.name, self.metric_type, self.value).hash(state);
+        hash_metadata(&self.metadata, state);
+    }
+}
+
+impl ContentHash for Gauge {
+    fn hash_content(&self, state: &mut DefaultHasher) {
+        (&self.name, self.metric_type, self.value).hash(state);
+        hash_metadata(&self.metadata, state);
+    }
+}
+
+impl ContentHash for Histogram {
+    fn hash_content(&self, state: &mut DefaultHasher) {
+        (&self.name, self.metric_type).hash(state);
+        hash_histogram(&self.value, state);
+        hash_metadata(&self.metadata, state);
+    }
+}
+
+impl ContentHash for Stats {
+    fn hash_content(&self, state: &mut DefaultHasher) {
+        (&self.name, self.metric_type).hash(state);
+        (self.min, self.max, self.sum, self.count).hash(state);
+        hash_metadata(&self.metadata, state);
+    }
+}
+
+impl ContentHash for Sketch {
+    fn hash_content(&self, state: &mut DefaultHasher) {
+        (&self.name, self.metric_type).hash(state);
+        let sketch = &self.value;
+        (sketch.relative_accuracy().to_bits(), sketch.zero_count()).hash(state);
+        sketch.positive_bins().for_each(|bin| bin.hash(state));
+        sketch.negative_bins().for_each(|bin| bin.hash(state));
+        hash_metadata(&self.metadata, state);
+    }
+}
+
+impl ContentHash for Cardinality {
+    fn hash_content(&self, state: &mut DefaultHasher) {
+        (&self.name, self.metric_type, self.estimate).hash(state);
+        (self.value.precision(), self.value.registers()).hash(state);
+        hash_metadata(&self.metadata, state);
+    }
+}
+
+impl ContentHash for TopK {
+    fn hash_content(&self, state: &mut DefaultHasher) {
+        (&self.name, self.metric_type, self.k).hash(state);
+        for entry in &self.entries {
+            (&entry.key, entry.count, entry.error).hash(state);
+        }
+        hash_metadata(&self.metadata, state);
+    }
+}
+
+impl ContentHash for Event {
+    fn hash_content(&self, state: &mut DefaultHasher) {
+        (&self.name, self.systemtime).hash(state);
+        hash_metadata(&self.metadata, state);
+    }
+}
+
+impl ContentHash for Snapshot {
+    fn hash_content(&self, state: &mut DefaultHasher) {
+        self.systemtime.hash(state);
+        hash_metadata(&self.metadata, state);
+        hash_section(&self.counters, state);
+        hash_section(&self.gauges, state);
+        hash_section(&self.histograms, state);
+        hash_section(&self.stats, state);
+        hash_section(&self.sketches, state);
+        hash_section(&self.cardinalities, state);
+        hash_section(&self.top_k, state);
+        hash_section(&self.events, state);
+    }
 }
 
+macro_rules! impl_content_hash {
+    ($($ty:ty),*) => {
+        $(
+            impl $ty {
+                /// A hash of the content, which is the same for values which
+                /// are equal, such as to deduplicate them. The hash is stable
+                /// within a build, but may change with the version of this
+                /// crate or of Rust, so it should not be persisted.
+                pub fn content_hash(&self) -> u64 {
+                    let mut hasher = DefaultHasher::new();
+                    self.hash_content(&mut hasher);
+                    hasher.finish()
+                }
+            }
+        )*
+    };
+}
+
+impl_content_hash!(
+    Snapshot,
+    Counter,
+    Gauge,
+    Histogram,
+    Stats,
+    Sketch,
+    Cardinality,
+    TopK,
+    Event
+);
+
 impl Snapshot {
     pub(crate) fn new() -> Self {
         Self {
@@ -67,20 +782,96 @@ impl Snapshot {
             counters: Vec::new(),
             gauges: Vec::new(),
             histograms: Vec::new(),
+            stats: Vec::new(),
+            sketches: Vec::new(),
+            cardinalities: Vec::new(),
+            top_k: Vec::new(),
+            events: Vec::new(),
+            index: SnapshotIndex::default(),
         }
     }
 
+    /// Start building a snapshot from metrics which do not come from a
+    /// metriken registry, such as those ingested from another system.
+    pub fn builder() -> SnapshotBuilder {
+        SnapshotBuilder::default()
+    }
+
     /// The system time when the snapshot was created.
     pub fn systemtime(&self) -> SystemTime {
         self.systemtime
     }
 
+    /// The sequence number of the snapshot, from the [`SEQUENCE`] metadata
+    /// key, if it has one.
+    pub fn sequence(&self) -> Option<u64> {
+        self.get_metadata(SEQUENCE)?.parse().ok()
+    }
+
     /// Fetch the value for a snapshot metadata key.
     pub fn get_metadata(&self, key: &str) -> Option<&str> {
         self.metadata.get(key).map(|x| x.as_str())
     }
 
+    /// The resource attributes of the snapshot, from the metadata keys with
+    /// the [`RESOURCE`] prefix, with the prefix removed and sorted by key.
+    pub fn resource(&self) -> Vec<(&str, &str)> {
+        let mut resource: Vec<(&str, &str)> = self
+            .metadata
+            .iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix(RESOURCE)?, v.as_str())))
+            .collect();
+        resource.sort_unstable();
+        resource
+    }
+
+    fn positions(&self) -> &Positions {
+        self.index.0.get_or_init(|| Positions {
+            counters: Section::new(self.counters.iter().map(Counter::canonical_name)),
+            gauges: Section::new(self.gauges.iter().map(Gauge::canonical_name)),
+            histograms: Section::new(self.histograms.iter().map(Histogram::canonical_name)),
+        })
+    }
+
+    /// The value of the counter with the provided canonical name. See
+    /// [`Counter::canonical_name`].
+    ///
+    /// The first lookup builds an index of the counters, gauges, and
+    /// histograms, so that later lookups do not need to scan the snapshot.
+    pub fn counter(&self, name: &str) -> Option<u64> {
+        let counter =
+            self.positions()
+                .counters
+                .find(&self.counters, name, Counter::canonical_name)?;
+        Some(counter.value)
+    }
+
+    /// The value of the gauge with the provided canonical name. See
+    /// [`Snapshot::counter`].
+    pub fn gauge(&self, name: &str) -> Option<i64> {
+        let gauge = self
+            .positions()
+            .gauges
+            .find(&self.gauges, name, Gauge::canonical_name)?;
+        Some(gauge.value)
+    }
+
+    /// The value of the histogram with the provided canonical name. See
+    /// [`Snapshot::counter`].
+    pub fn histogram(&self, name: &str) -> Option<&histogram::Histogram> {
+        let histogram =
+            self.positions()
+                .histograms
+                .find(&self.histograms, name, Histogram::canonical_name)?;
+        Some(&histogram.value)
+    }
+
     /// A view into the counters for this snapshot.
+    ///
+    /// This and the other section accessors borrow the snapshot, so they can
+    /// be called any number of times. To take ownership of the metrics, move
+    /// them out of the public fields, such as with
+    /// `std::mem::take(&mut snapshot.counters)`.
     pub fn counters(&self) -> &[Counter] {
         &self.counters
     }
@@ -95,6 +886,183 @@ impl Snapshot {
         &self.histograms
     }
 
+    /// A view into the stats for this snapshot.
+    pub fn stats(&self) -> &[Stats] {
+        &self.stats
+    }
+
+    /// A view into the sketches for this snapshot.
+    pub fn sketches(&self) -> &[Sketch] {
+        &self.sketches
+    }
+
+    /// A view into the cardinalities for this snapshot.
+    pub fn cardinalities(&self) -> &[Cardinality] {
+        &self.cardinalities
+    }
+
+    /// A view into the heavy hitters for this snapshot.
+    pub fn top_k(&self) -> &[TopK] {
+        &self.top_k
+    }
+
+    /// A view into the events for this snapshot.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Returns a new snapshot containing only the metrics with the provided
+    /// names. The snapshot time, metadata, and events are preserved.
+    pub fn project(&self, names: &[&str]) -> Self {
+        let keep = |name: &String| names.contains(&name.as_str());
+
+        Self {
+            systemtime: self.systemtime,
+            metadata: self.metadata.clone(),
+            counters: self
+                .counters
+                .iter()
+                .filter(|m| keep(&m.name))
+                .cloned()
+                .collect(),
+            gauges: self
+                .gauges
+                .iter()
+                .filter(|m| keep(&m.name))
+                .cloned()
+                .collect(),
+            histograms: self
+                .histograms
+                .iter()
+                .filter(|m| keep(&m.name))
+                .cloned()
+                .collect(),
+            stats: self
+                .stats
+                .iter()
+                .filter(|m| keep(&m.name))
+                .cloned()
+                .collect(),
+            sketches: self
+                .sketches
+                .iter()
+                .filter(|m| keep(&m.name))
+                .cloned()
+                .collect(),
+            cardinalities: self
+                .cardinalities
+                .iter()
+                .filter(|m| keep(&m.name))
+                .cloned()
+                .collect(),
+            top_k: self
+                .top_k
+                .iter()
+                .filter(|m| keep(&m.name))
+                .cloned()
+                .collect(),
+            events: self.events.clone(),
+            index: SnapshotIndex::default(),
+        }
+    }
+
+    /// Partition this snapshot by the value of a metric metadata key, such as
+    /// a tenant identifier.
+    ///
+    /// Each metric is moved into the child snapshot for its value of `key`,
+    /// with the key removed from the metric metadata. Children keep the time,
+    /// metadata, and events of this snapshot, and have `key` set in their
+    /// snapshot metadata so they can be routed without inspecting the metrics.
+    /// Metrics which do not have the key are returned under `None`.
+    pub fn split_by(self, key: &str) -> HashMap<Option<String>, Snapshot> {
+        let mut children: HashMap<Option<String>, Snapshot> = HashMap::new();
+
+        let empty = |value: &Option<String>| {
+            let mut metadata = self.metadata.clone();
+            if let Some(value) = value {
+                metadata.insert(key.to_string(), value.clone());
+            }
+            Snapshot {
+                systemtime: self.systemtime,
+                metadata,
+                counters: Vec::new(),
+                gauges: Vec::new(),
+                histograms: Vec::new(),
+                stats: Vec::new(),
+                sketches: Vec::new(),
+                cardinalities: Vec::new(),
+                top_k: Vec::new(),
+                events: self.events.clone(),
+                index: SnapshotIndex::default(),
+            }
+        };
+
+        macro_rules! split {
+            ($field:ident) => {
+                for mut metric in self.$field {
+                    let value = metric.metadata.remove(key);
+                    children
+                        .entry(value)
+                        .or_insert_with_key(empty)
+                        .$field
+                        .push(metric);
+                }
+            };
+        }
+
+        split!(counters);
+        split!(gauges);
+        split!(histograms);
+        split!(stats);
+        split!(sketches);
+        split!(cardinalities);
+        split!(top_k);
+
+        children
+    }
+
+    /// Remove all counters from this snapshot.
+    pub fn drop_counters(mut self) -> Self {
+        self.counters.clear();
+        self
+    }
+
+    /// Remove all gauges from this snapshot.
+    pub fn drop_gauges(mut self) -> Self {
+        self.gauges.clear();
+        self
+    }
+
+    /// Remove all histograms from this snapshot.
+    pub fn drop_histograms(mut self) -> Self {
+        self.histograms.clear();
+        self
+    }
+
+    /// Remove all stats from this snapshot.
+    pub fn drop_stats(mut self) -> Self {
+        self.stats.clear();
+        self
+    }
+
+    /// Remove all sketches from this snapshot.
+    pub fn drop_sketches(mut self) -> Self {
+        self.sketches.clear();
+        self
+    }
+
+    /// Remove all cardinalities from this snapshot.
+    pub fn drop_cardinalities(mut self) -> Self {
+        self.cardinalities.clear();
+        self
+    }
+
+    /// Remove all heavy hitters from this snapshot.
+    pub fn drop_top_k(mut self) -> Self {
+        self.top_k.clear();
+        self
+    }
+
     #[cfg(feature = "json")]
     pub fn to_json<T>(val: &T) -> Result<Vec<u8>, JsonError>
     where
@@ -105,6 +1073,27 @@ impl Snapshot {
         Ok(res)
     }
 
+    /// Serialize the snapshot as JSON, like [`Snapshot::to_json`], with its
+    /// times, field names, and key order as described by the options.
+    #[cfg(all(feature = "json", feature = "serde"))]
+    pub fn to_json_with(&self, options: &JsonOptions) -> Result<Vec<u8>, JsonError> {
+        if options.rfc3339.is_none() && !options.camel_case && !options.sort_keys {
+            return Self::to_json(self);
+        }
+
+        let mut value = serde_json::to_value(self)?;
+        if let Some(rfc3339) = &options.rfc3339 {
+            value["systemtime"] = rfc3339.format(self.systemtime).into();
+            for (idx, event) in self.events.iter().enumerate() {
+                value["events"][idx]["systemtime"] = rfc3339.format(event.systemtime).into();
+            }
+        }
+        if options.camel_case || options.sort_keys {
+            value = options.rewrite_keys(value, false);
+        }
+        Self::to_json(&value)
+    }
+
     #[cfg(feature = "msgpack")]
     pub fn to_msgpack<T>(val: &T) -> Result<Vec<u8>, SerializeMsgpackError>
     where
@@ -112,6 +1101,466 @@ impl Snapshot {
     {
         rmp_serde::encode::to_vec(val)
     }
+
+    #[cfg(feature = "cbor")]
+    pub fn to_cbor<T>(val: &T) -> Result<Vec<u8>, SerializeCborError<std::io::Error>>
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        let mut res = Vec::new();
+        ciborium::into_writer(val, &mut res)?;
+        Ok(res)
+    }
+
+    #[cfg(feature = "postcard")]
+    pub fn to_postcard<T>(val: &T) -> Result<Vec<u8>, PostcardError>
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        postcard::to_stdvec(val)
+    }
+}
+
+/// Options controlling how a snapshot is rendered as JSON by
+/// [`Snapshot::to_json_with`].
+///
+/// By default the time of the snapshot uses the serde representation of a
+/// `SystemTime`, which is an object of seconds and nanoseconds since the unix
+/// epoch. Snapshots with RFC 3339 times can be deserialized as usual.
+///
+/// Field names are in snake_case by default, and the metadata of the snapshot
+/// and its metrics is in no particular order. Consumers with a fixed contract
+/// can ask for camelCase field names and for the keys of every object to be
+/// sorted, so that the same snapshot always renders to the same bytes.
+#[cfg(all(feature = "json", feature = "serde"))]
+#[derive(Clone, Debug, Default)]
+pub struct JsonOptions {
+    rfc3339: Option<crate::Rfc3339>,
+    camel_case: bool,
+    sort_keys: bool,
+}
+
+#[cfg(all(feature = "json", feature = "serde"))]
+impl JsonOptions {
+    /// Create a new set of options with the default time representation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render the time of the snapshot as an RFC 3339 string in the provided
+    /// format.
+    pub fn rfc3339(mut self, format: crate::Rfc3339) -> Self {
+        self.rfc3339 = Some(format);
+        self
+    }
+
+    /// Render field names in camelCase, such as `topK` instead of `top_k`.
+    /// Metadata keys are left as they are. Snapshots rendered this way can't
+    /// be deserialized back into a [`Snapshot`].
+    pub fn camel_case(mut self, camel_case: bool) -> Self {
+        self.camel_case = camel_case;
+        self
+    }
+
+    /// Render the keys of every object, including metadata, in sorted order.
+    pub fn sort_keys(mut self, sort_keys: bool) -> Self {
+        self.sort_keys = sort_keys;
+        self
+    }
+
+    /// Rename and reorder the keys of the objects in the value. The keys of
+    /// metadata are user data rather than field names, and are not renamed.
+    fn rewrite_keys(&self, value: serde_json::Value, metadata: bool) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(object) => {
+                let mut entries: Vec<_> = object
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let value = self.rewrite_keys(value, key == "metadata");
+                        if self.camel_case && !metadata {
+                            (camel_case(&key), value)
+                        } else {
+                            (key, value)
+                        }
+                    })
+                    .collect();
+                if self.sort_keys {
+                    entries.sort_by(|a, b| a.0.cmp(&b.0));
+                }
+                serde_json::Value::Object(entries.into_iter().collect())
+            }
+            serde_json::Value::Array(values) => serde_json::Value::Array(
+                values
+                    .into_iter()
+                    .map(|value| self.rewrite_keys(value, false))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+}
+
+#[cfg(all(feature = "json", feature = "serde"))]
+fn camel_case(name: &str) -> String {
+    let mut parts = name.split('_');
+    let mut res = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            res.extend(first.to_uppercase());
+            res.push_str(chars.as_str());
+        }
+    }
+    res
+}
+
+/// Errors that can occur when building a snapshot with a [`SnapshotBuilder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SnapshotError {
+    /// A metric has an empty name.
+    EmptyName,
+    /// More than one metric has the same name and metadata.
+    Duplicate(String),
+    /// Metrics of different types have the same name.
+    TypeConflict(String),
+    /// The values of a stats metric are inconsistent.
+    InvalidStats(String),
+    /// An event has an empty name.
+    EmptyEventName,
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyName => write!(f, "metric name is empty"),
+            Self::Duplicate(name) => {
+                write!(
+                    f,
+                    "metric `{name}` appears more than once with the same metadata"
+                )
+            }
+            Self::TypeConflict(name) => write!(f, "metric `{name}` has more than one type"),
+            Self::InvalidStats(name) => write!(f, "stats metric `{name}` has inconsistent values"),
+            Self::EmptyEventName => write!(f, "event name is empty"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// Builds a [`Snapshot`] from individual metric readings.
+///
+/// The snapshot is validated when it is built: every metric must have a name,
+/// a name may only be used by metrics of one type, and each combination of
+/// name and metadata may only appear once. Metrics are sorted by name and
+/// metadata so that snapshots with the same contents are laid out the same
+/// way regardless of the order metrics were added.
+///
+/// ```
+/// # use metriken_exposition::Snapshot;
+/// let snapshot = Snapshot::builder()
+///     .metadata("source", "node_exporter")
+///     .counter("requests", 42, &[("method", "GET")])
+///     .gauge("connections", 7, &[])
+///     .build()
+///     .unwrap();
+/// assert_eq!(snapshot.counters()[0].value, 42);
+/// ```
+pub struct SnapshotBuilder {
+    snapshot: Snapshot,
+}
+
+impl Default for SnapshotBuilder {
+    fn default() -> Self {
+        Self {
+            snapshot: Snapshot::new(),
+        }
+    }
+}
+
+fn to_metadata(metadata: &[(&str, &str)]) -> HashMap<String, String> {
+    metadata
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+impl SnapshotBuilder {
+    /// Set the time of the snapshot. Defaults to the time the builder was
+    /// created.
+    pub fn systemtime(mut self, systemtime: SystemTime) -> Self {
+        self.snapshot.systemtime = systemtime;
+        self
+    }
+
+    /// Record the length of the interval covered by the snapshot in the
+    /// [`DURATION`] metadata key.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.snapshot
+            .metadata
+            .insert(DURATION.to_string(), duration.as_nanos().to_string());
+        self
+    }
+
+    /// Set a snapshot metadata key.
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.snapshot.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Set a resource attribute, such as `service.name`, which is stored in
+    /// the snapshot metadata with the [`RESOURCE`] prefix.
+    pub fn resource(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.snapshot
+            .metadata
+            .insert(format!("{RESOURCE}{key}"), value.into());
+        self
+    }
+
+    /// Add a counter holding a cumulative total.
+    pub fn counter(
+        mut self,
+        name: impl Into<String>,
+        value: u64,
+        metadata: &[(&str, &str)],
+    ) -> Self {
+        self.snapshot.counters.push(Counter {
+            name: name.into(),
+            metric_type: MetricType::Counter,
+            value,
+            metadata: to_metadata(metadata),
+        });
+        self
+    }
+
+    /// Add a counter holding the change since the previous snapshot.
+    pub fn delta_counter(
+        mut self,
+        name: impl Into<String>,
+        value: u64,
+        metadata: &[(&str, &str)],
+    ) -> Self {
+        self.snapshot.counters.push(Counter {
+            name: name.into(),
+            metric_type: MetricType::DeltaCounter,
+            value,
+            metadata: to_metadata(metadata),
+        });
+        self
+    }
+
+    /// Add a gauge.
+    pub fn gauge(mut self, name: impl Into<String>, value: i64, metadata: &[(&str, &str)]) -> Self {
+        self.snapshot.gauges.push(Gauge {
+            name: name.into(),
+            metric_type: MetricType::Gauge,
+            value,
+            metadata: to_metadata(metadata),
+        });
+        self
+    }
+
+    /// Add a histogram. The `grouping_power` and `max_value_power` metadata
+    /// keys are set from the histogram configuration, as they are for
+    /// histograms read from a registry.
+    pub fn histogram(
+        mut self,
+        name: impl Into<String>,
+        value: histogram::Histogram,
+        metadata: &[(&str, &str)],
+    ) -> Self {
+        let mut histogram = Histogram::new(name, value);
+        histogram.metadata.extend(to_metadata(metadata));
+        self.snapshot.histograms.push(histogram);
+        self
+    }
+
+    /// Add a histogram holding the values recorded since the previous
+    /// snapshot. The configuration metadata keys are set as they are by
+    /// [`SnapshotBuilder::histogram`].
+    pub fn delta_histogram(
+        mut self,
+        name: impl Into<String>,
+        value: histogram::Histogram,
+        metadata: &[(&str, &str)],
+    ) -> Self {
+        let mut histogram = Histogram::new(name, value);
+        histogram.metric_type = MetricType::DeltaHistogram;
+        histogram.metadata.extend(to_metadata(metadata));
+        self.snapshot.histograms.push(histogram);
+        self
+    }
+
+    /// Add summary statistics for the values recorded over the interval.
+    /// `min` and `max` must be `None` if, and only if, `count` is zero.
+    pub fn stats(
+        mut self,
+        name: impl Into<String>,
+        min: Option<u64>,
+        max: Option<u64>,
+        sum: u64,
+        count: u64,
+        metadata: &[(&str, &str)],
+    ) -> Self {
+        self.snapshot.stats.push(Stats {
+            name: name.into(),
+            metric_type: MetricType::Summary,
+            min,
+            max,
+            sum,
+            count,
+            metadata: to_metadata(metadata),
+        });
+        self
+    }
+
+    /// Add a sketch of a distribution.
+    pub fn sketch(
+        mut self,
+        name: impl Into<String>,
+        value: crate::DDSketch,
+        metadata: &[(&str, &str)],
+    ) -> Self {
+        self.snapshot.sketches.push(Sketch {
+            name: name.into(),
+            metric_type: MetricType::Sketch,
+            value,
+            metadata: to_metadata(metadata),
+        });
+        self
+    }
+
+    /// Add an estimate of the distinct values seen, from its sketch.
+    pub fn cardinality(
+        mut self,
+        name: impl Into<String>,
+        value: crate::HyperLogLog,
+        metadata: &[(&str, &str)],
+    ) -> Self {
+        self.snapshot.cardinalities.push(Cardinality {
+            name: name.into(),
+            metric_type: MetricType::Cardinality,
+            estimate: value.estimate(),
+            value,
+            metadata: to_metadata(metadata),
+        });
+        self
+    }
+
+    /// Add the keys recorded most often, out of up to `k` keys tracked.
+    pub fn top_k(
+        mut self,
+        name: impl Into<String>,
+        k: u64,
+        entries: Vec<HeavyHitter>,
+        metadata: &[(&str, &str)],
+    ) -> Self {
+        let mut top_k = TopK::new(name, k, entries);
+        top_k.metadata = to_metadata(metadata);
+        self.snapshot.top_k.push(top_k);
+        self
+    }
+
+    /// Add an event which happened during the interval covered by the
+    /// snapshot. Events are kept in the order they were added.
+    pub fn event(mut self, event: Event) -> Self {
+        self.snapshot.events.push(event);
+        self
+    }
+
+    /// Validate and return the snapshot.
+    pub fn build(mut self) -> Result<Snapshot, SnapshotError> {
+        let mut types: HashMap<&str, MetricType> = HashMap::new();
+        let mut series = HashSet::new();
+
+        let metrics = self
+            .snapshot
+            .counters
+            .iter()
+            .map(|m| (&m.name, m.metric_type, &m.metadata))
+            .chain(
+                self.snapshot
+                    .gauges
+                    .iter()
+                    .map(|m| (&m.name, m.metric_type, &m.metadata)),
+            )
+            .chain(
+                self.snapshot
+                    .histograms
+                    .iter()
+                    .map(|m| (&m.name, m.metric_type, &m.metadata)),
+            )
+            .chain(
+                self.snapshot
+                    .stats
+                    .iter()
+                    .map(|m| (&m.name, m.metric_type, &m.metadata)),
+            )
+            .chain(
+                self.snapshot
+                    .sketches
+                    .iter()
+                    .map(|m| (&m.name, m.metric_type, &m.metadata)),
+            )
+            .chain(
+                self.snapshot
+                    .cardinalities
+                    .iter()
+                    .map(|m| (&m.name, m.metric_type, &m.metadata)),
+            )
+            .chain(
+                self.snapshot
+                    .top_k
+                    .iter()
+                    .map(|m| (&m.name, m.metric_type, &m.metadata)),
+            );
+
+        for (name, metric_type, metadata) in metrics {
+            if name.is_empty() {
+                return Err(SnapshotError::EmptyName);
+            }
+            if *types.entry(name).or_insert(metric_type) != metric_type {
+                return Err(SnapshotError::TypeConflict(name.clone()));
+            }
+            if !series.insert(crate::temporality::series_key(name, metadata)) {
+                return Err(SnapshotError::Duplicate(name.clone()));
+            }
+        }
+
+        if self.snapshot.events.iter().any(|e| e.name.is_empty()) {
+            return Err(SnapshotError::EmptyEventName);
+        }
+
+        for stats in &self.snapshot.stats {
+            let consistent = match (stats.min, stats.max) {
+                (Some(min), Some(max)) => stats.count > 0 && min <= max,
+                (None, None) => stats.count == 0 && stats.sum == 0,
+                _ => false,
+            };
+            if !consistent {
+                return Err(SnapshotError::InvalidStats(stats.name.clone()));
+            }
+        }
+
+        macro_rules! sort {
+            ($field:ident) => {
+                self.snapshot
+                    .$field
+                    .sort_by_cached_key(|m| crate::temporality::series_key(&m.name, &m.metadata));
+            };
+        }
+
+        sort!(counters);
+        sort!(gauges);
+        sort!(histograms);
+        sort!(stats);
+        sort!(sketches);
+        sort!(cardinalities);
+        sort!(top_k);
+
+        Ok(self.snapshot)
+    }
 }
 
 #[cfg(feature = "parquet")]
@@ -120,7 +1569,7 @@ impl From<Snapshot> for HashedSnapshot {
         let ts: u64 = snapshot
             .systemtime
             .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("System Clock is earlier than 1970; needs reset")
+            .unwrap_or_default()
             .as_nanos() as u64;
 
         let counters: HashMap<String, Counter> =
@@ -129,12 +1578,378 @@ impl From<Snapshot> for HashedSnapshot {
             HashMap::from_iter(snapshot.gauges.into_iter().map(|v| (v.name.clone(), v)));
         let histograms: HashMap<String, Histogram> =
             HashMap::from_iter(snapshot.histograms.into_iter().map(|v| (v.name.clone(), v)));
+        let stats: HashMap<String, Stats> =
+            HashMap::from_iter(snapshot.stats.into_iter().map(|v| (v.name.clone(), v)));
 
         Self {
             ts,
             counters,
             gauges,
             histograms,
+            stats,
+            events: snapshot.events,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_snapshot() -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        for name in ["a", "b", "c"] {
+            snapshot.counters.push(Counter {
+                name: name.to_string(),
+                metric_type: MetricType::Counter,
+                value: 1,
+                metadata: HashMap::new(),
+            });
+            snapshot.histograms.push(Histogram {
+                name: format!("{name}_histogram"),
+                metric_type: MetricType::Histogram,
+                value: histogram::Histogram::new(4, 10).unwrap(),
+                metadata: HashMap::new(),
+            });
+        }
+        snapshot
+    }
+
+    #[test]
+    fn project() {
+        let snapshot = build_snapshot();
+
+        let projected = snapshot.project(&["a", "c_histogram", "missing"]);
+        assert_eq!(projected.systemtime, snapshot.systemtime);
+        assert_eq!(projected.counters.len(), 1);
+        assert_eq!(projected.counters[0].name, "a");
+        assert_eq!(projected.histograms.len(), 1);
+        assert_eq!(projected.histograms[0].name, "c_histogram");
+    }
+
+    #[test]
+    fn lookup() {
+        let mut snapshot = build_snapshot();
+        snapshot.counters[1]
+            .metadata
+            .insert("op".to_string(), "get".to_string());
+        snapshot.counters[1]
+            .metadata
+            .insert("description".to_string(), "requests".to_string());
+        snapshot.gauges.push(Gauge::new("depth", -3));
+
+        assert_eq!(snapshot.counters[1].canonical_name(), "b{op=get}");
+        assert_eq!(snapshot.counter("a"), Some(1));
+        assert_eq!(snapshot.counter("b{op=get}"), Some(1));
+        assert_eq!(snapshot.counter("b"), None);
+        assert_eq!(snapshot.gauge("depth"), Some(-3));
+        assert_eq!(snapshot.gauge("a"), None);
+        assert!(snapshot.histogram("c_histogram").is_some());
+
+        // changes after the index was built are still found
+        snapshot.counters[0].value = 5;
+        snapshot.counters.swap(0, 2);
+        assert_eq!(snapshot.counter("a"), Some(5));
+        snapshot.counters.push(Counter::new("d", 7));
+        assert_eq!(snapshot.counter("d"), Some(7));
+    }
+
+    #[test]
+    fn lookup_after_rename() {
+        let mut snapshot = Snapshot::new();
+        snapshot.counters.push(Counter::new("a", 1));
+        assert_eq!(snapshot.counter("a"), Some(1));
+
+        // the section has the same length, but the name is not indexed
+        snapshot.counters[0].name = "b".into();
+        assert_eq!(snapshot.counter("b"), Some(1));
+        assert_eq!(snapshot.counter("a"), None);
+    }
+
+    #[test]
+    fn accessors_borrow() {
+        let snapshot = build_snapshot();
+        assert_eq!(snapshot.counters().len(), 3);
+        assert_eq!(snapshot.counters().len(), 3);
+        assert_eq!(snapshot.histograms().len(), 3);
+        assert_eq!(snapshot.histograms().len(), 3);
+        assert!(snapshot.gauges().is_empty());
+    }
+
+    #[test]
+    fn equality() {
+        let snapshot = build_snapshot();
+        let mut other = snapshot.clone();
+        assert!(snapshot == other);
+        assert_eq!(snapshot.content_hash(), other.content_hash());
+
+        // the order of the metrics does not matter
+        other.counters.reverse();
+        assert!(snapshot == other);
+        assert_eq!(snapshot.content_hash(), other.content_hash());
+
+        // entries which repeat are matched once each
+        let mut a = snapshot.clone();
+        let mut b = snapshot.clone();
+        a.counters.push(Counter::new("a", 1));
+        b.counters.push(Counter::new("b", 1));
+        b.counters.reverse();
+        assert!(a != b);
+        b.counters[0] = Counter::new("a", 1);
+        assert!(a == b);
+
+        other.histograms[0].value.increment(1).unwrap();
+        assert!(snapshot != other);
+        assert_ne!(snapshot.content_hash(), other.content_hash());
+
+        // the same values in histograms with different configurations
+        let mut a = Histogram::new("h", histogram::Histogram::new(2, 10).unwrap());
+        let mut b = Histogram::new("h", histogram::Histogram::new(3, 10).unwrap());
+        a.value.increment(5).unwrap();
+        b.value.increment(5).unwrap();
+        assert!(a != b);
+
+        let mut counter = Counter::new("a", 1);
+        assert_eq!(counter.content_hash(), snapshot.counters[0].content_hash());
+        counter.metadata.insert("op".to_string(), "get".to_string());
+        assert!(counter != snapshot.counters[0]);
+    }
+
+    #[test]
+    fn split_by() {
+        let mut snapshot = build_snapshot();
+        snapshot
+            .metadata
+            .insert("source".to_string(), "proxy".to_string());
+        for (counter, tenant) in snapshot.counters.iter_mut().zip(["x", "y"]) {
+            counter
+                .metadata
+                .insert("tenant".to_string(), tenant.to_string());
         }
+        snapshot.histograms[0]
+            .metadata
+            .insert("tenant".to_string(), "x".to_string());
+        let systemtime = snapshot.systemtime;
+
+        let mut children = snapshot.split_by("tenant");
+        assert_eq!(children.len(), 3);
+
+        let x = children.remove(&Some("x".to_string())).unwrap();
+        assert_eq!(x.systemtime, systemtime);
+        assert_eq!(x.get_metadata("tenant"), Some("x"));
+        assert_eq!(x.get_metadata("source"), Some("proxy"));
+        assert_eq!(x.counters.len(), 1);
+        assert_eq!(x.counters[0].name, "a");
+        assert!(x.counters[0].metadata.is_empty());
+        assert_eq!(x.histograms.len(), 1);
+        assert_eq!(x.histograms[0].name, "a_histogram");
+
+        let y = children.remove(&Some("y".to_string())).unwrap();
+        assert_eq!(y.counters.len(), 1);
+        assert_eq!(y.counters[0].name, "b");
+        assert!(y.histograms.is_empty());
+
+        let rest = children.remove(&None).unwrap();
+        assert_eq!(rest.get_metadata("tenant"), None);
+        assert_eq!(rest.counters.len(), 1);
+        assert_eq!(rest.counters[0].name, "c");
+        assert_eq!(rest.histograms.len(), 2);
+    }
+
+    #[test]
+    fn drop_histograms() {
+        let snapshot = build_snapshot().drop_histograms();
+        assert_eq!(snapshot.counters.len(), 3);
+        assert!(snapshot.histograms.is_empty());
+    }
+
+    #[test]
+    fn builder() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+        let snapshot = Snapshot::builder()
+            .systemtime(time)
+            .duration(Duration::from_secs(1))
+            .metadata("source", "test")
+            .counter("requests", 2, &[("method", "POST")])
+            .counter("requests", 5, &[("method", "GET")])
+            .gauge("connections", -1, &[])
+            .histogram("latency", histogram::Histogram::new(4, 10).unwrap(), &[])
+            .stats("size", Some(1), Some(9), 10, 2, &[])
+            .build()
+            .unwrap();
+
+        assert_eq!(snapshot.systemtime(), time);
+        assert_eq!(snapshot.get_metadata(DURATION), Some("1000000000"));
+        assert_eq!(snapshot.get_metadata("source"), Some("test"));
+
+        // sorted by name and metadata
+        let methods: Vec<&str> = snapshot
+            .counters()
+            .iter()
+            .map(|c| c.metadata["method"].as_str())
+            .collect();
+        assert_eq!(methods, ["GET", "POST"]);
+
+        assert_eq!(snapshot.gauges()[0].value, -1);
+        assert_eq!(snapshot.histograms()[0].metadata["grouping_power"], "4");
+        assert_eq!(snapshot.stats()[0].metric_type, MetricType::Summary);
+    }
+
+    #[test]
+    fn events() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+        let snapshot = Snapshot::builder()
+            .counter("requests", 1, &[("tenant", "a")])
+            .event(
+                Event::new("deploy")
+                    .systemtime(time)
+                    .metadata("version", "1.2.3"),
+            )
+            .event(Event::new("gc"))
+            .build()
+            .unwrap();
+
+        let names: Vec<&str> = snapshot.events().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["deploy", "gc"]);
+        assert_eq!(snapshot.events()[0].systemtime, time);
+        assert_eq!(snapshot.events()[0].metadata["version"], "1.2.3");
+
+        assert_eq!(snapshot.project(&[]).events().len(), 2);
+        let children = snapshot.split_by("tenant");
+        assert_eq!(children[&Some("a".to_string())].events().len(), 2);
+
+        assert_eq!(
+            Snapshot::builder().event(Event::new("")).build().err(),
+            Some(SnapshotError::EmptyEventName)
+        );
+    }
+
+    #[test]
+    fn builder_validation() {
+        let err = |builder: SnapshotBuilder| builder.build().err();
+
+        assert_eq!(
+            err(Snapshot::builder().counter("", 1, &[])),
+            Some(SnapshotError::EmptyName)
+        );
+        assert_eq!(
+            err(Snapshot::builder()
+                .counter("a", 1, &[("k", "v")])
+                .counter("a", 2, &[("k", "v")])),
+            Some(SnapshotError::Duplicate("a".to_string()))
+        );
+        assert_eq!(
+            err(Snapshot::builder().counter("a", 1, &[]).gauge("a", 1, &[])),
+            Some(SnapshotError::TypeConflict("a".to_string()))
+        );
+        assert_eq!(
+            err(Snapshot::builder()
+                .counter("a", 1, &[])
+                .delta_counter("a", 1, &[("k", "v")])),
+            Some(SnapshotError::TypeConflict("a".to_string()))
+        );
+        assert_eq!(
+            err(Snapshot::builder().stats("a", Some(5), Some(1), 6, 2, &[])),
+            Some(SnapshotError::InvalidStats("a".to_string()))
+        );
+        assert_eq!(
+            err(Snapshot::builder().stats("a", None, None, 6, 0, &[])),
+            Some(SnapshotError::InvalidStats("a".to_string()))
+        );
+        assert_eq!(
+            err(Snapshot::builder().stats("a", None, None, 0, 0, &[])),
+            None
+        );
+    }
+
+    #[cfg(all(feature = "json", feature = "serde"))]
+    #[test]
+    fn json_rfc3339() {
+        let snapshot = Snapshot::builder()
+            .systemtime(SystemTime::UNIX_EPOCH + Duration::from_millis(1_500))
+            .counter("requests", 1, &[])
+            .event(Event::new("deploy").systemtime(SystemTime::UNIX_EPOCH))
+            .build()
+            .unwrap();
+
+        let options = JsonOptions::new().rfc3339(crate::Rfc3339::new().precision(3));
+        let json = snapshot.to_json_with(&options).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["systemtime"], "1970-01-01T00:00:01.500Z");
+        assert_eq!(value["events"][0]["systemtime"], "1970-01-01T00:00:00.000Z");
+
+        let parsed: Snapshot = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed.systemtime, snapshot.systemtime);
+        assert_eq!(parsed.counters[0].value, 1);
+        assert_eq!(parsed.events, snapshot.events);
+
+        // the default representation is still accepted
+        let json = Snapshot::to_json(&snapshot).unwrap();
+        let parsed: Snapshot = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed.systemtime, snapshot.systemtime);
+    }
+
+    #[cfg(all(feature = "json", feature = "serde"))]
+    #[test]
+    fn json_camel_case() {
+        let snapshot = Snapshot::builder()
+            .systemtime(SystemTime::UNIX_EPOCH)
+            .counter("requests", 1, &[("service_name", "api"), ("zone", "a")])
+            .build()
+            .unwrap();
+
+        let options = JsonOptions::new().camel_case(true).sort_keys(true);
+        let json = String::from_utf8(snapshot.to_json_with(&options).unwrap()).unwrap();
+        assert!(json.contains(r#""topK":[]"#));
+        assert!(json.contains(r#""secsSinceEpoch":0"#));
+        assert!(!json.contains("top_k"));
+        // metadata keys are kept, and sorted
+        assert!(json.contains(r#""metadata":{"service_name":"api","zone":"a"}"#));
+        assert_eq!(
+            json,
+            String::from_utf8(snapshot.to_json_with(&options).unwrap()).unwrap()
+        );
+
+        // snake_case is still the default
+        let json = String::from_utf8(Snapshot::to_json(&snapshot).unwrap()).unwrap();
+        assert!(json.contains(r#""top_k":[]"#));
+    }
+
+    #[cfg(all(feature = "json", feature = "serde"))]
+    #[test]
+    fn json_older_snapshot() {
+        let json = r#"{
+            "systemtime": {"secs_since_epoch": 1, "nanos_since_epoch": 0},
+            "counters": [{"name": "requests", "value": 1, "metadata": {}}],
+            "gauges": [],
+            "histograms": []
+        }"#;
+        let snapshot: Snapshot = serde_json::from_str(json).unwrap();
+        assert_eq!(snapshot.counter("requests"), Some(1));
+        assert!(snapshot.metadata.is_empty());
+        assert!(snapshot.stats.is_empty() && snapshot.events.is_empty());
+    }
+
+    #[test]
+    fn merge_top_k() {
+        let hits = |entries: &[(&str, u64, u64)]| {
+            entries
+                .iter()
+                .map(|(key, count, error)| HeavyHitter::new(*key, *count, *error))
+                .collect::<Vec<_>>()
+        };
+
+        let mut a = TopK::new("keys", 2, hits(&[("x", 10, 0), ("y", 4, 0)]));
+        let b = TopK::new("keys", 2, hits(&[("z", 6, 1), ("x", 3, 0)]));
+        a.merge(&b);
+
+        // "y" might have been recorded up to 3 times by `b`, and "z" up to 4
+        // times by `a`
+        assert_eq!(a.entries, hits(&[("x", 13, 0), ("z", 10, 5)]));
+
+        // a `TopK` which isn't full tracked every key it was given
+        let mut a = TopK::new("keys", 3, hits(&[("x", 1, 0)]));
+        a.merge(&TopK::new("keys", 3, hits(&[("y", 2, 0)])));
+        assert_eq!(a.entries, hits(&[("y", 2, 0), ("x", 1, 0)]));
     }
 }
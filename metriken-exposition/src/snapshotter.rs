@@ -1,63 +1,600 @@
+use std::any::Any;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 
-use metriken::{AtomicHistogram, MetricEntry, RwLockHistogram, Value};
+use metriken::{
+    AtomicHistogram, DynBoxedMetric, IntervalCounter, MetricBuilder, MetricEntry, PeakGauge,
+    RwLockHistogram, SampledCounter, SampledHistogram, ThreadLocalHistogram, Uninitialized, Unit,
+    Value,
+};
 
-use crate::snapshot::{Counter, Gauge, Histogram};
+use crate::budget::Budget;
+use crate::downsample::AGGREGATION;
+use crate::history::SnapshotHistory;
+use crate::snapshot::{
+    Cardinality, Counter, Event, Gauge, HeavyHitter, Histogram, MetricType, Stats, TopK, RESOURCE,
+    SEQUENCE,
+};
+use crate::temporality::{series_key, SeriesKey};
 use crate::Snapshot;
 
 /// Produces a snapshot of metric readings.
-pub struct Snapshotter {
-    filter: fn(&MetricEntry) -> bool,
+///
+/// By default the readings come from the metriken [`Registry`], but any
+/// [`SnapshotSource`] can be used with [`SnapshotterBuilder::with_source`].
+pub struct Snapshotter<S = Registry> {
+    source: S,
     metadata: HashMap<String, String>,
+    changed_only: Option<Mutex<Previous>>,
+    /// The time of the latest snapshot, in nanoseconds since the unix epoch.
+    latest: AtomicU64,
+    clock_regressions: AtomicU64,
+    sequence: AtomicU64,
+    /// Events recorded since the latest snapshot.
+    events: Mutex<Vec<Event>>,
+    self_metrics: Option<SelfMetrics>,
+    budget: Option<Budget>,
+    history: Option<std::sync::Arc<SnapshotHistory>>,
 }
 
-/// Used to build a new `Snapshotter`.
+/// Metrics about the operation of a snapshotter, registered with
+/// [`SnapshotterBuilder::self_metrics`].
+struct SelfMetrics {
+    start: Instant,
+    uptime: DynBoxedMetric<metriken::Gauge>,
+    snapshots: DynBoxedMetric<metriken::Counter>,
+    duration: DynBoxedMetric<AtomicHistogram>,
+}
+
+impl SelfMetrics {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            uptime: MetricBuilder::new("snapshotter_uptime_seconds")
+                .description("the time since the snapshotter was built")
+                .unit(Unit::Seconds)
+                .build(metriken::Gauge::new()),
+            snapshots: MetricBuilder::new("snapshots_total")
+                .description("the number of snapshots taken")
+                .build(metriken::Counter::new()),
+            duration: MetricBuilder::new("snapshot_duration_ns")
+                .description("the time taken to collect each snapshot")
+                .unit(Unit::Nanoseconds)
+                .build(AtomicHistogram::new(7, 64)),
+        }
+    }
+}
+
+/// The snapshot metadata key recording how far the system clock went
+/// backwards before a snapshot was taken, in nanoseconds. The time of such a
+/// snapshot is clamped to the time of the previous snapshot.
+pub const CLOCK_REGRESSION: &str = "clock_regression_ns";
+
+/// A source of metric readings for a [`Snapshotter`].
+///
+/// The metriken [`Registry`] is the default source. Implementing this trait
+/// allows metrics kept elsewhere, such as by another metrics library, to be
+/// exposed through the same snapshots and exporters.
+///
+/// ```
+/// # use metriken_exposition::{Counter, Snapshot, SnapshotSource, SnapshotterBuilder};
+/// # use std::sync::atomic::{AtomicU64, Ordering};
+/// struct Requests(AtomicU64);
+///
+/// impl SnapshotSource for Requests {
+///     fn collect(&self, snapshot: &mut Snapshot) {
+///         let requests = self.0.load(Ordering::Relaxed);
+///         snapshot.counters.push(Counter::new("requests", requests));
+///     }
+/// }
+///
+/// let snapshotter = SnapshotterBuilder::with_source(Requests(AtomicU64::new(3))).build();
+/// assert_eq!(snapshotter.snapshot().counters()[0].value, 3);
+/// ```
+pub trait SnapshotSource {
+    /// Add the current reading of each metric to the snapshot. The snapshot
+    /// already has its time and metadata set when this is called.
+    fn collect(&self, snapshot: &mut Snapshot);
+}
+
+impl<T: SnapshotSource + ?Sized> SnapshotSource for &T {
+    fn collect(&self, snapshot: &mut Snapshot) {
+        (**self).collect(snapshot)
+    }
+}
+
+impl<T: SnapshotSource + ?Sized> SnapshotSource for Box<T> {
+    fn collect(&self, snapshot: &mut Snapshot) {
+        (**self).collect(snapshot)
+    }
+}
+
+impl<T: SnapshotSource + ?Sized> SnapshotSource for std::sync::Arc<T> {
+    fn collect(&self, snapshot: &mut Snapshot) {
+        (**self).collect(snapshot)
+    }
+}
+
+/// Updates metriken metrics from outside the process, such as from `/proc`,
+/// just before each snapshot of the registry is taken.
+///
+/// Collectors are added with [`SnapshotterBuilder::collector`]. They usually
+/// keep a set of dynamic metrics, registering metrics for new devices and
+/// dropping those for devices which have gone away.
+pub trait Collector: Send + Sync {
+    /// Update the metrics kept by this collector.
+    fn refresh(&self);
+}
+
+/// The metriken registry as a [`SnapshotSource`]. This is the source used by
+/// a [`Snapshotter`] unless another is provided, and is configured through
+/// the [`SnapshotterBuilder`].
+pub struct Registry {
+    filter: fn(&MetricEntry) -> bool,
+    uninitialized: UninitializedPolicy,
+    expire: Option<fn(&MetricEntry)>,
+    transforms: Vec<Transform>,
+    collectors: Vec<Box<dyn Collector>>,
+}
+
+/// A transform along with the function which selects the metrics it applies
+/// to.
+type Transform = (fn(&MetricEntry) -> bool, fn(MetricMut<'_>));
+
+/// A mutable reference to the snapshot entry for a metric, passed to the
+/// transforms registered with [`SnapshotterBuilder::transform`].
+#[non_exhaustive]
+pub enum MetricMut<'a> {
+    Counter(&'a mut Counter),
+    Gauge(&'a mut Gauge),
+    Histogram(&'a mut Histogram),
+    Stats(&'a mut Stats),
+    Cardinality(&'a mut Cardinality),
+    TopK(&'a mut TopK),
+}
+
+impl MetricMut<'_> {
+    fn reborrow(&mut self) -> MetricMut<'_> {
+        match self {
+            Self::Counter(m) => MetricMut::Counter(m),
+            Self::Gauge(m) => MetricMut::Gauge(m),
+            Self::Histogram(m) => MetricMut::Histogram(m),
+            Self::Stats(m) => MetricMut::Stats(m),
+            Self::Cardinality(m) => MetricMut::Cardinality(m),
+            Self::TopK(m) => MetricMut::TopK(m),
+        }
+    }
+}
+
+/// The readings from the previous snapshot, used to find which metrics have
+/// changed.
 #[derive(Default)]
-pub struct SnapshotterBuilder {
-    snapshotter: Snapshotter,
+struct Previous {
+    counters: HashMap<SeriesKey, u64>,
+    gauges: HashMap<SeriesKey, i64>,
+    histograms: HashMap<SeriesKey, histogram::Histogram>,
+    stats: HashMap<SeriesKey, (Option<u64>, Option<u64>, u64, u64)>,
+    cardinalities: HashMap<SeriesKey, crate::HyperLogLog>,
+    top_k: HashMap<SeriesKey, Vec<HeavyHitter>>,
+}
+
+impl Previous {
+    /// Remove every metric which has the same reading as in the previous
+    /// snapshot, recording the new readings.
+    fn retain_changed(&mut self, snapshot: &mut Snapshot) {
+        snapshot.counters.retain(|c| {
+            let previous = self
+                .counters
+                .insert(series_key(&c.name, &c.metadata), c.value);
+
+            // delta counters only report a change when they are non-zero
+            match c.metric_type {
+                MetricType::DeltaCounter => c.value != 0,
+                _ => previous != Some(c.value),
+            }
+        });
+
+        snapshot.gauges.retain(|g| {
+            self.gauges
+                .insert(series_key(&g.name, &g.metadata), g.value)
+                != Some(g.value)
+        });
+
+        snapshot.histograms.retain(|h| {
+            let previous = self
+                .histograms
+                .insert(series_key(&h.name, &h.metadata), h.value.clone());
+
+            // delta histograms only report a change when they hold values
+            match h.metric_type {
+                MetricType::DeltaHistogram => h.value.as_slice().iter().any(|c| *c != 0),
+                _ => previous.as_ref() != Some(&h.value),
+            }
+        });
+
+        snapshot.stats.retain(|s| {
+            let value = (s.min, s.max, s.sum, s.count);
+            self.stats.insert(series_key(&s.name, &s.metadata), value) != Some(value)
+        });
+
+        snapshot.cardinalities.retain(|c| {
+            self.cardinalities
+                .insert(series_key(&c.name, &c.metadata), c.value.clone())
+                .as_ref()
+                != Some(&c.value)
+        });
+
+        snapshot.top_k.retain(|t| {
+            self.top_k
+                .insert(series_key(&t.name, &t.metadata), t.entries.clone())
+                .as_ref()
+                != Some(&t.entries)
+        });
+    }
+}
+
+/// Controls how metrics which have not been initialized, such as a
+/// [`LazyCounter`] that has never been written to, appear in snapshots.
+///
+/// [`LazyCounter`]: metriken::LazyCounter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UninitializedPolicy {
+    /// Leave uninitialized metrics out of the snapshot.
+    #[default]
+    Omit,
+    /// Include uninitialized counters and gauges with a value of zero and
+    /// the `uninitialized` metadata key set to `true`.
+    Mark,
+}
+
+/// Used to build a new `Snapshotter`.
+pub struct SnapshotterBuilder<S = Registry> {
+    snapshotter: Snapshotter<S>,
+}
+
+impl Default for SnapshotterBuilder {
+    fn default() -> Self {
+        Self::with_source(Registry::default())
+    }
 }
 
 impl SnapshotterBuilder {
-    /// Construct a new builder. By default, all metric types are enabled and no
-    /// filtering is applied.
+    /// Construct a new builder for a snapshotter of the metriken registry. By
+    /// default, all metric types are enabled and no filtering is applied.
     pub fn new() -> Self {
         Self::default()
     }
 
-    /// Consume the builder and return a `Snapshotter`.
-    pub fn build(self) -> Snapshotter {
-        self.snapshotter
-    }
-
     /// Allow a user-supplied filtering function to be applied based on the
     /// metric entry. The function must return true for any metric that should
     /// be included in the snapshot.
     pub fn filter(mut self, filter: fn(&MetricEntry) -> bool) -> Self {
-        self.snapshotter.filter = filter;
+        self.snapshotter.source.filter = filter;
         self
     }
 
+    /// Set how metrics which have not been initialized are handled. By
+    /// default they are omitted.
+    pub fn uninitialized(mut self, policy: UninitializedPolicy) -> Self {
+        self.snapshotter.source.uninitialized = policy;
+        self
+    }
+
+    /// Expire dynamic metrics before taking each snapshot, so that metrics
+    /// created with a [`ttl`] are dropped once they stop being updated.
+    /// `on_evict` is called with each metric that is removed.
+    ///
+    /// Every snapshot counts as an interval, so only one snapshotter in the
+    /// process should have this enabled.
+    ///
+    /// [`ttl`]: metriken::MetricBuilder::ttl
+    pub fn expire(mut self, on_evict: fn(&MetricEntry)) -> Self {
+        self.snapshotter.source.expire = Some(on_evict);
+        self
+    }
+
+    /// Run a transform on the snapshot entry of every metric for which
+    /// `matches` returns true. Transforms run as the snapshot is taken, before
+    /// the entry is visible to any exporter, and in the order they were
+    /// added.
+    ///
+    /// ```
+    /// # use metriken_exposition::{MetricMut, SnapshotterBuilder};
+    /// // convert a counter of 10ns ticks into nanoseconds
+    /// let snapshotter = SnapshotterBuilder::new()
+    ///     .transform(
+    ///         |entry| entry.name() == "cpu/ticks",
+    ///         |metric| {
+    ///             if let MetricMut::Counter(counter) = metric {
+    ///                 counter.value *= 10;
+    ///             }
+    ///         },
+    ///     )
+    ///     .build();
+    /// ```
+    pub fn transform(
+        mut self,
+        matches: fn(&MetricEntry) -> bool,
+        transform: fn(MetricMut<'_>),
+    ) -> Self {
+        self.snapshotter
+            .source
+            .transforms
+            .push((matches, transform));
+        self
+    }
+
+    /// Refresh a [`Collector`] before each snapshot, so that the metrics it
+    /// keeps are current when they are read. Collectors are refreshed in the
+    /// order they were added.
+    pub fn collector(mut self, collector: impl Collector + 'static) -> Self {
+        self.snapshotter.source.collectors.push(Box::new(collector));
+        self
+    }
+}
+
+impl<S: SnapshotSource> SnapshotterBuilder<S> {
+    /// Construct a new builder for a snapshotter which takes its readings
+    /// from the provided source instead of the metriken registry.
+    pub fn with_source(source: S) -> Self {
+        Self {
+            snapshotter: Snapshotter {
+                source,
+                metadata: HashMap::new(),
+                changed_only: None,
+                latest: AtomicU64::new(0),
+                clock_regressions: AtomicU64::new(0),
+                sequence: AtomicU64::new(0),
+                events: Mutex::new(Vec::new()),
+                self_metrics: None,
+                budget: None,
+                history: None,
+            },
+        }
+    }
+
+    /// Consume the builder and return a `Snapshotter`.
+    pub fn build(self) -> Snapshotter<S> {
+        self.snapshotter
+    }
+
     /// Add a key-value pair to the metadata.
     pub fn metadata(mut self, key: String, value: String) -> Self {
         self.snapshotter.metadata.insert(key, value);
         self
     }
+
+    /// Add the environment variables whose names start with the provided
+    /// prefix to the metadata, so that deployment context such as the region
+    /// can be injected without code changes. Keys are the rest of the name
+    /// in lowercase, so with the prefix `METRIKEN_META_` the variable
+    /// `METRIKEN_META_REGION=us-east-1` sets the `region` key.
+    ///
+    /// The environment is read when this is called. Variables whose name or
+    /// value is not valid unicode, or whose name is only the prefix, are
+    /// skipped.
+    pub fn metadata_from_env(mut self, prefix: &str) -> Self {
+        for (name, value) in std::env::vars_os() {
+            let (Some(name), Ok(value)) = (name.to_str(), value.into_string()) else {
+                continue;
+            };
+            match name.strip_prefix(prefix) {
+                Some(key) if !key.is_empty() => {
+                    self.snapshotter.metadata.insert(key.to_lowercase(), value);
+                }
+                _ => {}
+            }
+        }
+        self
+    }
+
+    /// Add a resource attribute, such as `service.name`, `service.version`
+    /// or `host.name`, to every snapshot. Resource attributes describe the
+    /// process which took the snapshot and are stored in the snapshot
+    /// metadata with the [`RESOURCE`] prefix, so exporters can render them
+    /// once per snapshot instead of on every metric.
+    pub fn resource(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.snapshotter
+            .metadata
+            .insert(format!("{RESOURCE}{key}"), value.into());
+        self
+    }
+
+    /// Only include metrics whose readings have changed since the previous
+    /// snapshot taken by this snapshotter. The first snapshot includes every
+    /// metric. Snapshots taken in this mode have the `changed_only` metadata
+    /// key set to `true`.
+    pub fn changed_only(mut self, enabled: bool) -> Self {
+        self.snapshotter.changed_only = enabled.then(Default::default);
+        self
+    }
+
+    /// Register metrics about the operation of the snapshotter, so that
+    /// stalls in collection can be found from the recorded data alone:
+    ///
+    /// - `snapshotter_uptime_seconds`: a gauge of the time since the
+    ///   snapshotter was built
+    /// - `snapshots_total`: a counter of the snapshots taken
+    /// - `snapshot_duration_ns`: a histogram of the time taken to collect
+    ///   each snapshot
+    ///
+    /// The metrics are dynamic metriken metrics, so they are read along with
+    /// the other metrics of the registry. The uptime and count are updated
+    /// before each snapshot is collected and so include it, while the
+    /// duration of a snapshot is only included in the next one. The metrics
+    /// are unregistered when the snapshotter is dropped.
+    pub fn self_metrics(mut self, enabled: bool) -> Self {
+        self.snapshotter.self_metrics = enabled.then(SelfMetrics::new);
+        self
+    }
+
+    /// Keep each snapshot within a [`Budget`] on its serialized size. The
+    /// budget is applied last, after only the changed metrics have been
+    /// kept, if enabled.
+    pub fn budget(mut self, budget: Budget) -> Self {
+        self.snapshotter.budget = Some(budget);
+        self
+    }
+
+    /// Keep a copy of every snapshot taken in a [`SnapshotHistory`], which
+    /// can be shared with the code that dumps it.
+    pub fn history(mut self, history: std::sync::Arc<SnapshotHistory>) -> Self {
+        self.snapshotter.history = Some(history);
+        self
+    }
 }
 
-impl Default for Snapshotter {
+impl Default for Registry {
     fn default() -> Self {
         Self {
             filter: |_| true,
-            metadata: HashMap::new(),
+            uninitialized: UninitializedPolicy::default(),
+            expire: None,
+            transforms: Vec::new(),
+            collectors: Vec::new(),
         }
     }
 }
 
-impl Snapshotter {
-    /// Produce a new snapshot.
+impl Default for Snapshotter {
+    fn default() -> Self {
+        SnapshotterBuilder::default().build()
+    }
+}
+
+impl<S: SnapshotSource> Snapshotter<S> {
+    /// The number of snapshots whose time was clamped because the system
+    /// clock went backwards, or was set before the unix epoch.
+    pub fn clock_regressions(&self) -> u64 {
+        self.clock_regressions.load(Ordering::Relaxed)
+    }
+
+    /// Record an event, such as a deploy or a failover, which is included in
+    /// the next snapshot. Events are kept in the order they were recorded and
+    /// are always included, even when only changed metrics are reported.
+    ///
+    /// ```
+    /// # use metriken_exposition::{Event, Snapshotter};
+    /// let snapshotter = Snapshotter::default();
+    /// snapshotter.annotate(Event::new("deploy").metadata("version", "1.2.3"));
+    ///
+    /// assert_eq!(snapshotter.snapshot().events()[0].name, "deploy");
+    /// assert!(snapshotter.snapshot().events().is_empty());
+    /// ```
+    pub fn annotate(&self, event: Event) {
+        self.events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(event);
+    }
+
+    /// Make sure snapshot times never go backwards, even if the system clock
+    /// does. A snapshot taken after the clock moved backwards is given the
+    /// time of the previous snapshot and marked with [`CLOCK_REGRESSION`].
+    fn guard_clock(&self, snapshot: &mut Snapshot) {
+        let (now, behind) = match snapshot.systemtime.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(since_epoch) => (since_epoch.as_nanos().min(u64::MAX as u128) as u64, 0),
+            Err(e) => (0, e.duration().as_nanos()),
+        };
+
+        let latest = self.latest.fetch_max(now, Ordering::Relaxed);
+        if now >= latest && behind == 0 {
+            return;
+        }
+
+        let latest = latest.max(now);
+        let behind = behind + (latest - now) as u128;
+
+        snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_nanos(latest);
+        snapshot
+            .metadata
+            .insert(CLOCK_REGRESSION.to_string(), behind.to_string());
+        self.clock_regressions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Produce a new snapshot. Each snapshot is numbered with the next
+    /// [`SEQUENCE`] number, so that readers can detect snapshots which were
+    /// dropped or duplicated on their way.
     pub fn snapshot(&self) -> Snapshot {
+        let start = Instant::now();
+
+        if let Some(metrics) = &self.self_metrics {
+            metrics.uptime.set(metrics.start.elapsed().as_secs() as i64);
+            metrics.snapshots.increment();
+        }
+
         let mut snapshot = Snapshot::new();
         snapshot.metadata = self.metadata.clone();
+        snapshot.metadata.insert(
+            SEQUENCE.to_string(),
+            self.sequence.fetch_add(1, Ordering::Relaxed).to_string(),
+        );
+        self.guard_clock(&mut snapshot);
+
+        self.source.collect(&mut snapshot);
+
+        snapshot.events =
+            std::mem::take(&mut *self.events.lock().unwrap_or_else(|e| e.into_inner()));
+
+        if let Some(previous) = &self.changed_only {
+            previous
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .retain_changed(&mut snapshot);
+            snapshot
+                .metadata
+                .insert("changed_only".to_string(), "true".to_string());
+        }
+
+        if let Some(budget) = &self.budget {
+            budget.apply(&mut snapshot);
+        }
+
+        if let Some(history) = &self.history {
+            history.push(snapshot.clone());
+        }
+
+        if let Some(metrics) = &self.self_metrics {
+            let elapsed = start.elapsed().as_nanos().min(u64::MAX as u128) as u64;
+            let _ = metrics.duration.increment(elapsed);
+        }
+
+        #[cfg(feature = "log")]
+        log::debug!(
+            "snapshot taken in {:?}: {} counters, {} gauges, {} histograms, {} stats",
+            start.elapsed(),
+            snapshot.counters.len(),
+            snapshot.gauges.len(),
+            snapshot.histograms.len(),
+            snapshot.stats.len(),
+        );
+
+        snapshot
+    }
+}
+
+impl Registry {
+    /// Run the transforms which match the metric on its snapshot entry.
+    fn transform(&self, metric: &MetricEntry, mut entry: MetricMut<'_>) {
+        for (matches, transform) in &self.transforms {
+            if matches(metric) {
+                transform(entry.reborrow());
+            }
+        }
+    }
+}
+
+impl SnapshotSource for Registry {
+    fn collect(&self, snapshot: &mut Snapshot) {
+        for collector in &self.collectors {
+            collector.refresh();
+        }
+
+        if let Some(on_evict) = self.expire {
+            metriken::dynmetrics::expire(on_evict);
+        }
 
         // iterate through the metrics and build-up the snapshot
         for metric in &metriken::metrics() {
@@ -67,8 +604,24 @@ impl Snapshotter {
 
             match metric.value() {
                 Some(Value::Counter(value)) => {
+                    // Interval counters are reset as they are read so the
+                    // snapshot holds the delta since the previous snapshot.
+                    let interval = metric
+                        .as_any()
+                        .and_then(|any| any.downcast_ref::<IntervalCounter>());
+                    let value = match interval {
+                        Some(interval) => interval.take(),
+                        None => value,
+                    };
+
+                    let metric_type = match interval {
+                        Some(_) => MetricType::DeltaCounter,
+                        None => MetricType::Counter,
+                    };
+
                     let mut counter = Counter {
                         name: metric.formatted(metriken::Format::Simple),
+                        metric_type,
                         value,
                         metadata: HashMap::from_iter(
                             metric
@@ -84,11 +637,45 @@ impl Snapshotter {
                             .insert("description".to_string(), description);
                     }
 
+                    if interval.is_some() {
+                        counter
+                            .metadata
+                            .insert("temporality".to_string(), "delta".to_string());
+                    }
+
+                    // Sampled counters already report the scaled estimate.
+                    if let Some(sampled) = metric
+                        .as_any()
+                        .and_then(|any| any.downcast_ref::<SampledCounter>())
+                    {
+                        counter.metadata.insert(
+                            "sampling_rate".to_string(),
+                            sampled.sampling().rate().to_string(),
+                        );
+                    }
+
+                    #[cfg(feature = "contention")]
+                    let contention = contention(metric, &counter.name);
+
+                    self.transform(metric, MetricMut::Counter(&mut counter));
                     snapshot.counters.push(counter);
+                    #[cfg(feature = "contention")]
+                    snapshot.counters.extend(contention);
                 }
                 Some(Value::Gauge(value)) => {
+                    // Peak gauges are reset as they are read so the snapshot
+                    // holds the peak since the previous snapshot.
+                    let peak = metric
+                        .as_any()
+                        .and_then(|any| any.downcast_ref::<PeakGauge>());
+                    let value = match peak {
+                        Some(peak) => peak.take(),
+                        None => value,
+                    };
+
                     let mut gauge = Gauge {
                         name: metric.formatted(metriken::Format::Simple),
+                        metric_type: MetricType::Gauge,
                         value,
                         metadata: HashMap::from_iter(
                             metric
@@ -104,7 +691,114 @@ impl Snapshotter {
                             .insert("description".to_string(), description);
                     }
 
+                    if let Some(peak) = peak {
+                        let aggregation = if peak.is_max() { "max" } else { "min" };
+                        gauge
+                            .metadata
+                            .entry(AGGREGATION.to_string())
+                            .or_insert_with(|| aggregation.to_string());
+                    }
+
+                    #[cfg(feature = "contention")]
+                    let contention = contention(metric, &gauge.name);
+
+                    let fill_ratio = metric.as_any().and_then(|any| fill_ratio(any, &gauge.name));
+
+                    self.transform(metric, MetricMut::Gauge(&mut gauge));
                     snapshot.gauges.push(gauge);
+                    snapshot.gauges.extend(fill_ratio);
+                    #[cfg(feature = "contention")]
+                    snapshot.counters.extend(contention);
+                }
+                Some(Value::Other(other)) if other.is::<metriken::Stats>() => {
+                    let stats = other
+                        .downcast_ref::<metriken::Stats>()
+                        .and_then(|stats| stats.drain());
+
+                    let mut metadata = HashMap::from_iter(
+                        metric
+                            .metadata()
+                            .into_iter()
+                            .map(|(k, v)| (k.to_string(), v.to_string())),
+                    );
+
+                    if let Some(description) = metric.description().map(|v| v.to_string()) {
+                        metadata.insert("description".to_string(), description);
+                    }
+
+                    let mut stats = Stats {
+                        name: metric.formatted(metriken::Format::Simple),
+                        metric_type: MetricType::Summary,
+                        min: stats.map(|s| s.min),
+                        max: stats.map(|s| s.max),
+                        sum: stats.map(|s| s.sum).unwrap_or(0),
+                        count: stats.map(|s| s.count).unwrap_or(0),
+                        metadata,
+                    };
+
+                    self.transform(metric, MetricMut::Stats(&mut stats));
+                    snapshot.stats.push(stats);
+                }
+                Some(Value::Other(other)) if other.is::<metriken::Cardinality>() => {
+                    let Some(value) = other
+                        .downcast_ref::<metriken::Cardinality>()
+                        .and_then(|c| crate::HyperLogLog::from_registers(c.drain()).ok())
+                    else {
+                        continue;
+                    };
+
+                    let mut metadata = HashMap::from_iter(
+                        metric
+                            .metadata()
+                            .into_iter()
+                            .map(|(k, v)| (k.to_string(), v.to_string())),
+                    );
+
+                    if let Some(description) = metric.description().map(|v| v.to_string()) {
+                        metadata.insert("description".to_string(), description);
+                    }
+
+                    let mut cardinality = Cardinality {
+                        name: metric.formatted(metriken::Format::Simple),
+                        metric_type: MetricType::Cardinality,
+                        estimate: value.estimate(),
+                        value,
+                        metadata,
+                    };
+
+                    self.transform(metric, MetricMut::Cardinality(&mut cardinality));
+                    snapshot.cardinalities.push(cardinality);
+                }
+                Some(Value::Other(other)) if other.is::<metriken::TopK>() => {
+                    let Some(top_k) = other.downcast_ref::<metriken::TopK>() else {
+                        continue;
+                    };
+
+                    let mut metadata = HashMap::from_iter(
+                        metric
+                            .metadata()
+                            .into_iter()
+                            .map(|(k, v)| (k.to_string(), v.to_string())),
+                    );
+
+                    if let Some(description) = metric.description().map(|v| v.to_string()) {
+                        metadata.insert("description".to_string(), description);
+                    }
+
+                    let entries = top_k
+                        .drain()
+                        .into_iter()
+                        .map(|h| HeavyHitter::new(h.key, h.count, h.error))
+                        .collect();
+                    let mut top_k = TopK::new(
+                        metric.formatted(metriken::Format::Simple),
+                        top_k.k() as u64,
+                        entries,
+                    );
+                    top_k.metadata = metadata;
+
+                    self.transform(metric, MetricMut::TopK(&mut top_k));
+                    snapshot.top_k.push(top_k);
                 }
                 Some(Value::Other(other)) => {
                     let histogram = if let Some(histogram) = other.downcast_ref::<AtomicHistogram>()
@@ -112,6 +806,10 @@ impl Snapshotter {
                         histogram.load()
                     } else if let Some(histogram) = other.downcast_ref::<RwLockHistogram>() {
                         histogram.load()
+                    } else if let Some(histogram) = other.downcast_ref::<ThreadLocalHistogram>() {
+                        histogram.load()
+                    } else if let Some(histogram) = other.downcast_ref::<SampledHistogram>() {
+                        histogram.load()
                     } else {
                         None
                     };
@@ -138,19 +836,312 @@ impl Snapshotter {
                             metadata.insert("description".to_string(), description);
                         }
 
-                        let histogram = Histogram {
+                        if let Some(sampled) = other.downcast_ref::<SampledHistogram>() {
+                            metadata.insert(
+                                "sampling_rate".to_string(),
+                                sampled.sampling().rate().to_string(),
+                            );
+                        }
+
+                        let mut histogram = Histogram {
                             name: metric.formatted(metriken::Format::Simple),
+                            metric_type: MetricType::Histogram,
                             value: histogram,
                             metadata,
                         };
 
+                        snapshot.counters.extend(overflow(other, &histogram.name));
+
+                        self.transform(metric, MetricMut::Histogram(&mut histogram));
                         snapshot.histograms.push(histogram);
                     }
                 }
+                None if self.uninitialized == UninitializedPolicy::Mark => {
+                    mark_uninitialized(snapshot, metric);
+                }
                 _ => continue,
             }
         }
+    }
+}
+
+/// Add a zero-valued entry for a metric which has not been initialized yet.
+fn mark_uninitialized(snapshot: &mut Snapshot, metric: &MetricEntry) {
+    let Some(uninitialized) = metric.request_value::<Uninitialized>() else {
+        return;
+    };
+
+    let name = metric.formatted(metriken::Format::Simple);
+    let mut metadata = HashMap::from_iter(
+        metric
+            .metadata()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string())),
+    );
+    metadata.insert("uninitialized".to_string(), "true".to_string());
+
+    if let Some(description) = metric.description().map(|v| v.to_string()) {
+        metadata.insert("description".to_string(), description);
+    }
+
+    if uninitialized.is::<metriken::Counter>() {
+        snapshot.counters.push(Counter {
+            name,
+            metric_type: MetricType::Counter,
+            value: 0,
+            metadata,
+        });
+    } else if uninitialized.is::<metriken::Gauge>() {
+        snapshot.gauges.push(Gauge {
+            name,
+            metric_type: MetricType::Gauge,
+            value: 0,
+            metadata,
+        });
+    }
+}
+
+/// Report the number of values which were too large to be recorded in a
+/// histogram as a counter named `<metric>/overflow`. This is only reported
+/// once a value has overflowed.
+fn overflow(histogram: &dyn Any, name: &str) -> Option<Counter> {
+    let value = if let Some(histogram) = histogram.downcast_ref::<AtomicHistogram>() {
+        histogram.overflow()
+    } else if let Some(histogram) = histogram.downcast_ref::<ThreadLocalHistogram>() {
+        histogram.overflow()
+    } else if let Some(histogram) = histogram.downcast_ref::<SampledHistogram>() {
+        histogram.overflow()
+    } else {
+        return None;
+    };
+
+    if value == 0 {
+        return None;
+    }
 
+    Some(Counter {
+        name: format!("{name}/overflow"),
+        metric_type: MetricType::Counter,
+        value,
+        metadata: HashMap::from([("metric".to_string(), name.to_string())]),
+    })
+}
+
+/// Report the fraction of the bits of a seen set which are set, in parts per
+/// million, as a gauge named `<metric>/fill_ratio`.
+fn fill_ratio(set: &dyn Any, name: &str) -> Option<Gauge> {
+    let set = set.downcast_ref::<metriken::SeenSet>()?;
+
+    Some(Gauge {
+        name: format!("{name}/fill_ratio"),
+        metric_type: MetricType::Gauge,
+        value: (set.fill_ratio() * 1_000_000.0).round() as i64,
+        metadata: HashMap::from([("metric".to_string(), name.to_string())]),
+    })
+}
+
+/// Report the number of contended updates to a counter or gauge as a counter
+/// named `<metric>/contention`. This is only reported once an update was
+/// contended.
+#[cfg(feature = "contention")]
+fn contention(metric: &MetricEntry, name: &str) -> Option<Counter> {
+    let any = metric.as_any()?;
+    let value = if let Some(counter) = any.downcast_ref::<metriken::Counter>() {
+        counter.contention()
+    } else if let Some(counter) = any.downcast_ref::<metriken::PaddedCounter>() {
+        counter.contention()
+    } else if let Some(gauge) = any.downcast_ref::<metriken::Gauge>() {
+        gauge.contention()
+    } else {
+        return None;
+    };
+
+    if value == 0 {
+        return None;
+    }
+
+    Some(Counter {
+        name: format!("{name}/contention"),
+        metric_type: MetricType::Counter,
+        value,
+        metadata: HashMap::from([("metric".to_string(), name.to_string())]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guarded(snapshotter: &Snapshotter, time: SystemTime) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        snapshot.systemtime = time;
+        snapshotter.guard_clock(&mut snapshot);
         snapshot
     }
+
+    #[test]
+    fn metadata_from_env() {
+        std::env::set_var("METRIKEN_TEST_META_REGION", "us-east-1");
+        std::env::set_var("METRIKEN_TEST_META_", "ignored");
+
+        let snapshotter = SnapshotterBuilder::new()
+            .filter(|_| false)
+            .metadata_from_env("METRIKEN_TEST_META_")
+            .build();
+
+        let snapshot = snapshotter.snapshot();
+        assert_eq!(snapshot.get_metadata("region"), Some("us-east-1"));
+        assert_eq!(snapshot.get_metadata(""), None);
+    }
+
+    #[test]
+    fn collectors() {
+        struct Refreshes(std::sync::Arc<AtomicU64>);
+
+        impl Collector for Refreshes {
+            fn refresh(&self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let refreshes = std::sync::Arc::new(AtomicU64::new(0));
+        let snapshotter = SnapshotterBuilder::new()
+            .filter(|_| false)
+            .collector(Refreshes(refreshes.clone()))
+            .build();
+
+        snapshotter.snapshot();
+        snapshotter.snapshot();
+        assert_eq!(refreshes.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn clock_regression() {
+        let snapshotter = Snapshotter::default();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+
+        let snapshot = guarded(&snapshotter, start);
+        assert_eq!(snapshot.systemtime, start);
+        assert_eq!(snapshot.get_metadata(CLOCK_REGRESSION), None);
+
+        // the clock goes backwards by 30s
+        let snapshot = guarded(&snapshotter, start - Duration::from_secs(30));
+        assert_eq!(snapshot.systemtime, start);
+        assert_eq!(snapshot.get_metadata(CLOCK_REGRESSION), Some("30000000000"));
+
+        // a clock set before the epoch does not panic
+        let snapshot = guarded(
+            &snapshotter,
+            SystemTime::UNIX_EPOCH - Duration::from_secs(1),
+        );
+        assert_eq!(snapshot.systemtime, start);
+        assert_eq!(
+            snapshot.get_metadata(CLOCK_REGRESSION),
+            Some("101000000000")
+        );
+
+        let snapshot = guarded(&snapshotter, start + Duration::from_secs(1));
+        assert_eq!(snapshot.systemtime, start + Duration::from_secs(1));
+        assert_eq!(snapshot.get_metadata(CLOCK_REGRESSION), None);
+
+        assert_eq!(snapshotter.clock_regressions(), 2);
+    }
+
+    #[test]
+    fn self_metrics() {
+        let snapshotter = SnapshotterBuilder::new()
+            .filter(|entry| entry.name().starts_with("snapshot"))
+            .self_metrics(true)
+            .build();
+
+        snapshotter.snapshot();
+        let snapshot = snapshotter.snapshot();
+
+        let total = snapshot
+            .counters()
+            .iter()
+            .find(|c| c.name == "snapshots_total")
+            .unwrap();
+        assert_eq!(total.value, 2);
+        assert!(snapshot
+            .gauges()
+            .iter()
+            .any(|g| g.name == "snapshotter_uptime_seconds"));
+
+        // only the first snapshot has been timed
+        let duration = snapshot
+            .histograms()
+            .iter()
+            .find(|h| h.name == "snapshot_duration_ns")
+            .unwrap();
+        assert_eq!(
+            duration.value.into_iter().map(|b| b.count()).sum::<u64>(),
+            1
+        );
+
+        // the metrics are unregistered along with the snapshotter
+        drop(snapshotter);
+        let snapshot = SnapshotterBuilder::new()
+            .filter(|entry| entry.name() == "snapshots_total")
+            .build()
+            .snapshot();
+        assert!(snapshot.counters().is_empty());
+    }
+
+    struct Fixed(u64);
+
+    impl SnapshotSource for Fixed {
+        fn collect(&self, snapshot: &mut Snapshot) {
+            snapshot.counters.push(Counter::new("fixed", self.0));
+        }
+    }
+
+    #[test]
+    fn custom_source() {
+        let snapshotter = SnapshotterBuilder::with_source(Fixed(5))
+            .metadata("source".to_string(), "fixed".to_string())
+            .changed_only(true)
+            .build();
+
+        let snapshot = snapshotter.snapshot();
+        assert_eq!(snapshot.counters().len(), 1);
+        assert_eq!(snapshot.counters()[0].value, 5);
+        assert_eq!(snapshot.get_metadata("source"), Some("fixed"));
+        assert_eq!(snapshot.sequence(), Some(0));
+
+        // the reading has not changed
+        let snapshot = snapshotter.snapshot();
+        assert!(snapshot.counters().is_empty());
+        assert_eq!(snapshot.sequence(), Some(1));
+
+        // a budget with no room for counters drops them all
+        let snapshotter = SnapshotterBuilder::with_source(Fixed(5))
+            .budget(
+                Budget::new(0, |snapshot| snapshot.counters().len())
+                    .degrade(crate::Degradation::TopCounters(0)),
+            )
+            .build();
+        let snapshot = snapshotter.snapshot();
+        assert!(snapshot.counters().is_empty());
+        assert_eq!(
+            snapshot.get_metadata(crate::BUDGET_DROPPED),
+            Some("counters=1")
+        );
+
+        // snapshots are kept in the history
+        let history = std::sync::Arc::new(SnapshotHistory::new(2));
+        let snapshotter = SnapshotterBuilder::with_source(Fixed(5))
+            .history(history.clone())
+            .build();
+        for _ in 0..3 {
+            snapshotter.snapshot();
+        }
+        let sequences: Vec<_> = history.snapshots().iter().map(|s| s.sequence()).collect();
+        assert_eq!(sequences, [Some(1), Some(2)]);
+
+        // sources can be shared between snapshotters
+        let source: Box<dyn SnapshotSource> = Box::new(Fixed(7));
+        let snapshotter = SnapshotterBuilder::with_source(&source).build();
+        assert_eq!(snapshotter.snapshot().counters()[0].value, 7);
+    }
 }
@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::rebucket::{common_config, rebucket};
+use crate::snapshot::*;
+use crate::{Downsample, GaugeAggregation, Temporality, TemporalityConverter};
+
+/// Combinators for iterators of snapshots, so that offline processing of a
+/// recording reads as a pipeline.
+///
+/// Each combinator wraps one of the transforms of this crate, and they can be
+/// mixed freely with the combinators of [`Iterator`], such as `filter` to
+/// skip snapshots or `chain` to join recordings.
+///
+/// With the `parquet` feature, the result can be written to a parquet file
+/// with [`write_parquet`](SnapshotIterExt::write_parquet).
+///
+/// # Example
+/// ```
+/// # use std::time::Duration;
+/// # use metriken_exposition::{Snapshot, SnapshotIterExt};
+/// # let recording: Vec<Snapshot> = Vec::new();
+/// let snapshots: Vec<Snapshot> = recording
+///     .into_iter()
+///     .filter_metrics("requests/*")
+///     .aggregate(Duration::from_secs(60))
+///     .group_by(&["op"])
+///     .collect();
+/// ```
+pub trait SnapshotIterExt: Iterator<Item = Snapshot> + Sized {
+    /// Keep only the metrics whose name matches a glob pattern, in which `*`
+    /// matches any sequence of characters, including `/`, and `?` matches any
+    /// single character. The snapshot time, metadata, and events are kept.
+    fn filter_metrics(self, pattern: &str) -> FilterMetrics<Self> {
+        FilterMetrics {
+            snapshots: self,
+            pattern: pattern.chars().collect(),
+        }
+    }
+
+    /// Reduce the snapshots to at most one per interval. See [`Downsample`].
+    fn aggregate(self, interval: Duration) -> Downsample<Self> {
+        Downsample::new(self, interval)
+    }
+
+    /// Combine the metrics of each snapshot which share a name and the same
+    /// values for the provided metadata keys. See [`GroupBy`].
+    fn group_by(self, keys: &[&str]) -> GroupBy<Self> {
+        GroupBy {
+            snapshots: self,
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+        }
+    }
+
+    /// Convert the counters and histograms of the snapshots to the provided
+    /// temporality. See [`TemporalityConverter`].
+    fn temporality(self, temporality: Temporality) -> ConvertTemporality<Self> {
+        ConvertTemporality {
+            snapshots: self,
+            converter: TemporalityConverter::new(temporality),
+        }
+    }
+
+    /// Write the snapshots to a parquet file at the provided path with the
+    /// default options, returning the number of rows written.
+    ///
+    /// The schema of the file is only known once every snapshot has been
+    /// seen, so the snapshots are held in memory until they are written.
+    #[cfg(feature = "parquet")]
+    fn write_parquet(
+        self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<i64, parquet::errors::ParquetError> {
+        self.write_parquet_with_options(path, crate::ParquetOptions::default())
+    }
+
+    /// Write the snapshots to a parquet file at the provided path with the
+    /// provided options, returning the number of rows written.
+    #[cfg(feature = "parquet")]
+    fn write_parquet_with_options(
+        self,
+        path: impl AsRef<std::path::Path>,
+        options: crate::ParquetOptions,
+    ) -> Result<i64, parquet::errors::ParquetError> {
+        let snapshots: Vec<Snapshot> = self.collect();
+        let mut schema = crate::ParquetSchema::new();
+        for snapshot in &snapshots {
+            schema.push(snapshot.clone());
+        }
+
+        let mut writer = schema.finalize(std::fs::File::create(path)?, options)?;
+        for snapshot in snapshots {
+            writer.push(snapshot)?;
+        }
+        Ok(writer.finalize()?.num_rows)
+    }
+}
+
+impl<I: Iterator<Item = Snapshot>> SnapshotIterExt for I {}
+
+/// Keeps only the metrics whose name matches a glob pattern. See
+/// [`SnapshotIterExt::filter_metrics`].
+pub struct FilterMetrics<I> {
+    snapshots: I,
+    pattern: Vec<char>,
+}
+
+impl<I: Iterator<Item = Snapshot>> Iterator for FilterMetrics<I> {
+    type Item = Snapshot;
+
+    fn next(&mut self) -> Option<Snapshot> {
+        let mut snapshot = self.snapshots.next()?;
+        let pattern = &self.pattern;
+        let keep = |name: &String| glob(pattern, &name.chars().collect::<Vec<_>>());
+
+        snapshot.counters.retain(|m| keep(&m.name));
+        snapshot.gauges.retain(|m| keep(&m.name));
+        snapshot.histograms.retain(|m| keep(&m.name));
+        snapshot.stats.retain(|m| keep(&m.name));
+        snapshot.sketches.retain(|m| keep(&m.name));
+        snapshot.cardinalities.retain(|m| keep(&m.name));
+        snapshot.top_k.retain(|m| keep(&m.name));
+        Some(snapshot)
+    }
+}
+
+/// Returns `true` if the name matches the glob pattern.
+fn glob(pattern: &[char], name: &[char]) -> bool {
+    // The position after the last `*`, and the position in the name it was
+    // matched up to, to backtrack to when the rest of the pattern fails.
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    p = sp;
+                    n = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Combines the metrics of each snapshot which share a name and the same
+/// values for a set of metadata keys, such as to total the requests for each
+/// operation across every host. See [`SnapshotIterExt::group_by`].
+///
+/// Counters are summed. Gauges are summed, unless their `aggregation`
+/// metadata is `min`, `max`, or `mean`, in which case that is used instead.
+/// Histograms are summed, after rebucketing them into a common configuration
+/// if their configurations differ. Stats, sketches, cardinalities, and top k
+/// metrics are merged. The combined metric keeps the grouping keys and any
+/// other metadata which is the same for every metric in the group.
+pub struct GroupBy<I> {
+    snapshots: I,
+    keys: Vec<String>,
+}
+
+impl<I: Iterator<Item = Snapshot>> Iterator for GroupBy<I> {
+    type Item = Snapshot;
+
+    fn next(&mut self) -> Option<Snapshot> {
+        let mut snapshot = self.snapshots.next()?;
+        let keys = &self.keys;
+
+        snapshot.counters = group(keys, std::mem::take(&mut snapshot.counters), |a, b| {
+            a.value = a.value.wrapping_add(b.value);
+        });
+
+        let gauges = std::mem::take(&mut snapshot.gauges)
+            .into_iter()
+            .map(|g| (g, 1))
+            .collect();
+        snapshot.gauges = group(keys, gauges, |(a, count), (b, _)| {
+            a.value = match GaugeAggregation::from_metadata(&a.metadata) {
+                GaugeAggregation::Min => a.value.min(b.value),
+                GaugeAggregation::Max => a.value.max(b.value),
+                // the running sum, which is divided by the count at the end
+                _ => a.value.wrapping_add(b.value),
+            };
+            *count += 1;
+        })
+        .into_iter()
+        .map(|(mut gauge, count)| {
+            if GaugeAggregation::from_metadata(&gauge.metadata) == GaugeAggregation::Mean {
+                gauge.value /= count;
+            }
+            gauge
+        })
+        .collect();
+
+        snapshot.histograms = group(keys, std::mem::take(&mut snapshot.histograms), |a, b| {
+            let config = common_config(a.value.config(), b.value.config());
+            let sum = rebucket(&a.value, &config).and_then(|x| {
+                let y = rebucket(&b.value, &config)?;
+                x.wrapping_add(&y)
+            });
+            if let Ok(sum) = sum {
+                a.value = sum;
+            }
+        });
+
+        snapshot.stats = group(keys, std::mem::take(&mut snapshot.stats), |a, b| {
+            a.min = a.min.into_iter().chain(b.min).min();
+            a.max = a.max.into_iter().chain(b.max).max();
+            a.sum = a.sum.wrapping_add(b.sum);
+            a.count = a.count.wrapping_add(b.count);
+        });
+
+        snapshot.sketches = group(keys, std::mem::take(&mut snapshot.sketches), |a, b| {
+            let _ = a.value.merge(&b.value);
+        });
+
+        snapshot.cardinalities =
+            group(keys, std::mem::take(&mut snapshot.cardinalities), |a, b| {
+                let _ = a.merge(&b.value);
+            });
+
+        snapshot.top_k = group(keys, std::mem::take(&mut snapshot.top_k), |a, b| {
+            a.merge(&b);
+        });
+
+        Some(snapshot)
+    }
+}
+
+/// The name and metadata of a metric, so that metrics of any type can be
+/// grouped.
+trait Grouped {
+    fn name(&self) -> &str;
+    fn metadata_mut(&mut self) -> &mut HashMap<String, String>;
+}
+
+macro_rules! impl_grouped {
+    ($($ty:ty),*) => {
+        $(
+            impl Grouped for $ty {
+                fn name(&self) -> &str {
+                    &self.name
+                }
+
+                fn metadata_mut(&mut self) -> &mut HashMap<String, String> {
+                    &mut self.metadata
+                }
+            }
+        )*
+    };
+}
+
+impl_grouped!(Counter, Gauge, Histogram, Stats, Sketch, Cardinality, TopK);
+
+impl<T: Grouped> Grouped for (T, i64) {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn metadata_mut(&mut self) -> &mut HashMap<String, String> {
+        self.0.metadata_mut()
+    }
+}
+
+/// Combine the metrics which share a name and the values of the keys, in the
+/// order each group was first seen.
+fn group<T: Grouped>(keys: &[String], metrics: Vec<T>, combine: impl Fn(&mut T, T)) -> Vec<T> {
+    let mut index: HashMap<(String, Vec<Option<String>>), usize> = HashMap::new();
+    let mut grouped: Vec<T> = Vec::new();
+
+    for mut metric in metrics {
+        let values: Vec<Option<String>> = keys
+            .iter()
+            .map(|k| metric.metadata_mut().get(k).cloned())
+            .collect();
+        let key = (metric.name().to_string(), values);
+
+        match index.get(&key) {
+            Some(i) => {
+                let current = &mut grouped[*i];
+                let metadata = std::mem::take(metric.metadata_mut());
+                current
+                    .metadata_mut()
+                    .retain(|k, v| metadata.get(k) == Some(v));
+                combine(current, metric);
+            }
+            None => {
+                index.insert(key, grouped.len());
+                grouped.push(metric);
+            }
+        }
+    }
+
+    grouped
+}
+
+/// Converts the temporality of the counters and histograms of the snapshots.
+/// See [`SnapshotIterExt::temporality`].
+pub struct ConvertTemporality<I> {
+    snapshots: I,
+    converter: TemporalityConverter,
+}
+
+impl<I: Iterator<Item = Snapshot>> Iterator for ConvertTemporality<I> {
+    type Item = Snapshot;
+
+    fn next(&mut self) -> Option<Snapshot> {
+        let snapshot = self.snapshots.next()?;
+        Some(self.converter.convert(snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn globs() {
+        let matches = |pattern: &str, name: &str| glob(&chars(pattern), &chars(name));
+        assert!(matches("requests/*", "requests/ok"));
+        assert!(matches("requests/*", "requests/"));
+        assert!(matches("*/latency", "rpc/get/latency"));
+        assert!(matches("rpc/???", "rpc/get"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("requests/*", "request"));
+        assert!(!matches("rpc/???", "rpc/gets"));
+        assert!(!matches("a*b*c", "aXbYb"));
+    }
+
+    fn counter(name: &str, value: u64, metadata: &[(&str, &str)]) -> Counter {
+        let mut counter = Counter::new(name, value);
+        for (k, v) in metadata {
+            counter.metadata.insert(k.to_string(), v.to_string());
+        }
+        counter
+    }
+
+    #[test]
+    fn pipeline() {
+        let snapshots = (0..4u64).map(|s| {
+            let mut snapshot = Snapshot::new();
+            snapshot.systemtime = SystemTime::UNIX_EPOCH + Duration::from_secs(s);
+            snapshot.counters = vec![
+                counter("requests", s, &[("op", "get"), ("host", "a")]),
+                counter("requests", 10 * s, &[("op", "get"), ("host", "b")]),
+                counter("requests", 100 * s, &[("op", "set"), ("host", "a")]),
+                counter("errors", 1, &[("op", "get"), ("host", "a")]),
+            ];
+            snapshot
+        });
+
+        let snapshots: Vec<Snapshot> = snapshots
+            .filter_metrics("req*")
+            .aggregate(Duration::from_secs(2))
+            .group_by(&["op"])
+            .collect();
+        assert_eq!(snapshots.len(), 2);
+
+        let mut counters: Vec<String> = snapshots[1]
+            .counters
+            .iter()
+            .map(|c| {
+                let mut metadata: Vec<String> =
+                    c.metadata.iter().map(|(k, v)| format!("{k}={v}")).collect();
+                metadata.sort();
+                format!("{} {} {}", c.name, c.value, metadata.join(","))
+            })
+            .collect();
+        counters.sort();
+        assert_eq!(
+            counters,
+            ["requests 300 host=a,op=set", "requests 33 op=get"]
+        );
+    }
+
+    #[test]
+    fn group_gauges_and_histograms() {
+        let mut snapshot = Snapshot::new();
+        for (host, value) in [("a", 3), ("b", 5)] {
+            let mut gauge = Gauge::new("connections", value);
+            gauge.metadata.insert("host".to_string(), host.to_string());
+            snapshot.gauges.push(gauge.clone());
+
+            gauge.name = "load".to_string();
+            gauge
+                .metadata
+                .insert("aggregation".to_string(), "mean".to_string());
+            snapshot.gauges.push(gauge);
+
+            let mut value = histogram::Histogram::new(value as u8, 64).unwrap();
+            value.increment(100).unwrap();
+            snapshot.histograms.push(Histogram::new("latency", value));
+        }
+
+        let snapshot = std::iter::once(snapshot).group_by(&[]).next().unwrap();
+        let gauges: Vec<(&str, i64)> = snapshot
+            .gauges
+            .iter()
+            .map(|g| (g.name.as_str(), g.value))
+            .collect();
+        assert_eq!(gauges, [("connections", 8), ("load", 4)]);
+        assert!(snapshot.gauges[0].metadata.is_empty());
+        assert_eq!(snapshot.gauges[1].metadata["aggregation"], "mean");
+
+        assert_eq!(snapshot.histograms.len(), 1);
+        let histogram = &snapshot.histograms[0].value;
+        assert_eq!(histogram.config().grouping_power(), 3);
+        assert_eq!(histogram.as_slice().iter().sum::<u64>(), 2);
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use crate::snapshot::{MetricType, Snapshot};
+
+/// The temporality with which an exporter reports counters and histograms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Temporality {
+    /// Values are running totals since the process started.
+    #[default]
+    Cumulative,
+    /// Values are the change since the previous snapshot.
+    Delta,
+}
+
+/// Identifies a series across snapshots by its name and metadata.
+pub(crate) type SeriesKey = (String, Vec<(String, String)>);
+
+pub(crate) fn series_key(name: &str, metadata: &HashMap<String, String>) -> SeriesKey {
+    let mut metadata: Vec<(String, String)> = metadata
+        .iter()
+        .filter(|(k, _)| k.as_str() != "temporality")
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    metadata.sort();
+    (name.to_string(), metadata)
+}
+
+/// Converts a stream of snapshots to the requested temporality.
+///
+/// Each exporter should hold its own converter, since the conversion depends
+/// on the previous snapshot that the exporter saw. Snapshots must be passed to
+/// [`TemporalityConverter::convert`] in the order they were taken.
+///
+/// When converting to delta temporality, cumulative counters and histograms
+/// are replaced by the difference from the previous snapshot. A series seen
+/// for the first time, or one which has gone backwards (for example after a
+/// restart), reports its full value. Delta counters and histograms are
+/// passed through. When converting to
+/// cumulative temporality, delta counters and histograms are summed into
+/// running totals. Gauges and stats are passed through unchanged.
+#[derive(Clone, Default)]
+pub struct TemporalityConverter {
+    temporality: Temporality,
+    counters: HashMap<SeriesKey, u64>,
+    histograms: HashMap<SeriesKey, histogram::Histogram>,
+}
+
+impl TemporalityConverter {
+    /// Create a converter which produces the provided temporality.
+    pub fn new(temporality: Temporality) -> Self {
+        Self {
+            temporality,
+            ..Default::default()
+        }
+    }
+
+    /// The temporality this converter produces.
+    pub fn temporality(&self) -> Temporality {
+        self.temporality
+    }
+
+    /// Convert a snapshot, updating the tracked state.
+    pub fn convert(&mut self, mut snapshot: Snapshot) -> Snapshot {
+        match self.temporality {
+            Temporality::Cumulative => {
+                for counter in snapshot.counters.iter_mut() {
+                    if counter.metric_type != MetricType::DeltaCounter {
+                        continue;
+                    }
+
+                    let total = self
+                        .counters
+                        .entry(series_key(&counter.name, &counter.metadata))
+                        .or_default();
+                    *total = total.wrapping_add(counter.value);
+
+                    counter.value = *total;
+                    counter.metric_type = MetricType::Counter;
+                    counter.metadata.remove("temporality");
+                }
+
+                for histogram in snapshot.histograms.iter_mut() {
+                    if histogram.metric_type != MetricType::DeltaHistogram {
+                        continue;
+                    }
+
+                    let key = series_key(&histogram.name, &histogram.metadata);
+                    let total = match self.histograms.get(&key) {
+                        // a histogram with a new configuration starts over
+                        Some(total) => total
+                            .wrapping_add(&histogram.value)
+                            .unwrap_or_else(|_| histogram.value.clone()),
+                        None => histogram.value.clone(),
+                    };
+                    self.histograms.insert(key, total.clone());
+
+                    histogram.value = total;
+                    histogram.metric_type = MetricType::Histogram;
+                    histogram.metadata.remove("temporality");
+                }
+            }
+            Temporality::Delta => {
+                for counter in snapshot.counters.iter_mut() {
+                    if counter.metric_type != MetricType::Counter {
+                        continue;
+                    }
+
+                    let key = series_key(&counter.name, &counter.metadata);
+                    let previous = self.counters.insert(key, counter.value);
+
+                    counter.value = match previous {
+                        Some(previous) if previous <= counter.value => counter.value - previous,
+                        _ => counter.value,
+                    };
+                    counter.metric_type = MetricType::DeltaCounter;
+                    counter
+                        .metadata
+                        .insert("temporality".to_string(), "delta".to_string());
+                }
+
+                for histogram in snapshot.histograms.iter_mut() {
+                    if histogram.metric_type != MetricType::Histogram {
+                        continue;
+                    }
+
+                    let key = series_key(&histogram.name, &histogram.metadata);
+                    let previous = self.histograms.insert(key, histogram.value.clone());
+
+                    if let Some(delta) = previous.and_then(|p| histogram.value.checked_sub(&p).ok())
+                    {
+                        histogram.value = delta;
+                    }
+                    histogram.metric_type = MetricType::DeltaHistogram;
+                    histogram
+                        .metadata
+                        .insert("temporality".to_string(), "delta".to_string());
+                }
+            }
+        }
+
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Counter, Histogram};
+
+    fn snapshot(counter: u64, metric_type: MetricType, value: u64) -> Snapshot {
+        let mut h = histogram::Histogram::new(2, 10).unwrap();
+        h.add(value, counter).unwrap();
+
+        let mut snapshot = Snapshot::new();
+        snapshot.counters.push(Counter {
+            name: "counter".to_string(),
+            metric_type,
+            value: counter,
+            metadata: HashMap::new(),
+        });
+        snapshot.histograms.push(Histogram {
+            name: "histogram".to_string(),
+            metric_type: MetricType::Histogram,
+            value: h,
+            metadata: HashMap::new(),
+        });
+        snapshot
+    }
+
+    #[test]
+    fn delta() {
+        let mut converter = TemporalityConverter::new(Temporality::Delta);
+
+        let s = converter.convert(snapshot(10, MetricType::Counter, 1));
+        assert_eq!(s.counters[0].value, 10);
+        assert_eq!(s.counters[0].metric_type, MetricType::DeltaCounter);
+
+        let s = converter.convert(snapshot(15, MetricType::Counter, 1));
+        assert_eq!(s.counters[0].value, 5);
+        assert_eq!(s.histograms[0].value.as_slice()[1], 5);
+
+        // counter reset
+        let s = converter.convert(snapshot(3, MetricType::Counter, 1));
+        assert_eq!(s.counters[0].value, 3);
+        assert_eq!(s.histograms[0].value.as_slice()[1], 3);
+    }
+
+    #[test]
+    fn cumulative() {
+        let mut converter = TemporalityConverter::new(Temporality::Cumulative);
+
+        converter.convert(snapshot(10, MetricType::DeltaCounter, 1));
+        let s = converter.convert(snapshot(5, MetricType::DeltaCounter, 1));
+        assert_eq!(s.counters[0].value, 15);
+        assert_eq!(s.counters[0].metric_type, MetricType::Counter);
+
+        // cumulative counters are untouched
+        let s = converter.convert(snapshot(5, MetricType::Counter, 1));
+        assert_eq!(s.counters[0].value, 5);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut delta = TemporalityConverter::new(Temporality::Delta);
+        let mut cumulative = TemporalityConverter::new(Temporality::Cumulative);
+
+        for count in [10, 15, 15, 20] {
+            let mut original = snapshot(count, MetricType::Counter, 1);
+            original.histograms[0].value.add(100, count * 2).unwrap();
+
+            let d = delta.convert(original.clone());
+            assert_eq!(d.histograms[0].metric_type, MetricType::DeltaHistogram);
+
+            // delta histograms are not differenced again
+            let again = delta.convert(d.clone());
+            assert_eq!(again.histograms[0].value, d.histograms[0].value);
+
+            let c = cumulative.convert(d);
+            assert_eq!(c.counters[0].value, original.counters[0].value);
+            assert_eq!(c.counters[0].metric_type, MetricType::Counter);
+            assert_eq!(c.histograms[0].value, original.histograms[0].value);
+            assert_eq!(c.histograms[0].metric_type, MetricType::Histogram);
+            assert!(!c.histograms[0].metadata.contains_key("temporality"));
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use metriken_core::Unit;
+
+use crate::snapshot::Snapshot;
+use crate::Rfc3339;
+
+/// The percentiles of each histogram shown by [`Snapshot::to_text`].
+pub(crate) const PERCENTILES: [f64; 4] = [50.0, 90.0, 99.0, 99.9];
+
+/// Format a value in the most readable scale for its unit, such as
+/// `1.18 MiB` for 1234567 bytes or `1.5 ms` for 1500000 nanoseconds.
+///
+/// Times are shown in nanoseconds up to seconds, data in bytes with binary
+/// prefixes, and fractions as percentages. Values in other units are shown as
+/// they are, followed by the name of any custom unit. Scaled values are
+/// rounded to two decimal places.
+///
+/// ```
+/// # use metriken_core::Unit;
+/// # use metriken_exposition::format_human;
+/// assert_eq!(format_human(1_234_567.0, Unit::Bytes), "1.18 MiB");
+/// assert_eq!(format_human(1_500_000.0, Unit::Nanoseconds), "1.5 ms");
+/// assert_eq!(format_human(0.25, Unit::Ratio), "25%");
+/// assert_eq!(format_human(12.0, Unit::Custom("requests")), "12 requests");
+/// ```
+pub fn format_human(value: f64, unit: Unit<'_>) -> String {
+    let (value, suffix) = match unit.base() {
+        Unit::Seconds => {
+            let seconds = unit.convert(value, Unit::Seconds).unwrap_or(value);
+            scale(
+                seconds,
+                &[(1e-9, "ns"), (1e-6, "µs"), (1e-3, "ms"), (1.0, "s")],
+            )
+        }
+        Unit::Bytes => {
+            let bytes = unit.convert(value, Unit::Bytes).unwrap_or(value);
+            scale(
+                bytes,
+                &[
+                    (1.0, "B"),
+                    (1024.0, "KiB"),
+                    (1024.0 * 1024.0, "MiB"),
+                    (1024.0 * 1024.0 * 1024.0, "GiB"),
+                    (1024.0 * 1024.0 * 1024.0 * 1024.0, "TiB"),
+                    (1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0, "PiB"),
+                ],
+            )
+        }
+        Unit::Ratio => {
+            let percent = unit.convert(value, Unit::Percent).unwrap_or(value);
+            return format!("{}%", round(percent));
+        }
+        Unit::Custom(name) => return format!("{} {name}", round(value)),
+        _ => return round(value),
+    };
+
+    format!("{} {suffix}", round(value))
+}
+
+/// Scale a value by the largest of the provided factors which it is at least
+/// one of, falling back to the smallest.
+fn scale(value: f64, scales: &[(f64, &'static str)]) -> (f64, &'static str) {
+    let (factor, suffix) = scales
+        .iter()
+        .rev()
+        .find(|(factor, _)| value.abs() >= *factor)
+        .unwrap_or(&scales[0]);
+    (value / factor, suffix)
+}
+
+/// Round to two decimal places, without trailing zeros.
+pub(crate) fn round(value: f64) -> String {
+    let formatted = format!("{value:.2}");
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    match trimmed {
+        "-0" => "0".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+/// Options controlling how a snapshot is rendered by [`Snapshot::to_text`].
+#[derive(Clone, Debug, Default)]
+pub struct TextOptions {
+    raw: bool,
+    bounds: bool,
+}
+
+impl TextOptions {
+    /// Create a new set of options which formats values by their unit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Show the raw values of metrics instead of formatting them by their
+    /// unit.
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.raw = raw;
+        self
+    }
+
+    /// Show the bounds of the bucket each histogram percentile falls in, such
+    /// as `p99=1023 (1008..1023)`. The percentile is reported as the upper
+    /// bound of its bucket, so the true value can be anywhere in the range.
+    /// The width of the range depends on the `grouping_power` of the
+    /// histogram.
+    pub fn bounds(mut self, bounds: bool) -> Self {
+        self.bounds = bounds;
+        self
+    }
+
+    fn format(&self, value: f64, unit: Option<Unit<'_>>) -> String {
+        match unit {
+            Some(unit) if !self.raw => format_human(value, unit),
+            _ => value.to_string(),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Render the snapshot as human readable text, with one metric per line
+    /// after a line with the time of the snapshot and a line with its
+    /// resource attributes, if it has any. This is intended for
+    /// people, such as when debugging with `curl`, and the layout may change.
+    ///
+    /// Values of metrics with a `unit` are formatted with [`format_human`]
+    /// unless [`TextOptions::raw`] is set. Histograms and sketches are
+    /// summarized by their count and a few percentiles.
+    pub fn to_text(&self, options: &TextOptions) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}",
+            Rfc3339::new().precision(3).format(self.systemtime)
+        );
+
+        let resource = self.resource();
+        if !resource.is_empty() {
+            let resource: Vec<String> = resource
+                .into_iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect();
+            let _ = writeln!(out, "resource: {}", resource.join(" "));
+        }
+
+        for counter in &self.counters {
+            let value = options.format(counter.value as f64, counter.unit());
+            let name = text_name(&counter.name, &counter.metadata);
+            let _ = writeln!(out, "{name}: {value}");
+        }
+
+        for gauge in &self.gauges {
+            let value = options.format(gauge.value as f64, gauge.unit());
+            let name = text_name(&gauge.name, &gauge.metadata);
+            let _ = writeln!(out, "{name}: {value}");
+        }
+
+        for histogram in &self.histograms {
+            let name = text_name(&histogram.name, &histogram.metadata);
+            let count: u64 = histogram.value.as_slice().iter().sum();
+            let _ = write!(out, "{name}: count={count}");
+
+            if let Ok(Some(percentiles)) = histogram.value.percentiles(&PERCENTILES) {
+                for (percentile, bucket) in percentiles {
+                    let value = options.format(bucket.end() as f64, histogram.unit());
+                    let _ = write!(out, " p{percentile}={value}");
+
+                    if options.bounds {
+                        let start = options.format(bucket.start() as f64, histogram.unit());
+                        let _ = write!(out, " ({start}..{value})");
+                    }
+                }
+            }
+            out.push('\n');
+        }
+
+        for stats in &self.stats {
+            let name = text_name(&stats.name, &stats.metadata);
+            let _ = write!(out, "{name}: count={}", stats.count);
+
+            if let (Some(min), Some(max)) = (stats.min, stats.max) {
+                let mean = stats.sum as f64 / stats.count as f64;
+                let _ = write!(
+                    out,
+                    " min={} mean={} max={}",
+                    options.format(min as f64, stats.unit()),
+                    options.format(mean, stats.unit()),
+                    options.format(max as f64, stats.unit()),
+                );
+            }
+            out.push('\n');
+        }
+
+        for sketch in &self.sketches {
+            let name = text_name(&sketch.name, &sketch.metadata);
+            let _ = write!(out, "{name}: count={}", sketch.value.count());
+
+            for percentile in PERCENTILES {
+                if let Some(value) = sketch.value.quantile(percentile / 100.0) {
+                    let value = options.format(value, sketch.unit());
+                    let _ = write!(out, " p{percentile}={value}");
+                }
+            }
+            out.push('\n');
+        }
+
+        for cardinality in &self.cardinalities {
+            let name = text_name(&cardinality.name, &cardinality.metadata);
+            let _ = writeln!(out, "{name}: estimate={}", cardinality.estimate);
+        }
+
+        for top_k in &self.top_k {
+            let name = text_name(&top_k.name, &top_k.metadata);
+            let _ = write!(out, "{name}:");
+            for entry in &top_k.entries {
+                let _ = write!(out, " {}={}", entry.key, entry.count);
+                if entry.error > 0 {
+                    let _ = write!(out, "±{}", entry.error);
+                }
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+/// The name of a metric with its metadata, leaving out the keys which only
+/// describe the metric.
+pub(crate) fn text_name(name: &str, metadata: &HashMap<String, String>) -> String {
+    let mut labels: Vec<String> = metadata
+        .iter()
+        .filter(|(k, _)| {
+            !matches!(
+                k.as_str(),
+                "description" | "unit" | "grouping_power" | "max_value_power"
+            )
+        })
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect();
+
+    if labels.is_empty() {
+        return name.to_string();
+    }
+
+    labels.sort();
+    format!("{name}{{{}}}", labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    #[test]
+    fn human() {
+        assert_eq!(format_human(0.0, Unit::Bytes), "0 B");
+        assert_eq!(format_human(512.0, Unit::Bytes), "512 B");
+        assert_eq!(format_human(2048.0, Unit::Bits), "256 B");
+        assert_eq!(format_human(3.0 * 1024.0f64.powi(4), Unit::Bytes), "3 TiB");
+        assert_eq!(format_human(999.0, Unit::Nanoseconds), "999 ns");
+        assert_eq!(format_human(1234.0, Unit::Microseconds), "1.23 ms");
+        assert_eq!(format_human(90.0, Unit::Seconds), "90 s");
+        assert_eq!(format_human(-1_500_000.0, Unit::Nanoseconds), "-1.5 ms");
+        assert_eq!(format_human(12.5, Unit::Percent), "12.5%");
+        assert_eq!(format_human(1234.0, Unit::Count), "1234");
+    }
+
+    #[test]
+    fn text() {
+        let mut latency = histogram::Histogram::new(4, 32).unwrap();
+        for value in [1_000_000, 2_000_000] {
+            latency.increment(value).unwrap();
+        }
+        let mut size = crate::DDSketch::new(0.01).unwrap();
+        size.add(100.0);
+
+        let snapshot = Snapshot::builder()
+            .systemtime(SystemTime::UNIX_EPOCH + Duration::from_secs(1))
+            .resource("service.name", "api")
+            .counter("requests", 3, &[("method", "GET")])
+            .gauge("memory", 1_234_567, &[("unit", "bytes")])
+            .histogram("latency", latency, &[("unit", "nanoseconds")])
+            .stats("idle", None, None, 0, 0, &[])
+            .sketch("size", size, &[("unit", "count")])
+            .cardinality("clients", crate::HyperLogLog::new(4).unwrap(), &[])
+            .top_k(
+                "keys",
+                2,
+                vec![
+                    crate::HeavyHitter::new("a", 7, 0),
+                    crate::HeavyHitter::new("b", 3, 2),
+                ],
+                &[],
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            snapshot.to_text(&TextOptions::new()),
+            "1970-01-01T00:00:01.000Z\n\
+             resource: service.name=api\n\
+             requests{method=GET}: 3\n\
+             memory: 1.18 MiB\n\
+             latency: count=2 p50=1.02 ms p90=2.03 ms p99=2.03 ms p99.9=2.03 ms\n\
+             idle: count=0\n\
+             size: count=1 p50=100.49 p90=100.49 p99=100.49 p99.9=100.49\n\
+             clients: estimate=0\n\
+             keys: a=7 b=3±2\n"
+        );
+
+        let raw = snapshot.to_text(&TextOptions::new().raw(true));
+        assert!(raw.contains("memory: 1234567\n"));
+
+        let bounds = snapshot.to_text(&TextOptions::new().raw(true).bounds(true));
+        assert!(bounds.contains(
+            "latency: count=2 p50=1015807 (983040..1015807) p90=2031615 (1966080..2031615)"
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use metriken::{Counter, DynBoxedMetric, MetricBuilder, Unit};
+
+use crate::snapshotter::Collector;
+
+/// The CPU time of a single thread, read from `/proc/self/task/<tid>/stat`.
+#[derive(Debug, PartialEq, Eq)]
+struct ThreadStat {
+    name: String,
+    /// Clock ticks spent in user mode.
+    user: u64,
+    /// Clock ticks spent in kernel mode.
+    system: u64,
+}
+
+/// Parse the `stat` file of a thread. The name is between the first `(` and
+/// the last `)`, since it may contain either, and the user and system times
+/// are the 14th and 15th fields.
+fn parse_stat(contents: &str) -> Option<ThreadStat> {
+    let start = contents.find('(')?;
+    let end = contents.rfind(')')?;
+    let name = contents.get(start + 1..end)?.to_string();
+
+    // the fields after the name start with the state, the 3rd field
+    let mut fields = contents[end + 1..].split_whitespace().skip(11);
+    let user = fields.next()?.parse().ok()?;
+    let system = fields.next()?.parse().ok()?;
+
+    Some(ThreadStat { name, user, system })
+}
+
+struct ThreadMetrics {
+    cpu_time: DynBoxedMetric<Counter>,
+    seen: Instant,
+}
+
+/// Collects the CPU time used by each thread of the current process, so that
+/// busy threads, such as a single hot runtime worker, can be found from a
+/// recording.
+///
+/// Each thread has a `thread/cpu_time` counter of the time it has spent in
+/// user and kernel mode, in nanoseconds, with the name of the thread in the
+/// `thread` metadata key and its id in the `tid` key. Counters of threads
+/// which have exited are kept for a time to live, so that their final value
+/// is still recorded, and are unregistered when it passes.
+///
+/// The readings come from `/proc/self/task`, so only Linux is supported. On
+/// other systems no metrics are registered.
+///
+/// ```
+/// # use metriken_exposition::{SnapshotterBuilder, ThreadCollector};
+/// # use std::time::Duration;
+/// let snapshotter = SnapshotterBuilder::new()
+///     .collector(ThreadCollector::new().ttl(Duration::from_secs(300)))
+///     .build();
+/// ```
+pub struct ThreadCollector {
+    ttl: Duration,
+    ticks: u64,
+    threads: Mutex<HashMap<u64, ThreadMetrics>>,
+}
+
+impl Default for ThreadCollector {
+    fn default() -> Self {
+        #[cfg(unix)]
+        let ticks = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+            ticks if ticks > 0 => ticks as u64,
+            _ => 100,
+        };
+        #[cfg(not(unix))]
+        let ticks = 100;
+
+        Self {
+            ttl: Duration::from_secs(60),
+            ticks,
+            threads: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl ThreadCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long the counter of a thread which has exited is kept before it
+    /// is unregistered. Defaults to one minute.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn nanoseconds(&self, ticks: u64) -> u64 {
+        (ticks as u128 * 1_000_000_000 / self.ticks as u128) as u64
+    }
+}
+
+impl Collector for ThreadCollector {
+    fn refresh(&self) {
+        let now = Instant::now();
+        let mut threads = self.threads.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Ok(tasks) = std::fs::read_dir("/proc/self/task") {
+            for task in tasks.flatten() {
+                let Some(tid) = task.file_name().to_str().and_then(|t| t.parse().ok()) else {
+                    continue;
+                };
+                let Some(stat) = std::fs::read_to_string(task.path().join("stat"))
+                    .ok()
+                    .and_then(|contents| parse_stat(&contents))
+                else {
+                    continue;
+                };
+
+                let metrics = threads.entry(tid).or_insert_with(|| ThreadMetrics {
+                    cpu_time: MetricBuilder::new("thread/cpu_time")
+                        .metadata("thread", stat.name.as_str())
+                        .metadata("tid", tid.to_string())
+                        .unit(Unit::Nanoseconds)
+                        .build(Counter::new()),
+                    seen: now,
+                });
+                metrics
+                    .cpu_time
+                    .set(self.nanoseconds(stat.user + stat.system));
+                metrics.seen = now;
+            }
+        }
+
+        threads.retain(|_, metrics| now.duration_since(metrics.seen) <= self.ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat() {
+        let contents = "1234 (tokio (worker) 1) S 1 1234 1234 0 -1 4194368 \
+            100 0 0 0 25 10 0 0 20 0 8 0 500 1000 100 18446744073709551615";
+
+        assert_eq!(
+            parse_stat(contents),
+            Some(ThreadStat {
+                name: "tokio (worker) 1".to_string(),
+                user: 25,
+                system: 10,
+            })
+        );
+        assert_eq!(parse_stat("1234 (truncated"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn threads() {
+        let registered = |thread: &str| {
+            metriken::metrics()
+                .iter()
+                .filter(|m| m.name() == "thread/cpu_time")
+                .filter(|m| m.metadata().get("thread") == Some(thread))
+                .count()
+        };
+
+        let collector = ThreadCollector::new().ttl(Duration::ZERO);
+
+        let (ready, wait) = std::sync::mpsc::channel();
+        let (exit, exited) = std::sync::mpsc::channel::<()>();
+        let handle = std::thread::Builder::new()
+            .name("metriken-test".to_string())
+            .spawn(move || {
+                ready.send(()).unwrap();
+                let _ = exited.recv();
+            })
+            .unwrap();
+
+        wait.recv().unwrap();
+        collector.refresh();
+        assert_eq!(registered("metriken-test"), 1);
+
+        drop(exit);
+        handle.join().unwrap();
+
+        // with no time to live the counter is unregistered on the next refresh
+        std::thread::sleep(Duration::from_millis(1));
+        collector.refresh();
+        assert_eq!(registered("metriken-test"), 0);
+    }
+}
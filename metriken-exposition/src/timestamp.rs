@@ -0,0 +1,296 @@
+use std::time::{Duration, SystemTime};
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+const SECS_PER_DAY: i128 = 86_400;
+
+/// Renders times as RFC 3339 strings, such as `2024-05-01T12:30:00.123Z`.
+///
+/// By default times are rendered in UTC with nanosecond precision. The number
+/// of fractional digits and the offset from UTC can both be changed.
+///
+/// ```
+/// # use metriken_exposition::Rfc3339;
+/// # use std::time::{Duration, SystemTime};
+/// let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_714_566_600_123);
+/// assert_eq!(
+///     Rfc3339::new().precision(3).format(time),
+///     "2024-05-01T12:30:00.123Z"
+/// );
+/// assert_eq!(
+///     Rfc3339::new().precision(0).utc_offset(-300).format(time),
+///     "2024-05-01T07:30:00-05:00"
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rfc3339 {
+    precision: u8,
+    offset: i16,
+}
+
+impl Default for Rfc3339 {
+    fn default() -> Self {
+        Self {
+            precision: 9,
+            offset: 0,
+        }
+    }
+}
+
+impl Rfc3339 {
+    /// Render times in UTC with nanosecond precision.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of fractional second digits, up to nine. With zero
+    /// digits the fraction is left out entirely. Times are truncated, not
+    /// rounded, to the precision.
+    pub fn precision(mut self, digits: u8) -> Self {
+        self.precision = digits.min(9);
+        self
+    }
+
+    /// Sets the offset from UTC in minutes which times are rendered in, such
+    /// as `60` for `+01:00`. Offsets are limited to less than a day in either
+    /// direction.
+    pub fn utc_offset(mut self, minutes: i16) -> Self {
+        self.offset = minutes.clamp(-1439, 1439);
+        self
+    }
+
+    /// Render the time.
+    pub fn format(&self, time: SystemTime) -> String {
+        let nanos = match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(since) => since.as_nanos() as i128,
+            Err(e) => -(e.duration().as_nanos() as i128),
+        };
+        let local = nanos + self.offset as i128 * 60 * NANOS_PER_SEC;
+
+        let secs = local.div_euclid(NANOS_PER_SEC);
+        let fraction = local.rem_euclid(NANOS_PER_SEC);
+        let (year, month, day) = civil_from_days(secs.div_euclid(SECS_PER_DAY));
+        let second_of_day = secs.rem_euclid(SECS_PER_DAY);
+
+        let mut formatted = format!(
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+            second_of_day / 3600,
+            second_of_day / 60 % 60,
+            second_of_day % 60,
+        );
+
+        if self.precision > 0 {
+            let digits = format!("{fraction:09}");
+            formatted.push('.');
+            formatted.push_str(&digits[..self.precision as usize]);
+        }
+
+        if self.offset == 0 {
+            formatted.push('Z');
+        } else {
+            let sign = if self.offset < 0 { '-' } else { '+' };
+            let offset = self.offset.unsigned_abs();
+            formatted.push_str(&format!("{sign}{:02}:{:02}", offset / 60, offset % 60));
+        }
+
+        formatted
+    }
+
+    /// Parse an RFC 3339 time with any precision and offset, such as one
+    /// rendered with [`Rfc3339::format`]. Digits beyond nanoseconds are
+    /// ignored.
+    pub fn parse(s: &str) -> Option<SystemTime> {
+        let bytes = s.as_bytes();
+        if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' {
+            return None;
+        }
+        if !matches!(bytes[10], b'T' | b't' | b' ') || bytes[16] != b':' {
+            return None;
+        }
+
+        let number = |range| number_in(s, range);
+
+        let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+        let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+
+        let mut rest = &s[19..];
+        let mut fraction = 0;
+        if let Some(after) = rest.strip_prefix('.') {
+            let len = after.bytes().take_while(|b| b.is_ascii_digit()).count();
+            if len == 0 {
+                return None;
+            }
+
+            let digits = format!("{:0<9}", &after[..len.min(9)]);
+            fraction = digits.parse().ok()?;
+            rest = &after[len..];
+        }
+
+        let offset = match rest {
+            "Z" | "z" => 0,
+            _ => {
+                let sign = match rest.as_bytes().first()? {
+                    b'+' => 1,
+                    b'-' => -1,
+                    _ => return None,
+                };
+                if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                    return None;
+                }
+                let (hours, minutes) = (number_in(rest, 1..3)?, number_in(rest, 4..6)?);
+                if hours > 23 || minutes > 59 {
+                    return None;
+                }
+                sign * (hours * 60 + minutes)
+            }
+        };
+
+        let secs =
+            days_from_civil(year, month, day) * SECS_PER_DAY + hour * 3600 + minute * 60 + second
+                - offset * 60;
+        let nanos = secs * NANOS_PER_SEC + fraction;
+
+        let since = Duration::from_nanos(u64::try_from(nanos.unsigned_abs()).ok()?);
+        if nanos < 0 {
+            SystemTime::UNIX_EPOCH.checked_sub(since)
+        } else {
+            SystemTime::UNIX_EPOCH.checked_add(since)
+        }
+    }
+}
+
+/// Parse the decimal digits of a range of the string.
+fn number_in(s: &str, range: std::ops::Range<usize>) -> Option<i128> {
+    let digits = s.get(range)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// The year, month, and day of a number of days since the unix epoch, in the
+/// proleptic Gregorian calendar.
+fn civil_from_days(days: i128) -> (i128, i128, i128) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i128;
+    (year, month, day)
+}
+
+/// The number of days since the unix epoch of a date in the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: i128, month: i128, day: i128) -> i128 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Deserialize a snapshot time from either the serde representation of a
+/// `SystemTime` or, for human readable formats, an RFC 3339 string as written
+/// with [`Rfc3339`].
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+
+    if !deserializer.is_human_readable() {
+        return SystemTime::deserialize(deserializer);
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Rfc3339(String),
+        SystemTime(SystemTime),
+    }
+
+    match Repr::deserialize(deserializer)? {
+        Repr::Rfc3339(s) => Rfc3339::parse(&s)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid RFC 3339 time `{s}`"))),
+        Repr::SystemTime(time) => Ok(time),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(nanos: i128) -> SystemTime {
+        let since = Duration::from_nanos(nanos.unsigned_abs() as u64);
+        if nanos < 0 {
+            SystemTime::UNIX_EPOCH - since
+        } else {
+            SystemTime::UNIX_EPOCH + since
+        }
+    }
+
+    #[test]
+    fn format() {
+        let time = at(1_709_210_096_987_654_321);
+        assert_eq!(
+            Rfc3339::new().format(time),
+            "2024-02-29T12:34:56.987654321Z"
+        );
+        assert_eq!(
+            Rfc3339::new().precision(6).format(time),
+            "2024-02-29T12:34:56.987654Z"
+        );
+        assert_eq!(
+            Rfc3339::new().precision(0).utc_offset(330).format(time),
+            "2024-02-29T18:04:56+05:30"
+        );
+        assert_eq!(
+            Rfc3339::new().precision(0).utc_offset(-780).format(time),
+            "2024-02-28T23:34:56-13:00"
+        );
+
+        assert_eq!(
+            Rfc3339::new().precision(3).format(SystemTime::UNIX_EPOCH),
+            "1970-01-01T00:00:00.000Z"
+        );
+        assert_eq!(
+            Rfc3339::new().precision(1).format(at(-100_000_000)),
+            "1969-12-31T23:59:59.9Z"
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let time = at(1_709_210_096_987_654_321);
+        for precision in [0, 3, 9] {
+            for offset in [0, 330, -780] {
+                let format = Rfc3339::new().precision(precision).utc_offset(offset);
+                let expected = at(1_709_210_096_987_654_321 / 10i128.pow(9 - precision as u32)
+                    * 10i128.pow(9 - precision as u32));
+                assert_eq!(Rfc3339::parse(&format.format(time)), Some(expected));
+            }
+        }
+
+        assert_eq!(
+            Rfc3339::parse("1969-12-31t23:59:59.9000000001z"),
+            Some(at(-100_000_000))
+        );
+        assert_eq!(Rfc3339::parse("2024-02-29T12:34:56"), None);
+        assert_eq!(Rfc3339::parse("2024-13-01T00:00:00Z"), None);
+        assert_eq!(Rfc3339::parse("2024-01-01T00:00:00.Z"), None);
+        assert_eq!(Rfc3339::parse("2024-01-01T00:00:00+0100"), None);
+    }
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, Cardinality};
+use metriken_exposition::{HyperLogLog, Snapshotter};
+
+#[metric(name = "clients", description = "unique clients")]
+static CLIENTS: Cardinality = Cardinality::new();
+
+#[test]
+fn cardinality_resets_each_snapshot() {
+    let snapshotter = Snapshotter::default();
+
+    for client in 0..100 {
+        CLIENTS.insert(&format!("10.0.0.{client}"));
+        CLIENTS.insert(&format!("10.0.0.{client}"));
+    }
+
+    let snapshot = snapshotter.snapshot();
+    let clients = &snapshot.cardinalities()[0];
+    assert_eq!(clients.name, "clients");
+    assert_eq!(clients.estimate, 100);
+    assert_eq!(clients.value.precision(), 12);
+    assert_eq!(
+        clients.metadata.get("description").map(|v| v.as_str()),
+        Some("unique clients")
+    );
+
+    // another process which saw half of the same clients and 50 others
+    let other = Cardinality::new();
+    for client in 50..150 {
+        other.insert(&format!("10.0.0.{client}"));
+    }
+    let mut merged = clients.clone();
+    merged
+        .merge(&HyperLogLog::from_registers(other.load()).unwrap())
+        .unwrap();
+    assert!(merged.estimate.abs_diff(150) <= 3, "{}", merged.estimate);
+
+    let snapshot = snapshotter.snapshot();
+    assert_eq!(snapshot.cardinalities()[0].estimate, 0);
+}
@@ -0,0 +1,61 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, AtomicHistogram, Counter, Gauge};
+use metriken_exposition::{Snapshot, SnapshotterBuilder};
+
+#[metric(name = "changed/counter")]
+static COUNTER: Counter = Counter::new();
+
+#[metric(name = "changed/gauge")]
+static GAUGE: Gauge = Gauge::new();
+
+#[metric(name = "changed/histogram")]
+static HISTOGRAM: AtomicHistogram = AtomicHistogram::new(4, 10);
+
+/// The values of the counters in the snapshot, ignoring those added by the
+/// `contention` feature.
+fn counter_values(snapshot: &Snapshot) -> Vec<u64> {
+    snapshot
+        .counters()
+        .iter()
+        .filter(|c| !c.name.ends_with("/contention"))
+        .map(|c| c.value)
+        .collect()
+}
+
+#[test]
+fn changed_only() {
+    let snapshotter = SnapshotterBuilder::new()
+        .filter(|entry| entry.name().starts_with("changed/"))
+        .changed_only(true)
+        .build();
+
+    HISTOGRAM.increment(1).unwrap();
+
+    // the first snapshot includes everything
+    let snapshot = snapshotter.snapshot();
+    assert_eq!(counter_values(&snapshot), [0]);
+    assert_eq!(snapshot.gauges().len(), 1);
+    assert_eq!(snapshot.histograms().len(), 1);
+    assert_eq!(snapshot.get_metadata("changed_only"), Some("true"));
+
+    let snapshot = snapshotter.snapshot();
+    assert!(counter_values(&snapshot).is_empty());
+    assert!(snapshot.gauges().is_empty());
+    assert!(snapshot.histograms().is_empty());
+
+    COUNTER.increment();
+    HISTOGRAM.increment(1).unwrap();
+
+    let snapshot = snapshotter.snapshot();
+    assert_eq!(counter_values(&snapshot), [1]);
+    assert!(snapshot.gauges().is_empty());
+    assert_eq!(snapshot.histograms().len(), 1);
+
+    GAUGE.set(5);
+
+    let snapshot = snapshotter.snapshot();
+    assert!(counter_values(&snapshot).is_empty());
+    assert_eq!(snapshot.gauges()[0].value, 5);
+    assert!(snapshot.histograms().is_empty());
+}
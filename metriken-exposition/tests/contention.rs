@@ -0,0 +1,57 @@
+#![cfg(feature = "contention")]
+
+use std::sync::Barrier;
+
+use metriken::{metric, Counter, PaddedCounter};
+use metriken_exposition::Snapshotter;
+
+#[metric(name = "hot")]
+static HOT: PaddedCounter = PaddedCounter::new();
+
+#[metric(name = "cold")]
+static COLD: Counter = Counter::new();
+
+#[test]
+fn contention_is_reported() {
+    let threads = 4;
+    let barrier = Barrier::new(threads);
+
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                barrier.wait();
+                for _ in 0..100_000 {
+                    HOT.increment();
+                }
+            });
+        }
+    });
+
+    assert_eq!(HOT.value(), 400_000);
+    assert_eq!(std::mem::align_of::<PaddedCounter>(), 128);
+
+    COLD.increment();
+
+    let snapshot = Snapshotter::default().snapshot();
+    let names: Vec<&str> = snapshot
+        .counters()
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect();
+
+    // counters which were never contended are not reported
+    assert!(!names.contains(&"cold/contention"));
+
+    if HOT.contention() > 0 {
+        // the contention counter follows the metric it belongs to
+        let index = names.iter().position(|n| *n == "hot/contention").unwrap();
+        assert_eq!(names[index - 1], "hot");
+
+        let contention = &snapshot.counters()[index];
+        assert_eq!(contention.value, HOT.contention());
+        assert_eq!(
+            contention.metadata.get("metric").map(|v| v.as_str()),
+            Some("hot")
+        );
+    }
+}
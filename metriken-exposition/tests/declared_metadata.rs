@@ -0,0 +1,31 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, AtomicHistogram, Unit};
+use metriken_exposition::Snapshotter;
+
+#[metric(
+    name = "request/latency",
+    description = "time taken to serve a request",
+    unit = "nanoseconds",
+    metadata = { endpoint = "get" }
+)]
+static LATENCY: AtomicHistogram = AtomicHistogram::new(4, 32);
+
+#[test]
+fn declared_metadata_in_snapshot() {
+    LATENCY.increment(1_000).unwrap();
+
+    let snapshot = Snapshotter::default().snapshot();
+    let histogram = &snapshot.histograms()[0];
+
+    assert_eq!(histogram.name, "request/latency");
+    assert_eq!(
+        histogram.description(),
+        Some("time taken to serve a request")
+    );
+    assert_eq!(histogram.unit(), Some(Unit::Nanoseconds));
+    assert_eq!(
+        histogram.metadata.get("endpoint").map(|v| v.as_str()),
+        Some("get")
+    );
+}
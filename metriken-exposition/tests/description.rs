@@ -0,0 +1,33 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, Counter, Gauge, MetricBuilder};
+use metriken_exposition::{PrometheusOptions, SnapshotterBuilder};
+
+#[metric(name = "described/requests", description = "requests served")]
+static REQUESTS: Counter = Counter::new();
+
+#[test]
+fn descriptions_reach_exporters() {
+    let connections = MetricBuilder::new("described/connections")
+        .description("open connections")
+        .build(Gauge::new());
+    connections.set(3);
+    REQUESTS.increment();
+
+    let snapshot = SnapshotterBuilder::new()
+        .filter(|entry| entry.name().starts_with("described/"))
+        .build()
+        .snapshot();
+
+    let requests = snapshot
+        .counters()
+        .iter()
+        .find(|c| c.name == "described/requests")
+        .unwrap();
+    assert_eq!(requests.description(), Some("requests served"));
+    assert_eq!(snapshot.gauges()[0].description(), Some("open connections"));
+
+    let text = snapshot.to_prometheus(&PrometheusOptions::new());
+    assert!(text.contains("# HELP described_requests requests served\n"));
+    assert!(text.contains("# HELP described_connections open connections\n"));
+}
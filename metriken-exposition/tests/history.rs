@@ -0,0 +1,42 @@
+#![cfg(all(feature = "serde", feature = "msgpack"))]
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use metriken_exposition::{RecordingReader, Snapshot, SnapshotHistory};
+
+#[test]
+fn flush_on_panic() {
+    let dir = tempfile::tempdir().unwrap();
+    let crashes = dir.path().join("crashes");
+
+    let history = Arc::new(SnapshotHistory::new(10));
+    for secs in 1..=3 {
+        history.push(
+            Snapshot::builder()
+                .systemtime(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .counter("requests", secs, &[])
+                .build()
+                .unwrap(),
+        );
+    }
+    history.flush_on_panic(&crashes);
+
+    let result = std::thread::spawn(|| panic!("crash")).join();
+    assert!(result.is_err());
+
+    let recordings: Vec<_> = std::fs::read_dir(&crashes)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(recordings.len(), 1);
+
+    let name = recordings[0].file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with(&format!("metrics-{}-", std::process::id())));
+
+    let recorded = RecordingReader::open(&recordings[0])
+        .unwrap()
+        .range(SystemTime::UNIX_EPOCH, SystemTime::now())
+        .unwrap();
+    assert_eq!(recorded.len(), 3);
+}
@@ -0,0 +1,28 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, IntervalCounter};
+use metriken_exposition::{MetricType, Snapshotter};
+
+#[metric(name = "interval")]
+static INTERVAL: IntervalCounter = IntervalCounter::new();
+
+#[test]
+fn interval_counter_reports_deltas() {
+    let snapshotter = Snapshotter::default();
+
+    INTERVAL.add(5);
+    let snapshot = snapshotter.snapshot();
+    let counter = &snapshot.counters()[0];
+    assert_eq!(counter.value, 5);
+    assert_eq!(counter.metric_type, MetricType::DeltaCounter);
+    assert!(!counter.metric_type.is_monotonic());
+    assert_eq!(
+        counter.metadata.get("temporality").map(|v| v.as_str()),
+        Some("delta")
+    );
+
+    INTERVAL.add(3);
+    INTERVAL.increment();
+    assert_eq!(snapshotter.snapshot().counters()[0].value, 4);
+    assert_eq!(snapshotter.snapshot().counters()[0].value, 0);
+}
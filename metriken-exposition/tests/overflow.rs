@@ -0,0 +1,51 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, AtomicHistogram, ThreadLocalHistogram};
+use metriken_exposition::{Snapshot, SnapshotterBuilder};
+
+#[metric(name = "overflow/atomic")]
+static ATOMIC: AtomicHistogram = AtomicHistogram::new(2, 10);
+
+#[metric(name = "overflow/thread_local")]
+static THREAD_LOCAL: ThreadLocalHistogram = ThreadLocalHistogram::new(2, 10);
+
+fn snapshot() -> Snapshot {
+    SnapshotterBuilder::new()
+        .filter(|entry| entry.name().starts_with("overflow/"))
+        .build()
+        .snapshot()
+}
+
+fn overflow(snapshot: &Snapshot, name: &str) -> Option<u64> {
+    snapshot
+        .counters()
+        .iter()
+        .find(|c| c.name == format!("{name}/overflow"))
+        .map(|c| c.value)
+}
+
+#[test]
+fn overflow_is_reported() {
+    ATOMIC.increment(1).unwrap();
+    assert_eq!(overflow(&snapshot(), "overflow/atomic"), None);
+
+    assert!(ATOMIC.increment(1 << 10).is_err());
+    assert!(ATOMIC.increment(u64::MAX).is_err());
+    assert!(THREAD_LOCAL.add(1 << 12, 5).is_err());
+    assert_eq!(ATOMIC.overflow(), 2);
+    assert_eq!(THREAD_LOCAL.overflow(), 5);
+
+    let snapshot = snapshot();
+    assert_eq!(overflow(&snapshot, "overflow/atomic"), Some(2));
+    assert_eq!(overflow(&snapshot, "overflow/thread_local"), Some(5));
+
+    let companion = snapshot
+        .counters()
+        .iter()
+        .find(|c| c.name == "overflow/atomic/overflow")
+        .unwrap();
+    assert_eq!(
+        companion.metadata.get("metric").map(|v| v.as_str()),
+        Some("overflow/atomic")
+    );
+}
@@ -0,0 +1,28 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, PeakGauge};
+use metriken_exposition::{Snapshotter, AGGREGATION};
+
+#[metric(name = "queue_depth")]
+static QUEUE_DEPTH: PeakGauge = PeakGauge::max();
+
+#[test]
+fn peak_resets_each_snapshot() {
+    let snapshotter = Snapshotter::default();
+
+    QUEUE_DEPTH.add(10);
+    QUEUE_DEPTH.sub(8);
+
+    let snapshot = snapshotter.snapshot();
+    let depth = &snapshot.gauges()[0];
+    assert_eq!(depth.name, "queue_depth");
+    assert_eq!(depth.value, 10);
+    assert_eq!(
+        depth.metadata.get(AGGREGATION).map(|v| v.as_str()),
+        Some("max")
+    );
+
+    // nothing changed, so the peak is the current value
+    let snapshot = snapshotter.snapshot();
+    assert_eq!(snapshot.gauges()[0].value, 2);
+}
@@ -0,0 +1,357 @@
+#![cfg(feature = "config")]
+
+use std::time::{Duration, SystemTime};
+
+use metriken::*;
+use metriken_exposition::{FilterConfig, Pipeline, PipelineConfig, RecordingReader, Severity};
+
+#[metric(name = "pipeline/requests", metadata = { internal = "true" })]
+static REQUESTS: Counter = Counter::new();
+
+#[metric(name = "pipeline/debug/depth")]
+static DEPTH: Gauge = Gauge::new();
+
+#[metric(name = "other/requests")]
+static OTHER: Counter = Counter::new();
+
+#[test]
+fn export() {
+    let dir = tempfile::tempdir().unwrap();
+    let prometheus = dir.path().join("metrics.prom");
+    let recording = dir.path().join("metrics.msgpack");
+
+    let config = PipelineConfig::from_toml(&format!(
+        r#"
+        interval = "250ms"
+
+        [metadata]
+        source = "pipeline-test"
+
+        [filter]
+        include = ["pipeline/"]
+        exclude = ["pipeline/debug/"]
+
+        [[transform]]
+        prefix = "pipeline/"
+        metadata = {{ team = "storage" }}
+        remove_metadata = ["internal"]
+
+        [[exporter]]
+        type = "prometheus"
+        path = "{}"
+
+        [[exporter]]
+        type = "msgpack"
+        path = "{}"
+        "#,
+        prometheus.display(),
+        recording.display()
+    ))
+    .unwrap();
+
+    let mut pipeline = Pipeline::from_config(&config).unwrap();
+    assert_eq!(pipeline.interval().as_millis(), 250);
+
+    REQUESTS.add(3);
+    DEPTH.set(7);
+    OTHER.increment();
+
+    let snapshot = pipeline.export().unwrap();
+    assert_eq!(snapshot.get_metadata("source"), Some("pipeline-test"));
+    assert!(snapshot.gauges.is_empty());
+
+    let requests = snapshot
+        .counters
+        .iter()
+        .find(|c| c.name == "pipeline/requests")
+        .unwrap();
+    assert_eq!(requests.value, 3);
+    assert_eq!(
+        requests.metadata.get("team").map(|v| v.as_str()),
+        Some("storage")
+    );
+    assert!(!requests.metadata.contains_key("internal"));
+    assert!(snapshot
+        .counters
+        .iter()
+        .all(|c| c.name.starts_with("pipeline/")));
+
+    let text = std::fs::read_to_string(&prometheus).unwrap();
+    assert!(
+        text.contains("pipeline_requests{team=\"storage\"} 3"),
+        "{text}"
+    );
+    assert!(!text.contains("other_requests"));
+
+    pipeline.export().unwrap();
+    let recorded = RecordingReader::open(&recording)
+        .unwrap()
+        .range(SystemTime::UNIX_EPOCH, SystemTime::now())
+        .unwrap();
+    assert_eq!(recorded.len(), 2);
+}
+
+#[metric(name = "reconfigure/requests")]
+static RECONFIGURE: Counter = Counter::new();
+
+fn exported(pipeline: &mut Pipeline) -> bool {
+    pipeline
+        .export()
+        .unwrap()
+        .counters
+        .iter()
+        .any(|c| c.name == "reconfigure/requests")
+}
+
+#[test]
+fn reconfigure() {
+    let dir = tempfile::tempdir().unwrap();
+    let recording = dir.path().join("metrics.msgpack");
+
+    let config = PipelineConfig::from_toml(
+        r#"
+        interval = "1h"
+
+        [filter]
+        include = ["nothing/"]
+        "#,
+    )
+    .unwrap();
+
+    let mut pipeline = Pipeline::from_config(&config).unwrap();
+    let handle = pipeline.handle();
+
+    RECONFIGURE.increment();
+    assert!(!exported(&mut pipeline));
+
+    handle.set_filter(FilterConfig {
+        include: vec!["reconfigure/".to_string()],
+        ..Default::default()
+    });
+    assert!(exported(&mut pipeline));
+
+    // an invalid config is rejected without changing the pipeline
+    let invalid = PipelineConfig::from_toml("interval = \"soon\"").unwrap();
+    assert!(handle.reload(&invalid).is_err());
+    assert!(exported(&mut pipeline));
+
+    handle
+        .reload(
+            &PipelineConfig::from_toml(&format!(
+                r#"
+                interval = "1h"
+
+                [filter]
+                include = ["reconfigure/"]
+
+                [[exporter]]
+                type = "msgpack"
+                path = "{}"
+                "#,
+                recording.display()
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+
+    // changing the interval wakes the running pipeline rather than waiting
+    // for the hour to end
+    std::thread::spawn(move || pipeline.run());
+    handle.set_interval(Duration::from_millis(10));
+
+    let start = std::time::Instant::now();
+    while std::fs::metadata(&recording).unwrap().len() == 0 {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[metric(name = "validate/latency")]
+static LATENCY: AtomicHistogram = AtomicHistogram::new(4, 32);
+
+#[test]
+fn validate() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let config = PipelineConfig::from_toml(&format!(
+        r#"
+        interval = "10s"
+
+        [filter]
+        include = ["validate/"]
+
+        [[exporter]]
+        type = "msgpack"
+        path = "{}"
+        "#,
+        dir.path().join("metrics.msgpack").display()
+    ))
+    .unwrap();
+    assert_eq!(Pipeline::validate(&config), []);
+    // validation does not create the exporter files
+    assert!(!dir.path().join("metrics.msgpack").exists());
+
+    let config = PipelineConfig::from_toml(&format!(
+        r#"
+        interval = "10 fortnights"
+
+        [filter]
+        include = ["validate/", "typo/"]
+
+        [[exporter]]
+        type = "prometheus"
+        path = "{}"
+
+        [[exporter]]
+        type = "msgpack"
+        path = "{}"
+        "#,
+        dir.path().join("metrics.prom").display(),
+        dir.path().join("missing/metrics.msgpack").display()
+    ))
+    .unwrap();
+
+    let diagnostics = Pipeline::validate(&config);
+    let locations: Vec<(Severity, &str)> = diagnostics
+        .iter()
+        .map(|d| (d.severity, d.location.as_str()))
+        .collect();
+    assert_eq!(
+        locations,
+        [
+            (Severity::Error, "interval"),
+            (Severity::Warning, "filter.include[1]"),
+            (Severity::Warning, "exporter[0]"),
+            (Severity::Error, "exporter[1]"),
+        ]
+    );
+    assert!(diagnostics[0].to_string().starts_with("error: interval:"));
+
+    LATENCY.increment(1).unwrap();
+}
+
+#[metric(name = "fan_out/requests")]
+static FAN_OUT: Counter = Counter::new();
+
+#[test]
+fn fan_out() {
+    let dir = tempfile::tempdir().unwrap();
+    let fast = dir.path().join("fast.msgpack");
+    let slow = dir.path().join("slow.msgpack");
+
+    let config = PipelineConfig::from_toml(&format!(
+        r#"
+        interval = "1h"
+
+        [filter]
+        include = ["fan_out/"]
+
+        [[exporter]]
+        type = "msgpack"
+        path = "{}"
+        interval = "10ms"
+
+        [[exporter]]
+        type = "msgpack"
+        path = "{}"
+        "#,
+        fast.display(),
+        slow.display()
+    ))
+    .unwrap();
+    assert_eq!(Pipeline::validate(&config), []);
+
+    let mut pipeline = Pipeline::from_config(&config).unwrap();
+    let handle = pipeline.handle();
+    assert!(handle.latest().is_none());
+
+    FAN_OUT.increment();
+    std::thread::spawn(move || pipeline.run());
+
+    let recorded = |path| {
+        RecordingReader::open(path)
+            .unwrap()
+            .range(SystemTime::UNIX_EPOCH, SystemTime::now())
+            .unwrap()
+            .len()
+    };
+
+    let start = std::time::Instant::now();
+    while recorded(&fast) < 2 {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // the exporter on the interval of the pipeline has not been sent any
+    assert_eq!(recorded(&slow), 0);
+    let latest = handle.latest().unwrap();
+    assert!(latest.counters.iter().any(|c| c.name == "fan_out/requests"));
+
+    let invalid = PipelineConfig::from_toml(&format!(
+        r#"
+        [[exporter]]
+        type = "msgpack"
+        path = "{}"
+        interval = "soon"
+        "#,
+        slow.display()
+    ))
+    .unwrap();
+    let diagnostics = Pipeline::validate(&invalid);
+    assert_eq!(diagnostics[0].location, "exporter[0].interval");
+    assert!(Pipeline::from_config(&invalid).is_err());
+}
+
+#[metric(name = "trigger/requests")]
+static TRIGGER: Counter = Counter::new();
+
+#[test]
+fn trigger() {
+    let dir = tempfile::tempdir().unwrap();
+    let recording = dir.path().join("metrics.msgpack");
+
+    let config = PipelineConfig::from_toml(&format!(
+        r#"
+        interval = "1h"
+
+        [filter]
+        include = ["trigger/"]
+
+        [[exporter]]
+        type = "msgpack"
+        path = "{}"
+        "#,
+        recording.display()
+    ))
+    .unwrap();
+
+    let mut pipeline = Pipeline::from_config(&config).unwrap();
+    let handle = pipeline.handle();
+    std::thread::spawn(move || pipeline.run());
+
+    TRIGGER.add(5);
+    let triggers: Vec<_> = (0..8)
+        .map(|_| {
+            let handle = handle.clone();
+            std::thread::spawn(move || handle.trigger())
+        })
+        .collect();
+
+    for trigger in triggers {
+        let snapshot = trigger.join().unwrap().unwrap();
+        let requests = snapshot
+            .counters
+            .iter()
+            .find(|c| c.name == "trigger/requests")
+            .unwrap();
+        assert_eq!(requests.value, 5);
+    }
+
+    // concurrent triggers are coalesced, but every snapshot was exported
+    let exported = RecordingReader::open(&recording)
+        .unwrap()
+        .range(SystemTime::UNIX_EPOCH, SystemTime::now())
+        .unwrap()
+        .len();
+    assert!((1..=8).contains(&exported), "{exported}");
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, SampledCounter, SampledHistogram, Sampling};
+use metriken_exposition::Snapshotter;
+
+#[metric(name = "sampled/counter")]
+static COUNTER: SampledCounter = SampledCounter::new(Sampling::OneIn(10));
+
+#[metric(name = "sampled/histogram")]
+static HISTOGRAM: SampledHistogram = SampledHistogram::new(4, 20, Sampling::Probability(0.5));
+
+#[test]
+fn sampled_metrics_are_scaled() {
+    for _ in 0..100_000 {
+        COUNTER.increment();
+        HISTOGRAM.increment(100).unwrap();
+    }
+
+    let recorded = COUNTER.recorded();
+    assert!(recorded > 9_000 && recorded < 11_000, "{recorded}");
+    assert_eq!(COUNTER.value(), recorded * 10);
+
+    let snapshot = Snapshotter::default().snapshot();
+
+    let counter = &snapshot.counters()[0];
+    assert_eq!(counter.value, recorded * 10);
+    assert_eq!(
+        counter.metadata.get("sampling_rate").map(|v| v.as_str()),
+        Some("0.1")
+    );
+
+    let histogram = &snapshot.histograms()[0];
+    let total: u64 = histogram.value.as_slice().iter().sum();
+    let recorded: u64 = HISTOGRAM.load_recorded().unwrap().as_slice().iter().sum();
+    assert_eq!(total, recorded * 2);
+    assert!(total > 95_000 && total < 105_000, "{total}");
+    assert_eq!(
+        histogram.metadata.get("sampling_rate").map(|v| v.as_str()),
+        Some("0.5")
+    );
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, SeenSet};
+use metriken_exposition::Snapshotter;
+
+#[metric(name = "keys")]
+static KEYS: SeenSet = SeenSet::new(1 << 16, 4);
+
+#[test]
+fn seen_set_reports_fill_ratio() {
+    let snapshotter = Snapshotter::default();
+
+    for key in 0..1000 {
+        KEYS.insert(&key);
+    }
+
+    let snapshot = snapshotter.snapshot();
+    let keys = &snapshot.gauges()[0];
+    assert_eq!(keys.name, "keys");
+    assert!(keys.value.abs_diff(1000) < 20, "{}", keys.value);
+
+    // the metadata does not change from one snapshot to the next
+    assert!(keys.metadata.is_empty());
+
+    let fill_ratio = &snapshot.gauges()[1];
+    assert_eq!(fill_ratio.name, "keys/fill_ratio");
+    assert_eq!(
+        fill_ratio.value,
+        (KEYS.fill_ratio() * 1_000_000.0).round() as i64
+    );
+    assert!(
+        fill_ratio.value > 50_000 && fill_ratio.value < 70_000,
+        "{}",
+        fill_ratio.value
+    );
+
+    // unlike a cardinality, the set is not reset by snapshots
+    let snapshot = snapshotter.snapshot();
+    assert_eq!(snapshot.gauges()[0].value, keys.value);
+    assert_eq!(snapshot.gauges()[1].value, fill_ratio.value);
+}
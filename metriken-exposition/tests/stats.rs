@@ -0,0 +1,35 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, Stats};
+use metriken_exposition::Snapshotter;
+
+#[metric(name = "stats", description = "some stats")]
+static STATS: Stats = Stats::new();
+
+#[test]
+fn stats_reset_each_snapshot() {
+    let snapshotter = Snapshotter::default();
+
+    for value in [5, 2, 9] {
+        STATS.record(value);
+    }
+
+    let snapshot = snapshotter.snapshot();
+    let stats = &snapshot.stats()[0];
+    assert_eq!(stats.name, "stats");
+    assert_eq!(stats.min, Some(2));
+    assert_eq!(stats.max, Some(9));
+    assert_eq!(stats.sum, 16);
+    assert_eq!(stats.count, 3);
+    assert_eq!(
+        stats.metadata.get("description").map(|v| v.as_str()),
+        Some("some stats")
+    );
+
+    let snapshot = snapshotter.snapshot();
+    let stats = &snapshot.stats()[0];
+    assert_eq!(stats.min, None);
+    assert_eq!(stats.max, None);
+    assert_eq!(stats.sum, 0);
+    assert_eq!(stats.count, 0);
+}
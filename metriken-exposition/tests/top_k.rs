@@ -0,0 +1,32 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, TopK};
+use metriken_exposition::{HeavyHitter, Snapshotter};
+
+#[metric(name = "hot_keys", description = "hottest keys")]
+static HOT_KEYS: TopK = TopK::new(2);
+
+#[test]
+fn top_k_resets_each_snapshot() {
+    let snapshotter = Snapshotter::default();
+
+    HOT_KEYS.add("a", 5);
+    HOT_KEYS.add("b", 2);
+    HOT_KEYS.add("c", 1);
+
+    let snapshot = snapshotter.snapshot();
+    let hot_keys = &snapshot.top_k()[0];
+    assert_eq!(hot_keys.name, "hot_keys");
+    assert_eq!(hot_keys.k, 2);
+    assert_eq!(
+        hot_keys.entries,
+        [HeavyHitter::new("a", 5, 0), HeavyHitter::new("c", 3, 2)]
+    );
+    assert_eq!(
+        hot_keys.metadata.get("description").map(|v| v.as_str()),
+        Some("hottest keys")
+    );
+
+    let snapshot = snapshotter.snapshot();
+    assert!(snapshot.top_k()[0].entries.is_empty());
+}
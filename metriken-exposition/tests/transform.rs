@@ -0,0 +1,59 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, Counter, Gauge};
+use metriken_exposition::{MetricMut, SnapshotterBuilder};
+
+#[metric(name = "transform/ticks")]
+static TICKS: Counter = Counter::new();
+
+#[metric(name = "transform/masked")]
+static MASKED: Gauge = Gauge::new();
+
+#[metric(name = "transform/untouched")]
+static UNTOUCHED: Counter = Counter::new();
+
+#[test]
+fn transforms() {
+    TICKS.add(3);
+    MASKED.set(0x1234);
+    UNTOUCHED.add(3);
+
+    let snapshotter = SnapshotterBuilder::new()
+        .filter(|entry| entry.name().starts_with("transform/"))
+        .transform(
+            |entry| entry.name() == "transform/ticks",
+            |metric| {
+                if let MetricMut::Counter(counter) = metric {
+                    counter.value *= 10;
+                    counter
+                        .metadata
+                        .insert("unit".to_string(), "nanoseconds".to_string());
+                }
+            },
+        )
+        .transform(
+            |entry| entry.name().ends_with("/masked"),
+            |metric| {
+                if let MetricMut::Gauge(gauge) = metric {
+                    gauge.value &= !0xff;
+                }
+            },
+        )
+        .build();
+
+    let snapshot = snapshotter.snapshot();
+    let counter = |name: &str| {
+        snapshot
+            .counters()
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap()
+            .clone()
+    };
+
+    let ticks = counter("transform/ticks");
+    assert_eq!(ticks.value, 30);
+    assert_eq!(ticks.unit(), Some(metriken::Unit::Nanoseconds));
+    assert_eq!(counter("transform/untouched").value, 3);
+    assert_eq!(snapshot.gauges()[0].value, 0x1200);
+}
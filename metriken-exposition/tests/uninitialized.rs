@@ -0,0 +1,50 @@
+#![cfg(feature = "snapshotter")]
+
+use metriken::{metric, Counter, Gauge, LazyCounter, LazyGauge};
+use metriken_exposition::{Snapshot, SnapshotterBuilder, UninitializedPolicy};
+
+#[metric(name = "lazy/counter", description = "a lazy counter")]
+static COUNTER: LazyCounter = LazyCounter::new(Counter::default);
+
+#[metric(name = "lazy/gauge")]
+static GAUGE: LazyGauge = LazyGauge::new(Gauge::default);
+
+fn lazy_counter(snapshot: &Snapshot) -> &metriken_exposition::Counter {
+    snapshot
+        .counters()
+        .iter()
+        .find(|c| c.name == "lazy/counter")
+        .unwrap()
+}
+
+#[test]
+fn uninitialized() {
+    let omitted = SnapshotterBuilder::new().build().snapshot();
+    assert!(omitted.counters().is_empty());
+    assert!(omitted.gauges().is_empty());
+
+    let marked = SnapshotterBuilder::new()
+        .uninitialized(UninitializedPolicy::Mark)
+        .build();
+
+    let snapshot = marked.snapshot();
+    let counter = lazy_counter(&snapshot);
+    assert_eq!(counter.value, 0);
+    assert_eq!(
+        counter.metadata.get("uninitialized").map(|v| v.as_str()),
+        Some("true")
+    );
+    assert_eq!(
+        counter.metadata.get("description").map(|v| v.as_str()),
+        Some("a lazy counter")
+    );
+    assert_eq!(snapshot.gauges()[0].name, "lazy/gauge");
+
+    COUNTER.increment();
+
+    let snapshot = marked.snapshot();
+    let counter = lazy_counter(&snapshot);
+    assert_eq!(counter.value, 1);
+    assert!(!counter.metadata.contains_key("uninitialized"));
+    assert!(snapshot.gauges()[0].metadata.contains_key("uninitialized"));
+}
@@ -0,0 +1,201 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::any::Any;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{DynBoxedMetric, Metric, MetricBuilder, Unit, Value};
+
+/// A global allocator which counts the allocations made through another
+/// allocator, such as the [`System`] allocator.
+///
+/// The counts are kept in [`AllocatorStats`] and can be registered as
+/// metriken metrics with [`CountingAllocator::register`], so that memory use
+/// is recorded in every snapshot without instrumenting the application.
+/// Counting adds a few relaxed atomic operations to each allocation.
+///
+/// # Example
+/// ```
+/// use metriken::CountingAllocator;
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: CountingAllocator<System> = CountingAllocator::new(System);
+///
+/// let metrics = ALLOCATOR.register();
+///
+/// let buffer = vec![0u8; 4096];
+/// assert!(ALLOCATOR.stats().allocated() >= 4096);
+/// # drop(buffer);
+/// # drop(metrics);
+/// ```
+pub struct CountingAllocator<A = System> {
+    inner: A,
+    stats: AllocatorStats,
+}
+
+/// Counts of the allocations made through a [`CountingAllocator`].
+#[derive(Debug, Default)]
+pub struct AllocatorStats {
+    allocated: AtomicU64,
+    allocated_total: AtomicU64,
+    allocations: AtomicU64,
+    deallocations: AtomicU64,
+}
+
+impl AllocatorStats {
+    const fn new() -> Self {
+        Self {
+            allocated: AtomicU64::new(0),
+            allocated_total: AtomicU64::new(0),
+            allocations: AtomicU64::new(0),
+            deallocations: AtomicU64::new(0),
+        }
+    }
+
+    /// The number of bytes currently allocated.
+    pub fn allocated(&self) -> u64 {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    /// The total number of bytes which have ever been allocated.
+    pub fn allocated_total(&self) -> u64 {
+        self.allocated_total.load(Ordering::Relaxed)
+    }
+
+    /// The number of allocations made. Reallocations are counted as both an
+    /// allocation and a deallocation.
+    pub fn allocations(&self) -> u64 {
+        self.allocations.load(Ordering::Relaxed)
+    }
+
+    /// The number of deallocations made.
+    pub fn deallocations(&self) -> u64 {
+        self.deallocations.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn alloc(&self, size: usize) {
+        self.allocated.fetch_add(size as u64, Ordering::Relaxed);
+        self.allocated_total
+            .fetch_add(size as u64, Ordering::Relaxed);
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn dealloc(&self, size: usize) {
+        self.allocated.fetch_sub(size as u64, Ordering::Relaxed);
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<A> CountingAllocator<A> {
+    /// Count the allocations made through the provided allocator.
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            stats: AllocatorStats::new(),
+        }
+    }
+
+    /// The counts of the allocations made so far.
+    pub fn stats(&self) -> &AllocatorStats {
+        &self.stats
+    }
+}
+
+impl<A: Sync + 'static> CountingAllocator<A> {
+    /// Register the allocator statistics as dynamic metrics:
+    ///
+    /// - `allocator/allocated`: a gauge of the bytes currently allocated
+    /// - `allocator/allocated_total`: a counter of all the bytes allocated
+    /// - `allocator/allocations`: a counter of the allocations made
+    /// - `allocator/deallocations`: a counter of the deallocations made
+    ///
+    /// The metrics are unregistered when the returned value is dropped.
+    pub fn register(&'static self) -> AllocatorMetrics {
+        let stats = &self.stats;
+        let metric = |name: &'static str, reading: Reading| {
+            let builder = MetricBuilder::new(name);
+            let builder = match reading {
+                Reading::Allocations | Reading::Deallocations => builder,
+                _ => builder.unit(Unit::Bytes),
+            };
+            builder.build(AllocatorMetric { stats, reading })
+        };
+
+        AllocatorMetrics {
+            _metrics: vec![
+                metric("allocator/allocated", Reading::Allocated),
+                metric("allocator/allocated_total", Reading::AllocatedTotal),
+                metric("allocator/allocations", Reading::Allocations),
+                metric("allocator/deallocations", Reading::Deallocations),
+            ],
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            self.stats.alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            self.stats.alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        self.stats.dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = self.inner.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            self.stats.dealloc(layout.size());
+            self.stats.alloc(new_size);
+        }
+        new
+    }
+}
+
+/// The metrics registered by [`CountingAllocator::register`]. They are
+/// unregistered when this is dropped.
+pub struct AllocatorMetrics {
+    _metrics: Vec<DynBoxedMetric<AllocatorMetric>>,
+}
+
+#[derive(Clone, Copy)]
+enum Reading {
+    Allocated,
+    AllocatedTotal,
+    Allocations,
+    Deallocations,
+}
+
+/// A single reading of the statistics of an allocator.
+struct AllocatorMetric {
+    stats: &'static AllocatorStats,
+    reading: Reading,
+}
+
+impl Metric for AllocatorMetric {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(match self.reading {
+            Reading::Allocated => Value::Gauge(self.stats.allocated() as i64),
+            Reading::AllocatedTotal => Value::Counter(self.stats.allocated_total()),
+            Reading::Allocations => Value::Counter(self.stats.allocations()),
+            Reading::Deallocations => Value::Counter(self.stats.deallocations()),
+        })
+    }
+}
@@ -0,0 +1,145 @@
+use parking_lot::Mutex;
+
+use crate::{DynBoxedMetric, Gauge, MetricBuilder};
+
+/// The registered `build_info` gauge. Registering it again replaces it, so
+/// there is only ever one.
+static BUILD_INFO: Mutex<Option<DynBoxedMetric<Gauge>>> = Mutex::new(None);
+
+/// Describes the build of an application, registered as a `build_info` gauge
+/// with a value of `1` and the details of the build in its metadata.
+///
+/// Recording the gauge alongside the other metrics makes the versions running
+/// across a fleet visible, following the Prometheus convention. The gauge has
+/// the following metadata keys, where the optional ones are only present when
+/// they were provided:
+///
+/// - `name` and `version`: the name and version of the crate
+/// - `git_sha`: the commit the application was built from
+/// - `rustc_version`: the version of the compiler used
+/// - `features`: the enabled cargo features, separated by commas
+/// - `target`: the target triple the application was built for
+///
+/// The [`crate::build_info!`] macro fills these in from the environment of
+/// the crate it is called from.
+///
+/// ```
+/// metriken::BuildInfo::new("server", "1.2.0")
+///     .git_sha("4b825dc")
+///     .features(["tls", "metrics"])
+///     .register();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildInfo {
+    name: String,
+    version: String,
+    git_sha: Option<String>,
+    rustc_version: Option<String>,
+    features: Option<String>,
+    target: Option<String>,
+}
+
+impl BuildInfo {
+    /// Describe a build of the named crate at the provided version.
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: version.into(),
+            git_sha: None,
+            rustc_version: None,
+            features: None,
+            target: None,
+        }
+    }
+
+    /// The commit the application was built from.
+    pub fn git_sha(mut self, sha: impl Into<String>) -> Self {
+        self.git_sha = Some(sha.into());
+        self
+    }
+
+    /// The version of the compiler the application was built with.
+    pub fn rustc_version(mut self, version: impl Into<String>) -> Self {
+        self.rustc_version = Some(version.into());
+        self
+    }
+
+    /// The cargo features the application was built with.
+    pub fn features<T: AsRef<str>>(mut self, features: impl IntoIterator<Item = T>) -> Self {
+        let features: Vec<_> = features
+            .into_iter()
+            .map(|feature| feature.as_ref().to_string())
+            .collect();
+        self.features = Some(features.join(","));
+        self
+    }
+
+    /// The target triple the application was built for.
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Register the `build_info` gauge, replacing any registered before. The
+    /// gauge stays registered for the life of the process.
+    pub fn register(self) {
+        let mut builder = MetricBuilder::new("build_info")
+            .description("the build of the application, with a value of 1")
+            .metadata("name", self.name)
+            .metadata("version", self.version);
+
+        let optional = [
+            ("git_sha", self.git_sha),
+            ("rustc_version", self.rustc_version),
+            ("features", self.features),
+            ("target", self.target),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                builder = builder.metadata(key, value);
+            }
+        }
+
+        let gauge = builder.build(Gauge::new());
+        gauge.set(1);
+
+        // the previous gauge is dropped, unregistering it, after the lock is
+        // released
+        let previous = BUILD_INFO.lock().replace(gauge);
+        drop(previous);
+    }
+}
+
+/// Creates a [`BuildInfo`] for the crate this is called from.
+///
+/// The name and version come from cargo. The optional details are read, at
+/// compile time, from environment variables, which a build script can set
+/// with `cargo:rustc-env=<NAME>=<VALUE>`:
+///
+/// - `GIT_SHA` for the commit
+/// - `RUSTC_VERSION` for the compiler version
+/// - `CARGO_FEATURES` for the enabled features, separated by commas
+/// - `TARGET` for the target triple
+///
+/// ```
+/// metriken::build_info!().register();
+/// ```
+#[macro_export]
+macro_rules! build_info {
+    () => {{
+        let mut info = $crate::BuildInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        if let Some(sha) = option_env!("GIT_SHA") {
+            info = info.git_sha(sha);
+        }
+        if let Some(version) = option_env!("RUSTC_VERSION") {
+            info = info.rustc_version(version);
+        }
+        if let Some(features) = option_env!("CARGO_FEATURES") {
+            info = info.features(features.split(',').filter(|f| !f.is_empty()));
+        }
+        if let Some(target) = option_env!("TARGET") {
+            info = info.target(target);
+        }
+        info
+    }};
+}
@@ -0,0 +1,211 @@
+use std::any::Any;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::{Metric, Value};
+
+/// The number of bits of each hash which select a register of a
+/// [`Cardinality`].
+pub const CARDINALITY_PRECISION: u8 = 12;
+
+const REGISTERS: usize = 1 << CARDINALITY_PRECISION;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicU8 = AtomicU8::new(0);
+
+/// Estimates the number of distinct values recorded since it was last
+/// drained, such as the unique clients seen in an interval, in a fixed 4KiB
+/// of memory.
+///
+/// This is a HyperLogLog with 4096 registers, which gives estimates with a
+/// standard error of about 1.6%. The snapshotter drains it on every snapshot,
+/// so each snapshot reports the distinct values for the interval since the
+/// previous one. The registers are included in the snapshot along with the
+/// estimate, so the values seen by several processes can be counted by
+/// merging their registers.
+///
+/// Values are hashed with a fixed hash function, so the same value sets the
+/// same register in every process. Values are fed to the hash with their
+/// [`Hash`] implementation, which for integers depends on the byte order of
+/// the machine. Use [`Cardinality::insert_hash`] to supply hashes directly.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, Cardinality};
+/// #[metric(name = "clients")]
+/// static CLIENTS: Cardinality = Cardinality::new();
+///
+/// fn handle_request(client: &str) {
+///     CLIENTS.insert(client);
+///     // ...
+/// }
+/// # handle_request("10.0.0.1");
+/// # handle_request("10.0.0.1");
+/// # assert_eq!(CLIENTS.estimate(), 1);
+/// ```
+pub struct Cardinality {
+    registers: [AtomicU8; REGISTERS],
+}
+
+impl Cardinality {
+    /// Create a new `Cardinality` with no recorded values.
+    pub const fn new() -> Self {
+        Self {
+            registers: [EMPTY; REGISTERS],
+        }
+    }
+
+    /// Record a value.
+    #[inline]
+    pub fn insert<T: Hash + ?Sized>(&self, value: &T) {
+        self.insert_hash(fixed_hash(value));
+    }
+
+    /// Record a value by its 64-bit hash, which should be uniformly
+    /// distributed.
+    #[inline]
+    pub fn insert_hash(&self, hash: u64) {
+        let index = (hash >> (64 - CARDINALITY_PRECISION)) as usize;
+        // the position of the first set bit in the remaining bits, with a
+        // sentinel bit so that a hash of zero still has one
+        let rest = (hash << CARDINALITY_PRECISION) | (1 << (CARDINALITY_PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index].fetch_max(rank, Ordering::Relaxed);
+    }
+
+    /// Estimate the number of distinct values recorded.
+    pub fn estimate(&self) -> u64 {
+        estimate(&self.load())
+    }
+
+    /// Read the registers without resetting them.
+    pub fn load(&self) -> Vec<u8> {
+        self.registers
+            .iter()
+            .map(|r| r.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Read the registers and reset them.
+    ///
+    /// Each register is reset independently, so a value recorded while the
+    /// metric is being drained may be counted in this interval, the next, or
+    /// both.
+    pub fn drain(&self) -> Vec<u8> {
+        self.registers
+            .iter()
+            .map(|r| r.swap(0, Ordering::Relaxed))
+            .collect()
+    }
+}
+
+impl Default for Cardinality {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Cardinality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cardinality")
+            .field("estimate", &self.estimate())
+            .finish()
+    }
+}
+
+impl Metric for Cardinality {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Other(self))
+    }
+}
+
+/// The HyperLogLog estimate for a set of registers, using linear counting
+/// for small cardinalities.
+fn estimate(registers: &[u8]) -> u64 {
+    let m = registers.len() as f64;
+    let alpha = 0.7213 / (1.0 + 1.079 / m);
+
+    let sum: f64 = registers.iter().map(|r| 2.0_f64.powi(-(*r as i32))).sum();
+    let raw = alpha * m * m / sum;
+
+    let zeros = registers.iter().filter(|r| **r == 0).count();
+    let estimate = if raw <= 2.5 * m && zeros > 0 {
+        m * (m / zeros as f64).ln()
+    } else {
+        raw
+    };
+    estimate.round() as u64
+}
+
+/// A hash which is the same in every process and release, unlike the hasher
+/// of the standard library. Input is mixed eight bytes at a time with the
+/// finalizer of splitmix64, so every bit of the hash depends on every bit of
+/// the input.
+#[derive(Default)]
+struct FixedHasher(u64);
+
+/// Hash a value with the [`FixedHasher`].
+pub(crate) fn fixed_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = FixedHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl Hasher for FixedHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let state = self.0.wrapping_add(0x9e3779b97f4a7c15 ^ chunk.len() as u64);
+            self.0 = mix(state ^ u64::from_le_bytes(word));
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        mix(self.0.wrapping_add(0x9e3779b97f4a7c15))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accuracy() {
+        for n in [0, 1, 100, 10_000, 1_000_000] {
+            let cardinality = Cardinality::new();
+            for value in 0..n {
+                cardinality.insert(&value);
+                // repeats don't change the estimate
+                cardinality.insert(&value);
+            }
+            let estimate = cardinality.estimate() as f64;
+            assert!(
+                (estimate - n as f64).abs() <= n as f64 * 0.05,
+                "{n}: {estimate}"
+            );
+        }
+    }
+
+    #[test]
+    fn drain() {
+        let cardinality = Cardinality::new();
+        cardinality.insert("a");
+        cardinality.insert("b");
+        assert_eq!(cardinality.load().len(), 4096);
+
+        let registers = cardinality.drain();
+        assert_eq!(estimate(&registers), 2);
+        assert_eq!(cardinality.estimate(), 0);
+    }
+}
@@ -25,8 +25,20 @@ use crate::{Metric, Value};
 /// }
 /// # a_method();
 /// ```
+///
+/// # Contention
+/// With the `contention` feature enabled, additions are performed with a
+/// compare-and-swap loop and every failed attempt is counted. The count is
+/// available from [`Counter::contention`] and is reported in snapshots, which
+/// helps to find counters that are hot enough to need a [`PaddedCounter`] or
+/// a [`ShardedCounter`](crate::ShardedCounter). This makes every update
+/// slower and is intended for auditing rather than for production builds.
 #[derive(Default, Debug)]
-pub struct Counter(AtomicU64);
+pub struct Counter {
+    value: AtomicU64,
+    #[cfg(feature = "contention")]
+    contention: AtomicU64,
+}
 
 impl Counter {
     /// Create a counter initialized to 0.
@@ -36,7 +48,11 @@ impl Counter {
 
     /// Create a counter initialized to `value`.
     pub const fn with_value(value: u64) -> Self {
-        Self(AtomicU64::new(value))
+        Self {
+            value: AtomicU64::new(value),
+            #[cfg(feature = "contention")]
+            contention: AtomicU64::new(0),
+        }
     }
 
     #[inline]
@@ -44,19 +60,48 @@ impl Counter {
         self.add(1)
     }
 
+    #[cfg(not(feature = "contention"))]
     #[inline]
     pub fn add(&self, value: u64) -> u64 {
-        self.0.fetch_add(value, Ordering::Relaxed)
+        self.value.fetch_add(value, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "contention")]
+    #[inline]
+    pub fn add(&self, value: u64) -> u64 {
+        let mut current = self.value.load(Ordering::Relaxed);
+        loop {
+            match self.value.compare_exchange_weak(
+                current,
+                current.wrapping_add(value),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(previous) => return previous,
+                Err(actual) => {
+                    current = actual;
+                    self.contention.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// The number of times an update to this counter had to be retried
+    /// because another thread updated it concurrently.
+    #[cfg(feature = "contention")]
+    #[inline]
+    pub fn contention(&self) -> u64 {
+        self.contention.load(Ordering::Relaxed)
     }
 
     #[inline]
     pub fn value(&self) -> u64 {
-        self.0.load(Ordering::Relaxed)
+        self.value.load(Ordering::Relaxed)
     }
 
     #[inline]
     pub fn set(&self, value: u64) -> u64 {
-        self.0.swap(value, Ordering::Relaxed)
+        self.value.swap(value, Ordering::Relaxed)
     }
 
     #[inline]
@@ -70,7 +115,124 @@ impl Metric for Counter {
         Some(self)
     }
 
-    fn value(&self) -> Option<Value> {
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Counter(self.value()))
+    }
+}
+
+/// A [`Counter`] which occupies its own cache lines.
+///
+/// Statics are often placed next to each other in memory, so a very hot
+/// counter can slow down updates to unrelated metrics which share its cache
+/// line. Padding the counter out to 128 bytes, which covers the adjacent line
+/// prefetching done by many CPUs, prevents this false sharing. It does not
+/// reduce contention between threads updating the counter itself.
+///
+/// A `PaddedCounter` dereferences to a [`Counter`] and is reported in the same
+/// way.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, PaddedCounter};
+/// #[metric(name = "hot.counter")]
+/// static HOT: PaddedCounter = PaddedCounter::new();
+///
+/// HOT.increment();
+/// # assert_eq!(HOT.value(), 1);
+/// ```
+#[derive(Default, Debug)]
+#[repr(align(128))]
+pub struct PaddedCounter(Counter);
+
+impl PaddedCounter {
+    /// Create a padded counter initialized to 0.
+    pub const fn new() -> Self {
+        Self(Counter::new())
+    }
+}
+
+impl std::ops::Deref for PaddedCounter {
+    type Target = Counter;
+
+    fn deref(&self) -> &Counter {
+        &self.0
+    }
+}
+
+impl Metric for PaddedCounter {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Counter(self.0.value()))
+    }
+}
+
+/// A counter that is reset every time it is snapshotted.
+///
+/// This behaves like a [`Counter`] except that the snapshotter reads it by
+/// atomically swapping its value with zero. Each snapshot therefore contains
+/// the number of events during the interval since the previous snapshot
+/// rather than a running total. No increments are lost or double counted.
+///
+/// Since reading the counter resets it, using more than one snapshotter with
+/// an `IntervalCounter` will split the events between them.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, IntervalCounter};
+/// #[metric(name = "requests/interval")]
+/// static REQUESTS: IntervalCounter = IntervalCounter::new();
+///
+/// fn handle_request() {
+///     REQUESTS.increment();
+///     // ...
+/// }
+/// # handle_request();
+/// ```
+#[derive(Default, Debug)]
+pub struct IntervalCounter(AtomicU64);
+
+impl IntervalCounter {
+    /// Create an interval counter initialized to 0.
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    #[inline]
+    pub fn increment(&self) -> u64 {
+        self.add(1)
+    }
+
+    #[inline]
+    pub fn add(&self, value: u64) -> u64 {
+        self.0.fetch_add(value, Ordering::Relaxed)
+    }
+
+    /// Read the count accumulated since the last call to [`take`] without
+    /// resetting it.
+    ///
+    /// [`take`]: IntervalCounter::take
+    #[inline]
+    pub fn value(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Read the accumulated count and reset it to zero as a single atomic
+    /// operation.
+    #[inline]
+    pub fn take(&self) -> u64 {
+        self.0.swap(0, Ordering::Relaxed)
+    }
+}
+
+impl Metric for IntervalCounter {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
         Some(Value::Counter(self.value()))
     }
 }
@@ -0,0 +1,224 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::{Metric, Value};
+
+/// The interval at which the average is updated.
+const TICK: Duration = Duration::from_secs(5);
+
+/// An exponentially weighted moving average of the rate of events, in events
+/// per second.
+///
+/// This is the same construct as the 1/5/15 minute load averages or the
+/// meters found in other metrics libraries. Recording an event only touches a
+/// single atomic counter. The smoothed rate is updated on a fixed cadence of
+/// five seconds, which is caught up on whenever the metric is read, so the
+/// average does not depend on how often, or by how many readers, it is read.
+///
+/// The first read starts the clock and the first update seeds the average
+/// with the observed rate. From then on each update moves the average towards
+/// the observed rate. The events recorded since the last update are spread
+/// evenly over the updates which are caught up on together.
+///
+/// In snapshots the rate is reported as a gauge in milli-events per second,
+/// so that slow rates, such as those of 5 and 15 minute averages, are not
+/// rounded to zero.
+///
+/// # Example
+/// ```
+/// # use std::time::Duration;
+/// # use metriken::{metric, Ewma};
+/// #[metric(name = "requests/rate/1m")]
+/// static REQUEST_RATE_1M: Ewma = Ewma::new(Duration::from_secs(60));
+///
+/// #[metric(name = "requests/rate/5m")]
+/// static REQUEST_RATE_5M: Ewma = Ewma::new(Duration::from_secs(300));
+///
+/// fn handle_request() {
+///     REQUEST_RATE_1M.mark();
+///     REQUEST_RATE_5M.mark();
+///     // ...
+/// }
+/// # handle_request();
+/// ```
+pub struct Ewma {
+    window: Duration,
+    pending: AtomicU64,
+    state: Mutex<State>,
+}
+
+enum State {
+    /// The metric has never been read.
+    Idle,
+    /// The clock has started but there has been no update yet.
+    Started { last: Instant },
+    /// The average has been seeded and is being updated on each tick.
+    Running { last: Instant, rate: f64 },
+}
+
+impl Ewma {
+    /// Create a new moving average whose time constant is `window`.
+    ///
+    /// After one `window` has passed, a change in the event rate will be
+    /// ~63% reflected in the average.
+    ///
+    /// # Panics
+    /// This will panic if `window` is zero.
+    pub const fn new(window: Duration) -> Self {
+        if window.is_zero() {
+            panic!("ewma window must be non-zero");
+        }
+
+        Self {
+            window,
+            pending: AtomicU64::new(0),
+            state: parking_lot::const_mutex(State::Idle),
+        }
+    }
+
+    /// The time constant of this moving average.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Record a single event.
+    #[inline]
+    pub fn mark(&self) {
+        self.add(1)
+    }
+
+    /// Record `count` events.
+    #[inline]
+    pub fn add(&self, count: u64) {
+        self.pending.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// The smoothed rate in events per second, after catching up on any
+    /// updates which are due.
+    ///
+    /// Returns `None` until the first update, one tick after the metric was
+    /// first read.
+    pub fn rate(&self) -> Option<f64> {
+        self.tick(Instant::now())
+    }
+
+    fn tick(&self, now: Instant) -> Option<f64> {
+        let mut state = self.state.lock();
+
+        let (last, rate) = match *state {
+            State::Idle => {
+                // Events recorded before the clock started cannot be assigned
+                // to an interval so they are discarded.
+                self.pending.swap(0, Ordering::Relaxed);
+                *state = State::Started { last: now };
+                return None;
+            }
+            State::Started { last } => (last, None),
+            State::Running { last, rate } => (last, Some(rate)),
+        };
+
+        let ticks = now.saturating_duration_since(last).as_nanos() / TICK.as_nanos();
+        if ticks == 0 {
+            return rate;
+        }
+
+        // Catching up on several ticks at once with the average rate over
+        // all of them is the same as updating on each of them in turn.
+        let elapsed = TICK.as_secs_f64() * ticks as f64;
+        let count = self.pending.swap(0, Ordering::Relaxed);
+        let instant = count as f64 / elapsed;
+
+        let rate = match rate {
+            Some(rate) => {
+                let alpha = 1.0 - (-elapsed / self.window.as_secs_f64()).exp();
+                rate + alpha * (instant - rate)
+            }
+            None => instant,
+        };
+
+        let last = last + TICK * ticks as u32;
+        *state = State::Running { last, rate };
+        Some(rate)
+    }
+}
+
+impl Metric for Ewma {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        let rate = self.rate().unwrap_or(0.0);
+        Some(Value::Gauge((rate * 1000.0).round() as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_then_smooths() {
+        let ewma = Ewma::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        ewma.add(100);
+        assert_eq!(ewma.tick(start), None);
+
+        ewma.add(600);
+        let seeded = ewma.tick(start + Duration::from_secs(60)).unwrap();
+        assert_eq!(seeded, 10.0);
+
+        // After one window with no events the rate decays by a factor of e.
+        let decayed = ewma.tick(start + Duration::from_secs(120)).unwrap();
+        assert!((decayed - 10.0 / std::f64::consts::E).abs() < 1e-9);
+    }
+
+    #[test]
+    fn readers_do_not_interfere() {
+        let a = Ewma::new(Duration::from_secs(10));
+        let b = Ewma::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        for ewma in [&a, &b] {
+            ewma.tick(start);
+            ewma.add(250);
+            ewma.tick(start + TICK);
+        }
+
+        // A constant rate of 50/s read once per tick, or read many times
+        // between ticks, produces the same average.
+        for tick in 2..=4 {
+            a.add(250);
+            a.tick(start + TICK * tick);
+        }
+
+        for tick in 2..=4 {
+            for millis in (0..5000).step_by(700) {
+                b.tick(start + TICK * (tick - 1) + Duration::from_millis(millis));
+            }
+            b.add(250);
+            b.tick(start + TICK * tick);
+        }
+
+        let a = a.tick(start + TICK * 4).unwrap();
+        let b = b.tick(start + TICK * 4 + Duration::from_secs(1)).unwrap();
+        assert!((a - b).abs() < 1e-9);
+        assert!((a - 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn slow_rates() {
+        let ewma = Ewma::new(Duration::from_secs(900));
+        *ewma.state.lock() = State::Running {
+            last: Instant::now(),
+            rate: 0.1,
+        };
+
+        // 0.1 events per second is reported as 100 milli-events per second
+        assert!(matches!(ewma.value(), Some(Value::Gauge(100))));
+    }
+}
@@ -3,6 +3,8 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::any::Any;
+#[cfg(feature = "contention")]
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::{AtomicI64, Ordering};
 
 use crate::{Metric, Value};
@@ -26,8 +28,15 @@ use crate::{Metric, Value};
 /// }
 /// # a_method();
 /// ```
+///
+/// With the `contention` feature enabled, failed attempts to update the gauge
+/// are counted in the same way as for a [`Counter`](crate::Counter).
 #[derive(Default, Debug)]
-pub struct Gauge(AtomicI64);
+pub struct Gauge {
+    value: AtomicI64,
+    #[cfg(feature = "contention")]
+    contention: AtomicU64,
+}
 
 impl Gauge {
     /// Create a new guage with the default value of 0.
@@ -37,7 +46,11 @@ impl Gauge {
 
     /// Create a new guage with the provided initial value.
     pub const fn with_value(value: i64) -> Self {
-        Self(AtomicI64::new(value))
+        Self {
+            value: AtomicI64::new(value),
+            #[cfg(feature = "contention")]
+            contention: AtomicU64::new(0),
+        }
     }
 
     /// Increment the value of this gauge by 1.
@@ -59,9 +72,30 @@ impl Gauge {
     /// Increase the value of this gauge by `value`.
     ///
     /// Returns the od value of the gauge.
+    #[cfg(not(feature = "contention"))]
+    #[inline]
+    pub fn add(&self, value: i64) -> i64 {
+        self.value.fetch_add(value, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "contention")]
     #[inline]
     pub fn add(&self, value: i64) -> i64 {
-        self.0.fetch_add(value, Ordering::Relaxed)
+        let mut current = self.value.load(Ordering::Relaxed);
+        loop {
+            match self.value.compare_exchange_weak(
+                current,
+                current.wrapping_add(value),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(previous) => return previous,
+                Err(actual) => {
+                    current = actual;
+                    self.contention.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
     }
 
     /// Decrease the value of this gauge by `value`.
@@ -69,17 +103,25 @@ impl Gauge {
     /// Returns the od value of the gauge.
     #[inline]
     pub fn sub(&self, value: i64) -> i64 {
-        self.0.fetch_sub(value, Ordering::Relaxed)
+        self.add(value.wrapping_neg())
+    }
+
+    /// The number of times an update to this gauge had to be retried because
+    /// another thread updated it concurrently.
+    #[cfg(feature = "contention")]
+    #[inline]
+    pub fn contention(&self) -> u64 {
+        self.contention.load(Ordering::Relaxed)
     }
 
     #[inline]
     pub fn value(&self) -> i64 {
-        self.0.load(Ordering::Relaxed)
+        self.value.load(Ordering::Relaxed)
     }
 
     #[inline]
     pub fn set(&self, value: i64) -> i64 {
-        self.0.swap(value, Ordering::Relaxed)
+        self.value.swap(value, Ordering::Relaxed)
     }
 
     #[inline]
@@ -93,7 +135,192 @@ impl Metric for Gauge {
         Some(self)
     }
 
-    fn value(&self) -> Option<Value> {
+    fn value(&self) -> Option<Value<'_>> {
         Some(Value::Gauge(self.value()))
     }
 }
+
+/// A gauge which also tracks the highest, or lowest, value it held since it
+/// was last snapshotted, such as the peak depth of a queue.
+///
+/// A gauge only shows the value at the moment it is read, so a queue which
+/// fills up and drains between two snapshots looks empty in both. A
+/// `PeakGauge` is updated like a [`Gauge`], and every update also moves the
+/// peak with an atomic max, or min. Snapshots report the peak, after which
+/// it starts over from the current value, so a snapshot taken while nothing
+/// is changing reports the current value.
+///
+/// Snapshots include the `aggregation` metadata key, set to `max` or `min`,
+/// so that the peak is kept when snapshots are downsampled.
+///
+/// Since reading the peak resets it, using more than one snapshotter with a
+/// `PeakGauge` will split the peaks between them.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, PeakGauge};
+/// #[metric(name = "queue/depth")]
+/// static QUEUE_DEPTH: PeakGauge = PeakGauge::max();
+///
+/// fn enqueue() {
+///     QUEUE_DEPTH.increment();
+/// }
+///
+/// fn dequeue() {
+///     QUEUE_DEPTH.decrement();
+/// }
+/// # enqueue();
+/// # enqueue();
+/// # dequeue();
+/// # assert_eq!(QUEUE_DEPTH.value(), 1);
+/// # assert_eq!(QUEUE_DEPTH.take(), 2);
+/// # assert_eq!(QUEUE_DEPTH.take(), 1);
+/// ```
+#[derive(Debug)]
+pub struct PeakGauge {
+    value: AtomicI64,
+    peak: AtomicI64,
+    max: bool,
+}
+
+impl PeakGauge {
+    /// Create a new gauge with a value of 0 which tracks its highest value.
+    pub const fn max() -> Self {
+        Self {
+            value: AtomicI64::new(0),
+            peak: AtomicI64::new(0),
+            max: true,
+        }
+    }
+
+    /// Create a new gauge with a value of 0 which tracks its lowest value.
+    pub const fn min() -> Self {
+        Self {
+            value: AtomicI64::new(0),
+            peak: AtomicI64::new(0),
+            max: false,
+        }
+    }
+
+    /// Returns `true` if this gauge tracks its highest value, and `false`
+    /// if it tracks its lowest.
+    pub fn is_max(&self) -> bool {
+        self.max
+    }
+
+    /// Increment the value of this gauge by 1.
+    ///
+    /// Returns the old value of the gauge.
+    #[inline]
+    pub fn increment(&self) -> i64 {
+        self.add(1)
+    }
+
+    /// Decrement the value of this gauge by 1.
+    ///
+    /// Returns the old value of the gauge.
+    #[inline]
+    pub fn decrement(&self) -> i64 {
+        self.sub(1)
+    }
+
+    /// Increase the value of this gauge by `value`.
+    ///
+    /// Returns the old value of the gauge.
+    #[inline]
+    pub fn add(&self, value: i64) -> i64 {
+        let previous = self.value.fetch_add(value, Ordering::Relaxed);
+        self.observe(previous.wrapping_add(value));
+        previous
+    }
+
+    /// Decrease the value of this gauge by `value`.
+    ///
+    /// Returns the old value of the gauge.
+    #[inline]
+    pub fn sub(&self, value: i64) -> i64 {
+        self.add(value.wrapping_neg())
+    }
+
+    /// Set the value of this gauge.
+    ///
+    /// Returns the old value of the gauge.
+    #[inline]
+    pub fn set(&self, value: i64) -> i64 {
+        let previous = self.value.swap(value, Ordering::Relaxed);
+        self.observe(value);
+        previous
+    }
+
+    /// The current value of this gauge.
+    #[inline]
+    pub fn value(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    /// The peak value since the last call to [`take`], without resetting it.
+    ///
+    /// [`take`]: PeakGauge::take
+    #[inline]
+    pub fn peak(&self) -> i64 {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Read the peak value and restart it from the current value.
+    #[inline]
+    pub fn take(&self) -> i64 {
+        let peak = self.peak.swap(self.value(), Ordering::Relaxed);
+        // an update which raced with the swap may have been overwritten, so
+        // make sure the new peak includes the current value
+        self.observe(self.value());
+        peak
+    }
+
+    #[inline]
+    fn observe(&self, value: i64) {
+        if self.max {
+            self.peak.fetch_max(value, Ordering::Relaxed);
+        } else {
+            self.peak.fetch_min(value, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Metric for PeakGauge {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Gauge(self.peak()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peak() {
+        let gauge = PeakGauge::max();
+        gauge.add(5);
+        gauge.sub(3);
+        assert_eq!(gauge.value(), 2);
+        assert_eq!(gauge.peak(), 5);
+
+        // the peak starts over from the current value
+        assert_eq!(gauge.take(), 5);
+        assert_eq!(gauge.take(), 2);
+
+        gauge.set(-1);
+        assert_eq!(gauge.take(), 2);
+        assert_eq!(gauge.take(), -1);
+
+        let gauge = PeakGauge::min();
+        gauge.set(4);
+        gauge.decrement();
+        gauge.set(7);
+        assert_eq!(gauge.take(), 0);
+        assert_eq!(gauge.take(), 7);
+    }
+}
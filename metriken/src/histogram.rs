@@ -1,7 +1,9 @@
-use std::sync::OnceLock;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
 
 pub use histogram::{Bucket, Config, Error, Histogram};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
 use crate::{Metric, Value};
 
@@ -16,6 +18,7 @@ use crate::{Metric, Value};
 pub struct AtomicHistogram {
     inner: OnceLock<histogram::AtomicHistogram>,
     config: Config,
+    overflow: AtomicU64,
 }
 
 impl AtomicHistogram {
@@ -36,18 +39,39 @@ impl AtomicHistogram {
         Self {
             inner: OnceLock::new(),
             config,
+            overflow: AtomicU64::new(0),
         }
     }
 
     /// Increments the bucket for a corresponding value.
+    ///
+    /// Values greater than the maximum value of the histogram are not
+    /// recorded and are counted in [`overflow`] instead.
+    ///
+    /// [`overflow`]: AtomicHistogram::overflow
     pub fn increment(&self, value: u64) -> Result<(), Error> {
-        self.get_or_init().increment(value)
+        let histogram = self.get_or_init();
+
+        // The range is checked here rather than by the inner histogram, which
+        // panics for a value of exactly `2^max_value_power`.
+        if out_of_range(&self.config, value) {
+            self.overflow.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::OutOfRange);
+        }
+
+        histogram.increment(value)
     }
 
     pub fn config(&self) -> Config {
         self.config
     }
 
+    /// The number of values which were not recorded because they exceed the
+    /// maximum value of the histogram.
+    pub fn overflow(&self) -> u64 {
+        self.overflow.load(Ordering::Relaxed)
+    }
+
     /// Loads and returns the histogram. Returns `None` if the histogram has
     /// never been incremented.
     pub fn load(&self) -> Option<Histogram> {
@@ -65,7 +89,7 @@ impl Metric for AtomicHistogram {
         Some(self)
     }
 
-    fn value(&self) -> Option<Value> {
+    fn value(&self) -> Option<Value<'_>> {
         Some(Value::Other(self))
     }
 }
@@ -139,7 +163,230 @@ impl Metric for RwLockHistogram {
         Some(self)
     }
 
-    fn value(&self) -> Option<Value> {
+    fn value(&self) -> Option<Value<'_>> {
         Some(Value::Other(self))
     }
 }
+
+/// Hands out a unique id to each `ThreadLocalHistogram` on first use.
+static NEXT_THREAD_LOCAL_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The buffers owned by this thread, indexed by histogram id.
+    static THREAD_LOCAL_BUFFERS: RefCell<Vec<Option<Arc<Buffer>>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The buckets for one thread. Only the owning thread writes to them, so
+/// increments are a plain load and store rather than an atomic
+/// read-modify-write.
+struct Buffer {
+    buckets: Box<[AtomicU64]>,
+}
+
+impl Buffer {
+    fn new(config: &Config) -> Self {
+        Self {
+            buckets: (0..config.total_buckets())
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        }
+    }
+
+    fn add(&self, index: usize, count: u64) {
+        let bucket = &self.buckets[index];
+        bucket.store(
+            bucket.load(Ordering::Relaxed).wrapping_add(count),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+struct ThreadLocalState {
+    id: usize,
+    /// The buffer for every thread which has recorded into this histogram.
+    buffers: Mutex<Vec<Arc<Buffer>>>,
+    /// The counts from threads which have exited.
+    retired: Mutex<Histogram>,
+}
+
+/// A histogram which records into a separate buffer for each thread and
+/// merges them when it is read.
+///
+/// Recording a value does not contend with other threads and avoids atomic
+/// read-modify-write instructions, which makes it cheaper than an
+/// [`AtomicHistogram`] on very hot paths. In exchange, each thread that
+/// records a value allocates its own set of buckets and [`load`] must sum
+/// the buckets of every thread.
+///
+/// The counts recorded by a thread are kept after it exits and are folded
+/// into a single set of buckets the next time the histogram is loaded.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, ThreadLocalHistogram};
+/// #[metric(name = "request/latency")]
+/// static LATENCY: ThreadLocalHistogram = ThreadLocalHistogram::new(7, 64);
+///
+/// LATENCY.increment(1500).unwrap();
+/// # assert_eq!(LATENCY.load().unwrap().as_slice().iter().sum::<u64>(), 1);
+/// ```
+///
+/// [`load`]: ThreadLocalHistogram::load
+pub struct ThreadLocalHistogram {
+    state: OnceLock<ThreadLocalState>,
+    config: Config,
+    overflow: AtomicU64,
+}
+
+impl ThreadLocalHistogram {
+    /// Create a new histogram with the given parameters.
+    ///
+    /// # Panics
+    /// This will panic if the `grouping_power` and `max_value_power` do not
+    /// adhere to the following constraints:
+    ///
+    /// - `max_value_power` must be in the range 1..=64
+    /// - `grouping_power` must be in the range `0..=(max_value_power - 1)`
+    pub const fn new(grouping_power: u8, max_value_power: u8) -> Self {
+        let config = match ::histogram::Config::new(grouping_power, max_value_power) {
+            Ok(c) => c,
+            Err(_) => panic!("invalid histogram config"),
+        };
+
+        Self {
+            state: OnceLock::new(),
+            config,
+            overflow: AtomicU64::new(0),
+        }
+    }
+
+    /// Increments the bucket for a corresponding value.
+    pub fn increment(&self, value: u64) -> Result<(), Error> {
+        self.add(value, 1)
+    }
+
+    /// Adds `count` to the bucket for a corresponding value.
+    ///
+    /// Values greater than the maximum value of the histogram are not
+    /// recorded and are counted in [`overflow`] instead.
+    ///
+    /// [`overflow`]: ThreadLocalHistogram::overflow
+    pub fn add(&self, value: u64, count: u64) -> Result<(), Error> {
+        let state = self.get_or_init();
+        let index = bucket_index(&self.config, value).inspect_err(|_| {
+            self.overflow.fetch_add(count, Ordering::Relaxed);
+        })?;
+
+        let recorded = THREAD_LOCAL_BUFFERS.try_with(|buffers| {
+            let mut buffers = buffers.borrow_mut();
+            if buffers.len() <= state.id {
+                buffers.resize(state.id + 1, None);
+            }
+
+            let buffer = buffers[state.id].get_or_insert_with(|| {
+                let buffer = Arc::new(Buffer::new(&self.config));
+                state.buffers.lock().push(buffer.clone());
+                buffer
+            });
+            buffer.add(index, count);
+        });
+
+        // the thread local storage is unavailable while the thread is being
+        // torn down, so record directly into the retired counts instead
+        if recorded.is_err() {
+            state.retired.lock().as_mut_slice()[index] += count;
+        }
+
+        Ok(())
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// The number of values which were not recorded because they exceed the
+    /// maximum value of the histogram.
+    pub fn overflow(&self) -> u64 {
+        self.overflow.load(Ordering::Relaxed)
+    }
+
+    /// Merges and returns the buffers of every thread. Returns `None` if the
+    /// histogram has never been incremented.
+    pub fn load(&self) -> Option<Histogram> {
+        let state = self.state.get()?;
+
+        let mut retired = state.retired.lock();
+        let mut buffers = state.buffers.lock();
+
+        // fold the buffers of exited threads, which are only referenced from
+        // here, into the retired counts
+        buffers.retain(|buffer| {
+            if Arc::strong_count(buffer) > 1 {
+                return true;
+            }
+
+            for (total, bucket) in retired.as_mut_slice().iter_mut().zip(buffer.buckets.iter()) {
+                *total = total.wrapping_add(bucket.load(Ordering::Relaxed));
+            }
+            false
+        });
+
+        let mut histogram = retired.clone();
+        for buffer in buffers.iter() {
+            for (total, bucket) in histogram
+                .as_mut_slice()
+                .iter_mut()
+                .zip(buffer.buckets.iter())
+            {
+                *total = total.wrapping_add(bucket.load(Ordering::Relaxed));
+            }
+        }
+
+        Some(histogram)
+    }
+
+    fn get_or_init(&self) -> &ThreadLocalState {
+        self.state.get_or_init(|| ThreadLocalState {
+            id: NEXT_THREAD_LOCAL_ID.fetch_add(1, Ordering::Relaxed),
+            buffers: Mutex::new(Vec::new()),
+            retired: Mutex::new(Histogram::with_config(&self.config)),
+        })
+    }
+}
+
+impl Metric for ThreadLocalHistogram {
+    fn as_any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Other(self))
+    }
+}
+
+/// The index of the bucket for a value, matching the bucketing used by the
+/// `histogram` crate.
+fn bucket_index(config: &Config, value: u64) -> Result<usize, Error> {
+    let grouping_power = config.grouping_power() as u32;
+    let cutoff_power = grouping_power + 1;
+    let cutoff_value = 1_u64 << cutoff_power;
+
+    if value < cutoff_value {
+        return Ok(value as usize);
+    }
+
+    if out_of_range(config, value) {
+        return Err(Error::OutOfRange);
+    }
+
+    let power = 63 - value.leading_zeros();
+    let log_bin = (power - cutoff_power) as u64;
+    let offset = (value - (1 << power)) >> (power - grouping_power);
+
+    Ok((cutoff_value + (log_bin << grouping_power) + offset) as usize)
+}
+
+/// Whether a value is larger than the largest value a histogram can hold.
+fn out_of_range(config: &Config, value: u64) -> bool {
+    config.max_value_power() < 64 && value >= 1 << config.max_value_power()
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use http::{Request, Response};
+use parking_lot::RwLock;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{AtomicHistogram, Counter, DynBoxedMetric, MetricBuilder, RecordDuration, Unit};
+
+/// The route recorded for requests which match none of the route templates.
+const UNMATCHED: &str = "unmatched";
+
+/// The classes of response status, followed by requests which failed with
+/// an error instead of producing a response.
+const STATUS: [&str; 6] = ["1xx", "2xx", "3xx", "4xx", "5xx", "error"];
+
+/// A route template, such as `/users/{id}/posts`, split into its segments.
+#[derive(Clone, Debug)]
+struct Template {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Literal(String),
+    /// `{name}`, which matches any single segment.
+    Param,
+    /// `{*name}`, which matches the rest of the path.
+    Rest,
+}
+
+impl Template {
+    fn new(template: &str) -> Self {
+        let segments = segments(template)
+            .map(|segment| {
+                if segment.starts_with("{*") && segment.ends_with('}') {
+                    Segment::Rest
+                } else if segment.starts_with('{') && segment.ends_with('}') {
+                    Segment::Param
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect();
+
+        Self {
+            template: template.to_string(),
+            segments,
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let mut path = segments(path);
+        for segment in &self.segments {
+            match (segment, path.next()) {
+                (Segment::Rest, _) => return true,
+                (Segment::Param, Some(_)) => {}
+                (Segment::Literal(literal), Some(s)) if literal == s => {}
+                _ => return false,
+            }
+        }
+        path.next().is_none()
+    }
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+/// The metrics for a single route.
+struct RouteMetrics {
+    requests: DynBoxedMetric<Counter>,
+    responses: [DynBoxedMetric<Counter>; STATUS.len()],
+    latency: DynBoxedMetric<AtomicHistogram>,
+}
+
+impl RouteMetrics {
+    fn new(prefix: &str, route: &str) -> Self {
+        let requests = MetricBuilder::new(format!("{prefix}/requests"))
+            .description("the number of requests received")
+            .metadata("route", route)
+            .build(Counter::new());
+        let responses = STATUS.map(|status| {
+            MetricBuilder::new(format!("{prefix}/responses"))
+                .description("the number of responses by class of status")
+                .metadata("route", route)
+                .metadata("status", status)
+                .build(Counter::new())
+        });
+        let latency = MetricBuilder::new(format!("{prefix}/latency"))
+            .description("the time from receiving a request to producing its response")
+            .metadata("route", route)
+            .unit(Unit::Nanoseconds)
+            .build(AtomicHistogram::new(7, 64));
+
+        Self {
+            requests,
+            responses,
+            latency,
+        }
+    }
+}
+
+/// The metrics for each route, shared by every service created by a layer.
+type RouteMap = Arc<RwLock<HashMap<String, Arc<RouteMetrics>>>>;
+
+struct Routes {
+    prefix: String,
+    templates: Vec<Template>,
+    metrics: RouteMap,
+}
+
+impl Routes {
+    fn route(&self, path: &str) -> Arc<RouteMetrics> {
+        let route = self
+            .templates
+            .iter()
+            .find(|t| t.matches(path))
+            .map_or(UNMATCHED, |t| t.template.as_str());
+
+        if let Some(metrics) = self.metrics.read().get(route) {
+            return metrics.clone();
+        }
+
+        self.metrics
+            .write()
+            .entry(route.to_string())
+            .or_insert_with(|| Arc::new(RouteMetrics::new(&self.prefix, route)))
+            .clone()
+    }
+}
+
+/// A tower [`Layer`] which records metrics for the HTTP requests handled by
+/// the service it wraps, for use with servers such as axum, hyper and tonic.
+///
+/// The metrics are registered as dynamic metrics under a common prefix, with
+/// the route of the request in their `route` metadata:
+///
+/// - `{prefix}/requests`: a counter of the requests received
+/// - `{prefix}/responses`: counters of the responses, with the class of their
+///   status (`1xx` to `5xx`) in their `status` metadata, or `error` for
+///   requests which failed without a response
+/// - `{prefix}/latency`: a histogram of the time from receiving each request
+///   until its response was ready, in nanoseconds, which does not include
+///   the time taken to send a streaming body
+///
+/// The route of a request is the first of the route templates added with
+/// [`HttpMetricsLayer::route`] which matches its path, such as `/users/{id}`
+/// rather than `/users/1234`, so that the number of metrics stays bounded no
+/// matter which paths clients request. Requests which match none of the
+/// templates are recorded with the route `unmatched`. The metrics for each
+/// route are registered the first time it is requested, and are shared by
+/// every service the layer is applied to.
+///
+/// Requests which are dropped before their response is ready, such as when
+/// the client disconnects, are counted in the requests but not in the
+/// responses or the latency.
+///
+/// # Example
+/// ```
+/// # use metriken::HttpMetricsLayer;
+/// let layer = HttpMetricsLayer::new("http")
+///     .route("/users/{id}")
+///     .route("/users/{id}/posts")
+///     .route("/static/{*path}");
+///
+/// // wrap a service with `tower::ServiceBuilder::new().layer(layer)`, or
+/// // with `Router::layer(layer)` in axum
+/// ```
+#[derive(Clone)]
+pub struct HttpMetricsLayer {
+    prefix: String,
+    templates: Vec<Template>,
+    metrics: RouteMap,
+}
+
+impl HttpMetricsLayer {
+    /// Record metrics with names starting with the provided prefix.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            templates: Vec::new(),
+            metrics: Default::default(),
+        }
+    }
+
+    /// Add a route template. Segments of the form `{name}` match any single
+    /// segment of the path, and a final segment of the form `{*name}`
+    /// matches the rest of the path. Templates are tried in the order they
+    /// were added.
+    pub fn route(mut self, template: &str) -> Self {
+        self.templates.push(Template::new(template));
+        self
+    }
+}
+
+impl<S> Layer<S> for HttpMetricsLayer {
+    type Service = HttpMetrics<S>;
+
+    fn layer(&self, inner: S) -> HttpMetrics<S> {
+        HttpMetrics {
+            inner,
+            routes: Arc::new(Routes {
+                prefix: self.prefix.clone(),
+                templates: self.templates.clone(),
+                metrics: self.metrics.clone(),
+            }),
+        }
+    }
+}
+
+/// A service which records metrics for the HTTP requests handled by the
+/// service it wraps. See [`HttpMetricsLayer`].
+#[derive(Clone)]
+pub struct HttpMetrics<S> {
+    inner: S,
+    routes: Arc<Routes>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for HttpMetrics<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = HttpMetricsFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let metrics = self.routes.route(request.uri().path());
+        metrics.requests.increment();
+
+        HttpMetricsFuture {
+            future: self.inner.call(request),
+            metrics,
+            start: Instant::now(),
+        }
+    }
+}
+
+/// The response future of [`HttpMetrics`].
+#[must_use = "futures do nothing unless polled"]
+pub struct HttpMetricsFuture<F> {
+    future: F,
+    metrics: Arc<RouteMetrics>,
+    start: Instant,
+}
+
+impl<F, ResBody, E> Future for HttpMetricsFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: the wrapped future is never moved out of `self`, and no
+        // other field is pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        let result = future.poll(cx);
+        if let Poll::Ready(result) = &result {
+            let status = match result {
+                Ok(response) => (response.status().as_u16() / 100).clamp(1, 5) as usize - 1,
+                Err(_) => STATUS.len() - 1,
+            };
+            this.metrics.responses[status].increment();
+            this.metrics.latency.record_duration(this.start.elapsed());
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::Ready;
+    use std::task::{Wake, Waker};
+
+    use super::*;
+
+    #[test]
+    fn templates() {
+        let template = Template::new("/users/{id}/posts");
+        assert!(template.matches("/users/1234/posts"));
+        assert!(template.matches("/users/1234/posts/"));
+        assert!(!template.matches("/users/1234"));
+        assert!(!template.matches("/users/1234/posts/5"));
+        assert!(!template.matches("/groups/1234/posts"));
+
+        let template = Template::new("/static/{*path}");
+        assert!(template.matches("/static/css/main.css"));
+        assert!(!template.matches("/index.html"));
+
+        assert!(Template::new("/").matches("/"));
+        assert!(!Template::new("/").matches("/users"));
+    }
+
+    /// Responds with the status in the path, such as `/status/404`.
+    #[derive(Clone)]
+    struct Status;
+
+    impl Service<Request<()>> for Status {
+        type Response = Response<()>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<()>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<()>) -> Self::Future {
+            let status: u16 = request.uri().path()[8..].parse().unwrap();
+            let mut response = Response::new(());
+            *response.status_mut() = status.try_into().unwrap();
+            std::future::ready(Ok(response))
+        }
+    }
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn service() {
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+
+        let layer = HttpMetricsLayer::new("test/http").route("/status/200");
+        let mut service = layer.layer(Status);
+        let mut other = layer.layer(Status);
+
+        for path in ["/status/200", "/status/200", "/status/404", "/status/503"] {
+            let request = Request::get(path).body(()).unwrap();
+            let mut response = std::pin::pin!(service.call(request));
+            assert!(response.as_mut().poll(&mut cx).is_ready());
+        }
+
+        // services from the same layer share their metrics
+        let request = Request::get("/status/200").body(()).unwrap();
+        assert!(std::pin::pin!(other.call(request)).poll(&mut cx).is_ready());
+
+        let routes = service.routes.metrics.read();
+        assert_eq!(routes.len(), 2);
+
+        let ok = &routes["/status/200"];
+        assert_eq!(ok.requests.value(), 3);
+        assert_eq!(ok.responses[1].value(), 3);
+        assert!(ok.latency.load().is_some());
+
+        let unmatched = &routes[UNMATCHED];
+        assert_eq!(unmatched.requests.value(), 2);
+        assert_eq!(unmatched.responses[3].value(), 1);
+        assert_eq!(unmatched.responses[4].value(), 1);
+        assert_eq!(unmatched.responses[0].value(), 0);
+    }
+}
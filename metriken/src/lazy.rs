@@ -2,6 +2,7 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use std::any::TypeId;
 use std::cell::Cell;
 use std::ops::{Deref, DerefMut};
 
@@ -119,7 +120,44 @@ impl<T: Metric, F: Send + 'static> Metric for Lazy<T, F> {
         }
     }
 
-    fn value(&self) -> Option<crate::Value> {
+    fn value(&self) -> Option<crate::Value<'_>> {
         Lazy::get(self).and_then(|metric| metric.value())
     }
+
+    fn provide<'a>(&'a self, request: &mut metriken_core::Request<'a>) {
+        match Lazy::get(self) {
+            Some(metric) => metric.provide(request),
+            None => {
+                request.provide_value(Uninitialized {
+                    type_id: TypeId::of::<T>(),
+                });
+            }
+        }
+    }
+}
+
+/// Provided by a [`Lazy`] metric which has not been initialized yet.
+///
+/// Uninitialized metrics have no value, but this can be requested from the
+/// metric entry to find out what type of metric it will be once it is used.
+/// ```
+/// # use metriken::*;
+/// #[metric]
+/// static REQUESTS: LazyCounter = LazyCounter::new(Counter::default);
+///
+/// let metrics = metrics();
+/// let entry = metrics.get("REQUESTS").unwrap();
+/// let uninitialized = entry.request_value::<Uninitialized>().unwrap();
+/// assert!(uninitialized.is::<Counter>());
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Uninitialized {
+    type_id: TypeId,
+}
+
+impl Uninitialized {
+    /// Returns `true` if the metric will be a `T` once it is initialized.
+    pub fn is<T: 'static>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
 }
@@ -69,26 +69,56 @@
 //!
 //! [`linkme`]: https://docs.rs/linkme
 
+mod allocator;
+mod build_info;
+mod cardinality;
 mod counter;
+mod ewma;
 mod gauge;
 pub mod histogram;
+#[cfg(feature = "tower")]
+mod http_metrics;
 mod lazy;
+#[cfg(feature = "log")]
+mod log_counters;
+mod sampled;
+mod seen_set;
+mod sharded;
+mod stats;
+mod task;
+mod timer;
+mod top_k;
 
 extern crate self as metriken;
 
 #[doc(inline)]
 pub use metriken_core::{
     default_formatter, dynmetrics, metrics, DynMetricsIter, Format, Metadata, MetadataIter, Metric,
-    MetricEntry, Metrics, MetricsIter, Value,
+    MetricEntry, Metrics, MetricsIter, Unit, Value,
 };
 pub use metriken_derive::metric;
 
-pub use crate::counter::Counter;
+pub use crate::allocator::{AllocatorMetrics, AllocatorStats, CountingAllocator};
+pub use crate::build_info::BuildInfo;
+pub use crate::cardinality::{Cardinality, CARDINALITY_PRECISION};
+pub use crate::counter::{Counter, IntervalCounter, PaddedCounter};
 #[doc(inline)]
 pub use crate::dynmetrics::{DynBoxedMetric, DynPinnedMetric, MetricBuilder};
-pub use crate::gauge::Gauge;
-pub use crate::histogram::{AtomicHistogram, RwLockHistogram};
-pub use crate::lazy::Lazy;
+pub use crate::ewma::Ewma;
+pub use crate::gauge::{Gauge, PeakGauge};
+pub use crate::histogram::{AtomicHistogram, RwLockHistogram, ThreadLocalHistogram};
+#[cfg(feature = "tower")]
+pub use crate::http_metrics::{HttpMetrics, HttpMetricsFuture, HttpMetricsLayer};
+pub use crate::lazy::{Lazy, Uninitialized};
+#[cfg(feature = "log")]
+pub use crate::log_counters::{LogCounters, LogFilter};
+pub use crate::sampled::{SampledCounter, SampledHistogram, Sampling};
+pub use crate::seen_set::SeenSet;
+pub use crate::sharded::ShardedCounter;
+pub use crate::stats::{Stats, StatsValue};
+pub use crate::task::{Instrumented, TaskMetrics};
+pub use crate::timer::{BusyTimer, RecordDuration, Timer};
+pub use crate::top_k::{HeavyHitter, TopK};
 
 /// A counter holds a unsigned 64bit monotonically non-decreasing value. The
 /// counter behavior is to wrap on overflow.
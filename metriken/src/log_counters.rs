@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use ::log::{Level, Log, Metadata, Record};
+use parking_lot::RwLock;
+
+use crate::{Counter, DynBoxedMetric, MetricBuilder};
+
+/// Selects the log records counted by [`LogCounters`] and names the counter
+/// they are counted in.
+///
+/// By default every record is counted. Records can be limited to those at or
+/// above a level of severity and to those whose target starts with a prefix.
+#[derive(Clone, Debug)]
+pub struct LogFilter {
+    name: String,
+    level: Option<Level>,
+    target: Option<String>,
+    by_target: bool,
+}
+
+impl LogFilter {
+    /// Count records in a counter with the provided name.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            level: None,
+            target: None,
+            by_target: false,
+        }
+    }
+
+    /// Only count records at this level or a more severe one. For example,
+    /// `Level::Warn` counts both warnings and errors.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Only count records whose target starts with this prefix, such as the
+    /// path of a module.
+    pub fn target(mut self, prefix: impl Into<String>) -> Self {
+        self.target = Some(prefix.into());
+        self
+    }
+
+    /// Count records in a separate counter for each target, with the target
+    /// in its `target` metadata key. Counters are registered the first time
+    /// a record with their target is counted.
+    pub fn by_target(mut self, enabled: bool) -> Self {
+        self.by_target = enabled;
+        self
+    }
+
+    fn matches(&self, metadata: &Metadata<'_>) -> bool {
+        self.level.is_none_or(|level| metadata.level() <= level)
+            && self
+                .target
+                .as_deref()
+                .is_none_or(|prefix| metadata.target().starts_with(prefix))
+    }
+
+    fn counter(&self, target: Option<&str>) -> DynBoxedMetric<Counter> {
+        let mut builder = MetricBuilder::new(self.name.clone())
+            .description("log records counted by a LogCounters adapter");
+        if let Some(level) = self.level {
+            builder = builder.metadata("level", level.as_str().to_ascii_lowercase());
+        }
+        if let Some(target) = target.or(self.target.as_deref()) {
+            builder = builder.metadata("target", target);
+        }
+        builder.build(Counter::new())
+    }
+}
+
+/// The counters for a single filter.
+enum Counters {
+    Single(DynBoxedMetric<Counter>),
+    ByTarget(RwLock<HashMap<String, DynBoxedMetric<Counter>>>),
+}
+
+/// A logger which counts log records in metriken counters before passing
+/// them on to another logger, so that the rate of warnings and errors is
+/// recorded in the same snapshots as the other metrics.
+///
+/// Each [`LogFilter`] registers a dynamic counter which is incremented for
+/// every record it matches. A record is counted by every filter it matches.
+/// Note that records above the maximum level set with `log::set_max_level`
+/// never reach the logger and are not counted.
+///
+/// ```
+/// # use metriken::{LogCounters, LogFilter};
+/// # use log::Level;
+/// let logger = LogCounters::new(log::logger())
+///     .filter(LogFilter::new("log/errors").level(Level::Error))
+///     .filter(LogFilter::new("log/warnings").level(Level::Warn).by_target(true));
+///
+/// // install it with `log::set_boxed_logger(Box::new(logger))`
+/// ```
+pub struct LogCounters {
+    filters: Vec<(LogFilter, Counters)>,
+    inner: Box<dyn Log>,
+}
+
+impl LogCounters {
+    /// Count records before passing them on to the provided logger.
+    pub fn new(inner: impl Log + 'static) -> Self {
+        Self {
+            filters: Vec::new(),
+            inner: Box::new(inner),
+        }
+    }
+
+    /// Add a filter, registering its counter.
+    pub fn filter(mut self, filter: LogFilter) -> Self {
+        let counters = match filter.by_target {
+            true => Counters::ByTarget(RwLock::new(HashMap::new())),
+            false => Counters::Single(filter.counter(None)),
+        };
+        self.filters.push((filter, counters));
+        self
+    }
+
+    /// Count a record without logging it. This allows records to be counted
+    /// by a logger which does not delegate to `LogCounters`.
+    pub fn observe(&self, metadata: &Metadata<'_>) {
+        for (filter, counters) in &self.filters {
+            if !filter.matches(metadata) {
+                continue;
+            }
+
+            match counters {
+                Counters::Single(counter) => {
+                    counter.increment();
+                }
+                Counters::ByTarget(counters) => {
+                    let target = metadata.target();
+                    if let Some(counter) = counters.read().get(target) {
+                        counter.increment();
+                        continue;
+                    }
+                    counters
+                        .write()
+                        .entry(target.to_string())
+                        .or_insert_with(|| filter.counter(Some(target)))
+                        .increment();
+                }
+            }
+        }
+    }
+}
+
+impl Log for LogCounters {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        self.observe(record.metadata());
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
@@ -0,0 +1,235 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::histogram::{Config, Error, Histogram};
+
+use crate::{AtomicHistogram, Counter, Metric, Value};
+
+thread_local! {
+    static RNG: Cell<u64> = Cell::new({
+        // seed each thread differently, and never with zero
+        let seed = RandomState::new().build_hasher().finish();
+        seed | 1
+    });
+}
+
+/// A fast per-thread xorshift generator. Sampling decisions touch no shared
+/// state so that unsampled events cost almost nothing.
+fn next_random() -> u64 {
+    RNG.try_with(|rng| {
+        let mut x = rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        rng.set(x);
+        x
+    })
+    .unwrap_or(0)
+}
+
+/// How often a sampled metric records an event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+    /// Record one in every `n` events on average. Each event is sampled
+    /// independently with a probability of `1 / n`.
+    OneIn(u64),
+    /// Record each event with the provided probability, which must be in the
+    /// range `(0.0, 1.0]`.
+    Probability(f64),
+}
+
+impl Sampling {
+    /// The probability with which each event is recorded.
+    pub fn rate(&self) -> f64 {
+        match *self {
+            Self::OneIn(n) => 1.0 / n.max(1) as f64,
+            Self::Probability(p) if p > 0.0 => p.min(1.0),
+            Self::Probability(_) => 0.0,
+        }
+    }
+
+    /// The factor which recorded counts are multiplied by to estimate the
+    /// true counts.
+    pub fn scale(&self) -> f64 {
+        match self.rate() {
+            rate if rate > 0.0 => 1.0 / rate,
+            _ => 0.0,
+        }
+    }
+
+    #[inline]
+    fn sample(&self) -> bool {
+        match *self {
+            Self::OneIn(0 | 1) => true,
+            Self::OneIn(n) => next_random().is_multiple_of(n),
+            Self::Probability(p) if p >= 1.0 => true,
+            Self::Probability(p) => (next_random() as f64) < p * u64::MAX as f64,
+        }
+    }
+}
+
+fn scale(value: u64, sampling: &Sampling) -> u64 {
+    (value as f64 * sampling.scale()).round() as u64
+}
+
+/// A counter which only records a sample of the events it is given.
+///
+/// The value reported for the counter is the number of recorded events
+/// multiplied by the inverse of the sampling rate, which estimates the true
+/// number of events. The snapshotter records the sampling rate in the
+/// metadata of the counter.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, SampledCounter, Sampling};
+/// #[metric(name = "cache/lookups")]
+/// static LOOKUPS: SampledCounter = SampledCounter::new(Sampling::OneIn(100));
+///
+/// fn lookup() {
+///     LOOKUPS.increment();
+///     // ...
+/// }
+/// # lookup();
+/// ```
+#[derive(Debug)]
+pub struct SampledCounter {
+    counter: Counter,
+    sampling: Sampling,
+}
+
+impl SampledCounter {
+    /// Create a sampled counter initialized to 0.
+    pub const fn new(sampling: Sampling) -> Self {
+        Self {
+            counter: Counter::new(),
+            sampling,
+        }
+    }
+
+    /// Count a single event, if it is sampled.
+    #[inline]
+    pub fn increment(&self) {
+        self.add(1)
+    }
+
+    /// Count `value` events as a single sample, if it is sampled.
+    #[inline]
+    pub fn add(&self, value: u64) {
+        if self.sampling.sample() {
+            self.counter.add(value);
+        }
+    }
+
+    /// The estimated number of events.
+    pub fn value(&self) -> u64 {
+        scale(self.counter.value(), &self.sampling)
+    }
+
+    /// The number of events which were actually recorded.
+    pub fn recorded(&self) -> u64 {
+        self.counter.value()
+    }
+
+    pub fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+}
+
+impl Metric for SampledCounter {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Counter(self.value()))
+    }
+}
+
+/// A histogram which only records a sample of the values it is given.
+///
+/// When loaded, each bucket count is multiplied by the inverse of the
+/// sampling rate to estimate the true distribution. The snapshotter records
+/// the sampling rate in the metadata of the histogram.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, SampledHistogram, Sampling};
+/// #[metric(name = "packet/size")]
+/// static PACKET_SIZE: SampledHistogram =
+///     SampledHistogram::new(7, 64, Sampling::Probability(0.01));
+///
+/// fn receive(packet: &[u8]) {
+///     let _ = PACKET_SIZE.increment(packet.len() as u64);
+///     // ...
+/// }
+/// # receive(b"hello");
+/// ```
+pub struct SampledHistogram {
+    histogram: AtomicHistogram,
+    sampling: Sampling,
+}
+
+impl SampledHistogram {
+    /// Create a new sampled histogram with the given parameters.
+    ///
+    /// # Panics
+    /// This will panic if the `grouping_power` and `max_value_power` do not
+    /// adhere to the constraints of an [`AtomicHistogram`].
+    pub const fn new(grouping_power: u8, max_value_power: u8, sampling: Sampling) -> Self {
+        Self {
+            histogram: AtomicHistogram::new(grouping_power, max_value_power),
+            sampling,
+        }
+    }
+
+    /// Record a value, if it is sampled.
+    pub fn increment(&self, value: u64) -> Result<(), Error> {
+        if self.sampling.sample() {
+            self.histogram.increment(value)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.histogram.config()
+    }
+
+    /// The number of sampled values which were not recorded because they
+    /// exceed the maximum value of the histogram. Like the bucket counts,
+    /// this only includes the values which were sampled.
+    pub fn overflow(&self) -> u64 {
+        self.histogram.overflow()
+    }
+
+    pub fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+
+    /// Loads the histogram with its counts scaled up to estimate the true
+    /// distribution. Returns `None` if no value has been recorded.
+    pub fn load(&self) -> Option<Histogram> {
+        let mut histogram = self.histogram.load()?;
+        for count in histogram.as_mut_slice() {
+            *count = scale(*count, &self.sampling);
+        }
+        Some(histogram)
+    }
+
+    /// Loads the histogram of the values which were actually recorded.
+    pub fn load_recorded(&self) -> Option<Histogram> {
+        self.histogram.load()
+    }
+}
+
+impl Metric for SampledHistogram {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Other(self))
+    }
+}
@@ -0,0 +1,216 @@
+use std::any::Any;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use crate::cardinality::{fixed_hash, mix};
+use crate::{Metric, Value};
+
+/// A Bloom filter which remembers, approximately, which values have been
+/// seen, in a fixed amount of memory. This is useful for deduplication
+/// statistics, such as how many requests are for keys which were requested
+/// before, as in cache analysis.
+///
+/// [`SeenSet::insert`] reports whether a value is new. A value which was
+/// seen before is always reported as such, but a new value may also be
+/// reported as seen before, which is more likely as the filter fills up.
+/// With `n` values inserted into a filter of `m` bits with `k` hashes the
+/// false positive rate is about `(1 - e^(-kn/m))^k`, which is lowest when
+/// `k = m/n * ln(2)`. For example, a filter of 2^20 bits (128KiB) with 7
+/// hashes holds 100,000 values with a false positive rate below 1%.
+///
+/// Unlike a [`Cardinality`], the set is never reset by the snapshotter. In
+/// snapshots it appears as a gauge of the estimated number of distinct values
+/// inserted, along with a `<name>/fill_ratio` gauge of the fraction of bits
+/// which are set, in parts per million, so that consumers can tell when the
+/// estimate, and the answers from [`SeenSet::insert`], are becoming
+/// unreliable. The memory for the filter is allocated by the first insertion.
+///
+/// [`Cardinality`]: crate::Cardinality
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, Counter, SeenSet};
+/// #[metric(name = "cache/keys")]
+/// static KEYS: SeenSet = SeenSet::new(1 << 20, 7);
+///
+/// #[metric(name = "cache/repeats")]
+/// static REPEATS: Counter = Counter::new();
+///
+/// fn lookup(key: &str) {
+///     if !KEYS.insert(key) {
+///         REPEATS.increment();
+///     }
+///     // ...
+/// }
+/// # lookup("a");
+/// # lookup("a");
+/// # assert_eq!(REPEATS.value(), 1);
+/// # assert_eq!(KEYS.estimate(), 1);
+/// ```
+pub struct SeenSet {
+    words: OnceLock<Box<[AtomicU64]>>,
+    bits: usize,
+    hashes: u32,
+}
+
+impl SeenSet {
+    /// Create a new, empty `SeenSet` of at least `bits` bits, rounded up to
+    /// a multiple of 64, which sets `hashes` bits for each value.
+    ///
+    /// # Panics
+    /// This will panic if `bits` or `hashes` is zero.
+    pub const fn new(bits: usize, hashes: u32) -> Self {
+        if bits == 0 || hashes == 0 {
+            panic!("seen set must have a non-zero number of bits and hashes");
+        }
+
+        Self {
+            words: OnceLock::new(),
+            bits: bits.div_ceil(64) * 64,
+            hashes,
+        }
+    }
+
+    /// The number of bits in the filter.
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    /// The number of bits set for each value.
+    pub fn hashes(&self) -> u32 {
+        self.hashes
+    }
+
+    /// Record a value, returning `true` if it had not been seen before.
+    pub fn insert<T: Hash + ?Sized>(&self, value: &T) -> bool {
+        let words = self
+            .words
+            .get_or_init(|| (0..self.bits / 64).map(|_| AtomicU64::new(0)).collect());
+
+        let mut new = false;
+        for bit in self.indices(value) {
+            let mask = 1 << (bit % 64);
+            new |= words[bit / 64].fetch_or(mask, Ordering::Relaxed) & mask == 0;
+        }
+        new
+    }
+
+    /// Returns `true` if the value may have been seen before, and `false` if
+    /// it has certainly not been.
+    pub fn contains<T: Hash + ?Sized>(&self, value: &T) -> bool {
+        let Some(words) = self.words.get() else {
+            return false;
+        };
+        self.indices(value)
+            .all(|bit| words[bit / 64].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0)
+    }
+
+    /// The fraction of the bits in the filter which are set, from `0.0` when
+    /// it is empty to `1.0` when it is full.
+    pub fn fill_ratio(&self) -> f64 {
+        self.ones() as f64 / self.bits as f64
+    }
+
+    /// Estimate the number of distinct values inserted from the number of
+    /// bits which are set. This is `u64::MAX` once every bit is set.
+    pub fn estimate(&self) -> u64 {
+        let (m, k) = (self.bits as f64, self.hashes as f64);
+        let estimate = -(m / k) * (1.0 - self.ones() as f64 / m).ln();
+        estimate.round() as u64
+    }
+
+    /// Forget every value which has been inserted.
+    ///
+    /// Values inserted while the set is being cleared may or may not be
+    /// remembered.
+    pub fn clear(&self) {
+        if let Some(words) = self.words.get() {
+            for word in words.iter() {
+                word.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn ones(&self) -> u64 {
+        self.words.get().map_or(0, |words| {
+            words
+                .iter()
+                .map(|w| w.load(Ordering::Relaxed).count_ones() as u64)
+                .sum()
+        })
+    }
+
+    /// The bits for a value, derived from two hashes of it as
+    /// `h1 + i * h2` so that only one hash needs to be computed.
+    fn indices<T: Hash + ?Sized>(&self, value: &T) -> impl Iterator<Item = usize> {
+        let h1 = fixed_hash(value);
+        let h2 = mix(h1) | 1;
+        let bits = self.bits as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+    }
+}
+
+impl Metric for SeenSet {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Gauge(self.estimate().min(i64::MAX as u64) as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let set = SeenSet::new(1000, 4);
+        assert_eq!(set.bits(), 1024);
+        assert!(!set.contains("a"));
+        assert_eq!(set.fill_ratio(), 0.0);
+        assert_eq!(set.estimate(), 0);
+
+        assert!(set.insert("a"));
+        assert!(!set.insert("a"));
+        assert!(set.contains("a"));
+        assert_eq!(set.estimate(), 1);
+
+        set.clear();
+        assert!(!set.contains("a"));
+        assert!(set.insert("a"));
+    }
+
+    #[test]
+    fn estimate() {
+        let set = SeenSet::new(1 << 20, 7);
+        let mut repeats = 0;
+        for value in 0..100_000u64 {
+            if !set.insert(&value) {
+                repeats += 1;
+            }
+        }
+        // false positives while filling, well below 1%
+        assert!(repeats < 500, "{repeats}");
+
+        let estimate = set.estimate() as f64;
+        assert!((estimate - 100_000.0).abs() < 1_000.0, "{estimate}");
+        assert!((set.fill_ratio() - 0.487).abs() < 0.01);
+
+        let false_positives = (100_000..200_000u64).filter(|v| set.contains(v)).count();
+        assert!(false_positives < 1_000, "{false_positives}");
+    }
+
+    #[test]
+    fn full() {
+        let set = SeenSet::new(64, 1);
+        (0..10_000).for_each(|v| {
+            set.insert(&v);
+        });
+        assert_eq!(set.fill_ratio(), 1.0);
+        assert_eq!(set.estimate(), u64::MAX);
+        assert!(matches!(set.value(), Some(Value::Gauge(i64::MAX))));
+    }
+}
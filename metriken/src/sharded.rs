@@ -0,0 +1,115 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::{Metric, Value};
+
+/// The number of cells in a [`ShardedCounter`].
+const SHARDS: usize = 64;
+
+/// A single cell of a sharded counter, padded to avoid false sharing with
+/// the neighbouring cells.
+#[derive(Debug)]
+#[repr(align(128))]
+struct Shard(AtomicU64);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Shard = Shard(AtomicU64::new(0));
+
+/// Hands out shard indices to threads in round-robin order.
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+}
+
+/// A counter which spreads its increments across many cells.
+///
+/// Each thread is assigned one of 64 cells, each on its own cache lines, and
+/// only ever updates that cell. Threads therefore rarely contend with each
+/// other, at the cost of 8KiB of memory per counter and a more expensive
+/// read, which sums all of the cells. This is intended as a drop-in
+/// replacement for a [`Counter`](crate::Counter) that is updated so often
+/// that the single shared value limits scalability.
+///
+/// Cells are assigned to threads in the order that they first update any
+/// sharded counter, so with more than 64 threads some of them will share a
+/// cell.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, ShardedCounter};
+/// #[metric(name = "proxy/requests")]
+/// static REQUESTS: ShardedCounter = ShardedCounter::new();
+///
+/// fn handle_request() {
+///     REQUESTS.increment();
+///     // ...
+/// }
+/// # handle_request();
+/// # assert_eq!(REQUESTS.value(), 1);
+/// ```
+#[derive(Debug)]
+pub struct ShardedCounter {
+    shards: [Shard; SHARDS],
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShardedCounter {
+    /// Create a sharded counter initialized to 0.
+    pub const fn new() -> Self {
+        Self {
+            shards: [EMPTY; SHARDS],
+        }
+    }
+
+    #[inline]
+    fn shard(&self) -> &AtomicU64 {
+        let idx = SHARD.try_with(|idx| *idx).unwrap_or(0);
+        &self.shards[idx].0
+    }
+
+    #[inline]
+    pub fn increment(&self) {
+        self.add(1)
+    }
+
+    /// Add to the counter. Unlike [`Counter::add`](crate::Counter::add) the
+    /// previous value is not returned, since that would require reading every
+    /// cell.
+    #[inline]
+    pub fn add(&self, value: u64) {
+        self.shard().fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// The sum of all of the cells. Increments which happen while the cells
+    /// are being read may or may not be included.
+    pub fn value(&self) -> u64 {
+        self.shards.iter().fold(0, |sum, shard| {
+            sum.wrapping_add(shard.0.load(Ordering::Relaxed))
+        })
+    }
+
+    /// Reset every cell to zero, returning the sum of their previous values.
+    /// No increments are lost, but ones which happen during the reset may be
+    /// counted either before or after it.
+    pub fn reset(&self) -> u64 {
+        self.shards.iter().fold(0, |sum, shard| {
+            sum.wrapping_add(shard.0.swap(0, Ordering::Relaxed))
+        })
+    }
+}
+
+impl Metric for ShardedCounter {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Counter(self.value()))
+    }
+}
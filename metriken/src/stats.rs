@@ -0,0 +1,120 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{Metric, Value};
+
+/// Tracks the minimum, maximum, sum, and count of the values recorded since
+/// it was last drained.
+///
+/// This is a lightweight alternative to a histogram for cases where the full
+/// distribution is not needed but a single gauge would lose too much
+/// information. The snapshotter drains it on every snapshot so each snapshot
+/// reports the statistics for the interval since the previous one.
+///
+/// Each field is updated and drained independently. A value recorded while
+/// the metric is being drained may therefore have some of its fields counted
+/// in one interval and the rest in the next. Nothing is lost and the fields
+/// are consistent again once recording stops.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, Stats};
+/// #[metric(name = "request/size")]
+/// static REQUEST_SIZE: Stats = Stats::new();
+///
+/// fn handle_request(body: &[u8]) {
+///     REQUEST_SIZE.record(body.len() as u64);
+///     // ...
+/// }
+/// # handle_request(b"hello");
+/// ```
+#[derive(Debug)]
+pub struct Stats {
+    min: AtomicU64,
+    max: AtomicU64,
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+/// The statistics read from a [`Stats`] metric.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatsValue {
+    pub min: u64,
+    pub max: u64,
+    pub sum: u64,
+    pub count: u64,
+}
+
+impl Stats {
+    /// Create a new `Stats` with no recorded values.
+    pub const fn new() -> Self {
+        Self {
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a single value.
+    ///
+    /// The sum wraps around on overflow.
+    #[inline]
+    pub fn record(&self, value: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.min.fetch_min(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// Read the current statistics without resetting them. Returns `None` if
+    /// no values have been recorded.
+    pub fn load(&self) -> Option<StatsValue> {
+        Self::value_from(
+            self.min.load(Ordering::Relaxed),
+            self.max.load(Ordering::Relaxed),
+            self.sum.load(Ordering::Relaxed),
+            self.count.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Read the current statistics and reset them. Returns `None` if no values
+    /// have been recorded since the last time this was called.
+    pub fn drain(&self) -> Option<StatsValue> {
+        Self::value_from(
+            self.min.swap(u64::MAX, Ordering::Relaxed),
+            self.max.swap(0, Ordering::Relaxed),
+            self.sum.swap(0, Ordering::Relaxed),
+            self.count.swap(0, Ordering::Relaxed),
+        )
+    }
+
+    fn value_from(min: u64, max: u64, sum: u64, count: u64) -> Option<StatsValue> {
+        if count == 0 {
+            return None;
+        }
+
+        Some(StatsValue {
+            min: min.min(max),
+            max,
+            sum,
+            count,
+        })
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metric for Stats {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Other(self))
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::{AtomicHistogram, Counter, DynBoxedMetric, MetricBuilder, RecordDuration, Unit};
+
+/// The metrics for each prefix used with [`Instrumented::new`], which are
+/// registered the first time the prefix is used.
+static PREFIXES: Mutex<BTreeMap<String, Arc<TaskMetrics>>> =
+    parking_lot::const_mutex(BTreeMap::new());
+
+/// The metrics recorded by [`Instrumented`] futures, registered as dynamic
+/// metrics under a common prefix:
+///
+/// - `{prefix}/polls`: a histogram of the number of times each task was
+///   polled
+/// - `{prefix}/poll_time`: a histogram of the total time each task spent
+///   being polled, in nanoseconds
+/// - `{prefix}/duration`: a histogram of the time from the creation of each
+///   task to its completion, in nanoseconds
+/// - `{prefix}/cancelled`: a counter of the tasks dropped before they
+///   completed, which are not included in the histograms
+///
+/// The metrics are unregistered when the `TaskMetrics` is dropped.
+pub struct TaskMetrics {
+    polls: DynBoxedMetric<AtomicHistogram>,
+    poll_time: DynBoxedMetric<AtomicHistogram>,
+    duration: DynBoxedMetric<AtomicHistogram>,
+    cancelled: DynBoxedMetric<Counter>,
+}
+
+impl TaskMetrics {
+    /// Register the metrics for tasks under the provided prefix.
+    pub fn new(prefix: &str) -> Self {
+        let histogram = |name: &str, description: &'static str, unit: Option<Unit<'_>>| {
+            let builder = MetricBuilder::new(format!("{prefix}/{name}")).description(description);
+            let builder = match unit {
+                Some(unit) => builder.unit(unit),
+                None => builder,
+            };
+            builder.build(AtomicHistogram::new(7, 64))
+        };
+
+        Self {
+            polls: histogram("polls", "the number of polls of each task", None),
+            poll_time: histogram(
+                "poll_time",
+                "the time each task spent being polled",
+                Some(Unit::Nanoseconds),
+            ),
+            duration: histogram(
+                "duration",
+                "the time from the creation of each task to its completion",
+                Some(Unit::Nanoseconds),
+            ),
+            cancelled: MetricBuilder::new(format!("{prefix}/cancelled"))
+                .description("the tasks dropped before they completed")
+                .build(Counter::new()),
+        }
+    }
+
+    /// Wrap a future so that it records into these metrics.
+    pub fn instrument<F: Future>(self: &Arc<Self>, future: F) -> Instrumented<F> {
+        Instrumented {
+            future,
+            metrics: self.clone(),
+            created: Instant::now(),
+            polls: 0,
+            busy: Duration::ZERO,
+            done: false,
+        }
+    }
+}
+
+/// A future which records how it was polled into the [`TaskMetrics`] for a
+/// prefix when it completes: how many times it was polled, how long it spent
+/// being polled, and how long it took to complete from when it was created.
+///
+/// Comparing the time spent being polled with the time to complete shows how
+/// much of the latency of a task was spent waiting, whether for I/O or for
+/// the executor to get to it, and a large number of polls points to a task
+/// which is woken more often than it makes progress.
+///
+/// # Example
+/// ```
+/// # use metriken::Instrumented;
+/// async fn handle_request() {
+///     Instrumented::new(
+///         async {
+///             // ...
+///         },
+///         "tasks/request",
+///     )
+///     .await
+/// }
+/// ```
+#[must_use = "futures do nothing unless polled"]
+pub struct Instrumented<F> {
+    future: F,
+    metrics: Arc<TaskMetrics>,
+    created: Instant,
+    polls: u64,
+    busy: Duration,
+    done: bool,
+}
+
+impl<F: Future> Instrumented<F> {
+    /// Wrap a future so that it records into the metrics with the provided
+    /// prefix. The metrics for each prefix are registered the first time it
+    /// is used and stay registered for the life of the process. Use
+    /// [`TaskMetrics::instrument`] to control when they are unregistered.
+    pub fn new(future: F, metric_prefix: &str) -> Self {
+        let metrics = PREFIXES
+            .lock()
+            .entry(metric_prefix.to_string())
+            .or_insert_with(|| Arc::new(TaskMetrics::new(metric_prefix)))
+            .clone();
+        metrics.instrument(future)
+    }
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: the wrapped future is never moved out of `self`, and no
+        // other field is pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        let start = Instant::now();
+        let result = future.poll(cx);
+        this.busy += start.elapsed();
+        this.polls += 1;
+
+        if result.is_ready() {
+            this.done = true;
+            let metrics = &this.metrics;
+            let _ = metrics.polls.increment(this.polls);
+            metrics.poll_time.record_duration(this.busy);
+            metrics.duration.record_duration(this.created.elapsed());
+        }
+        result
+    }
+}
+
+impl<F> Drop for Instrumented<F> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.metrics.cancelled.increment();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+
+    use super::*;
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn recorded(metric: &AtomicHistogram) -> Vec<u64> {
+        let Some(histogram) = metric.load() else {
+            return Vec::new();
+        };
+        histogram
+            .into_iter()
+            .filter(|b| b.count() > 0)
+            .map(|b| b.end())
+            .collect()
+    }
+
+    #[test]
+    fn instrumented() {
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+
+        let mut remaining = 2;
+        let future = std::future::poll_fn(|_| {
+            if remaining == 0 {
+                Poll::Ready(())
+            } else {
+                remaining -= 1;
+                Poll::Pending
+            }
+        });
+
+        let mut task = Box::pin(Instrumented::new(future, "test/task"));
+        while task.as_mut().poll(&mut cx).is_pending() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let metrics = task.metrics.clone();
+        drop(task);
+        assert_eq!(recorded(&metrics.polls), [3]);
+        assert_eq!(recorded(&metrics.poll_time).len(), 1);
+        assert!(recorded(&metrics.duration)[0] >= 2_000_000);
+        assert_eq!(metrics.cancelled.value(), 0);
+
+        // the same prefix records into the same metrics
+        let task = Instrumented::new(async {}, "test/task");
+        assert!(Arc::ptr_eq(&task.metrics, &metrics));
+        drop(task);
+        assert_eq!(metrics.cancelled.value(), 1);
+    }
+}
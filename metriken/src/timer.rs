@@ -0,0 +1,240 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::{AtomicHistogram, SampledHistogram, ThreadLocalHistogram};
+
+/// A histogram which a [`Timer`] can record durations into, in nanoseconds.
+pub trait RecordDuration: Sync {
+    /// Record a duration in nanoseconds. Durations which are too long for
+    /// the histogram are counted in its overflow.
+    fn record_duration(&self, duration: Duration);
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().min(u64::MAX as u128) as u64
+}
+
+macro_rules! impl_timers {
+    ($($ty:ty),*) => {
+        $(
+            impl RecordDuration for $ty {
+                fn record_duration(&self, duration: Duration) {
+                    let _ = self.increment(nanos(duration));
+                }
+            }
+
+            impl $ty {
+                /// Start a [`Timer`] which records the time until it is
+                /// dropped, in nanoseconds, into this histogram.
+                pub fn start(&self) -> Timer<'_> {
+                    Timer::new(self)
+                }
+
+                /// Wrap a future so that the time spent polling it, in
+                /// nanoseconds, is recorded into this histogram when it
+                /// completes. See [`BusyTimer`].
+                pub fn time_busy<F: Future>(&self, future: F) -> BusyTimer<'_, F> {
+                    BusyTimer::new(self, future)
+                }
+            }
+        )*
+    };
+}
+
+impl_timers!(AtomicHistogram, ThreadLocalHistogram, SampledHistogram);
+
+/// Records the time from its creation until it is dropped, in nanoseconds,
+/// into a histogram.
+///
+/// The time is measured with [`Instant`], which is monotonic, so it is not
+/// affected by changes to the system clock. A timer which is held across
+/// `.await` points measures the wall time of the operation, including any
+/// time the task spent waiting. Use [`BusyTimer`] to measure only the time
+/// spent running.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, AtomicHistogram};
+/// #[metric(name = "request/latency", metadata = { unit = "nanoseconds" })]
+/// static REQUEST_LATENCY: AtomicHistogram = AtomicHistogram::new(7, 64);
+///
+/// fn handle_request() {
+///     let _timer = REQUEST_LATENCY.start();
+///     // ...
+/// }
+/// # handle_request();
+/// # assert!(REQUEST_LATENCY.load().is_some());
+/// ```
+#[must_use = "the time is recorded when the timer is dropped"]
+pub struct Timer<'a> {
+    histogram: &'a dyn RecordDuration,
+    start: Instant,
+    recording: bool,
+}
+
+impl<'a> Timer<'a> {
+    /// Start a timer which records into the provided histogram.
+    pub fn new(histogram: &'a dyn RecordDuration) -> Self {
+        Self {
+            histogram,
+            start: Instant::now(),
+            recording: true,
+        }
+    }
+
+    /// The time since the timer was started.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Stop the timer and record the time since it was started, which is
+    /// also returned.
+    pub fn stop(mut self) -> Duration {
+        self.recording = false;
+        let elapsed = self.elapsed();
+        self.histogram.record_duration(elapsed);
+        elapsed
+    }
+
+    /// Stop the timer without recording anything, such as when the
+    /// operation being timed failed.
+    pub fn cancel(mut self) {
+        self.recording = false;
+    }
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        if self.recording {
+            self.histogram.record_duration(self.elapsed());
+        }
+    }
+}
+
+/// A future which records the time spent polling the future it wraps, in
+/// nanoseconds, into a histogram when it completes.
+///
+/// Only the time spent inside calls to [`Future::poll`] is counted, so the
+/// time the task spends waiting for I/O or timers, or queued behind other
+/// tasks in the executor, is excluded. This is the CPU cost of the operation
+/// rather than its latency. Nothing is recorded if the future is dropped
+/// before it completes.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, AtomicHistogram};
+/// #[metric(name = "request/busy", metadata = { unit = "nanoseconds" })]
+/// static REQUEST_BUSY: AtomicHistogram = AtomicHistogram::new(7, 64);
+///
+/// async fn handle_request() {
+///     REQUEST_BUSY
+///         .time_busy(async {
+///             // ...
+///         })
+///         .await
+/// }
+/// ```
+#[must_use = "futures do nothing unless polled"]
+pub struct BusyTimer<'a, F> {
+    histogram: &'a dyn RecordDuration,
+    future: F,
+    busy: Duration,
+}
+
+impl<'a, F: Future> BusyTimer<'a, F> {
+    /// Wrap a future so that the time spent polling it is recorded into the
+    /// provided histogram.
+    pub fn new(histogram: &'a dyn RecordDuration, future: F) -> Self {
+        Self {
+            histogram,
+            future,
+            busy: Duration::ZERO,
+        }
+    }
+}
+
+impl<F: Future> Future for BusyTimer<'_, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: the wrapped future is never moved out of `self`, and no
+        // other field is pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        let start = Instant::now();
+        let result = future.poll(cx);
+        this.busy += start.elapsed();
+
+        if result.is_ready() {
+            this.histogram.record_duration(this.busy);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+
+    use super::*;
+
+    fn count(histogram: &AtomicHistogram) -> u64 {
+        histogram
+            .load()
+            .map(|h| h.as_slice().iter().sum())
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn timer() {
+        let histogram = AtomicHistogram::new(7, 64);
+
+        drop(histogram.start());
+        assert_eq!(count(&histogram), 1);
+
+        let timer = histogram.start();
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(timer.stop() >= Duration::from_millis(1));
+        assert_eq!(count(&histogram), 2);
+
+        histogram.start().cancel();
+        assert_eq!(count(&histogram), 2);
+    }
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn busy_timer() {
+        let histogram = AtomicHistogram::new(7, 64);
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+
+        // a future which is pending on its first poll
+        let mut polled = false;
+        let future = std::future::poll_fn(|_| {
+            if std::mem::replace(&mut polled, true) {
+                Poll::Ready(5)
+            } else {
+                Poll::Pending
+            }
+        });
+
+        let mut timed = Box::pin(histogram.time_busy(future));
+        assert!(timed.as_mut().poll(&mut cx).is_pending());
+        // time spent waiting between polls is not counted
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(timed.as_mut().poll(&mut cx), Poll::Ready(5));
+
+        let recorded = histogram.load().unwrap();
+        let bucket = recorded.into_iter().find(|b| b.count() > 0).unwrap();
+        assert!(bucket.start() < 10_000_000);
+    }
+}
@@ -0,0 +1,191 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use parking_lot::Mutex;
+
+use crate::{Metric, Value};
+
+/// Tracks the keys recorded most often since it was last drained, such as
+/// the hottest keys of a cache, in memory bounded by the number of keys
+/// tracked.
+///
+/// This uses the SpaceSaving algorithm. Up to `k` keys are tracked at once.
+/// When a new key is recorded and all `k` are in use, the key with the lowest
+/// count is replaced and the new key inherits its count, which is reported as
+/// the possible error of the new key. Every key recorded more than `n / k`
+/// times, out of `n` recordings, is guaranteed to be tracked, and the true
+/// count of each key is between its `count - error` and its `count`.
+/// Tracking more keys than are needed improves the accuracy of the top few.
+///
+/// The snapshotter drains it on every snapshot, so each snapshot reports the
+/// heavy hitters for the interval since the previous one.
+///
+/// # Example
+/// ```
+/// # use metriken::{metric, TopK};
+/// #[metric(name = "cache/hot_keys")]
+/// static HOT_KEYS: TopK = TopK::new(100);
+///
+/// fn get(key: &str) {
+///     HOT_KEYS.increment(key);
+///     // ...
+/// }
+/// # get("a");
+/// # get("a");
+/// # get("b");
+/// # assert_eq!(HOT_KEYS.load()[0].key, "a");
+/// ```
+pub struct TopK {
+    k: usize,
+    /// The count and error of each tracked key.
+    keys: Mutex<BTreeMap<String, (u64, u64)>>,
+}
+
+/// A key tracked by a [`TopK`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeavyHitter {
+    pub key: String,
+    /// The count of the key, which may be an overestimate by up to `error`.
+    pub count: u64,
+    pub error: u64,
+}
+
+impl TopK {
+    /// Create a new `TopK` which tracks up to `k` keys.
+    ///
+    /// # Panics
+    /// This will panic if `k` is zero.
+    pub const fn new(k: usize) -> Self {
+        if k == 0 {
+            panic!("top k must track at least one key");
+        }
+
+        Self {
+            k,
+            keys: parking_lot::const_mutex(BTreeMap::new()),
+        }
+    }
+
+    /// The maximum number of keys tracked.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Record a key once.
+    #[inline]
+    pub fn increment(&self, key: &str) {
+        self.add(key, 1)
+    }
+
+    /// Record a key `count` times.
+    pub fn add(&self, key: &str, count: u64) {
+        let mut keys = self.keys.lock();
+
+        if let Some((value, _)) = keys.get_mut(key) {
+            *value = value.saturating_add(count);
+            return;
+        }
+
+        let error = if keys.len() < self.k {
+            0
+        } else {
+            // replace the key with the lowest count
+            let (min, (value, _)) = keys
+                .iter()
+                .min_by_key(|(_, (value, _))| *value)
+                .map(|(key, value)| (key.clone(), *value))
+                .unwrap();
+            keys.remove(&min);
+            value
+        };
+
+        keys.insert(key.to_string(), (error.saturating_add(count), error));
+    }
+
+    /// Read the tracked keys, with the highest count first, without
+    /// resetting them.
+    pub fn load(&self) -> Vec<HeavyHitter> {
+        Self::sorted(self.keys.lock().clone())
+    }
+
+    /// Read the tracked keys, with the highest count first, and reset them.
+    pub fn drain(&self) -> Vec<HeavyHitter> {
+        Self::sorted(std::mem::take(&mut *self.keys.lock()))
+    }
+
+    fn sorted(keys: BTreeMap<String, (u64, u64)>) -> Vec<HeavyHitter> {
+        let mut keys: Vec<HeavyHitter> = keys
+            .into_iter()
+            .map(|(key, (count, error))| HeavyHitter { key, count, error })
+            .collect();
+        // the map is ordered by key, so ties stay in key order
+        keys.sort_by_key(|h| std::cmp::Reverse(h.count));
+        keys
+    }
+}
+
+impl std::fmt::Debug for TopK {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TopK").field("k", &self.k).finish()
+    }
+}
+
+impl Metric for TopK {
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn value(&self) -> Option<Value<'_>> {
+        Some(Value::Other(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heavy_hitters() {
+        let top = TopK::new(3);
+        top.add("a", 10);
+        top.add("b", 5);
+        top.add("c", 2);
+        top.increment("c");
+        assert_eq!(top.load().len(), 3);
+
+        // replaces "c", the key with the lowest count
+        top.increment("d");
+        let keys: Vec<_> = top
+            .load()
+            .into_iter()
+            .map(|h| (h.key, h.count, h.error))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                ("a".to_string(), 10, 0),
+                ("b".to_string(), 5, 0),
+                ("d".to_string(), 4, 3)
+            ]
+        );
+
+        assert_eq!(top.drain().len(), 3);
+        assert!(top.load().is_empty());
+    }
+
+    #[test]
+    fn guarantee() {
+        // keys recorded more than n / k times are always tracked
+        let top = TopK::new(10);
+        for i in 0..10_000u64 {
+            match i % 4 {
+                0 => top.increment("hot"),
+                _ => top.increment(&i.to_string()),
+            }
+        }
+
+        let keys = top.load();
+        assert_eq!(keys[0].key, "hot");
+        assert!(keys[0].count - keys[0].error <= 2500 && 2500 <= keys[0].count);
+    }
+}
@@ -0,0 +1,40 @@
+use std::alloc::System;
+
+use metriken::{metrics, CountingAllocator, Value};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator<System> = CountingAllocator::new(System);
+
+fn value(name: &str) -> Option<Value<'static>> {
+    metrics()
+        .iter()
+        .find(|m| m.name() == name)
+        .and_then(|m| match m.value()? {
+            Value::Counter(v) => Some(Value::Counter(v)),
+            Value::Gauge(v) => Some(Value::Gauge(v)),
+            _ => None,
+        })
+}
+
+#[test]
+fn counts_allocations() {
+    let metrics = ALLOCATOR.register();
+
+    let before = ALLOCATOR.stats().allocations();
+    let mut buffer: Vec<u8> = Vec::with_capacity(1 << 20);
+    assert!(ALLOCATOR.stats().allocations() > before);
+    assert!(ALLOCATOR.stats().allocated() >= 1 << 20);
+
+    // growing the buffer counts as an allocation and a deallocation
+    let deallocations = ALLOCATOR.stats().deallocations();
+    buffer.reserve_exact(3 << 20);
+    assert!(ALLOCATOR.stats().deallocations() > deallocations);
+    assert!(ALLOCATOR.stats().allocated_total() >= 4 << 20);
+
+    assert!(matches!(value("allocator/allocated"), Some(Value::Gauge(v)) if v >= 3 << 20));
+    assert!(matches!(value("allocator/allocations"), Some(Value::Counter(v)) if v > before));
+
+    drop(buffer);
+    drop(metrics);
+    assert!(value("allocator/allocated").is_none());
+}
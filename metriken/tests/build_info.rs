@@ -0,0 +1,63 @@
+use metriken::{metrics, BuildInfo, Value};
+
+/// The metadata and value of each registered `build_info` gauge.
+fn build_info() -> Vec<(Vec<(String, String)>, i64)> {
+    metrics()
+        .iter()
+        .filter(|m| m.name() == "build_info")
+        .filter_map(|m| match m.value() {
+            Some(Value::Gauge(value)) => {
+                let mut metadata: Vec<_> = m
+                    .metadata()
+                    .iter()
+                    .filter(|(k, _)| *k != "description")
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+                metadata.sort();
+                Some((metadata, value))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn registers_a_single_gauge() {
+    let metadata = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+
+    metriken::build_info!().register();
+    assert_eq!(
+        build_info(),
+        [(
+            metadata(&[("name", "metriken"), ("version", env!("CARGO_PKG_VERSION"))]),
+            1
+        )]
+    );
+
+    // registering again replaces the gauge
+    BuildInfo::new("server", "1.2.0")
+        .git_sha("4b825dc")
+        .rustc_version("1.80.0")
+        .features(["tls", "metrics"])
+        .target("x86_64-unknown-linux-gnu")
+        .register();
+    assert_eq!(
+        build_info(),
+        [(
+            metadata(&[
+                ("features", "tls,metrics"),
+                ("git_sha", "4b825dc"),
+                ("name", "server"),
+                ("rustc_version", "1.80.0"),
+                ("target", "x86_64-unknown-linux-gnu"),
+                ("version", "1.2.0"),
+            ]),
+            1
+        )]
+    );
+}
@@ -99,3 +99,48 @@ fn multi_metric() {
     drop(m2);
     assert_eq!(metrics().dynamic_metrics().len(), 0);
 }
+
+#[test]
+fn ttl_expiry() {
+    let _guard = TestGuard::new();
+
+    let idle = MetricBuilder::new("ttl_idle").ttl(2).build(Counter::new());
+    let busy = MetricBuilder::new("ttl_busy").ttl(2).build(Gauge::new());
+    let forever = MetricBuilder::new("ttl_forever").build(Counter::new());
+
+    let mut evicted = Vec::new();
+    for _ in 0..3 {
+        busy.increment();
+        dynmetrics::expire(|entry| evicted.push(entry.name().to_string()));
+    }
+
+    assert_eq!(evicted, ["ttl_idle"]);
+    assert_eq!(metrics().dynamic_metrics().len(), 2);
+
+    // the handle remains usable after it has been evicted
+    idle.increment();
+    assert_eq!(idle.value(), 1);
+
+    drop(idle);
+    drop(busy);
+    drop(forever);
+    assert_eq!(metrics().dynamic_metrics().len(), 0);
+}
+
+#[test]
+fn builder_provided_values() {
+    let _guard = TestGuard::new();
+
+    let metric = MetricBuilder::new("provided")
+        .metadata("key", "value")
+        .formatter(|_, _| "formatted".to_string())
+        .build(Counter::new());
+
+    let metrics = metrics();
+    let entry = metrics.dynamic_metrics().next().unwrap();
+    assert_eq!(entry.metadata().get("key"), Some("value"));
+    assert_eq!(entry.formatted(Format::Simple), "formatted");
+
+    drop(metrics);
+    drop(metric);
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "log")]
+
+use log::{Level, Log, Metadata, Record};
+use metriken::{metrics, LogCounters, LogFilter, Value};
+
+struct Discard;
+
+impl Log for Discard {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, _: &Record<'_>) {}
+
+    fn flush(&self) {}
+}
+
+fn log(logger: &LogCounters, level: Level, target: &str) {
+    logger.log(
+        &Record::builder()
+            .level(level)
+            .target(target)
+            .args(format_args!("message"))
+            .build(),
+    );
+}
+
+/// The values of the dynamic counters with the provided name, keyed by their
+/// target metadata.
+fn counters(name: &str) -> Vec<(Option<String>, u64)> {
+    let mut counters: Vec<_> = metrics()
+        .iter()
+        .filter(|m| m.name() == name)
+        .filter_map(|m| match m.value() {
+            Some(Value::Counter(value)) => {
+                Some((m.metadata().get("target").map(str::to_string), value))
+            }
+            _ => None,
+        })
+        .collect();
+    counters.sort();
+    counters
+}
+
+#[test]
+fn counts_matching_records() {
+    let logger = LogCounters::new(Discard)
+        .filter(LogFilter::new("log/all"))
+        .filter(LogFilter::new("log/errors").level(Level::Error))
+        .filter(
+            LogFilter::new("log/warnings")
+                .level(Level::Warn)
+                .target("app")
+                .by_target(true),
+        );
+
+    // counters are registered before anything is logged
+    assert_eq!(counters("log/errors"), [(None, 0)]);
+    assert!(counters("log/warnings").is_empty());
+
+    log(&logger, Level::Info, "app::server");
+    log(&logger, Level::Warn, "app::server");
+    log(&logger, Level::Warn, "app::server");
+    log(&logger, Level::Error, "app::client");
+    log(&logger, Level::Error, "hyper");
+
+    assert_eq!(counters("log/all"), [(None, 5)]);
+    assert_eq!(counters("log/errors"), [(None, 2)]);
+    assert_eq!(
+        counters("log/warnings"),
+        [
+            (Some("app::client".to_string()), 1),
+            (Some("app::server".to_string()), 2)
+        ]
+    );
+
+    // the counters are unregistered along with the logger
+    drop(logger);
+    assert!(counters("log/all").is_empty());
+}
@@ -0,0 +1,27 @@
+use metriken::{metric, metrics, ShardedCounter, Value};
+
+#[metric(name = "sharded")]
+static SHARDED: ShardedCounter = ShardedCounter::new();
+
+#[test]
+fn sums_across_threads() {
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    SHARDED.increment();
+                }
+                SHARDED.add(5);
+            });
+        }
+    });
+
+    assert_eq!(SHARDED.value(), 80_040);
+
+    let metrics = metrics();
+    let entry = metrics.iter().find(|m| m.name() == "sharded").unwrap();
+    assert!(matches!(entry.value(), Some(Value::Counter(80_040))));
+
+    assert_eq!(SHARDED.reset(), 80_040);
+    assert_eq!(SHARDED.value(), 0);
+}
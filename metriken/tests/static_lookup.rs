@@ -0,0 +1,48 @@
+use metriken::*;
+
+#[metric(name = "lookup/c")]
+static C: Counter = Counter::new();
+
+#[metric(name = "lookup/a")]
+static A: Counter = Counter::new();
+
+#[metric(name = "lookup/b")]
+static B: Gauge = Gauge::new();
+
+#[test]
+fn sorted_by_name() {
+    let metrics = metrics();
+    let names: Vec<_> = metrics
+        .static_metrics_by_name()
+        .iter()
+        .map(|entry| entry.name())
+        .collect();
+
+    assert_eq!(names, ["lookup/a", "lookup/b", "lookup/c"]);
+}
+
+#[test]
+fn get() {
+    let metrics = metrics();
+
+    assert!(metrics.get("lookup/a").unwrap().is(&A));
+    assert!(metrics.get("lookup/b").unwrap().is(&B));
+    assert!(metrics.get("lookup/c").unwrap().is(&C));
+    assert!(metrics.get("lookup/d").is_none());
+}
+
+#[test]
+fn get_dynamic() {
+    let metric = MetricBuilder::new("lookup/dynamic").build(Counter::new());
+
+    metric.add(5);
+
+    let registry = metrics();
+    let entry = registry.get("lookup/dynamic").unwrap();
+    assert_eq!(entry.name(), "lookup/dynamic");
+    assert!(matches!(entry.value(), Some(Value::Counter(5))));
+    drop(registry);
+
+    drop(metric);
+    assert!(metrics().get("lookup/dynamic").is_none());
+}
@@ -0,0 +1,42 @@
+use metriken::{metric, ThreadLocalHistogram};
+
+#[metric(name = "latency")]
+static LATENCY: ThreadLocalHistogram = ThreadLocalHistogram::new(4, 20);
+
+#[test]
+fn merges_threads() {
+    assert!(LATENCY.load().is_none());
+
+    std::thread::scope(|s| {
+        for value in [10, 100, 1000, 10000] {
+            s.spawn(move || {
+                for _ in 0..1000 {
+                    LATENCY.increment(value).unwrap();
+                }
+            });
+        }
+    });
+
+    LATENCY.add(100, 5).unwrap();
+    assert!(LATENCY.increment(1 << 20).is_err());
+
+    let mut expected = metriken::histogram::Histogram::new(4, 20).unwrap();
+    for value in [10, 100, 1000, 10000] {
+        expected.add(value, 1000).unwrap();
+    }
+    expected.add(100, 5).unwrap();
+
+    // the buffers of the exited threads are folded in, and loading again
+    // gives the same result
+    assert_eq!(LATENCY.load().unwrap(), expected);
+    assert_eq!(LATENCY.load().unwrap(), expected);
+
+    // the bucketing matches the histogram crate across the whole range
+    for value in [0, 1, 31, 32, 33, 1000, (1 << 20) - 1] {
+        let histogram = ThreadLocalHistogram::new(4, 20);
+        histogram.increment(value).unwrap();
+        let mut expected = metriken::histogram::Histogram::new(4, 20).unwrap();
+        expected.increment(value).unwrap();
+        assert_eq!(histogram.load().unwrap(), expected);
+    }
+}
@@ -0,0 +1,10 @@
+#[allow(unused_imports)]
+use metriken::{metric, Counter};
+
+#[metric(
+    unit = "bytes",
+    metadata = { unit = "bits" }
+)]
+static DUMMY: Counter = Counter::new();
+
+fn main() {}
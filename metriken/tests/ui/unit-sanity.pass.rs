@@ -0,0 +1,10 @@
+#[allow(unused_imports)]
+use metriken::{metric, AtomicHistogram, Counter, Unit};
+
+#[metric(unit = "nanoseconds", description = "latency")]
+static LATENCY: AtomicHistogram = AtomicHistogram::new(4, 32);
+
+#[metric(unit = Unit::Custom("widgets"))]
+static WIDGETS: Counter = Counter::new();
+
+fn main() {}
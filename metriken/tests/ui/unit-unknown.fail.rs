@@ -0,0 +1,7 @@
+#[allow(unused_imports)]
+use metriken::{metric, Counter};
+
+#[metric(unit = "nanosecond")]
+static DUMMY: Counter = Counter::new();
+
+fn main() {}
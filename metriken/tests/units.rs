@@ -0,0 +1,53 @@
+use metriken::*;
+
+#[metric(name = "latency", unit = Unit::Nanoseconds)]
+static LATENCY: AtomicHistogram = AtomicHistogram::new(4, 32);
+
+#[metric(name = "bandwidth", unit = "bits")]
+static BANDWIDTH: Gauge = Gauge::new();
+
+#[metric(name = "widgets", unit = Unit::Custom("widgets"))]
+static WIDGETS: Counter = Counter::new();
+
+fn unit(name: &str) -> Option<String> {
+    metrics()
+        .get(name)
+        .and_then(|entry| entry.metadata().get("unit"))
+        .map(|unit| unit.to_string())
+}
+
+#[test]
+fn static_units() {
+    assert_eq!(unit("latency").as_deref(), Some("nanoseconds"));
+    assert_eq!(unit("bandwidth").as_deref(), Some("bits"));
+    assert_eq!(unit("widgets").as_deref(), Some("widgets"));
+}
+
+#[test]
+fn dynamic_unit() {
+    let metric = MetricBuilder::new("dynamic")
+        .unit(Unit::Bytes)
+        .build(Gauge::new());
+
+    assert_eq!(unit("dynamic").as_deref(), Some("bytes"));
+    drop(metric);
+}
+
+#[test]
+fn conversion() {
+    assert_eq!(Unit::parse("nanoseconds"), Unit::Nanoseconds);
+    assert_eq!(Unit::parse("furlongs"), Unit::Custom("furlongs"));
+
+    assert_eq!(Unit::Nanoseconds.base(), Unit::Seconds);
+    assert_eq!(Unit::Bits.base(), Unit::Bytes);
+    assert_eq!(Unit::Percent.base(), Unit::Ratio);
+    assert_eq!(Unit::Custom("widgets").base(), Unit::Custom("widgets"));
+
+    assert_eq!(Unit::Nanoseconds.convert(1.5e9, Unit::Seconds), Some(1.5));
+    assert_eq!(Unit::Seconds.convert(2.0, Unit::Milliseconds), Some(2000.0));
+    assert_eq!(Unit::Bits.convert(16.0, Unit::Bytes), Some(2.0));
+    assert_eq!(Unit::Percent.convert(50.0, Unit::Ratio), Some(0.5));
+    assert_eq!(Unit::Bytes.convert(1.0, Unit::Seconds), None);
+    assert_eq!(Unit::Custom("a").convert(1.0, Unit::Custom("a")), Some(1.0));
+    assert_eq!(Unit::Custom("a").convert(1.0, Unit::Custom("b")), None);
+}